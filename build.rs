@@ -0,0 +1,40 @@
+use std::process::Command;
+
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=ISSUE_SERVICE_GIT_COMMIT={commit}");
+
+    // Honor SOURCE_DATE_EPOCH so reproducible builds report a stable time.
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH").unwrap_or_else(|_| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+            .to_string()
+    });
+    println!("cargo:rustc-env=ISSUE_SERVICE_BUILD_TIMESTAMP={timestamp}");
+
+    // protox compiles the protobuf definitions in-process, so building the
+    // gRPC feature does not need `protoc` installed.
+    #[cfg(feature = "grpc")]
+    {
+        let descriptors = protox::compile(["issue_service.proto"], ["proto"])
+            .expect("compile proto/issue_service.proto");
+        tonic_build::configure()
+            .build_client(false)
+            .compile_fds(descriptors)
+            .expect("generate gRPC service");
+        println!("cargo:rerun-if-changed=proto");
+    }
+
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
@@ -0,0 +1,42 @@
+//! Binary PSBT bodies, as `/sign_psbt/binary` and the file drop read them.
+//!
+//! The first byte of the input is the size of the chunks the rest arrives
+//! in, so the decoder is also fed PSBTs split at every possible offset. The
+//! chunked decode must agree with a decode of the whole buffer, and whatever
+//! decodes must survive a round trip through its serialization.
+
+#![no_main]
+
+use std::collections::VecDeque;
+
+use bitcoin::Psbt;
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/psbt_codec.rs"]
+mod psbt_codec;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&chunk_size, body)) = data.split_first() else {
+        return;
+    };
+    let chunks: VecDeque<Bytes> = body
+        .chunks(usize::from(chunk_size).max(1))
+        .map(Bytes::copy_from_slice)
+        .collect();
+
+    match (Psbt::deserialize(body), psbt_codec::decode_chunks(chunks)) {
+        (Ok(whole), Ok(chunked)) => {
+            assert_eq!(whole, chunked);
+            let again = Psbt::deserialize(&whole.serialize()).expect("re-encoded PSBT decodes");
+            assert_eq!(whole, again);
+        }
+        (Err(_), Err(_)) => {}
+        (whole, chunked) => panic!(
+            "whole and chunked decodes disagree: {:?} / {:?}",
+            whole.err(),
+            chunked.err()
+        ),
+    }
+});
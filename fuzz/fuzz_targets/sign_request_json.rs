@@ -0,0 +1,48 @@
+//! JSON sign requests, as `/sign_psbt`, `/ws` and the QR endpoints take
+//! them, down to the base64 PSBT inside.
+//!
+//! Whatever parses must come back unchanged from a round trip through the
+//! service's own encoder.
+
+#![no_main]
+
+use std::collections::BTreeMap;
+
+use bitcoin::Psbt;
+use libfuzzer_sys::fuzz_target;
+use serde::{Deserialize, Serialize};
+
+#[allow(dead_code)]
+#[path = "../../src/psbt_codec.rs"]
+mod psbt_codec;
+
+/// Same shape as `SignRequest` in `src/main.rs`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SignRequest {
+    #[serde(
+        deserialize_with = "psbt_codec::de_psbt_from_base64",
+        serialize_with = "psbt_codec::serialize_psbt_to_base64"
+    )]
+    psbt: Psbt,
+    #[serde(default)]
+    consignment: Option<Consignment>,
+    #[serde(default)]
+    metadata: BTreeMap<String, String>,
+}
+
+/// Same shape as `rgb::Consignment`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Consignment {
+    Data(String),
+    Id(String),
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(request) = serde_json::from_slice::<SignRequest>(data) else {
+        return;
+    };
+    let encoded = serde_json::to_vec(&request).expect("sign request encodes");
+    let again: SignRequest = serde_json::from_slice(&encoded).expect("re-encoded request parses");
+    assert_eq!(request, again);
+});
@@ -0,0 +1,37 @@
+//! One access-log line per request.
+//!
+//! Only request metadata is logged here. Handlers that know which
+//! transaction a request was about attach an [`UnsignedTxid`] to the response
+//! instead of logging the PSBT themselves, so raw PSBTs and signatures never
+//! reach the logs at any level.
+
+use std::time::Instant;
+
+use axum::{extract::Request, middleware::Next, response::Response};
+use bitcoin::Txid;
+
+/// Response extension naming the unsigned txid a request operated on.
+#[derive(Debug, Clone, Copy)]
+pub struct UnsignedTxid(pub Txid);
+
+pub async fn access_log(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let caller = crate::context::peer(request.extensions());
+
+    let response = next.run(request).await;
+
+    let txid = response.extensions().get::<UnsignedTxid>().map(|t| t.0);
+    tracing::info!(
+        target: "access",
+        %method,
+        path,
+        caller = caller.map(tracing::field::display),
+        status = response.status().as_u16(),
+        latency_ms = start.elapsed().as_millis() as u64,
+        txid = txid.map(tracing::field::display),
+        "access",
+    );
+    response
+}
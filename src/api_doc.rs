@@ -0,0 +1,35 @@
+//! OpenAPI description of the public HTTP API, served with Swagger UI on
+//! `/docs`.
+
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Issue Service"),
+    paths(
+        crate::sign_service,
+        crate::sign_binary,
+        crate::rpc::handle,
+        crate::descriptor::parse,
+        crate::qr::encode,
+        crate::qr::decode,
+        crate::bip21::payment_uri,
+        crate::reserves::verify_handler,
+        crate::psbt_diff::diff_handler,
+        crate::health,
+        crate::ready,
+        crate::version,
+        crate::test_vectors::get,
+    )
+)]
+struct ApiDoc;
+
+/// Swagger UI on `/docs`, with the spec itself at `/docs/openapi.json`.
+pub fn swagger_ui() -> SwaggerUi {
+    #[allow(unused_mut)]
+    let mut doc = ApiDoc::openapi();
+    #[cfg(feature = "liquid")]
+    doc.merge(crate::liquid::ApiDoc::openapi());
+    SwaggerUi::new("/docs").url("/docs/openapi.json", doc)
+}
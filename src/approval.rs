@@ -0,0 +1,173 @@
+//! External approval of signing requests.
+//!
+//! With `[approval]` set, every PSBT that passes the service's own checks is
+//! described to an external endpoint, typically a risk engine, which answers
+//! whether it may be signed. Nothing is signed without its answer: a
+//! timeout, an error status or an unreadable reply refuses the request as
+//! surely as a denial does.
+
+use std::time::Duration;
+
+use bitcoin::{Address, Amount, Network, OutPoint, Psbt, Txid};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::Metadata, config::ApprovalConfig, context::RequestContext, error::Error, AppState,
+};
+
+#[derive(Serialize)]
+struct ApprovalRequest<'a> {
+    txid: Txid,
+    network: Network,
+    /// Base64-encoded PSBT, as it would be signed.
+    psbt: String,
+    inputs: Vec<ApprovalInput>,
+    outputs: Vec<ApprovalOutput>,
+    /// In sat, when the amount of every spent coin is known.
+    fee_sat: Option<u64>,
+    request_id: Option<&'a str>,
+    caller: Option<&'a str>,
+    metadata: &'a Metadata,
+}
+
+#[derive(Serialize)]
+struct ApprovalInput {
+    outpoint: OutPoint,
+    /// In sat, from the PSBT's UTXO data.
+    value_sat: Option<u64>,
+    address: Option<String>,
+    /// Whether the coin is this wallet's.
+    is_mine: bool,
+}
+
+#[derive(Serialize)]
+struct ApprovalOutput {
+    value_sat: u64,
+    /// Unset for scripts without an address, such as `OP_RETURN`.
+    address: Option<String>,
+    script_pubkey: String,
+    /// Whether the output pays this wallet.
+    is_mine: bool,
+}
+
+#[derive(Deserialize)]
+struct Decision {
+    allow: bool,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// Asks `policy.url` whether `psbt` may be signed for `ctx`.
+pub async fn check(
+    state: &AppState,
+    ctx: &RequestContext,
+    psbt: &Psbt,
+    policy: &ApprovalConfig,
+) -> Result<(), Error> {
+    let request = describe(state, ctx, psbt);
+    let txid = request.txid;
+    let mut call = state
+        .http
+        .post(&policy.url)
+        .timeout(Duration::from_millis(policy.timeout_ms))
+        .json(&request);
+    if let Some(token) = &policy.auth_token {
+        call = call.bearer_auth(token);
+    }
+    let decision: Decision = async { call.send().await?.error_for_status()?.json().await }
+        .await
+        .map_err(|e| {
+            tracing::warn!(%txid, error = %e, "approval endpoint failed, refusing");
+            match e.is_timeout() {
+                true => Error::Unavailable(format!(
+                    "approval: no answer within {} ms",
+                    policy.timeout_ms
+                )),
+                false => Error::Unavailable(format!("approval: endpoint failed: {e}")),
+            }
+        })?;
+    if !decision.allow {
+        tracing::info!(%txid, reason = decision.reason.as_deref(), "approval denied");
+        return Err(Error::ApprovalDenied(decision.reason.unwrap_or_else(
+            || "the approval endpoint denied the transaction".into(),
+        )));
+    }
+    tracing::info!(%txid, "approved");
+    Ok(())
+}
+
+/// Checks `policy.url` answers at all, without asking it about anything.
+/// Any status will do: the endpoint need only take `POST`s.
+pub async fn probe(policy: &ApprovalConfig) -> Result<reqwest::StatusCode, String> {
+    reqwest::Client::new()
+        .head(&policy.url)
+        .timeout(Duration::from_millis(policy.timeout_ms))
+        .send()
+        .await
+        .map(|response| response.status())
+        .map_err(|e| e.to_string())
+}
+
+fn describe<'a>(state: &AppState, ctx: &'a RequestContext, psbt: &Psbt) -> ApprovalRequest<'a> {
+    let network = state.config().network;
+    let wallet = state.wallet();
+    let address = |script: &bitcoin::Script| {
+        Address::from_script(script, network)
+            .ok()
+            .map(|address| address.to_string())
+    };
+    let tx = &psbt.unsigned_tx;
+    let inputs: Vec<ApprovalInput> = tx
+        .input
+        .iter()
+        .zip(&psbt.inputs)
+        .map(|(txin, input)| {
+            let outpoint = txin.previous_output;
+            let utxo = input.witness_utxo.clone().or_else(|| {
+                input
+                    .non_witness_utxo
+                    .as_ref()
+                    .and_then(|tx| tx.output.get(outpoint.vout as usize).cloned())
+            });
+            ApprovalInput {
+                outpoint,
+                value_sat: utxo.as_ref().map(|utxo| utxo.value.to_sat()),
+                address: utxo.as_ref().and_then(|utxo| address(&utxo.script_pubkey)),
+                is_mine: utxo.is_some_and(|utxo| wallet.is_mine(utxo.script_pubkey)),
+            }
+        })
+        .collect();
+    let outputs = tx
+        .output
+        .iter()
+        .map(|output| ApprovalOutput {
+            value_sat: output.value.to_sat(),
+            address: address(&output.script_pubkey),
+            script_pubkey: output.script_pubkey.to_hex_string(),
+            is_mine: wallet.is_mine(output.script_pubkey.clone()),
+        })
+        .collect();
+    let fee_sat = inputs
+        .iter()
+        .try_fold(Amount::ZERO, |sum, input| {
+            sum.checked_add(Amount::from_sat(input.value_sat?))
+        })
+        .and_then(|spent| {
+            tx.output
+                .iter()
+                .try_fold(Amount::ZERO, |sum, output| sum.checked_add(output.value))
+                .and_then(|paid| spent.checked_sub(paid))
+        })
+        .map(Amount::to_sat);
+    ApprovalRequest {
+        txid: tx.compute_txid(),
+        network,
+        psbt: psbt.to_string(),
+        inputs,
+        outputs,
+        fee_sat,
+        request_id: ctx.request_id.as_deref(),
+        caller: ctx.caller.as_deref(),
+        metadata: &ctx.metadata,
+    }
+}
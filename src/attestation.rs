@@ -0,0 +1,90 @@
+//! Attestations of signed PSBTs.
+//!
+//! With `[attestation]` set, every answer to a signing request carries a
+//! statement signed by the service's attestation key: the SHA-256 of the
+//! PSBT as returned, its unsigned txid, the request id and when it was
+//! made. A broadcaster that checks it against the published key knows the
+//! PSBT came from this service unaltered. The signature is DER-encoded
+//! ECDSA over the SHA-256 of [`Attestation::message`].
+
+use bitcoin::{
+    hashes::{sha256, Hash},
+    secp256k1::{Message, PublicKey, Secp256k1, SecretKey},
+    Psbt, Txid,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::AttestationConfig;
+
+/// Header the attestation of a binary answer is sent in, as JSON.
+pub const HEADER: &str = "x-attestation";
+
+pub struct Attester {
+    key: SecretKey,
+    public_key: PublicKey,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Attestation {
+    /// Hex SHA-256 of the serialized PSBT.
+    pub psbt_sha256: String,
+    #[schema(value_type = String)]
+    pub txid: Txid,
+    pub request_id: Option<String>,
+    /// RFC 3339, exactly as signed.
+    pub attested_at: String,
+    /// Hex compressed attestation key.
+    pub public_key: String,
+    /// Hex DER-encoded ECDSA signature.
+    pub signature: String,
+}
+
+impl Attester {
+    pub fn new(config: &AttestationConfig) -> Result<Self, String> {
+        let key: SecretKey = config
+            .signing_key
+            .parse()
+            .map_err(|e| format!("attestation.signing_key: {e}"))?;
+        Ok(Attester {
+            key,
+            public_key: PublicKey::from_secret_key(&Secp256k1::signing_only(), &key),
+        })
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    pub fn attest(&self, psbt: &Psbt, request_id: Option<&str>, now: DateTime<Utc>) -> Attestation {
+        let mut attestation = Attestation {
+            psbt_sha256: sha256::Hash::hash(&psbt.serialize()).to_string(),
+            txid: psbt.unsigned_tx.compute_txid(),
+            request_id: request_id.map(str::to_owned),
+            attested_at: now.to_rfc3339_opts(SecondsFormat::Millis, true),
+            public_key: self.public_key.to_string(),
+            signature: String::new(),
+        };
+        let digest = sha256::Hash::hash(attestation.message().as_bytes());
+        let msg = Message::from_digest(digest.to_byte_array());
+        attestation.signature = Secp256k1::signing_only()
+            .sign_ecdsa(&msg, &self.key)
+            .serialize_der()
+            .to_string();
+        attestation
+    }
+}
+
+impl Attestation {
+    /// What is signed: a version line and one line per field, the request
+    /// id empty when there is none.
+    pub fn message(&self) -> String {
+        format!(
+            "issue-service attestation v1\n{}\n{}\n{}\n{}",
+            self.psbt_sha256,
+            self.txid,
+            self.request_id.as_deref().unwrap_or_default(),
+            self.attested_at
+        )
+    }
+}
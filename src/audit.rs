@@ -0,0 +1,283 @@
+//! Append-only audit log of signing decisions.
+//!
+//! Entries are stored one JSON object per line. The log is the source of
+//! truth: queries and exports read it back rather than keeping a copy in
+//! memory, so with file storage it can be archived or inspected with
+//! ordinary tools.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use bitcoin::{
+    hashes::{sha256, Hash, HashEngine},
+    secp256k1::{Message, PublicKey, Secp256k1, SecretKey},
+    Txid,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::AuditConfig, context::RequestContext, error::Error, storage::Storage};
+
+/// Name recorded for the single wallet served by this process.
+pub const DEFAULT_WALLET: &str = "default";
+
+const MAX_METADATA_ENTRIES: usize = 16;
+const MAX_METADATA_KEY_BYTES: usize = 64;
+const MAX_METADATA_VALUE_BYTES: usize = 512;
+
+/// Caller metadata of a signing request, such as an order or batch id,
+/// recorded with its audit entry.
+pub type Metadata = BTreeMap<String, String>;
+
+/// Keeps metadata small enough that every audit entry stays one short line.
+pub fn check_metadata(metadata: &Metadata) -> Result<(), Error> {
+    if metadata.len() > MAX_METADATA_ENTRIES {
+        return Err(Error::MalformedRequest(format!(
+            "metadata has more than {MAX_METADATA_ENTRIES} entries"
+        )));
+    }
+    for (key, value) in metadata {
+        if key.is_empty() || key.len() > MAX_METADATA_KEY_BYTES {
+            return Err(Error::MalformedRequest(format!(
+                "metadata keys must be 1 to {MAX_METADATA_KEY_BYTES} bytes"
+            )));
+        }
+        if value.len() > MAX_METADATA_VALUE_BYTES {
+            return Err(Error::MalformedRequest(format!(
+                "metadata {key}: values must be at most {MAX_METADATA_VALUE_BYTES} bytes"
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Signed,
+    Rejected,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub request_id: Option<String>,
+    pub wallet: String,
+    pub caller: Option<String>,
+    pub txid: Txid,
+    pub outcome: Outcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    /// Checked but left unsigned by `signing.dry_run`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+    /// Caller metadata sent with the request.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: Metadata,
+    /// BIP-329 label of the transaction when it was signed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Answered again from the request journal, without signing.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub replayed: bool,
+}
+
+impl AuditEntry {
+    pub fn new(ctx: &RequestContext, txid: Txid, outcome: Outcome) -> Self {
+        AuditEntry {
+            timestamp: Utc::now(),
+            request_id: ctx.request_id.clone(),
+            wallet: DEFAULT_WALLET.to_owned(),
+            caller: ctx.caller.clone(),
+            txid,
+            outcome,
+            error_code: None,
+            dry_run: false,
+            metadata: ctx.metadata.clone(),
+            label: None,
+            replayed: false,
+        }
+    }
+}
+
+/// Criteria for [`AuditLog::query`] and [`AuditLog::export`]; unset fields
+/// match everything.
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditFilter {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub wallet: Option<String>,
+    pub caller: Option<String>,
+    pub txid: Option<Txid>,
+    pub outcome: Option<Outcome>,
+    /// `key:value` to match entries whose metadata has that value, or just
+    /// `key` to match entries that have the key.
+    pub metadata: Option<String>,
+}
+
+impl AuditFilter {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.from.map_or(true, |from| entry.timestamp >= from)
+            && self.to.map_or(true, |to| entry.timestamp < to)
+            && self.wallet.as_ref().map_or(true, |w| *w == entry.wallet)
+            && self
+                .caller
+                .as_ref()
+                .map_or(true, |c| entry.caller.as_ref() == Some(c))
+            && self.txid.map_or(true, |t| t == entry.txid)
+            && self.outcome.map_or(true, |o| o == entry.outcome)
+            && self
+                .metadata
+                .as_deref()
+                .map_or(true, |m| match m.split_once(':') {
+                    Some((key, value)) => entry.metadata.get(key).is_some_and(|v| v == value),
+                    None => entry.metadata.contains_key(m),
+                })
+    }
+}
+
+/// Where the log stands, see [`AuditLog::head`].
+#[derive(Debug, Serialize)]
+pub struct AuditHead {
+    pub entry_count: usize,
+    /// SHA-256 of every stored line, each terminated by `\n`.
+    pub sha256: String,
+    pub latest: Option<AuditEntry>,
+}
+
+/// Trailer appended to an export, attesting to the lines before it.
+#[derive(Debug, Serialize)]
+pub struct SnapshotTrailer {
+    pub generated_at: DateTime<Utc>,
+    pub entry_count: usize,
+    /// SHA-256 of every exported line, each terminated by `\n`.
+    pub sha256: String,
+    /// DER-encoded ECDSA signature over `sha256`, present when
+    /// `audit.signing_key` is configured.
+    pub signature: Option<String>,
+    pub public_key: Option<String>,
+}
+
+pub struct AuditLog {
+    storage: Arc<dyn Storage>,
+    name: String,
+    signing_key: Option<SecretKey>,
+}
+
+impl AuditLog {
+    pub fn open(storage: Arc<dyn Storage>, config: &AuditConfig) -> Result<Self, String> {
+        let name = crate::storage::name(&config.path);
+        storage
+            .append(&name, &[])
+            .map_err(|e| format!("opening {name}: {e}"))?;
+        let signing_key = config
+            .signing_key
+            .as_deref()
+            .map(|key| key.parse::<SecretKey>())
+            .transpose()
+            .map_err(|e| format!("audit.signing_key: {e}"))?;
+        Ok(AuditLog {
+            storage,
+            name,
+            signing_key,
+        })
+    }
+
+    /// Appends `entry` and flushes it to storage before returning.
+    pub fn append(&self, entry: &AuditEntry) -> std::io::Result<()> {
+        self.append_lines(&[serde_json::to_string(entry)?])
+    }
+
+    /// Entries in the log.
+    pub fn len(&self) -> std::io::Result<usize> {
+        Ok(self.storage.lines(&self.name, usize::MAX, 0)?.1)
+    }
+
+    /// The number of entries, a digest of them all and the latest one, by
+    /// which a copy of the log can be checked for completeness.
+    pub fn head(&self) -> std::io::Result<AuditHead> {
+        let (lines, entry_count) = self.storage.lines(&self.name, 0, usize::MAX)?;
+        let mut engine = sha256::Hash::engine();
+        for line in &lines {
+            engine.input(line.as_bytes());
+            engine.input(b"\n");
+        }
+        Ok(AuditHead {
+            entry_count,
+            sha256: sha256::Hash::from_engine(engine).to_string(),
+            latest: lines
+                .last()
+                .map(|line| serde_json::from_str(line))
+                .transpose()?,
+        })
+    }
+
+    /// Up to `limit` entries from the `offset`-th on exactly as stored, and
+    /// the number of entries in the log.
+    pub fn lines_from(&self, offset: usize, limit: usize) -> std::io::Result<(Vec<String>, usize)> {
+        self.storage.lines(&self.name, offset, limit)
+    }
+
+    /// Appends entries already rendered, such as those replicated from a
+    /// primary, and flushes them to storage before returning.
+    pub fn append_lines(&self, lines: &[String]) -> std::io::Result<()> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+        self.storage.append(&self.name, lines)
+    }
+
+    /// Every matching entry with its index in the log, oldest first.
+    pub fn query(&self, filter: &AuditFilter) -> std::io::Result<Vec<(usize, AuditEntry)>> {
+        Ok(self
+            .matching(filter)?
+            .into_iter()
+            .map(|(index, _, entry)| (index, entry))
+            .collect())
+    }
+
+    /// Renders every matching entry exactly as stored, followed by a
+    /// `{"snapshot": ...}` trailer line carrying their digest and signature.
+    pub fn export(&self, filter: &AuditFilter) -> std::io::Result<String> {
+        let lines = self.matching(filter)?;
+        let mut body = String::new();
+        for (_, line, _) in &lines {
+            body.push_str(line);
+            body.push('\n');
+        }
+
+        let digest = sha256::Hash::hash(body.as_bytes());
+        let (signature, public_key) = match &self.signing_key {
+            Some(key) => {
+                let secp = Secp256k1::signing_only();
+                let msg = Message::from_digest(digest.to_byte_array());
+                let signature = secp.sign_ecdsa(&msg, key).serialize_der().to_string();
+                let public_key = PublicKey::from_secret_key(&secp, key).to_string();
+                (Some(signature), Some(public_key))
+            }
+            None => (None, None),
+        };
+        let trailer = SnapshotTrailer {
+            generated_at: Utc::now(),
+            entry_count: lines.len(),
+            sha256: digest.to_string(),
+            signature,
+            public_key,
+        };
+        body.push_str(&serde_json::json!({ "snapshot": trailer }).to_string());
+        body.push('\n');
+        Ok(body)
+    }
+
+    /// Matching entries with their index in the log, as stored and parsed.
+    fn matching(&self, filter: &AuditFilter) -> std::io::Result<Vec<(usize, String, AuditEntry)>> {
+        let (lines, _) = self.storage.lines(&self.name, 0, usize::MAX)?;
+        let mut matching = Vec::new();
+        for (index, line) in lines.into_iter().enumerate() {
+            let entry: AuditEntry = serde_json::from_str(&line)?;
+            if filter.matches(&entry) {
+                matching.push((index, line, entry));
+            }
+        }
+        Ok(matching)
+    }
+}
@@ -0,0 +1,186 @@
+//! Credentials and the scopes they grant.
+//!
+//! With `[auth]` set, every endpoint but `/health` and `/ready` needs a
+//! bearer token from `auth.credentials`, and the credential must hold the
+//! scopes the endpoint needs: `read` to look, `build` to have the wallet
+//! build transactions, `sign` to have it sign, `broadcast` to have it
+//! broadcast and `admin` to change what the service does. Scopes do not
+//! imply one another, so a monitoring credential with `read` can never
+//! sign. [`required`] is the table of what each endpoint needs; an endpoint
+//! missing from it needs `admin`. The gRPC service and JSON-RPC methods
+//! are checked the same way.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use bitcoin::hashes::{sha256, Hash};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::AuthConfig, context::BearerToken, error::Error, AppState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    Read,
+    Build,
+    Sign,
+    Broadcast,
+    Admin,
+}
+
+impl Scope {
+    fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Build => "build",
+            Scope::Sign => "sign",
+            Scope::Broadcast => "broadcast",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+use Scope::*;
+
+/// The scopes the route `path` answers `method` on needs, none for routes
+/// open to anyone. Paths are the routes as registered, without a hosted
+/// network's prefix.
+pub fn required(method: &Method, path: &str) -> &'static [Scope] {
+    let get = method == Method::GET || method == Method::HEAD;
+    match path {
+        "/health" | "/ready" => &[],
+        // The standby presents the replication token instead.
+        "/admin/replication/state" => &[],
+        // Checked per method, see `crate::rpc`.
+        "/rpc" => &[],
+        "/version"
+        | "/silent_payments/address"
+        | "/parse_descriptor"
+        | "/qr/encode"
+        | "/qr/decode"
+        | "/payment_uri"
+        | "/debug/test_vectors"
+        | "/proof_of_reserves/verify"
+        | "/diff_psbt"
+        | "/sign_requests/{request_id}"
+        | "/liquid/account"
+        | "/admin/audit"
+        | "/admin/audit/export"
+        | "/admin/metrics"
+        | "/admin/stats"
+        | "/admin/policies"
+        | "/admin/quorum"
+        | "/admin/utxos"
+        | "/admin/transactions"
+        | "/admin/labels/export"
+        | "/admin/schedules/{name}/runs"
+        | "/admin/replication/status"
+        | "/admin/signer/attestation"
+        | "/admin/silent_payments/outputs" => &[Read],
+        "/admin/channel_fundings"
+        | "/admin/labels"
+        | "/admin/schedules"
+        | "/admin/spent_outpoints"
+        | "/admin/bsms/wallets"
+            if get =>
+        {
+            &[Read]
+        }
+        "/sign_psbt"
+        | "/sign_psbt/binary"
+        | "/payjoin"
+        | "/ws"
+        | "/sign_pset"
+        | "/admin/sign_message"
+        | "/admin/proof_of_reserves"
+        | "/admin/utxo_proof" => &[Sign],
+        "/admin/create_psbt" => &[Build],
+        // Broadcasting as well needs `broadcast`, checked by the handler.
+        "/admin/create_payout" | "/admin/sweep" | "/admin/consolidate" => &[Build, Sign],
+        "/admin/schedules/{name}/run" => &[Build, Sign, Broadcast],
+        _ => &[Admin],
+    }
+}
+
+/// Refuses `bearer` unless `[auth]` is unset or it is a credential holding
+/// every one of `scopes`.
+pub fn authorize(
+    state: &AppState,
+    bearer: Option<&BearerToken>,
+    scopes: &[Scope],
+) -> Result<(), Error> {
+    let Some(auth) = state.config().auth.clone() else {
+        return Ok(());
+    };
+    let Some((name, granted)) = bearer.and_then(|token| credential(&auth, &token.0)) else {
+        return Err(Error::Unauthorized("credential".into()));
+    };
+    match scopes.iter().find(|scope| !granted.contains(scope)) {
+        Some(missing) => {
+            tracing::warn!(credential = name, scope = missing.as_str(), "scope missing");
+            Err(Error::Forbidden(format!(
+                "credential {name} lacks the {} scope",
+                missing.as_str()
+            )))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Whether `[auth]` is set and `bearer` is a credential holding `scope`.
+pub fn grants(state: &AppState, bearer: Option<&BearerToken>, scope: Scope) -> bool {
+    let Some(auth) = state.config().auth.clone() else {
+        return false;
+    };
+    bearer
+        .and_then(|token| credential(&auth, &token.0))
+        .is_some_and(|(_, granted)| granted.contains(&scope))
+}
+
+/// The name and scopes of the credential whose token is `given`.
+fn credential<'a>(
+    auth: &'a AuthConfig,
+    given: &str,
+) -> Option<(&'a str, &'a std::collections::BTreeSet<Scope>)> {
+    let given = sha256::Hash::hash(given.as_bytes());
+    auth.credentials
+        .iter()
+        .find(|(_, credential)| sha256::Hash::hash(credential.token.as_bytes()) == given)
+        .map(|(name, credential)| (name.as_str(), &credential.scopes))
+}
+
+/// Holds every route to the scopes [`required`] lists for it.
+pub async fn require(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let config = state.config();
+    if config.auth.is_none() {
+        return next.run(request).await;
+    }
+    let matched = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_default();
+    let prefix = crate::networks::prefix(config.network);
+    let path = match config.hosted {
+        true => matched.strip_prefix(&prefix).unwrap_or(&matched),
+        false => &matched,
+    };
+    let bearer = request
+        .headers()
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(BearerToken::parse);
+    let scopes = required(request.method(), path);
+    if scopes.is_empty() {
+        return next.run(request).await;
+    }
+    match authorize(&state, bearer.as_ref(), scopes) {
+        Ok(()) => next.run(request).await,
+        Err(e) => e.into_response(),
+    }
+}
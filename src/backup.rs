@@ -0,0 +1,309 @@
+//! Encrypted backups for offsite storage.
+//!
+//! With `[backup]` set, `GET /admin/backup` returns a bundle of what it
+//! takes to stand the wallet up again elsewhere: its public descriptors and
+//! first address, the registered BSMS wallets, the labels and the head of
+//! the audit log, and with `include_keys` the private descriptor and the
+//! software signer's xprv. The bundle is encrypted to
+//! `backup.recovery_pubkey`, whose secret key is kept offline, so the file
+//! can be stored anywhere. `issue-service decrypt-backup <backup> <key
+//! file>` opens it again on the recovery machine.
+//!
+//! Encryption is ECIES on secp256k1: ECDH between a fresh ephemeral key and
+//! the recovery key, HMAC-SHA256 of the shared secret into an AES-256-CTR
+//! key and a MAC key, and HMAC-SHA256 over the ephemeral key, IV and
+//! ciphertext.
+
+use std::{process::ExitCode, str::FromStr, sync::Arc};
+
+use aes::Aes256;
+use axum::{extract::State, Json};
+use bdk_wallet::{
+    descriptor::{Descriptor, DescriptorPublicKey},
+    KeychainKind,
+};
+use bitcoin::{
+    base64::{engine::general_purpose::STANDARD as BASE64, Engine},
+    hashes::{hmac, sha256, Hash, HashEngine},
+    key::Secp256k1,
+    secp256k1::{ecdh::SharedSecret, PublicKey, SecretKey},
+    Network,
+};
+use chrono::{DateTime, Utc};
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+
+use crate::{
+    audit::AuditHead,
+    bsms::WalletInfo,
+    config::{BackupConfig, Config, SignerConfig},
+    error::Error,
+    AppState,
+};
+
+const SCHEME: &str = "secp256k1-ecies-aes256ctr-hmacsha256";
+
+#[derive(Serialize, Deserialize)]
+pub struct Envelope {
+    pub version: u8,
+    pub scheme: String,
+    /// Key the bundle is encrypted to, so the right secret key can be found.
+    pub recovery_pubkey: String,
+    pub ephemeral_pubkey: String,
+    /// Hex AES-256-CTR IV.
+    pub iv: String,
+    /// Base64 encrypted bundle.
+    pub ciphertext: String,
+    /// Hex HMAC-SHA256 over the ephemeral key, IV and ciphertext.
+    pub mac: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+struct Bundle {
+    created_at: DateTime<Utc>,
+    service_version: &'static str,
+    network: Network,
+    /// Receive descriptor, public keys only.
+    descriptor: String,
+    change_descriptor: Option<String>,
+    first_address: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    bsms_wallets: Vec<WalletInfo>,
+    /// BIP-329 JSON Lines, when labels are kept.
+    labels: Option<String>,
+    /// When the audit log is configured.
+    audit: Option<AuditHead>,
+    /// With `include_keys`, when the service holds any.
+    keys: Option<Keys>,
+}
+
+#[derive(Serialize)]
+struct Keys {
+    /// Receive descriptor with its private key.
+    descriptor: Option<String>,
+    change_descriptor: Option<String>,
+    /// Master xprv of the `software` signer.
+    signer_xprv: Option<String>,
+}
+
+pub async fn export(State(state): State<Arc<AppState>>) -> Result<Json<Envelope>, Error> {
+    let config = state.config();
+    let policy = config
+        .backup
+        .as_ref()
+        .ok_or_else(|| Error::NotFound("backup is not configured".into()))?;
+    let recovery = PublicKey::from_str(&policy.recovery_pubkey)
+        .map_err(|e| Error::Internal(format!("backup.recovery_pubkey: {e}")))?;
+    let bundle = bundle(&state, &config, policy)?;
+    let plaintext = serde_json::to_vec(&bundle)
+        .map_err(|e| Error::Internal(format!("encoding backup: {e}")))?;
+    tracing::warn!(
+        keys = bundle.keys.is_some(),
+        recovery_pubkey = %recovery,
+        "exporting backup"
+    );
+    Ok(Json(seal(&recovery, &plaintext, bundle.created_at)))
+}
+
+fn bundle(state: &AppState, config: &Config, policy: &BackupConfig) -> Result<Bundle, Error> {
+    let (descriptor, change_descriptor, first_address) = {
+        let wallet = state.wallet();
+        (
+            wallet.public_descriptor(KeychainKind::External).to_string(),
+            config
+                .change_xprv
+                .is_some()
+                .then(|| wallet.public_descriptor(KeychainKind::Internal).to_string()),
+            wallet
+                .peek_address(KeychainKind::External, 0)
+                .address
+                .to_string(),
+        )
+    };
+    let audit = state
+        .audit
+        .as_ref()
+        .map(|audit| audit.head())
+        .transpose()
+        .map_err(|e| Error::Internal(format!("reading audit log: {e}")))?;
+    Ok(Bundle {
+        created_at: Utc::now(),
+        service_version: env!("CARGO_PKG_VERSION"),
+        network: config.network,
+        descriptor,
+        change_descriptor,
+        first_address,
+        bsms_wallets: state
+            .bsms
+            .as_ref()
+            .map(|bsms| bsms.wallets())
+            .unwrap_or_default(),
+        labels: state.labels.as_ref().map(|labels| labels.export()),
+        audit,
+        keys: policy.include_keys.then(|| keys(config)).flatten(),
+    })
+}
+
+/// The private keys `config` holds, if any.
+fn keys(config: &Config) -> Option<Keys> {
+    let secp = Secp256k1::new();
+    let private = |descriptor: &String| {
+        Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, descriptor)
+            .is_ok_and(|(_, keys)| !keys.is_empty())
+            .then(|| descriptor.clone())
+    };
+    let keys = Keys {
+        descriptor: (!config.throwaway_key)
+            .then(|| private(&config.xprv))
+            .flatten(),
+        change_descriptor: config.change_xprv.as_ref().and_then(private),
+        signer_xprv: match &config.signer {
+            Some(SignerConfig::Software { xprv }) => Some(xprv.to_string()),
+            _ => None,
+        },
+    };
+    let held = keys.descriptor.is_some() || keys.signer_xprv.is_some();
+    held.then_some(keys)
+}
+
+/// Encrypts `plaintext` to `recovery`.
+fn seal(recovery: &PublicKey, plaintext: &[u8], created_at: DateTime<Utc>) -> Envelope {
+    let secp = Secp256k1::new();
+    let ephemeral = std::iter::repeat_with(rand::random::<[u8; 32]>)
+        .find_map(|bytes| SecretKey::from_slice(&bytes).ok())
+        .expect("a valid key");
+    let ephemeral_pubkey = PublicKey::from_secret_key(&secp, &ephemeral);
+    let (key, mac_key) = derive_keys(&SharedSecret::new(recovery, &ephemeral));
+    let iv: [u8; 16] = rand::random();
+    let mut ciphertext = plaintext.to_vec();
+    ctr::Ctr128BE::<Aes256>::new(&key.into(), &iv.into()).apply_keystream(&mut ciphertext);
+    let mac = mac(&mac_key, &ephemeral_pubkey, &iv, &ciphertext);
+    Envelope {
+        version: 1,
+        scheme: SCHEME.into(),
+        recovery_pubkey: recovery.to_string(),
+        ephemeral_pubkey: ephemeral_pubkey.to_string(),
+        iv: hex::encode(iv),
+        ciphertext: BASE64.encode(ciphertext),
+        mac: hex::encode(mac),
+        created_at,
+    }
+}
+
+/// Decrypts `envelope` with the recovery secret key.
+fn open(envelope: &Envelope, recovery: &SecretKey) -> Result<Vec<u8>, String> {
+    if envelope.version != 1 || envelope.scheme != SCHEME {
+        return Err(format!(
+            "unsupported backup version {} ({})",
+            envelope.version, envelope.scheme
+        ));
+    }
+    let ephemeral_pubkey = PublicKey::from_str(&envelope.ephemeral_pubkey)
+        .map_err(|e| format!("ephemeral_pubkey: {e}"))?;
+    let iv: [u8; 16] = hex::decode(&envelope.iv)
+        .ok()
+        .and_then(|iv| iv.try_into().ok())
+        .ok_or("iv: not 16 hex bytes")?;
+    let mut data = BASE64
+        .decode(&envelope.ciphertext)
+        .map_err(|e| format!("ciphertext: {e}"))?;
+    let (key, mac_key) = derive_keys(&SharedSecret::new(&ephemeral_pubkey, recovery));
+    let expected = mac(&mac_key, &ephemeral_pubkey, &iv, &data);
+    let matches =
+        hex::decode(&envelope.mac).is_ok_and(|mac| bool::from(mac.as_slice().ct_eq(&expected[..])));
+    if !matches {
+        return Err("the MAC does not match: wrong recovery key or a damaged backup".into());
+    }
+    ctr::Ctr128BE::<Aes256>::new(&key.into(), &iv.into()).apply_keystream(&mut data);
+    Ok(data)
+}
+
+/// The encryption and MAC keys for `shared`.
+fn derive_keys(shared: &SharedSecret) -> ([u8; 32], [u8; 32]) {
+    let derive = |label: &[u8]| {
+        let mut engine = hmac::HmacEngine::<sha256::Hash>::new(&shared.secret_bytes());
+        engine.input(label);
+        hmac::Hmac::<sha256::Hash>::from_engine(engine).to_byte_array()
+    };
+    (
+        derive(b"issue-service backup encryption"),
+        derive(b"issue-service backup mac"),
+    )
+}
+
+fn mac(key: &[u8; 32], ephemeral: &PublicKey, iv: &[u8; 16], ciphertext: &[u8]) -> [u8; 32] {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(key);
+    engine.input(&ephemeral.serialize());
+    engine.input(iv);
+    engine.input(ciphertext);
+    hmac::Hmac::<sha256::Hash>::from_engine(engine).to_byte_array()
+}
+
+/// Runs `decrypt-backup <backup> <key file>`, writing the bundle to stdout.
+/// The key file holds the recovery secret key in hex.
+pub fn run(mut args: impl Iterator<Item = String>) -> ExitCode {
+    let (Some(backup), Some(key_file)) = (args.next(), args.next()) else {
+        eprintln!("usage: issue-service decrypt-backup <backup> <key file>");
+        return ExitCode::FAILURE;
+    };
+    let result = (|| {
+        let envelope: Envelope =
+            serde_json::from_slice(&std::fs::read(&backup).map_err(|e| format!("{backup}: {e}"))?)
+                .map_err(|e| format!("{backup}: {e}"))?;
+        let key = std::fs::read_to_string(&key_file)
+            .map_err(|e| format!("{key_file}: {e}"))?
+            .trim()
+            .parse::<SecretKey>()
+            .map_err(|e| format!("{key_file}: {e}"))?;
+        String::from_utf8(open(&envelope, &key)?).map_err(|e| e.to_string())
+    })();
+    match result {
+        Ok(bundle) => {
+            println!("{bundle}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recovery() -> (SecretKey, PublicKey) {
+        let secret = SecretKey::from_slice(&[7; 32]).unwrap();
+        (
+            secret,
+            PublicKey::from_secret_key(&Secp256k1::new(), &secret),
+        )
+    }
+
+    #[test]
+    fn opens_what_it_seals() {
+        let (secret, public) = recovery();
+        let envelope = seal(&public, b"bundle", Utc::now());
+        assert_eq!(open(&envelope, &secret).unwrap(), b"bundle");
+    }
+
+    #[test]
+    fn refuses_a_mac_that_does_not_match() {
+        let (secret, public) = recovery();
+        let flip = |mac: &mut Vec<u8>| mac[31] ^= 1;
+        let truncate = |mac: &mut Vec<u8>| mac.truncate(31);
+        for tamper in [flip as fn(&mut Vec<u8>), truncate] {
+            let mut envelope = seal(&public, b"bundle", Utc::now());
+            let mut mac = hex::decode(&envelope.mac).unwrap();
+            tamper(&mut mac);
+            envelope.mac = hex::encode(mac);
+            assert!(open(&envelope, &secret).unwrap_err().contains("MAC"));
+        }
+        let envelope = seal(&public, b"bundle", Utc::now());
+        let other = SecretKey::from_slice(&[8; 32]).unwrap();
+        assert!(open(&envelope, &other).unwrap_err().contains("MAC"));
+    }
+}
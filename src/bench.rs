@@ -0,0 +1,255 @@
+//! `issue-service bench <config>`: measures signing throughput and latency
+//! on this machine with the configured key, for capacity planning.
+//!
+//! Synthetic PSBTs spending outputs of the wallet's own receive addresses
+//! are signed with the same wallet, key cache and sign options as real
+//! requests. Policy checks, the audit log and HTTP are left out, so the
+//! numbers are an upper bound for `/sign_psbt`.
+
+use std::{
+    path::Path,
+    process::ExitCode,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use bdk_wallet::{miniscript::psbt::PsbtExt, KeychainKind, SignOptions, Wallet};
+use bitcoin::{
+    absolute::LockTime,
+    hashes::{sha256, Hash},
+    transaction::Version,
+    Amount, OutPoint, Psbt, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+
+use crate::config::Config;
+
+const USAGE: &str = "usage: issue-service bench <config> [--psbts N] [--inputs N] [--outputs N] \
+                     [--threads N]";
+const OUTPUT_VALUE: Amount = Amount::from_sat(100_000);
+/// Fee left per input, so the synthetic transactions look sane.
+const FEE_PER_INPUT: Amount = Amount::from_sat(200);
+
+struct Options {
+    psbts: usize,
+    inputs: usize,
+    outputs: usize,
+    /// Defaults to `signing.max_concurrent`.
+    threads: Option<usize>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            psbts: 200,
+            inputs: 10,
+            outputs: 2,
+            threads: None,
+        };
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("{flag} needs a value"))?
+                .parse::<usize>()
+                .map_err(|e| format!("{flag}: {e}"))?;
+            if value == 0 {
+                return Err(format!("{flag} must be at least 1"));
+            }
+            match flag.as_str() {
+                "--psbts" => options.psbts = value,
+                "--inputs" => options.inputs = value,
+                "--outputs" => options.outputs = value,
+                "--threads" => options.threads = Some(value),
+                _ => return Err(format!("unknown option {flag}")),
+            }
+        }
+        Ok(options)
+    }
+}
+
+pub fn run(config_path: &Path, args: impl Iterator<Item = String>) -> ExitCode {
+    match bench(config_path, args) {
+        Ok(report) => {
+            for line in report {
+                println!("{line}");
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            eprintln!("{USAGE}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn bench(config_path: &Path, args: impl Iterator<Item = String>) -> Result<Vec<String>, String> {
+    let options = Options::parse(args)?;
+    let config = Config::load(config_path).map_err(|e| e.to_string())?;
+    let wallet = crate::create_wallet(&config).map_err(|e| format!("xprv: {e}"))?;
+    let threads = options
+        .threads
+        .unwrap_or(config.signing.max_concurrent)
+        .max(1);
+    let previous = funding_tx(&wallet, options.inputs)?;
+    let template = spending_psbt(&wallet, &previous, options.outputs)?;
+    let sign_options = crate::sign_options::resolve(&config.sign_options, None)
+        .map_err(|e| e.to_string())?
+        .options;
+
+    // Checks the PSBT is signable, and warms up the key cache like a
+    // running service would be.
+    let mut warmup = template.clone();
+    sign(&wallet, &mut warmup, &sign_options)?;
+
+    let next = AtomicUsize::new(0);
+    let started = Instant::now();
+    let mut latencies = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut latencies = Vec::new();
+                    while next.fetch_add(1, Ordering::Relaxed) < options.psbts {
+                        let mut psbt = template.clone();
+                        let start = Instant::now();
+                        sign(&wallet, &mut psbt, &sign_options)?;
+                        latencies.push(start.elapsed());
+                    }
+                    Ok::<_, String>(latencies)
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("bench worker panicked"))
+            .collect::<Result<Vec<_>, _>>()
+    })?
+    .concat();
+    let elapsed = started.elapsed();
+    latencies.sort();
+
+    let per_sec = |count: usize| count as f64 / elapsed.as_secs_f64();
+    let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+    let script_type = crate::descriptor::script_type(
+        wallet.public_descriptor(KeychainKind::External).desc_type(),
+    );
+    Ok(vec![
+        format!(
+            "signed {} {script_type} PSBTs ({} inputs, {} outputs, {} bytes) in {:.3}s, threads: {threads}",
+            options.psbts,
+            options.inputs,
+            options.outputs,
+            template.serialize().len(),
+            elapsed.as_secs_f64(),
+        ),
+        format!(
+            "throughput: {:.1} PSBTs/s, {:.0} inputs/s",
+            per_sec(options.psbts),
+            per_sec(options.psbts * options.inputs),
+        ),
+        format!(
+            "latency: p50 {}, p90 {}, p99 {}, max {}",
+            millis(percentile(50)),
+            millis(percentile(90)),
+            millis(percentile(99)),
+            millis(percentile(100)),
+        ),
+    ])
+}
+
+fn sign(wallet: &Wallet, psbt: &mut Psbt, sign_options: &SignOptions) -> Result<(), String> {
+    wallet
+        .sign(psbt, sign_options.clone())
+        .map_err(|e| format!("signing failed: {e}"))?;
+    let unsigned = psbt.inputs.iter().position(|input| {
+        input.partial_sigs.is_empty()
+            && input.tap_key_sig.is_none()
+            && input.final_script_sig.is_none()
+            && input.final_script_witness.is_none()
+    });
+    match unsigned {
+        Some(index) => Err(format!("wallet left input {index} unsigned")),
+        None => Ok(()),
+    }
+}
+
+/// A synthetic transaction whose output `i` pays receive address `i`.
+pub fn funding_tx(wallet: &Wallet, outputs: usize) -> Result<Transaction, String> {
+    let mut prevouts = Vec::with_capacity(outputs);
+    for i in 0..outputs {
+        prevouts.push(TxOut {
+            value: OUTPUT_VALUE,
+            script_pubkey: wallet
+                .peek_address(KeychainKind::External, index(i)?)
+                .address
+                .script_pubkey(),
+        });
+    }
+    let seed = sha256::Hash::hash(b"issue-service bench");
+    Ok(Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array(seed.to_byte_array()), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: prevouts,
+    })
+}
+
+/// A PSBT spending every output of `previous`, a [`funding_tx`], back to
+/// receive address 0 in `outputs` equal parts.
+pub fn spending_psbt(
+    wallet: &Wallet,
+    previous: &Transaction,
+    outputs: usize,
+) -> Result<Psbt, String> {
+    let descriptor = wallet.public_descriptor(KeychainKind::External);
+    let inputs = previous.output.len();
+    let previous_txid = previous.compute_txid();
+    let total = OUTPUT_VALUE * inputs as u64 - FEE_PER_INPUT * inputs as u64;
+    let payee = wallet
+        .peek_address(KeychainKind::External, 0)
+        .address
+        .script_pubkey();
+    let spend = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: (0..inputs)
+            .map(|i| TxIn {
+                previous_output: OutPoint::new(previous_txid, i as u32),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: (0..outputs)
+            .map(|_| TxOut {
+                value: total / outputs as u64,
+                script_pubkey: payee.clone(),
+            })
+            .collect(),
+    };
+
+    let mut psbt = Psbt::from_unsigned_tx(spend).map_err(|e| format!("building PSBT: {e}"))?;
+    for i in 0..inputs {
+        // Both, so it signs without `trust_witness_utxo`.
+        psbt.inputs[i].witness_utxo = Some(previous.output[i].clone());
+        psbt.inputs[i].non_witness_utxo = Some(previous.clone());
+        let derived = descriptor
+            .at_derivation_index(index(i)?)
+            .map_err(|e| format!("deriving address {i}: {e}"))?;
+        psbt.update_input_with_descriptor(i, &derived)
+            .map_err(|e| format!("input {i}: {e}"))?;
+    }
+    Ok(psbt)
+}
+
+fn index(i: usize) -> Result<u32, String> {
+    u32::try_from(i).map_err(|_| "--inputs is too large".to_owned())
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.2}ms", duration.as_secs_f64() * 1000.0)
+}
@@ -0,0 +1,225 @@
+//! Command-line client for a running issue-service.
+//!
+//! ```text
+//! issue-cli [--url URL] [--network NETWORK] <command>
+//!
+//!   health                 liveness check
+//!   ready                  readiness and signing self-test status
+//!   version                build and network information
+//!   sign <psbt> [-o out]   sign a PSBT file (binary or base64)
+//!   decode <psbt>          describe a PSBT file locally, without the service
+//! ```
+//!
+//! The service URL defaults to `$ISSUE_SERVICE_URL`, then
+//! `http://127.0.0.1:3001`. `--network` only affects how `decode` renders
+//! addresses.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+};
+
+use bitcoin::{Address, Network, Psbt};
+use serde_json::{json, Value};
+
+const DEFAULT_URL: &str = "http://127.0.0.1:3001";
+
+const USAGE: &str = "usage: issue-cli [--url URL] [--network NETWORK] <health|ready|version|sign <psbt> [-o out]|decode <psbt>>";
+
+enum Command {
+    Health,
+    Ready,
+    Version,
+    Sign {
+        input: PathBuf,
+        output: Option<PathBuf>,
+    },
+    Decode {
+        input: PathBuf,
+    },
+}
+
+struct Args {
+    url: String,
+    network: Network,
+    command: Command,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut url = std::env::var("ISSUE_SERVICE_URL").unwrap_or_else(|_| DEFAULT_URL.to_owned());
+    let mut network = Network::Bitcoin;
+    let mut output = None;
+    let mut positional = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--url" => url = args.next().ok_or("--url needs a value")?,
+            "--network" => {
+                let value = args.next().ok_or("--network needs a value")?;
+                network = value
+                    .parse()
+                    .map_err(|e| format!("--network {value}: {e}"))?;
+            }
+            "-o" | "--output" => {
+                output = Some(PathBuf::from(args.next().ok_or("-o needs a path")?));
+            }
+            "-h" | "--help" => return Err(USAGE.to_owned()),
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let command = match positional.next().as_deref() {
+        Some("health") => Command::Health,
+        Some("ready") => Command::Ready,
+        Some("version") => Command::Version,
+        Some("sign") => Command::Sign {
+            input: positional.next().ok_or("sign needs a PSBT file")?.into(),
+            output,
+        },
+        Some("decode") => Command::Decode {
+            input: positional.next().ok_or("decode needs a PSBT file")?.into(),
+        },
+        Some(other) => return Err(format!("unknown command `{other}`\n{USAGE}")),
+        None => return Err(USAGE.to_owned()),
+    };
+    if let Some(extra) = positional.next() {
+        return Err(format!("unexpected argument `{extra}`\n{USAGE}"));
+    }
+    Ok(Args {
+        url: url.trim_end_matches('/').to_owned(),
+        network,
+        command,
+    })
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::from(2);
+        }
+    };
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<(), String> {
+    let client = reqwest::Client::new();
+    match args.command {
+        Command::Health => {
+            let body = send(client.get(format!("{}/health", args.url))).await?;
+            println!("{}", body.as_str().unwrap_or("ok"));
+        }
+        Command::Ready => print(&send(client.get(format!("{}/ready", args.url))).await?),
+        Command::Version => print(&send(client.get(format!("{}/version", args.url))).await?),
+        Command::Sign { input, output } => {
+            let psbt = read_psbt(&input)?;
+            let request = client
+                .post(format!("{}/sign_psbt", args.url))
+                .json(&json!({ "psbt": psbt.to_string() }));
+            let body = send(request).await?;
+            let signed = body["psbt"].as_str().ok_or("response is missing `psbt`")?;
+            match output {
+                Some(path) => {
+                    fs::write(&path, format!("{signed}\n"))
+                        .map_err(|e| format!("writing {}: {e}", path.display()))?;
+                    eprintln!("signed PSBT written to {}", path.display());
+                }
+                None => println!("{signed}"),
+            }
+        }
+        Command::Decode { input } => print(&decode(&read_psbt(&input)?, args.network)),
+    }
+    Ok(())
+}
+
+/// Sends `request`, returning the JSON (or plain text) body of a success and
+/// the error envelope's message otherwise.
+async fn send(request: reqwest::RequestBuilder) -> Result<Value, String> {
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    let status = response.status();
+    let text = response.text().await.map_err(|e| e.to_string())?;
+    let body = serde_json::from_str(&text).unwrap_or(Value::String(text));
+    // `/ready` answers 503 with a regular body when the self-test failed.
+    let not_ready =
+        status == reqwest::StatusCode::SERVICE_UNAVAILABLE && body.get("ready").is_some();
+    if status.is_success() || not_ready {
+        return Ok(body);
+    }
+    match body.get("error") {
+        Some(error) => Err(format!(
+            "{status}: {} ({})",
+            error["message"].as_str().unwrap_or_default(),
+            error["code"].as_str().unwrap_or("unknown"),
+        )),
+        None => Err(format!("{status}: {body}")),
+    }
+}
+
+fn print(body: &Value) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(body).expect("serialize response")
+    );
+}
+
+/// Reads a PSBT stored either in binary BIP-174 form or as base64 text.
+fn read_psbt(path: &Path) -> Result<Psbt, String> {
+    let bytes = fs::read(path).map_err(|e| format!("reading {}: {e}", path.display()))?;
+    let psbt = if bytes.starts_with(b"psbt\xff") {
+        Psbt::deserialize(&bytes).map_err(|e| e.to_string())
+    } else {
+        let text = String::from_utf8(bytes).map_err(|_| "not a PSBT file".to_owned())?;
+        Psbt::from_str(text.trim()).map_err(|e| e.to_string())
+    };
+    psbt.map_err(|e| format!("{}: {e}", path.display()))
+}
+
+fn decode(psbt: &Psbt, network: Network) -> Value {
+    let tx = &psbt.unsigned_tx;
+    let inputs: Vec<Value> = tx
+        .input
+        .iter()
+        .zip(&psbt.inputs)
+        .map(|(txin, input)| {
+            json!({
+                "previous_output": txin.previous_output.to_string(),
+                "value_sat": input.witness_utxo.as_ref().map(|utxo| utxo.value.to_sat()),
+                "partial_sigs": input.partial_sigs.len(),
+                "tap_key_sig": input.tap_key_sig.is_some(),
+                "tap_script_sigs": input.tap_script_sigs.len(),
+                "finalized": input.final_script_sig.is_some() || input.final_script_witness.is_some(),
+            })
+        })
+        .collect();
+    let outputs: Vec<Value> = tx
+        .output
+        .iter()
+        .map(|txout| {
+            json!({
+                "value_sat": txout.value.to_sat(),
+                "address": Address::from_script(&txout.script_pubkey, network).ok().map(|a| a.to_string()),
+                "script_pubkey": txout.script_pubkey.to_hex_string(),
+            })
+        })
+        .collect();
+    json!({
+        "txid": tx.compute_txid().to_string(),
+        "version": tx.version.0,
+        "lock_time": tx.lock_time.to_consensus_u32(),
+        "fee_sat": psbt.fee().ok().map(|fee| fee.to_sat()),
+        "inputs": inputs,
+        "outputs": outputs,
+    })
+}
@@ -0,0 +1,130 @@
+//! BIP-21 payment URIs for the wallet's next receive address.
+//!
+//! Every call reveals a fresh external address. Revealed indices are not
+//! persisted: after a restart they start over from the last index the chain
+//! backend has seen used, or from zero without `[chain]`.
+
+use std::sync::Arc;
+
+use axum::{extract::State, Json};
+use bdk_wallet::KeychainKind;
+use bitcoin::{Amount, Denomination};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{ApiJson, Error, ErrorResponse},
+    AppState,
+};
+
+#[derive(Deserialize, Default, utoipa::ToSchema)]
+#[serde(default)]
+pub struct PaymentUriRequest {
+    /// Amount requested, in satoshis.
+    pub amount_sat: Option<u64>,
+    /// Label for the recipient, e.g. an invoice number.
+    pub label: Option<String>,
+    /// Message shown to the payer.
+    pub message: Option<String>,
+    /// Add a BIP-78 `pj` endpoint. Needs `public_url` and `[chain]`.
+    pub payjoin: bool,
+    /// Add the wallet's BIP-352 silent payment address as `sp`. Needs
+    /// `[silent_payments]`.
+    pub silent_payment: bool,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct PaymentUriResponse {
+    #[schema(example = "bitcoin:bc1q...?amount=0.001&label=Invoice%2042")]
+    pub uri: String,
+    pub address: String,
+    /// Derivation index of `address` on the external keychain.
+    pub index: u32,
+}
+
+#[utoipa::path(
+    post,
+    path = "/payment_uri",
+    request_body = PaymentUriRequest,
+    responses(
+        (status = 200, description = "Payment URI for a fresh address", body = PaymentUriResponse),
+        (status = 400, description = "`malformed_request`", body = ErrorResponse),
+        (status = 404, description = "`not_found`: a requested parameter is not configured", body = ErrorResponse),
+        (status = 415, description = "`unsupported_media_type`", body = ErrorResponse),
+    )
+)]
+pub async fn payment_uri(
+    State(state): State<Arc<AppState>>,
+    ApiJson(request): ApiJson<PaymentUriRequest>,
+) -> Result<Json<PaymentUriResponse>, Error> {
+    let config = state.config();
+    let mut params = Vec::new();
+    if let Some(amount) = request.amount_sat {
+        if amount == 0 || Amount::from_sat(amount) > Amount::MAX_MONEY {
+            return Err(Error::MalformedRequest(format!(
+                "amount_sat {amount} is out of range"
+            )));
+        }
+        params.push((
+            "amount",
+            Amount::from_sat(amount).to_string_in(Denomination::Bitcoin),
+        ));
+    }
+    if let Some(label) = &request.label {
+        params.push(("label", encode(label)));
+    }
+    if let Some(message) = &request.message {
+        params.push(("message", encode(message)));
+    }
+    if request.payjoin {
+        let public_url = config
+            .public_url
+            .as_ref()
+            .ok_or_else(|| Error::NotFound("payjoin needs `public_url`".into()))?;
+        if config.chain.is_none() {
+            return Err(Error::NotFound("payjoin needs [chain]".into()));
+        }
+        let endpoint = format!("{}/payjoin", public_url.trim_end_matches('/'));
+        params.push(("pj", encode(&endpoint)));
+    }
+    if request.silent_payment {
+        let sp = state
+            .silent_payments
+            .as_ref()
+            .ok_or_else(|| Error::NotFound("silent payments are not configured".into()))?;
+        params.push(("sp", sp.address()));
+    }
+
+    let info = state
+        .wallet
+        .reveal_next_address(KeychainKind::External)
+        .await?;
+    tracing::info!(
+        index = info.index,
+        "revealed receive address for a payment URI"
+    );
+
+    let mut uri = format!("bitcoin:{}", info.address);
+    for (index, (key, value)) in params.iter().enumerate() {
+        let separator = if index == 0 { '?' } else { '&' };
+        uri.push_str(&format!("{separator}{key}={value}"));
+    }
+    Ok(Json(PaymentUriResponse {
+        uri,
+        address: info.address.to_string(),
+        index: info.index,
+    }))
+}
+
+/// Percent-encodes everything but RFC 3986 unreserved characters and the
+/// `:` and `/` of URLs.
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
@@ -0,0 +1,497 @@
+//! Signer side of BIP-129 Bitcoin Secure Multisig Setup (BSMS).
+//!
+//! Round 1 hands the coordinator a signed key record for this signer's
+//! multisig key. Round 2 verifies the coordinator's descriptor record, which
+//! must contain that key and derive the announced first address, and
+//! registers the resulting wallet. Registered wallets sign alongside the
+//! main one.
+//!
+//! The multisig key is derived from the descriptor's xprv at
+//! `48'/<coin>'/0'/2'` (BIP-48 P2WSH), `coin` being `0` on mainnet and `1`
+//! elsewhere. Registered wallets are saved to `bsms.wallets_path` with
+//! public descriptors only; the private key is put back when they are
+//! loaded.
+
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use aes::Aes256;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use bdk_wallet::{
+    descriptor::{Descriptor, DescriptorPublicKey},
+    keys::{DescriptorPublicKey as Key, DescriptorSecretKey},
+    miniscript::{
+        descriptor::{checksum::desc_checksum, DescriptorXKey, Wildcard},
+        ForEachKey,
+    },
+    KeychainKind, SignOptions, Wallet,
+};
+use bitcoin::{
+    bip32::{DerivationPath, Xpriv, Xpub},
+    hashes::{hmac, sha256, sha512, Hash, HashEngine},
+    key::Secp256k1,
+    secp256k1::Message,
+    sign_message::{signed_msg_hash, MessageSignature},
+    Network, Psbt,
+};
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{BsmsConfig, Config},
+    error::{ApiJson, Error},
+    storage::Storage,
+    AppState,
+};
+
+const VERSION: &str = "BSMS 1.0";
+const NO_ENCRYPTION: &str = "00";
+const NO_PATH_RESTRICTIONS: &str = "No path restrictions";
+const DEFAULT_PATH_RESTRICTIONS: &str = "/0/*,/1/*";
+/// PBKDF2 salt of the record encryption key.
+const KDF_SALT: &[u8] = b"No SPOF";
+const KDF_ROUNDS: u32 = 2048;
+
+pub struct Bsms {
+    /// This signer's key as written in records: `[fingerprint/path]xpub`.
+    key: String,
+    xpub: Xpub,
+    xprv: Xpriv,
+    network: Network,
+    storage: Arc<dyn Storage>,
+    name: String,
+    wallets: RwLock<BTreeMap<String, Multisig>>,
+}
+
+struct Multisig {
+    info: WalletInfo,
+    wallet: Wallet,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletInfo {
+    pub name: String,
+    /// Receive descriptor, public keys only.
+    pub descriptor: String,
+    /// Change descriptor, when the record allows a change branch.
+    pub change_descriptor: Option<String>,
+    pub first_address: String,
+}
+
+#[derive(Deserialize)]
+pub struct KeyRecordRequest {
+    /// Session token from the coordinator, `00` when records are not
+    /// encrypted.
+    pub token: String,
+    /// Label for this signer shown to the coordinator.
+    pub description: String,
+}
+
+#[derive(Serialize)]
+pub struct KeyRecordResponse {
+    /// The key record, hex-encoded when the token requires encryption.
+    pub record: String,
+}
+
+#[derive(Deserialize)]
+pub struct Registration {
+    pub name: String,
+    pub token: String,
+    /// The coordinator's descriptor record, as received.
+    pub descriptor_record: String,
+}
+
+/// A session token: no encryption, or the 8 or 16 bytes records are
+/// encrypted with.
+struct Token {
+    hex: String,
+    secret: Option<Vec<u8>>,
+}
+
+impl FromStr for Token {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let s = s.trim();
+        if s == NO_ENCRYPTION {
+            return Ok(Token {
+                hex: s.to_owned(),
+                secret: None,
+            });
+        }
+        match hex::decode(s) {
+            Ok(bytes) if bytes.len() == 8 || bytes.len() == 16 => Ok(Token {
+                hex: s.to_lowercase(),
+                secret: Some(bytes),
+            }),
+            _ => Err(Error::MalformedRequest(
+                "token must be `00` or 8 or 16 hex-encoded bytes".into(),
+            )),
+        }
+    }
+}
+
+impl Bsms {
+    pub fn new(
+        storage: Arc<dyn Storage>,
+        config: &Config,
+        bsms: &BsmsConfig,
+    ) -> Result<Self, String> {
+        let secp = Secp256k1::new();
+        let (_, keys) = Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, &config.xprv)
+            .map_err(|e| format!("descriptor: {e}"))?;
+        let root = keys
+            .values()
+            .find_map(|key| match key {
+                DescriptorSecretKey::XPrv(xkey) => Some(xkey.clone()),
+                _ => None,
+            })
+            .ok_or("the descriptor has no xprv to derive a multisig key from")?;
+        let coin = if config.network == Network::Bitcoin {
+            0
+        } else {
+            1
+        };
+        let path = DerivationPath::from_str(&format!("m/48'/{coin}'/0'/2'"))
+            .expect("valid derivation path");
+        let xprv = root
+            .xkey
+            .derive_priv(&secp, &path)
+            .map_err(|e| format!("deriving the multisig key: {e}"))?;
+        let xpub = Xpub::from_priv(&secp, &xprv);
+        let origin = match &root.origin {
+            Some((fingerprint, prefix)) => (*fingerprint, prefix.extend(&path)),
+            None => (root.xkey.fingerprint(&secp), path),
+        };
+        let key = Key::XPub(DescriptorXKey {
+            origin: Some(origin),
+            xkey: xpub,
+            derivation_path: DerivationPath::default(),
+            wildcard: Wildcard::None,
+        })
+        .to_string();
+
+        let bsms = Bsms {
+            key,
+            xpub,
+            xprv,
+            network: config.network,
+            storage,
+            name: crate::storage::name(&bsms.wallets_path),
+            wallets: Default::default(),
+        };
+        let stored = bsms
+            .storage
+            .read(&bsms.name)
+            .map_err(|e| e.to_string())
+            .and_then(|stored| {
+                stored
+                    .map(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+                    .transpose()
+            })
+            .map_err(|e| format!("{}: {e}", bsms.name))?;
+        if let Some(stored) = stored {
+            let stored: Vec<WalletInfo> = stored;
+            let mut wallets = bsms.wallets.write().expect("bsms wallets");
+            for info in stored {
+                let wallet = bsms
+                    .signing_wallet(&info)
+                    .map_err(|e| format!("multisig wallet {}: {e}", info.name))?;
+                wallets.insert(info.name.clone(), Multisig { info, wallet });
+            }
+            tracing::info!(count = wallets.len(), "loaded multisig wallets");
+        }
+        Ok(bsms)
+    }
+
+    /// Round 1: the signed key record for `token`.
+    fn key_record(&self, token: &Token, description: &str) -> Result<String, Error> {
+        if description.len() > 80 || description.contains('\n') {
+            return Err(Error::MalformedRequest(
+                "description must be a single line of at most 80 characters".into(),
+            ));
+        }
+        let lines = [VERSION, &token.hex, &self.key, description];
+        let message = lines.join("\n");
+        let secp = Secp256k1::signing_only();
+        let digest = Message::from_digest(signed_msg_hash(&message).to_byte_array());
+        let signature = MessageSignature {
+            signature: secp.sign_ecdsa_recoverable(&digest, &self.xprv.private_key),
+            compressed: true,
+        };
+        let record = format!("{message}\n{}", signature.to_base64());
+        Ok(match &token.secret {
+            Some(secret) => encrypt(secret, &record),
+            None => record,
+        })
+    }
+
+    /// Round 2: verifies `record` and registers its wallet as `name`.
+    fn register(&self, name: String, token: &Token, record: &str) -> Result<WalletInfo, Error> {
+        let invalid =
+            |reason: &str| Error::MalformedRequest(format!("descriptor record: {reason}"));
+        let record = match &token.secret {
+            Some(secret) => decrypt(secret, record.trim())?,
+            None => record.to_owned(),
+        };
+        let lines: Vec<&str> = record
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        let [version, descriptor, restrictions, first_address] = lines.as_slice() else {
+            return Err(invalid("expected 4 lines"));
+        };
+        if *version != VERSION {
+            return Err(invalid("unsupported version"));
+        }
+        let template = match descriptor.split_once('#') {
+            Some((template, checksum)) => {
+                if desc_checksum(template).ok().as_deref() != Some(checksum) {
+                    return Err(invalid("descriptor checksum mismatch"));
+                }
+                template
+            }
+            None => descriptor,
+        };
+        if *restrictions != NO_PATH_RESTRICTIONS && *restrictions != DEFAULT_PATH_RESTRICTIONS {
+            return Err(invalid("only `/0/*,/1/*` path restrictions are supported"));
+        }
+
+        let descriptor =
+            Descriptor::<DescriptorPublicKey>::from_str(&template.replace("/**", "/<0;1>/*"))
+                .map_err(|e| invalid(&e.to_string()))?;
+        let ours = descriptor.for_any_key(|key| match key {
+            Key::XPub(xkey) => xkey.xkey == self.xpub,
+            Key::MultiXPub(xkey) => xkey.xkey == self.xpub,
+            Key::Single(_) => false,
+        });
+        if !ours {
+            return Err(invalid("the descriptor does not contain this signer's key"));
+        }
+        let (receive, change) = if descriptor.is_multipath() {
+            match descriptor
+                .into_single_descriptors()
+                .map_err(|e| invalid(&e.to_string()))?
+                .as_slice()
+            {
+                [receive, change] => (receive.clone(), Some(change.clone())),
+                _ => return Err(invalid("only receive and change branches are supported")),
+            }
+        } else {
+            (descriptor, None)
+        };
+        let address = receive
+            .at_derivation_index(0)
+            .map_err(|e| invalid(&e.to_string()))?
+            .address(self.network)
+            .map_err(|e| invalid(&e.to_string()))?;
+        if address.to_string() != *first_address {
+            return Err(invalid(&format!(
+                "the descriptor's first address is {address}, not {first_address}"
+            )));
+        }
+
+        let info = WalletInfo {
+            name,
+            descriptor: receive.to_string(),
+            change_descriptor: change.map(|change| change.to_string()),
+            first_address: address.to_string(),
+        };
+        let wallet = self
+            .signing_wallet(&info)
+            .map_err(|e| Error::Internal(format!("multisig wallet: {e}")))?;
+        let mut wallets = self.wallets.write().expect("bsms wallets");
+        if wallets.contains_key(&info.name) {
+            return Err(Error::MalformedRequest(format!(
+                "wallet {} is already registered",
+                info.name
+            )));
+        }
+        wallets.insert(
+            info.name.clone(),
+            Multisig {
+                info: info.clone(),
+                wallet,
+            },
+        );
+        if let Err(e) = self.save(&wallets) {
+            wallets.remove(&info.name);
+            return Err(Error::Internal(format!("saving multisig wallets: {e}")));
+        }
+        tracing::info!(
+            wallet = info.name,
+            first_address = info.first_address,
+            "registered multisig wallet"
+        );
+        Ok(info)
+    }
+
+    /// Builds the wallet of `info` with this signer's xprv in place of its
+    /// xpub.
+    fn signing_wallet(&self, info: &WalletInfo) -> Result<Wallet, String> {
+        let with_xprv = |descriptor: &str| {
+            let body = descriptor.split('#').next().unwrap_or_default();
+            body.replace(&self.xpub.to_string(), &self.xprv.to_string())
+        };
+        let wallet = match &info.change_descriptor {
+            Some(change) => Wallet::create(with_xprv(&info.descriptor), with_xprv(change))
+                .network(self.network)
+                .create_wallet_no_persist(),
+            None => Wallet::create_single(with_xprv(&info.descriptor))
+                .network(self.network)
+                .create_wallet_no_persist(),
+        }
+        .map_err(|e| e.to_string())?;
+        if wallet
+            .get_signers(KeychainKind::External)
+            .signers()
+            .is_empty()
+        {
+            return Err("the descriptor does not contain this signer's key".into());
+        }
+        Ok(wallet)
+    }
+
+    fn save(&self, wallets: &BTreeMap<String, Multisig>) -> std::io::Result<()> {
+        let infos: Vec<&WalletInfo> = wallets.values().map(|multisig| &multisig.info).collect();
+        let json = serde_json::to_vec_pretty(&infos).expect("serialize multisig wallets");
+        self.storage.write(&self.name, &json)
+    }
+
+    /// Adds the signatures of every registered multisig wallet to `psbt`.
+    /// The registered wallets, by name.
+    pub fn wallets(&self) -> Vec<WalletInfo> {
+        let wallets = self.wallets.read().expect("bsms wallets");
+        wallets
+            .values()
+            .map(|multisig| multisig.info.clone())
+            .collect()
+    }
+
+    pub fn sign(&self, psbt: &mut Psbt, options: &SignOptions) -> Result<(), Error> {
+        for multisig in self.wallets.read().expect("bsms wallets").values() {
+            multisig.wallet.sign(psbt, options.clone()).map_err(|e| {
+                Error::InvalidTransaction(format!(
+                    "signing failed for wallet {}: {e}",
+                    multisig.info.name
+                ))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// PBKDF2-HMAC-SHA512 of `token` into the 32-byte record encryption key.
+fn encryption_key(token: &[u8]) -> [u8; 32] {
+    let prf = |data: &[u8]| {
+        let mut engine = hmac::HmacEngine::<sha512::Hash>::new(token);
+        engine.input(data);
+        hmac::Hmac::<sha512::Hash>::from_engine(engine).to_byte_array()
+    };
+    let mut block = prf(&[KDF_SALT, &1u32.to_be_bytes()].concat());
+    let mut output = block;
+    for _ in 1..KDF_ROUNDS {
+        block = prf(&block);
+        for (out, byte) in output.iter_mut().zip(block) {
+            *out ^= byte;
+        }
+    }
+    output[..32].try_into().expect("32 bytes")
+}
+
+fn record_mac(key: &[u8; 32], token: &[u8], data: &[u8]) -> [u8; 32] {
+    let mac_key = sha256::Hash::hash(key);
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(mac_key.as_byte_array());
+    engine.input(token);
+    engine.input(data);
+    hmac::Hmac::<sha256::Hash>::from_engine(engine).to_byte_array()
+}
+
+fn apply_keystream(key: &[u8; 32], mac: &[u8; 32], data: &mut [u8]) {
+    let iv: [u8; 16] = mac[..16].try_into().expect("16 bytes");
+    ctr::Ctr128BE::<Aes256>::new(key.into(), &iv.into()).apply_keystream(data);
+}
+
+/// BIP-129 encryption: hex of the MAC followed by the AES-256-CTR
+/// ciphertext, using the MAC's first 16 bytes as IV.
+fn encrypt(token: &[u8], plaintext: &str) -> String {
+    let key = encryption_key(token);
+    let mac = record_mac(&key, token, plaintext.as_bytes());
+    let mut data = plaintext.as_bytes().to_vec();
+    apply_keystream(&key, &mac, &mut data);
+    hex::encode([&mac[..], &data].concat())
+}
+
+fn decrypt(token: &[u8], record: &str) -> Result<String, Error> {
+    let invalid = || Error::MalformedRequest("descriptor record: cannot decrypt".into());
+    let bytes = hex::decode(record).map_err(|_| invalid())?;
+    if bytes.len() < 32 {
+        return Err(invalid());
+    }
+    let (mac, data) = bytes.split_at(32);
+    let mac: [u8; 32] = mac.try_into().expect("32 bytes");
+    let key = encryption_key(token);
+    let mut data = data.to_vec();
+    apply_keystream(&key, &mac, &mut data);
+    if record_mac(&key, token, &data) != mac {
+        return Err(invalid());
+    }
+    String::from_utf8(data).map_err(|_| invalid())
+}
+
+fn enabled(state: &AppState) -> Result<&Bsms, Error> {
+    state
+        .bsms
+        .as_ref()
+        .ok_or_else(|| Error::NotFound("BSMS is not configured".into()))
+}
+
+pub async fn key_record(
+    State(state): State<Arc<AppState>>,
+    ApiJson(request): ApiJson<KeyRecordRequest>,
+) -> Result<Json<KeyRecordResponse>, Error> {
+    let bsms = enabled(&state)?;
+    let token = request.token.parse()?;
+    Ok(Json(KeyRecordResponse {
+        record: bsms.key_record(&token, &request.description)?,
+    }))
+}
+
+pub async fn list(State(state): State<Arc<AppState>>) -> Result<Json<Vec<WalletInfo>>, Error> {
+    Ok(Json(enabled(&state)?.wallets()))
+}
+
+pub async fn register(
+    State(state): State<Arc<AppState>>,
+    ApiJson(registration): ApiJson<Registration>,
+) -> Result<(StatusCode, Json<WalletInfo>), Error> {
+    let bsms = enabled(&state)?;
+    let token = registration.token.parse()?;
+    let info = bsms.register(registration.name, &token, &registration.descriptor_record)?;
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+pub async fn remove(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, Error> {
+    let bsms = enabled(&state)?;
+    let mut wallets = bsms.wallets.write().expect("bsms wallets");
+    let removed = wallets
+        .remove(&name)
+        .ok_or_else(|| Error::NotFound(format!("multisig wallet {name}")))?;
+    if let Err(e) = bsms.save(&wallets) {
+        wallets.insert(name, removed);
+        return Err(Error::Internal(format!("saving multisig wallets: {e}")));
+    }
+    tracing::info!(wallet = name, "removed multisig wallet");
+    Ok(StatusCode::NO_CONTENT)
+}
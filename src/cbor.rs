@@ -0,0 +1,711 @@
+//! CBOR request and response bodies, on every endpoint.
+//!
+//! A body sent as `Content-Type: application/cbor` is turned into the JSON
+//! the handler expects, and an answer is turned back into CBOR when the
+//! request's `Accept` prefers `application/cbor`, or when the request was
+//! CBOR and `Accept` prefers nothing else. Handlers only ever see JSON.
+//! Byte strings become base64 text on the way in, and the base64 `psbt` and
+//! `pset` fields of an answer become byte strings on the way out, so a PSBT
+//! travels as raw bytes both ways. Other text, such as metadata, stays text
+//! whatever it looks like.
+//! Everything else maps the obvious way: CBOR maps need text keys, tags
+//! are dropped, and `undefined` reads as `null`. Answers that are not JSON,
+//! such as raw PSBTs or NDJSON exports, are left alone.
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use data_encoding::BASE64;
+use serde_json::{Map, Number, Value};
+
+use crate::{
+    error::Error,
+    negotiate::{self, JSON},
+    BINARY_PSBT,
+};
+
+pub const CBOR: &str = "application/cbor";
+
+/// Largest CBOR body read, the same as the limit on JSON bodies.
+const MAX_BODY: usize = 2 * 1024 * 1024;
+
+/// Deepest nesting decoded, so a hostile body cannot exhaust the stack.
+const MAX_DEPTH: usize = 64;
+
+/// The fields of an answer sent as byte strings, with the base64 of the
+/// magic their values start with: `psbt\xff` and `pset\xff`.
+const PSBT_FIELDS: [(&str, &str); 2] = [("psbt", "cHNidP8"), ("pset", "cHNldP8")];
+
+pub async fn transcode(request: Request, next: Next) -> Response {
+    let sent = is_media_type(request.headers(), CBOR);
+    let answer = answers_in_cbor(request.headers(), sent);
+    let response = match to_json(request, sent, answer).await {
+        Ok(request) => next.run(request).await,
+        Err(e) => e.into_response(),
+    };
+    if !is_media_type(response.headers(), JSON) {
+        return response;
+    }
+    let mut response = match answer {
+        true => to_cbor(response).await,
+        false => response,
+    };
+    let varies = response
+        .headers()
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.to_ascii_lowercase().contains("accept"));
+    if !varies {
+        response
+            .headers_mut()
+            .append(header::VARY, HeaderValue::from_static("accept"));
+    }
+    response
+}
+
+/// Whether the answer to a request with `headers` is sent as CBOR.
+fn answers_in_cbor(headers: &HeaderMap, sent: bool) -> bool {
+    let ranges = negotiate::ranges(headers);
+    if ranges.is_empty() {
+        return sent;
+    }
+    let cbor = negotiate::quality(&ranges, CBOR);
+    let other = negotiate::quality(&ranges, JSON).max(negotiate::quality(&ranges, BINARY_PSBT));
+    cbor > other || (sent && cbor > 0.0 && cbor == other)
+}
+
+/// `request` as the handler expects it: a CBOR body as JSON, and asking
+/// for the JSON that is then turned into CBOR.
+async fn to_json(request: Request, sent: bool, answer: bool) -> Result<Request, Error> {
+    let (mut parts, body) = request.into_parts();
+    if answer {
+        parts.headers.remove(header::ACCEPT);
+        parts
+            .headers
+            .insert(header::ACCEPT, HeaderValue::from_static(JSON));
+    }
+    if !sent {
+        return Ok(Request::from_parts(parts, body));
+    }
+    let bytes = axum::body::to_bytes(body, MAX_BODY)
+        .await
+        .map_err(|e| Error::PayloadTooLarge(format!("cbor body: {e}")))?;
+    let value = decode(&bytes).map_err(|e| Error::MalformedRequest(format!("cbor: {e}")))?;
+    let json = serde_json::to_vec(&value).expect("a JSON value serializes");
+    parts
+        .headers
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(JSON));
+    parts
+        .headers
+        .insert(header::CONTENT_LENGTH, json.len().into());
+    Ok(Request::from_parts(parts, Body::from(json)))
+}
+
+async fn to_cbor(response: Response) -> Response {
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return Error::Internal(format!("buffering a response for cbor: {e}")).into_response()
+        }
+    };
+    let value: Value = match serde_json::from_slice(&bytes) {
+        Ok(value) => value,
+        Err(e) => {
+            return Error::Internal(format!("encoding a response as cbor: {e}")).into_response()
+        }
+    };
+    let mut cbor = Vec::with_capacity(bytes.len());
+    encode(&value, &mut cbor);
+    parts
+        .headers
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(CBOR));
+    parts
+        .headers
+        .insert(header::CONTENT_LENGTH, cbor.len().into());
+    Response::from_parts(parts, Body::from(cbor))
+}
+
+fn is_media_type(headers: &HeaderMap, media_type: &str) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case(media_type))
+}
+
+/// Decodes one CBOR data item, refusing trailing bytes.
+pub fn decode(bytes: &[u8]) -> Result<Value, String> {
+    let mut decoder = Decoder { bytes, at: 0 };
+    let value = decoder.item(0)?;
+    if decoder.at != bytes.len() {
+        return Err(format!("{} bytes after the item", bytes.len() - decoder.at));
+    }
+    Ok(value)
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+/// What an initial byte announces: a major type and its argument, or an
+/// indefinite length.
+enum Head {
+    Definite(u8, u64),
+    Indefinite(u8),
+    Break,
+}
+
+impl Decoder<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], String> {
+        let end = self
+            .at
+            .checked_add(n)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or("truncated")?;
+        let taken = &self.bytes[self.at..end];
+        self.at = end;
+        Ok(taken)
+    }
+
+    fn head(&mut self) -> Result<Head, String> {
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        let argument = match info {
+            0..=23 => u64::from(info),
+            24 => u64::from(self.take(1)?[0]),
+            25 => u64::from(u16::from_be_bytes(
+                self.take(2)?.try_into().expect("2 bytes"),
+            )),
+            26 => u64::from(u32::from_be_bytes(
+                self.take(4)?.try_into().expect("4 bytes"),
+            )),
+            27 => u64::from_be_bytes(self.take(8)?.try_into().expect("8 bytes")),
+            31 if major == 7 => return Ok(Head::Break),
+            31 if (2..=5).contains(&major) => return Ok(Head::Indefinite(major)),
+            _ => return Err(format!("reserved initial byte {initial:#04x}")),
+        };
+        Ok(Head::Definite(major, argument))
+    }
+
+    fn item(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err(format!("nested deeper than {MAX_DEPTH}"));
+        }
+        let start = self.at;
+        match self.head()? {
+            Head::Break => Err("unexpected break".into()),
+            Head::Indefinite(major) => self.indefinite(major, depth),
+            Head::Definite(0, n) => Ok(Value::from(n)),
+            Head::Definite(1, n) => i64::try_from(n)
+                .map(|n| Value::from(-1 - n))
+                .map_err(|_| "negative integer out of range".into()),
+            Head::Definite(2, len) => Ok(Value::String(BASE64.encode(self.take(length(len)?)?))),
+            Head::Definite(3, len) => text(self.take(length(len)?)?).map(Value::String),
+            Head::Definite(4, len) => {
+                let mut items = Vec::new();
+                for _ in 0..len {
+                    items.push(self.item(depth + 1)?);
+                }
+                Ok(Value::Array(items))
+            }
+            Head::Definite(5, len) => {
+                let mut map = Map::new();
+                for _ in 0..len {
+                    let key = self.key(depth)?;
+                    map.insert(key, self.item(depth + 1)?);
+                }
+                Ok(Value::Object(map))
+            }
+            // Tags add meaning the handlers would not use.
+            Head::Definite(6, _) => self.item(depth + 1),
+            Head::Definite(_, argument) => self.simple(self.bytes[start] & 0x1f, argument),
+        }
+    }
+
+    fn indefinite(&mut self, major: u8, depth: usize) -> Result<Value, String> {
+        match major {
+            2 | 3 => {
+                let mut chunks = Vec::new();
+                loop {
+                    match self.head()? {
+                        Head::Break => break,
+                        Head::Definite(chunk, len) if chunk == major => {
+                            chunks.extend_from_slice(self.take(length(len)?)?)
+                        }
+                        _ => return Err("indefinite string with a chunk of another type".into()),
+                    }
+                }
+                match major {
+                    2 => Ok(Value::String(BASE64.encode(&chunks))),
+                    _ => text(&chunks).map(Value::String),
+                }
+            }
+            4 => {
+                let mut items = Vec::new();
+                while !self.at_break()? {
+                    items.push(self.item(depth + 1)?);
+                }
+                Ok(Value::Array(items))
+            }
+            _ => {
+                let mut map = Map::new();
+                while !self.at_break()? {
+                    let key = self.key(depth)?;
+                    map.insert(key, self.item(depth + 1)?);
+                }
+                Ok(Value::Object(map))
+            }
+        }
+    }
+
+    /// Consumes a break if one is next.
+    fn at_break(&mut self) -> Result<bool, String> {
+        match self.bytes.get(self.at) {
+            Some(0xff) => {
+                self.at += 1;
+                Ok(true)
+            }
+            Some(_) => Ok(false),
+            None => Err("truncated".into()),
+        }
+    }
+
+    fn key(&mut self, depth: usize) -> Result<String, String> {
+        let major = self.bytes.get(self.at).ok_or("truncated")? >> 5;
+        match (major, self.item(depth + 1)?) {
+            (3, Value::String(key)) => Ok(key),
+            _ => Err("map keys must be text strings".into()),
+        }
+    }
+
+    fn simple(&self, info: u8, argument: u64) -> Result<Value, String> {
+        let float = match info {
+            20 => return Ok(Value::Bool(false)),
+            21 => return Ok(Value::Bool(true)),
+            22 | 23 => return Ok(Value::Null),
+            25 => half(argument as u16),
+            26 => f64::from(f32::from_bits(argument as u32)),
+            27 => f64::from_bits(argument),
+            _ => return Err(format!("unsupported simple value {argument}")),
+        };
+        Number::from_f64(float)
+            .map(Value::Number)
+            .ok_or_else(|| format!("{float} has no JSON equivalent"))
+    }
+}
+
+fn length(len: u64) -> Result<usize, String> {
+    usize::try_from(len).map_err(|_| "length out of range".into())
+}
+
+fn text(bytes: &[u8]) -> Result<String, String> {
+    String::from_utf8(bytes.to_vec()).map_err(|e| format!("text string: {e}"))
+}
+
+/// An IEEE 754 half-precision float as `f64`.
+fn half(bits: u16) -> f64 {
+    let exponent = (bits >> 10) & 0x1f;
+    let mantissa = f64::from(bits & 0x3ff);
+    let magnitude = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mantissa + 1024.0) * 2f64.powi(i32::from(exponent) - 25),
+    };
+    match bits >> 15 {
+        0 => magnitude,
+        _ => -magnitude,
+    }
+}
+
+/// Encodes `value` into `out`, the base64 values of [`PSBT_FIELDS`] as byte
+/// strings.
+pub fn encode(value: &Value, out: &mut Vec<u8>) {
+    encode_field(value, None, out)
+}
+
+/// Encodes `value`, a base64 PSBT if it starts with `magic`.
+fn encode_field(value: &Value, magic: Option<&str>, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(0xf6),
+        Value::Bool(false) => out.push(0xf4),
+        Value::Bool(true) => out.push(0xf5),
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(n), _) => head(0, n, out),
+            (None, Some(n)) => head(1, (-1 - n) as u64, out),
+            (None, None) => {
+                out.push(0xfb);
+                out.extend_from_slice(&n.as_f64().unwrap_or_default().to_be_bytes());
+            }
+        },
+        Value::String(s) => match magic.and_then(|magic| psbt_bytes(s, magic)) {
+            Some(bytes) => {
+                head(2, bytes.len() as u64, out);
+                out.extend_from_slice(&bytes);
+            }
+            None => {
+                head(3, s.len() as u64, out);
+                out.extend_from_slice(s.as_bytes());
+            }
+        },
+        Value::Array(items) => {
+            head(4, items.len() as u64, out);
+            for item in items {
+                encode(item, out);
+            }
+        }
+        Value::Object(map) => {
+            head(5, map.len() as u64, out);
+            for (key, item) in map {
+                head(3, key.len() as u64, out);
+                out.extend_from_slice(key.as_bytes());
+                let magic = PSBT_FIELDS
+                    .iter()
+                    .find(|(field, _)| field == key)
+                    .map(|(_, magic)| *magic);
+                encode_field(item, magic, out);
+            }
+        }
+    }
+}
+
+/// The bytes of `s` if it is base64 starting with `magic`.
+fn psbt_bytes(s: &str, magic: &str) -> Option<Vec<u8>> {
+    s.starts_with(magic)
+        .then(|| BASE64.decode(s.as_bytes()).ok())
+        .flatten()
+}
+
+/// The shortest head of major type `major` and argument `n`.
+fn head(major: u8, n: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    match n {
+        0..=23 => out.push(major | n as u8),
+        24..=0xff => out.extend_from_slice(&[major | 24, n as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend_from_slice(&(n as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, routing::post, Json, Router};
+    use data_encoding::HEXLOWER;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        HEXLOWER.decode(s.as_bytes()).expect("hex")
+    }
+
+    fn decoded(s: &str) -> Result<Value, String> {
+        decode(&hex(s))
+    }
+
+    fn encoded(value: &Value) -> String {
+        let mut out = Vec::new();
+        encode(value, &mut out);
+        HEXLOWER.encode(&out)
+    }
+
+    /// A base64 PSBT, as far as the transcoding looks.
+    fn psbt() -> String {
+        BASE64.encode(b"psbt\xff\x01\x00\x00")
+    }
+
+    // The examples of RFC 8949, appendix A.
+    #[test]
+    fn decodes_definite_items() {
+        for (cbor, value) in [
+            ("00", json!(0)),
+            ("17", json!(23)),
+            ("1818", json!(24)),
+            ("1903e8", json!(1000)),
+            ("1a000f4240", json!(1_000_000)),
+            ("1bffffffffffffffff", json!(u64::MAX)),
+            ("20", json!(-1)),
+            ("3903e7", json!(-1000)),
+            ("3b7fffffffffffffff", json!(i64::MIN)),
+            ("f93e00", json!(1.5)),
+            ("f9c400", json!(-4.0)),
+            ("f90001", json!(5.960464477539063e-8)),
+            ("fa47c35000", json!(100000.0)),
+            ("fb3ff199999999999a", json!(1.1)),
+            ("f4", json!(false)),
+            ("f5", json!(true)),
+            ("f6", json!(null)),
+            ("f7", json!(null)),
+            ("4401020304", json!("AQIDBA==")),
+            ("6449455446", json!("IETF")),
+            ("62c3bc", json!("ü")),
+            ("8301820203820405", json!([1, [2, 3], [4, 5]])),
+            ("a26161016162820203", json!({"a": 1, "b": [2, 3]})),
+            ("c11a514b67b0", json!(1363896240)),
+        ] {
+            assert_eq!(decoded(cbor), Ok(value), "{cbor}");
+        }
+    }
+
+    #[test]
+    fn decodes_indefinite_lengths() {
+        for (cbor, value) in [
+            ("5f42010243030405ff", json!("AQIDBAU=")),
+            ("7f657374726561646d696e67ff", json!("streaming")),
+            ("5fff", json!("")),
+            ("9f018202039f0405ffff", json!([1, [2, 3], [4, 5]])),
+            ("9fff", json!([])),
+            ("bf61610161629f0203ffff", json!({"a": 1, "b": [2, 3]})),
+        ] {
+            assert_eq!(decoded(cbor), Ok(value), "{cbor}");
+        }
+        // A chunk of another string type, a chunk of indefinite length, and
+        // missing breaks.
+        for cbor in ["5f6161ff", "5f5f4101ffff", "5f4101", "9f01", "bf616101"] {
+            assert!(decoded(cbor).is_err(), "{cbor}");
+        }
+    }
+
+    #[test]
+    fn refuses_malformed_items() {
+        for cbor in [
+            "",
+            "18",
+            "1a0000",
+            "4401",
+            "6449",
+            "83010203ff",
+            "8301",
+            "a161",
+            "ff",
+            "1c",
+            "5c",
+            "a10102",
+            "a1410102",
+            "3bffffffffffffffff",
+            "62c328",
+            "f0",
+            "f97e00",
+            "f97c00",
+            "0000",
+        ] {
+            assert!(decoded(cbor).is_err(), "{cbor}");
+        }
+    }
+
+    #[test]
+    fn refuses_lengths_beyond_the_body() {
+        for cbor in [
+            "5bffffffffffffffff",
+            "7bffffffffffffffff",
+            "9bffffffffffffffff01",
+        ] {
+            assert_eq!(decoded(cbor), Err("truncated".into()), "{cbor}");
+        }
+    }
+
+    #[test]
+    fn limits_nesting() {
+        for (open, close) in [("81", ""), ("9f", "ff"), ("a16161", ""), ("c1", "")] {
+            let nested =
+                |depth: usize| hex(&format!("{}00{}", open.repeat(depth), close.repeat(depth)));
+            assert!(decode(&nested(MAX_DEPTH)).is_ok(), "{open}");
+            assert_eq!(
+                decode(&nested(MAX_DEPTH + 1)),
+                Err(format!("nested deeper than {MAX_DEPTH}")),
+                "{open}"
+            );
+        }
+    }
+
+    #[test]
+    fn encodes_the_shortest_heads() {
+        for (value, cbor) in [
+            (json!(0), "00"),
+            (json!(23), "17"),
+            (json!(24), "1818"),
+            (json!(255), "18ff"),
+            (json!(256), "190100"),
+            (json!(65535), "19ffff"),
+            (json!(65536), "1a00010000"),
+            (json!(4294967296u64), "1b0000000100000000"),
+            (json!(u64::MAX), "1bffffffffffffffff"),
+            (json!(-1), "20"),
+            (json!(-1000), "3903e7"),
+            (json!(i64::MIN), "3b7fffffffffffffff"),
+            (json!(1.1), "fb3ff199999999999a"),
+            (json!(null), "f6"),
+            (json!(true), "f5"),
+            (json!("IETF"), "6449455446"),
+            (json!([1, [2, 3], [4, 5]]), "8301820203820405"),
+            (json!({"a": 1, "b": [2, 3]}), "a26161016162820203"),
+        ] {
+            assert_eq!(encoded(&value), cbor, "{value}");
+        }
+    }
+
+    #[test]
+    fn sends_only_psbt_fields_as_bytes() {
+        let psbt = psbt();
+        let pset = BASE64.encode(b"pset\xff\x01\x00\x00");
+        let bytes = |s: &str| HEXLOWER.encode(&BASE64.decode(s.as_bytes()).unwrap());
+        let text = |s: &str| HEXLOWER.encode(s.as_bytes());
+        assert_eq!(
+            encoded(&json!({ "psbt": psbt })),
+            format!("a164{}48{}", text("psbt"), bytes(&psbt))
+        );
+        assert_eq!(
+            encoded(&json!({ "pset": pset })),
+            format!("a164{}48{}", text("pset"), bytes(&pset))
+        );
+        for value in [
+            json!(psbt),
+            json!([psbt]),
+            json!({ "metadata": { "note": psbt } }),
+            json!({ "psbts": psbt }),
+            json!({ "pset": psbt }),
+        ] {
+            assert!(
+                encoded(&value).contains(&format!("6c{}", text(&psbt))),
+                "{value}"
+            );
+        }
+        // A `psbt` field holding something other than a base64 PSBT.
+        assert_eq!(
+            encoded(&json!({ "psbt": "cHNidP8?" })),
+            format!("a164{}68{}", text("psbt"), text("cHNidP8?"))
+        );
+    }
+
+    #[test]
+    fn round_trips_every_response_shape() {
+        let psbt = psbt();
+        for value in [
+            json!({
+                "psbt": psbt,
+                "dry_run": true,
+                "cached": true,
+                "missing_signatures": [{ "input": 0, "missing": 2 }],
+                "attestation": { "signature": "3045", "key": "02ab" },
+            }),
+            json!({ "pset": "cHNldP8BAA==" }),
+            json!({
+                "error": {
+                    "code": "invalid_transaction",
+                    "message": "no inputs",
+                    "details": { "inputs": [], "limit": null },
+                    "request_id": "5f6e",
+                },
+            }),
+            json!({
+                "request_id": "pay-1",
+                "outcome": { "status": "signed", "psbt": psbt, "txid": "00ff" },
+                "metadata": { "note": psbt, "ratio": 0.25, "count": -3 },
+            }),
+            json!([{ "psbt": psbt }, { "psbt": psbt, "dry_run": false }]),
+            json!({ "requests": u64::MAX, "average_latency_ms": null, "rejections": {} }),
+        ] {
+            assert_eq!(decoded(&encoded(&value)), Ok(value.clone()), "{value}");
+        }
+    }
+
+    fn app() -> Router {
+        async fn echo(Json(value): Json<Value>) -> Json<Value> {
+            Json(value)
+        }
+        Router::new()
+            .route("/", post(echo))
+            .layer(axum::middleware::from_fn(transcode))
+    }
+
+    async fn send(
+        content_type: &str,
+        accept: Option<&str>,
+        body: Vec<u8>,
+    ) -> (StatusCode, Option<String>, Vec<u8>) {
+        let mut request = Request::post("/").header(header::CONTENT_TYPE, content_type);
+        if let Some(accept) = accept {
+            request = request.header(header::ACCEPT, accept);
+        }
+        let response = app()
+            .oneshot(request.body(Body::from(body)).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::VARY], "accept");
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .map(|value| value.to_str().unwrap().to_owned());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, content_type, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn answers_cbor_in_cbor() {
+        let value = json!({ "psbt": psbt(), "metadata": { "note": psbt() } });
+        let mut cbor = Vec::new();
+        encode(&value, &mut cbor);
+        let (status, content_type, body) = send(CBOR, None, cbor.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type.as_deref(), Some(CBOR));
+        assert_eq!(body, cbor);
+
+        let (_, content_type, body) = send(CBOR, Some(JSON), cbor).await;
+        assert_eq!(content_type.as_deref(), Some(JSON));
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), value);
+
+        let json = serde_json::to_vec(&value).unwrap();
+        let (_, content_type, body) = send(JSON, Some(CBOR), json.clone()).await;
+        assert_eq!(content_type.as_deref(), Some(CBOR));
+        assert_eq!(decode(&body), Ok(value));
+
+        let (_, content_type, body) = send(JSON, None, json.clone()).await;
+        assert_eq!(content_type.as_deref(), Some(JSON));
+        assert_eq!(body, json);
+    }
+
+    #[tokio::test]
+    async fn refuses_malformed_and_oversized_bodies() {
+        for (body, status, code) in [
+            (hex("a161"), StatusCode::BAD_REQUEST, "malformed_request"),
+            (hex("0000"), StatusCode::BAD_REQUEST, "malformed_request"),
+            // Read whole at the limit, and refused for its trailing bytes.
+            (
+                vec![0x40; MAX_BODY],
+                StatusCode::BAD_REQUEST,
+                "malformed_request",
+            ),
+            (
+                vec![0x40; MAX_BODY + 1],
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload_too_large",
+            ),
+        ] {
+            let (answered, content_type, body) = send(CBOR, None, body).await;
+            assert_eq!(answered, status);
+            assert_eq!(content_type.as_deref(), Some(CBOR));
+            assert_eq!(decode(&body).unwrap()["error"]["code"], code);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Keeps the wallet's view of its own coins current from an Esplora server.
+//!
+//! The first pass is a full scan up to `stop_gap` unused addresses; later
+//! passes only re-check addresses already revealed. Sync failures are
+//! logged and retried on the next interval; signing never depends on them.
+//! The same server broadcasts the transactions `/admin/sweep` is asked to,
+//! estimates the fee rates consolidation waits for and looks up the
+//! transactions `[fee_check]` needs. How the syncs go is reported on
+//! `/ready`.
+
+use std::{
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bdk_esplora::{esplora_client, EsploraAsyncExt};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{config::ChainConfig, error::Error, AppState};
+
+/// Concurrent requests made to the Esplora server during a scan.
+const PARALLEL_REQUESTS: usize = 4;
+
+/// Outcomes of the latest syncs.
+#[derive(Default)]
+pub struct SyncStatus(Mutex<SyncState>);
+
+#[derive(Clone, Default)]
+struct SyncState {
+    last_attempt: Option<DateTime<Utc>>,
+    last_success: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+    consecutive_failures: u32,
+    backend_height: Option<u32>,
+    backend_latency_ms: Option<u64>,
+}
+
+impl SyncStatus {
+    fn update(&self, f: impl FnOnce(&mut SyncState)) {
+        f(&mut self.0.lock().expect("sync status"));
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ChainStatus {
+    /// Whether the first full scan has completed.
+    pub synced: bool,
+    /// Height of the wallet's last synced block.
+    pub tip_height: Option<u32>,
+    /// Height the Esplora server reported at the last attempt.
+    pub backend_height: Option<u32>,
+    /// Round trip of that height request, unset when it failed.
+    pub backend_latency_ms: Option<u64>,
+    #[schema(value_type = Option<String>)]
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    #[schema(value_type = Option<String>)]
+    pub last_sync_at: Option<DateTime<Utc>>,
+    /// Failed syncs since the last successful one.
+    pub consecutive_failures: u32,
+    /// Why the last failed sync failed, while it is the latest attempt.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// The chain sync's state for `/ready`, `None` without `[chain]`.
+/// `tip_height` is left out when `wallet_usable` is false.
+pub fn status(state: &AppState, wallet_usable: bool) -> Option<ChainStatus> {
+    state.config().chain.as_ref()?;
+    let sync = state.chain_status.0.lock().expect("sync status").clone();
+    Some(ChainStatus {
+        synced: state.chain_synced.load(Ordering::Acquire),
+        tip_height: wallet_usable.then(|| state.wallet().latest_checkpoint().height()),
+        backend_height: sync.backend_height,
+        backend_latency_ms: sync.backend_latency_ms,
+        last_attempt_at: sync.last_attempt,
+        last_sync_at: sync.last_success,
+        consecutive_failures: sync.consecutive_failures,
+        last_error: sync.last_error,
+    })
+}
+
+pub async fn sync_forever(state: Arc<AppState>, config: ChainConfig) {
+    let client = match esplora_client::Builder::new(&config.esplora_url).build_async() {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!(url = config.esplora_url, "chain backend disabled: {e}");
+            return;
+        }
+    };
+    let mut interval = tokio::time::interval(Duration::from_secs(config.sync_interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+        let started = Instant::now();
+        let height = client.get_height().await;
+        let latency_ms = started.elapsed().as_millis() as u64;
+        state.chain_status.update(|status| {
+            status.last_attempt = Some(Utc::now());
+            status.backend_latency_ms = height.is_ok().then_some(latency_ms);
+            status.backend_height = height.as_ref().ok().copied();
+        });
+        let result = match height {
+            Ok(_) => sync(&state, &client, &config).await,
+            Err(e) => Err(e.to_string()),
+        };
+        match result {
+            Ok(()) => {
+                state.chain_synced.store(true, Ordering::Release);
+                state.chain_status.update(|status| {
+                    status.last_success = status.last_attempt;
+                    status.last_error = None;
+                    status.consecutive_failures = 0;
+                });
+                tracing::debug!("wallet synced");
+            }
+            Err(e) => {
+                tracing::warn!(url = config.esplora_url, "wallet sync failed: {e}");
+                state.chain_status.update(|status| {
+                    status.last_error = Some(e);
+                    status.consecutive_failures += 1;
+                });
+            }
+        }
+    }
+}
+
+async fn sync(
+    state: &AppState,
+    client: &esplora_client::AsyncClient,
+    config: &ChainConfig,
+) -> Result<(), String> {
+    // Requests are built under the read lock and updates applied by the
+    // wallet's writer; the network round-trips in between run without either.
+    let update: bdk_wallet::Update = if state.chain_synced.load(Ordering::Acquire) {
+        let request = state.wallet().start_sync_with_revealed_spks().build();
+        client
+            .sync(request, PARALLEL_REQUESTS)
+            .await
+            .map_err(|e| e.to_string())?
+            .into()
+    } else {
+        let request = state.wallet().start_full_scan().build();
+        client
+            .full_scan(request, config.stop_gap, PARALLEL_REQUESTS)
+            .await
+            .map_err(|e| e.to_string())?
+            .into()
+    };
+    state.wallet.apply_update(update).await
+}
+
+/// The height of the Esplora server of `config`, after checking it follows
+/// `network`'s chain.
+pub async fn probe(config: &ChainConfig, network: bitcoin::Network) -> Result<u32, String> {
+    let client = esplora_client::Builder::new(&config.esplora_url)
+        .build_async()
+        .map_err(|e| e.to_string())?;
+    let genesis = client.get_block_hash(0).await.map_err(|e| e.to_string())?;
+    if genesis != bitcoin::constants::genesis_block(network).block_hash() {
+        return Err(format!("the server follows another chain than {network}"));
+    }
+    client.get_height().await.map_err(|e| e.to_string())
+}
+
+/// Hands `tx` to the Esplora server of `config` for relay.
+pub async fn broadcast(config: &ChainConfig, tx: &bitcoin::Transaction) -> Result<(), String> {
+    esplora_client::Builder::new(&config.esplora_url)
+        .build_async()
+        .map_err(|e| e.to_string())?
+        .broadcast(tx)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// The server's fee rate estimate, in sat/vB, for confirmation within
+/// `target_blocks`.
+pub async fn fee_estimate(config: &ChainConfig, target_blocks: u16) -> Result<f64, String> {
+    let estimates = esplora_client::Builder::new(&config.esplora_url)
+        .build_async()
+        .map_err(|e| e.to_string())?
+        .get_fee_estimates()
+        .await
+        .map_err(|e| e.to_string())?;
+    esplora_client::convert_fee_rate(target_blocks.into(), estimates)
+        .map(f64::from)
+        .ok_or_else(|| format!("no estimate for {target_blocks} blocks or less"))
+}
+
+/// The transaction `txid` as the Esplora server of `config` has it, or
+/// `None` if it has not seen it.
+pub async fn transaction(
+    config: &ChainConfig,
+    txid: &bitcoin::Txid,
+) -> Result<Option<bitcoin::Transaction>, String> {
+    esplora_client::Builder::new(&config.esplora_url)
+        .build_async()
+        .map_err(|e| e.to_string())?
+        .get_tx(txid)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Fails unless the wallet's coins are known: `[chain]` is configured and
+/// the first sync has completed.
+pub fn require_synced(state: &AppState) -> Result<(), Error> {
+    if state.config().chain.is_none() {
+        return Err(Error::NotFound(
+            "this needs [chain] to know the wallet's coins".into(),
+        ));
+    }
+    if !state.chain_synced.load(Ordering::Acquire) {
+        return Err(Error::Unavailable(
+            "wallet is not synced with the chain".into(),
+        ));
+    }
+    Ok(())
+}
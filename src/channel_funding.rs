@@ -0,0 +1,256 @@
+//! Safeguards for Lightning channel funding through PSBTs, as in LND's
+//! `--psbt` funding flow.
+//!
+//! The operator registers each pending channel's funding address and
+//! amount, then the reference of the commitment transaction the peer has
+//! signed. A PSBT paying a registered funding address is only signed once
+//! that reference exists: a funding transaction broadcast without a signed
+//! commitment locks the coins in a 2-of-2 the wallet cannot leave alone.
+//! The PSBTs this service signs are broadcast by others, `/admin/sweep` aside,
+//! so signing is the step being guarded.
+//!
+//! Registrations live in memory and are lost on restart.
+
+use std::{collections::BTreeMap, str::FromStr, sync::Arc, sync::Mutex};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use bitcoin::{
+    opcodes::all::OP_CHECKMULTISIG, script::Builder, Address, Psbt, PublicKey, ScriptBuf, Txid,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::ApiJson, error::Error, AppState};
+
+#[derive(Default)]
+pub struct Fundings(Mutex<BTreeMap<String, Funding>>);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Funding {
+    /// Caller-chosen id, normally LND's pending channel id.
+    pub id: String,
+    pub address: String,
+    #[serde(skip)]
+    script_pubkey: ScriptBuf,
+    pub amount_sat: u64,
+    /// Reference of the peer-signed commitment transaction.
+    pub commitment_txid: Option<Txid>,
+    /// Funding transaction this service signed for the channel.
+    pub funding_txid: Option<Txid>,
+    pub status: Status,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    AwaitingCommitment,
+    Ready,
+    Signed,
+}
+
+#[derive(Deserialize)]
+pub struct Registration {
+    pub id: String,
+    /// Funding address handed out by the Lightning node.
+    pub address: String,
+    pub amount_sat: u64,
+    /// The two funding keys. When given, `address` must be their BOLT-3
+    /// 2-of-2 P2WSH output.
+    #[serde(default)]
+    pub pubkeys: Option<[String; 2]>,
+}
+
+#[derive(Deserialize)]
+pub struct CommitmentReference {
+    pub commitment_txid: Txid,
+}
+
+impl Fundings {
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Funding>> {
+        self.0.lock().expect("channel fundings")
+    }
+
+    /// Refuses to sign `psbt` if it pays a registered funding address with
+    /// the wrong amount, before the commitment is registered, or after a
+    /// different funding transaction was signed for the same channel.
+    /// Returns the ids of the fundings it pays.
+    pub fn check(&self, psbt: &Psbt) -> Result<Vec<String>, Error> {
+        let txid = psbt.unsigned_tx.compute_txid();
+        let fundings = self.lock();
+        let mut paid = Vec::new();
+        for (index, output) in psbt.unsigned_tx.output.iter().enumerate() {
+            let Some(funding) = fundings
+                .values()
+                .find(|funding| funding.script_pubkey == output.script_pubkey)
+            else {
+                continue;
+            };
+            let reject = |reason: String| {
+                Err(Error::InvalidTransaction(format!(
+                    "output {index} funds channel {}: {reason}",
+                    funding.id
+                )))
+            };
+            if output.value.to_sat() != funding.amount_sat {
+                return reject(format!(
+                    "pays {} sat, {} sat were registered",
+                    output.value.to_sat(),
+                    funding.amount_sat
+                ));
+            }
+            if funding.commitment_txid.is_none() {
+                return reject("no signed commitment transaction is registered yet".into());
+            }
+            if funding.funding_txid.is_some_and(|signed| signed != txid) {
+                return reject("a different funding transaction was already signed".into());
+            }
+            paid.push(funding.id.clone());
+        }
+        Ok(paid)
+    }
+
+    /// Records that the funding transaction `txid` was signed for `ids`.
+    pub fn mark_signed(&self, ids: &[String], txid: Txid) {
+        let mut fundings = self.lock();
+        for id in ids {
+            if let Some(funding) = fundings.get_mut(id) {
+                funding.funding_txid = Some(txid);
+                funding.status = Status::Signed;
+                tracing::info!(channel = id, %txid, "signed channel funding transaction");
+            }
+        }
+    }
+}
+
+/// BOLT-3 funding witness script: `2 <key1> <key2> 2 OP_CHECKMULTISIG` with
+/// the keys in lexicographic order.
+fn funding_script(pubkeys: &[String; 2]) -> Result<ScriptBuf, Error> {
+    let mut keys = pubkeys
+        .iter()
+        .map(|key| {
+            PublicKey::from_str(key)
+                .ok()
+                .filter(|key| key.compressed)
+                .ok_or_else(|| Error::MalformedRequest(format!("invalid funding pubkey `{key}`")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    keys.sort_by_key(|key| key.to_bytes());
+    Ok(Builder::new()
+        .push_int(2)
+        .push_key(&keys[0])
+        .push_key(&keys[1])
+        .push_int(2)
+        .push_opcode(OP_CHECKMULTISIG)
+        .into_script())
+}
+
+fn check_funding_output(pubkeys: &[String; 2], script_pubkey: &ScriptBuf) -> Result<(), Error> {
+    if ScriptBuf::new_p2wsh(&funding_script(pubkeys)?.wscript_hash()) != *script_pubkey {
+        return Err(Error::MalformedRequest(
+            "funding address is not the 2-of-2 output of the given pubkeys".into(),
+        ));
+    }
+    Ok(())
+}
+
+fn enabled(state: &AppState) -> Result<(), Error> {
+    match state.config().channel_funding {
+        Some(_) => Ok(()),
+        None => Err(Error::NotFound(
+            "channel funding policy is not enabled".into(),
+        )),
+    }
+}
+
+pub async fn list(State(state): State<Arc<AppState>>) -> Result<Json<Vec<Funding>>, Error> {
+    enabled(&state)?;
+    Ok(Json(
+        state.channel_fundings.lock().values().cloned().collect(),
+    ))
+}
+
+pub async fn register(
+    State(state): State<Arc<AppState>>,
+    ApiJson(registration): ApiJson<Registration>,
+) -> Result<(StatusCode, Json<Funding>), Error> {
+    enabled(&state)?;
+    let config = state.config();
+    let address = Address::from_str(&registration.address)
+        .and_then(|address| address.require_network(config.network))
+        .map_err(|e| Error::MalformedRequest(format!("funding address: {e}")))?;
+    let script_pubkey = address.script_pubkey();
+    let require_pubkeys = config
+        .channel_funding
+        .as_ref()
+        .is_some_and(|policy| policy.require_pubkeys);
+    match &registration.pubkeys {
+        Some(pubkeys) => check_funding_output(pubkeys, &script_pubkey)?,
+        None if require_pubkeys => {
+            return Err(Error::MalformedRequest(
+                "`pubkeys` are required to verify the funding script".into(),
+            ));
+        }
+        None => {}
+    }
+
+    let funding = Funding {
+        id: registration.id,
+        address: address.to_string(),
+        script_pubkey,
+        amount_sat: registration.amount_sat,
+        commitment_txid: None,
+        funding_txid: None,
+        status: Status::AwaitingCommitment,
+    };
+    let mut fundings = state.channel_fundings.lock();
+    if fundings.contains_key(&funding.id) {
+        return Err(Error::MalformedRequest(format!(
+            "channel {} is already registered",
+            funding.id
+        )));
+    }
+    tracing::info!(
+        channel = funding.id,
+        address = funding.address,
+        "registered channel funding"
+    );
+    fundings.insert(funding.id.clone(), funding.clone());
+    Ok((StatusCode::CREATED, Json(funding)))
+}
+
+pub async fn register_commitment(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    ApiJson(reference): ApiJson<CommitmentReference>,
+) -> Result<Json<Funding>, Error> {
+    enabled(&state)?;
+    let mut fundings = state.channel_fundings.lock();
+    let funding = fundings
+        .get_mut(&id)
+        .ok_or_else(|| Error::NotFound(format!("channel funding {id}")))?;
+    if funding.status == Status::Signed {
+        return Err(Error::MalformedRequest(format!(
+            "channel {id} is already funded"
+        )));
+    }
+    funding.commitment_txid = Some(reference.commitment_txid);
+    funding.status = Status::Ready;
+    tracing::info!(channel = id, commitment_txid = %reference.commitment_txid, "registered channel commitment");
+    Ok(Json(funding.clone()))
+}
+
+pub async fn remove(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, Error> {
+    enabled(&state)?;
+    state
+        .channel_fundings
+        .lock()
+        .remove(&id)
+        .map(|_| StatusCode::NO_CONTENT)
+        .ok_or_else(|| Error::NotFound(format!("channel funding {id}")))
+}
@@ -0,0 +1,507 @@
+use std::{path::Path, process::ExitCode};
+
+use bdk_wallet::{descriptor::DescriptorError, keys::KeyError, KeychainKind};
+
+use crate::config::{Config, TemplateMode};
+
+/// Runs the `check-config` subcommand: validates the config at `path` the
+/// same way startup would, without binding any listener.
+pub fn check_config(path: &Path) -> ExitCode {
+    match validate(path) {
+        Ok(summary) => {
+            println!("{}: ok", path.display());
+            for line in summary {
+                println!("  {line}");
+            }
+            ExitCode::SUCCESS
+        }
+        Err(errors) => {
+            for e in errors {
+                eprintln!("error: {e}");
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn validate(path: &Path) -> Result<Vec<String>, Vec<String>> {
+    let config = Config::load(path).map_err(|e| vec![e.to_string()])?;
+    let hosted = crate::networks::load(&config).map_err(|e| vec![e])?;
+    let mut summary = validate_config(&config);
+    for hosted in hosted {
+        let prefix = |line: String| format!("networks.{}: {line}", hosted.network);
+        let result = validate_config(&hosted.config);
+        match (&mut summary, result) {
+            (Ok(summary), Ok(lines)) => summary.extend(lines.into_iter().map(prefix)),
+            (Ok(_), Err(errors)) => summary = Err(errors.into_iter().map(prefix).collect()),
+            (Err(errors), Err(more)) => errors.extend(more.into_iter().map(prefix)),
+            (Err(_), Ok(_)) => {}
+        }
+    }
+    summary
+}
+
+/// Checks a loaded config, the main one or one of its `networks`.
+fn validate_config(config: &Config) -> Result<Vec<String>, Vec<String>> {
+    let mut errors = Vec::new();
+
+    crate::descriptor::check_key_network(&config.xprv, config.network)
+        .map_err(|e| vec![format!("xprv: {e}")])?;
+    let wallet = match crate::create_wallet(config) {
+        Ok(wallet) => wallet,
+        Err(DescriptorError::Key(KeyError::InvalidNetwork)) => {
+            return Err(vec![format!(
+                "xprv: key is not valid for network `{}`",
+                config.network
+            )]);
+        }
+        // Building the signer asks it for the descriptor's keys.
+        Err(DescriptorError::Key(KeyError::Message(e))) if e.starts_with("signer:") => {
+            return Err(vec![e]);
+        }
+        Err(e) => return Err(vec![format!("xprv: invalid descriptor: {e}")]),
+    };
+
+    if wallet
+        .get_signers(KeychainKind::External)
+        .signers()
+        .is_empty()
+    {
+        errors.push("xprv: descriptor contains no private keys, nothing could be signed".into());
+    } else if let Err(e) = crate::self_test::run(&wallet) {
+        errors.push(format!("xprv: signing self-test failed: {e}"));
+    }
+
+    // Stores are checked against an empty one when storage cannot be opened.
+    let storage = crate::storage::open_read_only(config.storage.as_ref()).unwrap_or_else(|e| {
+        errors.push(format!("storage: {e}"));
+        std::sync::Arc::new(crate::storage::Memory::default())
+    });
+    if let Some(path) = config
+        .storage
+        .as_ref()
+        .and_then(|storage| storage.wallet_path.as_deref())
+    {
+        let name = crate::storage::name(path);
+        let stored = storage
+            .read(&name)
+            .map_err(|e| e.to_string())
+            .and_then(|stored| {
+                stored
+                    .map(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+                    .transpose()
+            })
+            .and_then(|stored| match stored {
+                Some(changeset) => crate::load_wallet(config, changeset).map(drop),
+                None => Ok(()),
+            });
+        if let Err(e) = stored {
+            errors.push(format!("storage.wallet_path: {name}: {e}"));
+        }
+    }
+
+    if let Some(audit) = &config.audit {
+        if let Some(key) = &audit.signing_key {
+            if let Err(e) = key.parse::<bitcoin::secp256k1::SecretKey>() {
+                errors.push(format!("audit.signing_key: {e}"));
+            }
+        }
+    }
+
+    if let Some(file_drop) = &config.file_drop {
+        if let Err(e) = crate::file_drop::check_dirs(file_drop) {
+            errors.push(e);
+        }
+    }
+
+    if let Some(labels) = &config.labels {
+        if let Err(e) = crate::labels::Labels::new(storage.clone(), config, labels) {
+            errors.push(format!("labels: {e}"));
+        }
+    }
+
+    #[cfg(feature = "redis")]
+    let shared = match config.redis.as_ref().map(crate::shared::Shared::connect) {
+        Some(Ok(shared)) => Some(std::sync::Arc::new(shared)),
+        Some(Err(e)) => {
+            errors.push(format!("redis: {e}"));
+            None
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "redis"))]
+    if config.redis.is_some() {
+        errors.push("redis: this binary was built without `redis`".into());
+    }
+
+    if let Some(spend_guard) = &config.spend_guard {
+        #[cfg(feature = "redis")]
+        let guard = match &shared {
+            Some(shared) => Some(Ok(crate::spend_guard::SpendGuard::shared(shared.clone()))),
+            None if config.redis.is_some() => None,
+            None => Some(crate::spend_guard::SpendGuard::new(
+                storage.clone(),
+                spend_guard,
+            )),
+        };
+        #[cfg(not(feature = "redis"))]
+        let guard = config
+            .redis
+            .is_none()
+            .then(|| crate::spend_guard::SpendGuard::new(storage.clone(), spend_guard));
+        if let Some(Err(e)) = guard.map(|guard| guard.and_then(|guard| guard.probe())) {
+            errors.push(format!("spend_guard: {e}"));
+        }
+    }
+
+    if let Some(attestation) = &config.attestation {
+        if let Err(e) = crate::attestation::Attester::new(attestation) {
+            errors.push(e);
+        }
+    }
+
+    if let Some(journal) = &config.journal {
+        if let Err(e) = crate::journal::Journal::open(storage.clone(), journal) {
+            errors.push(format!("journal: {e}"));
+        }
+    }
+
+    if let Some(replication) = &config.replication {
+        if let Err(e) = crate::replication::Replication::new(replication, None) {
+            errors.push(format!("replication: {e}"));
+        }
+    }
+
+    if let Some(schedules) = &config.schedules {
+        if config.chain.is_none() {
+            errors.push("schedules: needs [chain]".into());
+        }
+        if let Err(e) = crate::schedule::Schedules::new(storage.clone(), config, schedules) {
+            errors.push(format!("schedules: {e}"));
+        }
+    }
+
+    if let Some(consolidation) = &config.consolidation {
+        if config.chain.is_none() {
+            errors.push("consolidation: needs [chain]".into());
+        }
+        let rate = consolidation.max_fee_rate_sat_vb;
+        if !rate.is_finite() || rate <= 0.0 {
+            errors.push("consolidation.max_fee_rate_sat_vb: must be a positive number".into());
+        }
+    }
+
+    if let Some(fee_check) = &config.fee_check {
+        if fee_check.fetch_prevouts && config.chain.is_none() {
+            errors.push("fee_check.fetch_prevouts: needs [chain]".into());
+        }
+        let (min, max) = (fee_check.min_fee_rate_sat_vb, fee_check.max_fee_rate_sat_vb);
+        if !min.is_finite() || min < 0.0 {
+            errors.push("fee_check.min_fee_rate_sat_vb: must be a number, at least 0".into());
+        }
+        if !max.is_finite() || max < min {
+            errors.push(
+                "fee_check.max_fee_rate_sat_vb: must be a number, at least the minimum".into(),
+            );
+        }
+    }
+
+    if let Some(locktime) = &config.locktime {
+        let needs_tip = locktime.anti_fee_sniping || locktime.max_future_blocks.is_some();
+        if needs_tip && config.chain.is_none() {
+            errors.push(
+                "locktime: anti_fee_sniping and max_future_blocks need [chain] to know the tip"
+                    .into(),
+            );
+        }
+    }
+
+    if let Some(approval) = &config.approval {
+        if let Err(e) = reqwest::Url::parse(&approval.url) {
+            errors.push(format!("approval.url: {e}"));
+        }
+        if approval.timeout_ms == 0 {
+            errors.push("approval.timeout_ms: must be positive".into());
+        }
+    }
+
+    if let Some(auth) = &config.auth {
+        if auth.credentials.is_empty() {
+            errors.push("auth.credentials: none, so every endpoint would be refused".into());
+        }
+        for (name, credential) in &auth.credentials {
+            if credential.token.is_empty() {
+                errors.push(format!("auth.credentials.{name}.token: empty"));
+            }
+            if credential.scopes.is_empty() {
+                errors.push(format!("auth.credentials.{name}.scopes: none"));
+            }
+        }
+        let tokens: std::collections::BTreeSet<&String> =
+            auth.credentials.values().map(|c| &c.token).collect();
+        if tokens.len() < auth.credentials.len() {
+            errors.push("auth.credentials: two credentials share a token".into());
+        }
+    }
+
+    if let Some(templates) = &config.templates {
+        if templates.templates.is_empty() && templates.mode == TemplateMode::Enforce {
+            errors.push("templates: none registered, so no PSBT would be signed".into());
+        }
+        let mut names = std::collections::BTreeSet::new();
+        for template in &templates.templates {
+            let name = &template.name;
+            if !names.insert(name) {
+                errors.push(format!("templates: `{name}` is registered twice"));
+            }
+            if template
+                .max_payments
+                .is_some_and(|max| template.min_payments > max)
+            {
+                errors.push(format!(
+                    "templates.{name}: `min_payments` is above `max_payments`"
+                ));
+            }
+            if template.change.min_outputs > template.change.max_outputs {
+                errors.push(format!(
+                    "templates.{name}.change: `min_outputs` is above `max_outputs`"
+                ));
+            }
+            for (i, output) in template.outputs.iter().enumerate() {
+                if let Some(address) = &output.address {
+                    if let Err(e) = address
+                        .parse::<bitcoin::Address<bitcoin::address::NetworkUnchecked>>()
+                        .and_then(|address| address.require_network(config.network))
+                    {
+                        errors.push(format!("templates.{name}.outputs[{i}].address: {e}"));
+                    }
+                }
+                if let (Some(min), Some(max)) = (output.min_sat, output.max_sat) {
+                    if min > max {
+                        errors.push(format!(
+                            "templates.{name}.outputs[{i}]: `min_sat` is above `max_sat`"
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(pairing) = &config.sighash_pairing {
+        if pairing.sighashes.is_empty() {
+            errors.push("sighash_pairing.sighashes: none".into());
+        }
+        for sighash in &pairing.sighashes {
+            if !crate::sighash_pairing::pairable(*sighash) {
+                errors.push(format!(
+                    "sighash_pairing.sighashes: `{}` does not commit to an output to pair",
+                    sighash.name()
+                ));
+            }
+            if config.sign_options.allowed_sighashes.contains(sighash) {
+                errors.push(format!(
+                    "sighash_pairing.sighashes: `{}` is also in sign_options.allowed_sighashes, \
+                     which signs it unpaired",
+                    sighash.name()
+                ));
+            }
+        }
+    }
+
+    if let Some(windows) = &config.signing_windows {
+        if let Err(e) = windows.utc_offset.parse::<chrono::FixedOffset>() {
+            errors.push(format!("signing_windows.utc_offset: {e}"));
+        }
+        for (i, window) in windows.windows.iter().enumerate() {
+            if window.days.is_empty() {
+                errors.push(format!("signing_windows.windows[{i}]: no days"));
+            }
+            if window.end <= window.start {
+                errors.push(format!(
+                    "signing_windows.windows[{i}]: `end` must be later than `start`"
+                ));
+            }
+        }
+        for (i, blackout) in windows.blackouts.iter().enumerate() {
+            if blackout.to <= blackout.from {
+                errors.push(format!(
+                    "signing_windows.blackouts[{i}]: `to` must be later than `from`"
+                ));
+            }
+        }
+        if let Some(threshold) = windows.override_threshold {
+            match &config.quorum {
+                None => errors.push("signing_windows.override_threshold: needs [quorum]".into()),
+                Some(quorum) if threshold == 0 || threshold > quorum.credentials.len() => errors
+                    .push(format!(
+                        "signing_windows.override_threshold: must be between 1 and the {} \
+                         quorum credentials",
+                        quorum.credentials.len()
+                    )),
+                Some(_) => {}
+            }
+        }
+    }
+
+    if let Some(backup) = &config.backup {
+        if let Err(e) = backup
+            .recovery_pubkey
+            .parse::<bitcoin::secp256k1::PublicKey>()
+        {
+            errors.push(format!("backup.recovery_pubkey: {e}"));
+        }
+    }
+
+    if let Some(quorum) = &config.quorum {
+        if quorum.threshold == 0 || quorum.threshold > quorum.credentials.len() {
+            errors.push(format!(
+                "quorum.threshold: must be between 1 and the {} credentials",
+                quorum.credentials.len()
+            ));
+        }
+        if quorum.window_secs == 0 {
+            errors.push("quorum.window_secs: must be positive".into());
+        }
+        let tokens: std::collections::BTreeSet<&String> = quorum.credentials.values().collect();
+        if tokens.len() < quorum.credentials.len() {
+            errors.push("quorum.credentials: two credentials share a token".into());
+        }
+        for (name, token) in &quorum.credentials {
+            if token.is_empty() {
+                errors.push(format!("quorum.credentials.{name}: the token is empty"));
+            }
+        }
+    }
+
+    if let Err(e) = crate::cors::layer(&config.cors) {
+        errors.push(e);
+    }
+
+    for listen in &config.listen {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &listen.tls {
+            if let Err(e) = crate::tls::acceptor(tls, config.http.http2) {
+                errors.push(format!("listen {}: {e}", listen.addr));
+            }
+        }
+        #[cfg(not(feature = "tls"))]
+        if listen.tls.is_some() {
+            errors.push(format!(
+                "listen {}: tls is set but this binary was built without `tls`",
+                listen.addr
+            ));
+        }
+    }
+
+    #[cfg(feature = "nostr")]
+    if let Some(nostr) = &config.nostr {
+        if let Err(e) = crate::nostr_transport::validate(nostr) {
+            errors.push(e);
+        }
+    }
+
+    #[cfg(feature = "liquid")]
+    let liquid = match config
+        .liquid
+        .as_ref()
+        .map(|liquid| crate::liquid::Liquid::new(config, liquid))
+    {
+        Some(Ok(liquid)) => Some(liquid),
+        Some(Err(e)) => {
+            errors.push(e);
+            None
+        }
+        None => None,
+    };
+
+    let probed = probe_backends(config);
+    errors.extend(probed.iter().filter_map(|probed| probed.clone().err()));
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut summary = vec![format!("network: {}", config.network)];
+    if let Some(port) = config.port {
+        summary.push(format!("port: {port}"));
+    }
+    if let Some(socket) = &config.unix_socket {
+        summary.push(format!("unix socket: {}", socket.path.display()));
+    }
+    if let Some(file_drop) = &config.file_drop {
+        summary.push(format!(
+            "file drop: {} -> {}",
+            file_drop.input_dir.display(),
+            file_drop.output_dir.display()
+        ));
+    }
+    summary.extend([
+        format!(
+            "descriptor: {}",
+            wallet.public_descriptor(KeychainKind::External)
+        ),
+        format!(
+            "first address: {}",
+            wallet.peek_address(KeychainKind::External, 0).address
+        ),
+    ]);
+    if config.change_xprv.is_some() {
+        summary.push(format!(
+            "change descriptor: {}",
+            wallet.public_descriptor(KeychainKind::Internal)
+        ));
+    }
+    #[cfg(feature = "liquid")]
+    if let Some(liquid) = liquid {
+        summary.push(format!("liquid descriptor: {}", liquid.descriptor()));
+    }
+    if let Some(signer) = &config.signer {
+        summary.push(format!(
+            "signer: {}, derives the descriptor's keys",
+            signer.kind()
+        ));
+    }
+    summary.extend(probed.into_iter().flatten());
+    Ok(summary)
+}
+
+/// Asks the chain backend and the approval endpoint, when configured,
+/// whether they answer, without changing anything at either. They are
+/// async, and `check-config` runs within the service's runtime, so they run
+/// on a runtime of their own.
+fn probe_backends(config: &Config) -> Vec<Result<String, String>> {
+    let probes = async {
+        let mut probed = Vec::new();
+        if let Some(chain) = &config.chain {
+            let url = &chain.esplora_url;
+            probed.push(
+                crate::chain::probe(chain, config.network)
+                    .await
+                    .map(|height| format!("chain: {url} at height {height}"))
+                    .map_err(|e| format!("chain.esplora_url: {url}: {e}")),
+            );
+        }
+        if let Some(approval) = &config.approval {
+            let url = &approval.url;
+            probed.push(
+                crate::approval::probe(approval)
+                    .await
+                    .map(|status| format!("approval: {url} answers ({status})"))
+                    .map_err(|e| format!("approval.url: {url}: {e}")),
+            );
+        }
+        probed
+    };
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map(|runtime| runtime.block_on(probes))
+                    .unwrap_or_else(|e| vec![Err(format!("probing backends: {e}"))])
+            })
+            .join()
+            .unwrap_or_else(|_| vec![Err("probing backends panicked".into())])
+    })
+}
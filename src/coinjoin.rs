@@ -0,0 +1,130 @@
+//! Signing policy for collaborative transactions such as coinjoins.
+//!
+//! A PSBT spending coins of other wallets is only signed if what this wallet
+//! puts in comes back to its own addresses, less a bounded share of the
+//! mining fee and a configured tolerance for anything else the round costs,
+//! such as a coordinator fee. Our inputs must use `SIGHASH_ALL`: any other
+//! mode would let the other participants change the transaction after we
+//! signed.
+//!
+//! With `signing.strict_foreign_inputs`, such a PSBT is refused outright
+//! unless the request declares it `collaborative`, so a transaction someone
+//! else built around one of our coins is not co-signed by accident.
+
+use bdk_wallet::Wallet;
+use bitcoin::{Amount, Psbt};
+
+use crate::{config::CoinjoinConfig, error::Error};
+
+/// Inputs of `psbt` that do not spend a coin of `wallet`, including those
+/// whose coin cannot be told for lack of UTXO data.
+pub fn foreign_inputs(wallet: &Wallet, psbt: &Psbt) -> Vec<usize> {
+    (0..psbt.inputs.len())
+        .filter(|&index| {
+            psbt.spend_utxo(index)
+                .map_or(true, |utxo| !wallet.is_mine(utxo.script_pubkey.clone()))
+        })
+        .collect()
+}
+
+/// Refuses `psbt` if it has foreign inputs and the request did not declare
+/// it `collaborative`, naming them.
+pub fn check_declared(wallet: &Wallet, psbt: &Psbt, collaborative: bool) -> Result<(), Error> {
+    let foreign = foreign_inputs(wallet, psbt);
+    if foreign.is_empty() {
+        return Ok(());
+    }
+    let listed = foreign
+        .iter()
+        .map(|&index| {
+            format!(
+                "{index} ({})",
+                psbt.unsigned_tx.input[index].previous_output
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    if !collaborative {
+        let inputs = match foreign.len() {
+            1 => "input",
+            _ => "inputs",
+        };
+        return Err(Error::InvalidTransaction(format!(
+            "{inputs} {listed} not spending this wallet's coins; declare the request \
+             `collaborative` to sign a transaction shared with other wallets"
+        )));
+    }
+    tracing::info!(foreign_inputs = %listed, "signing a declared collaborative transaction");
+    Ok(())
+}
+
+/// Checks `psbt` against `policy` if it spends coins of other wallets.
+pub fn check(wallet: &Wallet, psbt: &Psbt, policy: &CoinjoinConfig) -> Result<(), Error> {
+    let reject = |reason: String| Err(Error::InvalidTransaction(format!("coinjoin: {reason}")));
+    let mut ours_in = Amount::ZERO;
+    let mut ours = Vec::new();
+    let mut collaborative = false;
+    for index in 0..psbt.inputs.len() {
+        let utxo = match psbt.spend_utxo(index) {
+            Ok(utxo) => utxo,
+            Err(e) => return reject(format!("cannot tell whose coin input {index} spends: {e}")),
+        };
+        if wallet.is_mine(utxo.script_pubkey.clone()) {
+            ours_in += utxo.value;
+            ours.push(index);
+        } else {
+            collaborative = true;
+        }
+    }
+    if !collaborative || ours.is_empty() {
+        return Ok(());
+    }
+
+    for &index in &ours {
+        // 0x00 is the taproot default, which behaves as SIGHASH_ALL.
+        if let Some(ty) = psbt.inputs[index].sighash_type {
+            if !matches!(ty.to_u32(), 0x00 | 0x01) {
+                return reject(format!(
+                    "input {index} requests sighash {ty}, only ALL is allowed"
+                ));
+            }
+        }
+    }
+
+    let ours_out = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .filter(|output| wallet.is_mine(output.script_pubkey.clone()))
+        .map(|output| output.value)
+        .sum::<Amount>();
+    let loss = ours_in.checked_sub(ours_out).unwrap_or(Amount::ZERO);
+    let fee = match psbt.fee() {
+        Ok(fee) => fee,
+        Err(e) => return reject(format!("fee: {e}")),
+    };
+    // Whatever we lose beyond the whole mining fee goes to other participants.
+    let fee_share = loss.min(fee);
+    let unmatched = loss - fee_share;
+    if fee_share.to_sat() > policy.max_fee_sat {
+        return reject(format!(
+            "this wallet would pay up to {} sat of the mining fee, at most {} sat is allowed",
+            fee_share.to_sat(),
+            policy.max_fee_sat
+        ));
+    }
+    if unmatched.to_sat() > policy.tolerance_sat {
+        return reject(format!(
+            "outputs to this wallet fall {} sat short of its inputs beyond the mining fee, \
+             the tolerance is {} sat",
+            unmatched.to_sat(),
+            policy.tolerance_sat
+        ));
+    }
+    tracing::info!(
+        inputs = ours.len(),
+        loss_sat = loss.to_sat(),
+        "coinjoin policy passed"
+    );
+    Ok(())
+}
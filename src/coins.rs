@@ -0,0 +1,233 @@
+//! The wallet's coins and transactions as synced from `[chain]`, with their
+//! BIP-329 labels. On the admin listener, since they reveal the wallet's
+//! whole history. Both lists are paginated, see [`crate::page`].
+
+use std::sync::Arc;
+
+use axum::extract::{Query, State};
+use bdk_wallet::chain::{ChainPosition, ConfirmationBlockTime};
+use bitcoin::{Address, OutPoint, Txid};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Error,
+    labels::{LabelType, Labels},
+    page::{paginate, Key, Order, Page, PageQuery},
+    AppState,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Confirmed,
+    Unconfirmed,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UtxoSort {
+    #[default]
+    Outpoint,
+    Amount,
+    /// Confirmation height, unconfirmed coins last.
+    Height,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct UtxoFilter {
+    pub status: Option<Status>,
+    pub min_amount_sat: Option<u64>,
+    pub max_amount_sat: Option<u64>,
+    #[serde(default)]
+    pub sort: UtxoSort,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionSort {
+    /// Confirmation height, unconfirmed transactions last.
+    #[default]
+    Height,
+    Amount,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TransactionFilter {
+    pub status: Option<Status>,
+    /// Confirmed at or after; excludes unconfirmed transactions.
+    pub from: Option<DateTime<Utc>>,
+    /// Confirmed before; excludes unconfirmed transactions.
+    pub to: Option<DateTime<Utc>>,
+    /// Bounds on the net amount, what the transaction moves into or out of
+    /// the wallet.
+    pub min_amount_sat: Option<u64>,
+    pub max_amount_sat: Option<u64>,
+    #[serde(default)]
+    pub sort: TransactionSort,
+}
+
+fn in_range(amount: u64, min: Option<u64>, max: Option<u64>) -> bool {
+    min.map_or(true, |min| amount >= min) && max.map_or(true, |max| amount <= max)
+}
+
+fn has_status(confirmation_height: Option<u32>, status: Option<Status>) -> bool {
+    match status {
+        None => true,
+        Some(Status::Confirmed) => confirmation_height.is_some(),
+        Some(Status::Unconfirmed) => confirmation_height.is_none(),
+    }
+}
+
+/// Sorts unconfirmed items after every confirmed one.
+fn height_key(confirmation_height: Option<u32>) -> u64 {
+    confirmation_height.map_or(u64::MAX, u64::from)
+}
+
+#[derive(Serialize)]
+pub struct Utxo {
+    pub outpoint: OutPoint,
+    pub address: Option<String>,
+    pub amount_sat: u64,
+    /// Index of the receive address it pays.
+    pub derivation_index: u32,
+    /// Height of the confirming block; absent while unconfirmed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirmation_height: Option<u32>,
+    /// Label of the coin, or else of its address.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// `false` when the coin's label marks it as not spendable.
+    pub spendable: bool,
+}
+
+#[derive(Serialize)]
+pub struct WalletTransaction {
+    pub txid: Txid,
+    /// Value of the wallet's coins the transaction spends.
+    pub sent_sat: u64,
+    /// Value it pays to the wallet's addresses.
+    pub received_sat: u64,
+    /// Known when the wallet has every spent coin.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_sat: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirmation_height: Option<u32>,
+    /// Time of the confirming block.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirmation_time: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+pub async fn utxos(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<UtxoFilter>,
+    Query(page): Query<PageQuery>,
+) -> Result<Page<Utxo>, Error> {
+    crate::chain::require_synced(&state)?;
+    let network = state.config().network;
+    let labels = state.labels.as_ref();
+    let wallet = state.wallet();
+    let utxos = wallet
+        .list_unspent()
+        .filter(|utxo| {
+            in_range(
+                utxo.txout.value.to_sat(),
+                filter.min_amount_sat,
+                filter.max_amount_sat,
+            ) && has_status(height(&utxo.chain_position), filter.status)
+        })
+        .map(|utxo| {
+            let address = Address::from_script(&utxo.txout.script_pubkey, network)
+                .ok()
+                .map(|address| address.to_string());
+            let coin =
+                labels.and_then(|labels| labels.get(LabelType::Output, &utxo.outpoint.to_string()));
+            let label = coin
+                .as_ref()
+                .and_then(|coin| coin.label.clone())
+                .or_else(|| label_of(labels, LabelType::Addr, address.as_deref()));
+            Utxo {
+                outpoint: utxo.outpoint,
+                address,
+                amount_sat: utxo.txout.value.to_sat(),
+                derivation_index: utxo.derivation_index,
+                confirmation_height: height(&utxo.chain_position),
+                label,
+                spendable: coin.and_then(|coin| coin.spendable).unwrap_or(true),
+            }
+        });
+    paginate(utxos, &page, Order::Asc, |utxo| match filter.sort {
+        UtxoSort::Outpoint => Key::new(0, utxo.outpoint),
+        UtxoSort::Amount => Key::new(utxo.amount_sat, utxo.outpoint),
+        UtxoSort::Height => Key::new(height_key(utxo.confirmation_height), utxo.outpoint),
+    })
+}
+
+pub async fn transactions(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<TransactionFilter>,
+    Query(page): Query<PageQuery>,
+) -> Result<Page<WalletTransaction>, Error> {
+    crate::chain::require_synced(&state)?;
+    let labels = state.labels.as_ref();
+    let wallet = state.wallet();
+    let transactions = wallet
+        .transactions()
+        .map(|tx| {
+            let txid = tx.tx_node.txid;
+            let (sent, received) = wallet.sent_and_received(&tx.tx_node.tx);
+            WalletTransaction {
+                txid,
+                sent_sat: sent.to_sat(),
+                received_sat: received.to_sat(),
+                fee_sat: wallet
+                    .calculate_fee(&tx.tx_node.tx)
+                    .ok()
+                    .map(|fee| fee.to_sat()),
+                confirmation_height: height(&tx.chain_position),
+                confirmation_time: confirmation_time(&tx.chain_position),
+                label: label_of(labels, LabelType::Tx, Some(&txid.to_string())),
+            }
+        })
+        .filter(|tx| {
+            let time = tx.confirmation_time;
+            has_status(tx.confirmation_height, filter.status)
+                && filter
+                    .from
+                    .map_or(true, |from| time.is_some_and(|t| t >= from))
+                && filter.to.map_or(true, |to| time.is_some_and(|t| t < to))
+                && in_range(net_amount(tx), filter.min_amount_sat, filter.max_amount_sat)
+        });
+    paginate(transactions, &page, Order::Asc, |tx| match filter.sort {
+        TransactionSort::Height => Key::new(height_key(tx.confirmation_height), tx.txid),
+        TransactionSort::Amount => Key::new(net_amount(tx), tx.txid),
+    })
+}
+
+fn net_amount(tx: &WalletTransaction) -> u64 {
+    tx.received_sat.abs_diff(tx.sent_sat)
+}
+
+fn label_of(labels: Option<&Labels>, kind: LabelType, reference: Option<&str>) -> Option<String> {
+    labels
+        .zip(reference)
+        .and_then(|(labels, reference)| labels.text(kind, reference))
+}
+
+fn confirmation_time(position: &ChainPosition<ConfirmationBlockTime>) -> Option<DateTime<Utc>> {
+    match position {
+        ChainPosition::Confirmed { anchor, .. } => {
+            DateTime::from_timestamp(i64::try_from(anchor.confirmation_time).ok()?, 0)
+        }
+        ChainPosition::Unconfirmed { .. } => None,
+    }
+}
+
+fn height(position: &ChainPosition<ConfirmationBlockTime>) -> Option<u32> {
+    match position {
+        ChainPosition::Confirmed { anchor, .. } => Some(anchor.block_id.height),
+        ChainPosition::Unconfirmed { .. } => None,
+    }
+}
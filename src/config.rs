@@ -0,0 +1,1593 @@
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+/// Prefix of environment variables that override config fields. Nested keys
+/// are separated by `__`, e.g. `ISSUE_SERVICE__PORT=3002`.
+pub const ENV_PREFIX: &str = "ISSUE_SERVICE__";
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Config {
+    /// TCP port of the public listener, bound on `0.0.0.0`. May be omitted
+    /// when `listen` or `unix_socket` is set.
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Addresses the public listener is also bound to, each optionally with
+    /// TLS.
+    #[serde(default)]
+    pub listen: Vec<ListenConfig>,
+    /// Unix domain socket the public API is also served on.
+    #[serde(default)]
+    pub unix_socket: Option<UnixSocketConfig>,
+    pub network: bitcoin::Network,
+    /// Descriptor holding the signing xprv. May be omitted with
+    /// `signing.dry_run`, in which case a throwaway key is generated, or in
+    /// favour of `descriptor` and `key_file`, from which it is built at load.
+    #[serde(default)]
+    pub xprv: String,
+    /// Public receive descriptor, so the config file holds no secret. Its
+    /// xpub is swapped for the xprv in `key_file` at load.
+    #[serde(default)]
+    pub descriptor: Option<String>,
+    /// Public change descriptor with the same key as `descriptor`. Change
+    /// goes to receive addresses when unset.
+    #[serde(default)]
+    pub change_descriptor: Option<String>,
+    /// File holding the xprv of `descriptor`, optionally with its key
+    /// origin.
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+    /// Master key fingerprint the key origin in `descriptor` must carry.
+    #[serde(default)]
+    pub fingerprint: Option<bitcoin::bip32::Fingerprint>,
+    /// Signer of keys the descriptor only names by xpub. Only the
+    /// descriptor's own private keys sign when absent.
+    #[serde(default)]
+    pub signer: Option<SignerConfig>,
+    /// `change_descriptor` with the xprv, built at load.
+    #[serde(skip)]
+    pub change_xprv: Option<String>,
+    /// Set when `xprv` is a throwaway key generated at load.
+    #[serde(skip)]
+    pub throwaway_key: bool,
+    /// Address of the admin listener. Admin endpoints are not served at all
+    /// when this is unset; they are never exposed on the public port.
+    #[serde(default)]
+    pub admin_listen: Option<SocketAddr>,
+    /// Base URL outsiders reach the public API at, e.g.
+    /// `https://pay.example.com`. Used for the `pj` parameter of payment
+    /// URIs.
+    #[serde(default)]
+    pub public_url: Option<String>,
+    /// Serve `GET /debug/test_vectors`. Read on every request.
+    #[serde(default)]
+    pub debug_endpoints: bool,
+    /// Address of the gRPC listener. Only honored when built with the
+    /// `grpc` feature.
+    #[serde(default)]
+    pub grpc_listen: Option<SocketAddr>,
+    /// Audit log of signing decisions. Disabled when the section is absent.
+    #[serde(default)]
+    pub audit: Option<AuditConfig>,
+    /// Key signed PSBTs are attested with. Responses carry no attestation
+    /// when absent.
+    #[serde(default)]
+    pub attestation: Option<AttestationConfig>,
+    /// Log file written in addition to stdout. Disabled when absent.
+    #[serde(default)]
+    pub log: Option<LogConfig>,
+    #[serde(default)]
+    pub timeouts: TimeoutConfig,
+    #[serde(default)]
+    pub http: HttpConfig,
+    /// Browser access from other origins. Denied unless origins are listed.
+    #[serde(default)]
+    pub cors: CorsConfig,
+    #[serde(default)]
+    pub signing: SigningConfig,
+    /// What the wallet's keys may sign. A request may only tighten these.
+    #[serde(default)]
+    pub sign_options: SignOptionsConfig,
+    /// Sighash types signed only for inputs a request pairs with the output
+    /// they commit to. None are when absent.
+    #[serde(default)]
+    pub sighash_pairing: Option<SighashPairingConfig>,
+    /// Branches of the descriptor's policy the transactions the service
+    /// builds satisfy, for descriptors with alternative spending paths.
+    #[serde(default)]
+    pub policy_path: PolicyPath,
+    #[serde(default)]
+    pub rgb: RgbConfig,
+    #[serde(default)]
+    pub payout: PayoutConfig,
+    #[serde(default)]
+    pub op_return: OpReturnConfig,
+    /// Lightning channel funding safeguards. Disabled when absent.
+    #[serde(default)]
+    pub channel_funding: Option<ChannelFundingConfig>,
+    /// Dust thresholds outputs of signed and built transactions must meet.
+    /// Dust is not checked when absent.
+    #[serde(default)]
+    pub dust: Option<DustConfig>,
+    /// Fee rate bounds PSBTs are held to, their fee recomputed from the
+    /// coins they spend. Fees are not checked when absent.
+    #[serde(default)]
+    pub fee_check: Option<FeeCheckConfig>,
+    /// Rules on the locktime and input sequences of PSBTs. Neither is
+    /// checked when absent.
+    #[serde(default)]
+    pub locktime: Option<LocktimeConfig>,
+    /// Shapes of transaction PSBTs must take to be signed. Any shape is
+    /// signed when absent.
+    #[serde(default)]
+    pub templates: Option<TemplatesConfig>,
+    /// What becomes of unknown and proprietary PSBT fields and of taproot
+    /// annexes. All are kept when absent.
+    #[serde(default)]
+    pub psbt_fields: Option<PsbtFieldsConfig>,
+    /// External endpoint every signing must be approved by. Signing needs
+    /// no approval when absent.
+    #[serde(default)]
+    pub approval: Option<ApprovalConfig>,
+    /// Credentials of which a quorum must submit a transaction before it is
+    /// signed. One submission is enough when absent.
+    #[serde(default)]
+    pub quorum: Option<QuorumConfig>,
+    /// Credentials endpoints need and the scopes each grants. Endpoints are
+    /// open to anyone who reaches the listener when absent.
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+    /// Times of the week signing is open at, and blackouts it is closed
+    /// during. Signing is always open when absent.
+    #[serde(default)]
+    pub signing_windows: Option<SigningWindowsConfig>,
+    /// Policy for PSBTs that also spend other wallets' coins. Such PSBTs
+    /// are signed unchecked when absent.
+    #[serde(default)]
+    pub coinjoin: Option<CoinjoinConfig>,
+    /// Esplora backend the wallet is synced from. Features that need to
+    /// know the wallet's coins (payjoin) are unavailable without it.
+    #[serde(default)]
+    pub chain: Option<ChainConfig>,
+    /// BIP-352 receiving. Scanning for payments also needs `chain`.
+    #[serde(default)]
+    pub silent_payments: Option<SilentPaymentsConfig>,
+    /// BIP-129 multisig setup. Disabled when absent.
+    #[serde(default)]
+    pub bsms: Option<BsmsConfig>,
+    /// BIP-329 labels for addresses, coins and transactions. Disabled when
+    /// absent.
+    #[serde(default)]
+    pub labels: Option<LabelsConfig>,
+    /// Merging small coins into one while fees are low. Needs `chain`.
+    /// Disabled when absent.
+    #[serde(default)]
+    pub consolidation: Option<ConsolidationConfig>,
+    /// Xpubs derived below the wallet's key for coordinators. Disabled when
+    /// absent.
+    #[serde(default)]
+    pub xpub_derivation: Option<XpubDerivationConfig>,
+    /// Where the audit log, the spend guard's records, labels, schedules,
+    /// BSMS wallets and the wallet's changes are kept. Files at their
+    /// configured paths when absent.
+    #[serde(default)]
+    pub storage: Option<StorageConfig>,
+    /// Spend guard records and cached answers shared between replicas.
+    /// Needs the `redis` feature. Disabled when absent.
+    #[serde(default)]
+    pub redis: Option<RedisConfig>,
+    /// Mirroring the spend guard, labels and audit log to a standby.
+    /// Disabled when absent.
+    #[serde(default)]
+    pub replication: Option<ReplicationConfig>,
+    /// Refusing to sign a coin into a second, conflicting transaction.
+    /// Disabled when absent.
+    #[serde(default)]
+    pub spend_guard: Option<SpendGuardConfig>,
+    /// Journal of signing requests and their outcomes, kept across
+    /// restarts. Disabled when absent.
+    #[serde(default)]
+    pub journal: Option<JournalConfig>,
+    /// Encrypted backups served on `/admin/backup`. Disabled when absent.
+    #[serde(default)]
+    pub backup: Option<BackupConfig>,
+    /// Transactions built, signed and broadcast on a schedule. Needs
+    /// `chain`. Disabled when absent.
+    #[serde(default)]
+    pub schedules: Option<SchedulesConfig>,
+    /// Signing requests over Nostr relays. Only honored when built with the
+    /// `nostr` feature.
+    #[serde(default)]
+    pub nostr: Option<NostrConfig>,
+    /// Elements PSET signing for Liquid. Only honored when built with the
+    /// `liquid` feature.
+    #[serde(default)]
+    pub liquid: Option<LiquidConfig>,
+    /// Directories watched by `issue-service file-drop`, the offline mode
+    /// that signs files instead of serving requests.
+    #[serde(default)]
+    pub file_drop: Option<FileDropConfig>,
+    /// Config files of wallets on other networks, by network, served under
+    /// `/<network>` on this config's listeners. Fixed at startup.
+    #[serde(default)]
+    pub networks: std::collections::BTreeMap<String, PathBuf>,
+    /// Set when this is the config of one of the main config's `networks`.
+    #[serde(skip)]
+    pub hosted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SignerConfig {
+    /// A master xprv held in memory.
+    Software { xprv: bitcoin::bip32::Xpriv },
+    /// An HTTP signing service, see [`crate::signer::RemoteSigner`].
+    Remote {
+        url: String,
+        /// Master fingerprint of the service's keys.
+        fingerprint: bitcoin::bip32::Fingerprint,
+        /// Sent as a bearer token.
+        #[serde(default)]
+        auth_token: Option<String>,
+        #[serde(default = "default_remote_signer_timeout_ms")]
+        timeout_ms: u64,
+    },
+    /// A signer in a trusted execution environment, reached over a local
+    /// socket, see [`crate::signer::EnclaveSigner`].
+    Enclave {
+        /// Unix socket the enclave's host process listens on.
+        socket: PathBuf,
+        /// Master fingerprint of the enclave's keys.
+        fingerprint: bitcoin::bip32::Fingerprint,
+        #[serde(default = "default_remote_signer_timeout_ms")]
+        timeout_ms: u64,
+        /// Hex measurement the enclave must attest to. Not checked when
+        /// unset.
+        #[serde(default)]
+        measurement: Option<String>,
+    },
+    /// A key from a fixed seed, for tests.
+    Mock {
+        /// Hex seed of the master key. A fixed one when unset.
+        #[serde(default)]
+        seed: Option<String>,
+        /// Fail every signing call.
+        #[serde(default)]
+        refuse: bool,
+    },
+}
+
+impl SignerConfig {
+    /// The `type` of each variant, for `/version`.
+    pub const KINDS: &'static [&'static str] = &["software", "remote", "enclave", "mock"];
+
+    /// The `type` this signer is configured with.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SignerConfig::Software { .. } => "software",
+            SignerConfig::Remote { .. } => "remote",
+            SignerConfig::Enclave { .. } => "enclave",
+            SignerConfig::Mock { .. } => "mock",
+        }
+    }
+}
+
+fn default_remote_signer_timeout_ms() -> u64 {
+    5000
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct FileDropConfig {
+    /// Directory polled for `.psbt` files to sign. Processed files are
+    /// removed from it.
+    pub input_dir: PathBuf,
+    /// Directory signed PSBTs and rejection notes are written to.
+    pub output_dir: PathBuf,
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+}
+
+fn default_poll_interval_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct LiquidConfig {
+    /// Chain the PSETs are for. Fixed at startup.
+    pub network: LiquidNetwork,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LiquidNetwork {
+    Liquid,
+    LiquidTestnet,
+    ElementsRegtest,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct BsmsConfig {
+    /// JSON file the registered multisig wallets are kept in. Holds public
+    /// descriptors only.
+    pub wallets_path: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct XpubDerivationConfig {
+    /// Paths `/admin/derive_xpub` may derive, such as `m/48'/1'/*'/2'`. `*`
+    /// matches any unhardened index and `*'` any hardened one.
+    pub allowed_paths: Vec<crate::xpub::PathPattern>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct ReplicationConfig {
+    /// Shared secret of the primary and its standbys, at least 16
+    /// characters.
+    pub token: String,
+    /// Admin listener of the primary, such as `http://10.0.0.1:3002`. Set
+    /// on a standby only.
+    #[serde(default)]
+    pub primary_url: Option<String>,
+    /// Seconds between a standby's pulls.
+    #[serde(default = "default_replication_interval")]
+    pub interval_secs: u64,
+}
+
+fn default_replication_interval() -> u64 {
+    1
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct SpendGuardConfig {
+    /// JSON file the coins signed so far are kept in, with the transaction
+    /// each was signed into. Required unless `[redis]` keeps them.
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct JournalConfig {
+    /// JSON file the journal is kept in, see [`crate::journal`].
+    pub path: PathBuf,
+    /// Seconds a decided request stays in the journal.
+    #[serde(default = "default_journal_retention_secs")]
+    pub retention_secs: u64,
+}
+
+fn default_journal_retention_secs() -> u64 {
+    86400
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct BackupConfig {
+    /// Hex secp256k1 public key backups are encrypted to, see
+    /// [`crate::backup`]. Its secret key should be kept offline.
+    pub recovery_pubkey: String,
+    /// Whether backups carry the private descriptor and the software
+    /// signer's xprv.
+    #[serde(default)]
+    pub include_keys: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct StorageConfig {
+    #[serde(default)]
+    pub backend: StorageBackend,
+    /// Database file of the `sqlite` backend.
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    /// Where the wallet's revealed addresses and synced transactions are
+    /// kept, so a restart resumes from them. Rebuilt from the descriptor
+    /// and the chain backend on every start when absent.
+    #[serde(default)]
+    pub wallet_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Each store in the file at its configured path.
+    #[default]
+    Files,
+    /// Every store in one SQLite database, with the `sqlite` feature.
+    Sqlite,
+    /// Nothing survives a restart, for trials and tests.
+    Memory,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct RedisConfig {
+    /// Such as `redis://10.0.0.5:6379/0`, or `rediss://` for TLS.
+    pub url: String,
+    /// Prefix of every key, so wallets can share a server.
+    #[serde(default = "default_redis_prefix")]
+    pub key_prefix: String,
+    /// How long a call may take before it fails.
+    #[serde(default = "default_redis_timeout")]
+    pub timeout_ms: u64,
+}
+
+fn default_redis_prefix() -> String {
+    "issue-service".into()
+}
+
+fn default_redis_timeout() -> u64 {
+    500
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct LabelsConfig {
+    /// BIP-329 JSON Lines file the labels are kept in.
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct SchedulesConfig {
+    /// JSON file the schedules and their run history are kept in.
+    pub path: PathBuf,
+    /// Runs kept per schedule; older ones are dropped.
+    #[serde(default = "default_schedule_history")]
+    pub history: usize,
+}
+
+fn default_schedule_history() -> usize {
+    100
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct ConsolidationConfig {
+    /// Coins worth less than this are consolidated.
+    pub max_value_sat: u64,
+    /// Fewer coins below `max_value_sat` are left alone.
+    #[serde(default = "default_consolidation_min_count")]
+    pub min_count: usize,
+    /// Coins spent by one consolidation transaction; the smallest go first.
+    #[serde(default = "default_consolidation_max_inputs")]
+    pub max_inputs: usize,
+    /// Consolidation waits until Esplora's estimate for `target_blocks` is
+    /// at most this, then pays the estimate.
+    pub max_fee_rate_sat_vb: f64,
+    #[serde(default = "default_consolidation_target_blocks")]
+    pub target_blocks: u16,
+    /// How often to check and consolidate on its own, broadcasting the
+    /// result. Only `/admin/consolidate` consolidates when absent.
+    #[serde(default)]
+    pub interval_secs: Option<u64>,
+}
+
+fn default_consolidation_min_count() -> usize {
+    10
+}
+
+fn default_consolidation_max_inputs() -> usize {
+    200
+}
+
+fn default_consolidation_target_blocks() -> u16 {
+    144
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct NostrConfig {
+    /// Key the service signs and decrypts Nostr events with, as `nsec` or
+    /// hex. Fixed at startup.
+    pub secret_key: String,
+    /// Relay URLs, e.g. `wss://relay.damus.io`. Fixed at startup.
+    pub relays: Vec<String>,
+    /// Keys allowed to request signatures, as `npub` or hex. Read on every
+    /// request.
+    #[serde(default)]
+    pub authorized_npubs: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct SilentPaymentsConfig {
+    /// First block scanned for payments, normally the height at which the
+    /// address was first handed out.
+    pub birthday_height: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct ChainConfig {
+    /// Base URL of the Esplora API, e.g. `https://blockstream.info/api`.
+    pub esplora_url: String,
+    #[serde(default = "default_sync_interval_secs")]
+    pub sync_interval_secs: u64,
+    /// Unused addresses scanned past the last used one on the initial scan.
+    #[serde(default = "default_stop_gap")]
+    pub stop_gap: usize,
+    /// Fetch the previous transactions signing requests lack from here
+    /// before signing.
+    #[serde(default)]
+    pub backfill_prevouts: bool,
+}
+
+fn default_sync_interval_secs() -> u64 {
+    60
+}
+
+fn default_stop_gap() -> usize {
+    20
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct SigningConfig {
+    /// Signing operations allowed to run at once. Defaults to the number of
+    /// CPUs. Fixed at startup.
+    pub max_concurrent: usize,
+    /// How long a request may wait for a free signing slot before it is
+    /// shed with `503 overloaded`.
+    pub queue_timeout_ms: u64,
+    /// Slots batch-priority requests may hold at once. Defaults to one less
+    /// than `max_concurrent`, leaving a slot for interactive requests. Fixed
+    /// at startup.
+    pub batch_max_concurrent: Option<usize>,
+    /// Refuse PSBTs spending coins this wallet does not own unless the
+    /// request declares them `collaborative`.
+    pub strict_foreign_inputs: bool,
+    /// Rewrite an RGB commitment host output to embed its commitment
+    /// before signing, instead of refusing to sign.
+    pub finalize_rgb_commitments: bool,
+    /// Derived child keys kept per xprv, so repeat signing for the same
+    /// addresses skips BIP-32 derivation. `0` disables the cache. Fixed at
+    /// startup.
+    pub key_cache_size: usize,
+    /// Child indices of each keychain whose keys are derived into the cache
+    /// at startup, capped at `key_cache_size`. Fixed at startup.
+    pub prederive_keys: usize,
+    /// Run every check and write the audit entry, but answer with the PSBT
+    /// unsigned. Fixed at startup.
+    pub dry_run: bool,
+    /// Seconds a signed PSBT is answered from the cache to an identical
+    /// resubmission. `0` disables the cache.
+    pub response_cache_ttl_secs: u64,
+    /// Signed PSBTs kept in the response cache.
+    pub response_cache_size: usize,
+}
+
+impl Default for SigningConfig {
+    fn default() -> Self {
+        SigningConfig {
+            max_concurrent: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            queue_timeout_ms: 1000,
+            batch_max_concurrent: None,
+            strict_foreign_inputs: false,
+            finalize_rgb_commitments: false,
+            key_cache_size: 1024,
+            prederive_keys: 0,
+            dry_run: false,
+            response_cache_ttl_secs: 0,
+            response_cache_size: 1000,
+        }
+    }
+}
+
+impl SigningConfig {
+    /// `batch_max_concurrent`, or its default.
+    pub fn batch_slots(&self) -> usize {
+        self.batch_max_concurrent
+            .unwrap_or(self.max_concurrent.saturating_sub(1))
+            .max(1)
+    }
+}
+
+/// The options every PSBT is signed with, unless a request tightens them.
+/// The defaults are the strict ones. Read on every request.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct SignOptionsConfig {
+    /// Sign segwit v0 inputs that carry only their `witness_utxo`, trusting
+    /// the amount it claims. Without the previous transaction a malicious
+    /// caller can lie about it to inflate the fee.
+    pub trust_witness_utxo: bool,
+    /// Sighash types an input may ask for. `all` also covers the taproot
+    /// default.
+    pub allowed_sighashes: Vec<Sighash>,
+    /// Finalize inputs once they are fully signed.
+    pub try_finalize: bool,
+    /// Sign taproot inputs with the internal key.
+    pub sign_with_tap_internal_key: bool,
+    /// Sign the script leaves of taproot inputs.
+    pub sign_tap_leaves: bool,
+}
+
+impl Default for SignOptionsConfig {
+    fn default() -> Self {
+        SignOptionsConfig {
+            trust_witness_utxo: false,
+            allowed_sighashes: vec![Sighash::All],
+            try_finalize: true,
+            sign_with_tap_internal_key: true,
+            sign_tap_leaves: true,
+        }
+    }
+}
+
+/// Chosen branches of a descriptor's policy per keychain, as for bdk's
+/// `TxBuilder::policy_path`: each policy node id, as `/admin/policies`
+/// lists them, maps to the indexes of the node's items to satisfy.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyPath {
+    /// For coins received on external addresses.
+    pub external: std::collections::BTreeMap<String, Vec<usize>>,
+    /// For change coins.
+    pub internal: std::collections::BTreeMap<String, Vec<usize>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Sighash {
+    All,
+    None,
+    Single,
+    AllAnyonecanpay,
+    NoneAnyonecanpay,
+    SingleAnyonecanpay,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct SighashPairingConfig {
+    /// `all_anyonecanpay`, `single` or `single_anyonecanpay`.
+    pub sighashes: Vec<Sighash>,
+}
+
+/// Limits on `/admin/create_payout`. Read on every request.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct PayoutConfig {
+    /// Payments one request may carry.
+    pub max_payments: usize,
+    /// Largest single payment.
+    pub max_amount_sat: Option<u64>,
+    /// Largest total of one request.
+    pub max_total_sat: Option<u64>,
+    /// Weight a payout transaction may reach once signed; payments beyond
+    /// it go into further transactions.
+    pub max_tx_weight: u64,
+}
+
+impl Default for PayoutConfig {
+    fn default() -> Self {
+        PayoutConfig {
+            max_payments: 5000,
+            max_amount_sat: None,
+            max_total_sat: None,
+            // The standardness limit.
+            max_tx_weight: 400_000,
+        }
+    }
+}
+
+/// Limits on the `OP_RETURN` outputs of `/admin/create_psbt`. Read on every
+/// request.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct OpReturnConfig {
+    /// Data one output may carry.
+    pub max_bytes: usize,
+    /// Outputs one transaction may have.
+    pub max_outputs: usize,
+}
+
+impl Default for OpReturnConfig {
+    fn default() -> Self {
+        // What nodes before Bitcoin Core 30 relay.
+        OpReturnConfig {
+            max_bytes: 80,
+            max_outputs: 1,
+        }
+    }
+}
+
+/// Validation of RGB transfers before signing. Read on every request.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct RgbConfig {
+    /// Endpoint that validates an attached consignment against the PSBT.
+    /// Consignments are refused when unset.
+    pub validator_url: Option<String>,
+    /// Refuse to sign a PSBT hosting an RGB commitment unless a
+    /// consignment is attached and validates.
+    pub require_consignment: bool,
+}
+
+/// Limits on collaborative transactions. Read on every request.
+/// Smallest output value per script type, in sat. The defaults are Bitcoin
+/// Core's dust limits at its default dust relay fee.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct DustConfig {
+    pub p2pkh_sat: u64,
+    pub p2sh_sat: u64,
+    pub p2wpkh_sat: u64,
+    pub p2wsh_sat: u64,
+    pub p2tr_sat: u64,
+    /// Other scripts, `OP_RETURN` aside, which carry no value.
+    pub other_sat: u64,
+    /// Only check outputs paying this wallet, leaving what others receive
+    /// to them.
+    pub own_outputs_only: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct PsbtFieldsConfig {
+    /// Fields of key types BIP-174 does not define.
+    pub unknown: FieldPolicy,
+    /// `PSBT_*_PROPRIETARY` fields, such as RGB's commitments.
+    pub proprietary: FieldPolicy,
+    /// Annexes in the witnesses of finalized taproot inputs.
+    pub annex: AnnexPolicy,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldPolicy {
+    /// Keep them in the signed PSBT.
+    #[default]
+    Preserve,
+    /// Remove them before signing.
+    Strip,
+    /// Refuse PSBTs carrying any.
+    Reject,
+}
+
+/// An annex is committed to by the signature next to it, so it cannot be
+/// stripped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnnexPolicy {
+    #[default]
+    Preserve,
+    Reject,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct TemplatesConfig {
+    #[serde(default)]
+    pub mode: TemplateMode,
+    /// Written as `[[templates.template]]`.
+    #[serde(default, rename = "template")]
+    pub templates: Vec<TemplateConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TemplateMode {
+    /// Refuse PSBTs matching no template.
+    #[default]
+    Enforce,
+    /// Sign them, logging a warning, to try templates out.
+    Log,
+}
+
+/// A transaction shape: what each output paying others may be, and how many
+/// outputs may pay the wallet back.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct TemplateConfig {
+    pub name: String,
+    /// Each output paying others must match one of these. None may when
+    /// empty.
+    #[serde(default)]
+    pub outputs: Vec<OutputTemplate>,
+    /// Fewest outputs paying others.
+    #[serde(default)]
+    pub min_payments: usize,
+    /// Most outputs paying others. Any number when unset.
+    #[serde(default)]
+    pub max_payments: Option<usize>,
+    #[serde(default)]
+    pub change: ChangeTemplate,
+    /// Most inputs. Any number when unset.
+    #[serde(default)]
+    pub max_inputs: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct OutputTemplate {
+    /// `p2pkh`, `p2sh`, `p2wpkh`, `p2wsh`, `p2tr` or `op_return`. Any
+    /// script when unset.
+    pub script_type: Option<crate::templates::ScriptType>,
+    /// The one address the output may pay.
+    pub address: Option<String>,
+    pub min_sat: Option<u64>,
+    pub max_sat: Option<u64>,
+}
+
+/// Outputs paying the wallet's own addresses.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct ChangeTemplate {
+    pub min_outputs: usize,
+    pub max_outputs: usize,
+}
+
+impl Default for ChangeTemplate {
+    fn default() -> Self {
+        ChangeTemplate {
+            min_outputs: 0,
+            max_outputs: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct FeeCheckConfig {
+    /// PSBTs paying less than this are not signed; Core's default minimum
+    /// relay fee.
+    pub min_fee_rate_sat_vb: f64,
+    /// PSBTs paying more than this are not signed.
+    pub max_fee_rate_sat_vb: f64,
+    /// PSBTs paying more than this in total are not signed, whatever their
+    /// rate.
+    pub max_fee_sat: Option<u64>,
+    /// Fetch the transactions of spent coins the PSBT gives no full
+    /// transaction for from `[chain]`, and add them to it. Otherwise such
+    /// coins are only accepted with `sign_options.trust_witness_utxo`, or
+    /// for taproot ones, when the wallet signs taproot inputs only.
+    pub fetch_prevouts: bool,
+}
+
+impl Default for FeeCheckConfig {
+    fn default() -> Self {
+        FeeCheckConfig {
+            min_fee_rate_sat_vb: 1.0,
+            max_fee_rate_sat_vb: 1000.0,
+            max_fee_sat: None,
+            fetch_prevouts: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct LocktimeConfig {
+    /// Only sign transactions that signal replaceability (BIP-125).
+    pub require_rbf: bool,
+    /// Only sign transactions locked to a block height no more than
+    /// `anti_fee_sniping_depth` blocks below the chain tip, with an input
+    /// that enforces it. Needs `chain`.
+    pub anti_fee_sniping: bool,
+    pub anti_fee_sniping_depth: u32,
+    /// Only sign transactions that can be mined within this many blocks of
+    /// the chain tip, counting ten minutes a block for time locks. Needs
+    /// `chain`.
+    pub max_future_blocks: Option<u32>,
+}
+
+impl Default for LocktimeConfig {
+    fn default() -> Self {
+        LocktimeConfig {
+            require_rbf: false,
+            anti_fee_sniping: false,
+            anti_fee_sniping_depth: 100,
+            max_future_blocks: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct ApprovalConfig {
+    /// Endpoint the transaction is posted to, see [`crate::approval`].
+    pub url: String,
+    /// Sent as a bearer token.
+    #[serde(default)]
+    pub auth_token: Option<String>,
+    /// The request is refused when the endpoint has not answered by then.
+    #[serde(default = "default_approval_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_approval_timeout_ms() -> u64 {
+    5000
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct QuorumConfig {
+    /// Distinct credentials that must submit a transaction, see
+    /// [`crate::quorum`].
+    pub threshold: usize,
+    /// Seconds an approval counts for.
+    #[serde(default = "default_quorum_window_secs")]
+    pub window_secs: u64,
+    /// Credential names to the bearer tokens they present.
+    pub credentials: std::collections::BTreeMap<String, String>,
+}
+
+fn default_quorum_window_secs() -> u64 {
+    3600
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct AuthConfig {
+    /// Credential names to their token and scopes, see [`crate::auth`].
+    pub credentials: std::collections::BTreeMap<String, Credential>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct Credential {
+    /// Presented as a bearer token.
+    pub token: String,
+    pub scopes: std::collections::BTreeSet<crate::auth::Scope>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct SigningWindowsConfig {
+    /// Offset from UTC `windows` are in, such as `-05:00`. Daylight saving
+    /// time is not followed.
+    #[serde(default = "default_utc_offset")]
+    pub utc_offset: String,
+    /// Times signing is open at, see [`crate::signing_windows`]. Open at
+    /// any time outside blackouts when empty.
+    #[serde(default)]
+    pub windows: Vec<SigningWindow>,
+    #[serde(default)]
+    pub blackouts: Vec<Blackout>,
+    /// Quorum approvals a transaction needs to be signed while signing is
+    /// closed. Refused outright when unset.
+    #[serde(default)]
+    pub override_threshold: Option<usize>,
+}
+
+fn default_utc_offset() -> String {
+    "+00:00".into()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct SigningWindow {
+    /// Days of the week, such as `mon`.
+    pub days: Vec<chrono::Weekday>,
+    /// Local time the window opens at.
+    pub start: chrono::NaiveTime,
+    /// Local time it closes at, later than `start` on the same day.
+    pub end: chrono::NaiveTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct Blackout {
+    pub from: chrono::DateTime<chrono::Utc>,
+    /// Exclusive.
+    pub to: chrono::DateTime<chrono::Utc>,
+    /// Given to callers refused during the blackout.
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl Default for DustConfig {
+    fn default() -> Self {
+        DustConfig {
+            p2pkh_sat: 546,
+            p2sh_sat: 540,
+            p2wpkh_sat: 294,
+            p2wsh_sat: 330,
+            p2tr_sat: 330,
+            other_sat: 546,
+            own_outputs_only: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct CoinjoinConfig {
+    /// Most of the mining fee this wallet may end up paying.
+    pub max_fee_sat: u64,
+    /// How far outputs to this wallet may fall short of its inputs beyond
+    /// the mining fee, e.g. to cover a coordinator fee.
+    #[serde(default)]
+    pub tolerance_sat: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct ChannelFundingConfig {
+    /// Only accept registrations whose address can be checked against the
+    /// two funding pubkeys.
+    #[serde(default = "default_require_pubkeys")]
+    pub require_pubkeys: bool,
+}
+
+fn default_require_pubkeys() -> bool {
+    true
+}
+
+/// Per-route request timeouts. Read on every request, so a reload applies
+/// them immediately.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    pub sign_secs: u64,
+    /// Every route without a more specific timeout.
+    pub default_secs: u64,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        TimeoutConfig {
+            sign_secs: 5,
+            default_secs: 30,
+        }
+    }
+}
+
+/// Connection settings of the HTTP listeners. Read for every new
+/// connection, so a reload applies them to connections opened after it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Also accept HTTP/2 over cleartext (prior knowledge) on each listener.
+    pub http2: bool,
+    /// Keep HTTP/1.1 connections open for further requests.
+    pub keep_alive: bool,
+    /// How long a client may take to send a request's headers.
+    pub header_read_timeout_secs: u64,
+    /// Streams a client may have open at once on one HTTP/2 connection.
+    pub http2_max_concurrent_streams: u32,
+    /// Interval of HTTP/2 keep-alive pings; `0` sends none.
+    pub http2_keep_alive_interval_secs: u64,
+    /// How long a keep-alive ping may go unanswered before the connection
+    /// is closed.
+    pub http2_keep_alive_timeout_secs: u64,
+    /// Largest body accepted by `POST /sign_psbt/binary`.
+    pub max_binary_psbt_bytes: usize,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            http2: false,
+            keep_alive: true,
+            header_read_timeout_secs: 30,
+            http2_max_concurrent_streams: 200,
+            http2_keep_alive_interval_secs: 0,
+            http2_keep_alive_timeout_secs: 20,
+            max_binary_psbt_bytes: 32 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// Origins allowed to call the public API from a browser, such as
+    /// `https://wallet.example.com`, or `"*"` for any.
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    /// Request headers browsers may send besides the CORS-safelisted ones.
+    pub allowed_headers: Vec<String>,
+    /// How long browsers may cache a preflight response; `0` leaves it to
+    /// the browser.
+    pub max_age_secs: u64,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec!["GET".into(), "POST".into()],
+            allowed_headers: vec!["content-type".into(), "x-request-id".into()],
+            max_age_secs: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct LogConfig {
+    /// Path of the active log file; rotated files get `.1`, `.2`, ... suffixes.
+    pub file: PathBuf,
+    #[serde(default)]
+    pub rotation: Rotation,
+    /// Also rotate once the active file reaches this size.
+    #[serde(default)]
+    pub max_size_mb: Option<u64>,
+    /// Number of rotated files kept; older ones are deleted.
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rotation {
+    Never,
+    Hourly,
+    #[default]
+    Daily,
+}
+
+fn default_max_files() -> usize {
+    7
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct ListenConfig {
+    /// Address to bind, e.g. `127.0.0.1:3001` or `[::]:3001`.
+    pub addr: SocketAddr,
+    /// Serves HTTPS instead of plain HTTP. Only honored when built with the
+    /// `tls` feature; startup fails otherwise.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first.
+    pub cert: PathBuf,
+    /// PEM private key: PKCS#8, PKCS#1 or SEC1.
+    pub key: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct UnixSocketConfig {
+    /// Socket path. A stale socket left at this path is replaced on startup.
+    pub path: PathBuf,
+    /// Permission bits applied to the socket file, e.g. `0o660`.
+    #[serde(default = "default_socket_mode")]
+    pub mode: u32,
+}
+
+fn default_socket_mode() -> u32 {
+    0o660
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct AuditConfig {
+    /// Append-only JSON-lines file the entries are written to.
+    pub path: PathBuf,
+    /// Hex secp256k1 secret key used to sign audit exports.
+    #[serde(default)]
+    pub signing_key: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct AttestationConfig {
+    /// Hex secp256k1 secret key, see [`crate::attestation`]. Should be a
+    /// key of its own rather than `audit.signing_key`.
+    pub signing_key: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to read {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("failed to parse {path}: {source}")]
+    Parse {
+        path: String,
+        source: toml::de::Error,
+    },
+    #[error("invalid override {var}: {message}")]
+    Override { var: String, message: String },
+    #[error("invalid config {path}: {message}")]
+    Invalid { path: String, message: String },
+}
+
+impl Config {
+    /// Loads the config file at `path` with environment overrides applied on
+    /// top of it.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let config = Self::parse(path, std::env::vars())?;
+        if !config.has_listener() && config.file_drop.is_none() {
+            return Err(ConfigError::Invalid {
+                path: path.display().to_string(),
+                message: "no listener configured, set `port`, `[[listen]]` or \
+                          `[unix_socket]`, or `[file_drop]` to sign offline"
+                    .into(),
+            });
+        }
+        Ok(config)
+    }
+
+    /// Loads the config file of a wallet in the main config's `networks`.
+    /// Environment overrides are meant for the main config and are not
+    /// applied, and what only the main config can set must be left out.
+    pub fn load_hosted(path: &Path) -> Result<Self, ConfigError> {
+        let mut config = Self::parse(path, std::iter::empty())?;
+        let main_only = [
+            ("port", config.port.is_some()),
+            ("listen", !config.listen.is_empty()),
+            ("unix_socket", config.unix_socket.is_some()),
+            ("admin_listen", config.admin_listen.is_some()),
+            ("grpc_listen", config.grpc_listen.is_some()),
+            ("log", config.log.is_some()),
+            ("cors", !config.cors.allowed_origins.is_empty()),
+            ("nostr", config.nostr.is_some()),
+            ("file_drop", config.file_drop.is_some()),
+            ("networks", !config.networks.is_empty()),
+        ];
+        if let Some((field, _)) = main_only.iter().find(|(_, set)| *set) {
+            return Err(ConfigError::Invalid {
+                path: path.display().to_string(),
+                message: format!("`{field}` can only be set in the main config"),
+            });
+        }
+        config.hosted = true;
+        Ok(config)
+    }
+
+    fn parse(
+        path: &Path,
+        vars: impl Iterator<Item = (String, String)>,
+    ) -> Result<Self, ConfigError> {
+        let display = path.display().to_string();
+        let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: display.clone(),
+            source,
+        })?;
+        Self::parse_str(display, &content, vars)
+    }
+
+    /// A config file's `content`, as [`Self::load_hosted`] reads it, without
+    /// a file.
+    #[cfg(test)]
+    pub fn from_toml(content: &str) -> Self {
+        Self::parse_str("test.toml".into(), content, std::iter::empty()).expect("test config")
+    }
+
+    fn parse_str(
+        display: String,
+        content: &str,
+        vars: impl Iterator<Item = (String, String)>,
+    ) -> Result<Self, ConfigError> {
+        let mut table: toml::Table =
+            toml::from_str(content).map_err(|source| ConfigError::Parse {
+                path: display.clone(),
+                source,
+            })?;
+        apply_env_overrides(&mut table, vars)?;
+        let mut config: Config =
+            toml::Value::Table(table)
+                .try_into()
+                .map_err(|source| ConfigError::Parse {
+                    path: display.clone(),
+                    source,
+                })?;
+        if config.descriptor.is_some() {
+            if !config.xprv.trim().is_empty() {
+                return Err(ConfigError::Invalid {
+                    path: display,
+                    message: "set either `xprv` or `descriptor`, not both".into(),
+                });
+            }
+            let (xprv, change_xprv) =
+                config
+                    .signing_descriptors()
+                    .map_err(|message| ConfigError::Invalid {
+                        path: display.clone(),
+                        message,
+                    })?;
+            config.xprv = xprv;
+            config.change_xprv = change_xprv;
+        } else if config.change_descriptor.is_some() || config.key_file.is_some() {
+            return Err(ConfigError::Invalid {
+                path: display,
+                message: "`change_descriptor` and `key_file` need `descriptor`".into(),
+            });
+        }
+        if config.xprv.trim().is_empty() {
+            if !config.signing.dry_run {
+                return Err(ConfigError::Invalid {
+                    path: display,
+                    message: "`xprv` is required unless `signing.dry_run` is set".into(),
+                });
+            }
+            config.xprv = throwaway_descriptor(config.network);
+            config.throwaway_key = true;
+        }
+        Ok(config)
+    }
+
+    /// `descriptor` and `change_descriptor` with their xpub swapped for the
+    /// xprv in `key_file`, after checking the xprv is theirs and carries
+    /// `fingerprint`.
+    fn signing_descriptors(&self) -> Result<(String, Option<String>), String> {
+        use std::str::FromStr;
+
+        use bdk_wallet::{
+            descriptor::{Descriptor, DescriptorPublicKey},
+            keys::DescriptorSecretKey,
+            miniscript::ForEachKey,
+        };
+        use bitcoin::bip32::Xpub;
+
+        // The signer holds the keys, so the descriptors stay public.
+        if self.key_file.is_none() && self.signer.is_some() {
+            let descriptor = self.descriptor.clone().unwrap_or_default();
+            return Ok((descriptor, self.change_descriptor.clone()));
+        }
+        let key_file = self
+            .key_file
+            .as_ref()
+            .ok_or("`descriptor` needs `key_file`")?;
+        let secret = std::fs::read_to_string(key_file)
+            .map_err(|e| format!("key_file {}: {e}", key_file.display()))?;
+        // Never echoes the file, which may be a key with a typo in it.
+        let xprv = match DescriptorSecretKey::from_str(secret.trim()) {
+            Ok(DescriptorSecretKey::XPrv(xkey)) if xkey.derivation_path.is_empty() => xkey.xkey,
+            _ => {
+                return Err(format!(
+                    "key_file {}: expected an xprv, optionally with key origin",
+                    key_file.display()
+                ))
+            }
+        };
+        if bitcoin::NetworkKind::from(self.network) != xprv.network {
+            return Err(format!(
+                "key_file {}: key is not for network `{}`",
+                key_file.display(),
+                self.network
+            ));
+        }
+        let secp = bitcoin::key::Secp256k1::new();
+        let xpub = Xpub::from_priv(&secp, &xprv);
+        let with_xprv = |field: &str, descriptor: &str| -> Result<String, String> {
+            let body = descriptor.trim().split('#').next().unwrap_or_default();
+            let parsed = Descriptor::<DescriptorPublicKey>::from_str(body)
+                .map_err(|e| format!("{field}: {e}"))?;
+            let mut origin = None;
+            let found = parsed.for_any_key(|key| match key {
+                DescriptorPublicKey::XPub(key) if key.xkey == xpub => {
+                    origin = Some(key.origin.clone());
+                    true
+                }
+                DescriptorPublicKey::MultiXPub(key) if key.xkey == xpub => {
+                    origin = Some(key.origin.clone());
+                    true
+                }
+                _ => false,
+            });
+            if !found {
+                return Err(format!(
+                    "{field}: the xprv in key_file is not one of its keys"
+                ));
+            }
+            if let Some(expected) = self.fingerprint {
+                let actual = match origin.flatten() {
+                    Some((fingerprint, _)) => fingerprint,
+                    None => xpub.fingerprint(),
+                };
+                if actual != expected {
+                    return Err(format!(
+                        "{field}: key origin has fingerprint {actual}, expected {expected}"
+                    ));
+                }
+            }
+            Ok(body.replace(&xpub.to_string(), &xprv.to_string()))
+        };
+        let descriptor = self.descriptor.as_deref().unwrap_or_default();
+        Ok((
+            with_xprv("descriptor", descriptor)?,
+            self.change_descriptor
+                .as_deref()
+                .map(|change| with_xprv("change_descriptor", change))
+                .transpose()?,
+        ))
+    }
+
+    /// Whether any public listener is configured.
+    pub fn has_listener(&self) -> bool {
+        self.port.is_some() || !self.listen.is_empty() || self.unix_socket.is_some()
+    }
+
+    /// Builds the config that results from reloading `next` on top of the
+    /// running config `self`.
+    ///
+    /// Key material and the listener cannot change at runtime, so those fields
+    /// are carried over from the running config. The names of any such fields
+    /// that differ in `next` are returned so callers can report that a restart
+    /// is required for them to take effect.
+    pub fn reloaded(&self, mut next: Config) -> (Config, Vec<&'static str>) {
+        let mut restart_required = Vec::new();
+        if next.port != self.port {
+            restart_required.push("port");
+        }
+        if next.listen != self.listen {
+            restart_required.push("listen");
+        }
+        if next.unix_socket != self.unix_socket {
+            restart_required.push("unix_socket");
+        }
+        if next.admin_listen != self.admin_listen {
+            restart_required.push("admin_listen");
+        }
+        if next.grpc_listen != self.grpc_listen {
+            restart_required.push("grpc_listen");
+        }
+        if next.cors != self.cors {
+            restart_required.push("cors");
+        }
+        if next.audit != self.audit {
+            restart_required.push("audit");
+        }
+        if next.attestation != self.attestation {
+            restart_required.push("attestation");
+        }
+        if next.log != self.log {
+            restart_required.push("log");
+        }
+        if next.signing.max_concurrent != self.signing.max_concurrent {
+            restart_required.push("signing.max_concurrent");
+        }
+        if next.signing.batch_max_concurrent != self.signing.batch_max_concurrent {
+            restart_required.push("signing.batch_max_concurrent");
+        }
+        if next.signing.key_cache_size != self.signing.key_cache_size {
+            restart_required.push("signing.key_cache_size");
+        }
+        if next.signing.prederive_keys != self.signing.prederive_keys {
+            restart_required.push("signing.prederive_keys");
+        }
+        if next.signer != self.signer {
+            restart_required.push("signer");
+        }
+        if next.chain != self.chain {
+            restart_required.push("chain");
+        }
+        if next.silent_payments != self.silent_payments {
+            restart_required.push("silent_payments");
+        }
+        if next.bsms != self.bsms {
+            restart_required.push("bsms");
+        }
+        if next.labels != self.labels {
+            restart_required.push("labels");
+        }
+        if next.spend_guard != self.spend_guard {
+            restart_required.push("spend_guard");
+        }
+        if next.journal != self.journal {
+            restart_required.push("journal");
+        }
+        if next.replication != self.replication {
+            restart_required.push("replication");
+        }
+        if next.storage != self.storage {
+            restart_required.push("storage");
+        }
+        if next.redis != self.redis {
+            restart_required.push("redis");
+        }
+        if next.consolidation != self.consolidation {
+            restart_required.push("consolidation");
+        }
+        if next.schedules != self.schedules {
+            restart_required.push("schedules");
+        }
+        if next.liquid != self.liquid {
+            restart_required.push("liquid");
+        }
+        if next.file_drop != self.file_drop {
+            restart_required.push("file_drop");
+        }
+        if next.networks != self.networks {
+            restart_required.push("networks");
+        }
+        match (&self.nostr, &mut next.nostr) {
+            (Some(running), Some(nostr)) => {
+                if nostr.secret_key != running.secret_key {
+                    restart_required.push("nostr.secret_key");
+                }
+                if nostr.relays != running.relays {
+                    restart_required.push("nostr.relays");
+                }
+                nostr.secret_key.clone_from(&running.secret_key);
+                nostr.relays.clone_from(&running.relays);
+            }
+            (None, None) => {}
+            (running, nostr) => {
+                restart_required.push("nostr");
+                nostr.clone_from(running);
+            }
+        }
+        if next.network != self.network {
+            restart_required.push("network");
+        }
+        // Every load generates another throwaway key.
+        if next.xprv != self.xprv && !(next.throwaway_key && self.throwaway_key) {
+            restart_required.push("xprv");
+        }
+        if next.change_xprv != self.change_xprv {
+            restart_required.push("change_descriptor");
+        }
+        if next.signing.dry_run != self.signing.dry_run {
+            restart_required.push("signing.dry_run");
+        }
+        next.port = self.port;
+        next.listen.clone_from(&self.listen);
+        next.unix_socket.clone_from(&self.unix_socket);
+        next.admin_listen = self.admin_listen;
+        next.grpc_listen = self.grpc_listen;
+        next.cors.clone_from(&self.cors);
+        next.audit.clone_from(&self.audit);
+        next.attestation.clone_from(&self.attestation);
+        next.log.clone_from(&self.log);
+        next.signing.max_concurrent = self.signing.max_concurrent;
+        next.signing.batch_max_concurrent = self.signing.batch_max_concurrent;
+        next.signing.key_cache_size = self.signing.key_cache_size;
+        next.signing.prederive_keys = self.signing.prederive_keys;
+        next.signer.clone_from(&self.signer);
+        next.chain.clone_from(&self.chain);
+        next.silent_payments.clone_from(&self.silent_payments);
+        next.bsms.clone_from(&self.bsms);
+        next.labels.clone_from(&self.labels);
+        next.spend_guard.clone_from(&self.spend_guard);
+        next.journal.clone_from(&self.journal);
+        next.replication.clone_from(&self.replication);
+        next.storage.clone_from(&self.storage);
+        next.redis.clone_from(&self.redis);
+        next.consolidation.clone_from(&self.consolidation);
+        next.schedules.clone_from(&self.schedules);
+        next.liquid.clone_from(&self.liquid);
+        next.file_drop.clone_from(&self.file_drop);
+        next.networks.clone_from(&self.networks);
+        next.network = self.network;
+        next.xprv.clone_from(&self.xprv);
+        next.descriptor.clone_from(&self.descriptor);
+        next.change_descriptor.clone_from(&self.change_descriptor);
+        next.key_file.clone_from(&self.key_file);
+        next.fingerprint = self.fingerprint;
+        next.change_xprv.clone_from(&self.change_xprv);
+        next.throwaway_key = self.throwaway_key;
+        next.signing.dry_run = self.signing.dry_run;
+        (next, restart_required)
+    }
+}
+
+/// A BIP-84 style descriptor of a fresh random key, for dry runs without
+/// key material. Nothing it signs is ever released.
+fn throwaway_descriptor(network: bitcoin::Network) -> String {
+    let seed: [u8; 32] = rand::random();
+    let xprv = bitcoin::bip32::Xpriv::new_master(network, &seed).expect("32-byte seed");
+    let coin = match network {
+        bitcoin::Network::Bitcoin => 0,
+        _ => 1,
+    };
+    format!("wpkh({xprv}/84'/{coin}'/0'/0/*)")
+}
+
+/// Layers `ISSUE_SERVICE__*` variables over the parsed config file.
+///
+/// Each value is read as a TOML literal when it parses as one (`3002`,
+/// `true`, `["a", "b"]`) and as a plain string otherwise, so quoting is only
+/// needed to force a string that looks like another type.
+fn apply_env_overrides(
+    table: &mut toml::Table,
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<(), ConfigError> {
+    for (var, raw) in vars {
+        let Some(key) = var.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let path: Vec<String> = key.split("__").map(str::to_lowercase).collect();
+        if path.iter().any(String::is_empty) {
+            return Err(ConfigError::Override {
+                var,
+                message: "empty key segment".into(),
+            });
+        }
+
+        let value = toml::from_str::<toml::Table>(&format!("v = {raw}"))
+            .ok()
+            .and_then(|mut t| t.remove("v"))
+            .unwrap_or(toml::Value::String(raw));
+
+        let (last, parents) = path.split_last().expect("non-empty path");
+        let mut target = &mut *table;
+        for segment in parents {
+            target = target
+                .entry(segment.clone())
+                .or_insert_with(|| toml::Value::Table(Default::default()))
+                .as_table_mut()
+                .ok_or_else(|| ConfigError::Override {
+                    var: var.clone(),
+                    message: format!("`{segment}` is not a table"),
+                })?;
+        }
+        target.insert(last.clone(), value);
+    }
+    Ok(())
+}
@@ -0,0 +1,213 @@
+//! Merging the wallet's small coins into one while fees are low, so a
+//! deposit wallet does not end up paying to spend thousands of them when
+//! fees are high.
+//!
+//! Coins worth less than `max_value_sat` are candidates, the smallest first,
+//! up to `max_inputs` of them. Nothing happens while there are fewer than
+//! `min_count` candidates, or while Esplora's fee estimate for
+//! `target_blocks` is above `max_fee_rate_sat_vb`. Coins labeled as not
+//! spendable are left alone. The merged coin goes to a fresh receive
+//! address, and the transaction is signed and recorded like a sweep.
+//!
+//! With `interval_secs`, the service checks on its own and broadcasts what
+//! it builds; `POST /admin/consolidate` runs the same check on request.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{extract::State, Json};
+use bdk_wallet::KeychainKind;
+use bitcoin::OutPoint;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::Metadata,
+    config::{ChainConfig, ConsolidationConfig},
+    context::RequestContext,
+    error::{ApiJson, Error},
+    priority::Priority,
+    sweep::{SpendOptions, SweepResponse},
+    AppState,
+};
+
+/// Caller recorded in the audit entries of scheduled consolidations.
+const CALLER: &str = "consolidation";
+
+#[derive(Default, Deserialize)]
+pub struct ConsolidateRequest {
+    /// In sat/vB. Skips the fee estimate and `max_fee_rate_sat_vb` when
+    /// set.
+    #[serde(default)]
+    fee_rate_sat_vb: Option<f64>,
+    /// Broadcasts the signed transaction through `[chain]`.
+    #[serde(default)]
+    broadcast: bool,
+    /// Recorded in the audit entry, as for `/sign_psbt`.
+    #[serde(default)]
+    metadata: Metadata,
+}
+
+#[derive(Serialize)]
+pub struct ConsolidationResponse {
+    /// Coins below `max_value_sat`.
+    pub candidates: usize,
+    /// The rate paid, or the estimate that was too high to.
+    pub fee_rate_sat_vb: f64,
+    /// Why nothing was built.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+    #[serde(flatten)]
+    pub transaction: Option<SweepResponse>,
+}
+
+pub async fn consolidate(
+    State(state): State<Arc<AppState>>,
+    ctx: RequestContext,
+    ApiJson(request): ApiJson<ConsolidateRequest>,
+) -> Result<Json<ConsolidationResponse>, Error> {
+    if request.broadcast {
+        crate::auth::authorize(
+            &state,
+            ctx.bearer.as_ref(),
+            &[crate::auth::Scope::Broadcast],
+        )?;
+    }
+    let config = state.config();
+    let consolidation = config
+        .consolidation
+        .as_ref()
+        .ok_or_else(|| Error::NotFound("consolidation is not configured".into()))?;
+    crate::chain::require_synced(&state)?;
+    let chain = config.chain.as_ref().expect("checked by require_synced");
+    run(&state, &ctx, chain, consolidation, request)
+        .await
+        .map(Json)
+}
+
+/// Consolidates every `interval_secs` once the wallet is synced.
+pub async fn consolidate_forever(
+    state: Arc<AppState>,
+    chain: ChainConfig,
+    consolidation: ConsolidationConfig,
+    interval_secs: u64,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+        if crate::chain::require_synced(&state).is_err() {
+            continue;
+        }
+        let ctx = RequestContext {
+            request_id: None,
+            caller: Some(CALLER.to_owned()),
+            metadata: Default::default(),
+            bearer: None,
+            internal: true,
+        };
+        let request = ConsolidateRequest {
+            broadcast: true,
+            ..Default::default()
+        };
+        match run(&state, &ctx, &chain, &consolidation, request).await {
+            Ok(ConsolidationResponse {
+                transaction: Some(tx),
+                ..
+            }) => tracing::info!(txid = %tx.txid, inputs = tx.inputs.len(), "consolidated"),
+            Ok(response) => tracing::debug!(
+                candidates = response.candidates,
+                "not consolidating: {}",
+                response.skipped.unwrap_or_default()
+            ),
+            Err(e) => tracing::warn!("consolidation failed: {e}"),
+        }
+    }
+}
+
+async fn run(
+    state: &Arc<AppState>,
+    ctx: &RequestContext,
+    chain: &ChainConfig,
+    consolidation: &ConsolidationConfig,
+    request: ConsolidateRequest,
+) -> Result<ConsolidationResponse, Error> {
+    let candidates = candidates(state, consolidation);
+    let count = candidates.len();
+    let (sat_vb, estimated) = match request.fee_rate_sat_vb {
+        Some(sat_vb) => (sat_vb, false),
+        None => {
+            let estimate = crate::chain::fee_estimate(chain, consolidation.target_blocks)
+                .await
+                .map_err(|e| Error::Unavailable(format!("fee estimate: {e}")))?;
+            (estimate.max(1.0), true)
+        }
+    };
+    let fee_rate = crate::sweep::fee_rate(sat_vb)?;
+    let skipped = if count < consolidation.min_count.max(2) {
+        Some(format!(
+            "{count} coins below {} sat, fewer than min_count",
+            consolidation.max_value_sat
+        ))
+    } else if estimated && sat_vb > consolidation.max_fee_rate_sat_vb {
+        Some(format!(
+            "fee estimate {sat_vb} sat/vB is above max_fee_rate_sat_vb"
+        ))
+    } else {
+        None
+    };
+    if skipped.is_some() {
+        return Ok(ConsolidationResponse {
+            candidates: count,
+            fee_rate_sat_vb: sat_vb,
+            skipped,
+            transaction: None,
+        });
+    }
+
+    let inputs = candidates
+        .into_iter()
+        .take(consolidation.max_inputs.max(2))
+        .collect();
+    let destination = state
+        .wallet
+        .reveal_next_address(KeychainKind::External)
+        .await?
+        .address
+        .script_pubkey();
+    let transaction = crate::sweep::spend(
+        state,
+        ctx,
+        inputs,
+        destination,
+        fee_rate,
+        SpendOptions {
+            metadata: request.metadata,
+            priority: Priority::Batch,
+            broadcast: request.broadcast,
+            policy_path: state.config().policy_path.clone(),
+        },
+    )
+    .await?;
+    Ok(ConsolidationResponse {
+        candidates: count,
+        fee_rate_sat_vb: sat_vb,
+        skipped: None,
+        transaction: Some(transaction),
+    })
+}
+
+/// Spendable coins worth less than `max_value_sat`, smallest first.
+fn candidates(state: &AppState, consolidation: &ConsolidationConfig) -> Vec<OutPoint> {
+    let wallet = state.wallet();
+    let mut coins: Vec<_> = wallet
+        .list_unspent()
+        .filter(|utxo| utxo.txout.value.to_sat() < consolidation.max_value_sat)
+        .filter(|utxo| {
+            state
+                .labels
+                .as_ref()
+                .map_or(true, |labels| labels.spendable(utxo.outpoint))
+        })
+        .map(|utxo| (utxo.txout.value, utxo.outpoint))
+        .collect();
+    coins.sort();
+    coins.into_iter().map(|(_, outpoint)| outpoint).collect()
+}
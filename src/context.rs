@@ -0,0 +1,91 @@
+use std::{convert::Infallible, fmt, net::SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, Extensions},
+};
+use tower_http::request_id::RequestId;
+
+/// Who made a request and how to correlate it, for handlers that record
+/// what they did (audit entries, events).
+#[derive(Debug, Clone, Default)]
+pub struct RequestContext {
+    pub request_id: Option<String>,
+    pub caller: Option<String>,
+    /// Caller metadata of a signing request, see [`crate::audit::Metadata`].
+    /// Set from the request body, so extraction leaves it empty.
+    pub metadata: crate::audit::Metadata,
+    /// From the `Authorization` header, see [`crate::quorum`].
+    pub bearer: Option<BearerToken>,
+    /// Started by the service itself from its own config, such as a
+    /// consolidation paying the wallet's own address, rather than by a
+    /// client. Schedules are added through the API and are not.
+    pub internal: bool,
+}
+
+/// A bearer token a request presented, kept out of `Debug` output.
+#[derive(Clone)]
+pub struct BearerToken(pub String);
+
+impl BearerToken {
+    /// The token of an `Authorization` header value.
+    pub fn parse(value: &str) -> Option<BearerToken> {
+        value
+            .strip_prefix("Bearer ")
+            .map(|token| BearerToken(token.to_owned()))
+    }
+}
+
+impl fmt::Debug for BearerToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BearerToken(..)")
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for RequestContext {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let request_id = parts
+            .extensions
+            .get::<RequestId>()
+            .and_then(|id| id.header_value().to_str().ok())
+            .map(str::to_owned);
+        let caller = match parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+            Some(ConnectInfo(addr)) => Some(addr.ip().to_string()),
+            None => unix_peer(&parts.extensions),
+        };
+        let bearer = parts
+            .headers
+            .get(axum::http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(BearerToken::parse);
+        Ok(RequestContext {
+            request_id,
+            caller,
+            bearer,
+            ..Default::default()
+        })
+    }
+}
+
+/// Remote end of the connection as logged: `ip:port` over TCP, the peer's
+/// uid over a Unix socket.
+pub fn peer(extensions: &Extensions) -> Option<String> {
+    match extensions.get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(addr)) => Some(addr.to_string()),
+        None => unix_peer(extensions),
+    }
+}
+
+#[cfg(unix)]
+fn unix_peer(extensions: &Extensions) -> Option<String> {
+    extensions
+        .get::<ConnectInfo<crate::unix_socket::UnixPeer>>()
+        .map(|ConnectInfo(peer)| peer.to_string())
+}
+
+#[cfg(not(unix))]
+fn unix_peer(_extensions: &Extensions) -> Option<String> {
+    None
+}
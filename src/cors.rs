@@ -0,0 +1,66 @@
+//! Cross-origin access to the public API, as configured in `[cors]`.
+//!
+//! Without allowed origins no CORS headers are sent at all, so browsers
+//! refuse cross-origin reads. The policy is built once at startup.
+
+use std::time::Duration;
+
+use axum::http::{HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+use crate::config::CorsConfig;
+
+/// Response headers of the service that browser clients may read.
+const EXPOSED_HEADERS: [&str; 5] = [
+    "x-request-id",
+    "etag",
+    crate::DRY_RUN_HEADER,
+    crate::CACHED_HEADER,
+    crate::attestation::HEADER,
+];
+
+/// Builds the layer for `config`, `None` when cross-origin access is denied.
+pub fn layer(config: &CorsConfig) -> Result<Option<CorsLayer>, String> {
+    if config.allowed_origins.is_empty() {
+        return Ok(None);
+    }
+    let origins = if config.allowed_origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::from(Any)
+    } else {
+        let origins = config
+            .allowed_origins
+            .iter()
+            .map(|origin| {
+                HeaderValue::from_str(origin.trim_end_matches('/'))
+                    .map_err(|e| format!("cors.allowed_origins: `{origin}`: {e}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        AllowOrigin::list(origins)
+    };
+    let methods = config
+        .allowed_methods
+        .iter()
+        .map(|method| {
+            Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                .map_err(|e| format!("cors.allowed_methods: `{method}`: {e}"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let headers = config
+        .allowed_headers
+        .iter()
+        .map(|header| {
+            HeaderName::from_bytes(header.as_bytes())
+                .map_err(|e| format!("cors.allowed_headers: `{header}`: {e}"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut layer = CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .expose_headers(EXPOSED_HEADERS.map(HeaderName::from_static));
+    if config.max_age_secs > 0 {
+        layer = layer.max_age(Duration::from_secs(config.max_age_secs));
+    }
+    Ok(Some(layer))
+}
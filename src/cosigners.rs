@@ -0,0 +1,234 @@
+//! Which cosigners a signed PSBT still needs.
+//!
+//! Each input's spending conditions are read from the PSBT itself: the
+//! witness or redeem script, or the taproot internal key and leaf scripts,
+//! lifted to their semantic policy. Against the signatures already present
+//! this gives the fewest further signatures that complete the input, and
+//! the keys that could give them, named by the fingerprint of their BIP-32
+//! origin. Branches that also need a timelock or a hash preimage are not
+//! counted as completable by signatures.
+
+use bdk_wallet::miniscript::{
+    policy::{Liftable, Semantic},
+    Legacy, Miniscript, MiniscriptKey, Segwitv0, Tap,
+};
+use bitcoin::{
+    bip32::Fingerprint,
+    key::XOnlyPublicKey,
+    psbt::Input,
+    taproot::{LeafVersion, TapLeafHash},
+    Psbt, PublicKey,
+};
+use serde::{Deserialize, Serialize};
+
+/// BIP-341's provably unspendable internal key, used when a taproot output
+/// has no key path.
+const NUMS_KEY: &str = "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct InputSignatures {
+    /// Index of the input.
+    pub input: usize,
+    /// Fewest further signatures that complete the input. Absent when
+    /// signatures alone cannot, or the PSBT lacks the input's script.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub needed: Option<usize>,
+    /// Keys that have signed.
+    pub signed: Vec<Cosigner>,
+    /// Keys that have not signed and could complete the input.
+    pub missing: Vec<Cosigner>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Cosigner {
+    /// Master key fingerprint of the key's origin, when the PSBT has it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, example = "e650a2a0")]
+    pub fingerprint: Option<Fingerprint>,
+    /// Hex public key, x-only for taproot.
+    pub pubkey: String,
+}
+
+/// What an input needs: a key's signature, `k` of some conditions, or a
+/// condition signatures cannot meet.
+enum Need {
+    Key { cosigner: Cosigner, signed: bool },
+    Thresh(usize, Vec<Need>),
+    Trivial,
+    Impossible,
+}
+
+impl Need {
+    fn lift<Pk: MiniscriptKey>(
+        policy: &Semantic<Pk>,
+        key: &impl Fn(&Pk) -> (Cosigner, bool),
+    ) -> Need {
+        match policy {
+            Semantic::Key(pk) => {
+                let (cosigner, signed) = key(pk);
+                Need::Key { cosigner, signed }
+            }
+            Semantic::Trivial => Need::Trivial,
+            Semantic::Thresh(thresh) => Need::Thresh(
+                thresh.k(),
+                thresh
+                    .iter()
+                    .map(|policy| Need::lift(policy, key))
+                    .collect(),
+            ),
+            _ => Need::Impossible,
+        }
+    }
+
+    /// Fewest further signatures that meet this, if signatures can.
+    fn cost(&self) -> Option<usize> {
+        match self {
+            Need::Key { signed, .. } => Some(usize::from(!signed)),
+            Need::Trivial => Some(0),
+            Need::Impossible => None,
+            Need::Thresh(k, needs) => {
+                let mut costs: Vec<usize> = needs.iter().filter_map(Need::cost).collect();
+                if costs.len() < *k {
+                    return None;
+                }
+                costs.sort_unstable();
+                Some(costs[..*k].iter().sum())
+            }
+        }
+    }
+
+    /// The keys of branches signatures can meet, split by whether they
+    /// signed.
+    fn keys(&self, signed: &mut Vec<Cosigner>, missing: &mut Vec<Cosigner>) {
+        if self.cost().is_none() {
+            return;
+        }
+        match self {
+            Need::Key {
+                cosigner,
+                signed: true,
+            } => push_new(signed, cosigner),
+            Need::Key { cosigner, .. } => push_new(missing, cosigner),
+            Need::Thresh(_, needs) => {
+                for need in needs {
+                    need.keys(signed, missing);
+                }
+            }
+            Need::Trivial | Need::Impossible => {}
+        }
+    }
+}
+
+fn push_new(cosigners: &mut Vec<Cosigner>, cosigner: &Cosigner) {
+    if !cosigners.contains(cosigner) {
+        cosigners.push(cosigner.clone());
+    }
+}
+
+/// The inputs of `psbt` not yet complete, with what each still needs.
+pub fn missing(psbt: &Psbt) -> Vec<InputSignatures> {
+    psbt.inputs
+        .iter()
+        .enumerate()
+        .filter(|(_, input)| {
+            input.final_script_sig.is_none() && input.final_script_witness.is_none()
+        })
+        .filter_map(|(index, input)| {
+            let Some(need) = need(input) else {
+                return Some(InputSignatures {
+                    input: index,
+                    needed: None,
+                    signed: Vec::new(),
+                    missing: Vec::new(),
+                });
+            };
+            let needed = need.cost();
+            if needed == Some(0) {
+                return None;
+            }
+            let (mut signed, mut missing) = (Vec::new(), Vec::new());
+            need.keys(&mut signed, &mut missing);
+            missing.retain(|cosigner| !signed.contains(cosigner));
+            Some(InputSignatures {
+                input: index,
+                needed,
+                signed,
+                missing,
+            })
+        })
+        .collect()
+}
+
+/// The spending conditions of `input`, if the PSBT carries them.
+fn need(input: &Input) -> Option<Need> {
+    let ecdsa = |pk: &PublicKey| {
+        let cosigner = Cosigner {
+            fingerprint: input
+                .bip32_derivation
+                .get(&pk.inner)
+                .map(|(fingerprint, _)| *fingerprint),
+            pubkey: pk.to_string(),
+        };
+        (cosigner, input.partial_sigs.contains_key(pk))
+    };
+
+    if let Some(internal) = input.tap_internal_key {
+        let mut paths = Vec::new();
+        if internal.to_string() != NUMS_KEY {
+            paths.push(Need::Key {
+                cosigner: xonly_cosigner(input, &internal),
+                signed: input.tap_key_sig.is_some(),
+            });
+        }
+        for (script, version) in input.tap_scripts.values() {
+            if *version != LeafVersion::TapScript {
+                continue;
+            }
+            let leaf = TapLeafHash::from_script(script, *version);
+            let key = |pk: &XOnlyPublicKey| {
+                let signed = input.tap_script_sigs.contains_key(&(*pk, leaf));
+                (xonly_cosigner(input, pk), signed)
+            };
+            let need = Miniscript::<XOnlyPublicKey, Tap>::parse_insane(script)
+                .and_then(|ms| ms.lift())
+                .map_or(Need::Impossible, |policy| Need::lift(&policy, &key));
+            paths.push(need);
+        }
+        return Some(Need::Thresh(1, paths));
+    }
+    if let Some(script) = &input.witness_script {
+        let policy = Miniscript::<PublicKey, Segwitv0>::parse_insane(script)
+            .and_then(|ms| ms.lift())
+            .ok()?;
+        return Some(Need::lift(&policy, &ecdsa));
+    }
+    if let Some(script) = input
+        .redeem_script
+        .as_ref()
+        .filter(|s| !s.is_witness_program())
+    {
+        let policy = Miniscript::<PublicKey, Legacy>::parse_insane(script)
+            .and_then(|ms| ms.lift())
+            .ok()?;
+        return Some(Need::lift(&policy, &ecdsa));
+    }
+    // A single-key script, P2WPKH or P2PKH.
+    let mut keys = input.bip32_derivation.keys();
+    match (keys.next(), keys.next()) {
+        (Some(key), None) => {
+            let (cosigner, signed) = ecdsa(&PublicKey::new(*key));
+            Some(Need::Key { cosigner, signed })
+        }
+        _ => None,
+    }
+}
+
+fn xonly_cosigner(input: &Input, pk: &XOnlyPublicKey) -> Cosigner {
+    Cosigner {
+        fingerprint: input
+            .tap_key_origins
+            .get(pk)
+            .map(|(_, (fingerprint, _))| *fingerprint),
+        pubkey: pk.to_string(),
+    }
+}
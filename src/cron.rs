@@ -0,0 +1,182 @@
+//! Cron expressions for [schedules](crate::schedule), evaluated in UTC.
+//!
+//! The five standard fields are supported: minute, hour, day of month,
+//! month and day of week, each a `*`, a value, a range `a-b` or a
+//! comma-separated list of those, optionally stepped with `/n`. Months and
+//! weekdays also take English three-letter names, and Sunday is `0` or `7`.
+//! When both day fields are restricted, a day matching either one matches,
+//! as in cron. `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly`
+//! stand for their usual expressions.
+
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Datelike, Duration, DurationRound, NaiveTime, Timelike, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// How far ahead [`Cron::next_after`] looks, enough for any 29 February.
+const HORIZON_DAYS: i64 = 8 * 366;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Cron {
+    source: String,
+    /// Bit `n` set when the field matches `n`.
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of month and day of week fields were `*`.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    /// The first matching minute strictly after `time`, if there is one
+    /// within the next eight years.
+    pub fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut next = time.duration_trunc(Duration::minutes(1)).ok()? + Duration::minutes(1);
+        let horizon = time + Duration::days(HORIZON_DAYS);
+        while next <= horizon {
+            if !self.day_matches(next) {
+                next = (next.date_naive() + Duration::days(1))
+                    .and_time(NaiveTime::MIN)
+                    .and_utc();
+            } else if !bit(self.hours, next.hour()) {
+                next = next.duration_trunc(Duration::hours(1)).ok()? + Duration::hours(1);
+            } else if !bit(self.minutes, next.minute()) {
+                next += Duration::minutes(1);
+            } else {
+                return Some(next);
+            }
+        }
+        None
+    }
+
+    fn day_matches(&self, time: DateTime<Utc>) -> bool {
+        if !bit(self.months, time.month()) {
+            return false;
+        }
+        let day = bit(self.days, time.day());
+        let weekday = bit(self.weekdays, time.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let expression = match s.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "expected 5 fields (minute hour day month weekday), got {}",
+                fields.len()
+            ));
+        };
+        // Sunday is both 0 and 7.
+        let mut weekdays = field(weekday, 0, 7, &WEEKDAYS).map_err(|e| format!("weekday: {e}"))?;
+        if bit(weekdays, 7) {
+            weekdays |= 1;
+        }
+        Ok(Cron {
+            source: s.trim().to_owned(),
+            minutes: field(minute, 0, 59, &[]).map_err(|e| format!("minute: {e}"))?,
+            hours: field(hour, 0, 23, &[]).map_err(|e| format!("hour: {e}"))?,
+            days: field(day, 1, 31, &[]).map_err(|e| format!("day: {e}"))?,
+            months: field(month, 1, 12, &MONTHS).map_err(|e| format!("month: {e}"))?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl fmt::Debug for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cron({})", self.source)
+    }
+}
+
+impl Serialize for Cron {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cron {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+fn bit(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+/// The values matched by one field, as a bit set. `names` spell the values
+/// from `min` on.
+fn field(s: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let value = |s: &str| -> Result<u32, String> {
+        let value = match names.iter().position(|name| name.eq_ignore_ascii_case(s)) {
+            Some(index) => index as u32 + min,
+            None => s.parse().map_err(|_| format!("invalid value {s}"))?,
+        };
+        if !(min..=max).contains(&value) {
+            return Err(format!("{value} is not within {min}-{max}"));
+        }
+        Ok(value)
+    };
+    let mut set = 0;
+    for item in s.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("invalid step {step}")),
+            },
+            None => (item, 1),
+        };
+        let (first, last) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((first, last)) => (value(first)?, value(last)?),
+                // `a/n` runs from `a` to the end, as in cron.
+                None if step > 1 => (value(range)?, max),
+                None => {
+                    let value = value(range)?;
+                    (value, value)
+                }
+            },
+        };
+        if first > last {
+            return Err(format!("range {first}-{last} is reversed"));
+        }
+        for value in (first..=last).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
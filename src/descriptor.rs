@@ -0,0 +1,203 @@
+//! Descriptor inspection for coordinators validating a wallet setup against
+//! this signer.
+
+use std::{str::FromStr, sync::Arc};
+
+use axum::{extract::State, Json};
+use bdk_wallet::{
+    descriptor::{Descriptor, DescriptorPublicKey, Policy},
+    keys::DescriptorSecretKey,
+    miniscript::{
+        descriptor::{checksum::desc_checksum, DescriptorType, DescriptorXKey},
+        ForEachKey,
+    },
+    KeychainKind,
+};
+use bitcoin::{
+    bip32::{DerivationPath, Fingerprint, Xpriv, Xpub},
+    key::Secp256k1,
+    Network, NetworkKind,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{ApiJson, Error, ErrorResponse},
+    AppState,
+};
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct ParseRequest {
+    /// Output descriptor with public keys, with or without checksum.
+    #[schema(example = "wpkh([d34db33f/84'/0'/0']xpub6CatWdiZ.../0/*)")]
+    pub descriptor: String,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ParseResponse {
+    /// Canonical form, checksum included.
+    pub descriptor: String,
+    pub checksum: String,
+    /// e.g. `wpkh`, `sh_wpkh`, `wsh_sorted_multi` or `tr`.
+    pub script_type: &'static str,
+    /// Whether the descriptor derives addresses from a wildcard.
+    pub ranged: bool,
+    /// Whether it holds `<a;b>` multipath keys, such as receive and change.
+    pub multipath: bool,
+    /// Whether one of its keys is derived from this wallet's xprv.
+    pub can_sign: bool,
+}
+
+#[utoipa::path(
+    post,
+    path = "/parse_descriptor",
+    request_body = ParseRequest,
+    responses(
+        (status = 200, description = "Parsed descriptor", body = ParseResponse),
+        (status = 400, description = "`malformed_request`", body = ErrorResponse),
+        (status = 415, description = "`unsupported_media_type`", body = ErrorResponse),
+    )
+)]
+pub async fn parse(
+    State(state): State<Arc<AppState>>,
+    ApiJson(request): ApiJson<ParseRequest>,
+) -> Result<Json<ParseResponse>, Error> {
+    let invalid = |reason: String| Error::MalformedRequest(format!("descriptor: {reason}"));
+    let input = request.descriptor.trim();
+    if let Some((body, checksum)) = input.split_once('#') {
+        if desc_checksum(body).ok().as_deref() != Some(checksum) {
+            return Err(invalid("checksum mismatch".into()));
+        }
+    }
+    let descriptor =
+        Descriptor::<DescriptorPublicKey>::from_str(input).map_err(|e| invalid(e.to_string()))?;
+
+    let root = signing_root(&state.config().xprv);
+    let secp = Secp256k1::new();
+    let can_sign = root.is_some_and(|root| {
+        descriptor.for_any_key(|key| match key {
+            DescriptorPublicKey::XPub(xkey) => {
+                derives(&secp, &root, xkey.origin.as_ref(), &xkey.xkey)
+            }
+            DescriptorPublicKey::MultiXPub(xkey) => {
+                derives(&secp, &root, xkey.origin.as_ref(), &xkey.xkey)
+            }
+            DescriptorPublicKey::Single(_) => false,
+        })
+    });
+
+    let canonical = descriptor.to_string();
+    let checksum = canonical
+        .split_once('#')
+        .map(|(_, checksum)| checksum.to_owned())
+        .unwrap_or_default();
+    Ok(Json(ParseResponse {
+        checksum,
+        script_type: script_type(descriptor.desc_type()),
+        ranged: descriptor.has_wildcard(),
+        multipath: descriptor.is_multipath(),
+        can_sign,
+        descriptor: canonical,
+    }))
+}
+
+/// The xprv of this wallet's descriptor, with its key origin.
+pub fn signing_root(xprv: &str) -> Option<DescriptorXKey<Xpriv>> {
+    let secp = Secp256k1::new();
+    let (_, keys) = Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, xprv).ok()?;
+    keys.into_values().find_map(|key| match key {
+        DescriptorSecretKey::XPrv(xkey) => Some(xkey),
+        _ => None,
+    })
+}
+
+/// Checks that every private key in the wallet's descriptor `xprv` is for
+/// `network`, so a mainnet key is never served on a test network or the
+/// other way round.
+pub fn check_key_network(xprv: &str, network: Network) -> Result<(), String> {
+    let secp = Secp256k1::new();
+    let (_, keys) = Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, xprv)
+        .map_err(|e| format!("invalid descriptor: {e}"))?;
+    let expected = NetworkKind::from(network);
+    for key in keys.values() {
+        let kind = match key {
+            DescriptorSecretKey::Single(single) => single.key.network,
+            DescriptorSecretKey::XPrv(xkey) => xkey.xkey.network,
+            DescriptorSecretKey::MultiXPrv(xkey) => xkey.xkey.network,
+        };
+        if kind != expected {
+            let kind = match kind {
+                NetworkKind::Main => "a mainnet",
+                NetworkKind::Test => "a testnet",
+            };
+            return Err(format!(
+                "{kind} key cannot be used with network `{network}`"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `xpub`, found under `origin`, is `root` or one of its children.
+fn derives(
+    secp: &Secp256k1<bitcoin::secp256k1::All>,
+    root: &DescriptorXKey<Xpriv>,
+    origin: Option<&(Fingerprint, DerivationPath)>,
+    xpub: &Xpub,
+) -> bool {
+    let (root_fingerprint, root_path) = match &root.origin {
+        Some((fingerprint, path)) => (*fingerprint, path.clone()),
+        None => (root.xkey.fingerprint(secp), DerivationPath::default()),
+    };
+    let path = match origin {
+        Some((fingerprint, path)) if *fingerprint == root_fingerprint => path,
+        // Without an origin only the root itself can be recognized.
+        None => return Xpub::from_priv(secp, &root.xkey) == *xpub,
+        Some(_) => return false,
+    };
+    let Some(relative) = path.as_ref().strip_prefix(root_path.as_ref()) else {
+        return false;
+    };
+    root.xkey
+        .derive_priv(secp, &relative)
+        .is_ok_and(|child| Xpub::from_priv(secp, &child) == *xpub)
+}
+
+pub fn script_type(desc_type: DescriptorType) -> &'static str {
+    match desc_type {
+        DescriptorType::Bare => "bare",
+        DescriptorType::Sh => "sh",
+        DescriptorType::Pkh => "pkh",
+        DescriptorType::Wpkh => "wpkh",
+        DescriptorType::Wsh => "wsh",
+        DescriptorType::ShWsh => "sh_wsh",
+        DescriptorType::ShWpkh => "sh_wpkh",
+        DescriptorType::ShSortedMulti => "sh_sorted_multi",
+        DescriptorType::WshSortedMulti => "wsh_sorted_multi",
+        DescriptorType::ShWshSortedMulti => "sh_wsh_sorted_multi",
+        DescriptorType::Tr => "tr",
+    }
+}
+
+#[derive(Serialize)]
+pub struct PoliciesResponse {
+    /// Spending policy of the receive descriptor, as bdk reports it. The
+    /// `id` of each node is what `policy_path` names.
+    pub external: Option<Policy>,
+    /// Of the change descriptor.
+    pub internal: Option<Policy>,
+}
+
+/// The wallet's spending policies, to find the node ids and item indexes a
+/// `policy_path` chooses branches with.
+pub async fn policies(State(state): State<Arc<AppState>>) -> Result<Json<PoliciesResponse>, Error> {
+    let wallet = state.wallet();
+    let policy = |keychain| {
+        wallet
+            .policies(keychain)
+            .map_err(|e| Error::Internal(format!("descriptor policy: {e}")))
+    };
+    Ok(Json(PoliciesResponse {
+        external: policy(KeychainKind::External)?,
+        internal: policy(KeychainKind::Internal)?,
+    }))
+}
@@ -0,0 +1,54 @@
+//! Signing policy against dust outputs.
+//!
+//! With `[dust]` set, PSBTs with an output worth less than the threshold of
+//! its script type are not signed, and the transactions the service builds
+//! itself are held to the same thresholds. Dust paid to this wallet costs
+//! more to spend than it is worth, and a counterparty can use it to link the
+//! wallet's coins when it does get spent.
+
+use bdk_wallet::Wallet;
+use bitcoin::{Amount, Psbt, Script};
+
+use crate::{config::DustConfig, error::Error};
+
+/// The smallest value an output to `script` may carry, or `None` for an
+/// `OP_RETURN` output, which need not carry any.
+pub fn threshold(policy: &DustConfig, script: &Script) -> Option<Amount> {
+    let sat = if script.is_op_return() {
+        return None;
+    } else if script.is_p2pkh() {
+        policy.p2pkh_sat
+    } else if script.is_p2sh() {
+        policy.p2sh_sat
+    } else if script.is_p2wpkh() {
+        policy.p2wpkh_sat
+    } else if script.is_p2wsh() {
+        policy.p2wsh_sat
+    } else if script.is_p2tr() {
+        policy.p2tr_sat
+    } else {
+        policy.other_sat
+    };
+    Some(Amount::from_sat(sat))
+}
+
+/// Checks every output of `psbt`, or only those paying `wallet` with
+/// `own_outputs_only`, against `policy`.
+pub fn check(wallet: &Wallet, psbt: &Psbt, policy: &DustConfig) -> Result<(), Error> {
+    for (index, output) in psbt.unsigned_tx.output.iter().enumerate() {
+        if policy.own_outputs_only && !wallet.is_mine(output.script_pubkey.clone()) {
+            continue;
+        }
+        if let Some(threshold) = threshold(policy, &output.script_pubkey) {
+            if output.value < threshold {
+                return Err(Error::InvalidTransaction(format!(
+                    "dust: output {index} carries {} sat, below the {} sat threshold for its \
+                     script type",
+                    output.value.to_sat(),
+                    threshold.to_sat()
+                )));
+            }
+        }
+    }
+    Ok(())
+}
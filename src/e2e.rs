@@ -0,0 +1,882 @@
+//! `issue-service e2e`: end-to-end check of this binary against a mock
+//! Esplora server, built with `--features e2e`.
+//!
+//! The harness serves a regtest chain whose block 1 funds the first receive
+//! addresses of a fixed test key, starts this same executable as a child
+//! process with `[chain]` pointed at the mock, and drives it over HTTP only:
+//! readiness, chain sync as seen through `/payment_uri`, signing through
+//! `/sign_psbt` and `/sign_psbt/binary`, a binary body on `/sign_psbt`, the
+//! audit entries they leave, a conflicting spend refused by the spend guard
+//! and then signed as a replacement, a proof of reserves over the funded coins, a BIP-322 ownership
+//! statement for one of them, its label in `/admin/utxos`, a consolidation
+//! left undone for want of coins, a batch payout, a sweep of the other coin broadcast to
+//! the mock, and a scheduled sweep that finds nothing left.
+//! Signed PSBTs must extract into a transaction whose every input passes
+//! the miniscript interpreter, signatures included, so they are ready to
+//! broadcast. The harness builds the spend it signs itself and stops at the
+//! extracted transaction.
+//!
+//! The process exits non-zero at the first failed step and leaves the
+//! config, service log and audit log in its work directory.
+
+use std::{
+    collections::HashSet,
+    path::Path,
+    process::{Child, Command, ExitCode, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Path as UrlPath, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use bdk_wallet::{miniscript::psbt::PsbtExt, Wallet};
+use bitcoin::{
+    blockdata::constants::genesis_block,
+    consensus,
+    hashes::{sha256, Hash},
+    hex::DisplayHex,
+    secp256k1::Secp256k1,
+    BlockHash, Network, Psbt, Transaction,
+};
+use serde_json::{json, Value};
+
+use crate::{
+    audit::{AuditEntry, Outcome},
+    test_vectors::XPRV,
+};
+
+/// Receive addresses paid by the funding transaction.
+const FUNDED: usize = 2;
+/// How long the service may take to become ready, and then to sync.
+const STARTUP: Duration = Duration::from_secs(30);
+const POLL: Duration = Duration::from_millis(100);
+const BLOCK_TIME: u64 = 1_700_000_000;
+
+pub async fn run() -> ExitCode {
+    let dir = std::env::temp_dir().join(format!("issue-service-e2e-{}", std::process::id()));
+    match e2e(&dir).await {
+        Ok(()) => {
+            let _ = std::fs::remove_dir_all(&dir);
+            println!("e2e passed");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("e2e failed: {e}");
+            eprintln!("config and logs are in {}", dir.display());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn e2e(dir: &Path) -> Result<(), String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("creating {}: {e}", dir.display()))?;
+    let wallet = Wallet::create_single(XPRV)
+        .network(Network::Regtest)
+        .create_wallet_no_persist()
+        .map_err(|e| format!("test key: {e}"))?;
+    let funding = crate::bench::funding_tx(&wallet, FUNDED)?;
+    let esplora = Arc::new(Esplora::new(funding.clone()));
+    let esplora_url = esplora.serve().await?;
+
+    let port = free_port()?;
+    let admin_port = free_port()?;
+    let config_path = dir.join("config.toml");
+    std::fs::write(&config_path, config(dir, port, admin_port, &esplora_url))
+        .map_err(|e| format!("writing {}: {e}", config_path.display()))?;
+    let mut service = Service::start(&config_path)?;
+    let client = Client {
+        http: reqwest::Client::new(),
+        base: format!("http://127.0.0.1:{port}"),
+    };
+    let admin = Client {
+        http: reqwest::Client::new(),
+        base: format!("http://127.0.0.1:{admin_port}"),
+    };
+
+    let started = Instant::now();
+    loop {
+        service.check_running()?;
+        if client
+            .get("/ready")
+            .await
+            .is_ok_and(|status| status.is_success())
+        {
+            break;
+        }
+        if started.elapsed() > STARTUP {
+            return Err("service did not become ready".into());
+        }
+        tokio::time::sleep(POLL).await;
+    }
+    println!("ok: service ready");
+
+    // The sync task fetches `/blocks` first on every pass, so a second
+    // fetch means the first pass has been applied to the wallet.
+    while esplora.scans.load(Ordering::Acquire) < 2 {
+        service.check_running()?;
+        if started.elapsed() > STARTUP * 2 {
+            return Err("service did not sync from the mock Esplora".into());
+        }
+        tokio::time::sleep(POLL).await;
+    }
+    let (status, body) = client.post_json("/payment_uri", &json!({}), None).await?;
+    let index = body["index"].as_u64();
+    if status != StatusCode::OK || index != Some(FUNDED as u64) {
+        return Err(format!(
+            "/payment_uri: expected index {FUNDED} after the funded addresses, got {status} {body}"
+        ));
+    }
+    println!("ok: chain sync skipped the {FUNDED} funded addresses");
+
+    let unsigned = crate::bench::spending_psbt(&wallet, &funding, 1)?;
+    let txid = unsigned.unsigned_tx.compute_txid();
+    let (status, body) = client
+        .post_json(
+            "/sign_psbt",
+            &json!({ "psbt": unsigned.to_string() }),
+            Some("e2e-json"),
+        )
+        .await?;
+    if status != StatusCode::OK {
+        return Err(format!("/sign_psbt: {status} {body}"));
+    }
+    let signed: Psbt = body["psbt"]
+        .as_str()
+        .unwrap_or_default()
+        .parse()
+        .map_err(|e| format!("/sign_psbt: invalid psbt in response: {e}"))?;
+    let tx = extract("/sign_psbt", &signed)?;
+    println!(
+        "ok: /sign_psbt signed {} inputs, {} is ready to broadcast",
+        tx.input.len(),
+        tx.compute_txid()
+    );
+
+    let (status, bytes) = client
+        .post_binary("/sign_psbt/binary", unsigned.serialize(), "e2e-binary")
+        .await?;
+    if status != StatusCode::OK {
+        return Err(format!(
+            "/sign_psbt/binary: {status} {}",
+            String::from_utf8_lossy(&bytes)
+        ));
+    }
+    let signed_binary = Psbt::deserialize(&bytes)
+        .map_err(|e| format!("/sign_psbt/binary: invalid psbt in response: {e}"))?;
+    extract("/sign_psbt/binary", &signed_binary)?;
+    if signed_binary != signed {
+        return Err("/sign_psbt/binary and /sign_psbt signed differently".into());
+    }
+    println!("ok: /sign_psbt/binary matches /sign_psbt");
+
+    let (status, bytes) = client
+        .post_binary("/sign_psbt", unsigned.serialize(), "e2e-negotiated")
+        .await?;
+    if status != StatusCode::OK || Psbt::deserialize(&bytes).ok().as_ref() != Some(&signed) {
+        return Err(format!(
+            "/sign_psbt with a binary body: {status} {}",
+            String::from_utf8_lossy(&bytes)
+        ));
+    }
+    println!("ok: /sign_psbt answers a binary body in kind");
+
+    let audit_path = dir.join("audit.jsonl");
+    let audit = std::fs::read_to_string(&audit_path)
+        .map_err(|e| format!("reading {}: {e}", audit_path.display()))?;
+    let entries: Vec<AuditEntry> = audit
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("audit log: {e}"))?;
+    for request_id in ["e2e-json", "e2e-binary"] {
+        let recorded = entries.iter().any(|entry| {
+            entry.request_id.as_deref() == Some(request_id)
+                && entry.txid == txid
+                && entry.outcome == Outcome::Signed
+        });
+        if !recorded {
+            return Err(format!("audit log has no signed entry for {request_id}"));
+        }
+    }
+    println!("ok: audit log recorded both signatures");
+
+    let conflicting = crate::bench::spending_psbt(&wallet, &funding, 2)?;
+    let (status, body) = client
+        .post_json(
+            "/sign_psbt",
+            &json!({ "psbt": conflicting.to_string() }),
+            None,
+        )
+        .await?;
+    let message = body["error"]["message"].as_str().unwrap_or_default();
+    if status != StatusCode::BAD_REQUEST || !message.contains("double spend:") {
+        return Err(format!(
+            "/sign_psbt: expected a conflicting spend to be refused, got {status} {body}"
+        ));
+    }
+    let (status, body) = client
+        .post_json(
+            "/sign_psbt",
+            &json!({ "psbt": conflicting.to_string(), "replace": true }),
+            None,
+        )
+        .await?;
+    if status != StatusCode::OK {
+        return Err(format!("/sign_psbt with replace: {status} {body}"));
+    }
+    let (_, spends) = admin.get_json("/admin/spent_outpoints").await?;
+    let replacement = conflicting.unsigned_tx.compute_txid();
+    let spends = spends.as_array().cloned().unwrap_or_default();
+    if spends.len() != FUNDED
+        || spends
+            .iter()
+            .any(|spend| spend["txid"] != json!(replacement))
+    {
+        return Err(format!(
+            "/admin/spent_outpoints: expected {FUNDED} coins spent by {replacement}, got {spends:?}"
+        ));
+    }
+    // Forgotten again, so the later steps may spend the funded coins.
+    for spend in &spends {
+        let outpoint = spend["outpoint"].as_str().unwrap_or_default();
+        let path = format!("/admin/spent_outpoints/{outpoint}");
+        let status = admin.delete(&path).await?;
+        if status != StatusCode::NO_CONTENT {
+            return Err(format!("DELETE {path}: {status}"));
+        }
+    }
+    println!("ok: spend guard refused a conflicting spend and signed it as a replacement");
+
+    let message = "e2e audit";
+    let (status, proof) = admin
+        .post_json(
+            "/admin/proof_of_reserves",
+            &json!({ "message": message }),
+            None,
+        )
+        .await?;
+    let funded: u64 = funding.output.iter().map(|out| out.value.to_sat()).sum();
+    if status != StatusCode::OK || proof["amount_sat"].as_u64() != Some(funded) {
+        return Err(format!(
+            "/admin/proof_of_reserves: expected {funded} sat, got {status} {proof}"
+        ));
+    }
+    let (status, verdict) = client
+        .post_json(
+            "/proof_of_reserves/verify",
+            &json!({ "psbt": proof["psbt"], "message": message }),
+            None,
+        )
+        .await?;
+    if status != StatusCode::OK || verdict["valid"] != json!(true) {
+        return Err(format!("/proof_of_reserves/verify: {status} {verdict}"));
+    }
+    let (_, verdict) = client
+        .post_json(
+            "/proof_of_reserves/verify",
+            &json!({ "psbt": proof["psbt"], "message": "another challenge" }),
+            None,
+        )
+        .await?;
+    if verdict["valid"] != json!(false) {
+        return Err(format!(
+            "/proof_of_reserves/verify accepted the wrong message: {verdict}"
+        ));
+    }
+    println!("ok: proof of reserves covers {funded} sat and verifies");
+
+    let outpoint = bitcoin::OutPoint::new(funding.compute_txid(), 0);
+    let (status, statement) = admin
+        .post_json(
+            "/admin/utxo_proof",
+            &json!({ "outpoint": outpoint, "challenge": message }),
+            None,
+        )
+        .await?;
+    if status != StatusCode::OK {
+        return Err(format!("/admin/utxo_proof: {status} {statement}"));
+    }
+    let address = wallet
+        .peek_address(bdk_wallet::KeychainKind::External, 0)
+        .address;
+    let signature = statement["signature"].as_str().unwrap_or_default();
+    crate::ownership::verify(
+        &address,
+        &crate::ownership::message(outpoint, message),
+        signature,
+    )
+    .map_err(|e| format!("/admin/utxo_proof: {e}: {statement}"))?;
+    if crate::ownership::verify(
+        &address,
+        &crate::ownership::message(outpoint, "another challenge"),
+        signature,
+    )
+    .is_ok()
+    {
+        return Err("/admin/utxo_proof: statement verifies for another challenge".into());
+    }
+    println!("ok: utxo proof for {outpoint} verifies as BIP-322");
+
+    let (status, label) = admin
+        .post_json(
+            "/admin/labels",
+            &json!({ "type": "output", "ref": outpoint, "label": "e2e coin", "spendable": false }),
+            None,
+        )
+        .await?;
+    if status != StatusCode::OK {
+        return Err(format!("/admin/labels: {status} {label}"));
+    }
+    let (status, utxos) = admin.get_json("/admin/utxos").await?;
+    let labeled = utxos.as_array().and_then(|utxos| {
+        utxos
+            .iter()
+            .find(|utxo| utxo["outpoint"] == json!(outpoint))
+    });
+    match labeled {
+        Some(utxo) if utxo["label"] == "e2e coin" && utxo["spendable"] == json!(false) => {}
+        _ => return Err(format!("/admin/utxos: {status} {utxos}")),
+    }
+    println!("ok: /admin/utxos shows the label of {outpoint}");
+
+    // With one coin left spendable, there is nothing to merge it with.
+    let (status, consolidation) = admin
+        .post_json("/admin/consolidate", &json!({}), None)
+        .await?;
+    if status != StatusCode::OK
+        || consolidation["candidates"] != 1
+        || consolidation["fee_rate_sat_vb"] != 1.5
+        || consolidation["skipped"].is_null()
+        || !consolidation["txid"].is_null()
+    {
+        return Err(format!("/admin/consolidate: {status} {consolidation}"));
+    }
+    println!("ok: /admin/consolidate left a single coin alone");
+
+    let payees: Vec<_> = (101..104)
+        .map(|index| {
+            wallet
+                .peek_address(bdk_wallet::KeychainKind::External, index)
+                .address
+        })
+        .collect();
+    let payments: Vec<Value> = payees
+        .iter()
+        .map(|address| json!({ "address": address, "amount_sat": 10_000 }))
+        .collect();
+    let (status, payout) = admin
+        .post_json(
+            "/admin/create_payout",
+            &json!({ "payments": payments, "fee_rate_sat_vb": 2.0 }),
+            None,
+        )
+        .await?;
+    if status != StatusCode::OK || payout["transactions"].as_array().map(Vec::len) != Some(1) {
+        return Err(format!("/admin/create_payout: {status} {payout}"));
+    }
+    let psbt: Psbt = payout["transactions"][0]["psbt"]
+        .as_str()
+        .unwrap_or_default()
+        .parse()
+        .map_err(|e| format!("/admin/create_payout: invalid psbt in response: {e}"))?;
+    let tx = extract("/admin/create_payout", &psbt)?;
+    let spendable = bitcoin::OutPoint::new(funding.compute_txid(), 1);
+    let paid = payees.iter().all(|address| {
+        tx.output.iter().any(|output| {
+            output.script_pubkey == address.script_pubkey() && output.value.to_sat() == 10_000
+        })
+    });
+    if !paid || tx.input.len() != 1 || tx.input[0].previous_output != spendable {
+        return Err(format!(
+            "/admin/create_payout: unexpected transaction {tx:?}"
+        ));
+    }
+    // Split one payment per transaction, the second would need the coin the
+    // first one spends.
+    let (status, split) = admin
+        .post_json(
+            "/admin/create_payout",
+            &json!({ "payments": payments, "fee_rate_sat_vb": 2.0, "max_payments_per_tx": 1 }),
+            None,
+        )
+        .await?;
+    if status != StatusCode::BAD_REQUEST {
+        return Err(format!(
+            "/admin/create_payout: expected the split payout to run out of coins, got {status} {split}"
+        ));
+    }
+    // The payout is never broadcast, so the sweep below may spend its coin.
+    let path = format!("/admin/spent_outpoints/{spendable}");
+    let status = admin.delete(&path).await?;
+    if status != StatusCode::NO_CONTENT {
+        return Err(format!("DELETE {path}: {status}"));
+    }
+    println!("ok: /admin/create_payout paid 3 addresses from the spendable coin");
+
+    let anchor = [0x42u8; 32];
+    let (status, created) = admin
+        .post_json(
+            "/admin/create_psbt",
+            &json!({ "op_return": [anchor.to_lower_hex_string()], "fee_rate_sat_vb": 2.0 }),
+            None,
+        )
+        .await?;
+    if status != StatusCode::OK {
+        return Err(format!("/admin/create_psbt: {status} {created}"));
+    }
+    let psbt: Psbt = created["psbt"]
+        .as_str()
+        .unwrap_or_default()
+        .parse()
+        .map_err(|e| format!("/admin/create_psbt: invalid psbt in response: {e}"))?;
+    let data = bitcoin::script::PushBytesBuf::from(anchor);
+    let anchored = psbt.unsigned_tx.output.iter().any(|output| {
+        output.script_pubkey == bitcoin::ScriptBuf::new_op_return(&data)
+            && output.value.to_sat() == 0
+    });
+    if !anchored || psbt.fee().ok().map(|fee| fee.to_sat()) != created["fee_sat"].as_u64() {
+        return Err(format!(
+            "/admin/create_psbt: unexpected transaction {:?}",
+            psbt.unsigned_tx
+        ));
+    }
+    let oversized = [0u8; 81].to_lower_hex_string();
+    let (status, oversized) = admin
+        .post_json(
+            "/admin/create_psbt",
+            &json!({ "op_return": [oversized], "fee_rate_sat_vb": 2.0 }),
+            None,
+        )
+        .await?;
+    if status != StatusCode::BAD_REQUEST {
+        return Err(format!(
+            "/admin/create_psbt: expected 81 bytes of data to be refused, got {status} {oversized}"
+        ));
+    }
+    println!("ok: /admin/create_psbt anchored 32 bytes and refused 81");
+
+    let destination = wallet
+        .peek_address(bdk_wallet::KeychainKind::External, 100)
+        .address;
+    let (status, swept) = admin
+        .post_json(
+            "/admin/sweep",
+            &json!({ "destination": destination, "fee_rate_sat_vb": 2.0, "broadcast": true }),
+            None,
+        )
+        .await?;
+    // The coin labeled as not spendable stays behind.
+    let expected = bitcoin::OutPoint::new(funding.compute_txid(), 1);
+    if status != StatusCode::OK || swept["inputs"] != json!([expected]) {
+        return Err(format!(
+            "/admin/sweep: expected to sweep {expected} only, got {status} {swept}"
+        ));
+    }
+    let psbt: Psbt = swept["psbt"]
+        .as_str()
+        .unwrap_or_default()
+        .parse()
+        .map_err(|e| format!("/admin/sweep: invalid psbt in response: {e}"))?;
+    let tx = extract("/admin/sweep", &psbt)?;
+    let broadcast = esplora.broadcast.lock().expect("broadcast").clone();
+    if broadcast != [tx.clone()] {
+        return Err(format!(
+            "/admin/sweep: the mock Esplora received {} transactions, not the sweep",
+            broadcast.len()
+        ));
+    }
+    println!(
+        "ok: /admin/sweep left the unspendable coin and broadcast {}",
+        tx.compute_txid()
+    );
+
+    let (status, schedule) = admin
+        .post_json(
+            "/admin/schedules",
+            &json!({
+                "name": "weekly",
+                "cron": "0 9 * * mon",
+                "action": "sweep",
+                "destination": destination,
+            }),
+            None,
+        )
+        .await?;
+    if status != StatusCode::CREATED || schedule["next_run"].is_null() {
+        return Err(format!("/admin/schedules: {status} {schedule}"));
+    }
+    // The swept coin is spent even before the next sync, so nothing is left.
+    let (status, run) = admin
+        .post_json("/admin/schedules/weekly/run", &json!({}), None)
+        .await?;
+    let (_, runs) = admin.get_json("/admin/schedules/weekly/runs").await?;
+    if status != StatusCode::OK
+        || run["outcome"] != "skipped"
+        || run["fee_rate_sat_vb"] != 8.0
+        || runs != json!([run])
+    {
+        return Err(format!(
+            "/admin/schedules/weekly/run: {status} {run}, runs {runs}"
+        ));
+    }
+    println!("ok: scheduled sweep found nothing left and is in the run history");
+
+    service.stop();
+    Ok(())
+}
+
+fn config(dir: &Path, port: u16, admin_port: u16, esplora_url: &str) -> String {
+    format!(
+        r#"network = "regtest"
+port = {port}
+admin_listen = "127.0.0.1:{admin_port}"
+xprv = "{XPRV}"
+
+[chain]
+esplora_url = "{esplora_url}"
+sync_interval_secs = 1
+
+[audit]
+path = "{audit}"
+
+[log]
+file = "{log}"
+
+[labels]
+path = "{labels}"
+
+[spend_guard]
+path = "{spends}"
+
+[schedules]
+path = "{schedules}"
+
+[consolidation]
+max_value_sat = 1000000
+min_count = 2
+max_fee_rate_sat_vb = 2.0
+"#,
+        audit = dir.join("audit.jsonl").display(),
+        labels = dir.join("labels.jsonl").display(),
+        spends = dir.join("spent_outpoints.json").display(),
+        schedules = dir.join("schedules.json").display(),
+        log = dir.join("service.log").display(),
+    )
+}
+
+/// A port nothing listens on right now, for the service to bind.
+fn free_port() -> Result<u16, String> {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .map_err(|e| format!("finding a free port: {e}"))
+}
+
+/// The finalized transaction of `psbt`, once the interpreter has checked
+/// every input's script and signatures.
+fn extract(endpoint: &str, psbt: &Psbt) -> Result<Transaction, String> {
+    psbt.extract(&Secp256k1::verification_only())
+        .map_err(|e| format!("{endpoint}: signed PSBT does not verify: {e}"))
+}
+
+/// The service under test, killed when dropped.
+struct Service(Child);
+
+impl Service {
+    fn start(config_path: &Path) -> Result<Self, String> {
+        let exe = std::env::current_exe().map_err(|e| format!("locating this binary: {e}"))?;
+        Command::new(exe)
+            .arg(config_path)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map(Service)
+            .map_err(|e| format!("starting the service: {e}"))
+    }
+
+    fn check_running(&mut self) -> Result<(), String> {
+        match self.0.try_wait() {
+            Ok(None) => Ok(()),
+            Ok(Some(status)) => Err(format!("service exited early: {status}")),
+            Err(e) => Err(format!("service: {e}")),
+        }
+    }
+
+    fn stop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+impl Drop for Service {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+struct Client {
+    http: reqwest::Client,
+    base: String,
+}
+
+impl Client {
+    async fn get(&self, path: &str) -> Result<StatusCode, String> {
+        let response = self
+            .http
+            .get(format!("{}{path}", self.base))
+            .send()
+            .await
+            .map_err(|e| format!("GET {path}: {e}"))?;
+        Ok(response.status())
+    }
+
+    async fn delete(&self, path: &str) -> Result<StatusCode, String> {
+        let response = self
+            .http
+            .delete(format!("{}{path}", self.base))
+            .send()
+            .await
+            .map_err(|e| format!("DELETE {path}: {e}"))?;
+        Ok(response.status())
+    }
+
+    async fn get_json(&self, path: &str) -> Result<(StatusCode, Value), String> {
+        let response = self
+            .http
+            .get(format!("{}{path}", self.base))
+            .send()
+            .await
+            .map_err(|e| format!("GET {path}: {e}"))?;
+        let status = response.status();
+        let body = response
+            .json()
+            .await
+            .map_err(|e| format!("GET {path}: {e}"))?;
+        Ok((status, body))
+    }
+
+    async fn post_json(
+        &self,
+        path: &str,
+        body: &Value,
+        request_id: Option<&str>,
+    ) -> Result<(StatusCode, Value), String> {
+        let mut request = self.http.post(format!("{}{path}", self.base)).json(body);
+        if let Some(request_id) = request_id {
+            request = request.header("x-request-id", request_id);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("POST {path}: {e}"))?;
+        let status = response.status();
+        let body = response
+            .json()
+            .await
+            .map_err(|e| format!("POST {path}: {e}"))?;
+        Ok((status, body))
+    }
+
+    async fn post_binary(
+        &self,
+        path: &str,
+        body: Vec<u8>,
+        request_id: &str,
+    ) -> Result<(StatusCode, Vec<u8>), String> {
+        let response = self
+            .http
+            .post(format!("{}{path}", self.base))
+            .header("content-type", crate::BINARY_PSBT)
+            .header("x-request-id", request_id)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| format!("POST {path}: {e}"))?;
+        let status = response.status();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| format!("POST {path}: {e}"))?;
+        Ok((status, body.to_vec()))
+    }
+}
+
+/// Enough of the Esplora API for the wallet sync: a regtest chain of the
+/// genesis block and block 1, which confirms `funding`.
+struct Esplora {
+    blocks: Vec<BlockHash>,
+    funding: Transaction,
+    /// Esplora script hashes of the outputs of `funding`.
+    funded: HashSet<String>,
+    /// `/blocks` requests served, one per sync pass.
+    scans: AtomicUsize,
+    /// Transactions posted to `/tx`.
+    broadcast: Mutex<Vec<Transaction>>,
+}
+
+impl Esplora {
+    fn new(funding: Transaction) -> Self {
+        let genesis = genesis_block(Network::Regtest).block_hash();
+        let block1 = BlockHash::from_byte_array(
+            sha256::Hash::hash(b"issue-service e2e block 1").to_byte_array(),
+        );
+        let funded = funding
+            .output
+            .iter()
+            .map(|output| sha256::Hash::hash(output.script_pubkey.as_bytes()).to_string())
+            .collect();
+        Esplora {
+            blocks: vec![genesis, block1],
+            funding,
+            funded,
+            scans: AtomicUsize::new(0),
+            broadcast: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Serves the mock on a free local port and returns its base URL.
+    async fn serve(self: &Arc<Self>) -> Result<String, String> {
+        let router = Router::new()
+            .route("/blocks", get(blocks))
+            .route("/blocks/tip/height", get(tip_height))
+            .route("/blocks/tip/hash", get(tip_hash))
+            .route("/block-height/{height}", get(block_hash))
+            .route("/scripthash/{hash}/txs", get(scripthash_txs))
+            .route("/scripthash/{hash}/txs/chain/{last_seen}", get(no_txs))
+            .route("/fee-estimates", get(fee_estimates))
+            .route("/tx", post(broadcast))
+            .route("/tx/{txid}", get(tx))
+            .route("/tx/{txid}/outspend/{vout}", get(unspent))
+            .with_state(self.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .map_err(|e| format!("binding the mock Esplora: {e}"))?;
+        let addr = listener
+            .local_addr()
+            .map_err(|e| format!("mock Esplora: {e}"))?;
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        Ok(format!("http://{addr}"))
+    }
+
+    fn tip(&self) -> u32 {
+        self.blocks.len() as u32 - 1
+    }
+
+    fn funding_json(&self) -> Value {
+        let tx = &self.funding;
+        json!({
+            "txid": tx.compute_txid(),
+            "version": tx.version.0,
+            "locktime": tx.lock_time.to_consensus_u32(),
+            "vin": tx.input.iter().map(|input| json!({
+                "txid": input.previous_output.txid,
+                "vout": input.previous_output.vout,
+                "prevout": null,
+                "scriptsig": input.script_sig.to_hex_string(),
+                "witness": input.witness.iter().map(|item| item.to_lower_hex_string()).collect::<Vec<_>>(),
+                "sequence": input.sequence.0,
+                "is_coinbase": false,
+            })).collect::<Vec<_>>(),
+            "vout": tx.output.iter().map(|output| json!({
+                "value": output.value.to_sat(),
+                "scriptpubkey": output.script_pubkey.to_hex_string(),
+            })).collect::<Vec<_>>(),
+            "size": consensus::serialize(tx).len(),
+            "weight": tx.weight().to_wu(),
+            "fee": 0,
+            "status": {
+                "confirmed": true,
+                "block_height": 1,
+                "block_hash": self.blocks[1],
+                "block_time": BLOCK_TIME + 600,
+            },
+        })
+    }
+}
+
+type Mock = State<Arc<Esplora>>;
+
+async fn blocks(State(esplora): Mock) -> Json<Value> {
+    esplora.scans.fetch_add(1, Ordering::AcqRel);
+    let summaries = esplora
+        .blocks
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(height, hash)| {
+            json!({
+                "id": hash,
+                "height": height,
+                "timestamp": BLOCK_TIME + 600 * height as u64,
+                "previousblockhash": height.checked_sub(1).map(|prev| esplora.blocks[prev]),
+                "merkle_root": "00".repeat(32),
+            })
+        })
+        .collect();
+    Json(Value::Array(summaries))
+}
+
+async fn tip_height(State(esplora): Mock) -> String {
+    esplora.tip().to_string()
+}
+
+async fn tip_hash(State(esplora): Mock) -> String {
+    esplora.blocks[esplora.tip() as usize].to_string()
+}
+
+async fn block_hash(
+    State(esplora): Mock,
+    UrlPath(height): UrlPath<usize>,
+) -> Result<String, StatusCode> {
+    esplora
+        .blocks
+        .get(height)
+        .map(ToString::to_string)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn scripthash_txs(State(esplora): Mock, UrlPath(hash): UrlPath<String>) -> Json<Value> {
+    if esplora.funded.contains(&hash) {
+        Json(json!([esplora.funding_json()]))
+    } else {
+        Json(json!([]))
+    }
+}
+
+async fn no_txs() -> Json<Value> {
+    Json(json!([]))
+}
+
+async fn tx(
+    State(esplora): Mock,
+    UrlPath(txid): UrlPath<String>,
+) -> Result<Json<Value>, StatusCode> {
+    if txid == esplora.funding.compute_txid().to_string() {
+        Ok(Json(esplora.funding_json()))
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+async fn fee_estimates() -> Json<Value> {
+    Json(json!({ "1": 20.0, "6": 8.0, "144": 1.5 }))
+}
+
+async fn broadcast(State(esplora): Mock, body: String) -> Result<String, StatusCode> {
+    let tx: Transaction =
+        consensus::encode::deserialize_hex(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let txid = tx.compute_txid().to_string();
+    esplora.broadcast.lock().expect("broadcast").push(tx);
+    Ok(txid)
+}
+
+async fn unspent() -> Json<Value> {
+    Json(json!({ "spent": false }))
+}
@@ -0,0 +1,180 @@
+use axum::{
+    extract::{rejection::JsonRejection, FromRequest, Request},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use tower_http::request_id::RequestId;
+
+use crate::config::ConfigError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("malformed request: {0}")]
+    MalformedRequest(String),
+    #[error("unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+    #[error("payload too large: {0}")]
+    PayloadTooLarge(String),
+    #[error("not acceptable: {0}")]
+    NotAcceptable(String),
+    #[error("invalid transaction: {0}")]
+    InvalidTransaction(String),
+    #[error("consignment rejected: {0}")]
+    ConsignmentRejected(String),
+    #[error("approval denied: {0}")]
+    ApprovalDenied(String),
+    #[error("signing closed: {0}")]
+    OutsideSigningWindow(String),
+    #[error("awaiting approvals: {} of {threshold} credentials approved", approved_by.len())]
+    QuorumPending {
+        approved_by: Vec<String>,
+        threshold: usize,
+    },
+    #[error("not found: {0}")]
+    NotFound(String),
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    #[error("config reload failed: {0}")]
+    InvalidConfig(#[from] ConfigError),
+    #[error("overloaded: {0}")]
+    Overloaded(String),
+    #[error("unavailable: {0}")]
+    Unavailable(String),
+    #[error("request timed out after {0:?}")]
+    Timeout(std::time::Duration),
+    #[error("internal error: {0}")]
+    Internal(String),
+}
+
+impl Error {
+    /// Stable, machine-readable identifier of the failure. Clients branch on
+    /// this rather than on the message, which may change between releases.
+    pub fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            MalformedRequest(_) => "malformed_request",
+            UnsupportedMediaType(_) => "unsupported_media_type",
+            PayloadTooLarge(_) => "payload_too_large",
+            NotAcceptable(_) => "not_acceptable",
+            InvalidTransaction(_) => "invalid_transaction",
+            ConsignmentRejected(_) => "consignment_rejected",
+            ApprovalDenied(_) => "approval_denied",
+            OutsideSigningWindow(_) => "outside_signing_window",
+            QuorumPending { .. } => "quorum_pending",
+            NotFound(_) => "not_found",
+            Unauthorized(_) => "unauthorized",
+            Forbidden(_) => "forbidden",
+            InvalidConfig(_) => "invalid_config",
+            Overloaded(_) => "overloaded",
+            Unavailable(_) => "unavailable",
+            Timeout(_) => "timeout",
+            Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        use Error::*;
+        match self {
+            MalformedRequest(_) | InvalidTransaction(_) => StatusCode::BAD_REQUEST,
+            UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+            ConsignmentRejected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApprovalDenied(_) | OutsideSigningWindow(_) | Forbidden(_) => StatusCode::FORBIDDEN,
+            QuorumPending { .. } => StatusCode::ACCEPTED,
+            NotFound(_) => StatusCode::NOT_FOUND,
+            Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Overloaded(_) | Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            InvalidConfig(_) | Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Structured context for the failure, if any.
+    pub fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            Error::QuorumPending {
+                approved_by,
+                threshold,
+            } => Some(serde_json::json!({
+                "approved_by": approved_by,
+                "threshold": threshold,
+            })),
+            _ => None,
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+            details: self.details(),
+            request_id: REQUEST_ID.try_with(Clone::clone).ok(),
+        }
+    }
+}
+
+tokio::task_local! {
+    /// `X-Request-Id` of the HTTP request being answered.
+    static REQUEST_ID: String;
+}
+
+/// Answers the request with its `X-Request-Id` at hand for any error body
+/// rendered on the way, so a failure can be matched to its log lines from
+/// the body alone.
+pub async fn with_request_id(request: Request, next: Next) -> Response {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .map(str::to_owned);
+    match request_id {
+        Some(request_id) => REQUEST_ID.scope(request_id, next.run(request)).await,
+        None => next.run(request).await,
+    }
+}
+
+/// Body of every error response.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ErrorResponse {
+    pub error: ErrorBody,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ErrorBody {
+    #[schema(example = "invalid_transaction")]
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub details: Option<serde_json::Value>,
+    /// The request's `X-Request-Id`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl axum::response::IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        tracing::error!(self = ?self, "error");
+        let body = ErrorResponse { error: self.body() };
+        (self.status(), axum::Json(body)).into_response()
+    }
+}
+
+impl From<JsonRejection> for Error {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection {
+            JsonRejection::MissingJsonContentType(e) => Error::UnsupportedMediaType(e.body_text()),
+            e => Error::MalformedRequest(e.body_text()),
+        }
+    }
+}
+
+/// `axum::Json` with rejections reported through the [`Error`] envelope.
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(Error))]
+pub struct ApiJson<T>(pub T);
@@ -0,0 +1,63 @@
+//! Conditional GETs, so clients polling a read endpoint only get a body
+//! when it changed.
+//!
+//! Every successful `GET` answer carries a weak `ETag` derived from its
+//! body. A request whose `If-None-Match` names it is answered `304 Not
+//! Modified` without one. The tag is computed after the handler ran, so it
+//! saves the transfer, not the work; it is weak because compression may
+//! change the bytes sent.
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use bitcoin::hashes::{sha256, Hash};
+
+pub async fn conditional(request: Request, next: Next) -> Response {
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return next.run(request).await;
+    }
+    let if_none_match = request.headers().get(header::IF_NONE_MATCH).cloned();
+    let response = next.run(request).await;
+    if response.status() != StatusCode::OK || response.headers().contains_key(header::ETAG) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::warn!("buffering a response for its etag: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let digest = sha256::Hash::hash(&body).to_byte_array();
+    let etag = format!("W/\"{}\"", hex::encode(&digest[..16]));
+    let etag = HeaderValue::from_str(&etag).expect("hex is a valid header value");
+    if if_none_match.is_some_and(|tags| matches(&tags, &etag)) {
+        let mut not_modified = StatusCode::NOT_MODIFIED.into_response();
+        not_modified.headers_mut().insert(header::ETAG, etag);
+        for name in [header::CACHE_CONTROL, header::VARY] {
+            if let Some(value) = parts.headers.get(&name) {
+                not_modified.headers_mut().insert(name, value.clone());
+            }
+        }
+        return not_modified;
+    }
+    parts.headers.insert(header::ETAG, etag);
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Whether `If-None-Match` value `tags` names `etag`, comparing weakly as
+/// RFC 9110 asks.
+fn matches(tags: &HeaderValue, etag: &HeaderValue) -> bool {
+    let Ok(tags) = tags.to_str() else {
+        return false;
+    };
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_owned();
+    let etag = opaque(etag.to_str().unwrap_or_default());
+    tags.split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
@@ -0,0 +1,52 @@
+//! In-process bus of service events, pushed to WebSocket subscribers.
+
+use bitcoin::Txid;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// Events buffered per subscriber before the slowest one starts missing them.
+const CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Signed {
+        txid: Txid,
+        request_id: Option<String>,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        dry_run: bool,
+    },
+    Rejected {
+        txid: Txid,
+        request_id: Option<String>,
+        error_code: &'static str,
+    },
+    ConfigReloaded {
+        restart_required: Vec<&'static str>,
+    },
+    WalletReloaded {
+        descriptors_changed: bool,
+    },
+}
+
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus {
+            sender: broadcast::channel(CAPACITY).0,
+        }
+    }
+
+    /// Delivers `event` to current subscribers; it is dropped if there are
+    /// none.
+    pub fn publish(&self, event: Event) {
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
@@ -0,0 +1,368 @@
+//! Signing policy on the fee a PSBT pays.
+//!
+//! With `[fee_check]` set, the fee is recomputed from the coins the PSBT
+//! spends instead of taken on faith, and PSBTs paying less than
+//! `min_fee_rate_sat_vb`, more than `max_fee_rate_sat_vb` or more than
+//! `max_fee_sat` are not signed. An input's `witness_utxo` is only its
+//! constructor's word: a segwit v0 signature commits to its own coin's
+//! amount alone, so a lie about another coin's amount can hide the real fee.
+//! Coins are held to their full previous transaction instead, from the
+//! PSBT's `non_witness_utxo` or fetched with `fetch_prevouts`, and taken on
+//! `witness_utxo` alone only with `sign_options.trust_witness_utxo`. A
+//! taproot coin's `witness_utxo` is enough when every input the wallet signs
+//! is taproot: BIP-341 signatures commit to every spent amount, so a lie
+//! then only yields signatures that do not verify. With a segwit v0 input of
+//! the wallet's among them, a foreign taproot coin is held to its previous
+//! transaction like any other.
+//!
+//! Inputs not yet signed are sized from their script type, and P2WSH and
+//! P2SH scripts by their largest satisfaction. Scripts of no known type are
+//! counted without a satisfaction, which overstates the rate.
+
+use bdk_wallet::miniscript::{Legacy, Miniscript, Segwitv0};
+use bitcoin::{
+    consensus::encode::VarInt, psbt::Input, Amount, OutPoint, Psbt, PublicKey, Script, Transaction,
+    TxOut, Txid, Weight,
+};
+
+use crate::{
+    config::{ChainConfig, FeeCheckConfig},
+    error::Error,
+    AppState,
+};
+
+/// Witness of a P2WPKH spend: item count, signature and key, each with its
+/// length.
+const P2WPKH_WITNESS: usize = 1 + 73 + 34;
+/// Witness of a taproot key path spend with the default sighash.
+const P2TR_KEY_WITNESS: usize = 1 + 65;
+/// Script sig of a P2PKH spend.
+const P2PKH_SCRIPT_SIG: usize = 73 + 34;
+
+/// Checks the fee `psbt` pays against `policy`, first adding the previous
+/// transactions it lacks and needs when `policy.fetch_prevouts`.
+pub async fn check(
+    state: &AppState,
+    psbt: &mut Psbt,
+    policy: &FeeCheckConfig,
+    trust_witness_utxo: bool,
+) -> Result<(), Error> {
+    let chain = match policy.fetch_prevouts {
+        true => state.config().chain.clone(),
+        false => None,
+    };
+    let trust_taproot = crate::prevouts::signs_taproot_only(&state.wallet(), psbt);
+    let mut spent = Vec::with_capacity(psbt.inputs.len());
+    for (index, (txin, input)) in psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .zip(&mut psbt.inputs)
+        .enumerate()
+    {
+        let trusted = trust_taproot
+            && input
+                .witness_utxo
+                .as_ref()
+                .is_some_and(|utxo| utxo.script_pubkey.is_p2tr());
+        if let (None, false, Some(chain)) = (&input.non_witness_utxo, trusted, &chain) {
+            let txid = txin.previous_output.txid;
+            input.non_witness_utxo = Some(previous_transaction(state, chain, index, &txid).await?);
+        }
+        spent.push(spent_output(
+            index,
+            txin.previous_output,
+            input,
+            trust_taproot,
+            trust_witness_utxo,
+        )?);
+    }
+
+    let input_value = total(spent.iter().map(|output| output.value))
+        .ok_or_else(|| Error::InvalidTransaction("fee check: input amounts overflow".into()))?;
+    let output_value = total(psbt.unsigned_tx.output.iter().map(|output| output.value))
+        .ok_or_else(|| Error::InvalidTransaction("fee check: output amounts overflow".into()))?;
+    let fee = input_value.checked_sub(output_value).ok_or_else(|| {
+        Error::InvalidTransaction(format!(
+            "fee check: outputs carry {} sat more than the coins spent",
+            (output_value - input_value).to_sat()
+        ))
+    })?;
+    let vsize = estimated_weight(psbt, &spent).to_vbytes_ceil();
+    let rate = fee.to_sat() as f64 / vsize.max(1) as f64;
+    tracing::debug!(fee = fee.to_sat(), vsize, rate, "fee checked");
+
+    if rate < policy.min_fee_rate_sat_vb {
+        return Err(Error::InvalidTransaction(format!(
+            "fee check: pays {rate:.2} sat/vB, below the {} sat/vB floor",
+            policy.min_fee_rate_sat_vb
+        )));
+    }
+    if rate > policy.max_fee_rate_sat_vb {
+        return Err(Error::InvalidTransaction(format!(
+            "fee check: pays {rate:.2} sat/vB, above the {} sat/vB ceiling",
+            policy.max_fee_rate_sat_vb
+        )));
+    }
+    if let Some(max) = policy.max_fee_sat {
+        if fee.to_sat() > max {
+            return Err(Error::InvalidTransaction(format!(
+                "fee check: pays {} sat, above the {max} sat ceiling",
+                fee.to_sat()
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn total(mut amounts: impl Iterator<Item = Amount>) -> Option<Amount> {
+    amounts.try_fold(Amount::ZERO, Amount::checked_add)
+}
+
+/// `txid` from the wallet's own transactions, or else from `chain`.
+async fn previous_transaction(
+    state: &AppState,
+    chain: &ChainConfig,
+    index: usize,
+    txid: &Txid,
+) -> Result<Transaction, Error> {
+    match crate::prevouts::previous_transaction(state, chain, txid).await {
+        Ok(Some(tx)) => Ok(tx),
+        Ok(None) => Err(Error::InvalidTransaction(format!(
+            "fee check: input {index} spends {txid}, which the chain backend does not know"
+        ))),
+        Err(e) => Err(Error::Unavailable(format!(
+            "fee check: fetching {txid} failed: {e}"
+        ))),
+    }
+}
+
+/// The coin `input` spends, refusing UTXO data that is missing, disagrees
+/// with itself or is only `witness_utxo` for a coin that needs more.
+/// `trust_taproot` is set when the wallet signs taproot inputs only.
+fn spent_output(
+    index: usize,
+    outpoint: OutPoint,
+    input: &Input,
+    trust_taproot: bool,
+    trust_witness_utxo: bool,
+) -> Result<TxOut, Error> {
+    let invalid =
+        |reason: String| Error::InvalidTransaction(format!("fee check: input {index}: {reason}"));
+    if let Some(tx) = &input.non_witness_utxo {
+        let txid = tx.compute_txid();
+        if txid != outpoint.txid {
+            return Err(invalid(format!(
+                "non_witness_utxo is {txid}, not the spent {}",
+                outpoint.txid
+            )));
+        }
+        let Some(output) = tx.output.get(outpoint.vout as usize) else {
+            return Err(invalid(format!(
+                "non_witness_utxo has no output {}",
+                outpoint.vout
+            )));
+        };
+        if input
+            .witness_utxo
+            .as_ref()
+            .is_some_and(|utxo| utxo != output)
+        {
+            return Err(invalid(
+                "witness_utxo disagrees with the output non_witness_utxo has".into(),
+            ));
+        }
+        return Ok(output.clone());
+    }
+    match &input.witness_utxo {
+        Some(utxo) if (trust_taproot && utxo.script_pubkey.is_p2tr()) || trust_witness_utxo => {
+            Ok(utxo.clone())
+        }
+        Some(utxo) if utxo.script_pubkey.is_p2tr() => Err(invalid(
+            "only witness_utxo, which is not trusted for a taproot coin while the wallet signs \
+             segwit v0 inputs; add non_witness_utxo"
+                .into(),
+        )),
+        Some(_) => Err(invalid(
+            "only witness_utxo, which is not trusted for a segwit v0 coin; add non_witness_utxo"
+                .into(),
+        )),
+        None => Err(invalid("no UTXO data".into())),
+    }
+}
+
+/// The weight of `psbt` once signed, as far as its inputs tell.
+fn estimated_weight(psbt: &Psbt, spent: &[TxOut]) -> Weight {
+    let mut weight = psbt.unsigned_tx.weight();
+    let mut segwit = false;
+    for (input, output) in psbt.inputs.iter().zip(spent) {
+        let (script_sig, witness) = satisfaction_size(input, &output.script_pubkey);
+        weight += Weight::from_non_witness_data_size(script_sig as u64);
+        weight += Weight::from_witness_data_size(witness as u64);
+        segwit |= witness > 0;
+    }
+    if segwit {
+        // The segwit marker and flag.
+        weight += Weight::from_witness_data_size(2);
+    }
+    weight
+}
+
+/// Bytes of script sig and of witness that spending `script` takes.
+fn satisfaction_size(input: &Input, script: &Script) -> (usize, usize) {
+    if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+        let script_sig = input.final_script_sig.as_ref().map_or(0, |s| s.len());
+        let witness = input.final_script_witness.as_ref().map_or(0, |w| w.size());
+        return (script_sig, witness);
+    }
+    if script.is_p2wpkh() {
+        (0, P2WPKH_WITNESS)
+    } else if script.is_p2tr() {
+        (0, P2TR_KEY_WITNESS)
+    } else if script.is_p2pkh() {
+        (P2PKH_SCRIPT_SIG, 0)
+    } else if script.is_p2wsh() {
+        (0, p2wsh_witness(input))
+    } else if script.is_p2sh() {
+        let Some(redeem) = &input.redeem_script else {
+            return (0, 0);
+        };
+        let script_sig = push_size(redeem.len());
+        if redeem.is_p2wpkh() {
+            (script_sig, P2WPKH_WITNESS)
+        } else if redeem.is_p2wsh() {
+            (script_sig, p2wsh_witness(input))
+        } else {
+            let satisfaction = Miniscript::<PublicKey, Legacy>::parse_insane(redeem)
+                .and_then(|ms| ms.max_satisfaction_size())
+                .unwrap_or(0);
+            (script_sig + satisfaction, 0)
+        }
+    } else {
+        (0, 0)
+    }
+}
+
+fn p2wsh_witness(input: &Input) -> usize {
+    let Some(script) = &input.witness_script else {
+        return 0;
+    };
+    let satisfaction = Miniscript::<PublicKey, Segwitv0>::parse_insane(script)
+        .and_then(|ms| ms.max_satisfaction_size())
+        .unwrap_or(0);
+    // The item count, then the script itself as the last item.
+    1 + satisfaction + VarInt(script.len() as u64).size() + script.len()
+}
+
+/// Bytes of the script sig push of `len` bytes.
+fn push_size(len: usize) -> usize {
+    len + match len {
+        0..=75 => 1,
+        76..=255 => 2,
+        _ => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{coin, ours, psbt, script, state, taproot};
+
+    async fn checked(psbt: &mut Psbt, trust_witness_utxo: bool) -> Result<(), String> {
+        let policy = FeeCheckConfig {
+            max_fee_rate_sat_vb: 100.0,
+            max_fee_sat: Some(20_000),
+            ..Default::default()
+        };
+        check(&*state("").await, psbt, &policy, trust_witness_utxo)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    #[tokio::test]
+    async fn trusts_taproot_witness_utxo_when_the_wallet_signs_taproot_only() {
+        let (mine, theirs) = (coin(taproot(1), 100_000), coin(taproot(2), 1_000_000));
+        let mut spend = psbt(&[&mine, &theirs], &[(taproot(3), 1_095_000)]);
+        ours(&mut spend.inputs[0]);
+        checked(&mut spend, false).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn holds_foreign_taproot_coins_to_their_transaction_beside_segwit_v0() {
+        let mine = coin(script(0), 100_000);
+        let theirs = coin(taproot(2), 1_000_000);
+        let spend = |claimed: u64| {
+            let mut spend = psbt(&[&mine, &theirs], &[(taproot(3), 95_000 + claimed)]);
+            ours(&mut spend.inputs[0]);
+            spend.inputs[0].non_witness_utxo = Some(mine.clone());
+            spend.inputs[1].witness_utxo.as_mut().unwrap().value = Amount::from_sat(claimed);
+            spend
+        };
+
+        // Claiming 1,000 sat passes a 1,004,000 sat fee off as 5,000.
+        let error = checked(&mut spend(1_000), false).await.unwrap_err();
+        assert!(error.contains("input 1: only witness_utxo"), "{error}");
+        assert!(error.contains("taproot"), "{error}");
+
+        let mut lying = spend(1_000);
+        lying.inputs[1].non_witness_utxo = Some(theirs.clone());
+        let error = checked(&mut lying, false).await.unwrap_err();
+        assert!(error.contains("witness_utxo disagrees"), "{error}");
+
+        let mut honest = spend(1_000_000);
+        honest.inputs[1].non_witness_utxo = Some(theirs.clone());
+        checked(&mut honest, false).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn holds_segwit_v0_coins_to_their_transaction_unless_trusted() {
+        let mine = coin(script(0), 100_000);
+        let mut spend = psbt(&[&mine], &[(taproot(3), 95_000)]);
+        ours(&mut spend.inputs[0]);
+        let error = checked(&mut spend.clone(), false).await.unwrap_err();
+        assert!(
+            error.contains("not trusted for a segwit v0 coin"),
+            "{error}"
+        );
+        checked(&mut spend.clone(), true).await.unwrap();
+
+        spend.inputs[0].witness_utxo = None;
+        let error = checked(&mut spend.clone(), true).await.unwrap_err();
+        assert!(error.contains("no UTXO data"), "{error}");
+        let mut other = coin(script(1), 100_000);
+        spend.inputs[0].non_witness_utxo = Some(other.clone());
+        let error = checked(&mut spend.clone(), true).await.unwrap_err();
+        assert!(error.contains("not the spent"), "{error}");
+        other.output.clear();
+        spend.unsigned_tx.input[0].previous_output.txid = other.compute_txid();
+        spend.inputs[0].non_witness_utxo = Some(other);
+        let error = checked(&mut spend, true).await.unwrap_err();
+        assert!(error.contains("has no output 0"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn refuses_fees_outside_the_limits() {
+        let mine = coin(script(0), 100_000);
+        for (paid, refusal) in [
+            (100_001, "more than the coins spent"),
+            (99_990, "below the 1 sat/vB floor"),
+            (85_000, "above the 100 sat/vB ceiling"),
+            (0, "above the 100 sat/vB ceiling"),
+        ] {
+            let mut spend = psbt(&[&mine], &[(taproot(3), paid)]);
+            spend.inputs[0].non_witness_utxo = Some(mine.clone());
+            let error = checked(&mut spend, false).await.unwrap_err();
+            assert!(error.contains(refusal), "{paid}: {error}");
+        }
+        let big = coin(script(0), 10_000_000);
+        let mut spend = psbt(&[&big], &[(taproot(3), 10_000_000 - 25_000)]);
+        spend.inputs[0].non_witness_utxo = Some(big.clone());
+        spend.unsigned_tx.output.extend((0..20).map(|_| TxOut {
+            value: Amount::ZERO,
+            script_pubkey: taproot(4),
+        }));
+        spend.outputs.resize(21, Default::default());
+        let error = checked(&mut spend, false).await.unwrap_err();
+        assert!(error.contains("above the 20000 sat ceiling"), "{error}");
+    }
+}
@@ -0,0 +1,192 @@
+//! Offline signing through a pair of directories, for an air-gapped signer
+//! fed by SD card or USB stick.
+//!
+//! `issue-service file-drop <config>` serves no requests. It polls
+//! `file_drop.input_dir` for `.psbt` files, binary or base64, and signs each
+//! one through the same policy checks and audit log as `POST /sign_psbt`,
+//! with the file name as request id. The signed PSBT is written to
+//! `file_drop.output_dir` as `<name>-signed.psbt`, in the encoding it came
+//! in, or as `<name>-dry-run.psbt` unsigned under `signing.dry_run`; a
+//! rejected one leaves `<name>.error` with the reason instead. Either way
+//! the input file is then removed.
+
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use bitcoin::Psbt;
+
+use crate::{
+    config::{Config, FileDropConfig},
+    context::RequestContext,
+    AppState,
+};
+
+/// Caller recorded in the audit log for signed files.
+const CALLER: &str = "file-drop";
+const BINARY_MAGIC: &[u8] = b"psbt\xff";
+/// Files modified more recently than this may still be being written.
+const SETTLE: Duration = Duration::from_secs(1);
+
+pub async fn run(config: Config, config_path: PathBuf) -> ExitCode {
+    let Some(dirs) = config.file_drop.clone() else {
+        tracing::error!("file-drop mode needs a [file_drop] section");
+        return ExitCode::FAILURE;
+    };
+    if let Err(e) = check_dirs(&dirs) {
+        tracing::error!("{e}");
+        return ExitCode::FAILURE;
+    }
+    let state = match AppState::init(config, config_path).await {
+        Ok(state) => Arc::new(state),
+        Err(e) => {
+            tracing::error!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    // Nobody watches a readiness probe here, so refuse to start instead.
+    if state.self_test.is_err() {
+        return ExitCode::FAILURE;
+    }
+    #[cfg(unix)]
+    {
+        tokio::spawn(crate::reload_on_sighup(state.clone()));
+        crate::systemd::notify_ready();
+        crate::systemd::spawn_watchdog();
+    }
+    tracing::info!(
+        input = %dirs.input_dir.display(),
+        output = %dirs.output_dir.display(),
+        "watching for PSBT files"
+    );
+    let interval = Duration::from_millis(dirs.poll_interval_ms.max(1));
+    loop {
+        if let Err(e) = scan(&state, &dirs).await {
+            tracing::error!(input = %dirs.input_dir.display(), "cannot scan for PSBT files: {e}");
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Checks that both directories exist, for startup and `check-config`.
+pub fn check_dirs(dirs: &FileDropConfig) -> Result<(), String> {
+    for (field, dir) in [
+        ("file_drop.input_dir", &dirs.input_dir),
+        ("file_drop.output_dir", &dirs.output_dir),
+    ] {
+        if !dir.is_dir() {
+            return Err(format!("{field}: {} is not a directory", dir.display()));
+        }
+    }
+    Ok(())
+}
+
+async fn scan(state: &Arc<AppState>, dirs: &FileDropConfig) -> std::io::Result<()> {
+    let mut pending = Vec::new();
+    for entry in std::fs::read_dir(&dirs.input_dir)? {
+        let path = entry?.path();
+        let is_psbt = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("psbt"));
+        if !is_psbt {
+            continue;
+        }
+        let settled = std::fs::metadata(&path)
+            .and_then(|meta| meta.modified())
+            .is_ok_and(|modified| {
+                SystemTime::now()
+                    .duration_since(modified)
+                    .is_ok_and(|age| age >= SETTLE)
+            });
+        if settled && path.is_file() {
+            pending.push(path);
+        }
+    }
+    pending.sort();
+    for path in pending {
+        if let Err(e) = process(state, dirs, &path).await {
+            tracing::error!(file = %path.display(), "cannot process PSBT file: {e}");
+        }
+    }
+    Ok(())
+}
+
+async fn process(state: &Arc<AppState>, dirs: &FileDropConfig, path: &Path) -> std::io::Result<()> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let contents = std::fs::read(path)?;
+    let binary = contents.starts_with(BINARY_MAGIC);
+
+    let outcome = match parse(&contents, binary) {
+        Ok(psbt) => {
+            let ctx = RequestContext {
+                request_id: Some(name.clone()),
+                caller: Some(CALLER.to_owned()),
+                metadata: Default::default(),
+                bearer: None,
+                internal: false,
+            };
+            crate::sign_and_record(state, &ctx, psbt.into())
+                .await
+                .map_err(|e| e.to_string())
+        }
+        Err(e) => Err(e),
+    };
+    match outcome {
+        Ok(signed) => {
+            let psbt = &signed.psbt;
+            let contents = if binary {
+                psbt.serialize()
+            } else {
+                format!("{psbt}\n").into_bytes()
+            };
+            let suffix = if signed.dry_run { "dry-run" } else { "signed" };
+            write_atomic(
+                &dirs.output_dir.join(format!("{stem}-{suffix}.psbt")),
+                &contents,
+            )?;
+            tracing::info!(file = name, dry_run = signed.dry_run, "signed PSBT file");
+        }
+        Err(reason) => {
+            write_atomic(
+                &dirs.output_dir.join(format!("{stem}.error")),
+                format!("{reason}\n").as_bytes(),
+            )?;
+            tracing::warn!(file = name, "rejected PSBT file: {reason}");
+        }
+    }
+    std::fs::remove_file(path)
+}
+
+fn parse(contents: &[u8], binary: bool) -> Result<Psbt, String> {
+    let psbt = if binary {
+        Psbt::deserialize(contents).map_err(|e| e.to_string())
+    } else {
+        std::str::from_utf8(contents)
+            .map_err(|e| e.to_string())
+            .and_then(|text| Psbt::from_str(text.trim()).map_err(|e| e.to_string()))
+    };
+    psbt.map_err(|e| format!("malformed request: invalid psbt: {e}"))
+}
+
+/// Writes through a hidden temporary file, so a reader of the output
+/// directory never sees a partial file under the final name.
+fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let tmp = path.with_file_name(format!(".{file_name}.tmp"));
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)
+}
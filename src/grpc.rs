@@ -0,0 +1,169 @@
+//! gRPC interface, generated from `proto/issue_service.proto`.
+//!
+//! Handlers share [`AppState`] with the HTTP routes and go through the same
+//! signing path, so concurrency limits, timeouts and the audit log apply
+//! identically to both interfaces.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use bdk_wallet::KeychainKind;
+use bitcoin::{
+    base64::{engine::general_purpose::STANDARD as BASE64, Engine},
+    Psbt,
+};
+use tonic::{Request, Response, Status};
+
+use crate::{
+    auth::Scope,
+    context::{BearerToken, RequestContext},
+    error::Error,
+    priority::Priority,
+    rgb::Consignment,
+    timeout::RouteTimeout,
+    AppState, SignRequest, SIGNERS,
+};
+
+mod pb {
+    tonic::include_proto!("issue_service.v1");
+}
+
+use pb::{
+    issue_service_server::{IssueService, IssueServiceServer},
+    sign_psbt_request::Consignment::{ConsignmentData, ConsignmentId},
+};
+
+struct Service {
+    state: Arc<AppState>,
+}
+
+/// Serves the gRPC interface on `addr` until the process exits.
+pub async fn serve(state: Arc<AppState>, addr: SocketAddr) -> std::io::Result<()> {
+    tonic::transport::Server::builder()
+        .layer(tower_http::trace::TraceLayer::new_for_grpc())
+        .add_service(IssueServiceServer::new(Service { state }))
+        .serve(addr)
+        .await
+        .map_err(std::io::Error::other)
+}
+
+/// Who made a gRPC call, from its `x-request-id` metadata and peer address.
+fn request_context<T>(request: &Request<T>) -> RequestContext {
+    RequestContext {
+        request_id: request
+            .metadata()
+            .get("x-request-id")
+            .and_then(|id| id.to_str().ok())
+            .map(str::to_owned),
+        caller: request.remote_addr().map(|addr| addr.ip().to_string()),
+        bearer: request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(BearerToken::parse),
+        ..Default::default()
+    }
+}
+
+#[tonic::async_trait]
+impl IssueService for Service {
+    async fn sign_psbt(
+        &self,
+        request: Request<pb::SignPsbtRequest>,
+    ) -> Result<Response<pb::SignPsbtResponse>, Status> {
+        let ctx = request_context(&request);
+        crate::auth::authorize(&self.state, ctx.bearer.as_ref(), &[Scope::Sign])?;
+        let request = request.into_inner();
+        let psbt = Psbt::deserialize(&request.psbt)
+            .map_err(|e| Error::MalformedRequest(format!("invalid psbt: {e}")))?;
+        let priority = match request.priority() {
+            pb::Priority::Interactive => Priority::Interactive,
+            pb::Priority::Batch => Priority::Batch,
+        };
+        let consignment = request.consignment.map(|consignment| match consignment {
+            ConsignmentData(data) => Consignment::Data(BASE64.encode(data)),
+            ConsignmentId(id) => Consignment::Id(id),
+        });
+
+        let limit = RouteTimeout::Sign.duration(&self.state);
+        let signed = tokio::time::timeout(
+            limit,
+            crate::sign_and_record(
+                &self.state,
+                &ctx,
+                SignRequest {
+                    psbt,
+                    consignment,
+                    metadata: request.metadata.into_iter().collect(),
+                    replace: request.replace,
+                    priority,
+                    sign_options: None,
+                    collaborative: request.collaborative,
+                    prevouts: Vec::new(),
+                    pairings: Vec::new(),
+                },
+            ),
+        )
+        .await
+        .map_err(|_| Error::Timeout(limit))??;
+        let mut response = Response::new(pb::SignPsbtResponse {
+            psbt: signed.psbt.serialize(),
+            dry_run: signed.dry_run,
+            cached: signed.cached,
+        });
+        if let Some(attestation) = &signed.attestation {
+            let json = serde_json::to_string(attestation).expect("attestation is JSON");
+            if let Ok(value) = json.parse() {
+                response.metadata_mut().insert("x-attestation", value);
+            }
+        }
+        Ok(response)
+    }
+
+    async fn get_wallet_info(
+        &self,
+        request: Request<pb::GetWalletInfoRequest>,
+    ) -> Result<Response<pb::WalletInfo>, Status> {
+        let ctx = request_context(&request);
+        crate::auth::authorize(&self.state, ctx.bearer.as_ref(), &[Scope::Read])?;
+        let wallet = self.state.wallet();
+        Ok(Response::new(pb::WalletInfo {
+            network: self.state.config().network.to_string(),
+            descriptor: wallet.public_descriptor(KeychainKind::External).to_string(),
+            first_address: wallet
+                .peek_address(KeychainKind::External, 0)
+                .address
+                .to_string(),
+            signers: SIGNERS.iter().map(|s| s.to_string()).collect(),
+        }))
+    }
+}
+
+impl From<Error> for Status {
+    fn from(e: Error) -> Self {
+        tracing::error!(error = ?e, "grpc error");
+        use Error::*;
+        let code = match &e {
+            MalformedRequest(_)
+            | UnsupportedMediaType(_)
+            | NotAcceptable(_)
+            | InvalidTransaction(_) => tonic::Code::InvalidArgument,
+            ConsignmentRejected(_) => tonic::Code::FailedPrecondition,
+            ApprovalDenied(_) | OutsideSigningWindow(_) | Forbidden(_) => {
+                tonic::Code::PermissionDenied
+            }
+            QuorumPending { .. } => tonic::Code::FailedPrecondition,
+            NotFound(_) => tonic::Code::NotFound,
+            Unauthorized(_) => tonic::Code::Unauthenticated,
+            PayloadTooLarge(_) | Overloaded(_) => tonic::Code::ResourceExhausted,
+            Unavailable(_) => tonic::Code::Unavailable,
+            Timeout(_) => tonic::Code::DeadlineExceeded,
+            InvalidConfig(_) | Internal(_) => tonic::Code::Internal,
+        };
+        let mut status = Status::new(code, e.to_string());
+        // Same stable identifier as the HTTP error envelope.
+        status
+            .metadata_mut()
+            .insert("x-error-code", e.code().parse().expect("ascii error code"));
+        status
+    }
+}
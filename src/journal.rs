@@ -0,0 +1,350 @@
+//! Journal of signing requests, for callers that must know what became of
+//! a request across a crash or restart.
+//!
+//! With `[journal]` set, every signing request with a request id is
+//! recorded as in flight before it is decided and with its outcome after.
+//! A request still in flight when the service stops, or whose caller goes
+//! away before it is decided, is marked `interrupted` instead of being left
+//! unknown. `GET /sign_requests/{id}` answers what became of a request, and
+//! submitting a signed request's id again returns the PSBT it was signed
+//! into without signing anything. Requests that failed or were interrupted
+//! can be retried under the same id. The quorum's approvals are kept in the
+//! same document, so a restart does not forget them either.
+//!
+//! Request ids are scoped to their [`owner`]: one caller's id never
+//! answers another's request, and only the owner or a credential with the
+//! `sign` scope is shown a signed PSBT.
+
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use bitcoin::{
+    hashes::{sha256, Hash},
+    Psbt, Txid,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth, config::JournalConfig, context::RequestContext, error::Error, storage::Storage, AppState,
+    SignResponse,
+};
+
+pub struct Journal {
+    storage: Arc<dyn Storage>,
+    name: String,
+    retention: chrono::Duration,
+    document: Mutex<Document>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Document {
+    /// By [`key`].
+    requests: BTreeMap<String, Entry>,
+    #[serde(default)]
+    approvals: crate::quorum::Snapshot,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub request_id: String,
+    pub txid: Txid,
+    pub started_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum Outcome {
+    InFlight,
+    Signed {
+        /// Base64-encoded signed PSBT, left out for callers who may not
+        /// see it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        psbt: Option<String>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        dry_run: bool,
+    },
+    Failed {
+        code: String,
+        message: String,
+    },
+    /// The service stopped or the caller went away before the request was
+    /// decided. Its signatures may or may not have been produced.
+    Interrupted,
+}
+
+pub enum Begun<'a> {
+    Started(InFlight<'a>),
+    /// The request was signed before; this is its answer again.
+    Answered(SignResponse),
+}
+
+/// A request recorded as in flight. Dropped without [`InFlight::finish`],
+/// it is recorded as interrupted.
+pub struct InFlight<'a> {
+    journal: &'a Journal,
+    key: String,
+    request_id: String,
+    finished: bool,
+}
+
+/// Who a request belongs to: the bearer token it presented, by its hash,
+/// or without one the address it came from.
+pub fn owner(ctx: &RequestContext) -> String {
+    match (&ctx.bearer, &ctx.caller) {
+        (Some(token), _) => format!("token:{}", sha256::Hash::hash(token.0.as_bytes())),
+        (None, Some(caller)) => format!("caller:{caller}"),
+        (None, None) => "anonymous".into(),
+    }
+}
+
+/// Where `owner`'s request `request_id` is kept in the document.
+fn key(owner: &str, request_id: &str) -> String {
+    format!("{owner} {request_id}")
+}
+
+impl Journal {
+    pub fn open(storage: Arc<dyn Storage>, config: &JournalConfig) -> Result<Self, String> {
+        let name = crate::storage::name(&config.path);
+        let document = storage
+            .read(&name)
+            .map_err(|e| e.to_string())
+            .and_then(|stored| {
+                stored
+                    .map(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+                    .transpose()
+            })
+            .map_err(|e| format!("{name}: {e}"))?
+            .unwrap_or_default();
+        Ok(Journal {
+            storage,
+            name,
+            retention: chrono::Duration::seconds(config.retention_secs as i64),
+            document: Mutex::new(document),
+        })
+    }
+
+    /// Marks the requests a previous run left in flight as interrupted.
+    pub fn recover(&self) -> Result<(), String> {
+        let mut document = self.lock();
+        let now = Utc::now();
+        let mut interrupted = 0;
+        for entry in document.requests.values_mut() {
+            if let Outcome::InFlight = entry.outcome {
+                entry.outcome = Outcome::Interrupted;
+                entry.finished_at = Some(now);
+                interrupted += 1;
+            }
+        }
+        if interrupted > 0 {
+            tracing::warn!(
+                interrupted,
+                "signing requests were interrupted by a restart"
+            );
+            self.save(&document)
+                .map_err(|e| format!("{}: {e}", self.name))?;
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Document> {
+        self.document.lock().expect("journal")
+    }
+
+    fn save(&self, document: &Document) -> std::io::Result<()> {
+        self.storage
+            .write(&self.name, &serde_json::to_vec_pretty(document)?)
+    }
+
+    /// Records `owner`'s `request_id` as in flight for `txid`, or returns
+    /// the answer it was already given if it was signed.
+    pub fn begin(&self, owner: &str, request_id: &str, txid: Txid) -> Result<Begun<'_>, Error> {
+        let key = key(owner, request_id);
+        let mut document = self.lock();
+        let expired = Utc::now() - self.retention;
+        document
+            .requests
+            .retain(|_, entry| entry.finished_at.map_or(true, |at| at > expired));
+        if let Some(entry) = document.requests.get(&key) {
+            if entry.txid != txid {
+                return Err(Error::MalformedRequest(format!(
+                    "request id {request_id} was used for {}",
+                    entry.txid
+                )));
+            }
+            match &entry.outcome {
+                Outcome::InFlight => {
+                    return Err(Error::Unavailable(format!(
+                        "request {request_id} is still being decided"
+                    )))
+                }
+                Outcome::Signed { psbt, dry_run } => {
+                    let psbt = psbt
+                        .as_deref()
+                        .ok_or_else(|| "no PSBT".to_owned())
+                        .and_then(|psbt| Psbt::from_str(psbt).map_err(|e| e.to_string()))
+                        .map_err(|e| {
+                            Error::Internal(format!("journal: request {request_id}: {e}"))
+                        })?;
+                    tracing::info!(request_id, %txid, "answered from the journal");
+                    return Ok(Begun::Answered(SignResponse {
+                        psbt,
+                        dry_run: *dry_run,
+                        cached: true,
+                        missing_signatures: Vec::new(),
+                        attestation: None,
+                    }));
+                }
+                Outcome::Failed { .. } | Outcome::Interrupted => {}
+            }
+        }
+        let previous = document.requests.insert(
+            key.clone(),
+            Entry {
+                request_id: request_id.to_owned(),
+                txid,
+                started_at: Utc::now(),
+                finished_at: None,
+                outcome: Outcome::InFlight,
+            },
+        );
+        if let Err(e) = self.save(&document) {
+            match previous {
+                Some(previous) => document.requests.insert(key, previous),
+                None => document.requests.remove(&key),
+            };
+            tracing::error!(
+                request_id,
+                "refusing to sign, saving the journal failed: {e}"
+            );
+            return Err(Error::Internal("saving the journal failed".into()));
+        }
+        Ok(Begun::Started(InFlight {
+            journal: self,
+            key,
+            request_id: request_id.to_owned(),
+            finished: false,
+        }))
+    }
+
+    fn record(&self, key: &str, outcome: Outcome) -> std::io::Result<()> {
+        let mut document = self.lock();
+        let Some(entry) = document.requests.get_mut(key) else {
+            return Ok(());
+        };
+        entry.outcome = outcome;
+        entry.finished_at = Some(Utc::now());
+        self.save(&document)
+    }
+
+    /// What became of `request_id`: the request `owner` made under it, or
+    /// else the latest anyone did, with whether it is `owner`'s.
+    pub fn entry(&self, owner: &str, request_id: &str) -> Option<(Entry, bool)> {
+        let document = self.lock();
+        if let Some(entry) = document.requests.get(&key(owner, request_id)) {
+            return Some((entry.clone(), true));
+        }
+        document
+            .requests
+            .values()
+            .filter(|entry| entry.request_id == request_id)
+            .max_by_key(|entry| entry.started_at)
+            .map(|entry| (entry.clone(), false))
+    }
+
+    /// The quorum's approvals as last saved.
+    pub fn approvals(&self) -> crate::quorum::Snapshot {
+        self.lock().approvals.clone()
+    }
+
+    pub fn save_approvals(&self, approvals: crate::quorum::Snapshot) -> std::io::Result<()> {
+        let mut document = self.lock();
+        if document.approvals == approvals {
+            return Ok(());
+        }
+        document.approvals = approvals;
+        self.save(&document)
+    }
+}
+
+impl InFlight<'_> {
+    /// Records the outcome. A signed PSBT is withheld if it cannot be, so
+    /// the journal never shows a request as failed that was signed.
+    pub fn finish(mut self, result: Result<SignResponse, Error>) -> Result<SignResponse, Error> {
+        self.finished = true;
+        let outcome = match &result {
+            Ok(signed) => Outcome::Signed {
+                psbt: Some(signed.psbt.to_string()),
+                dry_run: signed.dry_run,
+            },
+            Err(e) => Outcome::Failed {
+                code: e.code().to_owned(),
+                message: e.to_string(),
+            },
+        };
+        match self.journal.record(&self.key, outcome) {
+            Ok(()) => result,
+            Err(e) if result.is_ok() => {
+                tracing::error!(
+                    request_id = self.request_id,
+                    "withholding signatures, saving the journal failed: {e}"
+                );
+                Err(Error::Internal("saving the journal failed".into()))
+            }
+            Err(e) => {
+                tracing::error!(
+                    request_id = self.request_id,
+                    "saving the journal failed: {e}"
+                );
+                result
+            }
+        }
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        tracing::warn!(request_id = self.request_id, "signing request interrupted");
+        if let Err(e) = self.journal.record(&self.key, Outcome::Interrupted) {
+            tracing::error!(
+                request_id = self.request_id,
+                "saving the journal failed: {e}"
+            );
+        }
+    }
+}
+
+pub async fn get(
+    State(state): State<Arc<AppState>>,
+    ctx: RequestContext,
+    Path(request_id): Path<String>,
+) -> Result<Json<Entry>, Error> {
+    let journal = state
+        .journal
+        .as_ref()
+        .ok_or_else(|| Error::NotFound("the journal is not configured".into()))?;
+    let (mut entry, own) = journal
+        .entry(&owner(&ctx), &request_id)
+        .ok_or_else(|| Error::NotFound(format!("signing request {request_id}")))?;
+    // Read access is enough for the state; the signatures are not.
+    if !own && !auth::grants(&state, ctx.bearer.as_ref(), auth::Scope::Sign) {
+        if let Outcome::Signed { psbt, .. } = &mut entry.outcome {
+            *psbt = None;
+        }
+    }
+    Ok(Json(entry))
+}
@@ -0,0 +1,378 @@
+//! Cache of derived child keys for the wallet's extended private keys.
+//!
+//! BDK derives the child key of every input from the xprv on each signing
+//! call. [`install`] swaps each xprv signer of a wallet for a
+//! [`CachingSigner`] that keeps the keys it derived in a bounded LRU, keyed
+//! by derivation path below the xprv. Evicted and dropped keys are erased,
+//! but the copy each input is signed with is handed to BDK's signer, which
+//! drops it without erasing it.
+//! The first keys of each xprv can be derived up front, so the first
+//! requests after a start don't pay for derivation.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Instant,
+};
+
+use bdk_wallet::{
+    descriptor::{Descriptor, DescriptorPublicKey},
+    keys::{DescriptorSecretKey, KeyMap},
+    miniscript::descriptor::{DescriptorXKey, SinglePubKey, Wildcard},
+    signer::{
+        InputSigner, SignerCommon, SignerContext, SignerError, SignerId, SignerOrdering,
+        SignerWrapper,
+    },
+    KeychainKind, SignOptions, Wallet,
+};
+use bitcoin::{
+    bip32::{ChildNumber, DerivationPath, Xpriv},
+    key::Secp256k1,
+    secp256k1::{All, PublicKey, SecretKey},
+    PrivateKey, Psbt, WitnessVersion, XOnlyPublicKey,
+};
+
+use crate::metrics::METRICS;
+
+/// Replaces the xprv signers of `wallet` with caching ones holding up to
+/// `capacity` keys each, with the keys at the first `prederive` child
+/// indices already in the cache. Does nothing if `capacity` is zero.
+pub fn install(wallet: &mut Wallet, capacity: usize, prederive: usize) {
+    if capacity == 0 {
+        return;
+    }
+    let secp = wallet.secp_ctx().clone();
+    let started = Instant::now();
+    let mut derived = 0;
+    for keychain in [KeychainKind::External, KeychainKind::Internal] {
+        let secrets: Vec<_> = wallet
+            .get_signers(keychain)
+            .signers()
+            .iter()
+            .filter_map(|signer| signer.descriptor_secret_key())
+            .collect();
+        if secrets.is_empty() {
+            continue;
+        }
+        let descriptor = wallet.public_descriptor(keychain).clone();
+        let mut keymap = KeyMap::new();
+        let mut cached = Vec::new();
+        for secret in secrets {
+            let Ok(public) = secret.to_public(&secp) else {
+                continue;
+            };
+            let ctx = signer_context(&descriptor, &public);
+            match secret {
+                DescriptorSecretKey::XPrv(key) => {
+                    let signer = CachingSigner::new(key, ctx, capacity);
+                    derived += signer.prederive(&secp, prederive.min(capacity));
+                    cached.push(signer);
+                }
+                other => {
+                    keymap.insert(public, other);
+                }
+            }
+        }
+        // Rebuilds the container without the xprvs, which are added back
+        // below in their caching form.
+        wallet.set_keymap(keychain, keymap);
+        for signer in cached {
+            wallet.add_signer(keychain, SignerOrdering::default(), Arc::new(signer));
+        }
+    }
+    if derived > 0 {
+        tracing::info!(
+            keys = derived,
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "pre-derived signing keys"
+        );
+    }
+}
+
+/// The context BDK itself gives a key of `descriptor`.
+pub fn signer_context(
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    key: &DescriptorPublicKey,
+) -> SignerContext {
+    match descriptor {
+        Descriptor::Tr(tr) => SignerContext::Tap {
+            is_internal_key: tr.internal_key() == key,
+        },
+        _ if descriptor.desc_type().segwit_version() == Some(WitnessVersion::V0) => {
+            SignerContext::Segwitv0
+        }
+        _ => SignerContext::Legacy,
+    }
+}
+
+/// Signs like BDK's xprv signer, deriving each child key at most once while
+/// it stays in the cache.
+pub struct CachingSigner {
+    key: DescriptorXKey<Xpriv>,
+    ctx: SignerContext,
+    cache: Mutex<Lru>,
+}
+
+impl CachingSigner {
+    fn new(key: DescriptorXKey<Xpriv>, ctx: SignerContext, capacity: usize) -> Self {
+        CachingSigner {
+            key,
+            ctx,
+            cache: Mutex::new(Lru::new(capacity)),
+        }
+    }
+
+    /// Caches the keys at the first `count` child indices, or the one key of
+    /// a key without wildcard. Returns the number of keys derived.
+    fn prederive(&self, secp: &Secp256k1<All>, count: usize) -> usize {
+        let count = u32::try_from(count).unwrap_or(u32::MAX);
+        let paths: Vec<_> = match self.key.wildcard {
+            _ if count == 0 => Vec::new(),
+            Wildcard::None => vec![self.key.derivation_path.clone()],
+            Wildcard::Unhardened => (0..count)
+                .filter_map(|i| ChildNumber::from_normal_idx(i).ok())
+                .map(|child| self.key.derivation_path.child(child))
+                .collect(),
+            Wildcard::Hardened => (0..count)
+                .filter_map(|i| ChildNumber::from_hardened_idx(i).ok())
+                .map(|child| self.key.derivation_path.child(child))
+                .collect(),
+        };
+        let mut cache = self.cache.lock().expect("key cache");
+        let mut derived = 0;
+        for path in paths {
+            let Ok(child) = self.key.xkey.derive_priv(secp, &path) else {
+                continue;
+            };
+            let secret = child.private_key;
+            let public = PublicKey::from_secret_key(secp, &secret);
+            cache.insert(path, CachedKey { secret, public });
+            derived += 1;
+        }
+        derived
+    }
+
+    /// The child key at `path` below the xprv, from the cache if possible.
+    fn derive(
+        &self,
+        secp: &Secp256k1<All>,
+        path: &DerivationPath,
+    ) -> Result<(SecretKey, PublicKey), SignerError> {
+        if let Some(key) = self.cache.lock().expect("key cache").get(path) {
+            METRICS.key_cache_hits.fetch_add(1, Ordering::Relaxed);
+            return Ok((key.secret, key.public));
+        }
+        METRICS.key_cache_misses.fetch_add(1, Ordering::Relaxed);
+        let secret = self
+            .key
+            .xkey
+            .derive_priv(secp, path)
+            .map_err(|_| SignerError::InvalidKey)?
+            .private_key;
+        let public = PublicKey::from_secret_key(secp, &secret);
+        self.cache
+            .lock()
+            .expect("key cache")
+            .insert(path.clone(), CachedKey { secret, public });
+        Ok((secret, public))
+    }
+}
+
+/// Shows the key origin only, never the xprv.
+impl std::fmt::Debug for CachingSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CachingSigner")
+            .field("origin", &self.key.origin)
+            .field("cache", &self.cache)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SignerCommon for CachingSigner {
+    fn id(&self, secp: &Secp256k1<All>) -> SignerId {
+        let fingerprint = match &self.key.origin {
+            Some((fingerprint, _)) => *fingerprint,
+            None => self.key.xkey.fingerprint(secp),
+        };
+        SignerId::from(fingerprint)
+    }
+
+    fn descriptor_secret_key(&self) -> Option<DescriptorSecretKey> {
+        Some(DescriptorSecretKey::XPrv(self.key.clone()))
+    }
+}
+
+impl InputSigner for CachingSigner {
+    fn sign_input(
+        &self,
+        psbt: &mut Psbt,
+        input_index: usize,
+        sign_options: &SignOptions,
+        secp: &Secp256k1<All>,
+    ) -> Result<(), SignerError> {
+        let input = psbt
+            .inputs
+            .get(input_index)
+            .ok_or(SignerError::InputIndexOutOfRange)?;
+        if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+            return Ok(());
+        }
+        let tap_key_origins = input
+            .tap_key_origins
+            .iter()
+            .map(|(key, (_, source))| (SinglePubKey::XOnly(*key), source));
+        let found = input
+            .bip32_derivation
+            .iter()
+            .map(|(key, source)| (SinglePubKey::FullKey(bitcoin::PublicKey::new(*key)), source))
+            .chain(tap_key_origins)
+            .find(|(_, source)| self.key.matches(source, secp).is_some());
+        let Some((expected, (_, full_path))) = found else {
+            return Ok(());
+        };
+        let origin_len = self.key.origin.as_ref().map_or(0, |(_, path)| path.len());
+        let path = full_path
+            .as_ref()
+            .get(origin_len..)
+            .map(DerivationPath::from)
+            .ok_or(SignerError::InvalidKey)?;
+
+        let (mut secret, public) = self.derive(secp, &path)?;
+        let valid = match expected {
+            SinglePubKey::FullKey(key) => key.inner == public,
+            SinglePubKey::XOnly(key) => XOnlyPublicKey::from(public) == key,
+        };
+        if !valid {
+            secret.non_secure_erase();
+            return Err(SignerError::InvalidKey);
+        }
+        // HD wallets imply compressed keys.
+        let private_key = PrivateKey {
+            compressed: true,
+            network: self.key.xkey.network,
+            inner: secret,
+        };
+        secret.non_secure_erase();
+        // BDK's signer takes its key by value and keeps it out of reach, so
+        // that copy is dropped without being erased.
+        SignerWrapper::new(private_key, self.ctx).sign_input(psbt, input_index, sign_options, secp)
+    }
+}
+
+struct CachedKey {
+    secret: SecretKey,
+    public: PublicKey,
+}
+
+impl Drop for CachedKey {
+    fn drop(&mut self) {
+        self.secret.non_secure_erase();
+    }
+}
+
+/// Least recently used keys are evicted first.
+struct Lru {
+    capacity: usize,
+    entries: HashMap<DerivationPath, (CachedKey, u64)>,
+    /// Paths by the tick they were last used at.
+    order: BTreeMap<u64, DerivationPath>,
+    tick: u64,
+}
+
+impl std::fmt::Debug for Lru {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Lru")
+            .field("capacity", &self.capacity)
+            .field("len", &self.entries.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Self {
+        Lru {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, path: &DerivationPath) -> Option<&CachedKey> {
+        self.tick += 1;
+        let (key, last_used) = self.entries.get_mut(path)?;
+        let path = self
+            .order
+            .remove(last_used)
+            .expect("cached path is ordered");
+        *last_used = self.tick;
+        self.order.insert(self.tick, path);
+        Some(key)
+    }
+
+    fn insert(&mut self, path: DerivationPath, key: CachedKey) {
+        self.tick += 1;
+        if let Some((_, last_used)) = self.entries.remove(&path) {
+            self.order.remove(&last_used);
+        }
+        while self.entries.len() >= self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+        self.order.insert(self.tick, path.clone());
+        self.entries.insert(path, (key, self.tick));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn key(seed: u8) -> CachedKey {
+        let secret = SecretKey::from_slice(&[seed; 32]).unwrap();
+        let public = PublicKey::from_secret_key(&Secp256k1::new(), &secret);
+        CachedKey { secret, public }
+    }
+
+    #[test]
+    fn signs_as_bdk_does() {
+        let coin = test_support::coin(test_support::script(0), 100_000);
+        let mut psbt = test_support::psbt(&[&coin], &[(test_support::script(1), 99_000)]);
+        test_support::ours(&mut psbt.inputs[0]);
+        psbt.inputs[0].non_witness_utxo = Some(coin);
+        let options = SignOptions {
+            try_finalize: false,
+            ..Default::default()
+        };
+        let mut expected = psbt.clone();
+        test_support::wallet()
+            .sign(&mut expected, options.clone())
+            .unwrap();
+        assert_eq!(expected.inputs[0].partial_sigs.len(), 1);
+
+        let mut wallet = test_support::wallet();
+        install(&mut wallet, 4, 0);
+        for _ in 0..2 {
+            let mut signed = psbt.clone();
+            wallet.sign(&mut signed, options.clone()).unwrap();
+            assert_eq!(signed, expected);
+        }
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_keys() {
+        let path = |index: u32| DerivationPath::from(vec![ChildNumber::from(index)]);
+        let mut lru = Lru::new(2);
+        lru.insert(path(0), key(1));
+        lru.insert(path(1), key(2));
+        assert!(lru.get(&path(0)).is_some());
+        lru.insert(path(2), key(3));
+        assert!(lru.get(&path(1)).is_none());
+        assert_eq!(lru.get(&path(0)).unwrap().secret, key(1).secret);
+        assert!(lru.get(&path(2)).is_some());
+        assert_eq!(lru.entries.len(), 2);
+    }
+}
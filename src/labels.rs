@@ -0,0 +1,369 @@
+//! BIP-329 labels for the wallet's addresses, coins and transactions.
+//!
+//! Labels are kept in `labels.path` in the BIP-329 JSON Lines format
+//! itself, one record per line, so the file can be handed to any wallet
+//! that imports BIP-329. References are checked and normalized when a label
+//! is set, and a type and reference carry at most one label. Imports skip
+//! records that fail those checks, as BIP-329 asks of importing wallets.
+//!
+//! Labels show up in `/admin/utxos`, `/admin/transactions` and the audit
+//! entries of labeled transactions.
+
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use bitcoin::{bip32::Xpub, Address, Network, OutPoint, PublicKey, Txid};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Config, LabelsConfig},
+    error::{ApiJson, Error},
+    storage::Storage,
+    AppState,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelType {
+    Tx,
+    Addr,
+    Pubkey,
+    Input,
+    Output,
+    Xpub,
+}
+
+impl LabelType {
+    fn as_str(self) -> &'static str {
+        match self {
+            LabelType::Tx => "tx",
+            LabelType::Addr => "addr",
+            LabelType::Pubkey => "pubkey",
+            LabelType::Input => "input",
+            LabelType::Output => "output",
+            LabelType::Xpub => "xpub",
+        }
+    }
+}
+
+impl FromStr for LabelType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        [
+            LabelType::Tx,
+            LabelType::Addr,
+            LabelType::Pubkey,
+            LabelType::Input,
+            LabelType::Output,
+            LabelType::Xpub,
+        ]
+        .into_iter()
+        .find(|kind| kind.as_str() == s)
+        .ok_or_else(|| Error::MalformedRequest(format!("unknown label type {s}")))
+    }
+}
+
+/// A BIP-329 record.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Label {
+    #[serde(rename = "type")]
+    pub kind: LabelType,
+    #[serde(rename = "ref")]
+    pub reference: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Descriptor of the wallet the reference belongs to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// Whether the coin may be spent; `output` labels only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spendable: Option<bool>,
+}
+
+pub struct Labels {
+    storage: Arc<dyn Storage>,
+    name: String,
+    network: Network,
+    labels: RwLock<BTreeMap<(LabelType, String), Label>>,
+}
+
+impl Labels {
+    pub fn new(
+        storage: Arc<dyn Storage>,
+        config: &Config,
+        labels: &LabelsConfig,
+    ) -> Result<Self, String> {
+        let name = crate::storage::name(&labels.path);
+        let stored = storage
+            .read(&name)
+            .map_err(|e| e.to_string())
+            .and_then(|stored| {
+                stored
+                    .map(|bytes| String::from_utf8(bytes).map_err(|e| e.to_string()))
+                    .transpose()
+            })
+            .map_err(|e| format!("{name}: {e}"))?;
+        let store = Labels {
+            storage,
+            name,
+            network: config.network,
+            labels: Default::default(),
+        };
+        if let Some(contents) = stored {
+            let mut stored = store.labels.write().expect("labels");
+            for (index, line) in contents.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let label = serde_json::from_str(line)
+                    .map_err(|e| e.to_string())
+                    .and_then(|label| store.normalize(label))
+                    .map_err(|e| format!("{} line {}: {e}", store.name, index + 1))?;
+                stored.insert(key(&label), label);
+            }
+            tracing::info!(count = stored.len(), "loaded labels");
+        }
+        Ok(store)
+    }
+
+    /// Checks `label.reference` is a valid reference of its type and puts
+    /// it in canonical form.
+    fn normalize(&self, mut label: Label) -> Result<Label, String> {
+        let reference = label.reference.as_str();
+        let canonical = match label.kind {
+            LabelType::Tx => Txid::from_str(reference)
+                .map(|txid| txid.to_string())
+                .map_err(|e| e.to_string()),
+            LabelType::Input | LabelType::Output => OutPoint::from_str(reference)
+                .map(|outpoint| outpoint.to_string())
+                .map_err(|e| e.to_string()),
+            LabelType::Addr => Address::from_str(reference)
+                .map_err(|e| e.to_string())
+                .and_then(|address| {
+                    address
+                        .require_network(self.network)
+                        .map_err(|_| format!("not an address on {}", self.network))
+                })
+                .map(|address| address.to_string()),
+            LabelType::Pubkey => PublicKey::from_str(reference)
+                .map(|key| key.to_string())
+                .map_err(|e| e.to_string()),
+            LabelType::Xpub => Xpub::from_str(reference)
+                .map(|xpub| xpub.to_string())
+                .map_err(|e| e.to_string()),
+        }
+        .map_err(|e| format!("invalid {} reference {reference}: {e}", label.kind.as_str()))?;
+        label.reference = canonical;
+        if label.spendable.is_some() && label.kind != LabelType::Output {
+            return Err("only output labels can set spendable".into());
+        }
+        Ok(label)
+    }
+
+    /// The record for `reference`, which must already be canonical.
+    pub fn get(&self, kind: LabelType, reference: &str) -> Option<Label> {
+        self.labels
+            .read()
+            .expect("labels")
+            .get(&(kind, reference.to_owned()))
+            .cloned()
+    }
+
+    /// Just the label text of `reference`.
+    pub fn text(&self, kind: LabelType, reference: &str) -> Option<String> {
+        self.get(kind, reference).and_then(|label| label.label)
+    }
+
+    /// Whether `outpoint` may be spent, which only its label can deny.
+    pub fn spendable(&self, outpoint: OutPoint) -> bool {
+        self.get(LabelType::Output, &outpoint.to_string())
+            .and_then(|label| label.spendable)
+            != Some(false)
+    }
+
+    fn set(&self, label: Label) -> Result<Label, Error> {
+        let label = self.normalize(label).map_err(Error::MalformedRequest)?;
+        let mut labels = self.labels.write().expect("labels");
+        let previous = labels.insert(key(&label), label.clone());
+        if let Err(e) = self.save(&labels) {
+            match previous {
+                Some(previous) => labels.insert(key(&previous), previous),
+                None => labels.remove(&key(&label)),
+            };
+            return Err(Error::Internal(format!("saving labels: {e}")));
+        }
+        Ok(label)
+    }
+
+    /// Adds every valid record of the JSON Lines `body`, replacing labels
+    /// of the same type and reference.
+    fn import(&self, body: &str) -> Result<ImportResponse, Error> {
+        let mut imported = Vec::new();
+        let mut skipped = 0;
+        for line in body.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str(line)
+                .map_err(|e| e.to_string())
+                .and_then(|label| self.normalize(label))
+            {
+                Ok(label) => imported.push(label),
+                Err(e) => {
+                    tracing::debug!("skipping label: {e}");
+                    skipped += 1;
+                }
+            }
+        }
+        let mut labels = self.labels.write().expect("labels");
+        let before = labels.clone();
+        let count = imported.len();
+        labels.extend(imported.into_iter().map(|label| (key(&label), label)));
+        if let Err(e) = self.save(&labels) {
+            *labels = before;
+            return Err(Error::Internal(format!("saving labels: {e}")));
+        }
+        tracing::info!(imported = count, skipped, "imported labels");
+        Ok(ImportResponse {
+            imported: count,
+            skipped,
+        })
+    }
+
+    pub fn export(&self) -> String {
+        to_jsonl(&self.labels.read().expect("labels"))
+    }
+
+    /// Replaces every label with the JSON Lines `body`, for a replication
+    /// standby. Records that fail validation are left out.
+    pub fn replace(&self, body: &str) -> std::io::Result<()> {
+        let next: BTreeMap<_, _> = body
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| {
+                serde_json::from_str(line)
+                    .map_err(|e| e.to_string())
+                    .and_then(|label| self.normalize(label))
+                    .map_err(|e| tracing::warn!("skipping replicated label: {e}"))
+                    .ok()
+            })
+            .map(|label| (key(&label), label))
+            .collect();
+        let mut current = self.labels.write().expect("labels");
+        if *current != next {
+            self.save(&next)?;
+            *current = next;
+        }
+        Ok(())
+    }
+
+    fn save(&self, labels: &BTreeMap<(LabelType, String), Label>) -> std::io::Result<()> {
+        self.storage.write(&self.name, to_jsonl(labels).as_bytes())
+    }
+}
+
+fn key(label: &Label) -> (LabelType, String) {
+    (label.kind, label.reference.clone())
+}
+
+fn to_jsonl(labels: &BTreeMap<(LabelType, String), Label>) -> String {
+    labels
+        .values()
+        .map(|label| serde_json::to_string(label).expect("serialize label") + "\n")
+        .collect()
+}
+
+#[derive(Serialize)]
+pub struct ImportResponse {
+    pub imported: usize,
+    /// Records that were not valid JSON or failed validation.
+    pub skipped: usize,
+}
+
+#[derive(Deserialize)]
+pub struct LabelFilter {
+    #[serde(rename = "type")]
+    kind: Option<LabelType>,
+}
+
+fn enabled(state: &AppState) -> Result<&Labels, Error> {
+    state
+        .labels
+        .as_ref()
+        .ok_or_else(|| Error::NotFound("labels are not configured".into()))
+}
+
+pub async fn list(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<LabelFilter>,
+) -> Result<Json<Vec<Label>>, Error> {
+    let labels = enabled(&state)?.labels.read().expect("labels");
+    Ok(Json(
+        labels
+            .values()
+            .filter(|label| filter.kind.map_or(true, |kind| label.kind == kind))
+            .cloned()
+            .collect(),
+    ))
+}
+
+pub async fn set(
+    State(state): State<Arc<AppState>>,
+    ApiJson(label): ApiJson<Label>,
+) -> Result<Json<Label>, Error> {
+    enabled(&state)?.set(label).map(Json)
+}
+
+pub async fn remove(
+    State(state): State<Arc<AppState>>,
+    Path((kind, reference)): Path<(String, String)>,
+) -> Result<StatusCode, Error> {
+    let labels = enabled(&state)?;
+    let kind = kind.parse()?;
+    let reference = labels
+        .normalize(Label {
+            kind,
+            reference,
+            label: None,
+            origin: None,
+            spendable: None,
+        })
+        .map_err(Error::MalformedRequest)?
+        .reference;
+    let mut stored = labels.labels.write().expect("labels");
+    let removed = stored
+        .remove(&(kind, reference.clone()))
+        .ok_or_else(|| Error::NotFound(format!("label for {reference}")))?;
+    if let Err(e) = labels.save(&stored) {
+        stored.insert(key(&removed), removed);
+        return Err(Error::Internal(format!("saving labels: {e}")));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn import(
+    State(state): State<Arc<AppState>>,
+    body: Bytes,
+) -> Result<Json<ImportResponse>, Error> {
+    let labels = enabled(&state)?;
+    let body = std::str::from_utf8(&body)
+        .map_err(|e| Error::MalformedRequest(format!("labels are not UTF-8: {e}")))?;
+    labels.import(body).map(Json)
+}
+
+pub async fn export(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl axum::response::IntoResponse, Error> {
+    let labels = enabled(&state)?;
+    Ok((
+        [(axum::http::header::CONTENT_TYPE, "application/jsonl")],
+        labels.export(),
+    ))
+}
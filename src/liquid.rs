@@ -0,0 +1,456 @@
+//! Signing of Elements PSETs for Liquid wallets.
+//!
+//! The Liquid wallet is a single-key P2WPKH account of its own, derived from
+//! the descriptor's xprv at `84'/1776'/0'` on Liquid and `84'/1'/0'` on
+//! Liquid testnet and Elements regtest, so no key is shared with the Bitcoin
+//! wallet. An input is signed when one of its `bip32_derivation` keys lies
+//! under that account and its `witness_utxo` is a P2WPKH output of that key.
+//!
+//! Input amounts may be confidential: the segwit sighash commits to the value
+//! commitment as recorded in `witness_utxo`. Outputs are different, since
+//! blinding rewrites them and so invalidates every signature made before. A
+//! PSET with an output that has a `blinding_key` but no value commitment yet
+//! is refused until it has been blinded.
+
+use std::{str::FromStr, sync::Arc};
+
+use axum::{extract::State, Extension, Json};
+use bitcoin::{
+    bip32::{DerivationPath, KeySource, Xpriv, Xpub},
+    hashes::Hash,
+    key::Secp256k1,
+    psbt::PsbtSighashType,
+    secp256k1::{All, Message},
+    sighash::EcdsaSighashType,
+    CompressedPublicKey, Network, ScriptBuf,
+};
+use elements::{
+    pset::PartiallySignedTransaction as Pset, sighash::SighashCache, Script, Transaction,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    access_log::UnsignedTxid,
+    config::{Config, LiquidConfig, LiquidNetwork, Sighash},
+    context::RequestContext,
+    error::{ApiJson, Error, ErrorResponse},
+    events::Event,
+    priority::Priority,
+    quorum, signing_windows, stats, AppState,
+};
+
+/// Wallet name of Liquid signing attempts in the audit log.
+pub const LIQUID_WALLET: &str = "liquid";
+
+/// SLIP-44 coin type of Liquid mainnet.
+const LIQUID_COIN_TYPE: u32 = 1776;
+
+pub struct Liquid {
+    /// Key origin of the account: the root fingerprint and `84'/coin'/0'`.
+    origin: KeySource,
+    xprv: Xpriv,
+    xpub: Xpub,
+}
+
+impl Liquid {
+    pub fn new(config: &Config, liquid: &LiquidConfig) -> Result<Self, String> {
+        let mainnet = liquid.network == LiquidNetwork::Liquid;
+        if mainnet != (config.network == Network::Bitcoin) {
+            return Err(format!(
+                "liquid.network: {:?} cannot be used with network {}",
+                liquid.network, config.network
+            ));
+        }
+        let root = crate::descriptor::signing_root(&config.xprv)
+            .ok_or("the descriptor has no xprv to derive a Liquid key from")?;
+        let coin = if mainnet { LIQUID_COIN_TYPE } else { 1 };
+        let path =
+            DerivationPath::from_str(&format!("m/84'/{coin}'/0'")).expect("valid derivation path");
+        let secp = Secp256k1::new();
+        let xprv = root
+            .xkey
+            .derive_priv(&secp, &path)
+            .map_err(|e| format!("deriving the Liquid key: {e}"))?;
+        let origin = match &root.origin {
+            Some((fingerprint, prefix)) => (*fingerprint, prefix.extend(&path)),
+            None => (root.xkey.fingerprint(&secp), path),
+        };
+        Ok(Liquid {
+            origin,
+            xpub: Xpub::from_priv(&secp, &xprv),
+            xprv,
+        })
+    }
+
+    /// Public descriptor of the account, for the wallet building the PSETs.
+    pub fn descriptor(&self) -> String {
+        let (fingerprint, path) = &self.origin;
+        format!("elwpkh([{fingerprint}/{path}]{}/<0;1>/*)", self.xpub)
+    }
+
+    /// Adds this wallet's signatures to `pset`, refusing it if an input asks
+    /// for a sighash type not in `allowed`.
+    pub fn sign(&self, pset: &mut Pset, allowed: &[Sighash]) -> Result<(), Error> {
+        let invalid = |reason: String| Error::InvalidTransaction(format!("pset: {reason}"));
+        for (index, input) in pset.inputs().iter().enumerate() {
+            // Inputs that ask for none are signed with SIGHASH_ALL.
+            let requested = PsbtSighashType::from_u32(
+                input
+                    .sighash_type
+                    .map_or(EcdsaSighashType::All.to_u32(), |t| t.to_u32()),
+            );
+            if !allowed.iter().any(|sighash| sighash.matches(requested)) {
+                return Err(invalid(format!(
+                    "input {index}: sighash type {requested} is not allowed"
+                )));
+            }
+        }
+        for (index, output) in pset.outputs().iter().enumerate() {
+            if output.blinding_key.is_some() && output.amount_comm.is_none() {
+                return Err(invalid(format!(
+                    "output {index} is to be blinded, blind the PSET before signing it"
+                )));
+            }
+        }
+        let tx = pset.extract_tx().map_err(|e| invalid(e.to_string()))?;
+        let secp = Secp256k1::new();
+        let mut cache = SighashCache::new(&tx);
+        for (index, input) in pset.inputs_mut().iter_mut().enumerate() {
+            let keys: Vec<_> = input
+                .bip32_derivation
+                .iter()
+                .filter_map(|(pubkey, source)| {
+                    let key = self.derive(&secp, source)?;
+                    (key.private_key.public_key(&secp) == pubkey.inner).then_some((*pubkey, key))
+                })
+                .collect();
+            if keys.is_empty() {
+                continue;
+            }
+            let utxo = input
+                .witness_utxo
+                .as_ref()
+                .ok_or_else(|| invalid(format!("input {index} has no witness_utxo")))?;
+            let sighash_type = input
+                .ecdsa_hash_ty()
+                .ok_or_else(|| invalid(format!("input {index} has a non-standard sighash type")))?;
+            for (pubkey, key) in keys {
+                let compressed = CompressedPublicKey::try_from(pubkey)
+                    .map_err(|_| invalid(format!("input {index} has an uncompressed key")))?;
+                let script_pubkey = ScriptBuf::new_p2wpkh(&compressed.wpubkey_hash());
+                if utxo.script_pubkey.as_bytes() != script_pubkey.as_bytes() {
+                    return Err(invalid(format!(
+                        "input {index} does not spend a P2WPKH output of key {pubkey}"
+                    )));
+                }
+                let script_code =
+                    Script::from(ScriptBuf::new_p2pkh(&pubkey.pubkey_hash()).into_bytes());
+                let sighash = cache.segwitv0_sighash(index, &script_code, utxo.value, sighash_type);
+                let message = Message::from_digest(sighash.to_byte_array());
+                let mut signature = secp
+                    .sign_ecdsa(&message, &key.private_key)
+                    .serialize_der()
+                    .to_vec();
+                signature.push(sighash_type.as_u32() as u8);
+                input.partial_sigs.insert(pubkey, signature);
+            }
+        }
+        Ok(())
+    }
+
+    /// The private key at `source` if it lies under the account.
+    fn derive(&self, secp: &Secp256k1<All>, (fingerprint, path): &KeySource) -> Option<Xpriv> {
+        let (account_fingerprint, account_path) = &self.origin;
+        if fingerprint != account_fingerprint {
+            return None;
+        }
+        let relative = path.as_ref().strip_prefix(account_path.as_ref())?;
+        self.xprv.derive_priv(secp, &relative).ok()
+    }
+}
+
+fn txid(tx: &Transaction) -> bitcoin::Txid {
+    bitcoin::Txid::from_byte_array(tx.txid().to_byte_array())
+}
+
+/// The signing policies that hold for PSETs as for PSBTs, keyed on the
+/// PSET's txid.
+fn check_policies(
+    state: &AppState,
+    ctx: &RequestContext,
+    txid: bitcoin::Txid,
+) -> Result<(), Error> {
+    // The approval request describes a Bitcoin PSBT, so a PSET cannot be
+    // put to the endpoint, and is not signed without its answer.
+    if state.config().approval.is_some() {
+        return Err(Error::Forbidden(
+            "PSETs are not signed while [approval] is set".into(),
+        ));
+    }
+    let mut quorum = state.config().quorum.clone();
+    if let Some(policy) = state.config().signing_windows.clone() {
+        match (signing_windows::check(ctx, &policy)?, &mut quorum) {
+            (Some(threshold), Some(quorum)) => quorum.threshold = threshold,
+            (Some(_), None) => {
+                return Err(Error::OutsideSigningWindow(
+                    "the override needs [quorum]".into(),
+                ))
+            }
+            (None, _) => {}
+        }
+    }
+    match quorum {
+        Some(policy) => quorum::check(state, ctx, txid, &policy),
+        None => Ok(()),
+    }
+}
+
+fn enabled(state: &AppState) -> Result<&Liquid, Error> {
+    state
+        .liquid
+        .as_ref()
+        .ok_or_else(|| Error::NotFound("Liquid signing is not configured".into()))
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct SignPsetRequest {
+    /// Base64-encoded Elements PSET.
+    pub pset: String,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct SignPsetResponse {
+    /// Base64-encoded PSET with this wallet's signatures added.
+    pub pset: String,
+    /// Set when `signing.dry_run` left the PSET unsigned.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct AccountResponse {
+    /// `elwpkh` descriptor of the Liquid account, with receive and change.
+    pub descriptor: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/sign_pset",
+    request_body = SignPsetRequest,
+    responses(
+        (status = 200, description = "Signed PSET", body = SignPsetResponse),
+        (status = 202, description = "`quorum_pending`", body = ErrorResponse),
+        (status = 400, description = "`malformed_request` or `invalid_transaction`", body = ErrorResponse),
+        (status = 401, description = "`unauthorized`", body = ErrorResponse),
+        (status = 403, description = "`forbidden` with `[approval]`, or `outside_signing_window`", body = ErrorResponse),
+        (status = 404, description = "`not_found` without `[liquid]`", body = ErrorResponse),
+        (status = 415, description = "`unsupported_media_type`", body = ErrorResponse),
+        (status = 503, description = "`overloaded`", body = ErrorResponse),
+        (status = 504, description = "`timeout`", body = ErrorResponse),
+    )
+)]
+pub async fn sign_pset(
+    State(state): State<Arc<AppState>>,
+    ctx: RequestContext,
+    ApiJson(request): ApiJson<SignPsetRequest>,
+) -> Result<(Extension<UnsignedTxid>, Json<SignPsetResponse>), Error> {
+    enabled(&state)?;
+    let mut pset = Pset::from_str(&request.pset)
+        .map_err(|e| Error::MalformedRequest(format!("invalid pset: {e}")))?;
+    let tx = pset
+        .extract_tx()
+        .map_err(|e| Error::InvalidTransaction(format!("pset: {e}")))?;
+    let txid = txid(&tx);
+    let started = std::time::Instant::now();
+    let before: Vec<usize> = pset
+        .inputs()
+        .iter()
+        .map(|input| input.partial_sigs.len())
+        .collect();
+
+    let result = match check_policies(&state, &ctx, txid) {
+        Ok(()) => match state.acquire_signing_permit(Priority::Interactive).await {
+            Ok(permit) => {
+                let state = state.clone();
+                crate::run_blocking(move || {
+                    let _permit = permit;
+                    let liquid = enabled(&state)?;
+                    let allowed = state.config().sign_options.allowed_sighashes.clone();
+                    if state.config().signing.dry_run {
+                        // Signed all the same, so errors surface, but not released.
+                        liquid.sign(&mut pset.clone(), &allowed)?;
+                        return Ok(pset);
+                    }
+                    liquid.sign(&mut pset, &allowed).map(|()| pset)
+                })
+                .await
+            }
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    let result = crate::record_signing(&state, &ctx, LIQUID_WALLET, txid, result);
+    let verdict = match &result {
+        Ok(_) if state.config().signing.dry_run => stats::Verdict::DryRun,
+        Ok(signed) => stats::Verdict::Signed {
+            inputs: signed
+                .inputs()
+                .iter()
+                .zip(&before)
+                .filter(|(input, had)| input.partial_sigs.len() > **had)
+                .count() as u64,
+            value_sat: 0,
+        },
+        Err(e) => stats::Verdict::Rejected(e.code()),
+    };
+    state.stats.count(verdict, started.elapsed());
+    let request_id = ctx.request_id.clone();
+    state.events.publish(match &result {
+        Ok(_) => Event::Signed {
+            txid,
+            request_id,
+            dry_run: state.config().signing.dry_run,
+        },
+        Err(e) => Event::Rejected {
+            txid,
+            request_id,
+            error_code: e.code(),
+        },
+    });
+    let pset = result?;
+    Ok((
+        Extension(UnsignedTxid(txid)),
+        Json(SignPsetResponse {
+            pset: pset.to_string(),
+            dry_run: state.config().signing.dry_run,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/liquid/account",
+    responses(
+        (status = 200, description = "Liquid account", body = AccountResponse),
+        (status = 404, description = "`not_found` without `[liquid]`", body = ErrorResponse),
+    )
+)]
+pub async fn account(State(state): State<Arc<AppState>>) -> Result<Json<AccountResponse>, Error> {
+    Ok(Json(AccountResponse {
+        descriptor: enabled(&state)?.descriptor(),
+    }))
+}
+
+/// Public Liquid endpoints, merged into the public router.
+pub fn routes(state: &Arc<AppState>) -> axum::Router<Arc<AppState>> {
+    use axum::{
+        middleware::from_fn_with_state,
+        routing::{get, post},
+    };
+
+    use crate::timeout::{self, RouteTimeout};
+
+    axum::Router::new()
+        .route("/liquid/account", get(account))
+        .route_layer(from_fn_with_state(
+            (state.clone(), RouteTimeout::Default),
+            timeout::enforce_timeout,
+        ))
+        .route(
+            "/sign_pset",
+            post(sign_pset).layer(from_fn_with_state(
+                (state.clone(), RouteTimeout::Sign),
+                timeout::enforce_timeout,
+            )),
+        )
+}
+
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(sign_pset, account))]
+pub struct ApiDoc;
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::PublicKey;
+    use elements::{
+        confidential::{Asset, Nonce, Value},
+        pset::{Input, Output},
+        AssetId, OutPoint, TxOut, TxOutWitness,
+    };
+
+    use super::*;
+
+    fn liquid() -> Liquid {
+        let config = crate::test_support::config("");
+        Liquid::new(
+            &config,
+            &LiquidConfig {
+                network: LiquidNetwork::ElementsRegtest,
+            },
+        )
+        .unwrap()
+    }
+
+    /// A PSET spending a coin of the account's first receive key.
+    fn spend(liquid: &Liquid, sighash_type: Option<u32>) -> Pset {
+        let secp = Secp256k1::new();
+        let path = DerivationPath::from_str("m/0/0").unwrap();
+        let key = liquid.xpub.derive_pub(&secp, &path).unwrap().public_key;
+        let pubkey = PublicKey::new(key);
+        let script = ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash().unwrap());
+        let script = Script::from(script.into_bytes());
+        let mut input = Input::from_prevout(OutPoint::default());
+        input.witness_utxo = Some(TxOut {
+            asset: Asset::Explicit(AssetId::LIQUID_BTC),
+            value: Value::Explicit(100_000),
+            nonce: Nonce::Null,
+            script_pubkey: script.clone(),
+            witness: TxOutWitness::default(),
+        });
+        let (fingerprint, account) = &liquid.origin;
+        input
+            .bip32_derivation
+            .insert(pubkey, (*fingerprint, account.extend(&path)));
+        input.sighash_type = sighash_type.map(elements::pset::PsbtSighashType::from_u32);
+        let mut pset = Pset::new_v2();
+        pset.add_input(input);
+        pset.add_output(Output::new_explicit(
+            script,
+            99_000,
+            AssetId::LIQUID_BTC,
+            None,
+        ));
+        pset
+    }
+
+    #[test]
+    fn signs_inputs_of_the_account() {
+        let liquid = liquid();
+        let mut pset = spend(&liquid, None);
+        liquid.sign(&mut pset, &[Sighash::All]).unwrap();
+        assert_eq!(pset.inputs()[0].partial_sigs.len(), 1);
+    }
+
+    #[test]
+    fn refuses_sighashes_not_allowed() {
+        let liquid = liquid();
+        let none = EcdsaSighashType::None.to_u32();
+        let mut pset = spend(&liquid, Some(none));
+        let err = liquid.sign(&mut pset, &[Sighash::All]).unwrap_err();
+        assert!(
+            matches!(&err, Error::InvalidTransaction(m) if m.contains("SIGHASH_NONE")),
+            "{err:?}"
+        );
+        assert!(pset.inputs()[0].partial_sigs.is_empty());
+
+        let mut pset = spend(&liquid, None);
+        let err = liquid.sign(&mut pset, &[Sighash::None]).unwrap_err();
+        assert!(matches!(err, Error::InvalidTransaction(_)), "{err:?}");
+
+        let mut pset = spend(&liquid, Some(none));
+        liquid
+            .sign(&mut pset, &[Sighash::All, Sighash::None])
+            .unwrap();
+        assert_eq!(pset.inputs()[0].partial_sigs.len(), 1);
+    }
+}
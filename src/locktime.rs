@@ -0,0 +1,79 @@
+//! Signing policy on locktimes and input sequences.
+//!
+//! With `[locktime]` set, a PSBT can be held to signal replaceability, so a
+//! stuck withdrawal can always be fee bumped, to carry an anti-fee-sniping
+//! locktime near the chain tip, and to be minable soon rather than locked
+//! far into the future. The tip is the wallet's last synced block, so the
+//! checks that need it wait for the first sync.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bitcoin::{absolute::LockTime, Psbt};
+
+use crate::{chain, config::LocktimeConfig, error::Error, AppState};
+
+/// Seconds a block is counted as for time locks.
+const BLOCK_INTERVAL_SECS: u64 = 600;
+
+pub fn check(state: &AppState, psbt: &Psbt, policy: &LocktimeConfig) -> Result<(), Error> {
+    let tx = &psbt.unsigned_tx;
+    let invalid = |reason: String| Error::InvalidTransaction(format!("locktime: {reason}"));
+    if policy.require_rbf && !tx.is_explicitly_rbf() {
+        return Err(invalid(
+            "no input signals replaceability (BIP-125), which is required".into(),
+        ));
+    }
+    if !policy.anti_fee_sniping && policy.max_future_blocks.is_none() {
+        return Ok(());
+    }
+    chain::require_synced(state)?;
+    let tip = state.wallet().latest_checkpoint().height();
+    let enforced = tx.is_lock_time_enabled();
+
+    if policy.anti_fee_sniping {
+        let LockTime::Blocks(height) = tx.lock_time else {
+            return Err(invalid(
+                "locked to a time, where anti-fee-sniping needs a block height".into(),
+            ));
+        };
+        let height = height.to_consensus_u32();
+        if height == 0 || !enforced {
+            return Err(invalid(
+                "no enforced locktime, where anti-fee-sniping needs one near the tip".into(),
+            ));
+        }
+        let depth = policy.anti_fee_sniping_depth;
+        if height < tip.saturating_sub(depth) {
+            return Err(invalid(format!(
+                "locked to height {height}, more than {depth} blocks below the tip at {tip}"
+            )));
+        }
+    }
+
+    if let (Some(blocks), true) = (policy.max_future_blocks, enforced) {
+        match tx.lock_time {
+            LockTime::Blocks(height) => {
+                let height = height.to_consensus_u32();
+                if u64::from(height) > u64::from(tip) + u64::from(blocks) {
+                    return Err(invalid(format!(
+                        "locked to height {height}, more than {blocks} blocks past the tip at \
+                         {tip}"
+                    )));
+                }
+            }
+            LockTime::Seconds(time) => {
+                let time = time.to_consensus_u32();
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                if u64::from(time) > now + u64::from(blocks) * BLOCK_INTERVAL_SECS {
+                    return Err(invalid(format!(
+                        "locked until {time}, more than {blocks} blocks from now"
+                    )));
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use rolling_file::{BasicRollingFileAppender, RollingConditionBasic};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{
+    field::RecordFields,
+    fmt::{
+        format::{DefaultFields, Writer},
+        time::ChronoLocal,
+        FormatFields,
+    },
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+};
+
+use crate::config::{LogConfig, Rotation};
+
+fn timer() -> ChronoLocal {
+    ChronoLocal::new("%FT%H:%M:%S%z".to_owned())
+}
+
+/// Span fields as formatted for the log file. A type of its own, since each
+/// layer keeps the formatted fields of a span under the type of its
+/// formatter: sharing stdout's would put its colors in the file, and write
+/// fields recorded after the span was created twice.
+struct FileFields(DefaultFields);
+
+impl<'writer> FormatFields<'writer> for FileFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        writer: Writer<'writer>,
+        fields: R,
+    ) -> std::fmt::Result {
+        self.0.format_fields(writer, fields)
+    }
+}
+
+/// Installs the global subscriber: always stdout, plus a rotating log file
+/// when `[log]` configures one.
+///
+/// The returned guard flushes the file writer on drop and must be held for
+/// the life of the process.
+pub fn init(config: Option<&LogConfig>) -> Result<Option<WorkerGuard>, String> {
+    let stdout = tracing_subscriber::fmt::layer().with_timer(timer());
+
+    let Some(config) = config else {
+        tracing_subscriber::registry().with(stdout).init();
+        return Ok(None);
+    };
+
+    let mut condition = RollingConditionBasic::new();
+    condition = match config.rotation {
+        Rotation::Never => condition,
+        Rotation::Hourly => condition.hourly(),
+        Rotation::Daily => condition.daily(),
+    };
+    if let Some(max_size_mb) = config.max_size_mb {
+        condition = condition.max_size(max_size_mb * 1024 * 1024);
+    }
+    let appender = BasicRollingFileAppender::new(&config.file, condition, config.max_files)
+        .map_err(|e| format!("opening log file {}: {e}", config.file.display()))?;
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+
+    let file = tracing_subscriber::fmt::layer()
+        .with_timer(timer())
+        .with_ansi(false)
+        .fmt_fields(FileFields(DefaultFields::new()))
+        .with_writer(writer);
+    tracing_subscriber::registry()
+        .with(stdout)
+        .with(file)
+        .init();
+    Ok(Some(guard))
+}
@@ -1,137 +1,2121 @@
+mod access_log;
+mod api_doc;
+mod approval;
+mod attestation;
+mod audit;
+mod auth;
+mod backup;
+mod bench;
+mod bip21;
+mod bsms;
+mod cbor;
+mod chain;
+mod channel_funding;
+mod check;
+mod coinjoin;
+mod coins;
+mod config;
+mod consolidation;
+mod context;
+mod cors;
+mod cosigners;
+mod cron;
+mod descriptor;
+mod dust;
+#[cfg(feature = "e2e")]
+mod e2e;
+mod error;
+mod etag;
+mod events;
+mod fee_check;
+mod file_drop;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod journal;
+mod key_cache;
+mod labels;
+#[cfg(feature = "liquid")]
+mod liquid;
+mod locktime;
+mod logging;
+mod metrics;
+mod migrate;
+mod negotiate;
+mod network;
+mod networks;
+#[cfg(feature = "nostr")]
+mod nostr_transport;
+mod ownership;
+mod page;
+mod payjoin;
+mod payout;
+mod prevouts;
+mod priority;
+mod psbt_codec;
+mod psbt_diff;
+mod psbt_fields;
+mod qr;
+mod quorum;
+mod replication;
+mod reserves;
+mod response_cache;
+mod rgb;
+mod rpc;
+mod schedule;
+mod self_test;
+mod server;
+#[cfg(feature = "redis")]
+mod shared;
+mod sighash_pairing;
+mod sign_options;
+mod signer;
+mod signing_windows;
+mod silent_payments;
+mod spend_guard;
+mod startup;
+mod stats;
+mod storage;
+mod sweep;
+#[cfg(unix)]
+mod systemd;
+mod templates;
+#[cfg(test)]
+mod test_support;
+mod test_vectors;
+mod timeout;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(unix)]
+mod unix_socket;
+mod upload;
+mod wallet;
+mod ws;
+mod xpub;
 
-use std::{str::FromStr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    process::ExitCode,
+    sync::{Arc, RwLock},
+};
 
-use axum::{extract::State, routing::post, Json};
-use bdk_wallet::{SignOptions, Wallet};
+use axum::{
+    extract::{Query, State},
+    middleware::from_fn_with_state,
+    routing::post,
+    Extension, Json,
+};
+use bdk_wallet::{descriptor::DescriptorError, KeychainKind, Wallet};
 use bitcoin::Psbt;
-use serde::{Deserialize, Serialize, Serializer};
+use serde::{Deserialize, Serialize};
+
+use access_log::UnsignedTxid;
+use audit::{AuditEntry, AuditFilter, AuditLog, Outcome, DEFAULT_WALLET};
+use config::{Config, ConfigError};
+use context::RequestContext;
+use error::{ApiJson, Error, ErrorResponse};
+use events::{Event, EventBus};
+use priority::Priority;
+pub use psbt_codec::{de_psbt_from_base64, serialize_psbt_to_base64};
+use startup::StartupError;
+use timeout::RouteTimeout;
 
 pub struct AppState {
-    pub wallet: Wallet,
+    /// Signing reads it concurrently; reveals and sync updates go through
+    /// its writer thread, see [`wallet::SharedWallet`].
+    pub wallet: wallet::SharedWallet,
+    /// Set once the chain backend has completed its first sync, so the
+    /// wallet's coin list can be trusted.
+    pub chain_synced: std::sync::atomic::AtomicBool,
+    pub chain_status: chain::SyncStatus,
+    /// Outcome of the startup signing self-test; the service is not ready
+    /// unless it passed.
+    pub self_test: Result<(), String>,
+    pub audit: Option<AuditLog>,
+    pub attester: Option<attestation::Attester>,
+    pub events: EventBus,
+    pub payjoin: payjoin::Reservations,
+    pub channel_fundings: channel_funding::Fundings,
+    pub quorum: quorum::Approvals,
+    pub stats: stats::Stats,
+    /// Client for outbound calls, such as the RGB consignment validator.
+    pub http: reqwest::Client,
+    pub silent_payments: Option<silent_payments::SilentPayments>,
+    /// Multisig wallets registered through BSMS.
+    pub bsms: Option<bsms::Bsms>,
+    /// The `[signer]` the wallet also signs with.
+    pub signer: Option<Arc<dyn signer::Signer>>,
+    pub labels: Option<labels::Labels>,
+    pub schedules: Option<schedule::Schedules>,
+    pub spend_guard: Option<spend_guard::SpendGuard>,
+    pub journal: Option<journal::Journal>,
+    pub response_cache: response_cache::ResponseCache,
+    pub replication: Option<replication::Replication>,
+    #[cfg(feature = "liquid")]
+    pub liquid: Option<liquid::Liquid>,
+    /// Bounds how many CPU-heavy signing operations run at once.
+    signing_slots: priority::SigningSlots,
+    config_path: PathBuf,
+    config: RwLock<Arc<Config>>,
 }
 
-#[derive(Debug, serde::Deserialize)]
-pub struct Config {
-    pub port: u16,
-    pub network: bitcoin::Network,
-    pub xprv: String,
+/// Builds the signing wallet described by `config`.
+pub fn create_wallet(config: &Config) -> Result<Wallet, DescriptorError> {
+    let params = match &config.change_xprv {
+        Some(change) => bdk_wallet::Wallet::create(config.xprv.clone(), change.clone()),
+        None => bdk_wallet::Wallet::create_single(config.xprv.clone()),
+    };
+    let mut wallet = params.network(config.network).create_wallet_no_persist()?;
+    key_cache::install(
+        &mut wallet,
+        config.signing.key_cache_size,
+        config.signing.prederive_keys,
+    );
+    install_signer(&mut wallet, config)
+        .map_err(|e| DescriptorError::Key(bdk_wallet::keys::KeyError::Message(e)))?;
+    Ok(wallet)
+}
+
+/// Loads the signing wallet `config` describes from `changeset`, checking it
+/// was stored for the same descriptors and network.
+pub fn load_wallet(
+    config: &Config,
+    changeset: bdk_wallet::ChangeSet,
+) -> Result<Option<Wallet>, String> {
+    let mut params = bdk_wallet::Wallet::load()
+        .descriptor(KeychainKind::External, Some(config.xprv.clone()))
+        .check_network(config.network)
+        .extract_keys();
+    if let Some(change) = &config.change_xprv {
+        params = params.descriptor(KeychainKind::Internal, Some(change.clone()));
+    }
+    let loaded = params
+        .load_wallet_no_persist(changeset)
+        .map_err(|e| e.to_string())?;
+    let Some(mut wallet) = loaded else {
+        return Ok(None);
+    };
+    key_cache::install(
+        &mut wallet,
+        config.signing.key_cache_size,
+        config.signing.prederive_keys,
+    );
+    install_signer(&mut wallet, config)?;
+    Ok(Some(wallet))
+}
+
+/// Adds the `[signer]` of `config`, if any, to `wallet`.
+fn install_signer(wallet: &mut Wallet, config: &Config) -> Result<(), String> {
+    match signer::build(config).map_err(|e| format!("signer: {e}"))? {
+        Some(plugin) => signer::install(wallet, plugin).map_err(|e| format!("signer: {e}")),
+        None => Ok(()),
+    }
 }
 
 impl AppState {
-    pub async fn init(config: &Config) -> Result<Self, String> {
+    pub async fn init(config: Config, config_path: PathBuf) -> Result<Self, StartupError> {
+        if config.signing.dry_run {
+            tracing::warn!(
+                throwaway_key = config.throwaway_key,
+                "signing.dry_run is set, PSBTs are checked and returned unsigned"
+            );
+        }
+        descriptor::check_key_network(&config.xprv, config.network).map_err(StartupError::Key)?;
+        let storage =
+            storage::open(config.storage.as_ref()).map_err(StartupError::section("storage"))?;
+        let mut wallet_store = config
+            .storage
+            .as_ref()
+            .and_then(|storage| storage.wallet_path.as_deref())
+            .map(|path| wallet::WalletStore::new(storage.clone(), storage::name(path)));
+        let wallet = match &mut wallet_store {
+            Some(store) => store
+                .open(&config)
+                .map_err(StartupError::section("storage"))?,
+            None => create_wallet(&config)
+                .map_err(|e| StartupError::Key(format!("invalid descriptor: {e}")))?,
+        };
+        let self_test = self_test::run(&wallet);
+        match &self_test {
+            Ok(()) => tracing::info!("signing self-test passed"),
+            Err(e) => tracing::error!("signing self-test failed: {e}"),
+        }
 
-        let wallet = bdk_wallet::Wallet::create_single(config.xprv.clone())
-            .network(config.network)
-            .create_wallet_no_persist()
-            .expect("create wallet");
+        let audit = config
+            .audit
+            .as_ref()
+            .map(|audit| AuditLog::open(storage.clone(), audit))
+            .transpose()
+            .map_err(StartupError::section("audit"))?;
+        let attester = config
+            .attestation
+            .as_ref()
+            .map(attestation::Attester::new)
+            .transpose()
+            .map_err(StartupError::section("attestation"))?;
 
-        let app = AppState { wallet };
+        let silent_payments = config
+            .silent_payments
+            .as_ref()
+            .map(|sp| silent_payments::SilentPayments::new(&config, sp))
+            .transpose()
+            .map_err(StartupError::section("silent_payments"))?;
+        let bsms = config
+            .bsms
+            .as_ref()
+            .map(|bsms| bsms::Bsms::new(storage.clone(), &config, bsms))
+            .transpose()
+            .map_err(StartupError::section("bsms"))?;
+        let labels = config
+            .labels
+            .as_ref()
+            .map(|labels| labels::Labels::new(storage.clone(), &config, labels))
+            .transpose()
+            .map_err(StartupError::section("labels"))?;
+        #[cfg(feature = "redis")]
+        let shared = config
+            .redis
+            .as_ref()
+            .map(shared::Shared::connect)
+            .transpose()
+            .map_err(StartupError::section("redis"))?
+            .map(Arc::new);
+        #[cfg(not(feature = "redis"))]
+        if config.redis.is_some() {
+            return Err(StartupError::Section {
+                section: "redis",
+                message: "this binary was built without `redis`".into(),
+            });
+        }
+        let spend_guard = config
+            .spend_guard
+            .as_ref()
+            .map(|guard| {
+                #[cfg(feature = "redis")]
+                if let Some(shared) = &shared {
+                    return Ok(spend_guard::SpendGuard::shared(shared.clone()));
+                }
+                spend_guard::SpendGuard::new(storage.clone(), guard)
+            })
+            .transpose()
+            .map_err(StartupError::section("spend_guard"))?;
+        let journal = config
+            .journal
+            .as_ref()
+            .map(|journal| {
+                let journal = journal::Journal::open(storage.clone(), journal)?;
+                journal.recover()?;
+                Ok::<_, String>(journal)
+            })
+            .transpose()
+            .map_err(StartupError::section("journal"))?;
+        let stored_cache = config
+            .storage
+            .as_ref()
+            .is_some_and(|storage| storage.backend != config::StorageBackend::Files);
+        let response_cache = if stored_cache {
+            response_cache::ResponseCache::stored(storage.clone())
+        } else {
+            Default::default()
+        };
+        #[cfg(feature = "redis")]
+        let response_cache = match &shared {
+            Some(shared) => response_cache::ResponseCache::shared(shared.clone()),
+            None => response_cache,
+        };
+        let replication = config
+            .replication
+            .as_ref()
+            .map(|replication| replication::Replication::new(replication, audit.as_ref()))
+            .transpose()
+            .map_err(StartupError::section("replication"))?;
+        let schedules = config
+            .schedules
+            .as_ref()
+            .map(|schedules| schedule::Schedules::new(storage.clone(), &config, schedules))
+            .transpose()
+            .map_err(StartupError::section("schedules"))?;
+        #[cfg(feature = "liquid")]
+        let liquid = config
+            .liquid
+            .as_ref()
+            .map(|liquid| liquid::Liquid::new(&config, liquid))
+            .transpose()
+            .map_err(StartupError::section("liquid"))?;
+        #[cfg(not(feature = "liquid"))]
+        if config.liquid.is_some() {
+            tracing::warn!("[liquid] is set but this binary was built without `liquid`");
+        }
+
+        let signer = signer::build(&config).map_err(StartupError::section("signer"))?;
+        let signing_slots = priority::SigningSlots::new(
+            config.signing.max_concurrent,
+            config.signing.batch_slots(),
+        );
+
+        let app = AppState {
+            wallet: wallet::SharedWallet::new(wallet, wallet_store),
+            chain_synced: Default::default(),
+            chain_status: Default::default(),
+            self_test,
+            audit,
+            attester,
+            events: EventBus::new(),
+            payjoin: Default::default(),
+            channel_fundings: Default::default(),
+            quorum: Default::default(),
+            stats: Default::default(),
+            http: reqwest::Client::new(),
+            silent_payments,
+            bsms,
+            signer,
+            labels,
+            schedules,
+            spend_guard,
+            journal,
+            response_cache,
+            replication,
+            #[cfg(feature = "liquid")]
+            liquid,
+            signing_slots,
+            config_path,
+            config: RwLock::new(Arc::new(config)),
+        };
+        if let Some(journal) = &app.journal {
+            app.quorum.restore(journal.approvals());
+        }
 
         Ok(app)
     }
+
+    pub fn wallet(&self) -> std::sync::RwLockReadGuard<'_, Wallet> {
+        self.wallet.read()
+    }
+
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().expect("config lock").clone()
+    }
+
+    /// Waits for a free signing slot, interactive requests ahead of batch
+    /// ones, shedding the request with [`Error::Overloaded`] if none frees
+    /// up within the configured queue timeout.
+    ///
+    /// The permit is owned so it can move into the blocking task that does
+    /// the signing, and is only released once that work has finished.
+    pub async fn acquire_signing_permit(
+        &self,
+        priority: Priority,
+    ) -> Result<priority::SigningPermit, Error> {
+        if replication::is_standby(self) {
+            return Err(Error::Unavailable(
+                "this instance is a replication standby, promote it to sign".into(),
+            ));
+        }
+        let wait = std::time::Duration::from_millis(self.config().signing.queue_timeout_ms);
+        match self.signing_slots.acquire(priority, wait).await {
+            Some(permit) => Ok(permit),
+            None => {
+                metrics::METRICS
+                    .signing_shed
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                Err(Error::Overloaded("all signing slots are busy".into()))
+            }
+        }
+    }
+
+    /// Re-reads the config file and swaps in its non-secret settings.
+    ///
+    /// The wallet is never rebuilt here: changes to key material or the
+    /// listener are logged and ignored until the next restart.
+    pub fn reload_config(&self) -> Result<Vec<&'static str>, ConfigError> {
+        let next = if self.config().hosted {
+            Config::load_hosted(&self.config_path)?
+        } else {
+            Config::load(&self.config_path)?
+        };
+        let (next, restart_required) = self.config().reloaded(next);
+        for field in &restart_required {
+            tracing::warn!(field, "config field changed, restart required to apply it");
+        }
+        *self.config.write().expect("config lock") = Arc::new(next);
+        // Cached answers were signed under the old policies.
+        self.response_cache.clear();
+        tracing::info!(path = %self.config_path.display(), "config reloaded");
+        self.events.publish(Event::ConfigReloaded {
+            restart_required: restart_required.clone(),
+        });
+        Ok(restart_required)
+    }
+
+    /// Rebuilds the wallet from the descriptors in the config file and what
+    /// its store holds now, as after a restore or migration done outside
+    /// the service, and swaps it in once it passes the signing self-test.
+    /// The rest of the config is left to [`AppState::reload_config`].
+    pub async fn reload_wallet(&self) -> Result<WalletReloadResponse, Error> {
+        let file = if self.config().hosted {
+            Config::load_hosted(&self.config_path)?
+        } else {
+            Config::load(&self.config_path)?
+        };
+        let running = self.config();
+        if file.network != running.network {
+            return Err(ConfigError::Invalid {
+                path: self.config_path.display().to_string(),
+                message: format!(
+                    "network is {}, the wallet runs on {}; restart to change it",
+                    file.network, running.network
+                ),
+            }
+            .into());
+        }
+        let mut next = (*running).clone();
+        // Every load generates another throwaway key; keep the running one.
+        if !(file.throwaway_key && running.throwaway_key) {
+            next.xprv = file.xprv;
+            next.descriptor = file.descriptor;
+            next.key_file = file.key_file;
+            next.fingerprint = file.fingerprint;
+        }
+        next.change_xprv = file.change_xprv;
+        next.change_descriptor = file.change_descriptor;
+        let descriptors_changed =
+            next.xprv != running.xprv || next.change_xprv != running.change_xprv;
+        descriptor::check_key_network(&next.xprv, next.network).map_err(|e| {
+            Error::InvalidConfig(ConfigError::Invalid {
+                path: self.config_path.display().to_string(),
+                message: e,
+            })
+        })?;
+
+        let last_checkpoint = self.wallet.reload(next.clone()).await?;
+        let mut restart_required = Vec::new();
+        if descriptors_changed {
+            // These derived their keys from the descriptor at startup.
+            for (section, set) in [
+                ("silent_payments", running.silent_payments.is_some()),
+                ("bsms", running.bsms.is_some()),
+                ("liquid", running.liquid.is_some()),
+            ] {
+                if set {
+                    tracing::warn!(section, "keeps the previous key, restart required");
+                    restart_required.push(section);
+                }
+            }
+            *self.config.write().expect("config lock") = Arc::new(next);
+        }
+        // The next sync scans the reloaded wallet from scratch.
+        self.chain_synced
+            .store(false, std::sync::atomic::Ordering::Release);
+        self.response_cache.clear();
+        tracing::info!(
+            path = %self.config_path.display(),
+            descriptors_changed,
+            last_checkpoint,
+            "wallet reloaded"
+        );
+        self.events.publish(Event::WalletReloaded {
+            descriptors_changed,
+        });
+        Ok(WalletReloadResponse {
+            descriptors_changed,
+            last_checkpoint,
+            restart_required,
+        })
+    }
 }
 
 #[tokio::main]
-async fn main() {
-    use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+async fn main() -> ExitCode {
+    match start().await {
+        Ok(code) => code,
+        // Before the subscriber is installed, errors only reach stderr.
+        Err(e) if tracing::dispatcher::has_been_set() => {
+            tracing::error!("{e}");
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer().with_timer(
-            tracing_subscriber::fmt::time::ChronoLocal::new("%FT%H:%M:%S%z".to_owned()),
-        ))
-        .init();
-    let config_path = std::env::args().nth(1).expect("config path");
-    let config = std::fs::read_to_string(config_path).unwrap();
-    let config: Config = toml::from_str(&config).unwrap();
+async fn start() -> Result<ExitCode, StartupError> {
+    let mut args = std::env::args().skip(1);
+    let first = args.next().ok_or(StartupError::Usage)?;
+    if first == "bench" {
+        logging::init(None).map_err(StartupError::Logging)?;
+        let config_path = PathBuf::from(args.next().ok_or(StartupError::Usage)?);
+        return Ok(bench::run(&config_path, args));
+    }
+    #[cfg(feature = "e2e")]
+    if first == "e2e" {
+        return Ok(e2e::run().await);
+    }
+    if first == "decrypt-backup" {
+        return Ok(backup::run(args));
+    }
+    if first == "migrate-config" {
+        return Ok(migrate::run(args));
+    }
+    if first == "check-config" {
+        logging::init(None).map_err(StartupError::Logging)?;
+        let config_path = PathBuf::from(args.next().ok_or(StartupError::Usage)?);
+        return Ok(check::check_config(&config_path));
+    }
+    let file_drop = first == "file-drop";
+    let config_path = if file_drop {
+        PathBuf::from(args.next().ok_or(StartupError::Usage)?)
+    } else {
+        PathBuf::from(first)
+    };
+    let config = Config::load(&config_path)?;
+    let _log_guard = logging::init(config.log.as_ref()).map_err(StartupError::Logging)?;
 
-    run(config).await;
+    if file_drop {
+        return Ok(file_drop::run(config, config_path).await);
+    }
+    if !config.has_listener() {
+        return Err(StartupError::NoListener);
+    }
+    run(config, config_path).await?;
+    Ok(ExitCode::SUCCESS)
 }
 
+async fn not_found(uri: axum::http::Uri) -> Error {
+    Error::NotFound(uri.path().to_owned())
+}
 
+#[utoipa::path(get, path = "/health", responses((status = 200, description = "Process is alive", body = String)))]
 async fn health() -> &'static str {
     "ok"
 }
 
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ReadyResponse {
+    pub ready: bool,
+    pub self_test: SelfTestStatus,
+    /// Set on a replication standby, which does not sign until promoted.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub standby: bool,
+    pub wallet: WalletStatus,
+    /// Unset without `[chain]`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chain: Option<chain::ChainStatus>,
+    /// The spend guard's records, unset without `[spend_guard]`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy_store: Option<StoreStatus>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct SelfTestStatus {
+    pub passed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct WalletStatus {
+    /// `free`, `busy` while a change is applied, or `poisoned`, which
+    /// leaves the wallet unusable until restart.
+    pub lock: &'static str,
+    /// Whether the writer keeping the wallet's changes still runs.
+    pub writer_running: bool,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct StoreStatus {
+    pub healthy: bool,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/ready",
+    responses(
+        (status = 200, description = "Ready to sign", body = ReadyResponse),
+        (status = 503, description = "The startup signing self-test failed, the wallet or the policy store is unusable, or this is a standby", body = ReadyResponse),
+    )
+)]
+async fn ready(
+    State(state): State<Arc<AppState>>,
+) -> (axum::http::StatusCode, Json<ReadyResponse>) {
+    let (status, body) = ready_info(&state).await;
+    (status, Json(body))
+}
+
+async fn ready_info(state: &Arc<AppState>) -> (axum::http::StatusCode, ReadyResponse) {
+    let self_test = SelfTestStatus {
+        passed: state.self_test.is_ok(),
+        error: state.self_test.clone().err(),
+    };
+    let standby = replication::is_standby(state);
+    let wallet = WalletStatus {
+        lock: state.wallet.lock_status(),
+        writer_running: state.wallet.writer_running(),
+    };
+    let wallet_usable = wallet.lock != "poisoned" && wallet.writer_running;
+    let chain = chain::status(state, wallet_usable);
+    let policy_store = match state.spend_guard.is_some() {
+        true => Some(probe_policy_store(state.clone()).await),
+        false => None,
+    };
+    let ready = self_test.passed
+        && !standby
+        && wallet_usable
+        && policy_store.as_ref().map_or(true, |store| store.healthy);
+    let status = if ready {
+        axum::http::StatusCode::OK
+    } else {
+        axum::http::StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = ReadyResponse {
+        ready,
+        self_test,
+        standby,
+        wallet,
+        chain,
+        policy_store,
+    };
+    (status, body)
+}
+
+/// Reads the spend guard's records back from their backend, off the async
+/// workers since a shared backend answers over the network.
+async fn probe_policy_store(state: Arc<AppState>) -> StoreStatus {
+    let started = std::time::Instant::now();
+    let probed = run_blocking(move || {
+        let guard = state.spend_guard.as_ref().expect("spend guard");
+        Ok(guard.probe())
+    })
+    .await
+    .unwrap_or_else(|e| Err(e.to_string()));
+    StoreStatus {
+        healthy: probed.is_ok(),
+        latency_ms: started.elapsed().as_millis() as u64,
+        error: probed.err(),
+    }
+}
+
+/// Cargo features compiled into this binary.
+const ENABLED_FEATURES: &[&str] = &[
+    #[cfg(feature = "grpc")]
+    "grpc",
+    #[cfg(feature = "nostr")]
+    "nostr",
+    #[cfg(feature = "liquid")]
+    "liquid",
+    #[cfg(feature = "tls")]
+    "tls",
+    #[cfg(feature = "redis")]
+    "redis",
+    #[cfg(feature = "sqlite")]
+    "sqlite",
+    #[cfg(feature = "e2e")]
+    "e2e",
+];
+
+/// Kinds of signers this binary can sign with.
+const SIGNERS: &[&str] = config::SignerConfig::KINDS;
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct VersionResponse {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub git_commit: &'static str,
+    pub build_timestamp: String,
+    pub features: &'static [&'static str],
+    pub signers: &'static [&'static str],
+    #[schema(value_type = String, example = "bitcoin")]
+    pub network: bitcoin::Network,
+    /// Hex key signed PSBTs are attested with, with `[attestation]`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attestation_key: Option<String>,
+}
+
+#[utoipa::path(get, path = "/version", responses((status = 200, body = VersionResponse)))]
+async fn version(State(state): State<Arc<AppState>>) -> Json<VersionResponse> {
+    Json(version_info(&state))
+}
+
+fn version_info(state: &AppState) -> VersionResponse {
+    let build_timestamp = env!("ISSUE_SERVICE_BUILD_TIMESTAMP")
+        .parse()
+        .ok()
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+        .map(|time| time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+        .unwrap_or_default();
+    VersionResponse {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: env!("ISSUE_SERVICE_GIT_COMMIT"),
+        build_timestamp,
+        features: ENABLED_FEATURES,
+        signers: SIGNERS,
+        network: state.config().network,
+        attestation_key: state
+            .attester
+            .as_ref()
+            .map(|attester| attester.public_key().to_string()),
+    }
+}
+
 use axum::routing::get;
 
-async fn run(config: Config) {
-    let state = AppState::init(&config).await.unwrap();
-    let listen = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", config.port))
-        .await
-        .unwrap();
+async fn run(config: Config, config_path: PathBuf) -> Result<(), StartupError> {
+    let port = config.port;
+    let listen_addrs = config.listen.clone();
+    let admin_listen = config.admin_listen;
+    let grpc_listen = config.grpc_listen;
+    let unix_socket = config.unix_socket.clone();
+    let nostr = config.nostr.clone();
+    let hosted = networks::load(&config).map_err(StartupError::section("networks"))?;
+    let state = AppState::init(config, config_path).await?;
+    let listen = bind(port).await?;
+    let listens = bind_all(&listen_addrs, state.config().http.http2).await?;
     let state = Arc::new(state);
+    spawn_tasks(&state);
+    let mut hosted_states = Vec::with_capacity(hosted.len());
+    for networks::Hosted {
+        network,
+        path,
+        config,
+    } in hosted
+    {
+        let hosted = AppState::init(config, path)
+            .await
+            .map_err(|e| StartupError::Section {
+                section: "networks",
+                message: format!("{network}: {e}"),
+            })?;
+        let hosted = Arc::new(hosted);
+        spawn_tasks(&hosted);
+        tracing::info!(%network, prefix = networks::prefix(network), "hosting wallet");
+        hosted_states.push((network, hosted));
+    }
+    match nostr {
+        #[cfg(feature = "nostr")]
+        Some(nostr) => {
+            nostr_transport::spawn(state.clone(), &nostr).map_err(StartupError::section("nostr"))?
+        }
+        #[cfg(not(feature = "nostr"))]
+        Some(_) => tracing::warn!("[nostr] is set but this binary was built without `nostr`"),
+        None => {}
+    }
+    let mut router = public_router(&state);
+    for (network, hosted) in &hosted_states {
+        router = router.nest(&networks::prefix(*network), public_router(hosted));
+    }
+    let router = router.merge(api_doc::swagger_ui()).fallback(not_found);
+    let router = match cors::layer(&state.config().cors).map_err(StartupError::section("cors"))? {
+        Some(cors) => router.layer(cors),
+        None => router,
+    };
+    let router = with_request_tracing(router);
+    let mut admin = admin_router(&state);
+    for (network, hosted) in &hosted_states {
+        admin = admin.nest(&networks::prefix(*network), admin_router(hosted));
+    }
+    let admin_router = with_request_tracing(admin.fallback(not_found));
+
+    #[cfg(unix)]
+    let unix_listen = match unix_socket {
+        Some(socket) => {
+            let listener = unix_socket::bind(&socket).map_err(|source| StartupError::Bind {
+                listener: "unix_socket",
+                addr: socket.path.display().to_string(),
+                source,
+            })?;
+            tracing::info!("listen on: {}", socket.path.display());
+            Some(listener)
+        }
+        None => None,
+    };
+    #[cfg(not(unix))]
+    if unix_socket.is_some() {
+        tracing::warn!("unix_socket is set but this platform has no Unix sockets");
+    }
+    let admin = match admin_listen {
+        Some(addr) => {
+            let admin_listen = tokio::net::TcpListener::bind(addr)
+                .await
+                .map_err(|source| StartupError::Bind {
+                    listener: "admin_listen",
+                    addr: addr.to_string(),
+                    source,
+                })?;
+            tracing::info!("admin listen on: {addr}");
+            Some(admin_listen)
+        }
+        None => {
+            tracing::info!("admin_listen not configured, admin endpoints disabled");
+            None
+        }
+    };
+    #[cfg(unix)]
+    {
+        if state.self_test.is_ok() && hosted_states.iter().all(|(_, h)| h.self_test.is_ok()) {
+            systemd::notify_ready();
+        } else {
+            tracing::warn!("not notifying systemd readiness, signing self-test failed");
+        }
+        systemd::spawn_watchdog();
+    }
+
+    // Every listener runs until the process exits; the first one to fail
+    // takes the service down with it.
+    let mut servers = tokio::task::JoinSet::new();
+    #[cfg(unix)]
+    if let Some(unix_listen) = unix_listen {
+        let server = server::serve(
+            unix_listen,
+            router.clone(),
+            state.clone(),
+            unix_socket::UnixPeer::of,
+        );
+        servers.spawn(async { ("unix_socket", server.await) });
+    }
+    for bound in listens {
+        #[cfg(feature = "tls")]
+        if let Some(acceptor) = bound.tls {
+            let server = server::serve_tls(bound.listener, acceptor, router.clone(), state.clone());
+            servers.spawn(async { ("listen", server.await) });
+            continue;
+        }
+        let server = server::serve(bound.listener, router.clone(), state.clone(), tcp_peer);
+        servers.spawn(async { ("listen", server.await) });
+    }
+    if let Some(listen) = listen {
+        let server = server::serve(listen, router, state.clone(), tcp_peer);
+        servers.spawn(async { ("port", server.await) });
+    }
+    if let Some(admin_listen) = admin {
+        let server = server::serve(admin_listen, admin_router, state.clone(), tcp_peer);
+        servers.spawn(async { ("admin_listen", server.await) });
+    }
+    match grpc_listen {
+        #[cfg(feature = "grpc")]
+        Some(addr) => {
+            tracing::info!("grpc listen on: {addr}");
+            let server = grpc::serve(state, addr);
+            servers.spawn(async { ("grpc_listen", server.await) });
+        }
+        #[cfg(not(feature = "grpc"))]
+        Some(_) => tracing::warn!("grpc_listen is set but this binary was built without `grpc`"),
+        None => {}
+    }
+    while let Some(server) = servers.join_next().await {
+        match server {
+            Ok((_, Ok(()))) => {}
+            Ok((listener, Err(e))) => {
+                return Err(StartupError::Serve {
+                    listener,
+                    message: e.to_string(),
+                })
+            }
+            Err(e) => {
+                return Err(StartupError::Serve {
+                    listener: "listener",
+                    message: e.to_string(),
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Starts the background tasks of the wallet of `state`: config reloads on
+/// SIGHUP and, with `[chain]`, syncing and what depends on it.
+fn spawn_tasks(state: &Arc<AppState>) {
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(state.clone()));
+    let config = state.config();
+    if let Some(replication) = &config.replication {
+        if let Some(primary_url) = &replication.primary_url {
+            tokio::spawn(replication::pull_forever(
+                state.clone(),
+                replication.clone(),
+                primary_url.clone(),
+            ));
+        }
+    }
+    let consolidation = config.consolidation.clone();
+    match config.chain.clone() {
+        Some(chain) => {
+            if state.silent_payments.is_some() {
+                tokio::spawn(silent_payments::scan_forever(state.clone(), chain.clone()));
+            }
+            if let Some(consolidation) = consolidation {
+                if let Some(interval_secs) = consolidation.interval_secs {
+                    tokio::spawn(consolidation::consolidate_forever(
+                        state.clone(),
+                        chain.clone(),
+                        consolidation,
+                        interval_secs,
+                    ));
+                }
+            }
+            if state.schedules.is_some() {
+                tokio::spawn(schedule::run_forever(state.clone()));
+            }
+            tokio::spawn(chain::sync_forever(state.clone(), chain));
+        }
+        None => {
+            if state.silent_payments.is_some() {
+                tracing::warn!(
+                    "silent_payments is set without [chain], payments are not scanned for"
+                );
+            }
+            if consolidation.is_some() {
+                tracing::warn!("consolidation is set without [chain], coins are not consolidated");
+            }
+            if state.schedules.is_some() {
+                tracing::warn!("schedules are set without [chain], none of them run");
+            }
+        }
+    }
+}
+
+/// Public endpoints of the wallet of `state`.
+fn public_router(state: &Arc<AppState>) -> axum::Router {
     let router = axum::Router::new()
-        .route("/sign_psbt", post(sign_service))
         .route("/health", get(health))
-        .with_state(state)
-        .layer(tower_http::cors::CorsLayer::permissive())
-        .layer(tower_http::trace::TraceLayer::new_for_http());
+        .route("/ready", get(ready))
+        .route("/version", get(version))
+        .route("/silent_payments/address", get(silent_payment_address))
+        .route("/parse_descriptor", post(descriptor::parse))
+        .route("/qr/encode", post(qr::encode))
+        .route("/qr/decode", post(qr::decode))
+        .route("/payment_uri", post(bip21::payment_uri))
+        .route("/debug/test_vectors", get(test_vectors::get))
+        .route("/proof_of_reserves/verify", post(reserves::verify_handler))
+        .route("/diff_psbt", post(psbt_diff::diff_handler))
+        .route("/sign_requests/{request_id}", get(journal::get))
+        .route_layer(from_fn_with_state(
+            (state.clone(), RouteTimeout::Default),
+            timeout::enforce_timeout,
+        ))
+        .route(
+            "/sign_psbt",
+            post(sign_service).layer(from_fn_with_state(
+                (state.clone(), RouteTimeout::Sign),
+                timeout::enforce_timeout,
+            )),
+        )
+        .route(
+            "/sign_psbt/binary",
+            post(sign_binary).layer(from_fn_with_state(
+                (state.clone(), RouteTimeout::Sign),
+                timeout::enforce_timeout,
+            )),
+        )
+        .route("/ws", get(ws::upgrade))
+        .route(
+            "/payjoin",
+            post(payjoin::handle).layer(from_fn_with_state(
+                (state.clone(), RouteTimeout::Sign),
+                timeout::enforce_timeout,
+            )),
+        )
+        .route(
+            "/rpc",
+            post(rpc::handle).layer(from_fn_with_state(
+                (state.clone(), RouteTimeout::Sign),
+                timeout::enforce_timeout,
+            )),
+        );
+    #[cfg(feature = "liquid")]
+    let router = router.merge(liquid::routes(state));
+    router
+        .route_layer(from_fn_with_state(state.clone(), auth::require))
+        .with_state(state.clone())
+}
 
-    tracing::info!("listen on: {}", listen.local_addr().unwrap());
-    axum::serve(listen, router).await.unwrap();
+fn tcp_peer(_: &tokio::net::TcpStream, addr: SocketAddr) -> SocketAddr {
+    addr
 }
 
+/// Control-plane endpoints of the wallet of `state`, served only on
+/// `admin_listen`.
+fn admin_router(state: &Arc<AppState>) -> axum::Router {
+    axum::Router::new()
+        .route("/admin/reload_config", post(reload_config))
+        .route("/admin/reload_wallet", post(reload_wallet))
+        .route("/admin/audit", get(query_audit))
+        .route("/admin/audit/export", get(export_audit))
+        .route("/admin/backup", get(backup::export))
+        .route("/admin/metrics", get(render_metrics))
+        .route("/admin/stats", get(stats::serve))
+        .route(
+            "/admin/channel_fundings",
+            get(channel_funding::list).post(channel_funding::register),
+        )
+        .route(
+            "/admin/channel_fundings/{id}",
+            axum::routing::delete(channel_funding::remove),
+        )
+        .route(
+            "/admin/channel_fundings/{id}/commitment",
+            post(channel_funding::register_commitment),
+        )
+        .route("/admin/proof_of_reserves", post(reserves::prove))
+        .route("/admin/utxo_proof", post(ownership::prove))
+        .route("/admin/sweep", post(sweep::sweep))
+        .route("/admin/consolidate", post(consolidation::consolidate))
+        .route("/admin/create_payout", post(payout::create_payout))
+        .route("/admin/create_psbt", post(payout::create_psbt))
+        .route("/admin/derive_xpub", post(xpub::derive))
+        .route("/admin/policies", get(descriptor::policies))
+        .route("/admin/quorum", get(quorum::list))
+        .route("/admin/sign_message", post(signer::sign_message))
+        .route("/admin/signer/attestation", get(signer::attestation))
+        .route("/admin/schedules", get(schedule::list).post(schedule::add))
+        .route(
+            "/admin/schedules/{name}",
+            axum::routing::delete(schedule::remove),
+        )
+        .route("/admin/schedules/{name}/run", post(schedule::run_now))
+        .route("/admin/schedules/{name}/runs", get(schedule::runs))
+        .route("/admin/spent_outpoints", get(spend_guard::list))
+        .route(
+            "/admin/spent_outpoints/{outpoint}",
+            axum::routing::delete(spend_guard::remove),
+        )
+        .route("/admin/replication/state", get(replication::serve_state))
+        .route("/admin/replication/status", get(replication::status))
+        .route("/admin/replication/promote", post(replication::promote))
+        .route("/admin/utxos", get(coins::utxos))
+        .route("/admin/transactions", get(coins::transactions))
+        .route("/admin/labels", get(labels::list).post(labels::set))
+        .route("/admin/labels/export", get(labels::export))
+        .route("/admin/labels/import", post(labels::import))
+        .route(
+            "/admin/labels/{type}/{ref}",
+            axum::routing::delete(labels::remove),
+        )
+        .route("/admin/bsms/key_record", post(bsms::key_record))
+        .route("/admin/bsms/wallets", get(bsms::list).post(bsms::register))
+        .route(
+            "/admin/bsms/wallets/{name}",
+            axum::routing::delete(bsms::remove),
+        )
+        .route(
+            "/admin/silent_payments/outputs",
+            get(silent_payment_outputs),
+        )
+        .route_layer(from_fn_with_state(
+            (state.clone(), RouteTimeout::Default),
+            timeout::enforce_timeout,
+        ))
+        .route_layer(from_fn_with_state(state.clone(), auth::require))
+        .with_state(state.clone())
+}
+
+/// Honors an incoming `X-Request-Id` or generates one, records it on the
+/// request span so every log line carries it, and echoes it back on the
+/// response, error responses included. Also writes the access log, turns
+/// handler panics into `500` responses, answers conditional GETs, speaks
+/// CBOR to clients that send or accept it, and compresses responses of more
+/// than [`COMPRESS_ABOVE`] bytes for clients that accept gzip or brotli.
+fn with_request_tracing(router: axum::Router) -> axum::Router {
+    use tower_http::{
+        catch_panic::CatchPanicLayer,
+        compression::{
+            predicate::{DefaultPredicate, Predicate, SizeAbove},
+            CompressionLayer,
+        },
+        request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    };
+
+    let compression = CompressionLayer::new()
+        .compress_when(DefaultPredicate::new().and(SizeAbove::new(COMPRESS_ABOVE)));
+    router
+        .layer(axum::middleware::from_fn(cbor::transcode))
+        .layer(axum::middleware::from_fn(etag::conditional))
+        .layer(compression)
+        .layer(CatchPanicLayer::custom(handler_panicked))
+        .layer(axum::middleware::from_fn(error::with_request_id))
+        .layer(axum::middleware::from_fn(access_log::access_log))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(tower_http::trace::TraceLayer::new_for_http().make_span_with(request_span))
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+}
+
+/// Smaller responses gain too little from compression to pay for it.
+const COMPRESS_ABOVE: u16 = 1024;
+
+fn handler_panicked(panic: Box<dyn std::any::Any + Send + 'static>) -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    let message = panic
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic");
+    metrics::METRICS
+        .handler_panics
+        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    tracing::error!(alert = true, "handler panicked: {message}");
+    Error::Internal("request handler panicked".into()).into_response()
+}
+
+fn request_span(request: &axum::http::Request<axum::body::Body>) -> tracing::Span {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        request_id,
+    )
+}
+
+/// Uses the socket handed over by systemd socket activation when present,
+/// otherwise binds `port` on all interfaces. Returns `None` when neither is
+/// available and only the Unix socket is served.
+async fn bind(port: Option<u16>) -> Result<Option<tokio::net::TcpListener>, StartupError> {
+    #[cfg(unix)]
+    if let Some(listener) = systemd::activated_listener()
+        .and_then(|listener| listener.map(tokio::net::TcpListener::from_std).transpose())
+        .map_err(|source| StartupError::Bind {
+            listener: "port",
+            addr: "the systemd socket".into(),
+            source,
+        })?
+    {
+        log_listen(&listener);
+        return Ok(Some(listener));
+    }
+    let Some(port) = port else {
+        return Ok(None);
+    };
+    let addr = format!("0.0.0.0:{port}");
+    let listener = tokio::net::TcpListener::bind(&addr)
+        .await
+        .map_err(|source| StartupError::Bind {
+            listener: "port",
+            addr,
+            source,
+        })?;
+    log_listen(&listener);
+    Ok(Some(listener))
+}
+
+/// A bound `[[listen]]` address.
+struct Bound {
+    listener: tokio::net::TcpListener,
+    #[cfg(feature = "tls")]
+    tls: Option<tokio_rustls::TlsAcceptor>,
+}
+
+/// Binds every `[[listen]]` address, loading its TLS settings first so a
+/// bad certificate fails startup before anything is served.
+#[cfg_attr(not(feature = "tls"), allow(unused_variables))]
+async fn bind_all(addrs: &[config::ListenConfig], http2: bool) -> Result<Vec<Bound>, StartupError> {
+    let mut bound = Vec::with_capacity(addrs.len());
+    for listen in addrs {
+        #[cfg(feature = "tls")]
+        let tls = listen
+            .tls
+            .as_ref()
+            .map(|tls| tls::acceptor(tls, http2))
+            .transpose()
+            .map_err(|e| StartupError::Section {
+                section: "listen",
+                message: format!("{}: {e}", listen.addr),
+            })?;
+        #[cfg(not(feature = "tls"))]
+        if listen.tls.is_some() {
+            return Err(StartupError::Section {
+                section: "listen",
+                message: format!(
+                    "{}: tls is set but this binary was built without `tls`",
+                    listen.addr
+                ),
+            });
+        }
+        let listener = tokio::net::TcpListener::bind(listen.addr)
+            .await
+            .map_err(|source| StartupError::Bind {
+                listener: "listen",
+                addr: listen.addr.to_string(),
+                source,
+            })?;
+        let scheme = if listen.tls.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        tracing::info!("listen on: {scheme}://{}", listen.addr);
+        bound.push(Bound {
+            listener,
+            #[cfg(feature = "tls")]
+            tls,
+        });
+    }
+    Ok(bound)
+}
+
+fn log_listen(listener: &tokio::net::TcpListener) {
+    match listener.local_addr() {
+        Ok(addr) => tracing::info!("listen on: {addr}"),
+        Err(e) => tracing::info!("listen on: unknown address: {e}"),
+    }
+}
+
+async fn render_metrics() -> String {
+    metrics::METRICS.render()
+}
+
+#[derive(Serialize)]
+struct SilentPaymentAddress {
+    address: String,
+}
+
+async fn silent_payment_address(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<SilentPaymentAddress>, Error> {
+    let sp = state
+        .silent_payments
+        .as_ref()
+        .ok_or_else(|| Error::NotFound("silent payments are not configured".into()))?;
+    Ok(Json(SilentPaymentAddress {
+        address: sp.address(),
+    }))
+}
+
+async fn silent_payment_outputs(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<silent_payments::OutputsResponse>, Error> {
+    let sp = state
+        .silent_payments
+        .as_ref()
+        .ok_or_else(|| Error::NotFound("silent payments are not configured".into()))?;
+    Ok(Json(sp.outputs()))
+}
+
+#[cfg(unix)]
+async fn reload_on_sighup(state: Arc<AppState>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup()).expect("install SIGHUP handler");
+    while hangup.recv().await.is_some() {
+        tracing::info!("received SIGHUP, reloading config");
+        if let Err(e) = state.reload_config() {
+            tracing::error!("config reload failed, keeping current config: {e}");
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ReloadConfigResponse {
+    pub restart_required: Vec<&'static str>,
+}
+
+async fn reload_config(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ReloadConfigResponse>, Error> {
+    let restart_required = state.reload_config()?;
+    Ok(Json(ReloadConfigResponse { restart_required }))
+}
+
+#[derive(Serialize)]
+pub struct WalletReloadResponse {
+    pub descriptors_changed: bool,
+    /// Height of the reloaded wallet's latest checkpoint.
+    pub last_checkpoint: u32,
+    /// Sections still using the previous keys until a restart.
+    pub restart_required: Vec<&'static str>,
+}
+
+async fn reload_wallet(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<WalletReloadResponse>, Error> {
+    state.reload_wallet().await.map(Json)
+}
+
+fn audit_log(state: &AppState) -> Result<&AuditLog, Error> {
+    state
+        .audit
+        .as_ref()
+        .ok_or_else(|| Error::NotFound("audit log is not configured".into()))
+}
+
+async fn query_audit(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<AuditFilter>,
+    Query(query): Query<page::PageQuery>,
+) -> Result<page::Page<AuditEntry>, Error> {
+    let entries = audit_log(&state)?
+        .query(&filter)
+        .map_err(|e| Error::Internal(format!("reading audit log: {e}")))?;
+    let page = page::paginate(entries, &query, page::Order::Asc, |(index, _)| {
+        page::Key::new(*index as u64, "")
+    })?;
+    Ok(page.map(|(_, entry)| entry))
+}
+
+async fn export_audit(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<AuditFilter>,
+) -> Result<impl axum::response::IntoResponse, Error> {
+    let snapshot = audit_log(&state)?
+        .export(&filter)
+        .map_err(|e| Error::Internal(format!("reading audit log: {e}")))?;
+    Ok((
+        [(axum::http::header::CONTENT_TYPE, "application/x-ndjson")],
+        snapshot,
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/sign_psbt",
+    request_body(description = "A JSON request, or a raw BIP-174 PSBT with the headers of `/sign_psbt/binary`", content(
+        (SignRequest = "application/json"),
+        (BinaryPsbt = "application/octet-stream"),
+    )),
+    params(
+        ("accept" = Option<String>, Header, description = "`application/json` or `application/octet-stream`; the encoding of the body when absent"),
+    ),
+    responses(
+        (status = 200, description = "Signed PSBT", content(
+            (SignResponse = "application/json"),
+            (BinaryPsbt = "application/octet-stream"),
+        )),
+        (status = 400, description = "`malformed_request` or `invalid_transaction`", body = ErrorResponse),
+        (status = 406, description = "`not_acceptable`", body = ErrorResponse),
+        (status = 413, description = "`payload_too_large`", body = ErrorResponse),
+        (status = 415, description = "`unsupported_media_type`", body = ErrorResponse),
+        (status = 202, description = "`quorum_pending`", body = ErrorResponse),
+        (status = 401, description = "`unauthorized`", body = ErrorResponse),
+        (status = 403, description = "`approval_denied` or `outside_signing_window`", body = ErrorResponse),
+        (status = 422, description = "`consignment_rejected`", body = ErrorResponse),
+        (status = 503, description = "`overloaded`", body = ErrorResponse),
+        (status = 504, description = "`timeout`", body = ErrorResponse),
+    )
+)]
 async fn sign_service(
     State(state): State<Arc<AppState>>,
-    Json(req): Json<SignRequest>,
-) -> Result<Json<SignResponse>, Error> {
-    let mut signed_psbt = req.psbt;
+    ctx: RequestContext,
+    request: axum::extract::Request,
+) -> Result<axum::response::Response, Error> {
+    use axum::extract::FromRequest;
 
-    let sign_options = SignOptions {
-        trust_witness_utxo: true,
-        allow_all_sighashes: true,
-        ..Default::default()
+    let sent = negotiate::Encoding::of_body(request.headers()).unwrap_or(negotiate::Encoding::Json);
+    let encoding = negotiate::negotiate(request.headers(), sent)?;
+    let request = match sent {
+        negotiate::Encoding::Json => {
+            ApiJson::<SignRequest>::from_request(request, &state)
+                .await?
+                .0
+        }
+        negotiate::Encoding::Binary => {
+            let (parts, body) = request.into_parts();
+            binary_request(&state, &parts.headers, body).await?
+        }
     };
-    state
-        .wallet
-        .sign(&mut signed_psbt, sign_options)
-        .map_err(|e| Error::InvalidTransaction(format!("signing failed: {e}")))?;
+    Ok(respond(&state, &ctx, request, encoding).await)
+}
+
+/// Media type of raw BIP-174 PSBTs on `/sign_psbt/binary`.
+const BINARY_PSBT: &str = "application/octet-stream";
+/// Marks a binary response left unsigned by `signing.dry_run`.
+const DRY_RUN_HEADER: &str = "x-dry-run";
+/// Marks a binary response answered from the response cache.
+const CACHED_HEADER: &str = "x-cached";
+/// Caller metadata of a binary request, as a JSON object.
+const METADATA_HEADER: &str = "x-metadata";
+/// Set to `true` on a binary request to sign a replacement.
+const REPLACE_HEADER: &str = "x-replace";
+/// `batch` on a binary request to yield to interactive ones.
+const PRIORITY_HEADER: &str = "x-priority";
+/// Set to `true` on a binary request to declare it collaborative.
+const COLLABORATIVE_HEADER: &str = "x-collaborative";
 
-    Ok(Json(SignResponse { psbt: signed_psbt }))
+/// Schema of a raw BIP-174 PSBT body, for the API docs.
+#[derive(utoipa::ToSchema)]
+#[schema(value_type = String, format = Binary)]
+struct BinaryPsbt(#[allow(dead_code)] Vec<u8>);
+
+#[utoipa::path(
+    post,
+    path = "/sign_psbt/binary",
+    request_body(content = BinaryPsbt, content_type = "application/octet-stream", description = "Raw BIP-174 PSBT"),
+    params(
+        ("x-metadata" = Option<String>, Header, description = "Caller metadata as a JSON object, recorded in the audit entry"),
+        ("x-replace" = Option<bool>, Header, description = "`true` to sign a coin already signed into another transaction"),
+        ("x-priority" = Option<String>, Header, description = "`interactive` (default) or `batch`"),
+        ("x-collaborative" = Option<bool>, Header, description = "`true` to sign a transaction shared with other wallets"),
+        ("accept" = Option<String>, Header, description = "`application/octet-stream` (default) or `application/json`"),
+    ),
+    responses(
+        (status = 200, description = "Signed PSBT", content(
+            (BinaryPsbt = "application/octet-stream"),
+            (SignResponse = "application/json"),
+        )),
+        (status = 400, description = "`malformed_request` or `invalid_transaction`", body = ErrorResponse),
+        (status = 406, description = "`not_acceptable`", body = ErrorResponse),
+        (status = 413, description = "`payload_too_large`", body = ErrorResponse),
+        (status = 415, description = "`unsupported_media_type`", body = ErrorResponse),
+        (status = 503, description = "`overloaded`", body = ErrorResponse),
+        (status = 504, description = "`timeout`", body = ErrorResponse),
+    )
+)]
+async fn sign_binary(
+    State(state): State<Arc<AppState>>,
+    ctx: RequestContext,
+    headers: axum::http::HeaderMap,
+    body: axum::body::Body,
+) -> Result<axum::response::Response, Error> {
+    let encoding = negotiate::negotiate(&headers, negotiate::Encoding::Binary)?;
+    let request = binary_request(&state, &headers, body).await?;
+    Ok(respond(&state, &ctx, request, encoding).await)
 }
 
-#[derive(serde::Deserialize)]
-pub struct SignRequest {
-    #[serde(deserialize_with = "de_psbt_from_base64")]
-    pub psbt: Psbt,
+/// The signing request of a raw PSBT `body`, with the options `headers`
+/// carry.
+async fn binary_request(
+    state: &AppState,
+    headers: &axum::http::HeaderMap,
+    body: axum::body::Body,
+) -> Result<SignRequest, Error> {
+    if negotiate::Encoding::of_body(headers) != Some(negotiate::Encoding::Binary) {
+        return Err(Error::UnsupportedMediaType(format!(
+            "expected `Content-Type: {BINARY_PSBT}`"
+        )));
+    }
+    let metadata = match headers.get(METADATA_HEADER) {
+        Some(value) => value
+            .to_str()
+            .map_err(|e| e.to_string())
+            .and_then(|value| serde_json::from_str(value).map_err(|e| e.to_string()))
+            .map_err(|e| Error::MalformedRequest(format!("{METADATA_HEADER}: {e}")))?,
+        None => Default::default(),
+    };
+    let replace = bool_header(headers, REPLACE_HEADER)?;
+    let collaborative = bool_header(headers, COLLABORATIVE_HEADER)?;
+    let priority = match headers.get(PRIORITY_HEADER) {
+        Some(value) => value
+            .to_str()
+            .map_err(|e| e.to_string())
+            .and_then(|value| value.parse())
+            .map_err(|e| Error::MalformedRequest(format!("{PRIORITY_HEADER}: {e}")))?,
+        None => Priority::Interactive,
+    };
+    let limit = state.config().http.max_binary_psbt_bytes;
+    let psbt = upload::read_psbt(headers, body, limit).await?;
+    Ok(SignRequest {
+        metadata,
+        replace,
+        priority,
+        collaborative,
+        ..psbt.into()
+    })
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct SignResponse {
-    #[serde(serialize_with = "serialize_psbt_to_base64")]
-    pub psbt: Psbt,
+/// Signs `request` and answers in `encoding`: a raw PSBT carries the
+/// markers and attestation of a JSON answer in headers.
+async fn respond(
+    state: &Arc<AppState>,
+    ctx: &RequestContext,
+    request: SignRequest,
+    encoding: negotiate::Encoding,
+) -> axum::response::Response {
+    use axum::{
+        http::header::{CONTENT_TYPE, VARY},
+        response::IntoResponse,
+    };
+
+    let txid = request.psbt.unsigned_tx.compute_txid();
+    let result = sign_and_record(state, ctx, request).await;
+    let vary = [(VARY, "accept")];
+    let extension = Extension(UnsignedTxid(txid));
+    match encoding {
+        negotiate::Encoding::Json => (extension, vary, result.map(Json)).into_response(),
+        negotiate::Encoding::Binary => {
+            let result = result.map(|signed| {
+                let dry_run = signed
+                    .dry_run
+                    .then_some((DRY_RUN_HEADER, "true".to_owned()));
+                let cached = signed.cached.then_some((CACHED_HEADER, "true".to_owned()));
+                let attestation = signed.attestation.as_ref().map(|attestation| {
+                    let json = serde_json::to_string(attestation).expect("attestation is JSON");
+                    (attestation::HEADER, json)
+                });
+                (
+                    [(CONTENT_TYPE, BINARY_PSBT)],
+                    axum::response::AppendHeaders(
+                        dry_run.into_iter().chain(cached).chain(attestation),
+                    ),
+                    signed.psbt.serialize(),
+                )
+            });
+            (extension, vary, result).into_response()
+        }
+    }
 }
 
-pub fn de_psbt_from_base64<'de, D>(deserializer: D) -> Result<Psbt, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    let s: &str = serde::Deserialize::deserialize(deserializer)?;
-    Psbt::from_str(s).map_err(serde::de::Error::custom)
+/// Header `name` as `true` or `false`, false when absent.
+fn bool_header(headers: &axum::http::HeaderMap, name: &str) -> Result<bool, Error> {
+    match headers.get(name).map(|value| value.to_str()) {
+        None => Ok(false),
+        Some(Ok(value)) if value.eq_ignore_ascii_case("true") => Ok(true),
+        Some(Ok(value)) if value.eq_ignore_ascii_case("false") => Ok(false),
+        Some(_) => Err(Error::MalformedRequest(format!(
+            "{name}: expected `true` or `false`"
+        ))),
+    }
+}
+
+/// Validates any attached consignment, signs under the concurrency limit,
+/// records the outcome in the audit log and publishes it as an event.
+/// Shared by every interface that accepts signing requests.
+///
+/// Runs in a `signing` span carrying the transaction's txid, shape and
+/// value, and the verdict once decided, so every log line of the request
+/// can be found by transaction.
+async fn sign_and_record(
+    state: &Arc<AppState>,
+    ctx: &RequestContext,
+    request: SignRequest,
+) -> Result<SignResponse, Error> {
+    use tracing::Instrument;
+
+    let started = std::time::Instant::now();
+    let span = signing_span(state, &request.psbt, request.priority);
+    let before = stats::Before::of(&request.psbt);
+    let txid = request.psbt.unsigned_tx.compute_txid();
+    let begun = match (&state.journal, &ctx.request_id) {
+        (Some(journal), Some(request_id)) => {
+            let owner = journal::owner(ctx);
+            span.in_scope(|| journal.begin(&owner, request_id, txid))
+                .map(Some)
+        }
+        _ => Ok(None),
+    };
+    let replayed = matches!(begun, Ok(Some(journal::Begun::Answered(_))));
+    let result = match begun {
+        Ok(Some(journal::Begun::Answered(signed))) => {
+            span.in_scope(|| record_replay(state, ctx, txid, signed))
+        }
+        Ok(Some(journal::Begun::Started(in_flight))) => {
+            let result = decide_signing(state, ctx, request)
+                .instrument(span.clone())
+                .await;
+            span.in_scope(|| in_flight.finish(result))
+        }
+        Ok(None) => {
+            decide_signing(state, ctx, request)
+                .instrument(span.clone())
+                .await
+        }
+        Err(e) => Err(e),
+    }
+    .map(|mut signed| {
+        signed.missing_signatures = cosigners::missing(&signed.psbt);
+        signed.attestation = state.attester.as_ref().map(|attester| {
+            Box::new(attester.attest(&signed.psbt, ctx.request_id.as_deref(), chrono::Utc::now()))
+        });
+        signed
+    });
+    let verdict = match &result {
+        Ok(signed) if signed.cached => "cached",
+        Ok(signed) if signed.dry_run => "dry_run",
+        Ok(_) => "signed",
+        Err(_) => "rejected",
+    };
+    span.record("verdict", verdict);
+    if let Err(e) = &result {
+        span.record("error_code", e.code());
+    }
+    span.in_scope(|| tracing::info!("signing request decided"));
+    state
+        .stats
+        .record(&before, &result, replayed, started.elapsed());
+    result
 }
 
-pub fn serialize_psbt_to_base64<S>(psbt: &Psbt, serializer: S) -> Result<S::Ok, S::Error>
+fn signing_span(state: &AppState, psbt: &Psbt, priority: Priority) -> tracing::Span {
+    let tx = &psbt.unsigned_tx;
+    let output_sat: u64 = tx.output.iter().map(|output| output.value.to_sat()).sum();
+    // Known only when every input carries its previous output.
+    let input_sat: Option<u64> = psbt
+        .inputs
+        .iter()
+        .zip(&tx.input)
+        .map(|(input, txin)| {
+            let vout = txin.previous_output.vout as usize;
+            input
+                .witness_utxo
+                .as_ref()
+                .or_else(|| input.non_witness_utxo.as_ref()?.output.get(vout))
+                .map(|prevout| prevout.value.to_sat())
+        })
+        .sum();
+    tracing::info_span!(
+        "signing",
+        wallet = DEFAULT_WALLET,
+        network = %state.config().network,
+        txid = %tx.compute_txid(),
+        inputs = tx.input.len(),
+        outputs = tx.output.len(),
+        input_sat,
+        output_sat,
+        priority = priority.as_str(),
+        verdict = tracing::field::Empty,
+        error_code = tracing::field::Empty,
+    )
+}
+
+async fn decide_signing(
+    state: &Arc<AppState>,
+    ctx: &RequestContext,
+    request: SignRequest,
+) -> Result<SignResponse, Error> {
+    let SignRequest {
+        mut psbt,
+        consignment,
+        metadata,
+        replace,
+        priority,
+        sign_options,
+        collaborative,
+        prevouts,
+        pairings,
+    } = request;
+    let txid = psbt.unsigned_tx.compute_txid();
+    let paired = !pairings.is_empty();
+    let (checked, metadata) = match audit::check_metadata(&metadata) {
+        Ok(()) => (Ok(()), metadata),
+        // Left out of the audit entry, since it is what the request got wrong.
+        Err(e) => (Err(e), Default::default()),
+    };
+    let ctx = &RequestContext {
+        metadata,
+        ..ctx.clone()
+    };
+    let checked = checked.and_then(|()| prevouts::apply(&state.wallet(), &mut psbt, &prevouts));
+    let checked = match (checked, state.config().chain.clone()) {
+        (Ok(()), Some(chain)) if chain.backfill_prevouts => {
+            prevouts::backfill(state, &chain, &mut psbt).await
+        }
+        (checked, _) => checked,
+    };
+    let checked = checked
+        .and_then(|()| prevouts::check_complete(&state.wallet(), &psbt))
+        .and_then(|()| {
+            if state.config().signing.strict_foreign_inputs {
+                coinjoin::check_declared(&state.wallet(), &psbt, collaborative)?;
+            }
+            Ok(())
+        })
+        .and_then(|()| sign_options::resolve(&state.config().sign_options, sign_options.as_ref()))
+        .map(|options| sign_options::Resolved {
+            pairings,
+            ..options
+        });
+    let checked = match checked {
+        Ok(options) => rgb::validate_transfer(state, &psbt, consignment.as_ref())
+            .await
+            .map(|()| options),
+        Err(e) => Err(e),
+    };
+    let checked = match (checked, state.config().fee_check.clone()) {
+        (Ok(options), Some(policy)) => {
+            let trust_witness_utxo = options.options.trust_witness_utxo;
+            fee_check::check(state, &mut psbt, &policy, trust_witness_utxo)
+                .await
+                .map(|()| options)
+        }
+        (checked, _) => checked,
+    };
+    let mut quorum = state.config().quorum.clone();
+    let checked = match (checked, state.config().signing_windows.clone()) {
+        (Ok(options), Some(policy)) => signing_windows::check(ctx, &policy).and_then(|elevated| {
+            match (elevated, &mut quorum) {
+                (Some(threshold), Some(quorum)) => {
+                    quorum.threshold = threshold;
+                    Ok(options)
+                }
+                (Some(_), None) => Err(Error::OutsideSigningWindow(
+                    "the override needs [quorum]".into(),
+                )),
+                (None, _) => Ok(options),
+            }
+        }),
+        (checked, _) => checked,
+    };
+    let checked = match (checked, quorum) {
+        (Ok(options), Some(policy)) => quorum::check(state, ctx, txid, &policy).map(|()| options),
+        (checked, _) => checked,
+    };
+    let checked = match (checked, state.config().approval.clone()) {
+        (Ok(options), Some(policy)) => approval::check(state, ctx, &psbt, &policy)
+            .await
+            .map(|()| options),
+        (checked, _) => checked,
+    };
+    let cache_ttl = std::time::Duration::from_secs(state.config().signing.response_cache_ttl_secs);
+    // An answer signed with other options or pairings is not this
+    // request's answer.
+    // A replacement is recorded by the spend guard, which a cached answer
+    // would skip.
+    let cache_ttl = match sign_options {
+        Some(_) => std::time::Duration::ZERO,
+        None if paired || replace => std::time::Duration::ZERO,
+        None => cache_ttl,
+    };
+    let policy = response_cache::policy(&state.config());
+    if let (Ok(options), false) = (&checked, cache_ttl.is_zero()) {
+        let cached = state.response_cache.get(&psbt, &policy);
+        match cached.map(|cached| check_cached(state, &psbt, &cached, options).map(|()| cached)) {
+            Some(Ok(psbt)) => {
+                metrics::METRICS
+                    .response_cache_hits
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                tracing::info!(%txid, "answered from the signed response cache");
+                return Ok(SignResponse {
+                    psbt,
+                    dry_run: false,
+                    cached: true,
+                    missing_signatures: Vec::new(),
+                    attestation: None,
+                });
+            }
+            // Decided afresh, so the refusal is audited as any other.
+            Some(Err(e)) => {
+                tracing::info!(%txid, "not answering from the signed response cache: {e}")
+            }
+            None => {}
+        }
+    }
+    let submitted = (!cache_ttl.is_zero()).then(|| psbt.clone());
+    let result = match checked {
+        Ok(options) => match state.acquire_signing_permit(priority).await {
+            Ok(permit) => {
+                let state = state.clone();
+                run_blocking(move || {
+                    let _permit = permit;
+                    sign_psbt(&state, psbt, replace, &options)
+                })
+                .await
+            }
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    let result = record_signing(state, ctx, DEFAULT_WALLET, txid, result);
+    if let (Some(submitted), Ok(signed)) = (submitted, &result) {
+        if !signed.dry_run {
+            let size = state.config().signing.response_cache_size;
+            state
+                .response_cache
+                .insert(submitted, signed.psbt.clone(), policy, cache_ttl, size);
+        }
+    }
+    let request_id = ctx.request_id.clone();
+    state.events.publish(match &result {
+        Ok(signed) => Event::Signed {
+            txid,
+            request_id,
+            dry_run: signed.dry_run,
+        },
+        Err(e) => Event::Rejected {
+            txid,
+            request_id,
+            error_code: e.code(),
+        },
+    });
+    result
+}
+
+/// Runs CPU-bound signing work on the blocking thread pool, so a large PSBT
+/// does not stall the async workers serving other requests. `f` runs in the
+/// caller's span. A panic in `f` is resumed here, where the handler's panic
+/// catcher sees it.
+pub async fn run_blocking<T, F>(f: F) -> Result<T, Error>
 where
-    S: Serializer,
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
 {
-    let base64 = psbt.to_string(); 
-    serializer.serialize_str(&base64)
+    let span = tracing::Span::current();
+    match tokio::task::spawn_blocking(move || span.in_scope(f)).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(Error::Internal(format!("signing task failed: {e}"))),
+    }
+}
+
+/// Writes the audit entry for a signing attempt. A signature is only
+/// released once its entry is on disk.
+fn record_signing<T>(
+    state: &AppState,
+    ctx: &RequestContext,
+    wallet: &str,
+    txid: bitcoin::Txid,
+    result: Result<T, Error>,
+) -> Result<T, Error> {
+    let Some(audit) = &state.audit else {
+        return result;
+    };
+    // A standby's log mirrors the primary's, and it signs nothing itself.
+    if replication::is_standby(state) {
+        return result;
+    }
+    let mut entry = match &result {
+        Ok(_) => {
+            let mut entry = AuditEntry::new(ctx, txid, Outcome::Signed);
+            entry.dry_run = state.config().signing.dry_run;
+            entry
+        }
+        Err(e) => {
+            let mut entry = AuditEntry::new(ctx, txid, Outcome::Rejected);
+            entry.error_code = Some(e.code().to_owned());
+            entry
+        }
+    };
+    entry.wallet = wallet.to_owned();
+    write_audit(state, audit, entry)?;
+    result
+}
+
+/// Writes the audit entry for an answer given again from the journal,
+/// which is released only once its entry is on disk like a signature.
+fn record_replay(
+    state: &AppState,
+    ctx: &RequestContext,
+    txid: bitcoin::Txid,
+    signed: SignResponse,
+) -> Result<SignResponse, Error> {
+    let Some(audit) = &state.audit else {
+        return Ok(signed);
+    };
+    if replication::is_standby(state) {
+        return Ok(signed);
+    }
+    let mut entry = AuditEntry::new(ctx, txid, Outcome::Signed);
+    entry.dry_run = signed.dry_run;
+    entry.replayed = true;
+    write_audit(state, audit, entry)?;
+    Ok(signed)
+}
+
+fn write_audit(state: &AppState, audit: &AuditLog, mut entry: AuditEntry) -> Result<(), Error> {
+    let txid = entry.txid;
+    entry.label = state
+        .labels
+        .as_ref()
+        .and_then(|labels| labels.text(labels::LabelType::Tx, &txid.to_string()));
+    if let Err(e) = audit.append(&entry) {
+        tracing::error!(%txid, "withholding signatures, audit log write failed: {e}");
+        return Err(Error::Internal("audit log unavailable".into()));
+    }
+    Ok(())
+}
+
+fn sign_psbt(
+    state: &AppState,
+    mut signed_psbt: Psbt,
+    replace: bool,
+    options: &sign_options::Resolved,
+) -> Result<SignResponse, Error> {
+    let config = state.config();
+    let fundings = check_policies(state, &mut signed_psbt, options)?;
+    // Only once the checks have read them.
+    if let Some(policy) = &config.psbt_fields {
+        psbt_fields::strip(&mut signed_psbt, policy);
+    }
+    // A dry run signs all the same, so signing errors still surface, but
+    // answers with the PSBT as it was before signing.
+    let unsigned = config.signing.dry_run.then(|| signed_psbt.clone());
+    let signatures = spend_guard::signatures(&signed_psbt);
+    let sign_options = options.options.clone();
+    state
+        .wallet()
+        .sign(&mut signed_psbt, sign_options.clone())
+        .map_err(|e| Error::InvalidTransaction(format!("signing failed: {e}")))?;
+    if let Some(bsms) = &state.bsms {
+        bsms.sign(&mut signed_psbt, &sign_options)?;
+    }
+    if let Some(sp) = &state.silent_payments {
+        sp.sign(&mut signed_psbt)?;
+    }
+    if let Some(guard) = &state.spend_guard {
+        let signed = spend_guard::signed_inputs(&signatures, &signed_psbt);
+        guard.claim(&signed_psbt, &signed, replace, unsigned.is_none())?;
+    }
+    if let Some(psbt) = unsigned {
+        return Ok(SignResponse {
+            psbt,
+            dry_run: true,
+            cached: false,
+            missing_signatures: Vec::new(),
+            attestation: None,
+        });
+    }
+    if !fundings.is_empty() {
+        state
+            .channel_fundings
+            .mark_signed(&fundings, signed_psbt.unsigned_tx.compute_txid());
+    }
+
+    Ok(SignResponse {
+        psbt: signed_psbt,
+        dry_run: false,
+        cached: false,
+        missing_signatures: Vec::new(),
+        attestation: None,
+    })
 }
 
-#[derive(Debug, thiserror::Error)]
-pub enum Error {
-    #[error("invalid transaction: {0}")]
-    InvalidTransaction(String),
+/// Whether `cached`, signed for a submission identical to `psbt`, may be
+/// given out again: `psbt` still passes the signing policies, and the coins
+/// `cached` signs were signed into no other transaction since.
+fn check_cached(
+    state: &AppState,
+    psbt: &Psbt,
+    cached: &Psbt,
+    options: &sign_options::Resolved,
+) -> Result<(), Error> {
+    check_policies(state, &mut psbt.clone(), options)?;
+    if let Some(guard) = &state.spend_guard {
+        let signed = spend_guard::signed_inputs(&spend_guard::signatures(psbt), cached);
+        guard.claim(cached, &signed, false, false)?;
+    }
+    Ok(())
 }
 
-impl axum::response::IntoResponse for Error {
-    fn into_response(self) -> axum::response::Response {
-        tracing::error!(self = ?self, "error");
-        use axum::http::StatusCode;
-        use Error::*;
-        match self {
-            InvalidTransaction(e) => (StatusCode::BAD_REQUEST, e),
+/// Holds `psbt` to the signing policies `options` and the config set, as
+/// signing it or answering it from the signed response cache needs.
+/// Returns the channel fundings it pays.
+fn check_policies(
+    state: &AppState,
+    psbt: &mut Psbt,
+    options: &sign_options::Resolved,
+) -> Result<Vec<String>, Error> {
+    let config = state.config();
+    network::check_psbt(psbt, config.network)?;
+    let paired = sighash_pairing::check(
+        psbt,
+        config.sighash_pairing.as_ref(),
+        &options.pairings,
+        config.network,
+    )?;
+    sign_options::check_sighashes(psbt, &options.allowed_sighashes, &paired)?;
+    if let Some(policy) = &config.psbt_fields {
+        psbt_fields::check(psbt, policy)?;
+    }
+    if let Some(policy) = &config.dust {
+        dust::check(&state.wallet(), psbt, policy)?;
+    }
+    if let Some(policy) = &config.locktime {
+        locktime::check(state, psbt, policy)?;
+    }
+    if let Some(policy) = &config.templates {
+        templates::check(&state.wallet(), psbt, policy, config.network)?;
+    }
+    rgb::check_commitments(psbt, config.signing.finalize_rgb_commitments)?;
+    // Checked after any commitment is embedded, so the txid is final.
+    let fundings = match config.channel_funding {
+        Some(_) => state.channel_fundings.check(psbt)?,
+        None => Vec::new(),
+    };
+    if let Some(policy) = &config.coinjoin {
+        coinjoin::check(&state.wallet(), psbt, policy)?;
+    }
+    Ok(fundings)
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct SignRequest {
+    /// Base64-encoded BIP-174 PSBT.
+    #[serde(deserialize_with = "de_psbt_from_base64")]
+    #[schema(value_type = String, format = Byte, example = "cHNidP8BAAoCAAAAAAAAAAAAAAAA")]
+    pub psbt: Psbt,
+    /// RGB consignment of the transfer, validated before signing.
+    #[serde(default)]
+    pub consignment: Option<rgb::Consignment>,
+    /// Caller metadata, such as an order or batch id, recorded in the audit
+    /// entry. At most 16 entries.
+    #[serde(default)]
+    #[schema(value_type = Object, example = json!({"order_id": "A-1001"}))]
+    pub metadata: audit::Metadata,
+    /// Signs even when an input's coin was already signed into another
+    /// transaction, for a deliberate replacement such as an RBF fee bump.
+    /// Only checked with `[spend_guard]`.
+    #[serde(default)]
+    pub replace: bool,
+    /// `batch` for bulk work that should wait for interactive requests
+    /// when every signing slot is busy.
+    #[serde(default)]
+    pub priority: Priority,
+    /// Tightens the configured `[sign_options]` for this request.
+    #[serde(default)]
+    pub sign_options: Option<sign_options::SignOptionsRequest>,
+    /// Declares a transaction shared with other wallets, such as a
+    /// coinjoin, whose foreign inputs `signing.strict_foreign_inputs`
+    /// would otherwise refuse.
+    #[serde(default)]
+    pub collaborative: bool,
+    /// UTXO data of inputs the PSBT lacks it for, checked before it is
+    /// added.
+    #[serde(default)]
+    pub prevouts: Vec<prevouts::Prevout>,
+    /// The outputs inputs asking for a `[sighash_pairing]` type commit to.
+    #[serde(default)]
+    pub pairings: Vec<sighash_pairing::Pairing>,
+}
+
+impl From<Psbt> for SignRequest {
+    fn from(psbt: Psbt) -> Self {
+        SignRequest {
+            psbt,
+            consignment: None,
+            metadata: Default::default(),
+            replace: false,
+            priority: Priority::Interactive,
+            sign_options: None,
+            collaborative: false,
+            prevouts: Vec::new(),
+            pairings: Vec::new(),
         }
-        .into_response()
+    }
+}
+
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+pub struct SignResponse {
+    /// Base64-encoded PSBT with this wallet's signatures added.
+    #[serde(serialize_with = "serialize_psbt_to_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub psbt: Psbt,
+    /// Set when `signing.dry_run` left the PSBT unsigned.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+    /// Set when the PSBT is the cached answer to an identical earlier
+    /// request, see `signing.response_cache_ttl_secs`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+    /// Inputs still short of signatures, such as those of a multisig
+    /// wallet waiting for its other cosigners.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub missing_signatures: Vec<cosigners::InputSignatures>,
+    /// The service's signed statement of the PSBT, with `[attestation]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<Box<attestation::Attestation>>,
+}
+
+/// Prints only the txid so a signed PSBT can never end up in a log line.
+impl std::fmt::Debug for SignResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SignResponse")
+            .field("txid", &self.psbt.unsigned_tx.compute_txid())
+            .finish_non_exhaustive()
     }
 }
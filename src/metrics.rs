@@ -0,0 +1,71 @@
+//! Process-wide counters, exposed in Prometheus text format on the admin
+//! listener.
+
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+pub struct Metrics {
+    /// Handler panics caught and turned into `500` responses.
+    pub handler_panics: AtomicU64,
+    /// Signing requests shed because no signing slot freed up in time.
+    pub signing_shed: AtomicU64,
+    /// Signing key lookups answered from the derived key cache.
+    pub key_cache_hits: AtomicU64,
+    /// Signing key lookups that had to derive the key.
+    pub key_cache_misses: AtomicU64,
+    /// Signing requests answered from the signed response cache.
+    pub response_cache_hits: AtomicU64,
+}
+
+pub static METRICS: Metrics = Metrics {
+    handler_panics: AtomicU64::new(0),
+    signing_shed: AtomicU64::new(0),
+    key_cache_hits: AtomicU64::new(0),
+    key_cache_misses: AtomicU64::new(0),
+    response_cache_hits: AtomicU64::new(0),
+};
+
+impl Metrics {
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        counter(
+            &mut out,
+            "issue_service_handler_panics_total",
+            "Handler panics caught and answered with 500.",
+            &self.handler_panics,
+        );
+        counter(
+            &mut out,
+            "issue_service_signing_shed_total",
+            "Signing requests shed while waiting for a signing slot.",
+            &self.signing_shed,
+        );
+        counter(
+            &mut out,
+            "issue_service_key_cache_hits_total",
+            "Signing keys found in the derived key cache.",
+            &self.key_cache_hits,
+        );
+        counter(
+            &mut out,
+            "issue_service_key_cache_misses_total",
+            "Signing keys derived because they were not cached.",
+            &self.key_cache_misses,
+        );
+        counter(
+            &mut out,
+            "issue_service_response_cache_hits_total",
+            "Signing requests answered from the signed response cache.",
+            &self.response_cache_hits,
+        );
+        out
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} counter");
+    let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+}
@@ -0,0 +1,375 @@
+//! The `migrate-config` subcommand: converts a config whose `xprv` holds
+//! the private descriptor into one with public `descriptor` and
+//! `change_descriptor`, the `fingerprint` of the master key and the xprv in
+//! a separate `key_file`.
+//!
+//! Only the `xprv` line of the file changes, so comments and every other
+//! setting are kept. The new config is loaded back and must derive the same
+//! receive addresses and pass the signing self-test; otherwise nothing is
+//! left behind. The report lists what was checked and what behaves
+//! differently, and is written next to the new config.
+
+use std::{
+    fmt::Write as _,
+    fs::OpenOptions,
+    io::Write as _,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+};
+
+use bdk_wallet::{
+    descriptor::{Descriptor, DescriptorPublicKey},
+    keys::DescriptorSecretKey,
+    miniscript::descriptor::{DescriptorXKey, Wildcard},
+    KeychainKind,
+};
+use bitcoin::{
+    bip32::{ChildNumber, DerivationPath, Fingerprint, Xpub},
+    key::Secp256k1,
+};
+
+use crate::config::{Config, ENV_PREFIX};
+
+/// Receive addresses compared between the old and the new config.
+const COMPARED_ADDRESSES: u32 = 20;
+
+/// Secrets other than the key that stay in the file, with the variable
+/// that can hold them instead.
+const OTHER_SECRETS: [(&str, &str, &str); 4] = [
+    ("audit", "signing_key", "AUDIT__SIGNING_KEY"),
+    ("attestation", "signing_key", "ATTESTATION__SIGNING_KEY"),
+    ("nostr", "secret_key", "NOSTR__SECRET_KEY"),
+    ("replication", "token", "REPLICATION__TOKEN"),
+];
+
+/// Runs `migrate-config <config> <new config> <key file> [--without-change]`.
+pub fn run(mut args: impl Iterator<Item = String>) -> ExitCode {
+    let (Some(legacy), Some(target), Some(key_file)) = (args.next(), args.next(), args.next())
+    else {
+        eprintln!(
+            "usage: issue-service migrate-config <config> <new config> <key file> \
+             [--without-change]"
+        );
+        return ExitCode::FAILURE;
+    };
+    let mut with_change = true;
+    for arg in args {
+        match arg.as_str() {
+            "--without-change" => with_change = false,
+            other => {
+                eprintln!("error: unknown option {other}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let target = PathBuf::from(target);
+    let report_path = PathBuf::from(format!("{}.report", target.display()));
+    let paths = Paths {
+        legacy: PathBuf::from(legacy),
+        key_file: PathBuf::from(key_file),
+        target,
+    };
+    match migrate(&paths, with_change) {
+        Ok(report) => {
+            print!("{report}");
+            if let Err(e) = std::fs::write(&report_path, &report) {
+                eprintln!("error: {}: {e}", report_path.display());
+                return ExitCode::FAILURE;
+            }
+            println!("report written to {}", report_path.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+struct Paths {
+    legacy: PathBuf,
+    target: PathBuf,
+    key_file: PathBuf,
+}
+
+/// The key of a legacy descriptor, split into what goes into each file.
+struct Split {
+    descriptor: String,
+    change_descriptor: Option<String>,
+    fingerprint: Option<Fingerprint>,
+    /// Contents of the key file: the xprv with its key origin.
+    key: String,
+    notes: Vec<String>,
+}
+
+fn migrate(paths: &Paths, with_change: bool) -> Result<String, String> {
+    let xprv_var = format!("{ENV_PREFIX}XPRV");
+    if std::env::var_os(&xprv_var).is_some() {
+        return Err(format!(
+            "{xprv_var} is set and would be migrated in place of the file's xprv; unset it"
+        ));
+    }
+    for path in [&paths.target, &paths.key_file] {
+        if path.exists() {
+            return Err(format!("{} already exists", path.display()));
+        }
+    }
+    let legacy = Config::load(&paths.legacy).map_err(|e| e.to_string())?;
+    if legacy.descriptor.is_some() {
+        return Err(format!(
+            "{} already uses `descriptor`",
+            paths.legacy.display()
+        ));
+    }
+    if legacy.throwaway_key {
+        return Err(format!("{} has no xprv to migrate", paths.legacy.display()));
+    }
+    crate::descriptor::check_key_network(&legacy.xprv, legacy.network)
+        .map_err(|e| format!("xprv: {e}"))?;
+    let split = split(&legacy.xprv, with_change)?;
+
+    let content = std::fs::read_to_string(&paths.legacy)
+        .map_err(|e| format!("{}: {e}", paths.legacy.display()))?;
+    let migrated = rewrite(&content, &split, &paths.key_file)?;
+    write_new(&paths.key_file, &split.key, true)?;
+    if let Err(e) = write_new(&paths.target, &migrated, false) {
+        let _ = std::fs::remove_file(&paths.key_file);
+        return Err(e);
+    }
+    let checked = verify(&legacy, &paths.target);
+    let checked = match checked {
+        Ok(checked) => checked,
+        Err(e) => {
+            let _ = std::fs::remove_file(&paths.target);
+            let _ = std::fs::remove_file(&paths.key_file);
+            return Err(format!(
+                "the migrated config does not match, nothing written: {e}"
+            ));
+        }
+    };
+
+    let mut report = String::new();
+    let _ = writeln!(
+        report,
+        "migrated {} -> {}",
+        paths.legacy.display(),
+        paths.target.display()
+    );
+    let mut line = |line: String| {
+        let _ = writeln!(report, "  {line}");
+    };
+    line(format!(
+        "key file: {} (mode 0600)",
+        paths.key_file.display()
+    ));
+    line(format!("network: {}, key matches", legacy.network));
+    match split.fingerprint {
+        Some(fingerprint) => line(format!("fingerprint: {fingerprint}")),
+        None => line("fingerprint: unset".into()),
+    }
+    line(format!("descriptor: {}", split.descriptor));
+    match &split.change_descriptor {
+        Some(change) => line(format!("change descriptor: {change}")),
+        None => line("change descriptor: none".into()),
+    }
+    line(format!(
+        "first address: {} (first {COMPARED_ADDRESSES} receive addresses unchanged)",
+        checked.first_address
+    ));
+    line("signing self-test passed".into());
+    for note in split.notes {
+        let _ = writeln!(report, "note: {note}");
+    }
+    if let Ok(table) = content.parse::<toml::Table>() {
+        for (section, field, var) in OTHER_SECRETS {
+            let set = table
+                .get(section)
+                .and_then(|section| section.get(field))
+                .is_some();
+            if set {
+                let _ = writeln!(
+                    report,
+                    "note: {section}.{field} is still in the config file; \
+                     {ENV_PREFIX}{var} can hold it instead"
+                );
+            }
+        }
+    }
+    Ok(report)
+}
+
+fn split(xprv: &str, with_change: bool) -> Result<Split, String> {
+    let secp = Secp256k1::new();
+    let (public, keys) = Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, xprv)
+        .map_err(|e| format!("xprv: invalid descriptor: {e}"))?;
+    let mut secrets = keys.into_values();
+    let secret = match (secrets.next(), secrets.next()) {
+        (Some(DescriptorSecretKey::XPrv(secret)), None) => secret,
+        _ => return Err("xprv: only descriptors with a single xprv can be migrated".into()),
+    };
+    let xpub = Xpub::from_priv(&secp, &secret.xkey);
+    let mut notes = Vec::new();
+
+    let fingerprint = match &secret.origin {
+        Some((fingerprint, _)) => Some(*fingerprint),
+        None if secret.xkey.depth == 0 => Some(xpub.fingerprint()),
+        None => {
+            notes.push(
+                "the xprv has no key origin and is not a master key, so `fingerprint` is \
+                 unset and coordinators cannot tell which master key it comes from"
+                    .into(),
+            );
+            None
+        }
+    };
+    let key = DescriptorSecretKey::XPrv(DescriptorXKey {
+        origin: secret.origin.clone(),
+        xkey: secret.xkey,
+        derivation_path: DerivationPath::default(),
+        wildcard: Wildcard::None,
+    })
+    .to_string();
+
+    let public_key = |derivation_path: DerivationPath| {
+        DescriptorPublicKey::XPub(DescriptorXKey {
+            origin: secret.origin.clone(),
+            xkey: xpub,
+            derivation_path,
+            wildcard: secret.wildcard,
+        })
+        .to_string()
+    };
+    let descriptor = public.to_string();
+    let receive_path = secret.derivation_path.clone();
+    let change_path = match receive_path.as_ref().split_last() {
+        Some((ChildNumber::Normal { index: 0 }, parent))
+            if secret.wildcard == Wildcard::Unhardened =>
+        {
+            Some(DerivationPath::from(parent).child(ChildNumber::Normal { index: 1 }))
+        }
+        _ => None,
+    };
+    let change_descriptor = match (with_change, change_path) {
+        (false, _) => {
+            notes.push("change keeps going to receive addresses (--without-change)".into());
+            None
+        }
+        (true, None) => {
+            notes.push(
+                "the key path does not end in /0/*, so there is no change descriptor and change \
+                 keeps going to receive addresses"
+                    .into(),
+            );
+            None
+        }
+        (true, Some(change_path)) => {
+            let body = descriptor.split('#').next().unwrap_or_default();
+            let change = body.replace(&public_key(receive_path), &public_key(change_path));
+            let change = Descriptor::<DescriptorPublicKey>::from_str(&change)
+                .map_err(|e| format!("change descriptor: {e}"))?;
+            notes.push(
+                "change now goes to the change descriptor instead of receive addresses; \
+                 coins received so far are unaffected"
+                    .into(),
+            );
+            Some(change.to_string())
+        }
+    };
+    Ok(Split {
+        descriptor,
+        change_descriptor,
+        fingerprint,
+        key,
+        notes,
+    })
+}
+
+/// `content` with its top-level `xprv` line replaced by the new fields.
+fn rewrite(content: &str, split: &Split, key_file: &Path) -> Result<String, String> {
+    let string = |value: &str| toml::Value::String(value.to_owned()).to_string();
+    let mut fields = vec![format!("descriptor = {}", string(&split.descriptor))];
+    if let Some(change) = &split.change_descriptor {
+        fields.push(format!("change_descriptor = {}", string(change)));
+    }
+    if let Some(fingerprint) = split.fingerprint {
+        fields.push(format!(
+            "fingerprint = {}",
+            string(&fingerprint.to_string())
+        ));
+    }
+    let key_file =
+        std::path::absolute(key_file).map_err(|e| format!("{}: {e}", key_file.display()))?;
+    fields.push(format!(
+        "key_file = {}",
+        string(&key_file.display().to_string())
+    ));
+
+    let mut lines = Vec::new();
+    let mut replaced = false;
+    let mut top_level = true;
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('[') {
+            top_level = false;
+        }
+        let is_xprv = top_level
+            && !replaced
+            && toml::from_str::<toml::Table>(line).is_ok_and(|table| table.contains_key("xprv"));
+        if is_xprv {
+            lines.extend(fields.iter().cloned());
+            replaced = true;
+        } else {
+            lines.push(line.to_owned());
+        }
+    }
+    if !replaced {
+        return Err("no single-line top-level `xprv = \"...\"` to replace".into());
+    }
+    let mut migrated = lines.join("\n");
+    migrated.push('\n');
+    Ok(migrated)
+}
+
+fn write_new(path: &Path, content: &str, secret: bool) -> Result<(), String> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if secret {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = secret;
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+struct Checked {
+    first_address: bitcoin::Address,
+}
+
+/// Loads the migrated config and compares its wallet with the legacy one.
+fn verify(legacy: &Config, target: &Path) -> Result<Checked, String> {
+    let migrated = Config::load(target).map_err(|e| e.to_string())?;
+    let old = crate::create_wallet(legacy).map_err(|e| format!("old wallet: {e}"))?;
+    let new = crate::create_wallet(&migrated).map_err(|e| format!("new wallet: {e}"))?;
+    if old.public_descriptor(KeychainKind::External)
+        != new.public_descriptor(KeychainKind::External)
+    {
+        return Err("the receive descriptors differ".into());
+    }
+    for index in 0..COMPARED_ADDRESSES {
+        let old = old.peek_address(KeychainKind::External, index).address;
+        let new = new.peek_address(KeychainKind::External, index).address;
+        if old != new {
+            return Err(format!("receive address {index} is {new}, was {old}"));
+        }
+    }
+    crate::self_test::run(&new).map_err(|e| format!("signing self-test: {e}"))?;
+    Ok(Checked {
+        first_address: new.peek_address(KeychainKind::External, 0).address,
+    })
+}
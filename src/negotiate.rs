@@ -0,0 +1,120 @@
+//! Choosing the encoding of a signing response.
+//!
+//! `/sign_psbt` and `/sign_psbt/binary` both answer with a JSON
+//! `SignResponse` or a raw BIP-174 PSBT, whichever the request's `Accept`
+//! header prefers. Without one, or when it likes both as much, the answer
+//! is in the encoding the request was sent in. A header that accepts
+//! neither is refused with `406 not_acceptable` before anything is signed.
+//! Errors are JSON, unless [`crate::cbor`] turns them into CBOR.
+
+use axum::http::{header, HeaderMap};
+
+use crate::{error::Error, BINARY_PSBT};
+
+pub const JSON: &str = "application/json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    Binary,
+}
+
+impl Encoding {
+    fn media_type(self) -> &'static str {
+        match self {
+            Encoding::Json => JSON,
+            Encoding::Binary => BINARY_PSBT,
+        }
+    }
+
+    /// The encoding of the body `headers` describe, if it is one of them.
+    pub fn of_body(headers: &HeaderMap) -> Option<Encoding> {
+        let mime = headers
+            .get(header::CONTENT_TYPE)?
+            .to_str()
+            .ok()?
+            .split(';')
+            .next()?
+            .trim();
+        [Encoding::Json, Encoding::Binary]
+            .into_iter()
+            .find(|encoding| mime.eq_ignore_ascii_case(encoding.media_type()))
+    }
+}
+
+/// The encoding `headers` accept best, or `sent` when they do not prefer
+/// one.
+pub fn negotiate(headers: &HeaderMap, sent: Encoding) -> Result<Encoding, Error> {
+    let ranges = ranges(headers);
+    if ranges.is_empty() {
+        return Ok(sent);
+    }
+    let json = quality(&ranges, JSON);
+    let binary = quality(&ranges, BINARY_PSBT);
+    if json <= 0.0 && binary <= 0.0 {
+        return Err(Error::NotAcceptable(format!(
+            "answers are `{JSON}` or `{BINARY_PSBT}`"
+        )));
+    }
+    Ok(match json.partial_cmp(&binary) {
+        Some(std::cmp::Ordering::Greater) => Encoding::Json,
+        Some(std::cmp::Ordering::Less) => Encoding::Binary,
+        _ => sent,
+    })
+}
+
+/// The media ranges of the `Accept` headers in `headers`, with their
+/// weights.
+pub fn ranges(headers: &HeaderMap) -> Vec<(&str, f32)> {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(range)
+        .collect()
+}
+
+/// A media range of an `Accept` header and its weight. Ranges whose
+/// weight does not parse are left out.
+fn range(range: &str) -> Option<(&str, f32)> {
+    let mut parts = range.split(';');
+    let mime = parts.next()?.trim();
+    if mime.is_empty() {
+        return None;
+    }
+    let mut weight = 1.0;
+    for param in parts {
+        if let Some((name, value)) = param.split_once('=') {
+            if name.trim().eq_ignore_ascii_case("q") {
+                weight = value.trim().parse::<f32>().ok()?.clamp(0.0, 1.0);
+            }
+        }
+    }
+    Some((mime, weight))
+}
+
+/// The weight of the most specific range matching `media_type`, 0 when
+/// none does.
+pub fn quality(ranges: &[(&str, f32)], media_type: &str) -> f32 {
+    let (kind, _) = media_type.split_once('/').expect("a media type");
+    let specificity = |mime: &str| {
+        if mime.eq_ignore_ascii_case(media_type) {
+            Some(2)
+        } else if mime
+            .strip_suffix("/*")
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(kind))
+        {
+            Some(1)
+        } else if mime == "*/*" {
+            Some(0)
+        } else {
+            None
+        }
+    };
+    ranges
+        .iter()
+        .filter_map(|(mime, weight)| specificity(mime).map(|rank| (rank, *weight)))
+        .max_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)))
+        .map_or(0.0, |(_, weight)| weight)
+}
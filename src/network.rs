@@ -0,0 +1,78 @@
+//! Guardrail against signing a PSBT built for another network.
+//!
+//! Output scripts look the same on every network, but extended keys and
+//! standard derivation paths do not: global xpubs carry a mainnet or test
+//! version, and BIP-44 style paths (`44'`, `48'`, `49'`, `84'`, `86'`) carry
+//! coin type `0'` on mainnet and `1'` on signet, testnet, testnet4 and
+//! regtest. A PSBT that shows either for the wrong network is refused.
+//! Paths with other purposes or coin types say nothing and are ignored.
+
+use bitcoin::{
+    bip32::{ChildNumber, DerivationPath},
+    Network, NetworkKind, Psbt,
+};
+
+use crate::error::Error;
+
+/// Purposes whose second path level is the SLIP-44 coin type.
+const COIN_TYPE_PURPOSES: [u32; 5] = [44, 48, 49, 84, 86];
+
+pub fn check_psbt(psbt: &Psbt, network: Network) -> Result<(), Error> {
+    let kind = NetworkKind::from(network);
+    for xpub in psbt.xpub.keys() {
+        if xpub.network != kind {
+            return Err(mismatch(format!("global xpub {xpub}"), network));
+        }
+    }
+
+    let mut paths: Vec<(String, &DerivationPath)> = psbt
+        .xpub
+        .values()
+        .map(|(_, path)| ("a global xpub".to_owned(), path))
+        .collect();
+    for (index, input) in psbt.inputs.iter().enumerate() {
+        let origins = input.bip32_derivation.values().map(|(_, path)| path);
+        let tap_origins = input.tap_key_origins.values().map(|(_, (_, path))| path);
+        paths.extend(
+            origins
+                .chain(tap_origins)
+                .map(|p| (format!("input {index}"), p)),
+        );
+    }
+    for (index, output) in psbt.outputs.iter().enumerate() {
+        let origins = output.bip32_derivation.values().map(|(_, path)| path);
+        let tap_origins = output.tap_key_origins.values().map(|(_, (_, path))| path);
+        paths.extend(
+            origins
+                .chain(tap_origins)
+                .map(|p| (format!("output {index}"), p)),
+        );
+    }
+
+    let expected = if network == Network::Bitcoin { 0 } else { 1 };
+    for (location, path) in paths {
+        let coin = match path.as_ref() {
+            [ChildNumber::Hardened { index: purpose }, ChildNumber::Hardened { index: coin }, ..]
+                if COIN_TYPE_PURPOSES.contains(purpose) =>
+            {
+                *coin
+            }
+            _ => continue,
+        };
+        if (coin == 0 || coin == 1) && coin != expected {
+            return Err(mismatch(format!("{location} derives at m/{path}"), network));
+        }
+    }
+    Ok(())
+}
+
+fn mismatch(what: String, network: Network) -> Error {
+    let other = if network == Network::Bitcoin {
+        "a test network"
+    } else {
+        "mainnet"
+    };
+    Error::InvalidTransaction(format!(
+        "{what}, which belongs to {other}; this service signs for {network}"
+    ))
+}
@@ -0,0 +1,56 @@
+//! Wallets on other networks hosted by the same process.
+//!
+//! Each entry of `networks` names a network and the config file of its
+//! wallet, which brings its own key, `[chain]` backend, policies and state
+//! files. Its endpoints are served under `/<network>`, public ones on the
+//! main config's public listeners and admin ones on `admin_listen`, and
+//! reach only its own wallet: nothing is shared with the main wallet or the
+//! other hosted ones besides the listeners and process-wide metrics. The
+//! main wallet keeps the unprefixed paths.
+//!
+//! A network can be listed once and not as the main config's own, so a
+//! prefix always names exactly one wallet.
+
+use std::{path::PathBuf, str::FromStr};
+
+use bitcoin::Network;
+
+use crate::config::Config;
+
+/// A wallet in `networks`, loaded.
+pub struct Hosted {
+    pub network: Network,
+    pub path: PathBuf,
+    pub config: Config,
+}
+
+/// Loads the config of each of `config.networks`.
+pub fn load(config: &Config) -> Result<Vec<Hosted>, String> {
+    let mut hosted = Vec::with_capacity(config.networks.len());
+    for (name, path) in &config.networks {
+        let network =
+            Network::from_str(name).map_err(|_| format!("networks.{name}: not a network name"))?;
+        if network == config.network {
+            return Err(format!("networks.{name}: is the main config's own network"));
+        }
+        let loaded = Config::load_hosted(path).map_err(|e| format!("networks.{name}: {e}"))?;
+        if loaded.network != network {
+            return Err(format!(
+                "networks.{name}: {} is for network `{}`",
+                path.display(),
+                loaded.network
+            ));
+        }
+        hosted.push(Hosted {
+            network,
+            path: path.clone(),
+            config: loaded,
+        });
+    }
+    Ok(hosted)
+}
+
+/// Path prefix the endpoints of the wallet on `network` are served under.
+pub fn prefix(network: Network) -> String {
+    format!("/{network}")
+}
@@ -0,0 +1,336 @@
+//! Signing requests over Nostr relays, in the style of NIP-46.
+//!
+//! The service subscribes on every configured relay to kind 24133 events
+//! addressed to its own Nostr key. Their content is an encrypted request
+//! (NIP-44, or NIP-04 for older clients):
+//!
+//! ```json
+//! {"id": "1", "method": "sign_psbt", "params": ["cHNidP8B..."]}
+//! ```
+//!
+//! Requests from keys outside `authorized_npubs` are dropped unanswered.
+//! Replies are `{"id": "1", "result": "cHNidP8B..."}` or
+//! `{"id": "1", "error": "..."}`, encrypted the same way and published on
+//! every relay. Only outbound connections are made, so the signer needs no
+//! inbound network exposure.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures_util::{SinkExt, StreamExt};
+use nostr::{
+    nips::{nip04, nip44},
+    ClientMessage, Event, EventBuilder, EventId, Filter, JsonUtil, Keys, Kind, PublicKey,
+    RelayMessage, SubscriptionId, Tag, Timestamp, ToBech32,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{
+    config::NostrConfig, context::RequestContext, error::Error, timeout::RouteTimeout, AppState,
+};
+
+/// Event ids remembered to drop a request delivered by several relays.
+const SEEN_CAPACITY: usize = 4096;
+/// Requests older than this are ignored, so a relay replaying stored
+/// events cannot trigger old signing requests again.
+const MAX_REQUEST_AGE_SECS: u64 = 300;
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+struct Transport {
+    state: Arc<AppState>,
+    keys: Keys,
+    /// Serialized `EVENT` messages for every relay connection to publish.
+    outgoing: broadcast::Sender<String>,
+    seen: Mutex<Seen>,
+}
+
+#[derive(Default)]
+struct Seen {
+    ids: HashSet<EventId>,
+    order: VecDeque<EventId>,
+}
+
+impl Seen {
+    /// Returns `false` if `id` was already seen.
+    fn insert(&mut self, id: EventId) -> bool {
+        if !self.ids.insert(id) {
+            return false;
+        }
+        self.order.push_back(id);
+        if self.order.len() > SEEN_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+#[derive(Deserialize)]
+struct Request {
+    id: String,
+    method: String,
+    #[serde(default)]
+    params: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Response {
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Encryption {
+    Nip44,
+    Nip04,
+}
+
+/// Checks the keys of `config`, for startup and `check-config`.
+pub fn validate(config: &NostrConfig) -> Result<(), String> {
+    Keys::parse(&config.secret_key).map_err(|e| format!("nostr.secret_key: {e}"))?;
+    for npub in &config.authorized_npubs {
+        PublicKey::parse(npub).map_err(|e| format!("nostr.authorized_npubs: `{npub}`: {e}"))?;
+    }
+    if config.relays.is_empty() {
+        return Err("nostr.relays: at least one relay is required".into());
+    }
+    Ok(())
+}
+
+/// Connects to every relay and serves requests until the process exits.
+pub fn spawn(state: Arc<AppState>, config: &NostrConfig) -> Result<(), String> {
+    validate(config)?;
+    let keys = Keys::parse(&config.secret_key).expect("validated nostr secret key");
+    tracing::info!(
+        npub = keys.public_key().to_bech32().unwrap_or_default(),
+        "nostr signer identity"
+    );
+    let transport = Arc::new(Transport {
+        state,
+        keys,
+        outgoing: broadcast::channel(64).0,
+        seen: Default::default(),
+    });
+    for relay in &config.relays {
+        tokio::spawn(relay_forever(transport.clone(), relay.clone()));
+    }
+    Ok(())
+}
+
+async fn relay_forever(transport: Arc<Transport>, url: String) {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match tokio_tungstenite::connect_async(&url).await {
+            Ok((socket, _)) => {
+                tracing::info!(relay = url, "connected to nostr relay");
+                backoff = MIN_BACKOFF;
+                if let Err(e) = session(&transport, socket).await {
+                    tracing::warn!(relay = url, "nostr relay connection failed: {e}");
+                }
+            }
+            Err(e) => tracing::warn!(relay = url, "cannot connect to nostr relay: {e}"),
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn session<S>(
+    transport: &Arc<Transport>,
+    socket: tokio_tungstenite::WebSocketStream<S>,
+) -> Result<(), tokio_tungstenite::tungstenite::Error>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let (mut sink, mut stream) = socket.split();
+    let filter = Filter::new()
+        .kind(Kind::NostrConnect)
+        .pubkey(transport.keys.public_key())
+        .since(Timestamp::now());
+    let subscribe = ClientMessage::req(SubscriptionId::generate(), filter).as_json();
+    sink.send(Message::Text(subscribe.into())).await?;
+
+    let mut outgoing = transport.outgoing.subscribe();
+    loop {
+        tokio::select! {
+            incoming = stream.next() => match incoming {
+                Some(Ok(Message::Text(text))) => receive(transport, &text),
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e),
+            },
+            reply = outgoing.recv() => match reply {
+                Ok(reply) => sink.send(Message::Text(reply.into())).await?,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "nostr replies dropped for a slow relay");
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+        }
+    }
+}
+
+/// Handles one relay message, spawning the request it carries if any.
+fn receive(transport: &Arc<Transport>, text: &str) {
+    let event = match RelayMessage::from_json(text) {
+        Ok(RelayMessage::Event { event, .. }) => event.into_owned(),
+        Ok(RelayMessage::Notice(notice)) => {
+            tracing::info!("nostr relay notice: {notice}");
+            return;
+        }
+        Ok(_) => return,
+        Err(e) => {
+            tracing::debug!("unparsable nostr relay message: {e}");
+            return;
+        }
+    };
+    if event.kind != Kind::NostrConnect || event.verify().is_err() {
+        return;
+    }
+    let age = Timestamp::now()
+        .as_u64()
+        .saturating_sub(event.created_at.as_u64());
+    if age > MAX_REQUEST_AGE_SECS {
+        return;
+    }
+    if !transport
+        .seen
+        .lock()
+        .expect("nostr seen ids")
+        .insert(event.id)
+    {
+        return;
+    }
+    if !authorized(&transport.state, &event.pubkey) {
+        tracing::warn!(
+            author = event.pubkey.to_bech32().unwrap_or_default(),
+            "dropping nostr request from an unauthorized key"
+        );
+        return;
+    }
+    tokio::spawn(handle(transport.clone(), event));
+}
+
+fn authorized(state: &AppState, author: &PublicKey) -> bool {
+    state.config().nostr.as_ref().is_some_and(|nostr| {
+        nostr
+            .authorized_npubs
+            .iter()
+            .any(|npub| PublicKey::parse(npub).is_ok_and(|key| key == *author))
+    })
+}
+
+async fn handle(transport: Arc<Transport>, event: Event) {
+    let secret_key = transport.keys.secret_key();
+    let (plaintext, encryption) = match nip44::decrypt(secret_key, &event.pubkey, &event.content) {
+        Ok(plaintext) => (plaintext, Encryption::Nip44),
+        Err(_) => match nip04::decrypt(secret_key, &event.pubkey, &event.content) {
+            Ok(plaintext) => (plaintext, Encryption::Nip04),
+            Err(e) => {
+                tracing::warn!(event = %event.id, "cannot decrypt nostr request: {e}");
+                return;
+            }
+        },
+    };
+    let request: Request = match serde_json::from_str(&plaintext) {
+        Ok(request) => request,
+        Err(e) => {
+            tracing::warn!(event = %event.id, "malformed nostr request: {e}");
+            return;
+        }
+    };
+
+    let ctx = RequestContext {
+        request_id: Some(request.id.clone()),
+        caller: Some(format!(
+            "nostr:{}",
+            event.pubkey.to_bech32().unwrap_or_default()
+        )),
+        metadata: Default::default(),
+        bearer: None,
+        internal: false,
+    };
+    let outcome = invoke(&transport, &ctx, &request).await;
+    let response = match outcome {
+        Ok(result) => Response {
+            id: request.id,
+            result: Some(result),
+            error: None,
+        },
+        Err(e) => {
+            tracing::error!(error = ?e, "nostr request failed");
+            Response {
+                id: request.id,
+                result: None,
+                error: Some(e.to_string()),
+            }
+        }
+    };
+    if let Err(e) = publish(&transport, &event.pubkey, encryption, &response) {
+        tracing::error!("cannot publish nostr reply: {e}");
+    }
+}
+
+async fn invoke(
+    transport: &Transport,
+    ctx: &RequestContext,
+    request: &Request,
+) -> Result<String, Error> {
+    match request.method.as_str() {
+        "connect" => Ok("ack".into()),
+        "ping" => Ok("pong".into()),
+        "get_public_key" => Ok(transport.keys.public_key().to_hex()),
+        "sign_psbt" => {
+            let [psbt] = request.params.as_slice() else {
+                return Err(Error::MalformedRequest("expected params [psbt]".into()));
+            };
+            let psbt = bitcoin::Psbt::from_str(psbt)
+                .map_err(|e| Error::MalformedRequest(format!("invalid psbt: {e}")))?;
+            let state = &transport.state;
+            let limit = RouteTimeout::Sign.duration(state);
+            let signed =
+                tokio::time::timeout(limit, crate::sign_and_record(state, ctx, psbt.into()))
+                    .await
+                    .unwrap_or(Err(Error::Timeout(limit)))?;
+            Ok(signed.psbt.to_string())
+        }
+        method => Err(Error::NotFound(format!("method {method}"))),
+    }
+}
+
+fn publish(
+    transport: &Transport,
+    to: &PublicKey,
+    encryption: Encryption,
+    response: &Response,
+) -> Result<(), String> {
+    let plaintext = serde_json::to_string(response).expect("serialize nostr reply");
+    let secret_key = transport.keys.secret_key();
+    let content = match encryption {
+        Encryption::Nip44 => nip44::encrypt(secret_key, to, plaintext, nip44::Version::V2)
+            .map_err(|e| e.to_string())?,
+        Encryption::Nip04 => {
+            nip04::encrypt(secret_key, to, plaintext).map_err(|e| e.to_string())?
+        }
+    };
+    let event = EventBuilder::new(Kind::NostrConnect, content)
+        .tag(Tag::public_key(*to))
+        .sign_with_keys(&transport.keys)
+        .map_err(|e| e.to_string())?;
+    // No relay connected right now means nobody can receive the reply.
+    let _ = transport
+        .outgoing
+        .send(ClientMessage::event(event).as_json());
+    Ok(())
+}
@@ -0,0 +1,222 @@
+//! Statements proving control of a single coin, for counterparties that ask
+//! for one during OTC settlement.
+//!
+//! The statement is a [BIP-322] simple signature by the coin's address over
+//! the counterparty's challenge and the outpoint, so any BIP-322 verifier can
+//! check it and nothing is spent. Simple signatures are witness-only, which
+//! limits them to native segwit addresses.
+//!
+//! Like proofs of reserves, this needs `[chain]` to know the wallet's coins
+//! and runs on the admin listener.
+//!
+//! [BIP-322]: https://github.com/bitcoin/bips/blob/master/bip-0322.mediawiki
+
+use std::sync::Arc;
+
+use axum::{extract::State, Json};
+use bdk_wallet::miniscript::interpreter::{Interpreter, SatisfiedConstraint};
+use bitcoin::{
+    absolute::LockTime,
+    base64::{prelude::BASE64_STANDARD, Engine},
+    consensus,
+    hashes::{sha256, Hash, HashEngine},
+    key::Secp256k1,
+    opcodes::{all::OP_RETURN, OP_0},
+    script::Builder,
+    sighash::Prevouts,
+    transaction::Version,
+    Address, Amount, OutPoint, Psbt, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    context::RequestContext,
+    error::{ApiJson, Error},
+    AppState,
+};
+
+const MESSAGE_TAG: &[u8] = b"BIP0322-signed-message";
+
+#[derive(Deserialize)]
+pub struct OwnershipRequest {
+    /// The coin to prove control of, as `txid:vout`.
+    outpoint: OutPoint,
+    /// The counterparty's challenge.
+    challenge: String,
+}
+
+#[derive(Serialize)]
+pub struct OwnershipResponse {
+    pub outpoint: OutPoint,
+    pub amount_sat: u64,
+    /// Address of the coin, which made the signature.
+    pub address: String,
+    /// The signed message, see [`message`].
+    pub message: String,
+    /// Base64-encoded BIP-322 simple signature; empty under `dry_run`.
+    pub signature: String,
+    /// Set when `signing.dry_run` left the statement unsigned.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+}
+
+pub async fn prove(
+    State(state): State<Arc<AppState>>,
+    ctx: RequestContext,
+    ApiJson(request): ApiJson<OwnershipRequest>,
+) -> Result<Json<OwnershipResponse>, Error> {
+    if request.challenge.is_empty() {
+        return Err(Error::MalformedRequest(
+            "challenge must not be empty".into(),
+        ));
+    }
+    crate::chain::require_synced(&state)?;
+    let outpoint = request.outpoint;
+    let message = message(outpoint, &request.challenge);
+    let (psbt, address, amount) = build(&state, outpoint, &message)?;
+    let (psbt, dry_run) = crate::reserves::sign_recorded(&state, &ctx, psbt).await?;
+
+    let signature = if dry_run {
+        String::new()
+    } else {
+        let witness = psbt.inputs[0]
+            .final_script_witness
+            .as_ref()
+            .ok_or_else(|| Error::Internal("the statement has no witness".into()))?;
+        let signature = BASE64_STANDARD.encode(consensus::serialize(witness));
+        verify(&address, &message, &signature)
+            .map_err(|e| Error::Internal(format!("statement does not verify: {e}")))?;
+        signature
+    };
+    Ok(Json(OwnershipResponse {
+        outpoint,
+        amount_sat: amount.to_sat(),
+        address: address.to_string(),
+        message,
+        signature,
+        dry_run,
+    }))
+}
+
+/// What gets signed: the challenge, then the outpoint on its own line.
+pub fn message(outpoint: OutPoint, challenge: &str) -> String {
+    format!("{challenge}\n{outpoint}")
+}
+
+/// The unsigned BIP-322 `to_sign` transaction for `message`, spending the
+/// `to_spend` output locked to the coin's script.
+fn build(
+    state: &AppState,
+    outpoint: OutPoint,
+    message: &str,
+) -> Result<(Psbt, Address, Amount), Error> {
+    let wallet = state.wallet();
+    let utxo = wallet.get_utxo(outpoint).ok_or_else(|| {
+        Error::MalformedRequest(format!("{outpoint} is not an unspent coin of this wallet"))
+    })?;
+    let script_pubkey = utxo.txout.script_pubkey.clone();
+    if !script_pubkey.is_witness_program() {
+        return Err(Error::InvalidTransaction(format!(
+            "{outpoint} is not a native segwit coin, which BIP-322 simple signatures need"
+        )));
+    }
+    let address = Address::from_script(&script_pubkey, state.config().network)
+        .map_err(|e| Error::Internal(format!("{outpoint}: {e}")))?;
+    let amount = utxo.txout.value;
+
+    let to_spend = to_spend(&script_pubkey, message);
+    let mut psbt = Psbt::from_unsigned_tx(to_sign(to_spend.compute_txid()))
+        .map_err(|e| Error::Internal(format!("building the statement: {e}")))?;
+    // The coin's derivation paths, so the wallet signs for its script.
+    psbt.inputs[0] = wallet
+        .get_psbt_input(utxo, None, true)
+        .map_err(|e| Error::Internal(format!("{outpoint}: {e}")))?;
+    psbt.inputs[0].witness_utxo = Some(to_spend.output[0].clone());
+    psbt.inputs[0].non_witness_utxo = None;
+    Ok((psbt, address, amount))
+}
+
+/// Checks `signature` is a BIP-322 simple signature by `address` over
+/// `message`.
+pub fn verify(address: &Address, message: &str, signature: &str) -> Result<(), String> {
+    let witness: Witness = BASE64_STANDARD
+        .decode(signature)
+        .map_err(|e| format!("signature: {e}"))
+        .and_then(|bytes| consensus::deserialize(&bytes).map_err(|e| format!("signature: {e}")))?;
+    let script_pubkey = address.script_pubkey();
+    let to_spend = to_spend(&script_pubkey, message);
+    let mut to_sign = to_sign(to_spend.compute_txid());
+    to_sign.input[0].witness = witness;
+
+    let txin = &to_sign.input[0];
+    let interpreter = Interpreter::from_txdata(
+        &script_pubkey,
+        &txin.script_sig,
+        &txin.witness,
+        txin.sequence,
+        to_sign.lock_time,
+    )
+    .map_err(|e| e.to_string())?;
+    let secp = Secp256k1::verification_only();
+    let prevouts = Prevouts::All(&to_spend.output);
+    let mut signed = false;
+    for constraint in interpreter.iter(&secp, &to_sign, 0, &prevouts) {
+        if let SatisfiedConstraint::PublicKey { .. } | SatisfiedConstraint::PublicKeyHash { .. } =
+            constraint.map_err(|e| e.to_string())?
+        {
+            signed = true;
+        }
+    }
+    if !signed {
+        return Err("not signed".into());
+    }
+    Ok(())
+}
+
+fn message_hash(message: &str) -> sha256::Hash {
+    let tag = sha256::Hash::hash(MESSAGE_TAG);
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    engine.input(message.as_bytes());
+    sha256::Hash::from_engine(engine)
+}
+
+/// The virtual transaction committing to `message` and paying `script_pubkey`.
+fn to_spend(script_pubkey: &ScriptBuf, message: &str) -> Transaction {
+    Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0xFFFF_FFFF),
+            script_sig: Builder::new()
+                .push_opcode(OP_0)
+                .push_slice(message_hash(message).to_byte_array())
+                .into_script(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    }
+}
+
+/// The virtual transaction whose signature is the statement.
+fn to_sign(to_spend: Txid) -> Transaction {
+    Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend, 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    }
+}
@@ -0,0 +1,143 @@
+//! Cursor pagination of the admin list endpoints.
+//!
+//! A list answers with at most `limit` items as a JSON array, and with an
+//! `X-Next-Cursor` header when more follow. Passing that value back as
+//! `cursor`, with the same sort and filters, returns the next page. The
+//! cursor holds the sort value and id of the last item returned, so items
+//! added or removed meanwhile neither repeat nor shift later pages.
+
+use axum::{
+    http::{HeaderName, HeaderValue},
+    response::{IntoResponse, Response},
+    Json,
+};
+use data_encoding::BASE64URL_NOPAD;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+pub const DEFAULT_LIMIT: usize = 1000;
+pub const MAX_LIMIT: usize = 10_000;
+const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PageQuery {
+    /// Items per page, at most [`MAX_LIMIT`].
+    pub limit: Option<usize>,
+    /// `X-Next-Cursor` of the previous page.
+    pub cursor: Option<String>,
+    /// Defaults per endpoint.
+    pub order: Option<Order>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+/// Where an item sorts: by `value`, then by `id` among equal values.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Key {
+    pub value: u64,
+    pub id: String,
+}
+
+impl Key {
+    pub fn new(value: u64, id: impl ToString) -> Self {
+        Key {
+            value,
+            id: id.to_string(),
+        }
+    }
+
+    fn encode(&self) -> String {
+        BASE64URL_NOPAD.encode(format!("{}:{}", self.value, self.id).as_bytes())
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        let decoded = BASE64URL_NOPAD.decode(cursor.as_bytes()).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let (value, id) = decoded.split_once(':')?;
+        Some(Key {
+            value: value.parse().ok()?,
+            id: id.to_owned(),
+        })
+    }
+}
+
+/// One page of a list, answered as a JSON array of its items.
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Page<T> {
+    fn into_response(self) -> Response {
+        let mut response = Json(self.items).into_response();
+        if let Some(cursor) = self.next_cursor {
+            response.headers_mut().insert(
+                HeaderName::from_static(NEXT_CURSOR_HEADER),
+                HeaderValue::from_str(&cursor).expect("base64url is a valid header value"),
+            );
+        }
+        response
+    }
+}
+
+/// Sorts `items` by `key` in `query.order`, or else `order`, and returns
+/// those after `query.cursor`.
+pub fn paginate<T>(
+    items: impl IntoIterator<Item = T>,
+    query: &PageQuery,
+    order: Order,
+    key: impl Fn(&T) -> Key,
+) -> Result<Page<T>, Error> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(Error::MalformedRequest(format!(
+            "limit must be 1 to {MAX_LIMIT}"
+        )));
+    }
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(|cursor| {
+            Key::decode(cursor).ok_or_else(|| Error::MalformedRequest("cursor is invalid".into()))
+        })
+        .transpose()?;
+    let order = query.order.unwrap_or(order);
+
+    let mut keyed: Vec<(Key, T)> = items
+        .into_iter()
+        .map(|item| (key(&item), item))
+        .filter(|(key, _)| match (&cursor, order) {
+            (None, _) => true,
+            (Some(cursor), Order::Asc) => key > cursor,
+            (Some(cursor), Order::Desc) => key < cursor,
+        })
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| a.cmp(b));
+    if order == Order::Desc {
+        keyed.reverse();
+    }
+    let more = keyed.len() > limit;
+    keyed.truncate(limit);
+    let next_cursor = more
+        .then(|| keyed.last().map(|(key, _)| key.encode()))
+        .flatten();
+    Ok(Page {
+        items: keyed.into_iter().map(|(_, item)| item).collect(),
+        next_cursor,
+    })
+}
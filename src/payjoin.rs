@@ -0,0 +1,348 @@
+//! BIP-78 payjoin receiver.
+//!
+//! The sender posts a finalized, broadcastable original PSBT paying this
+//! wallet. We add one of our confirmed coins as an extra input, fold its
+//! value into our payment output, sign our input through the regular
+//! signing path and return the proposal. Output substitution is never
+//! performed.
+//!
+//! Errors use the BIP-78 envelope (`errorCode`/`message`) rather than the
+//! service's own, since payjoin senders parse it.
+
+use std::{collections::HashSet, str::FromStr, sync::Arc, sync::Mutex};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use bdk_wallet::KeychainKind;
+use bitcoin::{
+    psbt, transaction::Sequence, Amount, OutPoint, Psbt, ScriptBuf, TxIn, Weight, Witness,
+};
+use rand::{seq::SliceRandom, Rng};
+use serde::Deserialize;
+
+use crate::{context::RequestContext, AppState};
+
+const SUPPORTED_VERSION: u32 = 1;
+
+/// Outpoints already offered in a proposal. A coin is never offered twice,
+/// so repeated probing cannot enumerate the wallet's UTXO set. Reset on
+/// restart.
+#[derive(Default)]
+pub struct Reservations(Mutex<HashSet<OutPoint>>);
+
+impl Reservations {
+    fn contains(&self, outpoint: &OutPoint) -> bool {
+        self.0
+            .lock()
+            .expect("payjoin reservations")
+            .contains(outpoint)
+    }
+
+    /// Returns `false` if `outpoint` was already reserved.
+    fn reserve(&self, outpoint: OutPoint) -> bool {
+        self.0
+            .lock()
+            .expect("payjoin reservations")
+            .insert(outpoint)
+    }
+
+    fn release(&self, outpoint: &OutPoint) {
+        self.0
+            .lock()
+            .expect("payjoin reservations")
+            .remove(outpoint);
+    }
+}
+
+/// BIP-78 query parameters.
+#[derive(Debug, Deserialize)]
+pub struct Params {
+    #[serde(default)]
+    v: Option<u32>,
+    #[serde(default)]
+    additionalfeeoutputindex: Option<usize>,
+    #[serde(default)]
+    maxadditionalfeecontribution: Option<u64>,
+    /// Minimum fee rate of the proposal in sat/vB.
+    #[serde(default)]
+    minfeerate: Option<f64>,
+}
+
+#[derive(Debug)]
+pub struct PayjoinError {
+    code: &'static str,
+    message: String,
+}
+
+impl PayjoinError {
+    fn unavailable(message: impl Into<String>) -> Self {
+        PayjoinError {
+            code: "unavailable",
+            message: message.into(),
+        }
+    }
+
+    fn not_enough_money(message: impl Into<String>) -> Self {
+        PayjoinError {
+            code: "not-enough-money",
+            message: message.into(),
+        }
+    }
+
+    fn rejected(message: impl Into<String>) -> Self {
+        PayjoinError {
+            code: "original-psbt-rejected",
+            message: message.into(),
+        }
+    }
+}
+
+impl IntoResponse for PayjoinError {
+    fn into_response(self) -> Response {
+        tracing::warn!(code = self.code, "payjoin refused: {}", self.message);
+        let mut body = serde_json::json!({ "errorCode": self.code, "message": self.message });
+        if self.code == "version-unsupported" {
+            body["supported"] = serde_json::json!([SUPPORTED_VERSION]);
+        }
+        (StatusCode::BAD_REQUEST, Json(body)).into_response()
+    }
+}
+
+/// A proposal ready to be signed, and where our contribution sits in it.
+struct Proposal {
+    psbt: Psbt,
+    input_index: usize,
+    outpoint: OutPoint,
+}
+
+pub async fn handle(
+    State(state): State<Arc<AppState>>,
+    ctx: RequestContext,
+    Query(params): Query<Params>,
+    body: String,
+) -> Result<String, PayjoinError> {
+    if let Some(v) = params.v.filter(|&v| v != SUPPORTED_VERSION) {
+        return Err(PayjoinError {
+            code: "version-unsupported",
+            message: format!("payjoin version {v} is not supported"),
+        });
+    }
+    if !state
+        .chain_synced
+        .load(std::sync::atomic::Ordering::Acquire)
+    {
+        return Err(PayjoinError::unavailable(
+            "wallet is not synced with the chain",
+        ));
+    }
+    let original = Psbt::from_str(body.trim())
+        .map_err(|e| PayjoinError::rejected(format!("invalid psbt: {e}")))?;
+
+    let proposal = propose(&state, &original, &params)?;
+    let outpoint = proposal.outpoint;
+    let release = |e: PayjoinError| {
+        state.payjoin.release(&outpoint);
+        e
+    };
+
+    let request = crate::SignRequest {
+        collaborative: true,
+        ..proposal.psbt.into()
+    };
+    let signed = crate::sign_and_record(&state, &ctx, request)
+        .await
+        .map_err(|e| release(PayjoinError::unavailable(format!("signing failed: {e}"))))?;
+    let mut psbt = signed.psbt;
+    let ours = &psbt.inputs[proposal.input_index];
+    if ours.final_script_sig.is_none() && ours.final_script_witness.is_none() {
+        return Err(release(PayjoinError::unavailable(
+            "could not finalize the contributed input",
+        )));
+    }
+    strip_for_sender(&mut psbt, proposal.input_index);
+    Ok(psbt.to_string())
+}
+
+/// Validates `original` and builds the unsigned proposal, reserving the
+/// coin it contributes.
+fn propose(state: &AppState, original: &Psbt, params: &Params) -> Result<Proposal, PayjoinError> {
+    let wallet = state.wallet();
+    let tx = &original.unsigned_tx;
+    if tx.input.is_empty() {
+        return Err(PayjoinError::rejected("original has no inputs"));
+    }
+
+    let mut sender_kind = None;
+    for (index, input) in original.inputs.iter().enumerate() {
+        if input.final_script_sig.is_none() && input.final_script_witness.is_none() {
+            return Err(PayjoinError::rejected(format!(
+                "input {index} is not finalized"
+            )));
+        }
+        let utxo = original
+            .spend_utxo(index)
+            .map_err(|e| PayjoinError::rejected(format!("input {index}: {e}")))?;
+        if wallet.is_mine(utxo.script_pubkey.clone()) {
+            return Err(PayjoinError::rejected(format!(
+                "input {index} spends a coin of this wallet"
+            )));
+        }
+        let kind = script_kind(&utxo.script_pubkey);
+        if sender_kind.is_some_and(|k| k != kind) {
+            return Err(PayjoinError::rejected("inputs mix script types"));
+        }
+        sender_kind = Some(kind);
+    }
+
+    let receiver_index = tx
+        .output
+        .iter()
+        .position(|out| wallet.is_mine(out.script_pubkey.clone()))
+        .ok_or_else(|| PayjoinError::rejected("original pays nothing to this wallet"))?;
+
+    let original_fee = original
+        .fee()
+        .map_err(|e| PayjoinError::rejected(format!("fee: {e}")))?;
+    let original_vsize = original
+        .clone()
+        .extract_tx_unchecked_fee_rate()
+        .weight()
+        .to_vbytes_ceil();
+    let fee_rate = original_fee.to_sat() as f64 / original_vsize as f64;
+
+    // Only coins of the sender's script type keep the transaction from
+    // standing out.
+    let mut candidates: Vec<_> = wallet
+        .list_unspent()
+        .filter(|utxo| utxo.chain_position.is_confirmed())
+        .filter(|utxo| Some(script_kind(&utxo.txout.script_pubkey)) == sender_kind)
+        .filter(|utxo| !state.payjoin.contains(&utxo.outpoint))
+        .collect();
+    candidates.shuffle(&mut rand::thread_rng());
+    let coin = candidates
+        .into_iter()
+        .next()
+        .ok_or_else(|| PayjoinError::unavailable("no confirmed coin to contribute"))?;
+
+    let satisfaction = wallet
+        .public_descriptor(KeychainKind::External)
+        .max_weight_to_satisfy()
+        .map_err(|e| PayjoinError::unavailable(format!("input weight: {e}")))?;
+    // Outpoint and sequence, plus the script and witness that spend it.
+    let input_vsize = (Weight::from_wu(160) + satisfaction).to_vbytes_ceil();
+    let additional_fee = (fee_rate * input_vsize as f64).ceil() as u64;
+
+    let mut psbt = original.clone();
+    let sender_contribution = params
+        .additionalfeeoutputindex
+        .filter(|&index| index != receiver_index)
+        .and_then(|index| psbt.unsigned_tx.output.get_mut(index))
+        .map(|output| {
+            let dust = output.script_pubkey.minimal_non_dust().to_sat();
+            let contribution = params
+                .maxadditionalfeecontribution
+                .unwrap_or(0)
+                .min(additional_fee)
+                .min(output.value.to_sat().saturating_sub(dust));
+            output.value -= Amount::from_sat(contribution);
+            contribution
+        })
+        .unwrap_or(0);
+    let receiver_fee = additional_fee - sender_contribution;
+    if receiver_fee > coin.txout.value.to_sat() {
+        return Err(PayjoinError::not_enough_money(
+            "contributed coin does not cover its own fee",
+        ));
+    }
+    if let Some(min) = params.minfeerate {
+        let new_fee = original_fee.to_sat() + additional_fee;
+        let new_rate = new_fee as f64 / (original_vsize + input_vsize) as f64;
+        if new_rate < min {
+            return Err(PayjoinError::not_enough_money(format!(
+                "proposal fee rate {new_rate:.2} sat/vB is below minfeerate {min}"
+            )));
+        }
+    }
+    psbt.unsigned_tx.output[receiver_index].value +=
+        coin.txout.value - Amount::from_sat(receiver_fee);
+
+    // The sender re-signs the proposal, so its old signatures are dropped.
+    // Its UTXO data stays until after we sign: taproot sighashes commit to
+    // every prevout.
+    for input in &mut psbt.inputs {
+        input.final_script_sig = None;
+        input.final_script_witness = None;
+    }
+    let input_index = rand::thread_rng().gen_range(0..=psbt.inputs.len());
+    psbt.unsigned_tx.input.insert(
+        input_index,
+        TxIn {
+            previous_output: coin.outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence: tx
+                .input
+                .first()
+                .map_or(Sequence::MAX, |input| input.sequence),
+            witness: Witness::new(),
+        },
+    );
+    psbt.inputs.insert(
+        input_index,
+        psbt::Input {
+            witness_utxo: Some(coin.txout.clone()),
+            non_witness_utxo: wallet
+                .get_tx(coin.outpoint.txid)
+                .map(|tx| tx.tx_node.tx.as_ref().clone()),
+            ..Default::default()
+        },
+    );
+
+    if !state.payjoin.reserve(coin.outpoint) {
+        return Err(PayjoinError::unavailable(
+            "coin was just offered to another sender",
+        ));
+    }
+    Ok(Proposal {
+        psbt,
+        input_index,
+        outpoint: coin.outpoint,
+    })
+}
+
+/// Removes what BIP-78 forbids in a proposal: key origins anywhere, and
+/// UTXO data on the sender's inputs.
+fn strip_for_sender(psbt: &mut Psbt, our_index: usize) {
+    psbt.xpub.clear();
+    for (index, input) in psbt.inputs.iter_mut().enumerate() {
+        input.bip32_derivation.clear();
+        input.tap_key_origins.clear();
+        if index != our_index {
+            input.witness_utxo = None;
+            input.non_witness_utxo = None;
+        }
+    }
+    for output in &mut psbt.outputs {
+        output.bip32_derivation.clear();
+        output.tap_key_origins.clear();
+    }
+}
+
+fn script_kind(script: &ScriptBuf) -> &'static str {
+    if script.is_p2pkh() {
+        "p2pkh"
+    } else if script.is_p2sh() {
+        "p2sh"
+    } else if script.is_p2wpkh() {
+        "p2wpkh"
+    } else if script.is_p2wsh() {
+        "p2wsh"
+    } else if script.is_p2tr() {
+        "p2tr"
+    } else {
+        "other"
+    }
+}
@@ -0,0 +1,371 @@
+//! `POST /admin/create_payout`: pays many addresses at once, such as a
+//! batch of user withdrawals.
+//!
+//! Payments are checked against `[payout]` as a whole before anything is
+//! built. They are then packed, in order, into as few transactions as fit
+//! `payout.max_tx_weight` once signed, each with change back to the wallet
+//! and none spending a coin another one does, so they can be broadcast in
+//! any order. Each is signed and recorded through the same path as
+//! `/sign_psbt`. Nothing is broadcast: the caller does that.
+//!
+//! `POST /admin/create_psbt` builds a single unsigned transaction from the
+//! same checked payments, optionally with `OP_RETURN` outputs carrying the
+//! caller's data, for anchoring and attestations. The data outputs are part
+//! of what the fee is computed over, which adding them to a built PSBT
+//! afterwards would not be. The PSBT is signed through `/sign_psbt`.
+
+use std::{collections::HashSet, sync::Arc};
+
+use axum::{extract::State, Json};
+use bdk_wallet::KeychainKind;
+use bitcoin::{script::PushBytesBuf, Amount, OutPoint, Psbt, ScriptBuf, Txid, Weight};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::Metadata,
+    config::PolicyPath,
+    context::RequestContext,
+    error::{ApiJson, Error},
+    priority::Priority,
+    AppState, SignRequest,
+};
+
+#[derive(Deserialize)]
+pub struct PayoutRequest {
+    payments: Vec<Payment>,
+    /// In sat/vB.
+    fee_rate_sat_vb: f64,
+    /// Caps the payments per transaction below what the weight limit allows.
+    #[serde(default)]
+    max_payments_per_tx: Option<usize>,
+    /// Recorded in the audit entry of every transaction, as for `/sign_psbt`.
+    #[serde(default)]
+    metadata: Metadata,
+    /// Signing priority of every transaction, as for `/sign_psbt`.
+    #[serde(default)]
+    priority: Priority,
+    /// Branches of the descriptor's policy to satisfy, as for
+    /// `/admin/sweep`.
+    #[serde(default)]
+    policy_path: Option<PolicyPath>,
+}
+
+#[derive(Deserialize)]
+pub struct Payment {
+    address: String,
+    amount_sat: u64,
+}
+
+#[derive(Serialize)]
+pub struct PayoutResponse {
+    pub transactions: Vec<PayoutTransaction>,
+    pub amount_sat: u64,
+    pub fee_sat: u64,
+    /// Set when `signing.dry_run` left the transactions unsigned.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize)]
+pub struct PayoutTransaction {
+    /// Base64-encoded, finalized PSBT.
+    #[serde(serialize_with = "crate::serialize_psbt_to_base64")]
+    pub psbt: Psbt,
+    pub txid: Txid,
+    /// Index in `payments` of the first payment this transaction makes.
+    pub first_payment: usize,
+    /// Number of payments it makes, from `first_payment` on.
+    pub payments: usize,
+    pub amount_sat: u64,
+    pub fee_sat: u64,
+    /// Expected weight once signed.
+    pub weight: u64,
+}
+
+pub async fn create_payout(
+    State(state): State<Arc<AppState>>,
+    ctx: RequestContext,
+    ApiJson(request): ApiJson<PayoutRequest>,
+) -> Result<Json<PayoutResponse>, Error> {
+    crate::chain::require_synced(&state)?;
+    let fee_rate = crate::sweep::fee_rate(request.fee_rate_sat_vb)?;
+    if request.payments.is_empty() {
+        return Err(Error::MalformedRequest("no payments".into()));
+    }
+    let recipients = check(&state, &request.payments)?;
+    let max_weight = Weight::from_wu(state.config().payout.max_tx_weight);
+    let max_per_tx = request.max_payments_per_tx.unwrap_or(usize::MAX).max(1);
+    let input_weight = {
+        let wallet = state.wallet();
+        wallet
+            .public_descriptor(KeychainKind::External)
+            .max_weight_to_satisfy()
+            .map_err(|e| Error::Internal(format!("descriptor weight: {e}")))?
+    };
+
+    // Coins spent by earlier transactions, and those labeled as not
+    // spendable, stay out of later ones.
+    let policy_path = request
+        .policy_path
+        .clone()
+        .unwrap_or_else(|| state.config().policy_path.clone());
+    let mut unspendable = unspendable(&state);
+    let mut built = Vec::new();
+    let mut next = 0;
+    while next < recipients.len() {
+        let mut take = (recipients.len() - next).min(max_per_tx);
+        let (psbt, weight) = loop {
+            let psbt = state
+                .wallet
+                .build_payout(
+                    recipients[next..next + take].to_vec(),
+                    unspendable.iter().copied().collect(),
+                    fee_rate,
+                    policy_path.clone(),
+                )
+                .await?;
+            // Every input spends one of the wallet's coins.
+            let weight = psbt.unsigned_tx.weight()
+                + Weight::from_wu(2)
+                + input_weight * psbt.unsigned_tx.input.len() as u64;
+            if weight <= max_weight {
+                break (psbt, weight);
+            }
+            if take == 1 {
+                return Err(Error::InvalidTransaction(format!(
+                    "paying payment {next} alone needs {weight} weight, more than \
+                     payout.max_tx_weight; consolidate the wallet's coins first"
+                )));
+            }
+            take /= 2;
+        };
+        unspendable.extend(
+            psbt.unsigned_tx
+                .input
+                .iter()
+                .map(|input| input.previous_output),
+        );
+        built.push((next, take, psbt, weight));
+        next += take;
+    }
+
+    let mut transactions = Vec::with_capacity(built.len());
+    let mut dry_run = false;
+    for (first_payment, payments, psbt, weight) in built {
+        let txid = psbt.unsigned_tx.compute_txid();
+        let fee = psbt
+            .fee()
+            .map_err(|e| Error::Internal(format!("payout fee: {e}")))?;
+        let amount = recipients[first_payment..first_payment + payments]
+            .iter()
+            .map(|(_, amount)| *amount)
+            .sum::<Amount>();
+        let signed = crate::sign_and_record(
+            &state,
+            &ctx,
+            SignRequest {
+                psbt,
+                consignment: None,
+                metadata: request.metadata.clone(),
+                replace: false,
+                priority: request.priority,
+                sign_options: None,
+                collaborative: false,
+                prevouts: Vec::new(),
+                pairings: Vec::new(),
+            },
+        )
+        .await?;
+        dry_run = signed.dry_run;
+        transactions.push(PayoutTransaction {
+            psbt: signed.psbt,
+            txid,
+            first_payment,
+            payments,
+            amount_sat: amount.to_sat(),
+            fee_sat: fee.to_sat(),
+            weight: weight.to_wu(),
+        });
+    }
+    tracing::info!(
+        payments = recipients.len(),
+        transactions = transactions.len(),
+        "created payout"
+    );
+    Ok(Json(PayoutResponse {
+        amount_sat: transactions.iter().map(|tx| tx.amount_sat).sum(),
+        fee_sat: transactions.iter().map(|tx| tx.fee_sat).sum(),
+        transactions,
+        dry_run,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct CreatePsbtRequest {
+    #[serde(default)]
+    payments: Vec<Payment>,
+    /// Data of each `OP_RETURN` output, hex-encoded.
+    #[serde(default)]
+    op_return: Vec<String>,
+    /// In sat/vB.
+    fee_rate_sat_vb: f64,
+    /// Branches of the descriptor's policy to satisfy, as for
+    /// `/admin/sweep`.
+    #[serde(default)]
+    policy_path: Option<PolicyPath>,
+}
+
+#[derive(Serialize)]
+pub struct CreatePsbtResponse {
+    /// Base64-encoded, unsigned PSBT.
+    #[serde(serialize_with = "crate::serialize_psbt_to_base64")]
+    pub psbt: Psbt,
+    pub txid: Txid,
+    pub fee_sat: u64,
+}
+
+pub async fn create_psbt(
+    State(state): State<Arc<AppState>>,
+    ApiJson(request): ApiJson<CreatePsbtRequest>,
+) -> Result<Json<CreatePsbtResponse>, Error> {
+    crate::chain::require_synced(&state)?;
+    let fee_rate = crate::sweep::fee_rate(request.fee_rate_sat_vb)?;
+    if request.payments.is_empty() && request.op_return.is_empty() {
+        return Err(Error::MalformedRequest(
+            "no payments or op_return outputs".into(),
+        ));
+    }
+    let mut recipients = check(&state, &request.payments)?;
+    recipients.extend(data_outputs(&state, &request.op_return)?);
+    let psbt = state
+        .wallet
+        .build_payout(
+            recipients,
+            unspendable(&state).into_iter().collect(),
+            fee_rate,
+            request
+                .policy_path
+                .unwrap_or_else(|| state.config().policy_path.clone()),
+        )
+        .await?;
+    let fee = psbt
+        .fee()
+        .map_err(|e| Error::Internal(format!("psbt fee: {e}")))?;
+    Ok(Json(CreatePsbtResponse {
+        txid: psbt.unsigned_tx.compute_txid(),
+        fee_sat: fee.to_sat(),
+        psbt,
+    }))
+}
+
+/// `OP_RETURN` outputs carrying `data`, checked against `[op_return]`.
+fn data_outputs(state: &AppState, data: &[String]) -> Result<Vec<(ScriptBuf, Amount)>, Error> {
+    let policy = state.config().op_return.clone();
+    if data.len() > policy.max_outputs {
+        return Err(Error::InvalidTransaction(format!(
+            "{} op_return outputs, more than op_return.max_outputs of {}",
+            data.len(),
+            policy.max_outputs
+        )));
+    }
+    data.iter()
+        .enumerate()
+        .map(|(index, data)| {
+            let bytes = hex::decode(data)
+                .map_err(|e| Error::MalformedRequest(format!("op_return {index}: {e}")))?;
+            if bytes.len() > policy.max_bytes {
+                return Err(Error::InvalidTransaction(format!(
+                    "op_return {index}: {} bytes, more than op_return.max_bytes of {}",
+                    bytes.len(),
+                    policy.max_bytes
+                )));
+            }
+            let push = PushBytesBuf::try_from(bytes)
+                .map_err(|e| Error::InvalidTransaction(format!("op_return {index}: {e}")))?;
+            Ok((ScriptBuf::new_op_return(push), Amount::ZERO))
+        })
+        .collect()
+}
+
+/// Checks `payments` against `[payout]` and the wallet's balance, and
+/// returns them as recipients.
+fn check(state: &AppState, payments: &[Payment]) -> Result<Vec<(ScriptBuf, Amount)>, Error> {
+    let config = state.config();
+    let policy = &config.payout;
+    let policy_dust = config.dust.as_ref();
+    if payments.len() > policy.max_payments {
+        return Err(Error::MalformedRequest(format!(
+            "{} payments, more than payout.max_payments of {}",
+            payments.len(),
+            policy.max_payments
+        )));
+    }
+    let mut recipients = Vec::with_capacity(payments.len());
+    let mut total = Amount::ZERO;
+    for (index, payment) in payments.iter().enumerate() {
+        let script = crate::sweep::destination(config.network, &payment.address)
+            .map_err(|e| Error::MalformedRequest(format!("payment {index}: {e}")))?
+            .script_pubkey();
+        let amount = Amount::from_sat(payment.amount_sat);
+        let dust = match policy_dust {
+            Some(dust) if !dust.own_outputs_only => {
+                crate::dust::threshold(dust, &script).unwrap_or(Amount::ZERO)
+            }
+            _ => script.minimal_non_dust(),
+        };
+        if amount < dust {
+            return Err(Error::InvalidTransaction(format!(
+                "payment {index}: {} sat is below the dust threshold of {} sat",
+                amount.to_sat(),
+                dust.to_sat()
+            )));
+        }
+        if policy
+            .max_amount_sat
+            .is_some_and(|max| payment.amount_sat > max)
+        {
+            return Err(Error::InvalidTransaction(format!(
+                "payment {index}: {} sat is above payout.max_amount_sat",
+                amount.to_sat()
+            )));
+        }
+        total = total
+            .checked_add(amount)
+            .ok_or_else(|| Error::MalformedRequest("payments overflow".into()))?;
+        recipients.push((script, amount));
+    }
+    if policy.max_total_sat.is_some_and(|max| total.to_sat() > max) {
+        return Err(Error::InvalidTransaction(format!(
+            "payments total {} sat, above payout.max_total_sat",
+            total.to_sat()
+        )));
+    }
+    let skipped = unspendable(state);
+    let available = state
+        .wallet()
+        .list_unspent()
+        .filter(|utxo| !skipped.contains(&utxo.outpoint))
+        .map(|utxo| utxo.txout.value)
+        .sum::<Amount>();
+    if total > available {
+        return Err(Error::InvalidTransaction(format!(
+            "payments total {} sat, more than the {} sat of spendable coins",
+            total.to_sat(),
+            available.to_sat()
+        )));
+    }
+    Ok(recipients)
+}
+
+/// Coins labeled as not spendable.
+fn unspendable(state: &AppState) -> HashSet<OutPoint> {
+    let Some(labels) = &state.labels else {
+        return HashSet::new();
+    };
+    state
+        .wallet()
+        .list_unspent()
+        .map(|utxo| utxo.outpoint)
+        .filter(|&outpoint| !labels.spendable(outpoint))
+        .collect()
+}
@@ -0,0 +1,451 @@
+//! UTXO data given alongside a PSBT.
+//!
+//! A signing request may carry `prevouts` for inputs whose PSBT lacks what
+//! they spend: the full previous `transaction`, set as the input's
+//! `non_witness_utxo`, or the spent output's `value_sat` and
+//! `script_pubkey`, set as its `witness_utxo`. Each is checked against the
+//! input's outpoint, against what the PSBT and the wallet already know of
+//! the coin and against each other before it is added, so a stripped PSBT
+//! can be signed without a chain backend. Legacy coins are signed over
+//! their whole previous transaction, so for them only a `transaction` does.
+//!
+//! With `chain.backfill_prevouts`, the previous transactions inputs still
+//! lack are then taken from the wallet's own or fetched from `[chain]`, and
+//! added the same way. Taproot inputs are left to their `witness_utxo` only
+//! while every input the wallet signs is taproot, since a segwit v0
+//! signature does not commit to the other inputs' amounts. An input of this wallet left without UTXO data is
+//! refused, where it would otherwise come back unsigned.
+
+use std::collections::{btree_map::Entry, BTreeMap, BTreeSet};
+
+use bdk_wallet::{miniscript::ForEachKey, KeychainKind, Wallet};
+use bitcoin::{
+    bip32::Fingerprint, consensus::encode::deserialize_hex, psbt::Input, Amount, Psbt, Script,
+    ScriptBuf, Transaction, TxIn, TxOut, Txid,
+};
+use serde::Deserialize;
+
+use crate::{chain, config::ChainConfig, error::Error, AppState};
+
+#[derive(Debug, Clone, Deserialize, utoipa::ToSchema)]
+pub struct Prevout {
+    /// Index of the input the data is for.
+    pub input: usize,
+    /// Hex consensus-encoded transaction the input spends an output of.
+    #[serde(default)]
+    pub transaction: Option<String>,
+    /// Amount of the spent output, given with `script_pubkey`.
+    #[serde(default)]
+    pub value_sat: Option<u64>,
+    /// Hex script of the spent output.
+    #[serde(default)]
+    pub script_pubkey: Option<String>,
+}
+
+/// Adds `prevouts` to the inputs of `psbt` they are for, refusing data
+/// that disagrees with the input, the PSBT, `wallet` or itself.
+pub fn apply(wallet: &Wallet, psbt: &mut Psbt, prevouts: &[Prevout]) -> Result<(), Error> {
+    let mut seen = BTreeSet::new();
+    for prevout in prevouts {
+        let index = prevout.input;
+        let invalid =
+            |reason: String| Error::MalformedRequest(format!("prevouts: input {index}: {reason}"));
+        if !seen.insert(index) {
+            return Err(invalid("given twice".into()));
+        }
+        let (Some(txin), Some(input)) = (
+            psbt.unsigned_tx.input.get(index),
+            psbt.inputs.get_mut(index),
+        ) else {
+            return Err(invalid("the PSBT has no such input".into()));
+        };
+        let outpoint = txin.previous_output;
+
+        let transaction = match &prevout.transaction {
+            Some(hex) => {
+                let tx: Transaction =
+                    deserialize_hex(hex).map_err(|e| invalid(format!("transaction: {e}")))?;
+                let txid = tx.compute_txid();
+                if txid != outpoint.txid {
+                    return Err(invalid(format!(
+                        "transaction is {txid}, not the spent {}",
+                        outpoint.txid
+                    )));
+                }
+                Some(tx)
+            }
+            None => None,
+        };
+        let from_transaction = transaction
+            .as_ref()
+            .map(|tx| {
+                tx.output
+                    .get(outpoint.vout as usize)
+                    .cloned()
+                    .ok_or_else(|| invalid(format!("transaction has no output {}", outpoint.vout)))
+            })
+            .transpose()?;
+        let explicit = match (prevout.value_sat, &prevout.script_pubkey) {
+            (Some(value), Some(script)) => Some(TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: ScriptBuf::from_hex(script)
+                    .map_err(|e| invalid(format!("script_pubkey: {e}")))?,
+            }),
+            (None, None) => None,
+            _ => return Err(invalid("value_sat and script_pubkey go together".into())),
+        };
+        let spent = match (from_transaction, explicit) {
+            (Some(output), Some(explicit)) if output != explicit => {
+                return Err(invalid(
+                    "value_sat and script_pubkey disagree with the transaction".into(),
+                ))
+            }
+            (Some(output), _) | (None, Some(output)) => output,
+            (None, None) => {
+                return Err(invalid(
+                    "neither a transaction nor value_sat and script_pubkey".into(),
+                ))
+            }
+        };
+
+        let known = [
+            input.witness_utxo.clone(),
+            input
+                .non_witness_utxo
+                .as_ref()
+                .and_then(|tx| tx.output.get(outpoint.vout as usize).cloned()),
+            wallet.tx_graph().get_txout(outpoint).cloned(),
+        ];
+        if known.iter().flatten().any(|known| *known != spent) {
+            return Err(invalid(format!(
+                "{} sat to {} disagrees with what is known of {outpoint}",
+                spent.value.to_sat(),
+                spent.script_pubkey
+            )));
+        }
+        if transaction.is_none() && input.non_witness_utxo.is_none() && legacy(&spent.script_pubkey)
+        {
+            return Err(invalid(
+                "a legacy coin is signed over its previous transaction; give the transaction"
+                    .into(),
+            ));
+        }
+        if let Some(tx) = transaction {
+            input.non_witness_utxo = Some(tx);
+        }
+        if !legacy(&spent.script_pubkey) {
+            input.witness_utxo = Some(spent);
+        }
+    }
+    Ok(())
+}
+
+/// Adds the previous transaction to each input of `psbt` that is not
+/// finalized and lacks one, unless it is a taproot input with its
+/// `witness_utxo` and the wallet signs taproot inputs only, see
+/// [`signs_taproot_only`]. A transaction neither the wallet nor `chain`
+/// knows is left out, for [`check_complete`] to refuse if the input is
+/// ours.
+pub async fn backfill(state: &AppState, chain: &ChainConfig, psbt: &mut Psbt) -> Result<(), Error> {
+    let mut fetched: BTreeMap<Txid, Option<Transaction>> = BTreeMap::new();
+    let mut added = 0;
+    let trust_taproot = signs_taproot_only(&state.wallet(), psbt);
+    for (index, (txin, input)) in psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .zip(&mut psbt.inputs)
+        .enumerate()
+    {
+        let trusted = trust_taproot
+            && input
+                .witness_utxo
+                .as_ref()
+                .is_some_and(|utxo| utxo.script_pubkey.is_p2tr());
+        let finalized = input.final_script_sig.is_some() || input.final_script_witness.is_some();
+        if input.non_witness_utxo.is_some() || trusted || finalized {
+            continue;
+        }
+        let outpoint = txin.previous_output;
+        let tx = match fetched.entry(outpoint.txid) {
+            Entry::Occupied(known) => known.get().clone(),
+            Entry::Vacant(entry) => {
+                let tx = previous_transaction(state, chain, &outpoint.txid)
+                    .await
+                    .map_err(|e| {
+                        Error::Unavailable(format!(
+                            "backfilling input {index}: fetching {}: {e}",
+                            outpoint.txid
+                        ))
+                    })?;
+                entry.insert(tx).clone()
+            }
+        };
+        let Some(tx) = tx else {
+            tracing::debug!(index, txid = %outpoint.txid, "previous transaction not found");
+            continue;
+        };
+        let Some(output) = tx.output.get(outpoint.vout as usize) else {
+            return Err(Error::InvalidTransaction(format!(
+                "input {index} spends output {} of {}, which has no such output",
+                outpoint.vout, outpoint.txid
+            )));
+        };
+        if input
+            .witness_utxo
+            .as_ref()
+            .is_some_and(|utxo| utxo != output)
+        {
+            return Err(Error::InvalidTransaction(format!(
+                "input {index}: witness_utxo disagrees with the output {} has",
+                outpoint.txid
+            )));
+        }
+        if input.witness_utxo.is_none() && !legacy(&output.script_pubkey) {
+            input.witness_utxo = Some(output.clone());
+        }
+        input.non_witness_utxo = Some(tx);
+        added += 1;
+    }
+    if added > 0 {
+        tracing::info!(inputs = added, "backfilled previous transactions");
+    }
+    Ok(())
+}
+
+/// `txid` from the wallet's own transactions, or else from `chain`.
+pub async fn previous_transaction(
+    state: &AppState,
+    chain: &ChainConfig,
+    txid: &Txid,
+) -> Result<Option<Transaction>, String> {
+    let known = state.wallet().tx_graph().get_tx(*txid);
+    match known {
+        Some(tx) => Ok(Some(tx.as_ref().clone())),
+        None => chain::transaction(chain, txid).await,
+    }
+}
+
+/// Refuses `psbt` if an input of `wallet`'s, by the origin of a key or a
+/// coin the wallet holds, is left without the UTXO data to sign it.
+pub fn check_complete(wallet: &Wallet, psbt: &Psbt) -> Result<(), Error> {
+    let fingerprints = fingerprints(wallet);
+    for (index, (txin, input)) in psbt.unsigned_tx.input.iter().zip(&psbt.inputs).enumerate() {
+        if !signed_by(wallet, &fingerprints, txin, input) {
+            continue;
+        }
+        match (&input.non_witness_utxo, &input.witness_utxo) {
+            (None, None) => {
+                return Err(Error::InvalidTransaction(format!(
+                    "input {index} has no UTXO data to sign over; add non_witness_utxo or \
+                     give it in `prevouts`"
+                )))
+            }
+            (None, Some(utxo)) if legacy(&utxo.script_pubkey) => {
+                return Err(Error::InvalidTransaction(format!(
+                    "input {index} spends a legacy coin, which is signed over its previous \
+                     transaction; add non_witness_utxo or give it in `prevouts`"
+                )))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Whether every input of `psbt` that `wallet` signs spends a taproot coin.
+/// Only then does every signature commit to the amounts of all the coins
+/// spent, as BIP-341's do, so that a taproot `witness_utxo` overstating its
+/// amount leaves the wallet's signatures invalid; a segwit v0 signature
+/// stays valid whatever the other inputs claim.
+pub fn signs_taproot_only(wallet: &Wallet, psbt: &Psbt) -> bool {
+    let fingerprints = fingerprints(wallet);
+    psbt.unsigned_tx
+        .input
+        .iter()
+        .zip(&psbt.inputs)
+        .filter(|(txin, input)| signed_by(wallet, &fingerprints, txin, input))
+        .all(|(txin, input)| {
+            let outpoint = txin.previous_output;
+            wallet
+                .get_utxo(outpoint)
+                .map(|utxo| utxo.txout)
+                .or_else(|| input.witness_utxo.clone())
+                .or_else(|| {
+                    let tx = input.non_witness_utxo.as_ref()?;
+                    tx.output.get(outpoint.vout as usize).cloned()
+                })
+                .is_some_and(|spent| spent.script_pubkey.is_p2tr())
+        })
+}
+
+/// Master fingerprints of `wallet`'s keys.
+fn fingerprints(wallet: &Wallet) -> BTreeSet<Fingerprint> {
+    let mut fingerprints = BTreeSet::new();
+    for keychain in [KeychainKind::External, KeychainKind::Internal] {
+        wallet.public_descriptor(keychain).for_each_key(|key| {
+            fingerprints.insert(key.master_fingerprint());
+            true
+        });
+    }
+    fingerprints
+}
+
+/// Whether `input` is one of `wallet`'s not yet finalized, by the origin of
+/// a key or a coin the wallet holds.
+fn signed_by(
+    wallet: &Wallet,
+    fingerprints: &BTreeSet<Fingerprint>,
+    txin: &TxIn,
+    input: &Input,
+) -> bool {
+    if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+        return false;
+    }
+    input
+        .bip32_derivation
+        .values()
+        .chain(input.tap_key_origins.values().map(|(_, source)| source))
+        .any(|(fingerprint, _)| fingerprints.contains(fingerprint))
+        || wallet.get_utxo(txin.previous_output).is_some()
+}
+
+/// Whether `script` is spent without a witness. P2SH may wrap a witness
+/// program, so it is not counted.
+fn legacy(script: &Script) -> bool {
+    !script.is_witness_program() && !script.is_p2sh()
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{consensus::encode::serialize_hex, hashes::Hash, PubkeyHash};
+
+    use super::*;
+    use crate::test_support::{coin, ours, psbt, script, state, taproot, wallet};
+
+    /// A chain backend nothing answers at, to tell when a fetch is tried.
+    const UNREACHABLE: &str =
+        "[chain]\nesplora_url = \"http://127.0.0.1:9\"\nbackfill_prevouts = true";
+
+    fn prevout(input: usize) -> Prevout {
+        Prevout {
+            input,
+            transaction: None,
+            value_sat: None,
+            script_pubkey: None,
+        }
+    }
+
+    #[test]
+    fn tells_when_the_wallet_signs_taproot_only() {
+        let wallet = wallet();
+        let (v0, tr) = (coin(script(0), 1_000), coin(taproot(1), 1_000));
+        let theirs = coin(taproot(2), 1_000);
+        assert!(signs_taproot_only(&wallet, &psbt(&[&theirs], &[])));
+        let mut spend = psbt(&[&tr, &theirs], &[]);
+        ours(&mut spend.inputs[0]);
+        assert!(signs_taproot_only(&wallet, &spend));
+        let mut spend = psbt(&[&v0, &theirs], &[]);
+        ours(&mut spend.inputs[0]);
+        assert!(!signs_taproot_only(&wallet, &spend));
+        spend.inputs[0].final_script_witness = Some(Default::default());
+        assert!(signs_taproot_only(&wallet, &spend));
+    }
+
+    #[tokio::test]
+    async fn backfills_foreign_taproot_inputs_beside_segwit_v0() {
+        let state = state(UNREACHABLE).await;
+        let chain = state.config().chain.clone().unwrap();
+        let (v0, tr) = (coin(script(0), 1_000), coin(taproot(1), 1_000));
+        let theirs = coin(taproot(2), 1_000);
+
+        let mut spend = psbt(&[&v0, &theirs], &[]);
+        ours(&mut spend.inputs[0]);
+        spend.inputs[0].non_witness_utxo = Some(v0.clone());
+        match backfill(&state, &chain, &mut spend).await {
+            Err(Error::Unavailable(message)) => assert!(message.contains("input 1"), "{message}"),
+            other => panic!("the foreign taproot input was not backfilled: {other:?}"),
+        }
+
+        let mut spend = psbt(&[&tr, &theirs], &[]);
+        ours(&mut spend.inputs[0]);
+        backfill(&state, &chain, &mut spend).await.unwrap();
+        assert!(spend
+            .inputs
+            .iter()
+            .all(|input| input.non_witness_utxo.is_none()));
+    }
+
+    #[test]
+    fn refuses_prevouts_that_disagree() {
+        let wallet = wallet();
+        let v0 = coin(script(0), 1_000);
+        let spend = psbt(&[&v0], &[]);
+        let refused = |prevouts: &[Prevout]| {
+            let mut spend = spend.clone();
+            match apply(&wallet, &mut spend, prevouts) {
+                Err(Error::MalformedRequest(message)) => message,
+                other => panic!("{prevouts:?}: {other:?}"),
+            }
+        };
+        let transaction = |tx: &Transaction| Prevout {
+            transaction: Some(serialize_hex(tx)),
+            ..prevout(0)
+        };
+        let explicit = |value_sat: u64, script: &ScriptBuf| Prevout {
+            value_sat: Some(value_sat),
+            script_pubkey: Some(script.to_hex_string()),
+            ..prevout(0)
+        };
+
+        assert!(refused(&[prevout(1)]).contains("no such input"));
+        assert!(refused(&[transaction(&v0), transaction(&v0)]).contains("given twice"));
+        assert!(refused(&[prevout(0)]).contains("neither a transaction"));
+        assert!(refused(&[transaction(&coin(script(0), 2_000))]).contains("not the spent"));
+        assert!(refused(&[explicit(2_000, &script(0))]).contains("disagrees with what is known"));
+        let both = Prevout {
+            value_sat: Some(2_000),
+            ..transaction(&v0)
+        };
+        assert!(refused(&[both]).contains("go together"));
+        let mut mismatch = explicit(2_000, &script(0));
+        mismatch.transaction = transaction(&v0).transaction;
+        assert!(refused(&[mismatch]).contains("disagree with the transaction"));
+
+        let legacy = coin(
+            ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array([1; 20])),
+            1_000,
+        );
+        let mut spend = psbt(&[&legacy], &[]);
+        spend.inputs[0].witness_utxo = None;
+        let error = apply(
+            &wallet,
+            &mut spend,
+            &[explicit(1_000, &legacy.output[0].script_pubkey)],
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("legacy coin"), "{error}");
+
+        let mut spend = psbt(&[&v0], &[]);
+        spend.inputs[0].witness_utxo = None;
+        apply(&wallet, &mut spend, &[transaction(&v0)]).unwrap();
+        assert_eq!(spend.inputs[0].non_witness_utxo.as_ref(), Some(&v0));
+        assert_eq!(spend.inputs[0].witness_utxo.as_ref(), Some(&v0.output[0]));
+    }
+
+    #[test]
+    fn refuses_inputs_of_the_wallet_without_utxo_data() {
+        let wallet = wallet();
+        let v0 = coin(script(0), 1_000);
+        let mut spend = psbt(&[&v0], &[]);
+        spend.inputs[0].witness_utxo = None;
+        check_complete(&wallet, &spend).unwrap();
+        ours(&mut spend.inputs[0]);
+        assert!(matches!(
+            check_complete(&wallet, &spend),
+            Err(Error::InvalidTransaction(_))
+        ));
+        spend.inputs[0].non_witness_utxo = Some(v0);
+        check_complete(&wallet, &spend).unwrap();
+    }
+}
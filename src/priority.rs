@@ -0,0 +1,208 @@
+//! Signing slots handed out by priority class.
+//!
+//! A signing request is either `interactive`, the default, or `batch`.
+//! When every slot is busy, a freed slot goes to the longest waiting
+//! interactive request before any batch request, and batch requests never
+//! hold more than `signing.batch_max_concurrent` slots, so a user waiting
+//! on a withdrawal is not queued behind a long consolidation or payout run.
+//! Within a class, requests are served in the order they arrived.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::Deserialize;
+use tokio::sync::oneshot;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    /// Someone is waiting on the answer.
+    #[default]
+    Interactive,
+    /// Bulk work that can wait for interactive requests.
+    Batch,
+}
+
+impl Priority {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::Interactive => "interactive",
+            Priority::Batch => "batch",
+        }
+    }
+}
+
+impl std::str::FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interactive" => Ok(Priority::Interactive),
+            "batch" => Ok(Priority::Batch),
+            _ => Err("expected `interactive` or `batch`".into()),
+        }
+    }
+}
+
+pub struct SigningSlots {
+    slots: Arc<Mutex<Slots>>,
+}
+
+struct Slots {
+    capacity: usize,
+    batch_capacity: usize,
+    running: usize,
+    batch_running: usize,
+    next_id: u64,
+    interactive: VecDeque<(u64, oneshot::Sender<()>)>,
+    batch: VecDeque<(u64, oneshot::Sender<()>)>,
+}
+
+impl Slots {
+    fn waiting(&mut self, priority: Priority) -> &mut VecDeque<(u64, oneshot::Sender<()>)> {
+        match priority {
+            Priority::Interactive => &mut self.interactive,
+            Priority::Batch => &mut self.batch,
+        }
+    }
+
+    /// Whether a `priority` request may take a slot without waiting.
+    fn free_for(&self, priority: Priority) -> bool {
+        self.running < self.capacity
+            && match priority {
+                Priority::Interactive => self.interactive.is_empty(),
+                Priority::Batch => {
+                    self.interactive.is_empty()
+                        && self.batch.is_empty()
+                        && self.batch_running < self.batch_capacity
+                }
+            }
+    }
+
+    fn take(&mut self, priority: Priority) {
+        self.running += 1;
+        if priority == Priority::Batch {
+            self.batch_running += 1;
+        }
+    }
+
+    /// Hands free slots to waiters, interactive ones first. A waiter that
+    /// gave up is skipped.
+    fn dispatch(&mut self) {
+        while self.running < self.capacity {
+            let priority = if !self.interactive.is_empty() {
+                Priority::Interactive
+            } else if !self.batch.is_empty() && self.batch_running < self.batch_capacity {
+                Priority::Batch
+            } else {
+                return;
+            };
+            let (_, waiter) = self.waiting(priority).pop_front().expect("checked");
+            if waiter.send(()).is_ok() {
+                self.take(priority);
+            }
+        }
+    }
+}
+
+/// A held signing slot, released on drop.
+pub struct SigningPermit {
+    slots: Arc<Mutex<Slots>>,
+    priority: Priority,
+}
+
+impl Drop for SigningPermit {
+    fn drop(&mut self) {
+        let mut slots = self.slots.lock().expect("signing slots");
+        slots.running -= 1;
+        if self.priority == Priority::Batch {
+            slots.batch_running -= 1;
+        }
+        slots.dispatch();
+    }
+}
+
+/// A queued request, leaving the queue when dropped, and giving back a slot
+/// it was handed but never took.
+struct Waiting {
+    slots: Arc<Mutex<Slots>>,
+    priority: Priority,
+    id: u64,
+    granted: oneshot::Receiver<()>,
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        let mut slots = self.slots.lock().expect("signing slots");
+        let id = self.id;
+        let waiting = slots.waiting(self.priority);
+        if let Some(position) = waiting.iter().position(|(queued, _)| *queued == id) {
+            waiting.remove(position);
+        } else if self.granted.try_recv().is_ok() {
+            slots.running -= 1;
+            if self.priority == Priority::Batch {
+                slots.batch_running -= 1;
+            }
+            slots.dispatch();
+        }
+    }
+}
+
+impl SigningSlots {
+    /// `capacity` slots, at most `batch_capacity` of them for batch
+    /// requests.
+    pub fn new(capacity: usize, batch_capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        SigningSlots {
+            slots: Arc::new(Mutex::new(Slots {
+                capacity,
+                batch_capacity: batch_capacity.clamp(1, capacity),
+                running: 0,
+                batch_running: 0,
+                next_id: 0,
+                interactive: VecDeque::new(),
+                batch: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Waits up to `wait` for a slot, returning `None` if none was handed
+    /// to this request in time.
+    pub async fn acquire(&self, priority: Priority, wait: Duration) -> Option<SigningPermit> {
+        let mut waiting = {
+            let mut slots = self.slots.lock().expect("signing slots");
+            if slots.free_for(priority) {
+                slots.take(priority);
+                return Some(self.permit(priority));
+            }
+            let id = slots.next_id;
+            slots.next_id += 1;
+            let (granted, receiver) = oneshot::channel();
+            slots.waiting(priority).push_back((id, granted));
+            Waiting {
+                slots: self.slots.clone(),
+                priority,
+                id,
+                granted: receiver,
+            }
+        };
+        let granted = match tokio::time::timeout(wait, &mut waiting.granted).await {
+            Ok(granted) => granted.is_ok(),
+            // Handed a slot just as the wait ran out.
+            Err(_) => waiting.granted.try_recv().is_ok(),
+        };
+        // Once received, the slot is the permit's to give back; otherwise
+        // `waiting` leaves the queue as it drops.
+        granted.then(|| self.permit(priority))
+    }
+
+    fn permit(&self, priority: Priority) -> SigningPermit {
+        SigningPermit {
+            slots: self.slots.clone(),
+            priority,
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Decoding and encoding of the PSBTs the service is handed, kept free of
+//! the rest of the crate so the fuzz targets in `fuzz/` build this file
+//! as it is.
+
+use std::{collections::VecDeque, str::FromStr};
+
+use bitcoin::{io, Psbt};
+use bytes::Bytes;
+use serde::Serializer;
+
+/// Decodes the PSBT from the string as the deserializer hands it over,
+/// without copying it into a `String` first.
+pub fn de_psbt_from_base64<'de, D>(deserializer: D) -> Result<Psbt, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    struct Base64Psbt;
+
+    impl serde::de::Visitor<'_> for Base64Psbt {
+        type Value = Psbt;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("a base64-encoded PSBT")
+        }
+
+        fn visit_str<E: serde::de::Error>(self, s: &str) -> Result<Psbt, E> {
+            Psbt::from_str(s).map_err(E::custom)
+        }
+    }
+
+    deserializer.deserialize_str(Base64Psbt)
+}
+
+/// Writes the base64 straight into the output, without an intermediate
+/// `String`.
+pub fn serialize_psbt_to_base64<S>(psbt: &Psbt, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_str(psbt)
+}
+
+/// Decodes a binary PSBT across `chunks`, as received, without copying
+/// them into one contiguous buffer.
+pub fn decode_chunks(chunks: VecDeque<Bytes>) -> Result<Psbt, bitcoin::psbt::Error> {
+    Psbt::deserialize_from_reader(&mut Chunks(chunks))
+}
+
+/// Body chunks, read in order as one stream of bytes.
+struct Chunks(VecDeque<Bytes>);
+
+impl io::Read for Chunks {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = io::BufRead::fill_buf(self)?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        io::BufRead::consume(self, len);
+        Ok(len)
+    }
+}
+
+impl io::BufRead for Chunks {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // Empty chunks would read as the end of the stream.
+        while self.0.front().is_some_and(Bytes::is_empty) {
+            self.0.pop_front();
+        }
+        Ok(self.0.front().map_or(&[], |chunk| chunk.as_ref()))
+    }
+
+    fn consume(&mut self, amount: usize) {
+        if let Some(chunk) = self.0.front_mut() {
+            let _ = chunk.split_to(amount.min(chunk.len()));
+            if chunk.is_empty() {
+                self.0.pop_front();
+            }
+        }
+    }
+}
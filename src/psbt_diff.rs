@@ -0,0 +1,308 @@
+//! Comparing two versions of a PSBT.
+//!
+//! Between signing rounds a PSBT passes through other parties' hands. The
+//! diff reports what changed from one version to the next: signatures added
+//! or removed, inputs finalized, coins spent and outputs paid, and the fee.
+//! Inputs are matched by the coin they spend and outputs by their script,
+//! so reordering them is not a change.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use axum::{extract::State, Json};
+use bitcoin::{psbt::Input, Address, Amount, Network, OutPoint, Psbt, ScriptBuf, Txid};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{ApiJson, ErrorResponse},
+    AppState,
+};
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct DiffRequest {
+    /// Base64-encoded earlier version.
+    #[serde(deserialize_with = "crate::de_psbt_from_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub before: Psbt,
+    /// Base64-encoded later version.
+    #[serde(deserialize_with = "crate::de_psbt_from_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub after: Psbt,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct DiffResponse {
+    #[schema(value_type = String)]
+    pub txid_before: Txid,
+    #[schema(value_type = String)]
+    pub txid_after: Txid,
+    /// Whether both versions are the same unsigned transaction, so only
+    /// PSBT fields such as signatures differ.
+    pub same_transaction: bool,
+    /// Coins spent by only one of the versions, as `txid:vout`.
+    #[schema(value_type = Vec<String>)]
+    pub inputs_added: Vec<OutPoint>,
+    #[schema(value_type = Vec<String>)]
+    pub inputs_removed: Vec<OutPoint>,
+    pub signatures_added: Vec<SignatureChange>,
+    pub signatures_removed: Vec<SignatureChange>,
+    /// Inputs carrying a final script in only one of the versions.
+    #[schema(value_type = Vec<String>)]
+    pub finalized: Vec<OutPoint>,
+    #[schema(value_type = Vec<String>)]
+    pub unfinalized: Vec<OutPoint>,
+    pub outputs_added: Vec<OutputEntry>,
+    pub outputs_removed: Vec<OutputEntry>,
+    /// Outputs to the same script with a different value.
+    pub outputs_changed: Vec<OutputChange>,
+    pub fee: FeeChange,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, utoipa::ToSchema)]
+pub struct SignatureChange {
+    /// The input's coin, as `txid:vout`.
+    #[schema(value_type = String)]
+    pub outpoint: OutPoint,
+    #[serde(rename = "type")]
+    pub kind: SignatureKind,
+    /// Hex key the signature is for; x-only for taproot.
+    pub pubkey: String,
+    /// Hex hash of the tapscript leaf, for script path signatures.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leaf_hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureKind {
+    Ecdsa,
+    TaprootKey,
+    TaprootScript,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct OutputEntry {
+    /// Unset for scripts without an address, such as `OP_RETURN`.
+    pub address: Option<String>,
+    pub script_pubkey: String,
+    pub value_sat: u64,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct OutputChange {
+    pub address: Option<String>,
+    pub script_pubkey: String,
+    pub before_sat: u64,
+    pub after_sat: u64,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct FeeChange {
+    /// In sat, when every input of the version carries its coin.
+    pub before_sat: Option<u64>,
+    pub after_sat: Option<u64>,
+    /// `after_sat - before_sat`, when both are known.
+    pub change_sat: Option<i64>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/diff_psbt",
+    request_body = DiffRequest,
+    responses(
+        (status = 200, description = "What changed between the two versions", body = DiffResponse),
+        (status = 400, description = "`malformed_request`", body = ErrorResponse),
+        (status = 415, description = "`unsupported_media_type`", body = ErrorResponse),
+    )
+)]
+pub async fn diff_handler(
+    State(state): State<Arc<AppState>>,
+    ApiJson(request): ApiJson<DiffRequest>,
+) -> Json<DiffResponse> {
+    Json(diff(
+        &request.before,
+        &request.after,
+        state.config().network,
+    ))
+}
+
+pub fn diff(before: &Psbt, after: &Psbt, network: Network) -> DiffResponse {
+    let (txid_before, txid_after) = (
+        before.unsigned_tx.compute_txid(),
+        after.unsigned_tx.compute_txid(),
+    );
+    let (inputs_before, inputs_after) = (inputs(before), inputs(after));
+    let (signatures_before, signatures_after) =
+        (signatures(&inputs_before), signatures(&inputs_after));
+    let (final_before, final_after) = (finalized(&inputs_before), finalized(&inputs_after));
+    let (outputs_added, outputs_removed, outputs_changed) = outputs(before, after, network);
+    let (fee_before, fee_after) = (fee(before), fee(after));
+
+    DiffResponse {
+        txid_before,
+        txid_after,
+        same_transaction: txid_before == txid_after,
+        inputs_added: missing_from(&inputs_after, &inputs_before),
+        inputs_removed: missing_from(&inputs_before, &inputs_after),
+        signatures_added: signatures_after
+            .difference(&signatures_before)
+            .cloned()
+            .collect(),
+        signatures_removed: signatures_before
+            .difference(&signatures_after)
+            .cloned()
+            .collect(),
+        finalized: final_after.difference(&final_before).copied().collect(),
+        unfinalized: final_before.difference(&final_after).copied().collect(),
+        outputs_added,
+        outputs_removed,
+        outputs_changed,
+        fee: FeeChange {
+            before_sat: fee_before,
+            after_sat: fee_after,
+            change_sat: fee_before
+                .zip(fee_after)
+                .map(|(before, after)| after as i64 - before as i64),
+        },
+    }
+}
+
+/// The PSBT inputs of `psbt` by the coin they spend.
+fn inputs(psbt: &Psbt) -> BTreeMap<OutPoint, &Input> {
+    psbt.unsigned_tx
+        .input
+        .iter()
+        .map(|txin| txin.previous_output)
+        .zip(&psbt.inputs)
+        .collect()
+}
+
+fn missing_from(
+    inputs: &BTreeMap<OutPoint, &Input>,
+    other: &BTreeMap<OutPoint, &Input>,
+) -> Vec<OutPoint> {
+    inputs
+        .keys()
+        .filter(|outpoint| !other.contains_key(outpoint))
+        .copied()
+        .collect()
+}
+
+fn signatures(inputs: &BTreeMap<OutPoint, &Input>) -> BTreeSet<SignatureChange> {
+    let mut signatures = BTreeSet::new();
+    for (outpoint, input) in inputs {
+        let outpoint = *outpoint;
+        for pubkey in input.partial_sigs.keys() {
+            signatures.insert(SignatureChange {
+                outpoint,
+                kind: SignatureKind::Ecdsa,
+                pubkey: pubkey.to_string(),
+                leaf_hash: None,
+            });
+        }
+        if let (Some(_), Some(key)) = (&input.tap_key_sig, &input.tap_internal_key) {
+            signatures.insert(SignatureChange {
+                outpoint,
+                kind: SignatureKind::TaprootKey,
+                pubkey: key.to_string(),
+                leaf_hash: None,
+            });
+        }
+        for (key, leaf_hash) in input.tap_script_sigs.keys() {
+            signatures.insert(SignatureChange {
+                outpoint,
+                kind: SignatureKind::TaprootScript,
+                pubkey: key.to_string(),
+                leaf_hash: Some(leaf_hash.to_string()),
+            });
+        }
+    }
+    signatures
+}
+
+fn finalized(inputs: &BTreeMap<OutPoint, &Input>) -> BTreeSet<OutPoint> {
+    inputs
+        .iter()
+        .filter(|(_, input)| {
+            input.final_script_sig.is_some() || input.final_script_witness.is_some()
+        })
+        .map(|(outpoint, _)| *outpoint)
+        .collect()
+}
+
+/// Outputs only `after` pays, only `before` pays, and paid by both with a
+/// different value. Outputs to the same script pair up by equal value
+/// first, then in order.
+fn outputs(
+    before: &Psbt,
+    after: &Psbt,
+    network: Network,
+) -> (Vec<OutputEntry>, Vec<OutputEntry>, Vec<OutputChange>) {
+    let values = |psbt: &Psbt| {
+        let mut values: BTreeMap<ScriptBuf, Vec<u64>> = BTreeMap::new();
+        for output in &psbt.unsigned_tx.output {
+            values
+                .entry(output.script_pubkey.clone())
+                .or_default()
+                .push(output.value.to_sat());
+        }
+        values
+    };
+    let (mut before, mut after) = (values(before), values(after));
+    let (mut added, mut removed, mut changed) = (Vec::new(), Vec::new(), Vec::new());
+    let scripts: BTreeSet<ScriptBuf> = before.keys().chain(after.keys()).cloned().collect();
+    for script in scripts {
+        let mut old = before.remove(&script).unwrap_or_default();
+        let mut new = after.remove(&script).unwrap_or_default();
+        old.retain(|value| match new.iter().position(|other| other == value) {
+            Some(index) => {
+                new.remove(index);
+                false
+            }
+            None => true,
+        });
+        let address = Address::from_script(&script, network)
+            .ok()
+            .map(|address| address.to_string());
+        let entry = |value_sat| OutputEntry {
+            address: address.clone(),
+            script_pubkey: script.to_hex_string(),
+            value_sat,
+        };
+        let paired = old.len().min(new.len());
+        for (before_sat, after_sat) in old.iter().zip(&new) {
+            changed.push(OutputChange {
+                address: address.clone(),
+                script_pubkey: script.to_hex_string(),
+                before_sat: *before_sat,
+                after_sat: *after_sat,
+            });
+        }
+        removed.extend(old[paired..].iter().map(|value| entry(*value)));
+        added.extend(new[paired..].iter().map(|value| entry(*value)));
+    }
+    (added, removed, changed)
+}
+
+/// The fee `psbt` pays in sat, when every input carries its coin.
+fn fee(psbt: &Psbt) -> Option<u64> {
+    let spent = psbt.unsigned_tx.input.iter().zip(&psbt.inputs).try_fold(
+        Amount::ZERO,
+        |sum, (txin, input)| {
+            let vout = txin.previous_output.vout as usize;
+            let utxo = input
+                .witness_utxo
+                .as_ref()
+                .or_else(|| input.non_witness_utxo.as_ref()?.output.get(vout))?;
+            sum.checked_add(utxo.value)
+        },
+    )?;
+    let paid = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .try_fold(Amount::ZERO, |sum, output| sum.checked_add(output.value))?;
+    spent.checked_sub(paid).map(Amount::to_sat)
+}
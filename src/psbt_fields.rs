@@ -0,0 +1,133 @@
+//! Policy on PSBT fields the signer does not use.
+//!
+//! With `[psbt_fields]` set, unknown fields, proprietary fields and the
+//! annexes of finalized taproot inputs are each kept, removed or refused.
+//! Some protocols carry their state in proprietary fields and need them to
+//! survive signing; others consider anything unexpected in a PSBT a sign it
+//! was tampered with. An annex is committed to by its input's signature, so
+//! removing one would only break that input, and annexes can be kept or
+//! refused but not stripped.
+
+use bitcoin::{
+    psbt::{raw, Input},
+    Psbt, Witness,
+};
+
+use crate::{
+    config::{AnnexPolicy, FieldPolicy, PsbtFieldsConfig},
+    error::Error,
+};
+
+/// First byte of a taproot annex, the last witness element when present.
+const ANNEX_TAG: u8 = 0x50;
+
+/// Refuses `psbt` if it carries fields `policy` rejects.
+pub fn check(psbt: &Psbt, policy: &PsbtFieldsConfig) -> Result<(), Error> {
+    let refuse = |what: String| Error::InvalidTransaction(format!("psbt fields: {what}"));
+    if policy.unknown == FieldPolicy::Reject {
+        if let Some(key) = psbt.unknown.keys().next() {
+            return Err(refuse(format!("unknown global field {}", describe(key))));
+        }
+        for (index, input) in psbt.inputs.iter().enumerate() {
+            if let Some(key) = input.unknown.keys().next() {
+                return Err(refuse(format!(
+                    "unknown field {} in input {index}",
+                    describe(key)
+                )));
+            }
+        }
+        for (index, output) in psbt.outputs.iter().enumerate() {
+            if let Some(key) = output.unknown.keys().next() {
+                return Err(refuse(format!(
+                    "unknown field {} in output {index}",
+                    describe(key)
+                )));
+            }
+        }
+    }
+    if policy.proprietary == FieldPolicy::Reject {
+        if let Some(key) = psbt.proprietary.keys().next() {
+            return Err(refuse(format!(
+                "proprietary global field {}",
+                describe_proprietary(key)
+            )));
+        }
+        for (index, input) in psbt.inputs.iter().enumerate() {
+            if let Some(key) = input.proprietary.keys().next() {
+                return Err(refuse(format!(
+                    "proprietary field {} in input {index}",
+                    describe_proprietary(key)
+                )));
+            }
+        }
+        for (index, output) in psbt.outputs.iter().enumerate() {
+            if let Some(key) = output.proprietary.keys().next() {
+                return Err(refuse(format!(
+                    "proprietary field {} in output {index}",
+                    describe_proprietary(key)
+                )));
+            }
+        }
+    }
+    if policy.annex == AnnexPolicy::Reject {
+        let annexed = psbt.inputs.iter().position(has_annex);
+        if let Some(index) = annexed {
+            return Err(refuse(format!("input {index} carries a taproot annex")));
+        }
+    }
+    Ok(())
+}
+
+/// Removes the fields `policy` strips from `psbt`.
+pub fn strip(psbt: &mut Psbt, policy: &PsbtFieldsConfig) {
+    let mut stripped = 0;
+    if policy.unknown == FieldPolicy::Strip {
+        stripped += std::mem::take(&mut psbt.unknown).len();
+        for input in &mut psbt.inputs {
+            stripped += std::mem::take(&mut input.unknown).len();
+        }
+        for output in &mut psbt.outputs {
+            stripped += std::mem::take(&mut output.unknown).len();
+        }
+    }
+    if policy.proprietary == FieldPolicy::Strip {
+        stripped += std::mem::take(&mut psbt.proprietary).len();
+        for input in &mut psbt.inputs {
+            stripped += std::mem::take(&mut input.proprietary).len();
+        }
+        for output in &mut psbt.outputs {
+            stripped += std::mem::take(&mut output.proprietary).len();
+        }
+    }
+    if stripped > 0 {
+        tracing::info!(fields = stripped, "stripped PSBT fields");
+    }
+}
+
+/// Whether `input` is a finalized taproot spend with an annex: a witness of
+/// at least two elements, the last starting with the annex tag.
+fn has_annex(input: &Input) -> bool {
+    let taproot = input
+        .witness_utxo
+        .as_ref()
+        .is_some_and(|utxo| utxo.script_pubkey.is_p2tr());
+    let annexed = |witness: &Witness| {
+        witness.len() >= 2
+            && witness
+                .last()
+                .is_some_and(|last| last.first() == Some(&ANNEX_TAG))
+    };
+    taproot && input.final_script_witness.as_ref().is_some_and(annexed)
+}
+
+fn describe(key: &raw::Key) -> String {
+    format!("of type {:#04x}", key.type_value)
+}
+
+fn describe_proprietary(key: &raw::ProprietaryKey) -> String {
+    format!(
+        "{}/{:#04x}",
+        String::from_utf8_lossy(&key.prefix),
+        key.subtype
+    )
+}
@@ -0,0 +1,710 @@
+//! PSBTs as sequences of QR code payloads, for air-gapped signers and
+//! mobile wallets.
+//!
+//! Two formats are supported:
+//!
+//! - [BBQr](https://bbqr.org): `B$` headers carrying base32, optionally
+//!   zlib-compressed, as used by Coldcard and Sparrow.
+//! - [UR](https://github.com/BlockchainCommons/Research/blob/master/papers/bcr-2020-005-ur.md):
+//!   `ur:crypto-psbt` parts in minimal bytewords, as used by Keystone,
+//!   Passport and BlueWallet. Multi-part URs are split into the pure
+//!   fountain fragments `1..=n`; decoding needs each of those, mixed parts a
+//!   scanner may have picked up are skipped.
+//!
+//! Payloads are returned uppercase so they fit the QR alphanumeric mode.
+
+use std::collections::BTreeMap;
+
+use axum::Json;
+use bitcoin::Psbt;
+use data_encoding::{BASE32_NOPAD, HEXUPPER_PERMISSIVE};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{ApiJson, Error, ErrorResponse};
+
+const DEFAULT_MAX_CHARS: usize = 400;
+const MIN_MAX_CHARS: usize = 100;
+/// Alphanumeric capacity of a version 40 QR code at the lowest error
+/// correction level, rounded down.
+const MAX_MAX_CHARS: usize = 4000;
+/// Upper bound on a decompressed BBQr payload.
+const MAX_DECODED_LEN: usize = 4 << 20;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    Bbqr,
+    Ur,
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct EncodeRequest {
+    /// Base64-encoded BIP-174 PSBT.
+    #[serde(deserialize_with = "crate::de_psbt_from_base64")]
+    #[schema(value_type = String, format = Byte, example = "cHNidP8BAAoCAAAAAAAAAAAAAAAA")]
+    pub psbt: Psbt,
+    pub format: QrFormat,
+    /// Most characters in one QR payload, header included.
+    #[serde(default = "default_max_chars")]
+    #[schema(default = 400, minimum = 100, maximum = 4000)]
+    pub max_chars: usize,
+}
+
+fn default_max_chars() -> usize {
+    DEFAULT_MAX_CHARS
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct EncodeResponse {
+    pub format: QrFormat,
+    /// QR payloads in display order.
+    pub parts: Vec<String>,
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct DecodeRequest {
+    /// Scanned payloads of one BBQr or UR sequence, in any order.
+    pub parts: Vec<String>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct DecodeResponse {
+    pub format: QrFormat,
+    /// Base64-encoded PSBT.
+    #[serde(serialize_with = "crate::serialize_psbt_to_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub psbt: Psbt,
+}
+
+#[utoipa::path(
+    post,
+    path = "/qr/encode",
+    request_body = EncodeRequest,
+    responses(
+        (status = 200, description = "QR payloads", body = EncodeResponse),
+        (status = 400, description = "`malformed_request`", body = ErrorResponse),
+        (status = 415, description = "`unsupported_media_type`", body = ErrorResponse),
+    )
+)]
+pub async fn encode(
+    ApiJson(request): ApiJson<EncodeRequest>,
+) -> Result<Json<EncodeResponse>, Error> {
+    if !(MIN_MAX_CHARS..=MAX_MAX_CHARS).contains(&request.max_chars) {
+        return Err(Error::MalformedRequest(format!(
+            "max_chars must be between {MIN_MAX_CHARS} and {MAX_MAX_CHARS}"
+        )));
+    }
+    let psbt = request.psbt.serialize();
+    let parts = match request.format {
+        QrFormat::Bbqr => bbqr::encode(&psbt, request.max_chars),
+        QrFormat::Ur => ur::encode(&psbt, request.max_chars),
+    }
+    .map_err(Error::MalformedRequest)?;
+    Ok(Json(EncodeResponse {
+        format: request.format,
+        parts,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/qr/decode",
+    request_body = DecodeRequest,
+    responses(
+        (status = 200, description = "Decoded PSBT", body = DecodeResponse),
+        (status = 400, description = "`malformed_request`", body = ErrorResponse),
+        (status = 415, description = "`unsupported_media_type`", body = ErrorResponse),
+    )
+)]
+pub async fn decode(
+    ApiJson(request): ApiJson<DecodeRequest>,
+) -> Result<Json<DecodeResponse>, Error> {
+    let parts: Vec<&str> = request
+        .parts
+        .iter()
+        .map(|part| part.trim())
+        .filter(|part| !part.is_empty())
+        .collect();
+    let Some(first) = parts.first() else {
+        return Err(Error::MalformedRequest("no parts given".into()));
+    };
+    let (format, decoded) = if first.starts_with("B$") {
+        (QrFormat::Bbqr, bbqr::decode(&parts))
+    } else if first
+        .get(..3)
+        .is_some_and(|s| s.eq_ignore_ascii_case("ur:"))
+    {
+        (QrFormat::Ur, ur::decode(&parts))
+    } else {
+        return Err(Error::MalformedRequest(
+            "parts are neither BBQr (`B$...`) nor UR (`ur:...`)".into(),
+        ));
+    };
+    let bytes = decoded.map_err(Error::MalformedRequest)?;
+    let psbt = Psbt::deserialize(&bytes)
+        .map_err(|e| Error::MalformedRequest(format!("invalid psbt: {e}")))?;
+    Ok(Json(DecodeResponse { format, psbt }))
+}
+
+mod bbqr {
+    use super::*;
+
+    const HEADER_LEN: usize = 8;
+    /// Largest part count two base-36 digits can express.
+    const MAX_PARTS: usize = 36 * 36 - 1;
+    const FILE_TYPE_PSBT: char = 'P';
+
+    pub fn encode(data: &[u8], max_chars: usize) -> Result<Vec<String>, String> {
+        // Compression is only used when it actually saves space.
+        let plain = BASE32_NOPAD.encode(data);
+        let compressed = BASE32_NOPAD.encode(&deflate::compress(data));
+        let (encoding, body) = if compressed.len() < plain.len() {
+            ('Z', compressed)
+        } else {
+            ('2', plain)
+        };
+
+        // Base32 parts must end on a whole 5-byte group to decode on their own.
+        let capacity = (max_chars - HEADER_LEN) / 8 * 8;
+        let count = body.len().div_ceil(capacity).max(1);
+        if count > MAX_PARTS {
+            return Err(format!(
+                "the psbt needs more than {MAX_PARTS} BBQr parts of {max_chars} characters"
+            ));
+        }
+        let per_part = body.len().div_ceil(count).div_ceil(8) * 8;
+        let chunks: Vec<&str> = if body.is_empty() {
+            vec![""]
+        } else {
+            body.as_bytes()
+                .chunks(per_part)
+                .map(|chunk| std::str::from_utf8(chunk).expect("base32 is ascii"))
+                .collect()
+        };
+        let total = chunks.len();
+        Ok(chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                format!(
+                    "B${encoding}{FILE_TYPE_PSBT}{}{}{chunk}",
+                    base36(total),
+                    base36(index)
+                )
+            })
+            .collect())
+    }
+
+    pub fn decode(parts: &[&str]) -> Result<Vec<u8>, String> {
+        let mut header = None;
+        let mut chunks = BTreeMap::new();
+        for part in parts {
+            if part.len() < HEADER_LEN || !part.is_ascii() {
+                return Err(format!("`{part}` is not a BBQr part"));
+            }
+            let (head, body) = part.split_at(HEADER_LEN);
+            let mut chars = head.chars().skip(2);
+            let encoding = chars.next().expect("header length checked");
+            let file_type = chars.next().expect("header length checked");
+            let total = from_base36(&head[4..6])?;
+            let index = from_base36(&head[6..8])?;
+            if header.is_some_and(|header| header != (encoding, file_type, total)) {
+                return Err("the parts belong to different BBQr sequences".into());
+            }
+            header = Some((encoding, file_type, total));
+            if index >= total {
+                return Err(format!("part index {index} of {total} parts"));
+            }
+            chunks.insert(index, body);
+        }
+        let (encoding, file_type, total) = header.ok_or("no BBQr parts")?;
+        if file_type != FILE_TYPE_PSBT {
+            return Err(format!("BBQr file type {file_type} is not a PSBT"));
+        }
+        let missing: Vec<String> = (0..total)
+            .filter(|index| !chunks.contains_key(index))
+            .map(|index| (index + 1).to_string())
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!(
+                "missing BBQr parts {} of {total}",
+                missing.join(", ")
+            ));
+        }
+
+        let mut bytes = Vec::new();
+        match encoding {
+            'H' => {
+                let hex: String = chunks.into_values().collect();
+                bytes = HEXUPPER_PERMISSIVE
+                    .decode(hex.as_bytes())
+                    .map_err(|e| format!("hex: {e}"))?;
+            }
+            '2' | 'Z' => {
+                for chunk in chunks.into_values() {
+                    let decoded = BASE32_NOPAD
+                        .decode(chunk.to_ascii_uppercase().as_bytes())
+                        .map_err(|e| format!("base32: {e}"))?;
+                    bytes.extend(decoded);
+                }
+            }
+            other => return Err(format!("unknown BBQr encoding {other}")),
+        }
+        if encoding == 'Z' {
+            bytes = miniz_oxide::inflate::decompress_to_vec_with_limit(&bytes, MAX_DECODED_LEN)
+                .map_err(|e| format!("decompressing: {e}"))?;
+        }
+        Ok(bytes)
+    }
+
+    fn base36(value: usize) -> String {
+        const DIGITS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+        [DIGITS[value / 36], DIGITS[value % 36]]
+            .iter()
+            .map(|&digit| digit as char)
+            .collect()
+    }
+
+    fn from_base36(digits: &str) -> Result<usize, String> {
+        usize::from_str_radix(digits, 36).map_err(|_| format!("`{digits}` is not a base-36 number"))
+    }
+}
+
+mod ur {
+    use super::*;
+
+    const TYPE: &str = "crypto-psbt";
+    /// Later name of the same type, accepted when decoding.
+    const TYPE_V2: &str = "psbt";
+    const MIN_FRAGMENT_LEN: usize = 10;
+
+    const BYTEWORDS: [&str; 256] = [
+        "able", "acid", "also", "apex", "aqua", "arch", "atom", "aunt", "away", "axis", "back",
+        "bald", "barn", "belt", "beta", "bias", "blue", "body", "brag", "brew", "bulb", "buzz",
+        "calm", "cash", "cats", "chef", "city", "claw", "code", "cola", "cook", "cost", "crux",
+        "curl", "cusp", "cyan", "dark", "data", "days", "deli", "dice", "diet", "door", "down",
+        "draw", "drop", "drum", "dull", "duty", "each", "easy", "echo", "edge", "epic", "even",
+        "exam", "exit", "eyes", "fact", "fair", "fern", "figs", "film", "fish", "fizz", "flap",
+        "flew", "flux", "foxy", "free", "frog", "fuel", "fund", "gala", "game", "gear", "gems",
+        "gift", "girl", "glow", "good", "gray", "grim", "guru", "gush", "gyro", "half", "hang",
+        "hard", "hawk", "heat", "help", "high", "hill", "holy", "hope", "horn", "huts", "iced",
+        "idea", "idle", "inch", "inky", "into", "iris", "iron", "item", "jade", "jazz", "join",
+        "jolt", "jowl", "judo", "jugs", "jump", "junk", "jury", "keep", "keno", "kept", "keys",
+        "kick", "kiln", "king", "kite", "kiwi", "knob", "lamb", "lava", "lazy", "leaf", "legs",
+        "liar", "limp", "lion", "list", "logo", "loud", "love", "luau", "luck", "lung", "main",
+        "many", "math", "maze", "memo", "menu", "meow", "mild", "mint", "miss", "monk", "nail",
+        "navy", "need", "news", "next", "noon", "note", "numb", "obey", "oboe", "omit", "onyx",
+        "open", "oval", "owls", "paid", "part", "peck", "play", "plus", "poem", "pool", "pose",
+        "puff", "puma", "purr", "quad", "quiz", "race", "ramp", "real", "redo", "rich", "road",
+        "rock", "roof", "ruby", "ruin", "runs", "rust", "safe", "saga", "scar", "sets", "silk",
+        "skew", "slot", "soap", "solo", "song", "stub", "surf", "swan", "taco", "task", "taxi",
+        "tent", "tied", "time", "tiny", "toil", "tomb", "toys", "trip", "tuna", "twin", "ugly",
+        "undo", "unit", "urge", "user", "vast", "very", "veto", "vial", "vibe", "view", "visa",
+        "void", "vows", "wall", "wand", "warm", "wasp", "wave", "waxy", "webs", "what", "when",
+        "whiz", "wolf", "work", "yank", "yawn", "yell", "yoga", "yurt", "zaps", "zero", "zest",
+        "zinc", "zone", "zoom",
+    ];
+
+    pub fn encode(data: &[u8], max_chars: usize) -> Result<Vec<String>, String> {
+        let mut message = Vec::new();
+        cbor_bytes(&mut message, data);
+
+        let single = format!("ur:{TYPE}/");
+        if single.len() + 2 * (message.len() + 4) <= max_chars {
+            return Ok(vec![(single + &bytewords(&message)).to_ascii_uppercase()]);
+        }
+
+        let fragment_len = (MIN_FRAGMENT_LEN..=max_chars / 2)
+            .rev()
+            .map(|max_fragment_len| nominal_fragment_len(message.len(), max_fragment_len))
+            .find(|&fragment_len| part_len(message.len(), fragment_len) <= max_chars)
+            .ok_or_else(|| format!("{max_chars} characters is too few for a UR part"))?;
+        let count = message.len().div_ceil(fragment_len);
+        let checksum = crc32fast::hash(&message);
+        let mut padded = message.clone();
+        padded.resize(count * fragment_len, 0);
+
+        Ok(padded
+            .chunks(fragment_len)
+            .enumerate()
+            .map(|(index, fragment)| {
+                let mut part = vec![0x85];
+                cbor_uint(&mut part, index as u64 + 1);
+                cbor_uint(&mut part, count as u64);
+                cbor_uint(&mut part, message.len() as u64);
+                cbor_uint(&mut part, checksum.into());
+                cbor_bytes(&mut part, fragment);
+                format!("ur:{TYPE}/{}-{count}/{}", index + 1, bytewords(&part)).to_ascii_uppercase()
+            })
+            .collect())
+    }
+
+    pub fn decode(parts: &[&str]) -> Result<Vec<u8>, String> {
+        let mut sequence = None;
+        let mut fragments = BTreeMap::new();
+        for part in parts {
+            let part = part.to_ascii_lowercase();
+            let path = part.strip_prefix("ur:").ok_or("not a UR")?;
+            let mut components = path.split('/');
+            let ur_type = components.next().unwrap_or_default();
+            if ur_type != TYPE && ur_type != TYPE_V2 {
+                return Err(format!("UR type `{ur_type}` is not a PSBT"));
+            }
+            match (components.next(), components.next(), components.next()) {
+                (Some(body), None, None) => {
+                    let message = from_bytewords(body)?;
+                    return cbor_read_bytes(&mut message.as_slice());
+                }
+                (Some(_), Some(body), None) => {
+                    let decoded = from_bytewords(body)?;
+                    let mut cbor = decoded.as_slice();
+                    if cbor_take(&mut cbor, 1)? != [0x85] {
+                        return Err("UR part is not a 5-element array".into());
+                    }
+                    let seq_num = cbor_read_uint(&mut cbor)?;
+                    let seq_len = cbor_read_uint(&mut cbor)?;
+                    let message_len = cbor_read_uint(&mut cbor)?;
+                    let checksum = cbor_read_uint(&mut cbor)?;
+                    let fragment = cbor_read_bytes(&mut cbor)?;
+                    if sequence.is_some_and(|seq| seq != (seq_len, message_len, checksum)) {
+                        return Err("the parts belong to different UR sequences".into());
+                    }
+                    sequence = Some((seq_len, message_len, checksum));
+                    if (1..=seq_len).contains(&seq_num) {
+                        fragments.insert(seq_num, fragment);
+                    }
+                }
+                _ => return Err(format!("malformed UR `{part}`")),
+            }
+        }
+        let (seq_len, message_len, checksum) = sequence.ok_or("no UR parts")?;
+        let missing: Vec<String> = (1..=seq_len)
+            .filter(|seq_num| !fragments.contains_key(seq_num))
+            .map(|seq_num| seq_num.to_string())
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!(
+                "missing UR parts {} of {seq_len}",
+                missing.join(", ")
+            ));
+        }
+        let mut message: Vec<u8> = fragments.into_values().flatten().collect();
+        let message_len = usize::try_from(message_len).map_err(|e| e.to_string())?;
+        if message_len > message.len() {
+            return Err("UR fragments are shorter than the message".into());
+        }
+        message.truncate(message_len);
+        if u64::from(crc32fast::hash(&message)) != checksum {
+            return Err("UR checksum mismatch".into());
+        }
+        cbor_read_bytes(&mut message.as_slice())
+    }
+
+    /// Fragment length that splits `message_len` bytes into as few, evenly
+    /// sized fragments as `max_fragment_len` allows.
+    fn nominal_fragment_len(message_len: usize, max_fragment_len: usize) -> usize {
+        let max_count = (message_len / MIN_FRAGMENT_LEN).max(1);
+        (1..=max_count)
+            .map(|count| message_len.div_ceil(count))
+            .find(|&len| len <= max_fragment_len)
+            .unwrap_or(MIN_FRAGMENT_LEN)
+    }
+
+    /// Longest part a message split into `fragment_len` fragments gives.
+    fn part_len(message_len: usize, fragment_len: usize) -> usize {
+        let count = message_len.div_ceil(fragment_len);
+        let cbor = 1
+            + 2 * cbor_uint_len(count as u64)
+            + cbor_uint_len(message_len as u64)
+            + cbor_uint_len(u32::MAX.into())
+            + cbor_uint_len(fragment_len as u64)
+            + fragment_len;
+        format!("ur:{TYPE}/{count}-{count}/").len() + 2 * (cbor + 4)
+    }
+
+    /// Minimal bytewords with the CRC32 checksum appended.
+    fn bytewords(data: &[u8]) -> String {
+        let checksum = crc32fast::hash(data).to_be_bytes();
+        data.iter()
+            .chain(&checksum)
+            .flat_map(|&byte| {
+                let word = BYTEWORDS[byte as usize].as_bytes();
+                [word[0] as char, word[3] as char]
+            })
+            .collect()
+    }
+
+    fn from_bytewords(text: &str) -> Result<Vec<u8>, String> {
+        if text.len() % 2 != 0 || !text.is_ascii() {
+            return Err("malformed bytewords".into());
+        }
+        let mut bytes = text
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| {
+                BYTEWORDS
+                    .iter()
+                    .position(|word| word.as_bytes()[0] == pair[0] && word.as_bytes()[3] == pair[1])
+                    .map(|byte| byte as u8)
+                    .ok_or_else(|| "malformed bytewords".to_owned())
+            })
+            .collect::<Result<Vec<u8>, String>>()?;
+        if bytes.len() < 4 {
+            return Err("bytewords too short for a checksum".into());
+        }
+        let checksum = bytes.split_off(bytes.len() - 4);
+        if crc32fast::hash(&bytes).to_be_bytes() != checksum.as_slice() {
+            return Err("bytewords checksum mismatch".into());
+        }
+        Ok(bytes)
+    }
+
+    fn cbor_head(out: &mut Vec<u8>, major: u8, value: u64) {
+        let major = major << 5;
+        match value {
+            0..=23 => out.push(major | value as u8),
+            24..=0xff => out.extend([major | 24, value as u8]),
+            0x100..=0xffff => {
+                out.push(major | 25);
+                out.extend((value as u16).to_be_bytes());
+            }
+            0x1_0000..=0xffff_ffff => {
+                out.push(major | 26);
+                out.extend((value as u32).to_be_bytes());
+            }
+            _ => {
+                out.push(major | 27);
+                out.extend(value.to_be_bytes());
+            }
+        }
+    }
+
+    fn cbor_uint_len(value: u64) -> usize {
+        match value {
+            0..=23 => 1,
+            24..=0xff => 2,
+            0x100..=0xffff => 3,
+            0x1_0000..=0xffff_ffff => 5,
+            _ => 9,
+        }
+    }
+
+    fn cbor_uint(out: &mut Vec<u8>, value: u64) {
+        cbor_head(out, 0, value);
+    }
+
+    fn cbor_bytes(out: &mut Vec<u8>, data: &[u8]) {
+        cbor_head(out, 2, data.len() as u64);
+        out.extend(data);
+    }
+
+    fn cbor_take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+        if input.len() < len {
+            return Err("truncated CBOR".into());
+        }
+        let (taken, rest) = input.split_at(len);
+        *input = rest;
+        Ok(taken)
+    }
+
+    fn cbor_read_head(input: &mut &[u8], major: u8) -> Result<u64, String> {
+        let initial = cbor_take(input, 1)?[0];
+        if initial >> 5 != major {
+            return Err(format!("expected CBOR major type {major}"));
+        }
+        let len = match initial & 0x1f {
+            value @ 0..=23 => return Ok(value.into()),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            _ => return Err("unsupported CBOR length".into()),
+        };
+        Ok(cbor_take(input, len)?
+            .iter()
+            .fold(0, |value, &byte| value << 8 | u64::from(byte)))
+    }
+
+    fn cbor_read_uint(input: &mut &[u8]) -> Result<u64, String> {
+        cbor_read_head(input, 0)
+    }
+
+    fn cbor_read_bytes(input: &mut &[u8]) -> Result<Vec<u8>, String> {
+        let len = cbor_read_head(input, 2)?;
+        let len = usize::try_from(len).map_err(|e| e.to_string())?;
+        Ok(cbor_take(input, len)?.to_vec())
+    }
+}
+
+/// Raw DEFLATE for BBQr, which Coldcard decodes with a 1 KiB window
+/// (`wbits=10`). General-purpose compressors use a 32 KiB window, so their
+/// output can refer back further than such a decoder remembers.
+mod deflate {
+    const WINDOW: usize = 1 << 10;
+    const MIN_MATCH: usize = 3;
+    const MAX_MATCH: usize = 258;
+    const MAX_CHAIN: usize = 128;
+    const HASH_BITS: u32 = 12;
+
+    const LENGTH_BASE: [usize; 29] = [
+        3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+        131, 163, 195, 227, 258,
+    ];
+    const LENGTH_EXTRA: [u32; 29] = [
+        0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+    ];
+    /// Distance codes up to the window size.
+    const DIST_BASE: [usize; 20] = [
+        1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769,
+    ];
+    const DIST_EXTRA: [u32; 20] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8];
+
+    /// A single block with the fixed Huffman codes.
+    pub fn compress(data: &[u8]) -> Vec<u8> {
+        let mut out = BitWriter::default();
+        out.bits(1, 1); // BFINAL
+        out.bits(1, 2); // BTYPE: fixed Huffman codes
+        let mut matcher = Matcher::new(data);
+        let mut pos = 0;
+        while pos < data.len() {
+            match matcher.longest_match(pos) {
+                Some((len, dist)) => {
+                    out.length(len);
+                    out.distance(dist);
+                    for p in pos..pos + len {
+                        matcher.insert(p);
+                    }
+                    pos += len;
+                }
+                None => {
+                    out.literal(data[pos]);
+                    matcher.insert(pos);
+                    pos += 1;
+                }
+            }
+        }
+        out.symbol(256);
+        out.finish()
+    }
+
+    /// Hash chains over the three-byte prefixes seen so far.
+    struct Matcher<'a> {
+        data: &'a [u8],
+        head: Vec<usize>,
+        prev: Vec<usize>,
+    }
+
+    impl<'a> Matcher<'a> {
+        fn new(data: &'a [u8]) -> Self {
+            Matcher {
+                data,
+                head: vec![usize::MAX; 1 << HASH_BITS],
+                prev: vec![usize::MAX; data.len()],
+            }
+        }
+
+        fn hash(&self, pos: usize) -> Option<usize> {
+            let bytes = self.data.get(pos..pos + MIN_MATCH)?;
+            let value = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+            Some((value.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize)
+        }
+
+        fn insert(&mut self, pos: usize) {
+            if let Some(hash) = self.hash(pos) {
+                self.prev[pos] = self.head[hash];
+                self.head[hash] = pos;
+            }
+        }
+
+        /// Length and distance of the longest earlier match within the window.
+        fn longest_match(&self, pos: usize) -> Option<(usize, usize)> {
+            let hash = self.hash(pos)?;
+            let max_len = MAX_MATCH.min(self.data.len() - pos);
+            let mut best: Option<(usize, usize)> = None;
+            let mut candidate = self.head[hash];
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || pos - candidate > WINDOW {
+                    break;
+                }
+                let len = self.data[candidate..]
+                    .iter()
+                    .zip(&self.data[pos..pos + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len >= MIN_MATCH && best.map_or(true, |(best_len, _)| len > best_len) {
+                    best = Some((len, pos - candidate));
+                    if len == max_len {
+                        break;
+                    }
+                }
+                candidate = self.prev[candidate];
+            }
+            best
+        }
+    }
+
+    #[derive(Default)]
+    struct BitWriter {
+        bytes: Vec<u8>,
+        acc: u64,
+        len: u32,
+    }
+
+    impl BitWriter {
+        /// Writes the low `count` bits of `value`, least significant first.
+        fn bits(&mut self, value: u32, count: u32) {
+            self.acc |= u64::from(value) << self.len;
+            self.len += count;
+            while self.len >= 8 {
+                self.bytes.push(self.acc as u8);
+                self.acc >>= 8;
+                self.len -= 8;
+            }
+        }
+
+        /// Writes a Huffman code, most significant bit first.
+        fn code(&mut self, code: u32, count: u32) {
+            let reversed = code.reverse_bits() >> (32 - count);
+            self.bits(reversed, count);
+        }
+
+        /// Writes a literal/length symbol with the fixed code.
+        fn symbol(&mut self, symbol: u32) {
+            match symbol {
+                0..=143 => self.code(0x30 + symbol, 8),
+                144..=255 => self.code(0x190 + symbol - 144, 9),
+                256..=279 => self.code(symbol - 256, 7),
+                _ => self.code(0xC0 + symbol - 280, 8),
+            }
+        }
+
+        fn literal(&mut self, byte: u8) {
+            self.symbol(byte.into());
+        }
+
+        fn length(&mut self, len: usize) {
+            let index = LENGTH_BASE
+                .iter()
+                .rposition(|&base| base <= len)
+                .expect("len >= 3");
+            self.symbol(257 + index as u32);
+            self.bits((len - LENGTH_BASE[index]) as u32, LENGTH_EXTRA[index]);
+        }
+
+        fn distance(&mut self, dist: usize) {
+            let index = DIST_BASE
+                .iter()
+                .rposition(|&base| base <= dist)
+                .expect("dist >= 1");
+            self.code(index as u32, 5);
+            self.bits((dist - DIST_BASE[index]) as u32, DIST_EXTRA[index]);
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            if self.len > 0 {
+                self.bytes.push(self.acc as u8);
+            }
+            self.bytes
+        }
+    }
+}
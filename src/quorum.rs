@@ -0,0 +1,245 @@
+//! Multi-operator control through API credentials.
+//!
+//! With `[quorum]` set, a PSBT is only signed once `threshold` distinct
+//! credentials from `quorum.credentials` have each submitted its unsigned
+//! transaction within `window_secs`. Every submission counts as its
+//! credential's approval of the txid; the ones short of the threshold are
+//! answered `quorum_pending`, and the one that completes it is signed.
+//! Consolidations, which the service runs from its own config and which
+//! only pay the wallet, need no quorum; schedules are added through the API
+//! and need one like any request. Approvals are kept in memory, and in the
+//! [journal](crate::journal) when there is one, which a restart picks them
+//! up from.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+};
+
+use axum::{extract::State, Json};
+use bitcoin::{hashes::sha256, hashes::Hash, Txid};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{config::QuorumConfig, context::RequestContext, error::Error, AppState};
+
+/// Approvals per unsigned txid, with when each credential last gave it.
+pub type Snapshot = BTreeMap<Txid, BTreeMap<String, DateTime<Utc>>>;
+
+#[derive(Default)]
+pub struct Approvals(Mutex<Snapshot>);
+
+#[derive(Serialize)]
+pub struct QuorumResponse {
+    pub threshold: usize,
+    pub window_secs: u64,
+    pub pending: Vec<Pending>,
+}
+
+#[derive(Serialize)]
+pub struct Pending {
+    pub txid: Txid,
+    pub approved_by: BTreeSet<String>,
+}
+
+impl Approvals {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Snapshot> {
+        self.0.lock().expect("quorum approvals")
+    }
+
+    /// Takes up approvals saved by an earlier run.
+    pub fn restore(&self, approvals: Snapshot) {
+        *self.lock() = approvals;
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.lock().clone()
+    }
+
+    /// Records `credential`'s approval of `txid`, then refuses unless
+    /// `policy.threshold` credentials have approved it within the window.
+    pub fn approve(
+        &self,
+        policy: &QuorumConfig,
+        txid: Txid,
+        credential: &str,
+    ) -> Result<(), Error> {
+        let mut approvals = self.lock();
+        expire(&mut approvals, policy);
+        let approved = approvals.entry(txid).or_default();
+        approved.insert(credential.to_owned(), Utc::now());
+        if approved.len() >= policy.threshold {
+            tracing::info!(%txid, approved_by = ?approved.keys(), "quorum reached");
+            return Ok(());
+        }
+        tracing::info!(%txid, credential, approvals = approved.len(), "quorum pending");
+        Err(Error::QuorumPending {
+            approved_by: approved.keys().cloned().collect(),
+            threshold: policy.threshold,
+        })
+    }
+
+    fn pending(&self, policy: &QuorumConfig) -> Vec<Pending> {
+        let mut approvals = self.lock();
+        expire(&mut approvals, policy);
+        approvals
+            .iter()
+            .filter(|(_, approved)| approved.len() < policy.threshold)
+            .map(|(txid, approved)| Pending {
+                txid: *txid,
+                approved_by: approved.keys().cloned().collect(),
+            })
+            .collect()
+    }
+}
+
+fn expire(approvals: &mut Snapshot, policy: &QuorumConfig) {
+    let expired = Utc::now() - chrono::Duration::seconds(policy.window_secs as i64);
+    for approved in approvals.values_mut() {
+        approved.retain(|name, at| *at > expired && policy.credentials.contains_key(name));
+    }
+    approvals.retain(|_, approved| !approved.is_empty());
+}
+
+/// Counts the submission of `txid` by `ctx` towards its quorum.
+pub fn check(
+    state: &AppState,
+    ctx: &RequestContext,
+    txid: Txid,
+    policy: &QuorumConfig,
+) -> Result<(), Error> {
+    if ctx.internal {
+        return Ok(());
+    }
+    let presented = ctx.bearer.as_ref().map(|token| token.0.as_str());
+    let Some(name) = presented.and_then(|given| credential(policy, given)) else {
+        tracing::warn!(%txid, "signing request without a quorum credential");
+        return Err(Error::Unauthorized("quorum credential".into()));
+    };
+    let approved = state.quorum.approve(policy, txid, name);
+    if let Some(journal) = &state.journal {
+        // Losing an approval to a restart only means submitting again.
+        if let Err(e) = journal.save_approvals(state.quorum.snapshot()) {
+            tracing::error!(%txid, "saving quorum approvals failed: {e}");
+        }
+    }
+    approved
+}
+
+/// The name of the credential whose token is `given`.
+fn credential<'a>(policy: &'a QuorumConfig, given: &str) -> Option<&'a str> {
+    let given = sha256::Hash::hash(given.as_bytes());
+    policy
+        .credentials
+        .iter()
+        .find(|(_, token)| sha256::Hash::hash(token.as_bytes()) == given)
+        .map(|(name, _)| name.as_str())
+}
+
+/// Transactions still short of their quorum, and who approved them.
+pub async fn list(State(state): State<Arc<AppState>>) -> Result<Json<QuorumResponse>, Error> {
+    let Some(policy) = state.config().quorum.clone() else {
+        return Err(Error::NotFound("quorum is not configured".into()));
+    };
+    Ok(Json(QuorumResponse {
+        threshold: policy.threshold,
+        window_secs: policy.window_secs,
+        pending: state.quorum.pending(&policy),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+    use crate::{context::BearerToken, test_support};
+
+    const QUORUM: &str = r#"
+[quorum]
+threshold = 2
+
+[quorum.credentials]
+alice = "token-of-alice"
+bob = "token-of-bob"
+"#;
+
+    fn ctx(token: Option<&str>) -> RequestContext {
+        RequestContext {
+            bearer: token.map(|token| BearerToken(token.into())),
+            ..Default::default()
+        }
+    }
+
+    fn txid(byte: u8) -> Txid {
+        Txid::from_byte_array([byte; 32])
+    }
+
+    #[tokio::test]
+    async fn signs_once_the_threshold_has_approved() {
+        let state = test_support::state(QUORUM).await;
+        let policy = state.config().quorum.clone().unwrap();
+        let alice = ctx(Some("token-of-alice"));
+        for _ in 0..2 {
+            match check(&state, &alice, txid(1), &policy) {
+                Err(Error::QuorumPending {
+                    approved_by,
+                    threshold,
+                }) => {
+                    assert_eq!(approved_by, ["alice"]);
+                    assert_eq!(threshold, 2);
+                }
+                other => panic!("one credential twice: {other:?}"),
+            }
+        }
+        assert!(matches!(
+            check(&state, &ctx(Some("token-of-bob")), txid(2), &policy),
+            Err(Error::QuorumPending { .. })
+        ));
+        check(&state, &ctx(Some("token-of-bob")), txid(1), &policy).unwrap();
+        check(&state, &alice, txid(1), &policy).unwrap();
+        assert_eq!(state.quorum.pending(&policy).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn refuses_requests_without_a_credential() {
+        let state = test_support::state(QUORUM).await;
+        let policy = state.config().quorum.clone().unwrap();
+        for token in [None, Some(""), Some("token-of-mallory"), Some("alice")] {
+            assert!(
+                matches!(
+                    check(&state, &ctx(token), txid(1), &policy),
+                    Err(Error::Unauthorized(_))
+                ),
+                "{token:?}"
+            );
+        }
+        assert!(state.quorum.pending(&policy).is_empty());
+    }
+
+    #[tokio::test]
+    async fn drops_approvals_of_removed_credentials() {
+        let state = test_support::state(QUORUM).await;
+        let mut policy = state.config().quorum.clone().unwrap();
+        let _ = check(&state, &ctx(Some("token-of-alice")), txid(1), &policy);
+        policy.credentials.remove("alice");
+        policy
+            .credentials
+            .insert("carol".into(), "token-of-carol".into());
+        assert!(matches!(
+            check(&state, &ctx(Some("token-of-bob")), txid(1), &policy),
+            Err(Error::QuorumPending { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn exempts_internal_jobs_only() {
+        let state = test_support::state(QUORUM).await;
+        let policy = state.config().quorum.clone().unwrap();
+        let internal = RequestContext {
+            internal: true,
+            ..Default::default()
+        };
+        check(&state, &internal, txid(1), &policy).unwrap();
+    }
+}
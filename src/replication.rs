@@ -0,0 +1,352 @@
+//! Hot-standby replication of the state signing decisions depend on.
+//!
+//! With `[replication]` set, the admin listener serves `GET
+//! /admin/replication/state` to callers presenting `replication.token`. A
+//! standby, one with `replication.primary_url`, pulls it every
+//! `interval_secs` and mirrors it: the spend guard's signed coins and the
+//! labels are replaced with the primary's, and audit entries it has not
+//! seen yet are appended to its own audit log, byte for byte, so an export
+//! from either instance verifies the same way.
+//!
+//! Each pull carries a fresh nonce, and the primary signs the nonce and the
+//! body with an HMAC keyed by the token. The standby applies nothing that
+//! fails to verify, so a tampered or replayed answer cannot roll its state
+//! back. The channel is not encrypted: keep it on a private network or in a
+//! tunnel.
+//!
+//! A standby refuses to sign and reports itself not ready until promoted
+//! with `POST /admin/replication/promote`, which stops the pulls. Its state
+//! is then the primary's as of the last pull.
+
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, HeaderValue},
+    response::IntoResponse,
+    Json,
+};
+use bitcoin::hashes::{hmac, sha256, Hash, HashEngine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::{AuditEntry, AuditLog},
+    config::ReplicationConfig,
+    error::Error,
+    spend_guard::Spend,
+    AppState,
+};
+
+/// Header of the standby's nonce.
+const NONCE_HEADER: &str = "x-replication-nonce";
+/// Header of the primary's HMAC over the nonce and the body.
+const SIGNATURE_HEADER: &str = "x-replication-signature";
+/// Audit entries sent in one pull; a standby behind by more catches up
+/// over several.
+const MAX_AUDIT_ENTRIES: usize = 1000;
+
+/// What a standby tracks of its pulls.
+pub struct Replication {
+    standby: AtomicBool,
+    status: Mutex<SyncStatus>,
+}
+
+#[derive(Clone, Default, Serialize)]
+struct SyncStatus {
+    last_synced_at: Option<DateTime<Utc>>,
+    /// Audit entries received from the primary, or held before the first
+    /// pull.
+    audit_entries: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct StatusResponse {
+    role: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    primary_url: Option<String>,
+    #[serde(flatten)]
+    status: SyncStatus,
+}
+
+#[derive(Deserialize)]
+pub struct StateQuery {
+    /// Audit entries the standby already has.
+    #[serde(default)]
+    audit_offset: usize,
+}
+
+/// The primary's state, as sent to a standby.
+#[derive(Serialize, Deserialize)]
+pub struct ReplicatedState {
+    /// Absent unless the primary has `[spend_guard]`.
+    spent_outpoints: Option<Vec<Spend>>,
+    /// BIP-329 JSON Lines, absent unless the primary has `[labels]`.
+    labels: Option<String>,
+    /// Absent unless the primary has `[audit]`.
+    audit: Option<AuditEntries>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AuditEntries {
+    /// Index of the first of `lines` in the primary's log.
+    offset: usize,
+    /// Entries exactly as stored.
+    lines: Vec<String>,
+    /// Entries in the primary's log.
+    total: usize,
+}
+
+impl Replication {
+    pub fn new(replication: &ReplicationConfig, audit: Option<&AuditLog>) -> Result<Self, String> {
+        if replication.token.len() < 16 {
+            return Err("token: must be at least 16 characters".into());
+        }
+        if let Some(url) = &replication.primary_url {
+            reqwest::Url::parse(url).map_err(|e| format!("primary_url: {e}"))?;
+        }
+        let audit_entries = match audit {
+            Some(audit) => audit
+                .len()
+                .map_err(|e| format!("reading the audit log: {e}"))?,
+            None => 0,
+        };
+        Ok(Replication {
+            standby: AtomicBool::new(replication.primary_url.is_some()),
+            status: Mutex::new(SyncStatus {
+                audit_entries,
+                ..Default::default()
+            }),
+        })
+    }
+
+    pub fn is_standby(&self) -> bool {
+        self.standby.load(Ordering::Acquire)
+    }
+}
+
+/// Whether `state` is a standby that must not sign.
+pub fn is_standby(state: &AppState) -> bool {
+    state
+        .replication
+        .as_ref()
+        .is_some_and(Replication::is_standby)
+}
+
+fn mac(token: &str, nonce: &str, body: &[u8]) -> hmac::Hmac<sha256::Hash> {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(token.as_bytes());
+    engine.input(nonce.as_bytes());
+    engine.input(b"\n");
+    engine.input(body);
+    hmac::Hmac::from_engine(engine)
+}
+
+/// Compares digests rather than the secrets themselves, so the time taken
+/// says nothing about how much of `given` was right.
+fn token_matches(given: &str, token: &str) -> bool {
+    sha256::Hash::hash(given.as_bytes()) == sha256::Hash::hash(token.as_bytes())
+}
+
+fn enabled(state: &AppState) -> Result<(ReplicationConfig, &Replication), Error> {
+    let config = state.config().replication.clone();
+    match (config, &state.replication) {
+        (Some(config), Some(replication)) => Ok((config, replication)),
+        _ => Err(Error::NotFound("replication is not configured".into())),
+    }
+}
+
+pub async fn serve_state(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<StateQuery>,
+) -> Result<impl IntoResponse, Error> {
+    let (config, _) = enabled(&state)?;
+    let presented = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !presented.is_some_and(|given| token_matches(given, &config.token)) {
+        tracing::warn!("replication pull with a missing or wrong token");
+        return Err(Error::Unauthorized("replication token".into()));
+    }
+    let nonce = headers
+        .get(NONCE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|nonce| !nonce.is_empty())
+        .ok_or_else(|| Error::MalformedRequest(format!("{NONCE_HEADER} is missing")))?
+        .to_owned();
+
+    let audit = match &state.audit {
+        Some(audit) => {
+            let (lines, total) = audit
+                .lines_from(query.audit_offset, MAX_AUDIT_ENTRIES)
+                .map_err(|e| Error::Internal(format!("reading the audit log: {e}")))?;
+            Some(AuditEntries {
+                offset: query.audit_offset,
+                lines,
+                total,
+            })
+        }
+        None => None,
+    };
+    let spent_outpoints = state
+        .spend_guard
+        .as_ref()
+        .map(|guard| guard.spends())
+        .transpose()
+        .map_err(Error::Unavailable)?;
+    let replicated = ReplicatedState {
+        spent_outpoints,
+        labels: state.labels.as_ref().map(|labels| labels.export()),
+        audit,
+    };
+    let body = serde_json::to_vec(&replicated)
+        .map_err(|e| Error::Internal(format!("encoding replicated state: {e}")))?;
+    let signature = mac(&config.token, &nonce, &body).to_string();
+    Ok((
+        [
+            (
+                axum::http::header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            ),
+            (
+                axum::http::HeaderName::from_static(SIGNATURE_HEADER),
+                HeaderValue::from_str(&signature).expect("hex is a valid header value"),
+            ),
+        ],
+        body,
+    ))
+}
+
+pub async fn status(State(state): State<Arc<AppState>>) -> Result<Json<StatusResponse>, Error> {
+    let (config, replication) = enabled(&state)?;
+    Ok(Json(StatusResponse {
+        role: if replication.is_standby() {
+            "standby"
+        } else {
+            "primary"
+        },
+        primary_url: config.primary_url,
+        status: replication.status.lock().expect("replication").clone(),
+    }))
+}
+
+pub async fn promote(State(state): State<Arc<AppState>>) -> Result<Json<StatusResponse>, Error> {
+    let (_, replication) = enabled(&state)?;
+    if replication.standby.swap(false, Ordering::AcqRel) {
+        tracing::warn!("promoted from standby, signing is enabled and pulls have stopped");
+    }
+    status(State(state)).await
+}
+
+/// Pulls from the primary every `interval_secs` until promoted.
+pub async fn pull_forever(state: Arc<AppState>, config: ReplicationConfig, primary_url: String) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+    let url = format!(
+        "{}/admin/replication/state",
+        primary_url.trim_end_matches('/')
+    );
+    let Some(replication) = &state.replication else {
+        return;
+    };
+    loop {
+        interval.tick().await;
+        if !replication.is_standby() {
+            return;
+        }
+        let result = pull(&state, &config, &url).await;
+        let mut status = replication.status.lock().expect("replication");
+        match result {
+            Ok(audit_entries) => {
+                if status.error.take().is_some() {
+                    tracing::info!("replication from the primary recovered");
+                }
+                status.last_synced_at = Some(Utc::now());
+                status.audit_entries = audit_entries;
+            }
+            Err(e) => {
+                if status.error.is_none() {
+                    tracing::warn!("replication from the primary failed: {e}");
+                }
+                status.error = Some(e);
+            }
+        }
+    }
+}
+
+/// Pulls and applies the primary's state once, returning the audit entries
+/// now held.
+async fn pull(state: &AppState, config: &ReplicationConfig, url: &str) -> Result<usize, String> {
+    let replication = state.replication.as_ref().expect("replication");
+    let audit_offset = replication
+        .status
+        .lock()
+        .expect("replication")
+        .audit_entries;
+    let nonce = hex::encode(rand::random::<[u8; 16]>());
+    let response = state
+        .http
+        .get(url)
+        .bearer_auth(&config.token)
+        .header(NONCE_HEADER, &nonce)
+        .query(&[("audit_offset", audit_offset)])
+        .timeout(Duration::from_secs(30))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("pulling {url}: {e}"))?;
+    let signature = response
+        .headers()
+        .get(SIGNATURE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| hmac::Hmac::<sha256::Hash>::from_str(value).ok())
+        .ok_or("the answer is not signed")?;
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| format!("pulling {url}: {e}"))?;
+    if mac(&config.token, &nonce, &body) != signature {
+        return Err("the answer's signature does not verify, nothing was applied".into());
+    }
+    let replicated: ReplicatedState =
+        serde_json::from_slice(&body).map_err(|e| format!("decoding the answer: {e}"))?;
+
+    if let (Some(guard), Some(spends)) = (&state.spend_guard, replicated.spent_outpoints) {
+        guard
+            .replace(spends)
+            .map_err(|e| format!("saving signed spends: {e}"))?;
+    }
+    if let (Some(labels), Some(body)) = (&state.labels, replicated.labels) {
+        labels
+            .replace(&body)
+            .map_err(|e| format!("saving labels: {e}"))?;
+    }
+    match (&state.audit, replicated.audit) {
+        (Some(audit), Some(entries)) => {
+            if entries.offset != audit_offset || entries.total < audit_offset {
+                return Err(format!(
+                    "the standby holds {audit_offset} audit entries, more than the primary's {}",
+                    entries.total
+                ));
+            }
+            for line in &entries.lines {
+                serde_json::from_str::<AuditEntry>(line)
+                    .map_err(|e| format!("the primary sent an invalid audit entry: {e}"))?;
+            }
+            audit
+                .append_lines(&entries.lines)
+                .map_err(|e| format!("appending audit entries: {e}"))?;
+            Ok(audit_offset + entries.lines.len())
+        }
+        _ => Ok(audit_offset),
+    }
+}
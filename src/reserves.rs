@@ -0,0 +1,370 @@
+//! BIP-127 proofs of reserves.
+//!
+//! A proof is a transaction that can never be mined: its first input spends
+//! a made-up outpoint derived from the auditor's challenge message, the
+//! others spend the wallet's coins, and its single output pays everything
+//! to an unspendable script with no fee. Signing it proves control of the
+//! coins, and the signatures commit to the challenge because they commit to
+//! every input. The layout follows `bdk-reserves`, so proofs can be checked
+//! with either.
+//!
+//! Building one needs the wallet's coins and so `[chain]`. It runs on the
+//! admin listener, since the proof reveals every coin it covers.
+//! Verification only needs the proof and is part of the public API.
+
+use std::sync::Arc;
+
+use axum::{extract::State, Json};
+use bdk_wallet::{
+    miniscript::interpreter::{Interpreter, KeySigPair, SatisfiedConstraint},
+    SignOptions,
+};
+use bitcoin::{
+    absolute::LockTime,
+    hashes::{hash160, sha256d, Hash},
+    key::Secp256k1,
+    opcodes::OP_TRUE,
+    script::Builder,
+    sighash::{EcdsaSighashType, Prevouts, TapSighashType},
+    transaction::Version,
+    Amount, OutPoint, Psbt, PubkeyHash, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+    Witness,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::DEFAULT_WALLET,
+    context::RequestContext,
+    error::{ApiJson, Error, ErrorResponse},
+    events::Event,
+    priority::Priority,
+    AppState,
+};
+
+const MESSAGE_PREFIX: &str = "Proof-of-Reserves: ";
+
+#[derive(Deserialize)]
+pub struct ProofRequest {
+    /// The auditor's challenge, committed to by the proof.
+    message: String,
+    /// Coins to cover, as `txid:vout`. Every confirmed coin when omitted.
+    #[serde(default)]
+    outpoints: Option<Vec<OutPoint>>,
+}
+
+#[derive(Serialize)]
+pub struct ProofResponse {
+    /// Base64-encoded, finalized proof PSBT.
+    #[serde(serialize_with = "crate::serialize_psbt_to_base64")]
+    pub psbt: Psbt,
+    /// Total value of the covered coins.
+    pub amount_sat: u64,
+    /// Covered coins, as `txid:vout`.
+    pub outpoints: Vec<OutPoint>,
+    /// Set when `signing.dry_run` left the proof unsigned.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+}
+
+pub async fn prove(
+    State(state): State<Arc<AppState>>,
+    ctx: RequestContext,
+    ApiJson(request): ApiJson<ProofRequest>,
+) -> Result<Json<ProofResponse>, Error> {
+    if request.message.is_empty() {
+        return Err(Error::MalformedRequest("message must not be empty".into()));
+    }
+    crate::chain::require_synced(&state)?;
+    let (psbt, outpoints, amount) = build(&state, &request)?;
+    let (psbt, dry_run) = sign_recorded(&state, &ctx, psbt).await?;
+    Ok(Json(ProofResponse {
+        psbt,
+        amount_sat: amount.to_sat(),
+        outpoints,
+        dry_run,
+    }))
+}
+
+/// Signs a proof under the concurrency limit and records it like any other
+/// signature. Returns whether `signing.dry_run` left it unsigned. Also used
+/// by [`crate::ownership`].
+pub async fn sign_recorded(
+    state: &Arc<AppState>,
+    ctx: &RequestContext,
+    psbt: Psbt,
+) -> Result<(Psbt, bool), Error> {
+    let txid = psbt.unsigned_tx.compute_txid();
+    let result = match state.acquire_signing_permit(Priority::Interactive).await {
+        Ok(permit) => {
+            let state = state.clone();
+            crate::run_blocking(move || {
+                let _permit = permit;
+                sign(&state, psbt)
+            })
+            .await
+        }
+        Err(e) => Err(e),
+    };
+    let result = crate::record_signing(state, ctx, DEFAULT_WALLET, txid, result);
+    let request_id = ctx.request_id.clone();
+    state.events.publish(match &result {
+        Ok((_, dry_run)) => Event::Signed {
+            txid,
+            request_id,
+            dry_run: *dry_run,
+        },
+        Err(e) => Event::Rejected {
+            txid,
+            request_id,
+            error_code: e.code(),
+        },
+    });
+    result
+}
+
+/// The unsigned proof over the requested coins.
+fn build(state: &AppState, request: &ProofRequest) -> Result<(Psbt, Vec<OutPoint>, Amount), Error> {
+    let wallet = state.wallet();
+    let coins = match &request.outpoints {
+        Some(outpoints) => outpoints
+            .iter()
+            .map(|&outpoint| {
+                wallet
+                    .get_utxo(outpoint)
+                    .filter(|utxo| utxo.chain_position.is_confirmed())
+                    .ok_or_else(|| {
+                        Error::MalformedRequest(format!(
+                            "{outpoint} is not a confirmed, unspent coin of this wallet"
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        None => wallet
+            .list_unspent()
+            .filter(|utxo| utxo.chain_position.is_confirmed())
+            .collect(),
+    };
+    if coins.is_empty() {
+        return Err(Error::MalformedRequest("no coins to prove".into()));
+    }
+    let amount: Amount = coins.iter().map(|utxo| utxo.txout.value).sum();
+    let outpoints: Vec<OutPoint> = coins.iter().map(|utxo| utxo.outpoint).collect();
+
+    let mut input = vec![challenge_txin(&request.message)];
+    input.extend(outpoints.iter().map(|&previous_output| TxIn {
+        previous_output,
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::MAX,
+        witness: Witness::new(),
+    }));
+    let tx = Transaction {
+        version: Version::ONE,
+        lock_time: LockTime::ZERO,
+        input,
+        output: vec![TxOut {
+            value: amount,
+            script_pubkey: unspendable(),
+        }],
+    };
+    let mut psbt = Psbt::from_unsigned_tx(tx)
+        .map_err(|e| Error::Internal(format!("building the proof: {e}")))?;
+    // Marked final so it is neither signed nor finalized.
+    psbt.inputs[0].witness_utxo = Some(challenge_txout());
+    psbt.inputs[0].final_script_sig = Some(ScriptBuf::new());
+    for (index, utxo) in coins.into_iter().enumerate() {
+        let outpoint = utxo.outpoint;
+        psbt.inputs[index + 1] = wallet
+            .get_psbt_input(utxo, None, false)
+            .map_err(|e| Error::Internal(format!("{outpoint}: {e}")))?;
+    }
+    Ok((psbt, outpoints, amount))
+}
+
+fn sign(state: &AppState, mut psbt: Psbt) -> Result<(Psbt, bool), Error> {
+    if state.config().signing.dry_run {
+        return Ok((psbt, true));
+    }
+    // The service built every input itself, so their `witness_utxo` is
+    // trusted whatever `[sign_options]` says.
+    let options = SignOptions {
+        trust_witness_utxo: true,
+        ..Default::default()
+    };
+    let finalized = state
+        .wallet()
+        .sign(&mut psbt, options)
+        .map_err(|e| Error::InvalidTransaction(format!("signing failed: {e}")))?;
+    if !finalized {
+        return Err(Error::InvalidTransaction(
+            "the proof could not be finalized".into(),
+        ));
+    }
+    Ok((psbt, false))
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct VerifyRequest {
+    /// Base64-encoded, finalized proof PSBT.
+    #[serde(deserialize_with = "crate::de_psbt_from_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub psbt: Psbt,
+    /// The challenge the proof must commit to.
+    pub message: String,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct VerifyResponse {
+    pub valid: bool,
+    /// Why the proof is invalid.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Total value of the proven coins; `0` for an invalid proof.
+    pub amount_sat: u64,
+    /// Proven coins, as `txid:vout`.
+    #[schema(value_type = Vec<String>)]
+    pub outpoints: Vec<OutPoint>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/proof_of_reserves/verify",
+    request_body = VerifyRequest,
+    responses(
+        (status = 200, description = "Verdict on the proof", body = VerifyResponse),
+        (status = 400, description = "`malformed_request`", body = ErrorResponse),
+        (status = 415, description = "`unsupported_media_type`", body = ErrorResponse),
+    )
+)]
+pub async fn verify_handler(ApiJson(request): ApiJson<VerifyRequest>) -> Json<VerifyResponse> {
+    Json(match verify(&request.psbt, &request.message) {
+        Ok((outpoints, amount)) => VerifyResponse {
+            valid: true,
+            reason: None,
+            amount_sat: amount.to_sat(),
+            outpoints,
+        },
+        Err(reason) => VerifyResponse {
+            valid: false,
+            reason: Some(reason),
+            amount_sat: 0,
+            outpoints: Vec::new(),
+        },
+    })
+}
+
+/// Checks `psbt` is a proof for `message` and returns the coins it covers.
+///
+/// The coins' values and scripts come from the PSBT. Segwit signatures
+/// commit to them, and legacy inputs must carry the full previous
+/// transaction. Whether the coins are still unspent is left to the caller.
+fn verify(psbt: &Psbt, message: &str) -> Result<(Vec<OutPoint>, Amount), String> {
+    let tx = psbt.clone().extract_tx_unchecked_fee_rate();
+    if tx.input.len() < 2 {
+        return Err("the proof covers no coins".into());
+    }
+    if tx.input[0].previous_output != challenge_txin(message).previous_output {
+        return Err("the proof does not commit to this message".into());
+    }
+    let [output] = tx.output.as_slice() else {
+        return Err("a proof must have exactly one output".into());
+    };
+    if output.script_pubkey != unspendable() {
+        return Err("the proof's output is spendable".into());
+    }
+
+    let mut prevouts = vec![challenge_txout()];
+    for (index, (txin, input)) in tx.input.iter().zip(&psbt.inputs).enumerate().skip(1) {
+        let outpoint = txin.previous_output;
+        let spent = match (&input.non_witness_utxo, &input.witness_utxo) {
+            (Some(previous), _) => {
+                if previous.compute_txid() != outpoint.txid {
+                    return Err(format!(
+                        "input {index}: previous transaction does not match"
+                    ));
+                }
+                previous
+                    .output
+                    .get(outpoint.vout as usize)
+                    .cloned()
+                    .ok_or_else(|| format!("input {index}: no output {}", outpoint.vout))?
+            }
+            (None, Some(utxo)) if !txin.witness.is_empty() => utxo.clone(),
+            _ => return Err(format!("input {index}: previous output is missing")),
+        };
+        prevouts.push(spent);
+    }
+    let amount: Amount = prevouts.iter().map(|prevout| prevout.value).sum();
+    if output.value != amount {
+        return Err(format!(
+            "the output pays {} but the coins hold {amount}",
+            output.value
+        ));
+    }
+
+    let secp = Secp256k1::verification_only();
+    let all = Prevouts::All(&prevouts);
+    for (index, (txin, spent)) in tx.input.iter().zip(&prevouts).enumerate().skip(1) {
+        let interpreter = Interpreter::from_txdata(
+            &spent.script_pubkey,
+            &txin.script_sig,
+            &txin.witness,
+            txin.sequence,
+            tx.lock_time,
+        )
+        .map_err(|e| format!("input {index}: {e}"))?;
+        let mut signed = false;
+        for constraint in interpreter.iter(&secp, &tx, index, &all) {
+            let key_sig = match constraint.map_err(|e| format!("input {index}: {e}"))? {
+                SatisfiedConstraint::PublicKey { key_sig }
+                | SatisfiedConstraint::PublicKeyHash { key_sig, .. } => key_sig,
+                _ => continue,
+            };
+            // Only these commit to the challenge input.
+            let commits = match key_sig {
+                KeySigPair::Ecdsa(_, sig) => sig.sighash_type == EcdsaSighashType::All,
+                KeySigPair::Schnorr(_, sig) => matches!(
+                    sig.sighash_type,
+                    TapSighashType::Default | TapSighashType::All
+                ),
+            };
+            if !commits {
+                return Err(format!("input {index}: signature is not SIGHASH_ALL"));
+            }
+            signed = true;
+        }
+        if !signed {
+            return Err(format!("input {index}: not signed"));
+        }
+    }
+    Ok((
+        tx.input[1..]
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect(),
+        amount,
+    ))
+}
+
+/// The first input, committing to `message` through its made-up outpoint.
+fn challenge_txin(message: &str) -> TxIn {
+    let hash = sha256d::Hash::hash(format!("{MESSAGE_PREFIX}{message}").as_bytes());
+    TxIn {
+        previous_output: OutPoint::new(Txid::from_raw_hash(hash), 0),
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::MAX,
+        witness: Witness::new(),
+    }
+}
+
+/// What the challenge input pretends to spend.
+fn challenge_txout() -> TxOut {
+    TxOut {
+        value: Amount::ZERO,
+        script_pubkey: Builder::new().push_opcode(OP_TRUE).into_script(),
+    }
+}
+
+/// P2PKH of a hash no key hashes to.
+fn unspendable() -> ScriptBuf {
+    ScriptBuf::new_p2pkh(&PubkeyHash::from_raw_hash(hash160::Hash::hash(&[0])))
+}
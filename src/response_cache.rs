@@ -0,0 +1,452 @@
+//! Short-lived cache of signed PSBTs, so an upstream retrying a request it
+//! already got an answer to is not signed and audited again.
+//!
+//! Entries are keyed by the unsigned txid, but a resubmission is only
+//! answered from the cache if its PSBT is identical to the one that was
+//! signed: the same transaction with another cosigner's signatures added is
+//! signed afresh. Each entry carries the [`policy`] it was signed under and
+//! only answers under the same one, and a hit is held to the signing
+//! policies and the spend guard again before it is given out, so a coin
+//! signed into a replacement since is not signed away twice. Dry runs are
+//! never cached, and a config reload empties the cache. With `[redis]` the
+//! entries are kept there instead, for every replica to answer from, and
+//! expire on their own. With a `[storage]` backend other than files they
+//! are kept in it as documents under `response_cache/`, and survive a
+//! restart.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bitcoin::{
+    hashes::{sha256, Hash},
+    Psbt, Txid,
+};
+use chrono::{DateTime, Utc};
+
+use crate::{config::Config, storage::Storage};
+
+/// Prefix of the documents entries are stored as.
+const PREFIX: &str = "response_cache/";
+
+#[derive(Default)]
+pub struct ResponseCache {
+    entries: Mutex<HashMap<Txid, Entry>>,
+    storage: Option<Arc<dyn Storage>>,
+    #[cfg(feature = "redis")]
+    shared: Option<Arc<crate::shared::Shared>>,
+}
+
+/// An entry as kept in Redis or storage, with base64 PSBTs.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredEntry {
+    request: String,
+    signed: String,
+    /// Absent in entries of earlier releases, which then never match.
+    #[serde(default)]
+    policy: String,
+    /// Absent in Redis, whose keys expire instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<DateTime<Utc>>,
+}
+
+struct Entry {
+    /// The PSBT as submitted.
+    request: Psbt,
+    signed: Psbt,
+    policy: String,
+    expires: Instant,
+}
+
+impl ResponseCache {
+    /// A cache keeping its entries in Redis.
+    #[cfg(feature = "redis")]
+    pub fn shared(shared: Arc<crate::shared::Shared>) -> Self {
+        ResponseCache {
+            shared: Some(shared),
+            ..Default::default()
+        }
+    }
+
+    /// A cache keeping its entries in `storage`.
+    pub fn stored(storage: Arc<dyn Storage>) -> Self {
+        ResponseCache {
+            storage: Some(storage),
+            ..Default::default()
+        }
+    }
+
+    /// The signed PSBT of an earlier, identical submission of `psbt` under
+    /// the same `policy`.
+    pub fn get(&self, psbt: &Psbt, policy: &str) -> Option<Psbt> {
+        let txid = psbt.unsigned_tx.compute_txid();
+        #[cfg(feature = "redis")]
+        if let Some(shared) = &self.shared {
+            let cached = tokio::task::block_in_place(|| shared.cached(txid))
+                .map_err(|e| tracing::warn!("signed response cache: {e}"))
+                .ok()??;
+            let entry: StoredEntry = serde_json::from_slice(&cached).ok()?;
+            return (entry.request == psbt.to_string() && entry.policy == policy)
+                .then(|| entry.signed.parse().ok())
+                .flatten();
+        }
+        if let Some(storage) = &self.storage {
+            let name = format!("{PREFIX}{txid}");
+            let stored = storage
+                .read(&name)
+                .map_err(|e| tracing::warn!("signed response cache: {e}"))
+                .ok()??;
+            let entry: StoredEntry = serde_json::from_slice(&stored).ok()?;
+            if entry
+                .expires_at
+                .map_or(true, |expires| expires <= Utc::now())
+            {
+                let _ = storage.remove(&name);
+                return None;
+            }
+            return (entry.request == psbt.to_string() && entry.policy == policy)
+                .then(|| entry.signed.parse().ok())
+                .flatten();
+        }
+        let mut entries = self.entries.lock().expect("response cache");
+        match entries.get(&txid) {
+            Some(entry) if entry.expires <= Instant::now() => {
+                entries.remove(&txid);
+                None
+            }
+            Some(entry) if entry.request == *psbt && entry.policy == policy => {
+                Some(entry.signed.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Keeps `signed`, the answer to `request` under `policy`, for `ttl`.
+    /// With `capacity` entries held, expired ones are dropped first and then
+    /// those closest to expiring.
+    pub fn insert(
+        &self,
+        request: Psbt,
+        signed: Psbt,
+        policy: String,
+        ttl: Duration,
+        capacity: usize,
+    ) {
+        if capacity == 0 {
+            return;
+        }
+        #[cfg(feature = "redis")]
+        if let Some(shared) = &self.shared {
+            let entry = StoredEntry {
+                request: request.to_string(),
+                signed: signed.to_string(),
+                policy,
+                expires_at: None,
+            };
+            let entry = serde_json::to_vec(&entry).expect("serialize cache entry");
+            let txid = request.unsigned_tx.compute_txid();
+            if let Err(e) = tokio::task::block_in_place(|| shared.cache(txid, &entry, ttl)) {
+                tracing::warn!("signed response cache: {e}");
+            }
+            return;
+        }
+        if let Some(storage) = &self.storage {
+            let entry = StoredEntry {
+                request: request.to_string(),
+                signed: signed.to_string(),
+                policy,
+                expires_at: chrono::Duration::from_std(ttl)
+                    .ok()
+                    .and_then(|ttl| Utc::now().checked_add_signed(ttl)),
+            };
+            let name = format!("{PREFIX}{}", request.unsigned_tx.compute_txid());
+            let stored = make_room(storage.as_ref(), &name, capacity).and_then(|()| {
+                let entry = serde_json::to_vec(&entry).expect("serialize cache entry");
+                storage.write(&name, &entry)
+            });
+            if let Err(e) = stored {
+                tracing::warn!("signed response cache: {e}");
+            }
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self.entries.lock().expect("response cache");
+        let txid = request.unsigned_tx.compute_txid();
+        if entries.len() >= capacity && !entries.contains_key(&txid) {
+            entries.retain(|_, entry| entry.expires > now);
+            while entries.len() >= capacity {
+                let Some(oldest) = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.expires)
+                    .map(|(txid, _)| *txid)
+                else {
+                    break;
+                };
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            txid,
+            Entry {
+                request,
+                signed,
+                policy,
+                expires: now + ttl,
+            },
+        );
+    }
+
+    pub fn clear(&self) {
+        #[cfg(feature = "redis")]
+        if let Some(shared) = &self.shared {
+            if let Err(e) = tokio::task::block_in_place(|| shared.clear_cache()) {
+                tracing::warn!("signed response cache: {e}");
+            }
+            return;
+        }
+        if let Some(storage) = &self.storage {
+            let cleared = storage
+                .names(PREFIX)
+                .and_then(|names| names.iter().try_for_each(|name| storage.remove(name)));
+            if let Err(e) = cleared {
+                tracing::warn!("signed response cache: {e}");
+            }
+            return;
+        }
+        self.entries.lock().expect("response cache").clear();
+    }
+}
+
+/// Digest of the config signing is held to in [`crate::sign_psbt`], which
+/// entries are kept under. Entries outlive a restart in storage or Redis,
+/// and one signed under other policies, such as before a restart with a
+/// tightened config or by a replica configured otherwise, is not given out.
+pub fn policy(config: &Config) -> String {
+    let policy = format!(
+        "{:?}",
+        (
+            config.network,
+            &config.signing,
+            &config.sign_options,
+            &config.sighash_pairing,
+            &config.psbt_fields,
+            &config.dust,
+            &config.locktime,
+            &config.templates,
+            &config.rgb,
+            &config.channel_funding,
+            &config.coinjoin,
+            (&config.spend_guard, &config.fee_check),
+        )
+    );
+    sha256::Hash::hash(policy.as_bytes()).to_string()
+}
+
+/// With `capacity` entries in `storage` and `name` not one of them, drops
+/// the expired ones and then those closest to expiring.
+fn make_room(storage: &dyn Storage, name: &str, capacity: usize) -> std::io::Result<()> {
+    let names = storage.names(PREFIX)?;
+    if names.len() < capacity || names.iter().any(|stored| stored == name) {
+        return Ok(());
+    }
+    let mut expiring = Vec::new();
+    for stored in names {
+        let expires_at = storage
+            .read(&stored)?
+            .and_then(|entry| serde_json::from_slice::<StoredEntry>(&entry).ok())
+            .and_then(|entry| entry.expires_at);
+        expiring.push((expires_at, stored));
+    }
+    expiring.sort();
+    let now = Utc::now();
+    let excess = expiring.len() + 1 - capacity;
+    for (index, (expires_at, stored)) in expiring.iter().enumerate() {
+        if index < excess || expires_at.map_or(true, |expires| expires <= now) {
+            storage.remove(stored)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        context::RequestContext,
+        error::Error,
+        storage::Memory,
+        test_support::{coin, config, ours, psbt, script, state, taproot},
+        SignRequest,
+    };
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn entry(value: u64) -> (Psbt, Psbt) {
+        let request = psbt(&[&coin(script(0), 100_000)], &[(taproot(3), value)]);
+        let mut signed = request.clone();
+        signed.inputs[0].final_script_witness = Some(Default::default());
+        (request, signed)
+    }
+
+    #[test]
+    fn answers_identical_submissions_under_the_same_policy() {
+        for cache in [
+            ResponseCache::default(),
+            ResponseCache::stored(Arc::new(Memory::default())),
+        ] {
+            let (request, signed) = entry(95_000);
+            cache.insert(request.clone(), signed.clone(), "a".into(), TTL, 10);
+            assert_eq!(cache.get(&request, "a"), Some(signed.clone()));
+            assert_eq!(cache.get(&request, "b"), None);
+            let mut cosigned = request.clone();
+            cosigned.inputs[0].final_script_sig = Some(Default::default());
+            assert_eq!(cache.get(&cosigned, "a"), None);
+            assert_eq!(cache.get(&entry(90_000).0, "a"), None);
+            cache.clear();
+            assert_eq!(cache.get(&request, "a"), None);
+
+            cache.insert(request.clone(), signed, "a".into(), Duration::ZERO, 10);
+            assert_eq!(cache.get(&request, "a"), None, "expired");
+        }
+    }
+
+    #[test]
+    fn drops_the_entries_closest_to_expiring_when_full() {
+        for cache in [
+            ResponseCache::default(),
+            ResponseCache::stored(Arc::new(Memory::default())),
+        ] {
+            let entries: Vec<_> = (0..3).map(|n| entry(90_000 + n)).collect();
+            for (n, (request, signed)) in entries.iter().enumerate() {
+                let ttl = TTL * (3 - n as u32);
+                cache.insert(request.clone(), signed.clone(), "a".into(), ttl, 2);
+            }
+            assert_eq!(cache.get(&entries[0].0, "a"), Some(entries[0].1.clone()));
+            assert_eq!(cache.get(&entries[1].0, "a"), None);
+            assert_eq!(cache.get(&entries[2].0, "a"), Some(entries[2].1.clone()));
+            cache.insert(
+                entries[0].0.clone(),
+                entries[0].1.clone(),
+                "a".into(),
+                TTL,
+                0,
+            );
+        }
+    }
+
+    #[test]
+    fn ignores_stored_entries_without_a_policy() {
+        let storage = Arc::new(Memory::default());
+        let (request, signed) = entry(95_000);
+        let entry = json!({
+            "request": request.to_string(),
+            "signed": signed.to_string(),
+            "expires_at": Utc::now() + chrono::Duration::minutes(1),
+        });
+        let name = format!("{PREFIX}{}", request.unsigned_tx.compute_txid());
+        storage
+            .write(&name, &serde_json::to_vec(&entry).unwrap())
+            .unwrap();
+        let cache = ResponseCache::stored(storage);
+        assert_eq!(cache.get(&request, &policy(&config(""))), None);
+    }
+
+    #[test]
+    fn policies_tell_signing_configs_apart() {
+        let base = policy(&config(""));
+        assert_eq!(policy(&config("port = 3000")), base);
+        for tightened in [
+            "[dust]\np2tr_sat = 1000",
+            "[sign_options]\nallowed_sighashes = [\"all\", \"none\"]",
+            "[signing]\ndry_run = true",
+            "[spend_guard]\npath = \"spends.json\"",
+        ] {
+            assert_ne!(policy(&config(tightened)), base, "{tightened}");
+        }
+    }
+
+    const CACHED: &str = r#"
+[signing]
+response_cache_ttl_secs = 60
+
+[storage]
+backend = "memory"
+
+[spend_guard]
+path = "spent_outpoints.json"
+"#;
+
+    fn request(psbt: &Psbt, replace: bool) -> SignRequest {
+        serde_json::from_value(json!({"psbt": psbt.to_string(), "replace": replace})).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn holds_cached_answers_to_the_spend_guard() {
+        let state = state(CACHED).await;
+        let ctx = RequestContext::default();
+        let funding = coin(script(0), 100_000);
+        let spend = |to: u8| {
+            let mut spend = psbt(&[&funding], &[(taproot(to), 95_000)]);
+            ours(&mut spend.inputs[0]);
+            spend.inputs[0].non_witness_utxo = Some(funding.clone());
+            spend
+        };
+        let (a, b) = (spend(3), spend(4));
+
+        let first = crate::decide_signing(&state, &ctx, request(&a, false))
+            .await
+            .unwrap();
+        assert!(!first.cached);
+        assert!(first.psbt.inputs[0].final_script_witness.is_some());
+        let again = crate::decide_signing(&state, &ctx, request(&a, false))
+            .await
+            .unwrap();
+        assert!(again.cached);
+        assert_eq!(again.psbt, first.psbt);
+
+        crate::decide_signing(&state, &ctx, request(&b, true))
+            .await
+            .unwrap();
+        match crate::decide_signing(&state, &ctx, request(&a, false)).await {
+            Err(Error::InvalidTransaction(message)) => {
+                assert!(message.contains("double spend"), "{message}")
+            }
+            Err(e) => panic!("replaced transaction refused with {e}"),
+            Ok(signed) => panic!(
+                "replaced transaction signed again, cached: {}",
+                signed.cached
+            ),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn holds_cached_answers_to_the_signing_policies() {
+        let state = state(CACHED).await;
+        let funding = coin(script(0), 100_000);
+        let mut spend = psbt(&[&funding], &[(taproot(3), 95_000)]);
+        ours(&mut spend.inputs[0]);
+        spend.inputs[0].non_witness_utxo = Some(funding);
+        spend.inputs[0].sighash_type = Some(bitcoin::EcdsaSighashType::None.into());
+        // An entry the running policies would not have signed, as one kept
+        // across a restart or left by a replica might be.
+        let mut signed = spend.clone();
+        signed.inputs[0].final_script_witness = Some(Default::default());
+        let policy = policy(&state.config());
+        state
+            .response_cache
+            .insert(spend.clone(), signed, policy, TTL, 10);
+        match crate::decide_signing(&state, &RequestContext::default(), request(&spend, false))
+            .await
+        {
+            Err(Error::InvalidTransaction(message)) => {
+                assert!(message.contains("sighash"), "{message}")
+            }
+            Err(e) => panic!("refused with {e}"),
+            Ok(signed) => panic!("signed, cached: {}", signed.cached),
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Awareness of RGB's deterministic bitcoin commitments (tapret and opret).
+//!
+//! RGB wallets mark the output hosting a commitment with proprietary PSBT
+//! keys under the `TAPRET` or `OPRET` prefix. Signatures commit to output
+//! scripts, so an output whose commitment is not embedded yet must not be
+//! signed for: embedding it afterwards would invalidate every signature.
+//! When finalizing is enabled, a host output still carrying its
+//! pre-commitment script is rewritten to the committed one before signing.
+//!
+//! Signing an invalid transfer burns the assets it spends, so a sign request
+//! may also carry the transfer's consignment, which is handed to an external
+//! validator together with the PSBT before any signature is produced.
+
+use bitcoin::{
+    key::{Secp256k1, XOnlyPublicKey},
+    opcodes::all::OP_RETURN,
+    psbt::raw::ProprietaryKey,
+    script::{Builder, PushBytesBuf},
+    taproot::{LeafVersion, TapLeafHash, TapNodeHash},
+    Psbt, ScriptBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, AppState};
+
+const TAPRET_PREFIX: &[u8] = b"TAPRET";
+const OPRET_PREFIX: &[u8] = b"OPRET";
+/// Marks the output as the commitment host.
+const HOST: u8 = 0x00;
+/// The 32-byte multi-protocol commitment to embed.
+const COMMITMENT: u8 = 0x01;
+/// Strict-encoded tapret proof: partner node option, nonce, internal key.
+const TAPRET_PROOF: u8 = 0x02;
+
+/// 29 `OP_RESERVED`, `OP_RETURN`, then a 33-byte push of commitment and nonce.
+const TAPRET_SCRIPT_PREFIX: [u8; 31] = {
+    let mut prefix = [0x50; 31];
+    prefix[29] = 0x6a;
+    prefix[30] = 0x21;
+    prefix
+};
+
+/// An RGB consignment attached to a sign request, inline or by reference:
+/// `{"data": "<base64>"}` or `{"id": "<reference>"}`.
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Consignment {
+    /// Base64-encoded consignment.
+    Data(String),
+    /// Reference the validator resolves itself, such as a consignment id.
+    Id(String),
+}
+
+#[derive(Serialize)]
+struct ValidationRequest<'a> {
+    /// Base64-encoded PSBT, as received.
+    psbt: String,
+    consignment: &'a Consignment,
+}
+
+#[derive(Deserialize)]
+struct Verdict {
+    valid: bool,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// Validates the transfer `psbt` belongs to against `consignment` with the
+/// configured validator.
+///
+/// Without a consignment this only enforces `rgb.require_consignment` for
+/// PSBTs hosting a commitment. Any validator failure refuses the request:
+/// an unchecked transfer is never signed.
+pub async fn validate_transfer(
+    state: &AppState,
+    psbt: &Psbt,
+    consignment: Option<&Consignment>,
+) -> Result<(), Error> {
+    let config = state.config();
+    let Some(consignment) = consignment else {
+        if config.rgb.require_consignment && hosts_commitment(psbt) {
+            return Err(Error::ConsignmentRejected(
+                "the PSBT hosts an RGB commitment but no consignment is attached".into(),
+            ));
+        }
+        return Ok(());
+    };
+    let url = config.rgb.validator_url.as_deref().ok_or_else(|| {
+        Error::MalformedRequest(
+            "a consignment is attached but no RGB validator is configured".into(),
+        )
+    })?;
+
+    let request = ValidationRequest {
+        psbt: psbt.to_string(),
+        consignment,
+    };
+    let verdict: Verdict = async {
+        state
+            .http
+            .post(url)
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+    .await
+    .map_err(|e| Error::Internal(format!("consignment validator failed: {e}")))?;
+    if !verdict.valid {
+        return Err(Error::ConsignmentRejected(
+            verdict
+                .reason
+                .unwrap_or_else(|| "the validator rejected the transfer".into()),
+        ));
+    }
+    tracing::info!("consignment validated");
+    Ok(())
+}
+
+fn hosts_commitment(psbt: &Psbt) -> bool {
+    psbt.outputs.iter().any(|output| {
+        [TAPRET_PREFIX, OPRET_PREFIX]
+            .iter()
+            .any(|prefix| output.proprietary.contains_key(&proprietary(prefix, HOST)))
+    })
+}
+
+fn proprietary(prefix: &[u8], subtype: u8) -> ProprietaryKey {
+    ProprietaryKey {
+        prefix: prefix.to_vec(),
+        subtype,
+        key: Vec::new(),
+    }
+}
+
+/// Checks every commitment host output of `psbt` and, with `finalize`,
+/// rewrites hosts whose commitment is not embedded yet.
+pub fn check_commitments(psbt: &mut Psbt, finalize: bool) -> Result<(), Error> {
+    let mut expected = Vec::new();
+    for (method, prefix) in [("tapret", TAPRET_PREFIX), ("opret", OPRET_PREFIX)] {
+        let hosts: Vec<usize> = psbt
+            .outputs
+            .iter()
+            .enumerate()
+            .filter(|(_, output)| output.proprietary.contains_key(&proprietary(prefix, HOST)))
+            .map(|(index, _)| index)
+            .collect();
+        if hosts.len() > 1 {
+            return Err(Error::InvalidTransaction(format!(
+                "outputs {hosts:?} are all {method} commitment hosts, at most one is allowed"
+            )));
+        }
+        for index in hosts {
+            let (committed, placeholder) = if prefix == TAPRET_PREFIX {
+                tapret_scripts(psbt, index)?
+            } else {
+                opret_scripts(psbt, index)?
+            };
+            expected.push((index, method, committed, placeholder));
+        }
+    }
+
+    let mut rewritten = Vec::new();
+    for (index, method, committed, placeholder) in expected {
+        let script_pubkey = &psbt.unsigned_tx.output[index].script_pubkey;
+        if *script_pubkey == committed {
+            continue;
+        }
+        if *script_pubkey != placeholder {
+            return Err(Error::InvalidTransaction(format!(
+                "output {index} does not match its {method} commitment"
+            )));
+        }
+        if !finalize {
+            return Err(Error::InvalidTransaction(format!(
+                "the {method} commitment of output {index} is not embedded yet; \
+                 signing now would be invalidated by embedding it"
+            )));
+        }
+        rewritten.push((index, committed));
+    }
+    if rewritten.is_empty() {
+        return Ok(());
+    }
+
+    // Rewriting an output changes what every signature commits to.
+    let signed = psbt.inputs.iter().any(|input| {
+        !input.partial_sigs.is_empty()
+            || input.tap_key_sig.is_some()
+            || !input.tap_script_sigs.is_empty()
+            || input.final_script_sig.is_some()
+            || input.final_script_witness.is_some()
+    });
+    if signed {
+        return Err(Error::InvalidTransaction(
+            "cannot embed an RGB commitment into a PSBT that already carries signatures".into(),
+        ));
+    }
+    for (index, committed) in rewritten {
+        psbt.unsigned_tx.output[index].script_pubkey = committed;
+        tracing::info!(output = index, "embedded RGB commitment into host output");
+    }
+    Ok(())
+}
+
+fn commitment(psbt: &Psbt, index: usize, prefix: &[u8]) -> Result<[u8; 32], Error> {
+    psbt.outputs[index]
+        .proprietary
+        .get(&proprietary(prefix, COMMITMENT))
+        .and_then(|value| value.as_slice().try_into().ok())
+        .ok_or_else(|| {
+            Error::InvalidTransaction(format!(
+                "output {index} is a commitment host without a 32-byte commitment"
+            ))
+        })
+}
+
+/// The committed `OP_RETURN` script, and the bare `OP_RETURN` placeholder.
+fn opret_scripts(psbt: &Psbt, index: usize) -> Result<(ScriptBuf, ScriptBuf), Error> {
+    let commitment = commitment(psbt, index, OPRET_PREFIX)?;
+    let committed = Builder::new()
+        .push_opcode(OP_RETURN)
+        .push_slice(PushBytesBuf::from(commitment))
+        .into_script();
+    Ok((
+        committed,
+        Builder::new().push_opcode(OP_RETURN).into_script(),
+    ))
+}
+
+/// The taproot output with the tapret leaf as its only script, and the
+/// key-only output of the same internal key.
+fn tapret_scripts(psbt: &Psbt, index: usize) -> Result<(ScriptBuf, ScriptBuf), Error> {
+    let commitment = commitment(psbt, index, TAPRET_PREFIX)?;
+    let invalid = |message: &str| Error::InvalidTransaction(format!("output {index}: {message}"));
+    let proof = psbt.outputs[index]
+        .proprietary
+        .get(&proprietary(TAPRET_PREFIX, TAPRET_PROOF))
+        .ok_or_else(|| invalid("tapret host without a tapret proof"))?;
+    let (nonce, internal_key) = match proof.as_slice() {
+        [0x00, nonce, key @ ..] => (*nonce, key),
+        [0x01, ..] => {
+            return Err(invalid(
+                "tapret proofs with a script tree partner are not supported",
+            ))
+        }
+        _ => return Err(invalid("malformed tapret proof")),
+    };
+    let internal_key =
+        XOnlyPublicKey::from_slice(internal_key).map_err(|_| invalid("malformed tapret proof"))?;
+
+    let mut script = TAPRET_SCRIPT_PREFIX.to_vec();
+    script.extend_from_slice(&commitment);
+    script.push(nonce);
+    let leaf = TapLeafHash::from_script(&ScriptBuf::from(script), LeafVersion::TapScript);
+
+    let secp = Secp256k1::verification_only();
+    Ok((
+        ScriptBuf::new_p2tr(&secp, internal_key, Some(TapNodeHash::from(leaf))),
+        ScriptBuf::new_p2tr(&secp, internal_key, None),
+    ))
+}
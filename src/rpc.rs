@@ -0,0 +1,219 @@
+//! JSON-RPC 2.0 endpoint mapping onto the HTTP API's operations.
+//!
+//! Methods: `sign_psbt` (params `{"psbt": ...}` or `[psbt]`), `version` and
+//! `ready`. The calls of a batch run concurrently, at most
+//! `signing.max_concurrent` at a time, and are answered in order;
+//! notifications (requests without an `id`) are executed but get no
+//! response.
+
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures_util::StreamExt;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use crate::{auth::Scope, context::RequestContext, error::Error, AppState, SignRequest};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Application errors; `data.code` carries the HTTP API's error code.
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Option<Value>,
+    /// `None` for a notification, `Some(Value::Null)` for an explicit null id.
+    #[serde(default, deserialize_with = "present")]
+    id: Option<Value>,
+}
+
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[derive(Serialize)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    #[serde(flatten)]
+    outcome: Outcome,
+    id: Value,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Result(Value),
+    Error(RpcError),
+}
+
+#[derive(Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+impl From<Error> for RpcError {
+    fn from(e: Error) -> Self {
+        tracing::error!(error = ?e, "rpc error");
+        let code = match e {
+            Error::MalformedRequest(_) => INVALID_PARAMS,
+            _ => SERVER_ERROR,
+        };
+        RpcError {
+            code,
+            message: e.to_string(),
+            data: Some(serde_json::json!({ "code": e.code() })),
+        }
+    }
+}
+
+fn response(id: Value, outcome: Result<Value, RpcError>) -> RpcResponse {
+    RpcResponse {
+        jsonrpc: "2.0",
+        outcome: match outcome {
+            Ok(value) => Outcome::Result(value),
+            Err(e) => Outcome::Error(e),
+        },
+        id,
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/rpc",
+    request_body(content = Object, description = "JSON-RPC 2.0 request or batch"),
+    responses(
+        (status = 200, description = "JSON-RPC 2.0 response or batch", body = Object),
+        (status = 204, description = "The request contained only notifications"),
+    )
+)]
+pub async fn handle(
+    State(state): State<Arc<AppState>>,
+    ctx: RequestContext,
+    body: Bytes,
+) -> Response {
+    let body: Value = match serde_json::from_slice(&body) {
+        Ok(body) => body,
+        Err(e) => {
+            let error = RpcError::new(PARSE_ERROR, format!("parse error: {e}"));
+            return Json(response(Value::Null, Err(error))).into_response();
+        }
+    };
+    match body {
+        Value::Array(calls) if calls.is_empty() => {
+            let error = RpcError::new(INVALID_REQUEST, "empty batch");
+            Json(response(Value::Null, Err(error))).into_response()
+        }
+        Value::Array(calls) => {
+            // Running more calls than there are signing slots would only
+            // have the rest shed as overloaded while they queue.
+            let workers = state.config().signing.max_concurrent.max(1);
+            let responses: Vec<_> = futures_util::stream::iter(calls)
+                .map(|call| dispatch(&state, &ctx, call))
+                .buffered(workers)
+                .filter_map(std::future::ready)
+                .collect()
+                .await;
+            if responses.is_empty() {
+                StatusCode::NO_CONTENT.into_response()
+            } else {
+                Json(responses).into_response()
+            }
+        }
+        call => match dispatch(&state, &ctx, call).await {
+            Some(response) => Json(response).into_response(),
+            None => StatusCode::NO_CONTENT.into_response(),
+        },
+    }
+}
+
+/// Runs one call, returning its response unless it was a notification.
+async fn dispatch(state: &Arc<AppState>, ctx: &RequestContext, call: Value) -> Option<RpcResponse> {
+    let request: RpcRequest = match serde_json::from_value(call) {
+        Ok(request) => request,
+        Err(e) => {
+            let error = RpcError::new(INVALID_REQUEST, format!("invalid request: {e}"));
+            return Some(response(Value::Null, Err(error)));
+        }
+    };
+    let valid_id = request
+        .id
+        .as_ref()
+        .map_or(true, |id| id.is_string() || id.is_number() || id.is_null());
+    if request.jsonrpc != "2.0" || !valid_id {
+        let error = RpcError::new(INVALID_REQUEST, "invalid request");
+        return Some(response(Value::Null, Err(error)));
+    }
+
+    let outcome = invoke(state, ctx, &request.method, request.params).await;
+    request.id.map(|id| response(id, outcome))
+}
+
+async fn invoke(
+    state: &Arc<AppState>,
+    ctx: &RequestContext,
+    method: &str,
+    params: Option<Value>,
+) -> Result<Value, RpcError> {
+    let scope = match method {
+        "sign_psbt" => Scope::Sign,
+        _ => Scope::Read,
+    };
+    crate::auth::authorize(state, ctx.bearer.as_ref(), &[scope])?;
+    let value = match method {
+        "sign_psbt" => {
+            let signed = crate::sign_and_record(state, ctx, sign_params(params)?).await?;
+            serde_json::to_value(signed)
+        }
+        "version" => serde_json::to_value(crate::version_info(state)),
+        "ready" => serde_json::to_value(crate::ready_info(state).await.1),
+        _ => {
+            return Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("method not found: {method}"),
+            ))
+        }
+    };
+    value.map_err(|e| Error::Internal(format!("encoding result: {e}")).into())
+}
+
+fn sign_params(params: Option<Value>) -> Result<SignRequest, RpcError> {
+    let params = match params {
+        Some(Value::Array(mut positional)) if positional.len() == 1 => {
+            serde_json::json!({ "psbt": positional.remove(0) })
+        }
+        Some(named @ Value::Object(_)) => named,
+        _ => {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                "expected {\"psbt\": ...} or [psbt]",
+            ))
+        }
+    };
+    serde_json::from_value(params)
+        .map_err(|e| RpcError::new(INVALID_PARAMS, format!("invalid params: {e}")))
+}
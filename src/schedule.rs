@@ -0,0 +1,583 @@
+//! Transactions built, signed and broadcast on a schedule, such as a weekly
+//! treasury sweep, in place of external cron jobs calling the admin API.
+//!
+//! A schedule pairs a [cron expression](crate::cron) with an action. When it
+//! comes due, the transaction is built and signed through the same path as
+//! the action's endpoint, so every signing policy and the audit log apply,
+//! then broadcast through `[chain]`. Schedules are added through the API, so
+//! their runs are held to the quorum and the signing windows as client
+//! requests are; a manual run presents its caller's credential. Each run is recorded with its outcome,
+//! and the last `schedules.history` runs of every schedule are kept.
+//!
+//! Schedules and their history are saved to `schedules.path`. Runs missed
+//! while the service was down are not caught up on.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use bitcoin::Txid;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::Metadata,
+    config::{Config, SchedulesConfig},
+    context::RequestContext,
+    cron::Cron,
+    error::{ApiJson, Error},
+    page::{paginate, Key, Order, Page, PageQuery},
+    priority::Priority,
+    storage::Storage,
+    sweep::SweepRequest,
+    AppState,
+};
+
+/// Caller recorded in the audit entries of scheduled transactions.
+const CALLER: &str = "scheduler";
+/// How often due schedules are looked for.
+const TICK: Duration = Duration::from_secs(10);
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    /// Letters, digits, `-` and `_`.
+    pub name: String,
+    /// When to run, in UTC.
+    pub cron: Cron,
+    #[serde(flatten)]
+    pub action: Action,
+    /// In sat/vB. Esplora's estimate for `target_blocks` when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee_rate_sat_vb: Option<f64>,
+    #[serde(default = "default_target_blocks")]
+    pub target_blocks: u16,
+    /// Runs are skipped while the estimate is above this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_fee_rate_sat_vb: Option<f64>,
+    /// Sends the signed transaction through `[chain]`.
+    #[serde(default = "default_broadcast")]
+    pub broadcast: bool,
+    /// Recorded in the audit entries, as for `/sign_psbt`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: Metadata,
+}
+
+fn default_target_blocks() -> u16 {
+    6
+}
+
+fn default_broadcast() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Every spendable coin to `destination`, as `/admin/sweep` does.
+    Sweep {
+        destination: String,
+        #[serde(default)]
+        min_value_sat: u64,
+        #[serde(default)]
+        confirmed_only: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// Signed and accepted by the Esplora server.
+    Broadcast,
+    /// Signed; the schedule does not broadcast.
+    Signed,
+    /// Left unsigned by `signing.dry_run`.
+    DryRun,
+    /// Nothing to do, or fees were too high.
+    Skipped,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Run {
+    pub started_at: DateTime<Utc>,
+    /// Set for runs started through `/admin/schedules/{name}/run`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub manual: bool,
+    pub outcome: Outcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub txid: Option<Txid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount_sat: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee_rate_sat_vb: Option<f64>,
+    /// Why the run was skipped or failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// A schedule as saved, with its runs, newest first.
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    schedule: Schedule,
+    #[serde(default)]
+    runs: VecDeque<Run>,
+    #[serde(skip)]
+    next_run: Option<DateTime<Utc>>,
+}
+
+pub struct Schedules {
+    storage: Arc<dyn Storage>,
+    name: String,
+    history: usize,
+    schedules: RwLock<BTreeMap<String, Stored>>,
+}
+
+impl Schedules {
+    pub fn new(
+        storage: Arc<dyn Storage>,
+        config: &Config,
+        schedules: &SchedulesConfig,
+    ) -> Result<Self, String> {
+        let name = crate::storage::name(&schedules.path);
+        let contents = storage.read(&name).map_err(|e| format!("{name}: {e}"))?;
+        let store = Schedules {
+            storage,
+            name,
+            history: schedules.history.max(1),
+            schedules: Default::default(),
+        };
+        if let Some(contents) = contents {
+            let stored: Vec<Stored> =
+                serde_json::from_slice(&contents).map_err(|e| format!("{}: {e}", store.name))?;
+            let now = Utc::now();
+            let mut loaded = store.schedules.write().expect("schedules");
+            for mut entry in stored {
+                check(config, &entry.schedule)
+                    .map_err(|e| format!("schedule {}: {e}", entry.schedule.name))?;
+                entry.next_run = entry.schedule.cron.next_after(now);
+                loaded.insert(entry.schedule.name.clone(), entry);
+            }
+            tracing::info!(count = loaded.len(), "loaded schedules");
+        }
+        Ok(store)
+    }
+
+    fn add(&self, config: &Config, schedule: Schedule) -> Result<ScheduleStatus, Error> {
+        check(config, &schedule).map_err(Error::MalformedRequest)?;
+        let next_run = schedule.cron.next_after(Utc::now());
+        if next_run.is_none() {
+            return Err(Error::MalformedRequest(format!(
+                "cron {} never comes due",
+                schedule.cron
+            )));
+        }
+        let mut schedules = self.schedules.write().expect("schedules");
+        let name = schedule.name.clone();
+        if schedules.contains_key(&name) {
+            return Err(Error::MalformedRequest(format!(
+                "schedule {name} already exists"
+            )));
+        }
+        let entry = Stored {
+            schedule,
+            runs: VecDeque::new(),
+            next_run,
+        };
+        let status = ScheduleStatus::of(&entry);
+        schedules.insert(name.clone(), entry);
+        if let Err(e) = self.save(&schedules) {
+            schedules.remove(&name);
+            return Err(Error::Internal(format!("saving schedules: {e}")));
+        }
+        tracing::info!(schedule = name, "added schedule");
+        Ok(status)
+    }
+
+    /// Adds `run` to the history of `name`, unless it was removed meanwhile.
+    fn record(&self, name: &str, run: Run) {
+        let mut schedules = self.schedules.write().expect("schedules");
+        let Some(entry) = schedules.get_mut(name) else {
+            return;
+        };
+        entry.runs.push_front(run);
+        entry.runs.truncate(self.history);
+        if let Err(e) = self.save(&schedules) {
+            tracing::error!(schedule = name, "saving schedule history: {e}");
+        }
+    }
+
+    /// Schedules come due by `now`, whose next run is moved past it.
+    fn take_due(&self, now: DateTime<Utc>) -> Vec<String> {
+        let mut schedules = self.schedules.write().expect("schedules");
+        schedules
+            .values_mut()
+            .filter(|entry| entry.next_run.is_some_and(|next| next <= now))
+            .map(|entry| {
+                entry.next_run = entry.schedule.cron.next_after(now);
+                entry.schedule.name.clone()
+            })
+            .collect()
+    }
+
+    fn save(&self, schedules: &BTreeMap<String, Stored>) -> std::io::Result<()> {
+        let stored: Vec<&Stored> = schedules.values().collect();
+        let json = serde_json::to_vec_pretty(&stored).expect("serialize schedules");
+        self.storage.write(&self.name, &json)
+    }
+}
+
+/// What makes `schedule` runnable in `config`, short of the chain.
+fn check(config: &Config, schedule: &Schedule) -> Result<(), String> {
+    let name = &schedule.name;
+    if name.is_empty()
+        || name.len() > MAX_NAME_LEN
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "name must be 1 to {MAX_NAME_LEN} letters, digits, `-` or `_`"
+        ));
+    }
+    for rate in [schedule.fee_rate_sat_vb, schedule.max_fee_rate_sat_vb]
+        .into_iter()
+        .flatten()
+    {
+        crate::sweep::fee_rate(rate).map_err(|_| "fee rates must be positive numbers")?;
+    }
+    match &schedule.action {
+        Action::Sweep { destination, .. } => {
+            crate::sweep::destination(config.network, destination)
+                .map_err(|e| format!("destination: {e}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs the schedules as they come due.
+pub async fn run_forever(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+        let Some(schedules) = &state.schedules else {
+            return;
+        };
+        for name in schedules.take_due(Utc::now()) {
+            match execute(&state, &name, None).await {
+                Some(run) if run.outcome == Outcome::Failed => tracing::warn!(
+                    schedule = name,
+                    "scheduled run failed: {}",
+                    run.message.unwrap_or_default()
+                ),
+                Some(run) => {
+                    tracing::info!(schedule = name, outcome = ?run.outcome, "scheduled run")
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+/// Runs `name` once and records the run, or `None` if there is no such
+/// schedule. `manual` is the request of a run started through the API.
+async fn execute(
+    state: &Arc<AppState>,
+    name: &str,
+    manual: Option<&RequestContext>,
+) -> Option<Run> {
+    let schedules = state.schedules.as_ref()?;
+    let schedule = schedules
+        .schedules
+        .read()
+        .expect("schedules")
+        .get(name)
+        .map(|entry| entry.schedule.clone())?;
+    let started_at = Utc::now();
+    let ctx = context(name, started_at, manual);
+    let mut run = Run {
+        started_at,
+        manual: manual.is_some(),
+        outcome: Outcome::Failed,
+        txid: None,
+        amount_sat: None,
+        fee_rate_sat_vb: None,
+        message: None,
+    };
+    if let Err(e) = perform(state, &ctx, schedule, &mut run).await {
+        run.outcome = Outcome::Failed;
+        run.message = Some(e.to_string());
+    }
+    schedules.record(name, run.clone());
+    Some(run)
+}
+
+/// The request a run of `name` signs as. Schedules are added through the
+/// API, so their runs are not [`RequestContext::internal`].
+fn context(
+    name: &str,
+    started_at: DateTime<Utc>,
+    manual: Option<&RequestContext>,
+) -> RequestContext {
+    RequestContext {
+        request_id: Some(format!("{name}-{}", started_at.timestamp())),
+        caller: Some(CALLER.to_owned()),
+        metadata: Default::default(),
+        bearer: manual.and_then(|request| request.bearer.clone()),
+        internal: false,
+    }
+}
+
+async fn perform(
+    state: &Arc<AppState>,
+    ctx: &RequestContext,
+    schedule: Schedule,
+    run: &mut Run,
+) -> Result<(), Error> {
+    crate::chain::require_synced(state)?;
+    let sat_vb = match schedule.fee_rate_sat_vb {
+        Some(sat_vb) => sat_vb,
+        None => {
+            let chain = state
+                .config()
+                .chain
+                .clone()
+                .expect("checked by require_synced");
+            let estimate = crate::chain::fee_estimate(&chain, schedule.target_blocks)
+                .await
+                .map_err(|e| Error::Unavailable(format!("fee estimate: {e}")))?
+                .max(1.0);
+            if schedule
+                .max_fee_rate_sat_vb
+                .is_some_and(|max| estimate > max)
+            {
+                run.fee_rate_sat_vb = Some(estimate);
+                run.outcome = Outcome::Skipped;
+                run.message = Some(format!(
+                    "fee estimate {estimate} sat/vB is above max_fee_rate_sat_vb"
+                ));
+                return Ok(());
+            }
+            estimate
+        }
+    };
+    run.fee_rate_sat_vb = Some(sat_vb);
+
+    let response = match schedule.action {
+        Action::Sweep {
+            destination,
+            min_value_sat,
+            confirmed_only,
+        } => {
+            let request = SweepRequest {
+                destination,
+                fee_rate_sat_vb: sat_vb,
+                outpoints: None,
+                confirmed_only,
+                min_value_sat,
+                broadcast: schedule.broadcast,
+                metadata: schedule.metadata,
+                priority: Priority::Batch,
+                policy_path: None,
+            };
+            if let Err(e) = crate::sweep::select(state, &request) {
+                run.outcome = Outcome::Skipped;
+                run.message = Some(e.to_string());
+                return Ok(());
+            }
+            crate::sweep::run(state, ctx, request).await?
+        }
+    };
+    run.txid = Some(response.txid);
+    run.amount_sat = Some(response.amount_sat);
+    run.outcome = if response.dry_run {
+        Outcome::DryRun
+    } else if response.broadcast {
+        Outcome::Broadcast
+    } else {
+        Outcome::Signed
+    };
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct ScheduleStatus {
+    #[serde(flatten)]
+    pub schedule: Schedule,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_run: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_run: Option<Run>,
+}
+
+impl ScheduleStatus {
+    fn of(entry: &Stored) -> Self {
+        ScheduleStatus {
+            schedule: entry.schedule.clone(),
+            next_run: entry.next_run,
+            last_run: entry.runs.front().cloned(),
+        }
+    }
+}
+
+fn enabled(state: &AppState) -> Result<&Schedules, Error> {
+    state
+        .schedules
+        .as_ref()
+        .ok_or_else(|| Error::NotFound("schedules are not configured".into()))
+}
+
+pub async fn list(
+    State(state): State<Arc<AppState>>,
+    Query(page): Query<PageQuery>,
+) -> Result<Page<ScheduleStatus>, Error> {
+    let schedules = enabled(&state)?.schedules.read().expect("schedules");
+    paginate(
+        schedules.values().map(ScheduleStatus::of),
+        &page,
+        Order::Asc,
+        |status| Key::new(0, &status.schedule.name),
+    )
+}
+
+/// Criteria for a schedule's runs; unset fields match every run.
+#[derive(Debug, Default, Deserialize)]
+pub struct RunFilter {
+    pub outcome: Option<Outcome>,
+    /// Started at or after.
+    pub from: Option<DateTime<Utc>>,
+    /// Started before.
+    pub to: Option<DateTime<Utc>>,
+}
+
+pub async fn add(
+    State(state): State<Arc<AppState>>,
+    ApiJson(schedule): ApiJson<Schedule>,
+) -> Result<(StatusCode, Json<ScheduleStatus>), Error> {
+    let status = enabled(&state)?.add(&state.config(), schedule)?;
+    Ok((StatusCode::CREATED, Json(status)))
+}
+
+pub async fn remove(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, Error> {
+    let store = enabled(&state)?;
+    let mut schedules = store.schedules.write().expect("schedules");
+    let removed = schedules
+        .remove(&name)
+        .ok_or_else(|| Error::NotFound(format!("schedule {name}")))?;
+    if let Err(e) = store.save(&schedules) {
+        schedules.insert(name, removed);
+        return Err(Error::Internal(format!("saving schedules: {e}")));
+    }
+    tracing::info!(schedule = name, "removed schedule");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Runs a schedule now, outside of its cron expression.
+pub async fn run_now(
+    State(state): State<Arc<AppState>>,
+    ctx: RequestContext,
+    Path(name): Path<String>,
+) -> Result<Json<Run>, Error> {
+    enabled(&state)?;
+    execute(&state, &name, Some(&ctx))
+        .await
+        .map(Json)
+        .ok_or_else(|| Error::NotFound(format!("schedule {name}")))
+}
+
+pub async fn runs(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Query(filter): Query<RunFilter>,
+    Query(page): Query<PageQuery>,
+) -> Result<Page<Run>, Error> {
+    let schedules = enabled(&state)?.schedules.read().expect("schedules");
+    let entry = schedules
+        .get(&name)
+        .ok_or_else(|| Error::NotFound(format!("schedule {name}")))?;
+    let runs = entry
+        .runs
+        .iter()
+        .filter(|run| {
+            filter
+                .outcome
+                .map_or(true, |outcome| outcome == run.outcome)
+                && filter.from.map_or(true, |from| run.started_at >= from)
+                && filter.to.map_or(true, |to| run.started_at < to)
+        })
+        .cloned();
+    paginate(runs, &page, Order::Desc, |run| {
+        let started = run.started_at.timestamp_nanos_opt().unwrap_or_default();
+        Key::new(u64::try_from(started).unwrap_or_default(), "")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+    use crate::{context::BearerToken, test_support};
+
+    const QUORUM: &str = r#"
+[quorum]
+threshold = 2
+
+[quorum.credentials]
+alice = "token-of-alice"
+bob = "token-of-bob"
+"#;
+
+    #[tokio::test]
+    async fn runs_are_held_to_the_quorum() {
+        let state = test_support::state(QUORUM).await;
+        let policy = state.config().quorum.clone().unwrap();
+        let txid = Txid::from_byte_array([1; 32]);
+        let due = context("weekly", Utc::now(), None);
+        assert!(!due.internal);
+        assert!(matches!(
+            crate::quorum::check(&state, &due, txid, &policy),
+            Err(Error::Unauthorized(_))
+        ));
+        let request = RequestContext {
+            bearer: Some(BearerToken("token-of-alice".into())),
+            ..Default::default()
+        };
+        let manual = context("weekly", Utc::now(), Some(&request));
+        assert_eq!(manual.caller.as_deref(), Some(CALLER));
+        assert!(matches!(
+            crate::quorum::check(&state, &manual, txid, &policy),
+            Err(Error::QuorumPending { .. })
+        ));
+    }
+    #[test]
+    fn runs_are_held_to_the_signing_windows() {
+        let policy = crate::config::SigningWindowsConfig {
+            utc_offset: "+00:00".into(),
+            windows: Vec::new(),
+            blackouts: vec![crate::config::Blackout {
+                from: "2000-01-01T00:00:00Z".parse().unwrap(),
+                to: "2100-01-01T00:00:00Z".parse().unwrap(),
+                reason: None,
+            }],
+            override_threshold: None,
+        };
+        let request = RequestContext::default();
+        for manual in [None, Some(&request)] {
+            assert!(matches!(
+                crate::signing_windows::check(&context("weekly", Utc::now(), manual), &policy),
+                Err(Error::OutsideSigningWindow(_))
+            ));
+        }
+    }
+}
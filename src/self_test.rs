@@ -0,0 +1,129 @@
+//! Startup signing self-test.
+//!
+//! Signs a fixed PSBT spending a synthetic output of the wallet's first
+//! receive address and verifies every produced signature against its sighash.
+//! A failure means the key material or the secp backend cannot be trusted, so
+//! the service does not report itself ready.
+
+use bdk_wallet::{miniscript::psbt::PsbtExt, KeychainKind, SignOptions, Wallet};
+use bitcoin::{
+    absolute::LockTime,
+    hashes::{sha256, Hash},
+    secp256k1::{Secp256k1, Verification},
+    sighash::SighashCache,
+    transaction::Version,
+    Amount, OutPoint, Psbt, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+    XOnlyPublicKey,
+};
+
+const PREVOUT_VALUE: Amount = Amount::from_sat(100_000);
+const SPEND_VALUE: Amount = Amount::from_sat(90_000);
+
+pub fn run(wallet: &Wallet) -> Result<(), String> {
+    let script_pubkey = wallet
+        .peek_address(KeychainKind::External, 0)
+        .address
+        .script_pubkey();
+    let mut psbt = test_psbt(script_pubkey.clone())?;
+
+    let sign_options = SignOptions {
+        trust_witness_utxo: true,
+        try_finalize: false,
+        ..Default::default()
+    };
+    wallet
+        .sign(&mut psbt, sign_options)
+        .map_err(|e| format!("signing failed: {e}"))?;
+
+    let verified = verify_signatures(&psbt, &script_pubkey, &Secp256k1::verification_only())?;
+    if verified == 0 {
+        return Err("wallet produced no signatures for its own address".into());
+    }
+    Ok(())
+}
+
+/// Builds a one-input, one-output PSBT spending output 0 of a synthetic
+/// previous transaction that pays to `script_pubkey`. Both `witness_utxo` and
+/// `non_witness_utxo` are set so every script type can be signed.
+fn test_psbt(script_pubkey: ScriptBuf) -> Result<Psbt, String> {
+    let seed = sha256::Hash::hash(b"issue-service signing self-test");
+    let previous = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array(seed.to_byte_array()), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: PREVOUT_VALUE,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    };
+    let spend = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(previous.compute_txid(), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: SPEND_VALUE,
+            script_pubkey,
+        }],
+    };
+
+    let mut psbt = Psbt::from_unsigned_tx(spend).map_err(|e| format!("building PSBT: {e}"))?;
+    psbt.inputs[0].witness_utxo = Some(previous.output[0].clone());
+    psbt.inputs[0].non_witness_utxo = Some(previous);
+    Ok(psbt)
+}
+
+/// Checks every signature on input 0 against the sighash it commits to and
+/// returns how many were verified.
+fn verify_signatures<C: Verification>(
+    psbt: &Psbt,
+    script_pubkey: &ScriptBuf,
+    secp: &Secp256k1<C>,
+) -> Result<usize, String> {
+    let input = &psbt.inputs[0];
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+    let mut verified = 0;
+
+    for (pubkey, sig) in &input.partial_sigs {
+        let msg = psbt
+            .sighash_msg(0, &mut cache, None)
+            .map_err(|e| format!("computing sighash: {e}"))?
+            .to_secp_msg();
+        secp.verify_ecdsa(&msg, &sig.signature, &pubkey.inner)
+            .map_err(|e| format!("invalid ECDSA signature for {pubkey}: {e}"))?;
+        verified += 1;
+    }
+
+    if let Some(sig) = input.tap_key_sig {
+        let output_key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..])
+            .map_err(|e| format!("invalid taproot output key: {e}"))?;
+        let msg = psbt
+            .sighash_msg(0, &mut cache, None)
+            .map_err(|e| format!("computing sighash: {e}"))?
+            .to_secp_msg();
+        secp.verify_schnorr(&sig.signature, &msg, &output_key)
+            .map_err(|e| format!("invalid taproot key-path signature: {e}"))?;
+        verified += 1;
+    }
+
+    for ((pubkey, leaf_hash), sig) in &input.tap_script_sigs {
+        let msg = psbt
+            .sighash_msg(0, &mut cache, Some(*leaf_hash))
+            .map_err(|e| format!("computing sighash: {e}"))?
+            .to_secp_msg();
+        secp.verify_schnorr(&sig.signature, &msg, pubkey)
+            .map_err(|e| format!("invalid taproot script-path signature for {pubkey}: {e}"))?;
+        verified += 1;
+    }
+
+    Ok(verified)
+}
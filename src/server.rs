@@ -0,0 +1,132 @@
+//! Accept loop of the HTTP listeners, in place of `axum::serve`, so the
+//! connection settings in `[http]` can be applied.
+//!
+//! The settings are read for every accepted connection: a reload changes
+//! them for new connections and leaves open ones alone.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{extract::ConnectInfo, serve::Listener, Router};
+use hyper::{body::Incoming, server::conn::http1};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto::Builder,
+};
+use tower::ServiceExt;
+
+use crate::{config::HttpConfig, AppState};
+
+/// Serves `router` on `listener` until the process exits. `peer` turns an
+/// accepted connection into the [`ConnectInfo`] its requests carry.
+pub async fn serve<L, P>(
+    mut listener: L,
+    router: Router,
+    state: Arc<AppState>,
+    peer: fn(&L::Io, L::Addr) -> P,
+) -> std::io::Result<()>
+where
+    L: Listener,
+    P: Clone + Send + Sync + 'static,
+{
+    loop {
+        let (io, addr) = listener.accept().await;
+        let peer = peer(&io, addr);
+        let config = state.config().http.clone();
+        tokio::spawn(serve_connection(io, peer, router.clone(), config));
+    }
+}
+
+/// [`serve`] over TLS. The handshake runs in the connection's own task and
+/// is held to `http.header_read_timeout_secs`, so a slow client only holds
+/// up itself.
+#[cfg(feature = "tls")]
+pub async fn serve_tls(
+    mut listener: tokio::net::TcpListener,
+    acceptor: tokio_rustls::TlsAcceptor,
+    router: Router,
+    state: Arc<AppState>,
+) -> std::io::Result<()> {
+    loop {
+        // Through `Listener`, which retries failed accepts.
+        let (io, addr) = Listener::accept(&mut listener).await;
+        let config = state.config().http.clone();
+        let router = router.clone();
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            let io = match tokio::time::timeout(header_read_timeout(&config), acceptor.accept(io))
+                .await
+            {
+                Ok(Ok(io)) => io,
+                Ok(Err(e)) => {
+                    tracing::debug!("tls handshake with {addr} failed: {e}");
+                    return;
+                }
+                Err(_) => {
+                    tracing::debug!("tls handshake with {addr} timed out");
+                    return;
+                }
+            };
+            serve_connection(io, addr, router, config).await;
+        });
+    }
+}
+
+async fn serve_connection<I, P>(io: I, peer: P, router: Router, config: HttpConfig)
+where
+    I: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+    P: Clone + Send + Sync + 'static,
+{
+    let service = hyper::service::service_fn(move |mut request: hyper::Request<Incoming>| {
+        request.extensions_mut().insert(ConnectInfo(peer.clone()));
+        router.clone().oneshot(request)
+    });
+    let io = TokioIo::new(io);
+    // Upgrades are needed by `/ws`.
+    let result = if config.http2 {
+        auto_builder(&config)
+            .serve_connection_with_upgrades(io, service)
+            .await
+    } else {
+        http1_builder(&config)
+            .serve_connection(io, service)
+            .with_upgrades()
+            .await
+            .map_err(Into::into)
+    };
+    if let Err(e) = result {
+        tracing::debug!("connection closed: {e}");
+    }
+}
+
+fn http1_builder(config: &HttpConfig) -> http1::Builder {
+    let mut builder = http1::Builder::new();
+    builder
+        .timer(TokioTimer::new())
+        .keep_alive(config.keep_alive)
+        .header_read_timeout(header_read_timeout(config));
+    builder
+}
+
+/// Detects HTTP/1.1 or HTTP/2 from the first bytes a client sends.
+fn auto_builder(config: &HttpConfig) -> Builder<TokioExecutor> {
+    let mut builder = Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .keep_alive(config.keep_alive)
+        .header_read_timeout(header_read_timeout(config));
+    builder
+        .http2()
+        .timer(TokioTimer::new())
+        .max_concurrent_streams(config.http2_max_concurrent_streams)
+        .keep_alive_interval(
+            (config.http2_keep_alive_interval_secs > 0)
+                .then(|| Duration::from_secs(config.http2_keep_alive_interval_secs)),
+        )
+        .keep_alive_timeout(Duration::from_secs(config.http2_keep_alive_timeout_secs));
+    builder
+}
+
+fn header_read_timeout(config: &HttpConfig) -> Duration {
+    Duration::from_secs(config.header_read_timeout_secs.max(1))
+}
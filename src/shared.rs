@@ -0,0 +1,215 @@
+//! State shared between signer replicas through Redis, with `[redis]` set
+//! and built with `--features redis`.
+//!
+//! The spend guard's records and the signed response cache are kept under
+//! `redis.key_prefix` instead of per process, so replicas behind one load
+//! balancer refuse each other's conflicting spends and answer each other's
+//! retries. A spend is checked and recorded by one Lua script, making the
+//! check atomic across replicas. Cached answers expire with their Redis
+//! key.
+//!
+//! Calls block for at most `redis.timeout_ms`. When Redis cannot be reached
+//! the spend guard fails closed, refusing to sign with `503 unavailable`,
+//! while the cache only misses.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use bitcoin::{OutPoint, Txid};
+use redis::{Commands, Connection, Script};
+
+use crate::{config::RedisConfig, error::Error, spend_guard::Spend};
+
+/// Refuses the first recorded spend of `KEYS` into another transaction than
+/// `ARGV[1]` unless `ARGV[2]` is set, then records `ARGV[4..]` if `ARGV[3]`
+/// is. Returns the index and record of the conflict, if any.
+const CLAIM: &str = r"
+if ARGV[2] == '0' then
+  for i, key in ipairs(KEYS) do
+    local current = redis.call('GET', key)
+    if current and cjson.decode(current).txid ~= ARGV[1] then
+      return {tostring(i - 1), current}
+    end
+  end
+end
+if ARGV[3] == '1' then
+  for i, key in ipairs(KEYS) do
+    redis.call('SET', key, ARGV[i + 3])
+  end
+end
+return {}
+";
+
+/// Deletes `KEYS[1]`, returning what it held.
+const TAKE: &str = r"
+local current = redis.call('GET', KEYS[1])
+if current then
+  redis.call('DEL', KEYS[1])
+end
+return current
+";
+
+pub struct Shared {
+    client: redis::Client,
+    prefix: String,
+    timeout: Duration,
+    /// Reused between calls, and dropped after a failed one to reconnect.
+    connection: Mutex<Option<Connection>>,
+}
+
+impl Shared {
+    /// Connects to `redis.url` and checks the server answers.
+    pub fn connect(config: &RedisConfig) -> Result<Self, String> {
+        let client = redis::Client::open(config.url.as_str()).map_err(|e| format!("url: {e}"))?;
+        let shared = Shared {
+            client,
+            prefix: config.key_prefix.clone(),
+            timeout: Duration::from_millis(config.timeout_ms.max(1)),
+            connection: Mutex::new(None),
+        };
+        let started = Instant::now();
+        shared.ping()?;
+        tracing::info!(
+            latency_ms = started.elapsed().as_millis() as u64,
+            prefix = %shared.prefix,
+            "connected to redis"
+        );
+        Ok(shared)
+    }
+
+    fn run<T>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> redis::RedisResult<T>,
+    ) -> Result<T, String> {
+        let mut connection = self.connection.lock().expect("redis connection");
+        if connection.is_none() {
+            let opened = self
+                .client
+                .get_connection_with_timeout(self.timeout)
+                .and_then(|opened| {
+                    opened.set_read_timeout(Some(self.timeout))?;
+                    opened.set_write_timeout(Some(self.timeout))?;
+                    Ok(opened)
+                })
+                .map_err(|e| format!("connecting to redis: {e}"))?;
+            *connection = Some(opened);
+        }
+        let result = f(connection.as_mut().expect("connected"));
+        if result.is_err() {
+            *connection = None;
+        }
+        result.map_err(|e| format!("redis: {e}"))
+    }
+
+    /// Checks the server answers.
+    pub fn ping(&self) -> Result<(), String> {
+        self.run(|connection| redis::cmd("PING").query::<String>(connection))
+            .map(|_| ())
+    }
+
+    fn spent_key(&self, outpoint: OutPoint) -> String {
+        format!("{}:spent:{outpoint}", self.prefix)
+    }
+
+    fn signed_key(&self, txid: Txid) -> String {
+        format!("{}:signed:{txid}", self.prefix)
+    }
+
+    /// The spend guard's check and record of `spends`, see
+    /// [`crate::spend_guard::SpendGuard::claim`]. Returns the conflicting
+    /// input and its recorded spend, if any.
+    pub fn claim(
+        &self,
+        spends: &[(usize, Spend)],
+        replace: bool,
+        record: bool,
+    ) -> Result<Option<(usize, Spend)>, Error> {
+        let Some((_, first)) = spends.first() else {
+            return Ok(None);
+        };
+        let script = Script::new(CLAIM);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .arg(first.txid.to_string())
+            .arg(if replace { "1" } else { "0" })
+            .arg(if record { "1" } else { "0" });
+        for (_, spend) in spends {
+            let json = serde_json::to_string(spend).expect("serialize spend");
+            invocation.key(self.spent_key(spend.outpoint)).arg(json);
+        }
+        let conflict: Vec<String> = self
+            .run(|connection| invocation.invoke(connection))
+            .map_err(|e| {
+                tracing::error!("refusing to sign, the spend guard is unreachable: {e}");
+                Error::Unavailable("spend guard: redis is unreachable".into())
+            })?;
+        match conflict.as_slice() {
+            [] => Ok(None),
+            [position, spend] => {
+                let position: usize = position
+                    .parse()
+                    .map_err(|e| Error::Internal(format!("spend guard: {e}")))?;
+                let spend = serde_json::from_str(spend)
+                    .map_err(|e| Error::Internal(format!("spend guard: {e}")))?;
+                Ok(Some((spends[position].0, spend)))
+            }
+            _ => Err(Error::Internal(
+                "spend guard: unexpected redis reply".into(),
+            )),
+        }
+    }
+
+    /// Every spend recorded by any replica.
+    pub fn spends(&self) -> Result<Vec<Spend>, String> {
+        let pattern = format!("{}:spent:*", self.prefix);
+        let values: Vec<Option<String>> = self.run(|connection| {
+            let keys: Vec<String> = connection.scan_match(&pattern)?.collect();
+            if keys.is_empty() {
+                return Ok(Vec::new());
+            }
+            redis::cmd("MGET").arg(keys).query(connection)
+        })?;
+        values
+            .into_iter()
+            .flatten()
+            .map(|json| serde_json::from_str(&json).map_err(|e| format!("stored spend: {e}")))
+            .collect()
+    }
+
+    /// Forgets the spend of `outpoint`, returning it.
+    pub fn remove(&self, outpoint: OutPoint) -> Result<Option<Spend>, String> {
+        let key = self.spent_key(outpoint);
+        let removed: Option<String> =
+            self.run(|connection| Script::new(TAKE).key(&key).invoke(connection))?;
+        removed
+            .map(|json| serde_json::from_str(&json).map_err(|e| format!("stored spend: {e}")))
+            .transpose()
+    }
+
+    /// The cached answer to the PSBT with unsigned txid `txid`.
+    pub fn cached(&self, txid: Txid) -> Result<Option<Vec<u8>>, String> {
+        let key = self.signed_key(txid);
+        self.run(|connection| connection.get(&key))
+    }
+
+    /// Caches `entry`, the answer to the PSBT with unsigned txid `txid`,
+    /// for `ttl`.
+    pub fn cache(&self, txid: Txid, entry: &[u8], ttl: Duration) -> Result<(), String> {
+        let key = self.signed_key(txid);
+        self.run(|connection| connection.set_ex(&key, entry, ttl.as_secs().max(1)))
+    }
+
+    /// Drops every cached answer.
+    pub fn clear_cache(&self) -> Result<(), String> {
+        let pattern = format!("{}:signed:*", self.prefix);
+        self.run(|connection| {
+            let keys: Vec<String> = connection.scan_match(&pattern)?.collect();
+            if keys.is_empty() {
+                return Ok(());
+            }
+            connection.del(keys)
+        })
+    }
+}
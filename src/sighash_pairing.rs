@@ -0,0 +1,260 @@
+//! Signing with sighash types that leave the transaction open.
+//!
+//! `SIGHASH_ALL|ANYONECANPAY` lets others add inputs, as a crowdfund does,
+//! and `SIGHASH_SINGLE`, with or without `ANYONECANPAY`, commits an input
+//! to only the output of the same index, as splices and swap offers use.
+//! Allowing them in `sign_options.allowed_sighashes` signs them for any
+//! input. With `[sighash_pairing]`, the types it lists are instead signed
+//! only for inputs the request pairs with the output they are meant to
+//! commit to, by index, script and amount: `SIGHASH_SINGLE` only with the
+//! output of its own index, which must exist, and `ALL|ANYONECANPAY` with
+//! any output of the transaction. An input asking for one of the types
+//! without a pairing that matches is refused.
+
+use std::collections::BTreeSet;
+
+use bitcoin::{address::NetworkUnchecked, Address, Amount, Network, Psbt, ScriptBuf};
+use serde::Deserialize;
+
+use crate::{
+    config::{Sighash, SighashPairingConfig},
+    error::Error,
+};
+
+/// The output a request means an input to commit to.
+#[derive(Debug, Clone, Deserialize, utoipa::ToSchema)]
+pub struct Pairing {
+    pub input: usize,
+    pub output: usize,
+    /// What the output must pay, or `script_pubkey`.
+    #[serde(default)]
+    pub address: Option<String>,
+    /// Hex script the output must pay.
+    #[serde(default)]
+    pub script_pubkey: Option<String>,
+    pub value_sat: u64,
+}
+
+/// Checks each input of `psbt` asking for one of `policy`'s sighash types
+/// against its pairing, returning the inputs that passed, whose sighash
+/// types then need no other permission.
+pub fn check(
+    psbt: &Psbt,
+    policy: Option<&SighashPairingConfig>,
+    pairings: &[Pairing],
+    network: Network,
+) -> Result<BTreeSet<usize>, Error> {
+    let Some(policy) = policy else {
+        if pairings.is_empty() {
+            return Ok(BTreeSet::new());
+        }
+        return Err(Error::MalformedRequest(
+            "pairings: this needs [sighash_pairing]".into(),
+        ));
+    };
+    let mut declared = BTreeSet::new();
+    for pairing in pairings {
+        if !declared.insert(pairing.input) {
+            return Err(Error::MalformedRequest(format!(
+                "pairings: input {} is paired twice",
+                pairing.input
+            )));
+        }
+    }
+
+    let mut paired = BTreeSet::new();
+    for (index, input) in psbt.inputs.iter().enumerate() {
+        let finalized = input.final_script_sig.is_some() || input.final_script_witness.is_some();
+        let Some(requested) = input.sighash_type else {
+            continue;
+        };
+        let Some(sighash) = policy
+            .sighashes
+            .iter()
+            .find(|sighash| sighash.matches(requested))
+        else {
+            continue;
+        };
+        if finalized {
+            // Signed by someone else already; there is nothing to sign.
+            paired.insert(index);
+            continue;
+        }
+        let refuse = |reason: String| {
+            Error::InvalidTransaction(format!("sighash pairing: input {index}: {reason}"))
+        };
+        let Some(pairing) = pairings.iter().find(|pairing| pairing.input == index) else {
+            return Err(refuse(format!(
+                "asks for sighash type {requested}; pair it with its output in `pairings`"
+            )));
+        };
+        let single = matches!(*sighash, Sighash::Single | Sighash::SingleAnyonecanpay);
+        if single && pairing.output != index {
+            return Err(refuse(format!(
+                "SIGHASH_SINGLE commits to output {index}, not the paired output {}",
+                pairing.output
+            )));
+        }
+        let Some(output) = psbt.unsigned_tx.output.get(pairing.output) else {
+            return Err(refuse(format!(
+                "the transaction has no output {}",
+                pairing.output
+            )));
+        };
+        let script = expected_script(pairing, network)?;
+        if output.script_pubkey != script || output.value != Amount::from_sat(pairing.value_sat) {
+            return Err(refuse(format!(
+                "output {} pays {} sat to {}, not the paired {} sat to {script}",
+                pairing.output,
+                output.value.to_sat(),
+                output.script_pubkey,
+                pairing.value_sat
+            )));
+        }
+        tracing::info!(
+            input = index,
+            output = pairing.output,
+            sighash = %requested,
+            "signing a paired input"
+        );
+        paired.insert(index);
+    }
+    if let Some(unused) = declared.difference(&paired).next() {
+        return Err(Error::MalformedRequest(format!(
+            "pairings: input {unused} does not ask for a sighash type of [sighash_pairing]"
+        )));
+    }
+    Ok(paired)
+}
+
+/// The script `pairing` says its output pays.
+fn expected_script(pairing: &Pairing, network: Network) -> Result<ScriptBuf, Error> {
+    let invalid = |reason: String| {
+        Error::MalformedRequest(format!("pairings: input {}: {reason}", pairing.input))
+    };
+    match (&pairing.address, &pairing.script_pubkey) {
+        (Some(address), None) => address
+            .parse::<Address<NetworkUnchecked>>()
+            .and_then(|address| address.require_network(network))
+            .map(|address| address.script_pubkey())
+            .map_err(|e| invalid(format!("address: {e}"))),
+        (None, Some(script)) => {
+            ScriptBuf::from_hex(script).map_err(|e| invalid(format!("script_pubkey: {e}")))
+        }
+        _ => Err(invalid("give one of address and script_pubkey".into())),
+    }
+}
+
+/// Whether `sighash` commits to an output a pairing can name. `NONE`
+/// commits to none, and `ALL` needs no pairing.
+pub fn pairable(sighash: Sighash) -> bool {
+    matches!(
+        sighash,
+        Sighash::AllAnyonecanpay | Sighash::Single | Sighash::SingleAnyonecanpay
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::sighash::EcdsaSighashType;
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support;
+
+    fn policy() -> SighashPairingConfig {
+        SighashPairingConfig {
+            sighashes: vec![Sighash::Single, Sighash::AllAnyonecanpay],
+        }
+    }
+
+    /// A PSBT of two inputs, the first asking for `sighash`, paying 40,000
+    /// and 50,000 sat to the wallet's addresses 2 and 3.
+    fn psbt(sighash: EcdsaSighashType) -> Psbt {
+        let coins = [0, 1].map(|i| test_support::coin(test_support::script(i), 50_000));
+        let outputs = [
+            (test_support::script(2), 40_000),
+            (test_support::script(3), 50_000),
+        ];
+        let mut psbt = test_support::psbt(&[&coins[0], &coins[1]], &outputs);
+        psbt.inputs[0].sighash_type = Some(sighash.into());
+        psbt
+    }
+
+    fn pairing(output: usize, script: u32, value_sat: u64) -> Pairing {
+        serde_json::from_value(json!({
+            "input": 0,
+            "output": output,
+            "script_pubkey": test_support::script(script).to_hex_string(),
+            "value_sat": value_sat,
+        }))
+        .unwrap()
+    }
+
+    fn refusal(psbt: &Psbt, pairings: &[Pairing]) -> Error {
+        check(psbt, Some(&policy()), pairings, Network::Regtest).unwrap_err()
+    }
+
+    #[test]
+    fn passes_inputs_paired_with_their_output() {
+        let psbt = psbt(EcdsaSighashType::Single);
+        let paired = check(
+            &psbt,
+            Some(&policy()),
+            &[pairing(0, 2, 40_000)],
+            Network::Regtest,
+        );
+        assert_eq!(paired.unwrap(), BTreeSet::from([0]));
+
+        let psbt = self::psbt(EcdsaSighashType::AllPlusAnyoneCanPay);
+        let paired = check(
+            &psbt,
+            Some(&policy()),
+            &[pairing(1, 3, 50_000)],
+            Network::Regtest,
+        );
+        assert_eq!(paired.unwrap(), BTreeSet::from([0]));
+    }
+
+    #[test]
+    fn refuses_inputs_without_a_matching_pairing() {
+        let psbt = psbt(EcdsaSighashType::Single);
+        for pairings in [
+            vec![],
+            vec![pairing(1, 3, 50_000)],
+            vec![pairing(0, 2, 39_000)],
+            vec![pairing(0, 3, 40_000)],
+        ] {
+            let err = refusal(&psbt, &pairings);
+            assert!(
+                matches!(&err, Error::InvalidTransaction(m) if m.starts_with("sighash pairing:")),
+                "{err:?}"
+            );
+        }
+        let psbt = self::psbt(EcdsaSighashType::AllPlusAnyoneCanPay);
+        let err = refusal(&psbt, &[pairing(2, 3, 50_000)]);
+        assert!(matches!(err, Error::InvalidTransaction(m) if m.contains("no output 2")));
+    }
+
+    #[test]
+    fn refuses_malformed_pairings() {
+        let psbt = psbt(EcdsaSighashType::Single);
+        let twice = [pairing(0, 2, 40_000), pairing(0, 2, 40_000)];
+        assert!(matches!(refusal(&psbt, &twice), Error::MalformedRequest(_)));
+
+        let unlisted = self::psbt(EcdsaSighashType::All);
+        let err = refusal(&unlisted, &[pairing(0, 2, 40_000)]);
+        assert!(matches!(err, Error::MalformedRequest(_)), "{err:?}");
+
+        let err = check(&psbt, None, &[pairing(0, 2, 40_000)], Network::Regtest).unwrap_err();
+        assert!(matches!(err, Error::MalformedRequest(m) if m.contains("[sighash_pairing]")));
+    }
+
+    #[test]
+    fn leaves_finalized_inputs_alone() {
+        let mut psbt = psbt(EcdsaSighashType::Single);
+        psbt.inputs[0].final_script_witness = Some(bitcoin::Witness::new());
+        let paired = check(&psbt, Some(&policy()), &[], Network::Regtest);
+        assert_eq!(paired.unwrap(), BTreeSet::from([0]));
+    }
+}
@@ -0,0 +1,264 @@
+//! The options PSBTs are signed with: `[sign_options]`, tightened by the
+//! request.
+//!
+//! A request may switch `trust_witness_utxo`, `try_finalize`,
+//! `sign_with_tap_internal_key` and `sign_tap_leaves` off, and narrow
+//! `allowed_sighashes` to some of those configured, but never turn on or
+//! widen what the config leaves off. Sighashes are checked here against the
+//! configured list rather than by BDK, whose only choice is `SIGHASH_ALL`
+//! or anything.
+
+use std::collections::BTreeSet;
+
+use bdk_wallet::{signer::TapLeavesOptions, SignOptions};
+use bitcoin::{
+    psbt::PsbtSighashType,
+    sighash::{EcdsaSighashType, TapSighashType},
+    Psbt,
+};
+use serde::Deserialize;
+
+use crate::{
+    config::{Sighash, SignOptionsConfig},
+    error::Error,
+};
+
+/// Per-request tightening of `[sign_options]`. Unset fields keep the
+/// configured value.
+#[derive(Debug, Clone, Default, Deserialize, utoipa::ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SignOptionsRequest {
+    pub trust_witness_utxo: Option<bool>,
+    pub allowed_sighashes: Option<Vec<Sighash>>,
+    pub try_finalize: Option<bool>,
+    pub sign_with_tap_internal_key: Option<bool>,
+    pub sign_tap_leaves: Option<bool>,
+}
+
+/// Options resolved for one request.
+#[derive(Debug, Clone)]
+pub struct Resolved {
+    pub options: SignOptions,
+    pub allowed_sighashes: Vec<Sighash>,
+    /// The request's pairings, see [`crate::sighash_pairing`].
+    pub pairings: Vec<crate::sighash_pairing::Pairing>,
+}
+
+impl Sighash {
+    pub fn name(self) -> &'static str {
+        match self {
+            Sighash::All => "all",
+            Sighash::None => "none",
+            Sighash::Single => "single",
+            Sighash::AllAnyonecanpay => "all_anyonecanpay",
+            Sighash::NoneAnyonecanpay => "none_anyonecanpay",
+            Sighash::SingleAnyonecanpay => "single_anyonecanpay",
+        }
+    }
+
+    pub fn matches(self, requested: PsbtSighashType) -> bool {
+        // The taproot default commits to the same data as SIGHASH_ALL.
+        if self == Sighash::All && requested == TapSighashType::Default.into() {
+            return true;
+        }
+        let ecdsa = match self {
+            Sighash::All => EcdsaSighashType::All,
+            Sighash::None => EcdsaSighashType::None,
+            Sighash::Single => EcdsaSighashType::Single,
+            Sighash::AllAnyonecanpay => EcdsaSighashType::AllPlusAnyoneCanPay,
+            Sighash::NoneAnyonecanpay => EcdsaSighashType::NonePlusAnyoneCanPay,
+            Sighash::SingleAnyonecanpay => EcdsaSighashType::SinglePlusAnyoneCanPay,
+        };
+        requested.to_u32() == ecdsa.to_u32()
+    }
+}
+
+/// `config` tightened by `request`, refusing any field that would loosen it.
+pub fn resolve(
+    config: &SignOptionsConfig,
+    request: Option<&SignOptionsRequest>,
+) -> Result<Resolved, Error> {
+    let request = request.cloned().unwrap_or_default();
+    let tighten = |name: &str, configured: bool, requested: Option<bool>| match requested {
+        Some(true) if !configured => Err(Error::MalformedRequest(format!(
+            "sign_options.{name}: may only be tightened, and is off in the config"
+        ))),
+        Some(requested) => Ok(requested),
+        None => Ok(configured),
+    };
+    let trust_witness_utxo = tighten(
+        "trust_witness_utxo",
+        config.trust_witness_utxo,
+        request.trust_witness_utxo,
+    )?;
+    let try_finalize = tighten("try_finalize", config.try_finalize, request.try_finalize)?;
+    let sign_with_tap_internal_key = tighten(
+        "sign_with_tap_internal_key",
+        config.sign_with_tap_internal_key,
+        request.sign_with_tap_internal_key,
+    )?;
+    let sign_tap_leaves = tighten(
+        "sign_tap_leaves",
+        config.sign_tap_leaves,
+        request.sign_tap_leaves,
+    )?;
+    let allowed_sighashes = match request.allowed_sighashes {
+        Some(requested) => {
+            if let Some(sighash) = requested
+                .iter()
+                .find(|sighash| !config.allowed_sighashes.contains(sighash))
+            {
+                return Err(Error::MalformedRequest(format!(
+                    "sign_options.allowed_sighashes: `{}` is not allowed by the config",
+                    sighash.name()
+                )));
+            }
+            requested
+        }
+        None => config.allowed_sighashes.clone(),
+    };
+
+    Ok(Resolved {
+        options: SignOptions {
+            trust_witness_utxo,
+            try_finalize,
+            sign_with_tap_internal_key,
+            tap_leaves_options: if sign_tap_leaves {
+                TapLeavesOptions::All
+            } else {
+                TapLeavesOptions::None
+            },
+            // Checked by `check_sighashes` instead.
+            allow_all_sighashes: true,
+            ..Default::default()
+        },
+        allowed_sighashes,
+        pairings: Vec::new(),
+    })
+}
+
+/// Refuses a PSBT with an input asking for a sighash type not in
+/// `allowed`, leaving out the `paired` inputs. Inputs that ask for none are
+/// signed with `SIGHASH_ALL`, or the taproot default, and so need `all`.
+pub fn check_sighashes(
+    psbt: &Psbt,
+    allowed: &[Sighash],
+    paired: &BTreeSet<usize>,
+) -> Result<(), Error> {
+    for (index, input) in psbt.inputs.iter().enumerate() {
+        if paired.contains(&index) {
+            continue;
+        }
+        let requested = input
+            .sighash_type
+            .unwrap_or_else(|| EcdsaSighashType::All.into());
+        if !allowed.iter().any(|sighash| sighash.matches(requested)) {
+            return Err(Error::InvalidTransaction(format!(
+                "input {index}: sighash type {requested} is not allowed"
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn request(json: serde_json::Value) -> SignOptionsRequest {
+        serde_json::from_value(json).unwrap()
+    }
+
+    /// A PSBT of two inputs asking for `sighashes`.
+    fn psbt(sighashes: [Option<EcdsaSighashType>; 2]) -> Psbt {
+        let coins = [0, 1].map(|i| test_support::coin(test_support::script(i), 50_000));
+        let mut psbt = test_support::psbt(&[&coins[0], &coins[1]], &[]);
+        for (input, sighash) in psbt.inputs.iter_mut().zip(sighashes) {
+            input.sighash_type = sighash.map(Into::into);
+        }
+        psbt
+    }
+
+    #[test]
+    fn keeps_the_strict_defaults() {
+        let resolved = resolve(&SignOptionsConfig::default(), None).unwrap();
+        assert!(!resolved.options.trust_witness_utxo);
+        assert!(resolved.options.try_finalize);
+        assert_eq!(resolved.allowed_sighashes, [Sighash::All]);
+    }
+
+    #[test]
+    fn tightens_the_config() {
+        let config = SignOptionsConfig {
+            trust_witness_utxo: true,
+            allowed_sighashes: vec![Sighash::All, Sighash::Single],
+            ..Default::default()
+        };
+        let resolved = resolve(
+            &config,
+            Some(&request(serde_json::json!({
+                "trust_witness_utxo": false,
+                "try_finalize": false,
+                "sign_tap_leaves": false,
+                "allowed_sighashes": ["single"],
+            }))),
+        )
+        .unwrap();
+        assert!(!resolved.options.trust_witness_utxo);
+        assert!(!resolved.options.try_finalize);
+        assert!(matches!(
+            resolved.options.tap_leaves_options,
+            TapLeavesOptions::None
+        ));
+        assert_eq!(resolved.allowed_sighashes, [Sighash::Single]);
+    }
+
+    #[test]
+    fn refuses_to_loosen_the_config() {
+        let config = SignOptionsConfig::default();
+        for loosened in [
+            serde_json::json!({"trust_witness_utxo": true}),
+            serde_json::json!({"allowed_sighashes": ["all", "none"]}),
+        ] {
+            let err = resolve(&config, Some(&request(loosened.clone()))).unwrap_err();
+            assert!(matches!(err, Error::MalformedRequest(_)), "{loosened}");
+        }
+        let config = SignOptionsConfig {
+            try_finalize: false,
+            ..Default::default()
+        };
+        let loosened = request(serde_json::json!({"try_finalize": true}));
+        assert!(resolve(&config, Some(&loosened)).is_err());
+    }
+
+    #[test]
+    fn refuses_sighashes_not_allowed() {
+        let none = BTreeSet::new();
+        let all = [Sighash::All];
+        check_sighashes(&psbt([None, Some(EcdsaSighashType::All)]), &all, &none).unwrap();
+
+        let single = psbt([None, Some(EcdsaSighashType::Single)]);
+        let err = check_sighashes(&single, &all, &none).unwrap_err();
+        assert!(
+            matches!(&err, Error::InvalidTransaction(m) if m.starts_with("input 1:")),
+            "{err:?}"
+        );
+        check_sighashes(&single, &[Sighash::All, Sighash::Single], &none).unwrap();
+        check_sighashes(&single, &all, &BTreeSet::from([1])).unwrap();
+
+        // An input asking for none is signed with SIGHASH_ALL.
+        let err = check_sighashes(&psbt([None, None]), &[Sighash::Single], &none).unwrap_err();
+        assert!(matches!(err, Error::InvalidTransaction(_)));
+    }
+
+    #[test]
+    fn matches_the_taproot_default_as_all() {
+        let default = TapSighashType::Default.into();
+        assert!(Sighash::All.matches(default));
+        assert!(!Sighash::None.matches(default));
+        let anyonecanpay = EcdsaSighashType::AllPlusAnyoneCanPay.into();
+        assert!(Sighash::AllAnyonecanpay.matches(anyonecanpay));
+        assert!(!Sighash::All.matches(anyonecanpay));
+    }
+}
@@ -0,0 +1,839 @@
+//! Signers holding keys outside the wallet's descriptor.
+//!
+//! A [`Signer`] holds the keys below one master fingerprint and signs PSBT
+//! inputs and messages with them without handing them out. With `[signer]`
+//! set, [`install`] adds one to the wallet next to whatever private keys its
+//! descriptor has, so the descriptor may name the signer's keys by xpub
+//! alone. Every signing path, the startup self-test included, then reaches
+//! the signer through BDK's own signing.
+//!
+//! Four come with the service: [`SoftwareSigner`] over a master xprv in
+//! memory, [`RemoteSigner`] over an HTTP signing service, such as a gateway
+//! in front of an HSM, [`EnclaveSigner`] over a trusted execution
+//! environment on the same host, whose keys never enter this process, and
+//! [`MockSigner`] for tests. Another backend implements the trait and gets a
+//! `[signer]` type in [`build`].
+
+use std::{collections::BTreeSet, fmt::Debug, str::FromStr, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use bdk_wallet::{
+    descriptor::{Descriptor, DescriptorPublicKey},
+    miniscript::{descriptor::SinglePubKey, ForEachKey},
+    signer::{
+        InputSigner, SignerCommon, SignerContext, SignerError, SignerId, SignerOrdering,
+        SignerWrapper,
+    },
+    KeychainKind, SignOptions, Wallet,
+};
+use bitcoin::{
+    bip32::{DerivationPath, Fingerprint, Xpriv, Xpub},
+    hashes::{sha256, Hash},
+    key::Secp256k1,
+    secp256k1::{All, Message, PublicKey},
+    sign_message::{signed_msg_hash, MessageSignature},
+    NetworkKind, PrivateKey, Psbt, XOnlyPublicKey,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    config::{Config, SignerConfig},
+    error::{ApiJson, Error},
+    AppState,
+};
+
+/// Keys below one master fingerprint, used without being handed out.
+pub trait Signer: Send + Sync + Debug {
+    /// Master fingerprint of the keys, as key origins in descriptors and
+    /// PSBTs name it.
+    fn fingerprint(&self) -> Fingerprint;
+
+    /// The public key at `path` below the master key.
+    fn derive_pubkey(&self, path: &DerivationPath) -> Result<PublicKey, String>;
+
+    /// Adds signatures to input `index` of `psbt` for each of this signer's
+    /// keys its derivations name, leaving an input that names none alone.
+    /// `context` is the script context of the keys in the wallet's
+    /// descriptor.
+    fn sign_input(
+        &self,
+        psbt: &mut Psbt,
+        index: usize,
+        context: SignerContext,
+        options: &SignOptions,
+    ) -> Result<(), String>;
+
+    /// Signs `message` with the key at `path`, as Bitcoin Core's
+    /// `signmessage` does.
+    fn sign_message(
+        &self,
+        path: &DerivationPath,
+        message: &str,
+    ) -> Result<MessageSignature, String>;
+
+    /// Evidence, bound to `nonce`, of the code holding the keys, from
+    /// signers running in an enclave. `None` for the others.
+    fn attestation(&self, nonce: &[u8]) -> Option<Result<Quote, String>> {
+        let _ = nonce;
+        None
+    }
+}
+
+/// An enclave's attestation, as its platform produced it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Quote {
+    /// What produced the quote, as the enclave names it, e.g. `sgx_dcap`
+    /// or `sev_snp`.
+    pub format: String,
+    /// Base64 quote, signed by the platform.
+    pub quote: String,
+    /// Hex measurement of the enclave's code, its MRENCLAVE or launch
+    /// digest.
+    pub measurement: String,
+    /// Hex report data the quote carries, starting with the SHA-256 of the
+    /// nonce.
+    pub report_data: String,
+}
+
+#[derive(Deserialize)]
+pub struct SignMessageRequest {
+    /// Path of the key below the signer's master key, e.g.
+    /// `m/84'/0'/0'/0/0`.
+    path: DerivationPath,
+    message: String,
+}
+
+#[derive(Serialize)]
+pub struct SignMessageResponse {
+    pub fingerprint: Fingerprint,
+    /// Hex public key at `path`.
+    pub pubkey: String,
+    /// Base64, as Bitcoin Core's `signmessage` gives it.
+    pub signature: String,
+}
+
+/// Signs a message with a key of the `[signer]`, for attestations a
+/// counterparty checks against the signer's xpub.
+pub async fn sign_message(
+    State(state): State<Arc<AppState>>,
+    ApiJson(request): ApiJson<SignMessageRequest>,
+) -> Result<Json<SignMessageResponse>, Error> {
+    let signer = state
+        .signer
+        .clone()
+        .ok_or_else(|| Error::NotFound("this needs [signer]".into()))?;
+    crate::run_blocking(move || {
+        let SignMessageRequest { path, message } = request;
+        let pubkey = signer.derive_pubkey(&path).map_err(Error::Unavailable)?;
+        let signature = signer
+            .sign_message(&path, &message)
+            .map_err(Error::Unavailable)?;
+        Ok(Json(SignMessageResponse {
+            fingerprint: signer.fingerprint(),
+            pubkey: pubkey.to_string(),
+            signature: signature.to_base64(),
+        }))
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct AttestationQuery {
+    /// Hex, chosen by the verifier.
+    nonce: String,
+}
+
+#[derive(Serialize)]
+pub struct AttestationResponse {
+    pub fingerprint: Fingerprint,
+    pub nonce: String,
+    #[serde(flatten)]
+    pub quote: Quote,
+}
+
+/// The `[signer]`'s attestation of the code holding its keys, for a
+/// verifier to check with the platform's tooling.
+pub async fn attestation(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<AttestationQuery>,
+) -> Result<Json<AttestationResponse>, Error> {
+    let signer = state
+        .signer
+        .clone()
+        .ok_or_else(|| Error::NotFound("this needs [signer]".into()))?;
+    let nonce = hex::decode(&query.nonce)
+        .ok()
+        .filter(|nonce| (16..=64).contains(&nonce.len()))
+        .ok_or_else(|| Error::MalformedRequest("nonce: not 16 to 64 hex bytes".into()))?;
+    crate::run_blocking(move || {
+        let quote = signer
+            .attestation(&nonce)
+            .ok_or_else(|| {
+                Error::NotFound(format!(
+                    "signer {} runs in no enclave",
+                    signer.fingerprint()
+                ))
+            })?
+            .map_err(Error::Unavailable)?;
+        Ok(Json(AttestationResponse {
+            fingerprint: signer.fingerprint(),
+            nonce: hex::encode(nonce),
+            quote,
+        }))
+    })
+    .await
+}
+
+/// The signer `config` describes, if any.
+pub fn build(config: &Config) -> Result<Option<Arc<dyn Signer>>, String> {
+    let network = NetworkKind::from(config.network);
+    let signer: Arc<dyn Signer> = match &config.signer {
+        None => return Ok(None),
+        Some(SignerConfig::Software { xprv }) => {
+            if xprv.network != network {
+                return Err(format!("xprv is not for network `{}`", config.network));
+            }
+            Arc::new(SoftwareSigner::new(*xprv))
+        }
+        Some(SignerConfig::Remote {
+            url,
+            fingerprint,
+            auth_token,
+            timeout_ms,
+        }) => Arc::new(RemoteSigner::new(
+            url,
+            *fingerprint,
+            auth_token.clone(),
+            Duration::from_millis(*timeout_ms),
+        )?),
+        #[cfg(unix)]
+        Some(SignerConfig::Enclave {
+            socket,
+            fingerprint,
+            timeout_ms,
+            measurement,
+        }) => Arc::new(EnclaveSigner::connect(
+            socket,
+            *fingerprint,
+            Duration::from_millis(*timeout_ms),
+            measurement.clone(),
+        )?),
+        #[cfg(not(unix))]
+        Some(SignerConfig::Enclave { .. }) => {
+            return Err("an enclave signer needs Unix sockets".into())
+        }
+        Some(SignerConfig::Mock { seed, refuse }) => {
+            let seed = match seed {
+                Some(seed) => hex::decode(seed).map_err(|e| format!("seed: {e}"))?,
+                None => sha256::Hash::hash(b"issue-service mock signer")
+                    .to_byte_array()
+                    .to_vec(),
+            };
+            Arc::new(MockSigner::new(network, &seed, *refuse)?)
+        }
+    };
+    Ok(Some(signer))
+}
+
+/// Adds `signer` to each keychain of `wallet` whose descriptor has one of
+/// its keys, after checking the signer derives those keys.
+pub fn install(wallet: &mut Wallet, signer: Arc<dyn Signer>) -> Result<(), String> {
+    let fingerprint = signer.fingerprint();
+    let mut installed = false;
+    for keychain in [KeychainKind::External, KeychainKind::Internal] {
+        let descriptor = wallet.public_descriptor(keychain).clone();
+        // A wallet without a change descriptor answers with the receive one.
+        if keychain == KeychainKind::Internal
+            && descriptor == *wallet.public_descriptor(KeychainKind::External)
+        {
+            continue;
+        }
+        let mut keys = Vec::new();
+        descriptor.for_each_key(|key| {
+            if key.master_fingerprint() == fingerprint {
+                keys.push(key.clone());
+            }
+            true
+        });
+        let Some(first) = keys.first() else {
+            continue;
+        };
+        for key in &keys {
+            check_key(signer.as_ref(), key)?;
+        }
+        let context = match &descriptor {
+            Descriptor::Tr(tr) => SignerContext::Tap {
+                is_internal_key: keys.iter().any(|key| key == tr.internal_key()),
+            },
+            _ => crate::key_cache::signer_context(&descriptor, first),
+        };
+        wallet.add_signer(
+            keychain,
+            SignerOrdering::default(),
+            Arc::new(PluginSigner {
+                signer: signer.clone(),
+                context,
+            }),
+        );
+        installed = true;
+    }
+    if !installed {
+        return Err(format!("the descriptor has no key of signer {fingerprint}"));
+    }
+    Ok(())
+}
+
+/// Fails unless `signer` derives `key` at its origin path.
+fn check_key(signer: &dyn Signer, key: &DescriptorPublicKey) -> Result<(), String> {
+    let (origin, expected) = match key {
+        DescriptorPublicKey::XPub(xkey) => (
+            &xkey.origin,
+            SinglePubKey::FullKey(xkey.xkey.public_key.into()),
+        ),
+        DescriptorPublicKey::MultiXPub(xkey) => (
+            &xkey.origin,
+            SinglePubKey::FullKey(xkey.xkey.public_key.into()),
+        ),
+        DescriptorPublicKey::Single(single) => (&single.origin, single.key.clone()),
+    };
+    let path = origin
+        .as_ref()
+        .map_or_else(DerivationPath::master, |(_, path)| path.clone());
+    let derived = signer.derive_pubkey(&path)?;
+    let matches = match expected {
+        SinglePubKey::FullKey(expected) => derived == expected.inner,
+        SinglePubKey::XOnly(expected) => XOnlyPublicKey::from(derived) == expected,
+    };
+    if !matches {
+        return Err(format!(
+            "signer {} derives {derived} at {path}, not the descriptor's key {key}",
+            signer.fingerprint()
+        ));
+    }
+    Ok(())
+}
+
+/// A [`Signer`] as BDK signs with it.
+#[derive(Debug)]
+struct PluginSigner {
+    signer: Arc<dyn Signer>,
+    context: SignerContext,
+}
+
+impl SignerCommon for PluginSigner {
+    fn id(&self, _secp: &Secp256k1<All>) -> SignerId {
+        SignerId::from(self.signer.fingerprint())
+    }
+}
+
+impl InputSigner for PluginSigner {
+    fn sign_input(
+        &self,
+        psbt: &mut Psbt,
+        input_index: usize,
+        sign_options: &SignOptions,
+        _secp: &Secp256k1<All>,
+    ) -> Result<(), SignerError> {
+        let input = psbt
+            .inputs
+            .get(input_index)
+            .ok_or(SignerError::InputIndexOutOfRange)?;
+        if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+            return Ok(());
+        }
+        self.signer
+            .sign_input(psbt, input_index, self.context, sign_options)
+            .map_err(SignerError::External)
+    }
+}
+
+/// The paths below `fingerprint` input `index` of `psbt` derives keys at.
+fn input_paths(psbt: &Psbt, index: usize, fingerprint: Fingerprint) -> BTreeSet<DerivationPath> {
+    let Some(input) = psbt.inputs.get(index) else {
+        return BTreeSet::new();
+    };
+    let ecdsa = input.bip32_derivation.values();
+    let tap = input.tap_key_origins.values().map(|(_, source)| source);
+    ecdsa
+        .chain(tap)
+        .filter(|(origin, _)| *origin == fingerprint)
+        .map(|(_, path)| path.clone())
+        .collect()
+}
+
+/// Copies into input `index` of `psbt` the signatures of keys below
+/// `fingerprint` from `signed`, the base64 PSBT a signer answered with,
+/// ignoring anything else it changed.
+fn take_signatures(
+    psbt: &mut Psbt,
+    index: usize,
+    signed: &str,
+    fingerprint: Fingerprint,
+) -> Result<(), String> {
+    let signed = Psbt::from_str(signed).map_err(|e| format!("invalid psbt: {e}"))?;
+    if signed.unsigned_tx != psbt.unsigned_tx {
+        return Err("answered for another transaction".into());
+    }
+    let signed = signed
+        .inputs
+        .into_iter()
+        .nth(index)
+        .ok_or("answered without the input")?;
+    let input = &mut psbt.inputs[index];
+    let ours = |origin: Option<&(Fingerprint, DerivationPath)>| {
+        origin.is_some_and(|(origin, _)| *origin == fingerprint)
+    };
+    for (key, signature) in signed.partial_sigs {
+        if ours(input.bip32_derivation.get(&key.inner)) {
+            input.partial_sigs.insert(key, signature);
+        }
+    }
+    let tap_origin = |input: &bitcoin::psbt::Input, key: &XOnlyPublicKey| {
+        input
+            .tap_key_origins
+            .get(key)
+            .map(|(_, source)| source.clone())
+    };
+    if let (Some(signature), Some(internal)) = (signed.tap_key_sig, input.tap_internal_key) {
+        if ours(tap_origin(input, &internal).as_ref()) {
+            input.tap_key_sig = Some(signature);
+        }
+    }
+    for ((key, leaf), signature) in signed.tap_script_sigs {
+        if ours(tap_origin(input, &key).as_ref()) {
+            input.tap_script_sigs.insert((key, leaf), signature);
+        }
+    }
+    Ok(())
+}
+
+/// A master xprv held in memory.
+pub struct SoftwareSigner {
+    xprv: Xpriv,
+    secp: Secp256k1<All>,
+}
+
+impl SoftwareSigner {
+    pub fn new(xprv: Xpriv) -> Self {
+        SoftwareSigner {
+            xprv,
+            secp: Secp256k1::new(),
+        }
+    }
+
+    fn child(&self, path: &DerivationPath) -> Result<Xpriv, String> {
+        self.xprv
+            .derive_priv(&self.secp, path)
+            .map_err(|e| format!("deriving {path}: {e}"))
+    }
+}
+
+/// Shows the fingerprint only, never the xprv.
+impl Debug for SoftwareSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SoftwareSigner")
+            .field("fingerprint", &self.fingerprint())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Signer for SoftwareSigner {
+    fn fingerprint(&self) -> Fingerprint {
+        self.xprv.fingerprint(&self.secp)
+    }
+
+    fn derive_pubkey(&self, path: &DerivationPath) -> Result<PublicKey, String> {
+        Ok(Xpub::from_priv(&self.secp, &self.child(path)?).public_key)
+    }
+
+    fn sign_input(
+        &self,
+        psbt: &mut Psbt,
+        index: usize,
+        context: SignerContext,
+        options: &SignOptions,
+    ) -> Result<(), String> {
+        for path in input_paths(psbt, index, self.fingerprint()) {
+            // HD wallets imply compressed keys.
+            let key = PrivateKey {
+                compressed: true,
+                network: self.xprv.network,
+                inner: self.child(&path)?.private_key,
+            };
+            SignerWrapper::new(key, context)
+                .sign_input(psbt, index, options, &self.secp)
+                .map_err(|e| format!("input {index}: {e}"))?;
+        }
+        Ok(())
+    }
+
+    fn sign_message(
+        &self,
+        path: &DerivationPath,
+        message: &str,
+    ) -> Result<MessageSignature, String> {
+        let key = self.child(path)?.private_key;
+        let digest = Message::from_digest(signed_msg_hash(message).to_byte_array());
+        Ok(MessageSignature {
+            signature: self.secp.sign_ecdsa_recoverable(&digest, &key),
+            compressed: true,
+        })
+    }
+}
+
+/// A key made from a fixed seed, for tests of setups with a plugin signer.
+/// With `refuse`, it fails every signing call, standing in for an
+/// unreachable or declining backend.
+#[derive(Debug)]
+pub struct MockSigner {
+    inner: SoftwareSigner,
+    refuse: bool,
+}
+
+impl MockSigner {
+    pub fn new(network: NetworkKind, seed: &[u8], refuse: bool) -> Result<Self, String> {
+        let xprv = Xpriv::new_master(network, seed).map_err(|e| format!("seed: {e}"))?;
+        Ok(MockSigner {
+            inner: SoftwareSigner::new(xprv),
+            refuse,
+        })
+    }
+
+    fn refused(&self) -> Result<(), String> {
+        match self.refuse {
+            true => Err("the mock signer refuses to sign".into()),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Signer for MockSigner {
+    fn fingerprint(&self) -> Fingerprint {
+        self.inner.fingerprint()
+    }
+
+    fn derive_pubkey(&self, path: &DerivationPath) -> Result<PublicKey, String> {
+        self.inner.derive_pubkey(path)
+    }
+
+    fn sign_input(
+        &self,
+        psbt: &mut Psbt,
+        index: usize,
+        context: SignerContext,
+        options: &SignOptions,
+    ) -> Result<(), String> {
+        self.refused()?;
+        tracing::debug!(index, "mock signer signing input");
+        self.inner.sign_input(psbt, index, context, options)
+    }
+
+    fn sign_message(
+        &self,
+        path: &DerivationPath,
+        message: &str,
+    ) -> Result<MessageSignature, String> {
+        self.refused()?;
+        self.inner.sign_message(path, message)
+    }
+}
+
+/// An HTTP signing service, asked with JSON `POST`s below `url`:
+///
+/// - `derive_pubkey` with `{"path"}`, answering `{"pubkey"}` in hex;
+/// - `sign_input` with `{"psbt", "input"}`, answering `{"psbt"}` with the
+///   input signed, both base64;
+/// - `sign_message` with `{"path", "message"}`, answering `{"signature"}`
+///   in base64.
+///
+/// Only the signatures of the service's own keys are taken from a signed
+/// input; anything else it changed is ignored.
+#[derive(Debug)]
+pub struct RemoteSigner {
+    url: String,
+    fingerprint: Fingerprint,
+    auth_token: Option<String>,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct PubkeyReply {
+    pubkey: String,
+}
+
+#[derive(Deserialize)]
+struct PsbtReply {
+    psbt: String,
+}
+
+#[derive(Deserialize)]
+struct SignatureReply {
+    signature: String,
+}
+
+impl RemoteSigner {
+    pub fn new(
+        url: &str,
+        fingerprint: Fingerprint,
+        auth_token: Option<String>,
+        timeout: Duration,
+    ) -> Result<Self, String> {
+        // Each call runs on a runtime of its own, which pooled connections
+        // would outlive.
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .pool_max_idle_per_host(0)
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(RemoteSigner {
+            url: url.trim_end_matches('/').to_owned(),
+            fingerprint,
+            auth_token,
+            client,
+        })
+    }
+
+    /// `POST`s `body` to `endpoint`. BDK signs synchronously, from async
+    /// code as well as blocking threads, so the call is made on a thread of
+    /// its own.
+    fn call<T: DeserializeOwned + Send>(
+        &self,
+        endpoint: &str,
+        body: serde_json::Value,
+    ) -> Result<T, String> {
+        let call = async {
+            let mut request = self
+                .client
+                .post(format!("{}/{endpoint}", self.url))
+                .json(&body);
+            if let Some(token) = &self.auth_token {
+                request = request.bearer_auth(token);
+            }
+            let response = request.send().await.map_err(|e| e.to_string())?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await.unwrap_or_default();
+                return Err(format!("{status}: {}", body.trim()));
+            }
+            response.json::<T>().await.map_err(|e| e.to_string())
+        };
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                        .map_err(|e| e.to_string())?
+                        .block_on(call)
+                })
+                .join()
+                .unwrap_or_else(|_| Err("the call panicked".into()))
+        })
+        .map_err(|e| format!("remote signer {endpoint}: {e}"))
+    }
+}
+
+impl Signer for RemoteSigner {
+    fn fingerprint(&self) -> Fingerprint {
+        self.fingerprint
+    }
+
+    fn derive_pubkey(&self, path: &DerivationPath) -> Result<PublicKey, String> {
+        let reply: PubkeyReply = self.call("derive_pubkey", json!({ "path": path.to_string() }))?;
+        PublicKey::from_str(&reply.pubkey)
+            .map_err(|e| format!("remote signer derive_pubkey: invalid pubkey: {e}"))
+    }
+
+    fn sign_input(
+        &self,
+        psbt: &mut Psbt,
+        index: usize,
+        _context: SignerContext,
+        _options: &SignOptions,
+    ) -> Result<(), String> {
+        if input_paths(psbt, index, self.fingerprint).is_empty() {
+            return Ok(());
+        }
+        let reply: PsbtReply = self.call(
+            "sign_input",
+            json!({ "psbt": psbt.to_string(), "input": index }),
+        )?;
+        take_signatures(psbt, index, &reply.psbt, self.fingerprint)
+            .map_err(|e| format!("remote signer sign_input: {e}"))
+    }
+
+    fn sign_message(
+        &self,
+        path: &DerivationPath,
+        message: &str,
+    ) -> Result<MessageSignature, String> {
+        let reply: SignatureReply = self.call(
+            "sign_message",
+            json!({ "path": path.to_string(), "message": message }),
+        )?;
+        MessageSignature::from_base64(&reply.signature)
+            .map_err(|e| format!("remote signer sign_message: invalid signature: {e}"))
+    }
+}
+
+/// Longest reply read from an enclave.
+#[cfg(unix)]
+const MAX_ENCLAVE_REPLY: u64 = 16 << 20;
+
+/// A signer in a trusted execution environment, such as an SGX enclave or
+/// a SEV guest, whose host process listens on a Unix socket. Each call is a
+/// connection carrying one line of JSON each way: `{"method", "params"}`,
+/// answered by `{"result"}` or `{"error"}`. The methods and their results
+/// are those of [`RemoteSigner`], and `attest` with `{"nonce"}` in hex,
+/// answering a [`Quote`] whose report data starts with the nonce's SHA-256.
+///
+/// With `measurement` set, the enclave must attest to it when the signer is
+/// built and on every attestation asked for afterwards.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct EnclaveSigner {
+    socket: std::path::PathBuf,
+    fingerprint: Fingerprint,
+    timeout: Duration,
+    measurement: Option<String>,
+}
+
+#[cfg(unix)]
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum EnclaveReply<T> {
+    Result(T),
+    Error(String),
+}
+
+#[cfg(unix)]
+impl EnclaveSigner {
+    pub fn connect(
+        socket: &std::path::Path,
+        fingerprint: Fingerprint,
+        timeout: Duration,
+        measurement: Option<String>,
+    ) -> Result<Self, String> {
+        if let Some(measurement) = &measurement {
+            hex::decode(measurement).map_err(|e| format!("measurement: {e}"))?;
+        }
+        let signer = EnclaveSigner {
+            socket: socket.to_owned(),
+            fingerprint,
+            timeout,
+            measurement,
+        };
+        if signer.measurement.is_some() {
+            let quote = signer.attest(&rand::random::<[u8; 32]>())?;
+            tracing::info!(
+                measurement = quote.measurement,
+                format = quote.format,
+                "enclave signer attested"
+            );
+        }
+        Ok(signer)
+    }
+
+    /// Calls `method` with `params` on a connection of its own.
+    fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<T, String> {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let call = || -> Result<T, String> {
+            let mut stream = std::os::unix::net::UnixStream::connect(&self.socket)
+                .map_err(|e| format!("{}: {e}", self.socket.display()))?;
+            stream
+                .set_read_timeout(Some(self.timeout))
+                .and_then(|()| stream.set_write_timeout(Some(self.timeout)))
+                .map_err(|e| e.to_string())?;
+            let mut line = serde_json::to_vec(&json!({ "method": method, "params": params }))
+                .map_err(|e| e.to_string())?;
+            line.push(b'\n');
+            stream.write_all(&line).map_err(|e| e.to_string())?;
+            let mut reply = Vec::new();
+            BufReader::new(stream.take(MAX_ENCLAVE_REPLY))
+                .read_until(b'\n', &mut reply)
+                .map_err(|e| e.to_string())?;
+            match serde_json::from_slice(&reply).map_err(|e| format!("invalid reply: {e}"))? {
+                EnclaveReply::Result(result) => Ok(result),
+                EnclaveReply::Error(e) => Err(e),
+            }
+        };
+        call().map_err(|e| format!("enclave signer {method}: {e}"))
+    }
+
+    /// The enclave's quote for `nonce`, checked to carry it and, when
+    /// configured, the expected measurement. The quote's own signature is
+    /// for the verifier to check.
+    fn attest(&self, nonce: &[u8]) -> Result<Quote, String> {
+        let quote: Quote = self.call("attest", json!({ "nonce": hex::encode(nonce) }))?;
+        let bound = hex::encode(sha256::Hash::hash(nonce).to_byte_array());
+        if !quote.report_data.to_ascii_lowercase().starts_with(&bound) {
+            return Err("enclave signer attest: the report data does not carry the nonce".into());
+        }
+        if let Some(expected) = &self.measurement {
+            if !quote.measurement.eq_ignore_ascii_case(expected) {
+                return Err(format!(
+                    "enclave signer attests to measurement {}, not {expected}",
+                    quote.measurement
+                ));
+            }
+        }
+        Ok(quote)
+    }
+}
+
+#[cfg(unix)]
+impl Signer for EnclaveSigner {
+    fn fingerprint(&self) -> Fingerprint {
+        self.fingerprint
+    }
+
+    fn derive_pubkey(&self, path: &DerivationPath) -> Result<PublicKey, String> {
+        let reply: PubkeyReply = self.call("derive_pubkey", json!({ "path": path.to_string() }))?;
+        PublicKey::from_str(&reply.pubkey)
+            .map_err(|e| format!("enclave signer derive_pubkey: invalid pubkey: {e}"))
+    }
+
+    fn sign_input(
+        &self,
+        psbt: &mut Psbt,
+        index: usize,
+        _context: SignerContext,
+        _options: &SignOptions,
+    ) -> Result<(), String> {
+        if input_paths(psbt, index, self.fingerprint).is_empty() {
+            return Ok(());
+        }
+        let reply: PsbtReply = self.call(
+            "sign_input",
+            json!({ "psbt": psbt.to_string(), "input": index }),
+        )?;
+        take_signatures(psbt, index, &reply.psbt, self.fingerprint)
+            .map_err(|e| format!("enclave signer sign_input: {e}"))
+    }
+
+    fn sign_message(
+        &self,
+        path: &DerivationPath,
+        message: &str,
+    ) -> Result<MessageSignature, String> {
+        let reply: SignatureReply = self.call(
+            "sign_message",
+            json!({ "path": path.to_string(), "message": message }),
+        )?;
+        MessageSignature::from_base64(&reply.signature)
+            .map_err(|e| format!("enclave signer sign_message: invalid signature: {e}"))
+    }
+
+    fn attestation(&self, nonce: &[u8]) -> Option<Result<Quote, String>> {
+        Some(self.attest(nonce))
+    }
+}
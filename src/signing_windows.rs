@@ -0,0 +1,179 @@
+//! Restricting signing to set times.
+//!
+//! With `[signing_windows]` set, signing is open during `windows`, weekly
+//! times in `utc_offset`, and closed during `blackouts`, which take
+//! precedence. A request while signing is closed is refused, or with
+//! `override_threshold` held to a quorum of that many credentials instead
+//! of `quorum.threshold`. Consolidations, which the service runs from its
+//! own config and which only pay the wallet, are not held to the windows;
+//! schedules are added through the API, so their runs are.
+
+use chrono::{DateTime, Datelike, FixedOffset, Utc};
+
+use crate::{config::SigningWindowsConfig, context::RequestContext, error::Error};
+
+/// Refuses `ctx` while signing is closed, or returns the quorum threshold
+/// that overrides it. `None` when signing is open.
+pub fn check(ctx: &RequestContext, policy: &SigningWindowsConfig) -> Result<Option<usize>, Error> {
+    if ctx.internal {
+        return Ok(None);
+    }
+    let Some(reason) = closed(policy, Utc::now())? else {
+        return Ok(None);
+    };
+    match policy.override_threshold {
+        Some(threshold) => {
+            tracing::warn!(
+                threshold,
+                "signing closed ({reason}), holding to an elevated quorum"
+            );
+            Ok(Some(threshold))
+        }
+        None => {
+            tracing::warn!("refusing to sign: {reason}");
+            Err(Error::OutsideSigningWindow(reason))
+        }
+    }
+}
+
+/// Why signing is closed at `now`, if it is.
+fn closed(policy: &SigningWindowsConfig, now: DateTime<Utc>) -> Result<Option<String>, Error> {
+    if let Some(blackout) = policy
+        .blackouts
+        .iter()
+        .find(|blackout| blackout.from <= now && now < blackout.to)
+    {
+        return Ok(Some(blackout.reason.clone().unwrap_or_else(|| {
+            format!("blackout until {}", blackout.to.to_rfc3339())
+        })));
+    }
+    if policy.windows.is_empty() {
+        return Ok(None);
+    }
+    let offset: FixedOffset = policy
+        .utc_offset
+        .parse()
+        .map_err(|e| Error::Internal(format!("signing_windows.utc_offset: {e}")))?;
+    let local = now.with_timezone(&offset);
+    let (day, time) = (local.weekday(), local.time());
+    let open = policy
+        .windows
+        .iter()
+        .any(|window| window.days.contains(&day) && window.start <= time && time < window.end);
+    Ok((!open).then(|| {
+        format!(
+            "{} {} (UTC{}) is outside the signing windows",
+            day,
+            local.format("%H:%M"),
+            policy.utc_offset
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveTime, Weekday};
+
+    use super::*;
+    use crate::config::{Blackout, SigningWindow};
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        rfc3339.parse().unwrap()
+    }
+
+    fn business_hours() -> SigningWindowsConfig {
+        SigningWindowsConfig {
+            utc_offset: "-05:00".into(),
+            windows: vec![SigningWindow {
+                days: vec![Weekday::Mon, Weekday::Tue],
+                start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+            }],
+            blackouts: vec![Blackout {
+                from: at("2026-03-02T15:00:00Z"),
+                to: at("2026-03-02T16:00:00Z"),
+                reason: Some("month-end freeze".into()),
+            }],
+            override_threshold: None,
+        }
+    }
+
+    /// A policy closed now, until 2100.
+    fn closed_now(override_threshold: Option<usize>) -> SigningWindowsConfig {
+        SigningWindowsConfig {
+            blackouts: vec![Blackout {
+                from: at("2000-01-01T00:00:00Z"),
+                to: at("2100-01-01T00:00:00Z"),
+                reason: None,
+            }],
+            override_threshold,
+            ..business_hours()
+        }
+    }
+
+    #[test]
+    fn opens_during_windows_in_their_offset() {
+        let policy = business_hours();
+        // Monday 2026-03-02, 09:00 and 16:59 at -05:00.
+        for open in ["2026-03-02T14:00:00Z", "2026-03-02T21:59:00Z"] {
+            assert_eq!(closed(&policy, at(open)).unwrap(), None, "{open}");
+        }
+        // Before and at the end of the window, Wednesday, and 09:00 UTC.
+        for shut in [
+            "2026-03-02T13:59:00Z",
+            "2026-03-02T22:00:00Z",
+            "2026-03-04T15:00:00Z",
+            "2026-03-03T09:00:00Z",
+        ] {
+            let reason = closed(&policy, at(shut)).unwrap().expect(shut);
+            assert!(reason.contains("outside the signing windows"), "{reason}");
+        }
+    }
+
+    #[test]
+    fn blackouts_close_open_windows() {
+        let policy = business_hours();
+        assert_eq!(
+            closed(&policy, at("2026-03-02T15:30:00Z"))
+                .unwrap()
+                .as_deref(),
+            Some("month-end freeze")
+        );
+        assert_eq!(closed(&policy, at("2026-03-02T16:00:00Z")).unwrap(), None);
+        let always = SigningWindowsConfig {
+            windows: Vec::new(),
+            ..business_hours()
+        };
+        assert_eq!(closed(&always, at("2026-03-04T03:00:00Z")).unwrap(), None);
+        assert!(closed(&always, at("2026-03-02T15:00:00Z"))
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn refuses_or_raises_the_quorum_while_closed() {
+        let client = RequestContext::default();
+        assert!(matches!(
+            check(&client, &closed_now(None)),
+            Err(Error::OutsideSigningWindow(_))
+        ));
+        assert_eq!(check(&client, &closed_now(Some(3))).unwrap(), Some(3));
+        let internal = RequestContext {
+            internal: true,
+            ..Default::default()
+        };
+        assert_eq!(check(&internal, &closed_now(None)).unwrap(), None);
+    }
+
+    #[test]
+    fn refuses_a_bad_offset() {
+        let policy = SigningWindowsConfig {
+            utc_offset: "EST".into(),
+            ..business_hours()
+        };
+        assert!(matches!(
+            closed(&policy, at("2026-03-02T14:00:00Z")),
+            Err(Error::Internal(_))
+        ));
+    }
+}
@@ -0,0 +1,418 @@
+//! BIP-352 silent payments: a static receiving address, block scanning to
+//! find the outputs paid to it, and signing of their spends.
+//!
+//! The scan and spend keys are derived from the descriptor's xprv at
+//! `352'/<coin>'/0'/1'/0` and `352'/<coin>'/0'/0'/0`, `coin` being `0` on
+//! mainnet and `1` elsewhere. With a master xprv this is BIP-352's own
+//! derivation. Found outputs live in memory only: every start rescans from
+//! `birthday_height`, and outputs found in a block later reorganized away
+//! are kept.
+
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bdk_esplora::esplora_client;
+use bdk_wallet::{
+    descriptor::{Descriptor, DescriptorPublicKey},
+    keys::DescriptorSecretKey,
+};
+use bitcoin::{
+    bip32::DerivationPath,
+    hashes::Hash,
+    key::{Keypair, Secp256k1},
+    secp256k1::{Message, PublicKey, Scalar, SecretKey, XOnlyPublicKey},
+    sighash::{Prevouts, SighashCache},
+    taproot, Network, OutPoint, Psbt, TapSighashType, TxOut, Witness,
+};
+use serde::Serialize;
+use silentpayments::{
+    receiving::{Label, Receiver},
+    utils::receiving::{calculate_ecdh_shared_secret, calculate_tweak_data, get_pubkey_from_input},
+    SpVersion,
+};
+
+use crate::{
+    config::{ChainConfig, Config, SilentPaymentsConfig},
+    error::Error,
+    AppState,
+};
+
+/// Transactions per page of Esplora's `/block/:hash/txs/:start`.
+const BLOCK_TXS_PAGE: usize = 25;
+
+pub struct SilentPayments {
+    receiver: Receiver,
+    scan_key: SecretKey,
+    spend_key: SecretKey,
+    scan: Mutex<ScanState>,
+}
+
+struct ScanState {
+    /// Next block height to scan.
+    next_height: u32,
+    outputs: BTreeMap<OutPoint, FoundOutput>,
+}
+
+struct FoundOutput {
+    txout: TxOut,
+    /// Added to the spend key to get the output's private key.
+    tweak: Scalar,
+    height: u32,
+    spent: bool,
+}
+
+#[derive(Serialize)]
+pub struct OutputsResponse {
+    /// Highest block scanned so far, if any.
+    pub scanned_height: Option<u32>,
+    pub outputs: Vec<OutputInfo>,
+}
+
+#[derive(Serialize)]
+pub struct OutputInfo {
+    pub outpoint: String,
+    pub value_sat: u64,
+    pub height: u32,
+    pub spent: bool,
+}
+
+impl SilentPayments {
+    pub fn new(config: &Config, sp: &SilentPaymentsConfig) -> Result<Self, String> {
+        let secp = Secp256k1::new();
+        let (_, keys) = Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, &config.xprv)
+            .map_err(|e| format!("descriptor: {e}"))?;
+        let xprv = keys
+            .values()
+            .find_map(|key| match key {
+                DescriptorSecretKey::XPrv(xkey) => Some(xkey.xkey),
+                _ => None,
+            })
+            .ok_or("the descriptor has no xprv to derive silent payment keys from")?;
+        let coin = if config.network == Network::Bitcoin {
+            0
+        } else {
+            1
+        };
+        let derive = |branch: u32| {
+            let path = DerivationPath::from_str(&format!("m/352'/{coin}'/0'/{branch}'/0"))
+                .expect("valid derivation path");
+            xprv.derive_priv(&secp, &path)
+                .map(|key| key.private_key)
+                .map_err(|e| format!("deriving silent payment keys: {e}"))
+        };
+        let scan_key = derive(1)?;
+        let spend_key = derive(0)?;
+
+        let network = match config.network {
+            Network::Bitcoin => silentpayments::Network::Mainnet,
+            Network::Regtest => silentpayments::Network::Regtest,
+            _ => silentpayments::Network::Testnet,
+        };
+        let receiver = Receiver::new(
+            SpVersion::ZERO,
+            scan_key.public_key(&secp),
+            spend_key.public_key(&secp),
+            Label::new(scan_key, 0),
+            network,
+        )
+        .map_err(|e| format!("silent payment receiver: {e}"))?;
+
+        Ok(SilentPayments {
+            receiver,
+            scan_key,
+            spend_key,
+            scan: Mutex::new(ScanState {
+                next_height: sp.birthday_height,
+                outputs: BTreeMap::new(),
+            }),
+        })
+    }
+
+    /// The `sp1...` (`tsp1...`, `sprt1...`) address senders pay to.
+    pub fn address(&self) -> String {
+        self.receiver.receiving_code().to_string()
+    }
+
+    pub fn outputs(&self) -> OutputsResponse {
+        let scan = self.scan.lock().expect("silent payment scan state");
+        OutputsResponse {
+            scanned_height: scan.next_height.checked_sub(1),
+            outputs: scan
+                .outputs
+                .iter()
+                .map(|(outpoint, output)| OutputInfo {
+                    outpoint: outpoint.to_string(),
+                    value_sat: output.txout.value.to_sat(),
+                    height: output.height,
+                    spent: output.spent,
+                })
+                .collect(),
+        }
+    }
+
+    /// Adds a finalized key-path signature to every input spending a found
+    /// output. Other inputs are left alone.
+    pub fn sign(&self, psbt: &mut Psbt) -> Result<(), Error> {
+        let ours: Vec<(usize, Scalar)> = {
+            let scan = self.scan.lock().expect("silent payment scan state");
+            psbt.unsigned_tx
+                .input
+                .iter()
+                .zip(&psbt.inputs)
+                .enumerate()
+                .filter_map(|(index, (txin, input))| {
+                    let output = scan.outputs.get(&txin.previous_output)?;
+                    (input.witness_utxo.as_ref() == Some(&output.txout))
+                        .then_some((index, output.tweak))
+                })
+                .collect()
+        };
+        if ours.is_empty() {
+            return Ok(());
+        }
+
+        // Taproot sighashes commit to every input's prevout.
+        let prevouts = (0..psbt.inputs.len())
+            .map(|index| psbt.spend_utxo(index).cloned())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| {
+                Error::InvalidTransaction(format!("silent payment spend needs every prevout: {e}"))
+            })?;
+        let secp = Secp256k1::new();
+        let mut cache = SighashCache::new(&psbt.unsigned_tx);
+        let mut signatures = Vec::with_capacity(ours.len());
+        for (index, tweak) in ours {
+            let sighash_type = psbt.inputs[index]
+                .sighash_type
+                .map(|ty| ty.taproot_hash_ty())
+                .transpose()
+                .map_err(|e| Error::InvalidTransaction(format!("input {index}: {e}")))?
+                .unwrap_or(TapSighashType::Default);
+            let sighash = cache
+                .taproot_key_spend_signature_hash(index, &Prevouts::All(&prevouts), sighash_type)
+                .map_err(|e| Error::InvalidTransaction(format!("input {index}: {e}")))?;
+            let key = self
+                .spend_key
+                .add_tweak(&tweak)
+                .map_err(|e| Error::Internal(format!("silent payment key: {e}")))?;
+            // Silent payment outputs are bare output keys, without the
+            // BIP-341 taptweak.
+            let keypair = Keypair::from_secret_key(&secp, &key);
+            let signature =
+                secp.sign_schnorr_with_aux_rand(&Message::from(sighash), &keypair, &rand::random());
+            signatures.push((
+                index,
+                taproot::Signature {
+                    signature,
+                    sighash_type,
+                },
+            ));
+        }
+        for (index, signature) in signatures {
+            let input = &mut psbt.inputs[index];
+            input.final_script_witness = Some(Witness::p2tr_key_spend(&signature));
+            input.tap_key_sig = None;
+        }
+        Ok(())
+    }
+
+    /// Records the outputs of `tx` paid to us and marks found outputs it
+    /// spends.
+    fn scan_tx(&self, scan: &mut ScanState, tx: &esplora_client::Tx, height: u32) {
+        for vin in &tx.vin {
+            let outpoint = OutPoint::new(vin.txid, vin.vout);
+            if let Some(output) = scan.outputs.get_mut(&outpoint) {
+                output.spent = true;
+            }
+        }
+
+        let candidates: Vec<(usize, XOnlyPublicKey)> = tx
+            .vout
+            .iter()
+            .enumerate()
+            .filter(|(_, out)| out.scriptpubkey.is_p2tr())
+            .filter_map(|(vout, out)| {
+                XOnlyPublicKey::from_slice(&out.scriptpubkey.as_bytes()[2..])
+                    .ok()
+                    .map(|key| (vout, key))
+            })
+            .collect();
+        if candidates.is_empty() || tx.vin.iter().any(|vin| vin.is_coinbase) {
+            return;
+        }
+        // BIP-352 skips transactions spending unknown segwit versions.
+        let prevouts: Option<Vec<_>> = tx.vin.iter().map(|vin| vin.prevout.as_ref()).collect();
+        let Some(prevouts) = prevouts else {
+            return;
+        };
+        if prevouts.iter().any(|prevout| {
+            prevout
+                .scriptpubkey
+                .witness_version()
+                .is_some_and(|version| version.to_num() > 1)
+        }) {
+            return;
+        }
+
+        let input_keys: Vec<PublicKey> = tx
+            .vin
+            .iter()
+            .zip(&prevouts)
+            .filter_map(|(vin, prevout)| {
+                get_pubkey_from_input(
+                    vin.scriptsig.as_bytes(),
+                    &vin.witness,
+                    prevout.scriptpubkey.as_bytes(),
+                )
+                .ok()
+                .flatten()
+            })
+            .collect();
+        if input_keys.is_empty() {
+            return;
+        }
+        let outpoints: Vec<_> = tx
+            .vin
+            .iter()
+            .map(|vin| {
+                silentpayments::utils::OutPoint::from_txid_bytes_and_vout(
+                    vin.txid.to_byte_array(),
+                    vin.vout,
+                )
+            })
+            .collect();
+        let Ok(tweak_data) =
+            calculate_tweak_data(&input_keys.iter().collect::<Vec<_>>(), &outpoints)
+        else {
+            return;
+        };
+        let shared_secret = calculate_ecdh_shared_secret(&tweak_data, &self.scan_key);
+        let keys: Vec<XOnlyPublicKey> = candidates.iter().map(|(_, key)| *key).collect();
+        let found = match self.receiver.scan_transaction(&shared_secret, &keys) {
+            Ok(found) => found,
+            Err(e) => {
+                tracing::warn!(txid = %tx.txid, "silent payment scan failed: {e}");
+                return;
+            }
+        };
+
+        for (key, tweak) in found.into_values().flatten() {
+            let Some(&(vout, _)) = candidates.iter().find(|(_, candidate)| *candidate == key)
+            else {
+                continue;
+            };
+            let out = &tx.vout[vout];
+            let outpoint = OutPoint::new(tx.txid, vout as u32);
+            tracing::info!(%outpoint, value_sat = out.value, height, "silent payment received");
+            scan.outputs.insert(
+                outpoint,
+                FoundOutput {
+                    txout: TxOut {
+                        value: bitcoin::Amount::from_sat(out.value),
+                        script_pubkey: out.scriptpubkey.clone(),
+                    },
+                    tweak,
+                    height,
+                    spent: false,
+                },
+            );
+        }
+    }
+}
+
+/// Scans every new block from the Esplora server for payments, from the
+/// configured birthday height on.
+pub async fn scan_forever(state: Arc<AppState>, chain: ChainConfig) {
+    let Some(sp) = state.silent_payments.as_ref() else {
+        return;
+    };
+    let client = match esplora_client::Builder::new(&chain.esplora_url).build_async() {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!(
+                url = chain.esplora_url,
+                "silent payment scanning disabled: {e}"
+            );
+            return;
+        }
+    };
+    let http = reqwest::Client::new();
+    let base = chain.esplora_url.trim_end_matches('/');
+    let mut interval = tokio::time::interval(Duration::from_secs(chain.sync_interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+        if let Err(e) = scan_new_blocks(sp, &client, &http, base).await {
+            tracing::warn!(url = chain.esplora_url, "silent payment scan failed: {e}");
+        }
+    }
+}
+
+async fn scan_new_blocks(
+    sp: &SilentPayments,
+    client: &esplora_client::AsyncClient,
+    http: &reqwest::Client,
+    base: &str,
+) -> Result<(), String> {
+    let tip = client.get_height().await.map_err(|e| e.to_string())?;
+    loop {
+        let height = sp
+            .scan
+            .lock()
+            .expect("silent payment scan state")
+            .next_height;
+        if height > tip {
+            return Ok(());
+        }
+        let hash = client
+            .get_block_hash(height)
+            .await
+            .map_err(|e| e.to_string())?;
+        let txs = block_txs(http, base, &hash).await?;
+
+        let mut scan = sp.scan.lock().expect("silent payment scan state");
+        for tx in &txs {
+            sp.scan_tx(&mut scan, tx, height);
+        }
+        scan.next_height = height + 1;
+        tracing::debug!(height, "silent payment scan advanced");
+    }
+}
+
+/// Every transaction of a block, with prevouts, which the raw block lacks.
+async fn block_txs(
+    http: &reqwest::Client,
+    base: &str,
+    hash: &bitcoin::BlockHash,
+) -> Result<Vec<esplora_client::Tx>, String> {
+    #[derive(serde::Deserialize)]
+    struct BlockInfo {
+        tx_count: usize,
+    }
+
+    let get = |url: String| async move {
+        http.get(url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|e| e.to_string())
+    };
+    let info: BlockInfo = get(format!("{base}/block/{hash}"))
+        .await?
+        .json()
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut txs = Vec::with_capacity(info.tx_count);
+    for start in (0..info.tx_count).step_by(BLOCK_TXS_PAGE) {
+        let page: Vec<esplora_client::Tx> = get(format!("{base}/block/{hash}/txs/{start}"))
+            .await?
+            .json()
+            .await
+            .map_err(|e| e.to_string())?;
+        txs.extend(page);
+    }
+    Ok(txs)
+}
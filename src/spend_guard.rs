@@ -0,0 +1,386 @@
+//! Refusing to sign a second, conflicting spend of the same coin.
+//!
+//! With `[spend_guard]` set, every input a signing request added signatures
+//! to is recorded in `spend_guard.path` with the txid it was signed into. A
+//! later request signing one of those coins into a different transaction is
+//! refused, unless it sets `replace` for a deliberate replacement such as an
+//! RBF fee bump, which then becomes the recorded spend. Signing the same
+//! transaction again is not a conflict, and dry runs are checked but not
+//! recorded.
+//!
+//! Records are kept until removed with `DELETE
+//! /admin/spent_outpoints/{outpoint}`, for a signed transaction that was
+//! abandoned and whose coins are to be spent otherwise. With `[redis]` they
+//! are kept in Redis instead, shared by every replica, see
+//! [`crate::shared`].
+
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use bitcoin::{OutPoint, Psbt, Txid};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::SpendGuardConfig, error::Error, storage::Storage, AppState};
+
+pub struct SpendGuard {
+    /// Unused with `shared`.
+    storage: Arc<dyn Storage>,
+    name: String,
+    spends: RwLock<BTreeMap<OutPoint, Spend>>,
+    #[cfg(feature = "redis")]
+    shared: Option<Arc<crate::shared::Shared>>,
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Spend {
+    pub outpoint: OutPoint,
+    /// Transaction the coin was signed into.
+    pub txid: Txid,
+    pub signed_at: DateTime<Utc>,
+}
+
+impl SpendGuard {
+    pub fn new(storage: Arc<dyn Storage>, guard: &SpendGuardConfig) -> Result<Self, String> {
+        let name = guard
+            .path
+            .as_deref()
+            .map(crate::storage::name)
+            .ok_or("path: required unless [redis] is set")?;
+        let stored = storage
+            .read(&name)
+            .map_err(|e| e.to_string())
+            .and_then(|stored| {
+                stored
+                    .map(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+                    .transpose()
+            })
+            .map_err(|e| format!("{name}: {e}"))?;
+        let store = SpendGuard {
+            storage,
+            name,
+            spends: Default::default(),
+            #[cfg(feature = "redis")]
+            shared: None,
+        };
+        if let Some(stored) = stored {
+            let stored: Vec<Spend> = stored;
+            let mut spends = store.spends.write().expect("spends");
+            spends.extend(stored.into_iter().map(|spend| (spend.outpoint, spend)));
+            tracing::info!(count = spends.len(), "loaded signed spends");
+        }
+        Ok(store)
+    }
+
+    /// A guard keeping its records in Redis.
+    #[cfg(feature = "redis")]
+    pub fn shared(shared: Arc<crate::shared::Shared>) -> Self {
+        SpendGuard {
+            storage: Arc::new(crate::storage::Memory::default()),
+            name: String::new(),
+            spends: Default::default(),
+            shared: Some(shared),
+        }
+    }
+
+    /// Checks the backend the records are kept in can be read.
+    pub fn probe(&self) -> Result<(), String> {
+        #[cfg(feature = "redis")]
+        if let Some(shared) = &self.shared {
+            return shared.ping();
+        }
+        self.storage
+            .read(&self.name)
+            .map(|_| ())
+            .map_err(|e| format!("{}: {e}", self.name))
+    }
+
+    /// Checks that the inputs `signed` of `psbt` spend no coin already
+    /// signed into another transaction, unless `replace`, and with `record`
+    /// records them as spent by `psbt`.
+    pub fn claim(
+        &self,
+        psbt: &Psbt,
+        signed: &[usize],
+        replace: bool,
+        record: bool,
+    ) -> Result<(), Error> {
+        let txid = psbt.unsigned_tx.compute_txid();
+        let signed_at = Utc::now();
+        let claimed: Vec<(usize, Spend)> = signed
+            .iter()
+            .map(|&index| {
+                let spend = Spend {
+                    outpoint: psbt.unsigned_tx.input[index].previous_output,
+                    txid,
+                    signed_at,
+                };
+                (index, spend)
+            })
+            .collect();
+        #[cfg(feature = "redis")]
+        if let Some(shared) = &self.shared {
+            return match shared.claim(&claimed, replace, record)? {
+                Some((index, spend)) => Err(conflict(index, &spend)),
+                None => Ok(()),
+            };
+        }
+        let mut spends = self.spends.write().expect("spends");
+        if !replace {
+            for (index, claim) in &claimed {
+                if let Some(spend) = spends
+                    .get(&claim.outpoint)
+                    .filter(|spend| spend.txid != txid)
+                {
+                    return Err(conflict(*index, spend));
+                }
+            }
+        }
+        if !record {
+            return Ok(());
+        }
+        let previous: Vec<_> = claimed
+            .into_iter()
+            .map(|(_, spend)| (spend.outpoint, spends.insert(spend.outpoint, spend)))
+            .collect();
+        if let Err(e) = self.save(&spends) {
+            for (outpoint, spend) in previous {
+                match spend {
+                    Some(spend) => spends.insert(outpoint, spend),
+                    None => spends.remove(&outpoint),
+                };
+            }
+            tracing::error!(%txid, "withholding signatures, saving signed spends failed: {e}");
+            return Err(Error::Internal("saving signed spends failed".into()));
+        }
+        Ok(())
+    }
+
+    /// Every recorded spend.
+    pub fn spends(&self) -> Result<Vec<Spend>, String> {
+        #[cfg(feature = "redis")]
+        if let Some(shared) = &self.shared {
+            return shared.spends();
+        }
+        Ok(self
+            .spends
+            .read()
+            .expect("spends")
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    /// Replaces every record with `spends`, for a replication standby. In
+    /// Redis the records are shared already and left alone.
+    pub fn replace(&self, spends: Vec<Spend>) -> std::io::Result<()> {
+        #[cfg(feature = "redis")]
+        if self.shared.is_some() {
+            return Ok(());
+        }
+        let next: BTreeMap<_, _> = spends
+            .into_iter()
+            .map(|spend| (spend.outpoint, spend))
+            .collect();
+        let mut current = self.spends.write().expect("spends");
+        if *current != next {
+            self.save(&next)?;
+            *current = next;
+        }
+        Ok(())
+    }
+
+    fn save(&self, spends: &BTreeMap<OutPoint, Spend>) -> std::io::Result<()> {
+        let list: Vec<&Spend> = spends.values().collect();
+        self.storage
+            .write(&self.name, &serde_json::to_vec_pretty(&list)?)
+    }
+}
+
+fn conflict(index: usize, spend: &Spend) -> Error {
+    Error::InvalidTransaction(format!(
+        "double spend: input {index} spends {}, already signed into {} at {}; set replace to \
+         sign a replacement",
+        spend.outpoint,
+        spend.txid,
+        spend
+            .signed_at
+            .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+    ))
+}
+
+/// Signatures and final scripts of each input of `psbt`, to tell which
+/// inputs signing added to.
+pub fn signatures(psbt: &Psbt) -> Vec<usize> {
+    psbt.inputs
+        .iter()
+        .map(|input| {
+            input.partial_sigs.len()
+                + input.tap_script_sigs.len()
+                + usize::from(input.tap_key_sig.is_some())
+                + usize::from(input.final_script_sig.is_some())
+                + usize::from(input.final_script_witness.is_some())
+        })
+        .collect()
+}
+
+/// Indices of the inputs of `psbt` with more signatures than `before`.
+pub fn signed_inputs(before: &[usize], psbt: &Psbt) -> Vec<usize> {
+    signatures(psbt)
+        .into_iter()
+        .zip(before)
+        .enumerate()
+        .filter(|(_, (after, before))| after > before)
+        .map(|(index, _)| index)
+        .collect()
+}
+
+fn enabled(state: &AppState) -> Result<&SpendGuard, Error> {
+    state
+        .spend_guard
+        .as_ref()
+        .ok_or_else(|| Error::NotFound("spend_guard is not configured".into()))
+}
+
+pub async fn list(State(state): State<Arc<AppState>>) -> Result<Json<Vec<Spend>>, Error> {
+    let spends = enabled(&state)?.spends().map_err(Error::Unavailable)?;
+    Ok(Json(spends))
+}
+
+pub async fn remove(
+    State(state): State<Arc<AppState>>,
+    Path(outpoint): Path<String>,
+) -> Result<StatusCode, Error> {
+    let guard = enabled(&state)?;
+    let outpoint = OutPoint::from_str(&outpoint)
+        .map_err(|e| Error::MalformedRequest(format!("outpoint: {e}")))?;
+    #[cfg(feature = "redis")]
+    if let Some(shared) = &guard.shared {
+        let removed = shared
+            .remove(outpoint)
+            .map_err(Error::Unavailable)?
+            .ok_or_else(|| Error::NotFound(format!("signed spend of {outpoint}")))?;
+        tracing::info!(%outpoint, txid = %removed.txid, "forgot signed spend");
+        return Ok(StatusCode::NO_CONTENT);
+    }
+    let mut spends = guard.spends.write().expect("spends");
+    let removed = spends
+        .remove(&outpoint)
+        .ok_or_else(|| Error::NotFound(format!("signed spend of {outpoint}")))?;
+    if let Err(e) = guard.save(&spends) {
+        spends.insert(outpoint, removed);
+        return Err(Error::Internal(format!("saving signed spends: {e}")));
+    }
+    tracing::info!(%outpoint, txid = %removed.txid, "forgot signed spend");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{storage::Memory, test_support};
+
+    fn config() -> SpendGuardConfig {
+        SpendGuardConfig {
+            path: Some("spent_outpoints.json".into()),
+        }
+    }
+
+    /// A PSBT spending the coin of script `0` to script `to`.
+    fn spend(to: u32) -> Psbt {
+        let coin = test_support::coin(test_support::script(0), 100_000);
+        test_support::psbt(&[&coin], &[(test_support::script(to), 99_000)])
+    }
+
+    /// Refuses every write.
+    struct ReadOnly;
+
+    impl Storage for ReadOnly {
+        fn read(&self, _: &str) -> std::io::Result<Option<Vec<u8>>> {
+            Ok(None)
+        }
+
+        fn write(&self, _: &str, _: &[u8]) -> std::io::Result<()> {
+            Err(std::io::Error::other("read-only"))
+        }
+
+        fn remove(&self, _: &str) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn names(&self, _: &str) -> std::io::Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+
+        fn append(&self, _: &str, _: &[String]) -> std::io::Result<()> {
+            Err(std::io::Error::other("read-only"))
+        }
+
+        fn lines(&self, _: &str, _: usize, _: usize) -> std::io::Result<(Vec<String>, usize)> {
+            Ok((Vec::new(), 0))
+        }
+    }
+
+    #[test]
+    fn refuses_conflicting_spends() {
+        let guard = SpendGuard::new(Arc::new(Memory::default()), &config()).unwrap();
+        guard.claim(&spend(1), &[0], false, true).unwrap();
+        guard.claim(&spend(1), &[0], false, true).unwrap();
+
+        let err = guard.claim(&spend(2), &[0], false, false).unwrap_err();
+        assert!(
+            matches!(&err, Error::InvalidTransaction(m) if m.starts_with("double spend: input 0")),
+            "{err:?}"
+        );
+        // Inputs not signed are not claimed.
+        guard.claim(&spend(2), &[], false, true).unwrap();
+
+        guard.claim(&spend(2), &[0], true, true).unwrap();
+        assert!(guard.claim(&spend(1), &[0], false, true).is_err());
+        let spends = guard.spends().unwrap();
+        assert_eq!(spends.len(), 1);
+        assert_eq!(spends[0].txid, spend(2).unsigned_tx.compute_txid());
+    }
+
+    #[test]
+    fn checks_without_recording_unless_asked() {
+        let guard = SpendGuard::new(Arc::new(Memory::default()), &config()).unwrap();
+        guard.claim(&spend(1), &[0], false, false).unwrap();
+        assert!(guard.spends().unwrap().is_empty());
+        guard.claim(&spend(2), &[0], false, true).unwrap();
+    }
+
+    #[test]
+    fn keeps_records_across_restarts() {
+        let storage = Arc::new(Memory::default());
+        let guard = SpendGuard::new(storage.clone(), &config()).unwrap();
+        guard.claim(&spend(1), &[0], false, true).unwrap();
+        let guard = SpendGuard::new(storage, &config()).unwrap();
+        assert!(guard.claim(&spend(2), &[0], false, true).is_err());
+    }
+
+    #[test]
+    fn withholds_signatures_it_cannot_record() {
+        let guard = SpendGuard::new(Arc::new(ReadOnly), &config()).unwrap();
+        let err = guard.claim(&spend(1), &[0], false, true).unwrap_err();
+        assert!(matches!(err, Error::Internal(_)), "{err:?}");
+        assert!(guard.spends().unwrap().is_empty());
+    }
+
+    #[test]
+    fn tells_which_inputs_signing_added_to() {
+        let mut psbt = spend(1);
+        let before = signatures(&psbt);
+        assert!(signed_inputs(&before, &psbt).is_empty());
+        psbt.inputs[0].final_script_witness = Some(bitcoin::Witness::new());
+        assert_eq!(signed_inputs(&before, &psbt), [0]);
+    }
+}
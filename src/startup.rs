@@ -0,0 +1,52 @@
+//! Errors that keep the service from starting or bring its listeners down.
+//!
+//! Each one names the config field, section or listener it comes from, and
+//! `main` reports it and exits non-zero instead of panicking.
+
+use crate::config::ConfigError;
+
+pub const USAGE: &str = "usage: issue-service <config>\n       \
+                         issue-service file-drop <config>\n       \
+                         issue-service check-config <config>\n       \
+                         issue-service migrate-config <config> <new config> <key file>\n       \
+                         issue-service decrypt-backup <backup> <key file>\n       \
+                         issue-service bench <config> [options]";
+
+#[derive(Debug, thiserror::Error)]
+pub enum StartupError {
+    #[error("missing config path\n{USAGE}")]
+    Usage,
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error("log: {0}")]
+    Logging(String),
+    /// The signing descriptor. It holds the key, so it is never part of
+    /// the message.
+    #[error("xprv: {0}")]
+    Key(String),
+    #[error("[{section}] {message}")]
+    Section {
+        section: &'static str,
+        message: String,
+    },
+    #[error("no listener configured, run `issue-service file-drop` to sign offline")]
+    NoListener,
+    #[error("{listener}: cannot listen on {addr}: {source}")]
+    Bind {
+        /// Config field of the listener.
+        listener: &'static str,
+        addr: String,
+        source: std::io::Error,
+    },
+    #[error("{listener}: server failed: {message}")]
+    Serve {
+        listener: &'static str,
+        message: String,
+    },
+}
+
+impl StartupError {
+    pub fn section(section: &'static str) -> impl FnOnce(String) -> Self {
+        move |message| StartupError::Section { section, message }
+    }
+}
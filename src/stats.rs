@@ -0,0 +1,208 @@
+//! Signing statistics of a wallet.
+//!
+//! Every signing request a wallet decides, whichever interface it came in
+//! through and Liquid PSETs included, is counted by its verdict, refusals
+//! also by their error code. Signed requests add the inputs they signed and
+//! the amounts those inputs spend; cached answers, dry runs and journal
+//! replays sign nothing and add neither. PSET amounts are in assets of
+//! their own, often confidential, so PSETs add their inputs only.
+//! `GET /admin/stats` serves the totals, with each hosted wallet's under
+//! its own prefix, so capacity and risk reviews need not reconstruct them
+//! from the logs.
+//!
+//! The totals live in memory and start over when the service restarts.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{extract::State, Json};
+use bitcoin::{psbt::Input, Psbt};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{audit::DEFAULT_WALLET, error::Error, AppState, SignResponse};
+
+pub struct Stats {
+    since: DateTime<Utc>,
+    totals: Mutex<Totals>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            since: Utc::now(),
+            totals: Default::default(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    requests: u64,
+    signed: u64,
+    dry_runs: u64,
+    cached: u64,
+    rejected: u64,
+    rejections: BTreeMap<&'static str, u64>,
+    inputs_signed: u64,
+    value_signed_sat: u64,
+    latency_ms: u64,
+    last_request_at: Option<DateTime<Utc>>,
+    last_signed_at: Option<DateTime<Utc>>,
+}
+
+/// What each input of a PSBT held before signing, to tell which inputs the
+/// signer added to.
+pub struct Before(Vec<(bool, usize)>);
+
+impl Before {
+    pub fn of(psbt: &Psbt) -> Self {
+        Before(
+            psbt.inputs
+                .iter()
+                .map(|input| (finalized(input), signatures(input)))
+                .collect(),
+        )
+    }
+}
+
+/// How a request was decided.
+pub enum Verdict {
+    Signed { inputs: u64, value_sat: u64 },
+    DryRun,
+    Cached,
+    Rejected(&'static str),
+}
+
+impl Stats {
+    /// Counts a decided PSBT request. `replayed` is set when the answer came
+    /// from the journal rather than from signing.
+    pub fn record(
+        &self,
+        before: &Before,
+        result: &Result<SignResponse, Error>,
+        replayed: bool,
+        elapsed: Duration,
+    ) {
+        let verdict = match result {
+            Ok(signed) if signed.cached => Verdict::Cached,
+            Ok(signed) if signed.dry_run => Verdict::DryRun,
+            Ok(_) if replayed => Verdict::Signed {
+                inputs: 0,
+                value_sat: 0,
+            },
+            Ok(signed) => {
+                let (inputs, value_sat) = signed_inputs(before, &signed.psbt);
+                Verdict::Signed { inputs, value_sat }
+            }
+            Err(e) => Verdict::Rejected(e.code()),
+        };
+        self.count(verdict, elapsed);
+    }
+
+    /// Counts a decided request of any kind.
+    pub fn count(&self, verdict: Verdict, elapsed: Duration) {
+        let now = Utc::now();
+        let mut totals = self.totals.lock().expect("stats");
+        totals.requests += 1;
+        totals.latency_ms += u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX);
+        totals.last_request_at = Some(now);
+        match verdict {
+            Verdict::Cached => totals.cached += 1,
+            Verdict::DryRun => totals.dry_runs += 1,
+            Verdict::Signed { inputs, value_sat } => {
+                totals.signed += 1;
+                totals.last_signed_at = Some(now);
+                totals.inputs_signed += inputs;
+                totals.value_signed_sat += value_sat;
+            }
+            Verdict::Rejected(code) => {
+                totals.rejected += 1;
+                *totals.rejections.entry(code).or_default() += 1;
+            }
+        }
+    }
+}
+
+/// The inputs of `after` this signing finalized or added signatures to,
+/// and the amount they spend, where known.
+fn signed_inputs(before: &Before, after: &Psbt) -> (u64, u64) {
+    let mut inputs = 0;
+    let mut value_sat = 0;
+    for ((input, txin), (was_finalized, had)) in after
+        .inputs
+        .iter()
+        .zip(&after.unsigned_tx.input)
+        .zip(&before.0)
+    {
+        if *was_finalized || !(finalized(input) || signatures(input) > *had) {
+            continue;
+        }
+        inputs += 1;
+        let vout = txin.previous_output.vout as usize;
+        value_sat += input
+            .witness_utxo
+            .as_ref()
+            .or_else(|| input.non_witness_utxo.as_ref()?.output.get(vout))
+            .map_or(0, |prevout| prevout.value.to_sat());
+    }
+    (inputs, value_sat)
+}
+
+fn finalized(input: &Input) -> bool {
+    input.final_script_sig.is_some() || input.final_script_witness.is_some()
+}
+
+fn signatures(input: &Input) -> usize {
+    input.partial_sigs.len()
+        + input.tap_script_sigs.len()
+        + usize::from(input.tap_key_sig.is_some())
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsResponse {
+    pub wallet: &'static str,
+    pub network: String,
+    /// When counting started, at the service's start.
+    pub since: DateTime<Utc>,
+    pub requests: u64,
+    pub signed: u64,
+    pub dry_runs: u64,
+    pub cached: u64,
+    pub rejected: u64,
+    /// Refused requests by error code.
+    pub rejections: BTreeMap<&'static str, u64>,
+    /// Inputs signed, over all signed requests.
+    pub inputs_signed: u64,
+    /// Amount spent by the signed PSBT inputs; inputs without UTXO data add
+    /// 0.
+    pub value_signed_sat: u64,
+    /// Mean time to decide a request, over all of them.
+    pub average_latency_ms: Option<u64>,
+    pub last_request_at: Option<DateTime<Utc>>,
+    pub last_signed_at: Option<DateTime<Utc>>,
+}
+
+pub async fn serve(State(state): State<Arc<AppState>>) -> Json<StatsResponse> {
+    let stats = &state.stats;
+    let totals = stats.totals.lock().expect("stats");
+    Json(StatsResponse {
+        wallet: DEFAULT_WALLET,
+        network: state.config().network.to_string(),
+        since: stats.since,
+        requests: totals.requests,
+        signed: totals.signed,
+        dry_runs: totals.dry_runs,
+        cached: totals.cached,
+        rejected: totals.rejected,
+        rejections: totals.rejections.clone(),
+        inputs_signed: totals.inputs_signed,
+        value_signed_sat: totals.value_signed_sat,
+        average_latency_ms: totals.latency_ms.checked_div(totals.requests),
+        last_request_at: totals.last_request_at,
+        last_signed_at: totals.last_signed_at,
+    })
+}
@@ -0,0 +1,428 @@
+//! Where the service keeps what it must not lose.
+//!
+//! Every store goes through [`Storage`]: the audit log as an append-only
+//! log, and the spend guard's records, the request journal, labels,
+//! schedules, BSMS wallets, the wallet's changes and, on a shared backend,
+//! the signed response cache as documents replaced whole. Stores name their data by the path
+//! configured for it, so moving to another backend changes nothing but
+//! `[storage]`.
+//!
+//! [`Files`] keeps each store in the file at its path, as the service
+//! always has. [`Sqlite`] keeps them all in one database, and [`Memory`]
+//! keeps nothing across restarts. Another database, such as a managed
+//! Postgres, is another implementation of the trait selected in [`open`].
+
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::config::{StorageBackend, StorageConfig};
+
+/// A persistence backend. Implementations must be safe to call from any
+/// thread, and each call either takes effect whole or not at all.
+pub trait Storage: Send + Sync {
+    /// Contents of the document `name`, if there is one.
+    fn read(&self, name: &str) -> std::io::Result<Option<Vec<u8>>>;
+
+    /// Replaces the document `name` with `contents`, durably before
+    /// returning.
+    fn write(&self, name: &str, contents: &[u8]) -> std::io::Result<()>;
+
+    /// Removes the document `name`, if there is one.
+    fn remove(&self, name: &str) -> std::io::Result<()>;
+
+    /// Names of the documents starting with `prefix`, in order.
+    fn names(&self, prefix: &str) -> std::io::Result<Vec<String>>;
+
+    /// Appends `lines` to the log `name`, creating it if need be, durably
+    /// before returning.
+    fn append(&self, name: &str, lines: &[String]) -> std::io::Result<()>;
+
+    /// Up to `limit` lines of the log `name` from the `offset`-th on, and
+    /// the number of lines in the log.
+    fn lines(
+        &self,
+        name: &str,
+        offset: usize,
+        limit: usize,
+    ) -> std::io::Result<(Vec<String>, usize)>;
+}
+
+/// The name stores use for the data configured at `path`.
+pub fn name(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// Opens the backend `storage` selects, files when absent.
+pub fn open(storage: Option<&StorageConfig>) -> Result<Arc<dyn Storage>, String> {
+    let Some(storage) = storage else {
+        return Ok(Arc::new(Files::default()));
+    };
+    match storage.backend {
+        StorageBackend::Files => Ok(Arc::new(Files::default())),
+        StorageBackend::Memory => {
+            tracing::warn!("storage.backend is memory, nothing is kept across restarts");
+            Ok(Arc::new(Memory::default()))
+        }
+        #[cfg(feature = "sqlite")]
+        StorageBackend::Sqlite => {
+            let path = storage
+                .path
+                .as_ref()
+                .ok_or("path: required by the sqlite backend")?;
+            Ok(Arc::new(Sqlite::open(path)?))
+        }
+        #[cfg(not(feature = "sqlite"))]
+        StorageBackend::Sqlite => Err("this binary was built without `sqlite`".into()),
+    }
+}
+
+/// Opens the backend `storage` selects without creating or changing
+/// anything, for `check-config`. A database not created yet reads as empty.
+pub fn open_read_only(storage: Option<&StorageConfig>) -> Result<Arc<dyn Storage>, String> {
+    match storage {
+        #[cfg(feature = "sqlite")]
+        Some(StorageConfig {
+            backend: StorageBackend::Sqlite,
+            path: Some(path),
+            ..
+        }) => match path.exists() {
+            true => Ok(Arc::new(Sqlite::open_read_only(path)?)),
+            false => Ok(Arc::new(Memory::default())),
+        },
+        Some(StorageConfig {
+            backend: StorageBackend::Memory,
+            ..
+        }) => Ok(Arc::new(Memory::default())),
+        _ => open(storage),
+    }
+}
+
+/// Each document and log in the file at its name.
+#[derive(Default)]
+pub struct Files {
+    /// Keeps appends from interleaving with each other and with reads.
+    logs: Mutex<()>,
+}
+
+impl Storage for Files {
+    fn read(&self, name: &str) -> std::io::Result<Option<Vec<u8>>> {
+        match std::fs::read(name) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(&self, name: &str, contents: &[u8]) -> std::io::Result<()> {
+        let tmp = Path::new(name).with_extension("tmp");
+        std::fs::write(&tmp, contents)?;
+        std::fs::rename(tmp, name)
+    }
+
+    fn remove(&self, name: &str) -> std::io::Result<()> {
+        match std::fs::remove_file(name) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn names(&self, prefix: &str) -> std::io::Result<Vec<String>> {
+        let dir = match Path::new(prefix).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let path = path.strip_prefix("./").unwrap_or(&path);
+            let path = name(path);
+            if path.starts_with(prefix) && !path.ends_with(".tmp") {
+                names.push(path);
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn append(&self, name: &str, lines: &[String]) -> std::io::Result<()> {
+        let mut body = String::new();
+        for line in lines {
+            body.push_str(line);
+            body.push('\n');
+        }
+        let _logs = self.logs.lock().expect("log lock");
+        let mut file = OpenOptions::new().create(true).append(true).open(name)?;
+        file.write_all(body.as_bytes())?;
+        file.sync_data()
+    }
+
+    fn lines(
+        &self,
+        name: &str,
+        offset: usize,
+        limit: usize,
+    ) -> std::io::Result<(Vec<String>, usize)> {
+        let _logs = self.logs.lock().expect("log lock");
+        let file = match File::open(name) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+            Err(e) => return Err(e),
+        };
+        let mut lines = Vec::new();
+        let mut total = 0;
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if total >= offset && lines.len() < limit {
+                lines.push(line);
+            }
+            total += 1;
+        }
+        Ok((lines, total))
+    }
+}
+
+/// Everything in process memory, gone on exit.
+#[derive(Default)]
+pub struct Memory {
+    documents: Mutex<BTreeMap<String, Vec<u8>>>,
+    logs: Mutex<BTreeMap<String, Vec<String>>>,
+}
+
+impl Storage for Memory {
+    fn read(&self, name: &str) -> std::io::Result<Option<Vec<u8>>> {
+        Ok(self.documents.lock().expect("documents").get(name).cloned())
+    }
+
+    fn write(&self, name: &str, contents: &[u8]) -> std::io::Result<()> {
+        self.documents
+            .lock()
+            .expect("documents")
+            .insert(name.to_owned(), contents.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self, name: &str) -> std::io::Result<()> {
+        self.documents.lock().expect("documents").remove(name);
+        Ok(())
+    }
+
+    fn names(&self, prefix: &str) -> std::io::Result<Vec<String>> {
+        Ok(self
+            .documents
+            .lock()
+            .expect("documents")
+            .range(prefix.to_owned()..)
+            .map(|(name, _)| name)
+            .take_while(|name| name.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    fn append(&self, name: &str, lines: &[String]) -> std::io::Result<()> {
+        self.logs
+            .lock()
+            .expect("logs")
+            .entry(name.to_owned())
+            .or_default()
+            .extend_from_slice(lines);
+        Ok(())
+    }
+
+    fn lines(
+        &self,
+        name: &str,
+        offset: usize,
+        limit: usize,
+    ) -> std::io::Result<(Vec<String>, usize)> {
+        let logs = self.logs.lock().expect("logs");
+        let log = logs.get(name).map_or(&[][..], Vec::as_slice);
+        let lines = log.iter().skip(offset).take(limit).cloned().collect();
+        Ok((lines, log.len()))
+    }
+}
+
+/// Every document and log in one SQLite database.
+#[cfg(feature = "sqlite")]
+pub struct Sqlite {
+    connection: Mutex<rusqlite::Connection>,
+}
+
+#[cfg(feature = "sqlite")]
+const SCHEMA: &str = "
+PRAGMA journal_mode = WAL;
+PRAGMA synchronous = FULL;
+CREATE TABLE IF NOT EXISTS documents (
+    name TEXT PRIMARY KEY,
+    contents BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS log_lines (
+    log TEXT NOT NULL,
+    seq INTEGER NOT NULL,
+    line TEXT NOT NULL,
+    PRIMARY KEY (log, seq)
+);
+";
+
+#[cfg(feature = "sqlite")]
+impl Sqlite {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let connection = rusqlite::Connection::open(path)
+            .and_then(|connection| {
+                connection.execute_batch(SCHEMA)?;
+                Ok(connection)
+            })
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        tracing::info!(path = %path.display(), "opened sqlite storage");
+        Ok(Sqlite {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Opens the database at `path` for reading only, refusing one without
+    /// the service's tables. Even a reader of a WAL database creates its
+    /// shared-memory file, so one without a write-ahead log, which has
+    /// nothing to read from it, is opened as immutable instead.
+    pub fn open_read_only(path: &Path) -> Result<Self, String> {
+        use rusqlite::OpenFlags;
+
+        let mut wal = path.as_os_str().to_owned();
+        wal.push("-wal");
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI;
+        let uri = path
+            .to_string_lossy()
+            .replace('%', "%25")
+            .replace('?', "%3f")
+            .replace('#', "%23");
+        let uri = match Path::new(&wal).exists() {
+            true => format!("file:{uri}?mode=ro"),
+            false => format!("file:{uri}?immutable=1"),
+        };
+        let connection = rusqlite::Connection::open_with_flags(uri, flags)
+            .and_then(|connection| {
+                connection
+                .query_row(
+                    "SELECT count(*) FROM sqlite_master WHERE name IN ('documents', 'log_lines')",
+                    [],
+                    |row| row.get::<_, u32>(0),
+                )
+                .map(|tables| (connection, tables))
+            })
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        match connection {
+            (connection, 2) => Ok(Sqlite {
+                connection: Mutex::new(connection),
+            }),
+            _ => Err(format!(
+                "{}: not a database of this service",
+                path.display()
+            )),
+        }
+    }
+
+    fn run<T>(
+        &self,
+        f: impl FnOnce(&mut rusqlite::Connection) -> rusqlite::Result<T>,
+    ) -> std::io::Result<T> {
+        f(&mut self.connection.lock().expect("sqlite connection")).map_err(std::io::Error::other)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Storage for Sqlite {
+    fn read(&self, name: &str) -> std::io::Result<Option<Vec<u8>>> {
+        use rusqlite::OptionalExtension;
+        self.run(|connection| {
+            connection
+                .query_row(
+                    "SELECT contents FROM documents WHERE name = ?1",
+                    [name],
+                    |row| row.get(0),
+                )
+                .optional()
+        })
+    }
+
+    fn write(&self, name: &str, contents: &[u8]) -> std::io::Result<()> {
+        self.run(|connection| {
+            connection.execute(
+                "INSERT INTO documents (name, contents) VALUES (?1, ?2)
+                 ON CONFLICT (name) DO UPDATE SET contents = excluded.contents",
+                rusqlite::params![name, contents],
+            )
+        })
+        .map(drop)
+    }
+
+    fn remove(&self, name: &str) -> std::io::Result<()> {
+        self.run(|connection| connection.execute("DELETE FROM documents WHERE name = ?1", [name]))
+            .map(drop)
+    }
+
+    fn names(&self, prefix: &str) -> std::io::Result<Vec<String>> {
+        self.run(|connection| {
+            let mut statement = connection.prepare(
+                "SELECT name FROM documents WHERE substr(name, 1, length(?1)) = ?1
+                 ORDER BY name",
+            )?;
+            let names = statement.query_map([prefix], |row| row.get(0))?;
+            names.collect()
+        })
+    }
+
+    fn append(&self, name: &str, lines: &[String]) -> std::io::Result<()> {
+        self.run(|connection| {
+            let transaction = connection.transaction()?;
+            let next: i64 = transaction.query_row(
+                "SELECT COALESCE(MAX(seq) + 1, 0) FROM log_lines WHERE log = ?1",
+                [name],
+                |row| row.get(0),
+            )?;
+            for (seq, line) in (next..).zip(lines) {
+                transaction.execute(
+                    "INSERT INTO log_lines (log, seq, line) VALUES (?1, ?2, ?3)",
+                    rusqlite::params![name, seq, line],
+                )?;
+            }
+            transaction.commit()
+        })
+    }
+
+    fn lines(
+        &self,
+        name: &str,
+        offset: usize,
+        limit: usize,
+    ) -> std::io::Result<(Vec<String>, usize)> {
+        let offset = i64::try_from(offset).unwrap_or(i64::MAX);
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        let (lines, total) = self.run(|connection| {
+            let transaction = connection.transaction()?;
+            let total: i64 = transaction.query_row(
+                "SELECT COALESCE(MAX(seq) + 1, 0) FROM log_lines WHERE log = ?1",
+                [name],
+                |row| row.get(0),
+            )?;
+            let lines = {
+                let mut statement = transaction.prepare(
+                    "SELECT line FROM log_lines WHERE log = ?1 AND seq >= ?2
+                     ORDER BY seq LIMIT ?3",
+                )?;
+                let lines = statement
+                    .query_map(rusqlite::params![name, offset, limit], |row| row.get(0))?;
+                lines.collect::<rusqlite::Result<Vec<String>>>()?
+            };
+            Ok((lines, total))
+        })?;
+        Ok((lines, usize::try_from(total).unwrap_or(usize::MAX)))
+    }
+}
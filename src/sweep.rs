@@ -0,0 +1,260 @@
+//! `POST /admin/sweep`: drains the wallet's coins, or a filtered set of them,
+//! to one address, for migrating to another wallet.
+//!
+//! The transaction is built by the wallet, then signed and recorded through
+//! the same path as `/sign_psbt`, so every signing policy, the audit log and
+//! `signing.dry_run` apply. It is only broadcast when asked to, through the
+//! `[chain]` Esplora server. Being able to move every coin, it is only
+//! served on the admin listener.
+
+use std::{str::FromStr, sync::Arc};
+
+use axum::{extract::State, Json};
+use bitcoin::{Address, FeeRate, Network, OutPoint, Psbt, ScriptBuf, Txid};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::Metadata,
+    config::PolicyPath,
+    context::RequestContext,
+    error::{ApiJson, Error},
+    priority::Priority,
+    AppState, SignRequest,
+};
+
+#[derive(Deserialize)]
+pub struct SweepRequest {
+    /// Address the coins are sent to.
+    pub destination: String,
+    /// In sat/vB.
+    pub fee_rate_sat_vb: f64,
+    /// Coins to sweep, as `txid:vout`. Every coin of the wallet when omitted,
+    /// except those labeled as not spendable.
+    #[serde(default)]
+    pub outpoints: Option<Vec<OutPoint>>,
+    /// Leaves out unconfirmed coins.
+    #[serde(default)]
+    pub confirmed_only: bool,
+    /// Leaves out coins worth less, such as dust not worth its fee.
+    #[serde(default)]
+    pub min_value_sat: u64,
+    /// Broadcasts the signed transaction through `[chain]`.
+    #[serde(default)]
+    pub broadcast: bool,
+    /// Recorded in the audit entry, as for `/sign_psbt`.
+    #[serde(default)]
+    pub metadata: Metadata,
+    /// Signing priority, as for `/sign_psbt`.
+    #[serde(default)]
+    pub priority: Priority,
+    /// Branches of the descriptor's policy to satisfy, instead of the
+    /// configured `policy_path`.
+    #[serde(default)]
+    pub policy_path: Option<PolicyPath>,
+}
+
+#[derive(Serialize)]
+pub struct SweepResponse {
+    /// Base64-encoded, finalized PSBT.
+    #[serde(serialize_with = "crate::serialize_psbt_to_base64")]
+    pub psbt: Psbt,
+    pub txid: Txid,
+    /// Swept coins, as `txid:vout`.
+    pub inputs: Vec<OutPoint>,
+    /// What `destination` receives.
+    pub amount_sat: u64,
+    pub fee_sat: u64,
+    /// Set once the Esplora server accepted the transaction.
+    pub broadcast: bool,
+    /// Set when `signing.dry_run` left the transaction unsigned. It is
+    /// never broadcast then.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+}
+
+pub async fn sweep(
+    State(state): State<Arc<AppState>>,
+    ctx: RequestContext,
+    ApiJson(request): ApiJson<SweepRequest>,
+) -> Result<Json<SweepResponse>, Error> {
+    if request.broadcast {
+        crate::auth::authorize(
+            &state,
+            ctx.bearer.as_ref(),
+            &[crate::auth::Scope::Broadcast],
+        )?;
+    }
+    run(&state, &ctx, request).await.map(Json)
+}
+
+/// What `/admin/sweep` does, for callers other than the endpoint.
+pub async fn run(
+    state: &Arc<AppState>,
+    ctx: &RequestContext,
+    request: SweepRequest,
+) -> Result<SweepResponse, Error> {
+    crate::chain::require_synced(state)?;
+    let destination = destination(state.config().network, &request.destination)
+        .map_err(|e| Error::MalformedRequest(format!("destination: {e}")))?;
+    let fee_rate = fee_rate(request.fee_rate_sat_vb)?;
+
+    let inputs = select(state, &request)?;
+    spend(
+        state,
+        ctx,
+        inputs,
+        destination.script_pubkey(),
+        fee_rate,
+        SpendOptions {
+            metadata: request.metadata,
+            priority: request.priority,
+            broadcast: request.broadcast,
+            policy_path: request
+                .policy_path
+                .unwrap_or_else(|| state.config().policy_path.clone()),
+        },
+    )
+    .await
+}
+
+/// `address` checked to be on `network`.
+pub fn destination(network: Network, address: &str) -> Result<Address, String> {
+    Address::from_str(address)
+        .map_err(|e| e.to_string())
+        .and_then(|address| {
+            address
+                .require_network(network)
+                .map_err(|_| format!("not an address on {network}"))
+        })
+}
+
+/// How [`spend`] signs and releases its transaction.
+pub struct SpendOptions {
+    /// Recorded in the audit entry.
+    pub metadata: Metadata,
+    pub priority: Priority,
+    /// Broadcasts the signed transaction through `[chain]`.
+    pub broadcast: bool,
+    pub policy_path: PolicyPath,
+}
+
+/// Builds a transaction spending exactly `inputs` to `destination`, signs
+/// and records it like `/sign_psbt`, and broadcasts it if asked to. Once
+/// broadcast, the wallet counts `inputs` as spent.
+pub async fn spend(
+    state: &Arc<AppState>,
+    ctx: &RequestContext,
+    inputs: Vec<OutPoint>,
+    destination: ScriptBuf,
+    fee_rate: FeeRate,
+    options: SpendOptions,
+) -> Result<SweepResponse, Error> {
+    let SpendOptions {
+        metadata,
+        priority,
+        broadcast,
+        policy_path,
+    } = options;
+    let psbt = state
+        .wallet
+        .build_sweep(inputs.clone(), destination, fee_rate, policy_path)
+        .await?;
+    let txid = psbt.unsigned_tx.compute_txid();
+    let amount = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .map(|out| out.value)
+        .sum::<bitcoin::Amount>();
+    let fee = psbt
+        .fee()
+        .map_err(|e| Error::Internal(format!("sweep fee: {e}")))?;
+
+    let signed = crate::sign_and_record(
+        state,
+        ctx,
+        SignRequest {
+            psbt,
+            consignment: None,
+            metadata,
+            replace: false,
+            priority,
+            sign_options: None,
+            collaborative: false,
+            prevouts: Vec::new(),
+            pairings: Vec::new(),
+        },
+    )
+    .await?;
+
+    let broadcast = broadcast && !signed.dry_run;
+    if broadcast {
+        let tx = signed
+            .psbt
+            .clone()
+            .extract_tx()
+            .map_err(|e| Error::Internal(format!("extracting the sweep: {e}")))?;
+        let config = state.config();
+        let chain = config.chain.as_ref().expect("checked by require_synced");
+        crate::chain::broadcast(chain, &tx)
+            .await
+            .map_err(|e| Error::Unavailable(format!("broadcasting {txid}: {e}")))?;
+        tracing::info!(%txid, inputs = inputs.len(), "broadcast sweep");
+        state.wallet.apply_unconfirmed(tx).await?;
+    }
+    Ok(SweepResponse {
+        psbt: signed.psbt,
+        txid,
+        inputs,
+        amount_sat: amount.to_sat(),
+        fee_sat: fee.to_sat(),
+        broadcast,
+        dry_run: signed.dry_run,
+    })
+}
+
+/// `sat_vb` as a [`FeeRate`], if it is a positive number.
+pub fn fee_rate(sat_vb: f64) -> Result<FeeRate, Error> {
+    if !sat_vb.is_finite() || sat_vb <= 0.0 {
+        return Err(Error::MalformedRequest(
+            "fee_rate_sat_vb must be a positive number".into(),
+        ));
+    }
+    Ok(FeeRate::from_sat_per_kwu((sat_vb * 250.0).round() as u64))
+}
+
+/// The coins to sweep, after the request's filters.
+pub fn select(state: &AppState, request: &SweepRequest) -> Result<Vec<OutPoint>, Error> {
+    let wallet = state.wallet();
+    let coins = match &request.outpoints {
+        Some(outpoints) => outpoints
+            .iter()
+            .map(|&outpoint| {
+                wallet.get_utxo(outpoint).ok_or_else(|| {
+                    Error::MalformedRequest(format!(
+                        "{outpoint} is not an unspent coin of this wallet"
+                    ))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        None => wallet
+            .list_unspent()
+            .filter(|utxo| {
+                state
+                    .labels
+                    .as_ref()
+                    .map_or(true, |labels| labels.spendable(utxo.outpoint))
+            })
+            .collect(),
+    };
+    let selected: Vec<OutPoint> = coins
+        .into_iter()
+        .filter(|utxo| !request.confirmed_only || utxo.chain_position.is_confirmed())
+        .filter(|utxo| utxo.txout.value.to_sat() >= request.min_value_sat)
+        .map(|utxo| utxo.outpoint)
+        .collect();
+    if selected.is_empty() {
+        return Err(Error::MalformedRequest("no coins to sweep".into()));
+    }
+    Ok(selected)
+}
@@ -0,0 +1,53 @@
+//! systemd integration: socket activation, readiness and watchdog pings.
+//!
+//! Everything here is a no-op when the process was not started by systemd,
+//! so the same binary runs unchanged outside of a unit.
+
+use std::{net::TcpListener, os::fd::FromRawFd, time::Duration};
+
+use sd_notify::NotifyState;
+
+/// Takes the first listening socket passed by systemd socket activation, if
+/// any. Environment variables are left in place because the runtime is
+/// already multi-threaded by the time this runs.
+pub fn activated_listener() -> std::io::Result<Option<TcpListener>> {
+    let mut fds = sd_notify::listen_fds()?;
+    let Some(fd) = fds.next() else {
+        return Ok(None);
+    };
+    if fds.next().is_some() {
+        tracing::warn!("systemd passed more than one socket, only the first is used");
+    }
+    // SAFETY: systemd hands over ownership of descriptors starting at
+    // SD_LISTEN_FDS_START, and nothing else in the process claims them.
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+    listener.set_nonblocking(true)?;
+    Ok(Some(listener))
+}
+
+/// Signals READY=1. Called once the wallet is initialized and the listener
+/// is bound, right before requests start being served.
+pub fn notify_ready() {
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        tracing::warn!("failed to notify systemd readiness: {e}");
+    }
+}
+
+/// Spawns a task sending WATCHDOG=1 at half the interval systemd asked for.
+pub fn spawn_watchdog() {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return;
+    }
+    let interval = Duration::from_micros(usec) / 2;
+    tracing::info!(?interval, "systemd watchdog enabled");
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = sd_notify::notify(false, &[NotifyState::Watchdog]) {
+                tracing::warn!("failed to ping systemd watchdog: {e}");
+            }
+        }
+    });
+}
@@ -0,0 +1,244 @@
+//! Signing only transactions of registered shapes.
+//!
+//! With `[templates]` set, a PSBT is only signed if it matches one of the
+//! `[[templates.template]]`: every output paying others matches one of the
+//! template's output patterns, by script type, address and amount, there are
+//! between `min_payments` and `max_payments` of them, and the outputs paying
+//! the wallet back number between `change.min_outputs` and
+//! `change.max_outputs`. A service whose withdrawals and consolidations all
+//! look alike so refuses whatever does not. With `mode = "log"`, a PSBT matching none is signed
+//! all the same and logged, for trying templates out against real traffic.
+
+use bdk_wallet::Wallet;
+use bitcoin::{address::NetworkUnchecked, Address, Network, Psbt, Script, TxOut};
+use serde::Deserialize;
+
+use crate::{
+    config::{OutputTemplate, TemplateConfig, TemplateMode, TemplatesConfig},
+    error::Error,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptType {
+    P2pkh,
+    P2sh,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+    OpReturn,
+}
+
+impl ScriptType {
+    fn matches(self, script: &Script) -> bool {
+        match self {
+            ScriptType::P2pkh => script.is_p2pkh(),
+            ScriptType::P2sh => script.is_p2sh(),
+            ScriptType::P2wpkh => script.is_p2wpkh(),
+            ScriptType::P2wsh => script.is_p2wsh(),
+            ScriptType::P2tr => script.is_p2tr(),
+            ScriptType::OpReturn => script.is_op_return(),
+        }
+    }
+}
+
+/// Refuses `psbt` unless it matches one of `policy`'s templates, or logs it
+/// in `log` mode.
+pub fn check(
+    wallet: &Wallet,
+    psbt: &Psbt,
+    policy: &TemplatesConfig,
+    network: Network,
+) -> Result<(), Error> {
+    let mut mismatches = Vec::new();
+    for template in &policy.templates {
+        match mismatch(wallet, psbt, template, network)? {
+            None => {
+                tracing::debug!(template = template.name, "PSBT matches template");
+                return Ok(());
+            }
+            Some(why) => mismatches.push(format!("{}: {why}", template.name)),
+        }
+    }
+    let reason = match mismatches.is_empty() {
+        true => "no template is registered".to_owned(),
+        false => format!("matches no template ({})", mismatches.join("; ")),
+    };
+    match policy.mode {
+        TemplateMode::Enforce => Err(Error::InvalidTransaction(format!("template: {reason}"))),
+        TemplateMode::Log => {
+            tracing::warn!(
+                txid = %psbt.unsigned_tx.compute_txid(),
+                "signing anyway in log mode, template: {reason}"
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Why `psbt` does not match `template`, if it does not.
+fn mismatch(
+    wallet: &Wallet,
+    psbt: &Psbt,
+    template: &TemplateConfig,
+    network: Network,
+) -> Result<Option<String>, Error> {
+    let tx = &psbt.unsigned_tx;
+    if let Some(max) = template.max_inputs {
+        if tx.input.len() > max {
+            return Ok(Some(format!("{} inputs, more than {max}", tx.input.len())));
+        }
+    }
+    let (change, payments): (Vec<_>, Vec<_>) = tx
+        .output
+        .iter()
+        .enumerate()
+        .partition(|(_, output)| wallet.is_mine(output.script_pubkey.clone()));
+    let range = &template.change;
+    if change.len() < range.min_outputs || change.len() > range.max_outputs {
+        return Ok(Some(format!(
+            "{} change outputs, not between {} and {}",
+            change.len(),
+            range.min_outputs,
+            range.max_outputs
+        )));
+    }
+    if payments.len() < template.min_payments {
+        return Ok(Some(format!(
+            "{} outputs pay others, fewer than {}",
+            payments.len(),
+            template.min_payments
+        )));
+    }
+    if let Some(max) = template.max_payments {
+        if payments.len() > max {
+            return Ok(Some(format!(
+                "{} outputs pay others, more than {max}",
+                payments.len()
+            )));
+        }
+    }
+    for (index, output) in payments {
+        let mut matched = false;
+        for pattern in &template.outputs {
+            if matches(pattern, output, network)? {
+                matched = true;
+                break;
+            }
+        }
+        if !matched {
+            return Ok(Some(format!(
+                "output {index} of {} sat matches no output pattern",
+                output.value.to_sat()
+            )));
+        }
+    }
+    Ok(None)
+}
+
+fn matches(pattern: &OutputTemplate, output: &TxOut, network: Network) -> Result<bool, Error> {
+    let script = &output.script_pubkey;
+    if pattern
+        .script_type
+        .is_some_and(|script_type| !script_type.matches(script))
+    {
+        return Ok(false);
+    }
+    if let Some(address) = &pattern.address {
+        let address = address
+            .parse::<Address<NetworkUnchecked>>()
+            .and_then(|address| address.require_network(network))
+            .map_err(|e| Error::Internal(format!("templates: address {address}: {e}")))?;
+        if address.script_pubkey() != *script {
+            return Ok(false);
+        }
+    }
+    let sat = output.value.to_sat();
+    Ok(pattern.min_sat.map_or(true, |min| sat >= min)
+        && pattern.max_sat.map_or(true, |max| sat <= max))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::ScriptBuf;
+
+    use super::*;
+    use crate::test_support;
+
+    const WITHDRAWAL: &str = r#"
+[templates]
+[[templates.template]]
+name = "withdrawal"
+max_inputs = 2
+max_payments = 1
+outputs = [{ script_type = "p2tr", max_sat = 50000 }]
+change = { min_outputs = 1, max_outputs = 1 }
+"#;
+
+    fn policy(mode: TemplateMode) -> TemplatesConfig {
+        let templates = test_support::config(WITHDRAWAL).templates;
+        TemplatesConfig {
+            mode,
+            ..templates.expect("[templates]")
+        }
+    }
+
+    /// A PSBT of `inputs` inputs paying `outputs`.
+    fn psbt(inputs: u32, outputs: &[(ScriptBuf, u64)]) -> Psbt {
+        let coins: Vec<_> = (0..inputs)
+            .map(|i| test_support::coin(test_support::script(i), 100_000))
+            .collect();
+        test_support::psbt(&coins.iter().collect::<Vec<_>>(), outputs)
+    }
+
+    fn check(psbt: &Psbt, policy: &TemplatesConfig) -> Result<(), Error> {
+        super::check(&test_support::wallet(), psbt, policy, Network::Regtest)
+    }
+
+    #[test]
+    fn signs_matching_transactions() {
+        let outputs = [
+            (test_support::taproot(1), 40_000),
+            (test_support::script(5), 59_000),
+        ];
+        check(&psbt(2, &outputs), &policy(TemplateMode::Enforce)).unwrap();
+    }
+
+    #[test]
+    fn refuses_transactions_matching_no_template() {
+        let policy = policy(TemplateMode::Enforce);
+        let change = (test_support::script(5), 10_000);
+        for (inputs, outputs) in [
+            (3, vec![(test_support::taproot(1), 40_000), change.clone()]),
+            (1, vec![(test_support::taproot(1), 60_000), change.clone()]),
+            (1, vec![(test_support::script(6), 40_000), change.clone()]),
+            (1, vec![(test_support::taproot(1), 40_000)]),
+            (
+                1,
+                vec![
+                    (test_support::taproot(1), 20_000),
+                    (test_support::taproot(2), 20_000),
+                    change,
+                ],
+            ),
+        ] {
+            let err = check(&psbt(inputs, &outputs), &policy).unwrap_err();
+            assert!(
+                matches!(&err, Error::InvalidTransaction(m) if m.starts_with("template: matches no")),
+                "{err:?}"
+            );
+        }
+        let none = TemplatesConfig {
+            templates: Vec::new(),
+            ..policy
+        };
+        let err = check(&psbt(1, &[]), &none).unwrap_err();
+        assert!(matches!(err, Error::InvalidTransaction(m) if m.contains("no template is")));
+    }
+
+    #[test]
+    fn signs_anyway_in_log_mode() {
+        let outputs = [(test_support::taproot(1), 60_000)];
+        check(&psbt(1, &outputs), &policy(TemplateMode::Log)).unwrap();
+    }
+}
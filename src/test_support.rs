@@ -0,0 +1,119 @@
+//! Configs, states and PSBTs for the unit tests.
+
+use std::{path::PathBuf, sync::Arc};
+
+use bdk_wallet::{KeychainKind, Wallet};
+use bitcoin::{
+    absolute::LockTime,
+    bip32::{DerivationPath, Xpriv},
+    psbt::Input,
+    secp256k1::{Keypair, Secp256k1},
+    transaction::Version,
+    Amount, Network, OutPoint, Psbt, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+};
+
+use crate::{config::Config, test_vectors::XPRV, AppState};
+
+/// A regtest wallet of [`XPRV`] with `extra` appended to its config.
+pub fn config(extra: &str) -> Config {
+    Config::from_toml(&format!(
+        "network = \"regtest\"\nxprv = \"{XPRV}\"\n{extra}"
+    ))
+}
+
+pub async fn state(extra: &str) -> Arc<AppState> {
+    let state = AppState::init(config(extra), PathBuf::from("test.toml"))
+        .await
+        .expect("test state");
+    Arc::new(state)
+}
+
+/// A regtest wallet of [`XPRV`] that holds no coins.
+pub fn wallet() -> Wallet {
+    Wallet::create_single(XPRV)
+        .network(Network::Regtest)
+        .create_wallet_no_persist()
+        .expect("test wallet")
+}
+
+/// The receive address `index` of [`wallet`].
+pub fn script(index: u32) -> ScriptBuf {
+    wallet()
+        .peek_address(KeychainKind::External, index)
+        .script_pubkey()
+}
+
+/// A taproot output of the key `[seed; 32]`, which is not the wallet's.
+pub fn taproot(seed: u8) -> ScriptBuf {
+    let secp = Secp256k1::new();
+    let key = Keypair::from_seckey_slice(&secp, &[seed; 32]).expect("key");
+    ScriptBuf::new_p2tr(&secp, key.x_only_public_key().0, None)
+}
+
+/// A transaction paying `value` to `script` as its only output.
+pub fn coin(script: ScriptBuf, value: u64) -> Transaction {
+    Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: ScriptBuf::from_bytes(value.to_le_bytes().to_vec()),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: script,
+        }],
+    }
+}
+
+/// A PSBT spending the output of each of `coins` to `outputs`, with their
+/// `witness_utxo` and nothing else.
+pub fn psbt(coins: &[&Transaction], outputs: &[(ScriptBuf, u64)]) -> Psbt {
+    let tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: coins
+            .iter()
+            .map(|coin| TxIn {
+                previous_output: OutPoint::new(coin.compute_txid(), 0),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: outputs
+            .iter()
+            .map(|(script, value)| TxOut {
+                value: Amount::from_sat(*value),
+                script_pubkey: script.clone(),
+            })
+            .collect(),
+    };
+    let mut psbt = Psbt::from_unsigned_tx(tx).expect("unsigned");
+    for (input, coin) in psbt.inputs.iter_mut().zip(coins) {
+        input.witness_utxo = Some(coin.output[0].clone());
+    }
+    psbt
+}
+
+/// Marks `input` as spending [`script`] `0`, by the origin of its key.
+pub fn ours(input: &mut Input) {
+    let secp = Secp256k1::new();
+    let (origin, key) = XPRV
+        .trim_start_matches("wpkh([")
+        .split_once(']')
+        .expect("key origin");
+    let xprv: Xpriv = key.split('/').next().unwrap().parse().expect("xprv");
+    let path: DerivationPath = "m/0/0".parse().expect("path");
+    let key = xprv.derive_priv(&secp, &path).expect("derive");
+    let (fingerprint, account) = origin.split_once('/').expect("origin path");
+    let origin = (
+        fingerprint.parse().expect("fingerprint"),
+        format!("m/{account}/0/0").parse().expect("path"),
+    );
+    input
+        .bip32_derivation
+        .insert(key.private_key.public_key(&secp), origin);
+}
@@ -0,0 +1,147 @@
+//! Fixed request/response examples for client developers, served on
+//! `GET /debug/test_vectors` when `debug_endpoints` is set.
+//!
+//! The vectors are signed with a published test key, never the configured
+//! one, through the same sign options and response types as `/sign_psbt`.
+//! ECDSA signing is deterministic (RFC 6979), so every build returns the
+//! same bytes and clients can pin them in their own tests.
+
+use std::sync::{Arc, OnceLock};
+
+use axum::{extract::State, Json};
+use bdk_wallet::Wallet;
+use bitcoin::{hex::DisplayHex, Network, Psbt};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{
+    error::{Error, ErrorResponse},
+    AppState, SignResponse,
+};
+
+/// Test key of the sample config. Never fund it.
+pub const XPRV: &str = "wpkh([e650a2a0/84'/827167'/0']tprv8gbcURHVWT5Ghp7zcQExBDXcTL8aSBDjgLyxq1ovtMKbPhx3DD1fo2LjNsdXA2Z27u45P9T72dNHfZGBWrWSDASjdA7qm8zV8bUCNGSAD6m/0/*)";
+const NETWORK: Network = Network::Testnet;
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct TestVectors {
+    /// Network the vectors are built for.
+    #[schema(value_type = String, example = "testnet")]
+    pub network: Network,
+    /// Descriptor of the test key that signed them.
+    pub descriptor: &'static str,
+    pub vectors: Vec<TestVector>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct TestVector {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub method: &'static str,
+    pub path: &'static str,
+    pub content_type: &'static str,
+    /// Request body: a JSON value, or a hex string for binary bodies.
+    #[schema(value_type = Object)]
+    pub request: Value,
+    pub status: u16,
+    /// Response body, in the same form as `request`. Error messages are
+    /// left out: clients should match on the stable `code` only.
+    #[schema(value_type = Object)]
+    pub response: Value,
+}
+
+#[utoipa::path(
+    get,
+    path = "/debug/test_vectors",
+    responses(
+        (status = 200, description = "Canonical examples", body = TestVectors),
+        (status = 404, description = "`not_found` unless `debug_endpoints` is set", body = ErrorResponse),
+    )
+)]
+pub async fn get(State(state): State<Arc<AppState>>) -> Result<Json<&'static TestVectors>, Error> {
+    if !state.config().debug_endpoints {
+        return Err(Error::NotFound("/debug/test_vectors".into()));
+    }
+    static VECTORS: OnceLock<Result<TestVectors, String>> = OnceLock::new();
+    VECTORS
+        .get_or_init(build)
+        .as_ref()
+        .map(Json)
+        .map_err(|e| Error::Internal(format!("test vectors: {e}")))
+}
+
+fn build() -> Result<TestVectors, String> {
+    let wallet = Wallet::create_single(XPRV)
+        .network(NETWORK)
+        .create_wallet_no_persist()
+        .map_err(|e| e.to_string())?;
+    let funding = crate::bench::funding_tx(&wallet, 1)?;
+    let unsigned = crate::bench::spending_psbt(&wallet, &funding, 1)?;
+    let mut signed = unsigned.clone();
+    let options = crate::sign_options::resolve(&Default::default(), None)
+        .map_err(|e| e.to_string())?
+        .options;
+    wallet
+        .sign(&mut signed, options)
+        .map_err(|e| e.to_string())?;
+    let response = SignResponse {
+        psbt: signed.clone(),
+        dry_run: false,
+        cached: false,
+        missing_signatures: Vec::new(),
+        attestation: None,
+    };
+
+    Ok(TestVectors {
+        network: NETWORK,
+        descriptor: XPRV,
+        vectors: vec![
+            TestVector {
+                name: "sign_psbt",
+                description: "Standard base64 with padding in both directions; the \
+                              signed input comes back finalized",
+                method: "POST",
+                path: "/sign_psbt",
+                content_type: "application/json",
+                request: json!({ "psbt": unsigned.to_string() }),
+                status: 200,
+                response: serde_json::to_value(&response).map_err(|e| e.to_string())?,
+            },
+            TestVector {
+                name: "sign_psbt_binary",
+                description: "Raw BIP-174 bytes, shown here as hex",
+                method: "POST",
+                path: "/sign_psbt/binary",
+                content_type: crate::BINARY_PSBT,
+                request: Value::String(unsigned.serialize().to_lower_hex_string()),
+                status: 200,
+                response: Value::String(signed.serialize().to_lower_hex_string()),
+            },
+            TestVector {
+                name: "malformed_psbt",
+                description: "A PSBT that is not valid base64",
+                method: "POST",
+                path: "/sign_psbt",
+                content_type: "application/json",
+                request: json!({ "psbt": "not a psbt" }),
+                status: 400,
+                response: json!({ "error": { "code": "malformed_request" } }),
+            },
+            TestVector {
+                name: "psbt_with_urlsafe_base64",
+                description: "URL-safe base64 is not accepted, only the standard alphabet",
+                method: "POST",
+                path: "/sign_psbt",
+                content_type: "application/json",
+                request: json!({ "psbt": urlsafe(&unsigned) }),
+                status: 400,
+                response: json!({ "error": { "code": "malformed_request" } }),
+            },
+        ],
+    })
+}
+
+/// `psbt` in the URL-safe base64 alphabet, a common client mistake.
+fn urlsafe(psbt: &Psbt) -> String {
+    psbt.to_string().replace('+', "-").replace('/', "_")
+}
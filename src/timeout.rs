@@ -0,0 +1,40 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{error::Error, AppState};
+
+/// Which configured timeout a route is held to.
+#[derive(Debug, Clone, Copy)]
+pub enum RouteTimeout {
+    Sign,
+    Default,
+}
+
+impl RouteTimeout {
+    pub fn duration(self, state: &AppState) -> Duration {
+        let timeouts = &state.config().timeouts;
+        Duration::from_secs(match self {
+            RouteTimeout::Sign => timeouts.sign_secs,
+            RouteTimeout::Default => timeouts.default_secs,
+        })
+    }
+}
+
+/// Fails the request with `504 timeout` once it has run longer than its
+/// route's configured timeout.
+pub async fn enforce_timeout(
+    State((state, route)): State<(Arc<AppState>, RouteTimeout)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limit = route.duration(&state);
+    match tokio::time::timeout(limit, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => Error::Timeout(limit).into_response(),
+    }
+}
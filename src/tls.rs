@@ -0,0 +1,42 @@
+//! HTTPS for `[[listen]]` addresses with a `tls` table.
+//!
+//! The certificate and key are read once at startup; replacing them takes a
+//! restart. ALPN offers `h2` only when `http.http2` is set at startup.
+
+use std::sync::Arc;
+
+use tokio_rustls::{
+    rustls::{
+        self,
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    },
+    TlsAcceptor,
+};
+
+use crate::config::TlsConfig;
+
+pub fn acceptor(config: &TlsConfig, http2: bool) -> Result<TlsAcceptor, String> {
+    let certs = CertificateDer::pem_file_iter(&config.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("tls.cert: {}: {e}", config.cert.display()))?;
+    if certs.is_empty() {
+        return Err(format!(
+            "tls.cert: {}: no certificate found",
+            config.cert.display()
+        ));
+    }
+    let key = PrivateKeyDer::from_pem_file(&config.key)
+        .map_err(|e| format!("tls.key: {}: {e}", config.key.display()))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut server = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|e| format!("tls: {e}"))?;
+    server.alpn_protocols = if http2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
+    Ok(TlsAcceptor::from(Arc::new(server)))
+}
@@ -0,0 +1,55 @@
+//! Public API over a Unix domain socket, for co-located callers.
+
+use std::{
+    fmt,
+    fs::{self, Permissions},
+    io,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+};
+
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::config::UnixSocketConfig;
+
+/// Binds the configured socket, replacing a stale one left by a previous
+/// run, and applies its permission bits.
+pub fn bind(config: &UnixSocketConfig) -> io::Result<UnixListener> {
+    match fs::symlink_metadata(&config.path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(&config.path)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", config.path.display()),
+            ))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let listener = UnixListener::bind(&config.path)?;
+    fs::set_permissions(&config.path, Permissions::from_mode(config.mode))?;
+    Ok(listener)
+}
+
+/// Credentials of the process on the other end of a socket connection,
+/// recorded as the caller in place of a network address.
+#[derive(Debug, Clone, Copy)]
+pub struct UnixPeer {
+    pub uid: Option<u32>,
+}
+
+impl fmt::Display for UnixPeer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.uid {
+            Some(uid) => write!(f, "unix:uid={uid}"),
+            None => f.write_str("unix"),
+        }
+    }
+}
+
+impl UnixPeer {
+    pub fn of(stream: &UnixStream, _: tokio::net::unix::SocketAddr) -> Self {
+        UnixPeer {
+            uid: stream.peer_cred().ok().map(|cred| cred.uid()),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! Streaming read of binary PSBT request bodies.
+//!
+//! The body is taken chunk by chunk as it arrives and checked against
+//! `http.max_binary_psbt_bytes` after every chunk, so an oversized upload is
+//! refused as soon as it crosses the limit instead of after it has been
+//! buffered whole. A `Content-Length` above the limit is refused before any
+//! of the body is read. The chunks are kept as received and the PSBT is
+//! decoded across them, without copying them into one contiguous buffer.
+
+use std::collections::VecDeque;
+
+use axum::body::Body;
+use bitcoin::Psbt;
+use futures_util::StreamExt;
+
+use crate::error::Error;
+
+/// Reads and decodes a binary PSBT of at most `limit` bytes.
+pub async fn read_psbt(
+    headers: &axum::http::HeaderMap,
+    body: Body,
+    limit: usize,
+) -> Result<Psbt, Error> {
+    let declared = headers
+        .get(axum::http::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if declared.is_some_and(|len| len > limit as u64) {
+        return Err(too_large(limit));
+    }
+
+    let mut chunks = VecDeque::new();
+    let mut received = 0usize;
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| Error::MalformedRequest(format!("cannot read body: {e}")))?;
+        received += chunk.len();
+        if received > limit {
+            return Err(too_large(limit));
+        }
+        chunks.push_back(chunk);
+    }
+    crate::psbt_codec::decode_chunks(chunks)
+        .map_err(|e| Error::MalformedRequest(format!("invalid psbt: {e}")))
+}
+
+fn too_large(limit: usize) -> Error {
+    Error::PayloadTooLarge(format!(
+        "binary PSBTs are limited to {limit} bytes (http.max_binary_psbt_bytes)"
+    ))
+}
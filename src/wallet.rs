@@ -0,0 +1,399 @@
+//! Shared access to the signing wallet.
+//!
+//! Signing and queries take the read side of a lock and run concurrently.
+//! Mutations, meaning address reveals, chain sync updates, building
+//! transactions, which bdk treats as a mutation, and recording the ones
+//! broadcast, never take the
+//! write side themselves: they are sent to a single writer thread that applies
+//! them one at a time, holding the write lock only for the change itself.
+//! The writer then takes the staged changeset and hands it to the
+//! [`WalletStore`], if any, after the lock is released, so writing to a store never blocks signing
+//! and changesets reach it in the order they were made. Being a thread of
+//! its own, the writer never stalls the async runtime while it waits for
+//! readers to finish.
+//!
+//! [`SharedWallet::read`] hands out `&Wallet` only, and the write guard never
+//! leaves this module, so no other code can mutate the wallet directly.
+//!
+//! [`SharedWallet::reload`] goes through the writer too, so the wallet it
+//! replaces has had every change before it stored, and none after.
+
+use std::sync::{Arc, RwLock, RwLockReadGuard, TryLockError};
+
+use bdk_wallet::{
+    chain::{local_chain::CannotConnectError, Merge},
+    AddressInfo, ChangeSet, KeychainKind, Update, Wallet,
+};
+use bitcoin::{Amount, FeeRate, OutPoint, Psbt, ScriptBuf, Transaction};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    config::{Config, PolicyPath},
+    error::Error,
+    storage::Storage,
+};
+
+/// Mutations waiting for the writer before senders are held back.
+const QUEUE_DEPTH: usize = 64;
+
+enum Mutation {
+    RevealNextAddress {
+        keychain: KeychainKind,
+        reply: oneshot::Sender<AddressInfo>,
+    },
+    ApplyUpdate {
+        update: Box<Update>,
+        reply: oneshot::Sender<Result<(), CannotConnectError>>,
+    },
+    BuildSweep {
+        outpoints: Vec<OutPoint>,
+        destination: ScriptBuf,
+        fee_rate: FeeRate,
+        policy_path: PolicyPath,
+        reply: oneshot::Sender<Result<Psbt, String>>,
+    },
+    BuildPayout {
+        recipients: Vec<(ScriptBuf, Amount)>,
+        unspendable: Vec<OutPoint>,
+        fee_rate: FeeRate,
+        policy_path: PolicyPath,
+        reply: oneshot::Sender<Result<Psbt, String>>,
+    },
+    ApplyUnconfirmed {
+        tx: Box<Transaction>,
+        seen_at: u64,
+        reply: oneshot::Sender<()>,
+    },
+    Reload {
+        config: Box<Config>,
+        reply: oneshot::Sender<Result<u32, String>>,
+    },
+}
+
+pub struct SharedWallet {
+    wallet: Arc<RwLock<Wallet>>,
+    mutations: mpsc::Sender<Mutation>,
+}
+
+impl SharedWallet {
+    /// Wraps `wallet` and starts its writer thread, which keeps its
+    /// changes in `store`.
+    pub fn new(wallet: Wallet, store: Option<WalletStore>) -> Self {
+        let wallet = Arc::new(RwLock::new(wallet));
+        let (mutations, queue) = mpsc::channel(QUEUE_DEPTH);
+        let writer = wallet.clone();
+        std::thread::Builder::new()
+            .name("wallet-writer".into())
+            .spawn(move || write_forever(writer, queue, store))
+            .expect("spawn wallet writer");
+        SharedWallet { wallet, mutations }
+    }
+
+    /// Read access for signing and queries. The guard must not be held
+    /// across an `.await`.
+    pub fn read(&self) -> RwLockReadGuard<'_, Wallet> {
+        self.wallet.read().expect("wallet lock")
+    }
+
+    /// Whether the wallet can be read right now: `free`, `busy` while the
+    /// writer applies a change, or `poisoned` after a panic under the lock,
+    /// which leaves it unusable until restart.
+    pub fn lock_status(&self) -> &'static str {
+        match self.wallet.try_read() {
+            Ok(_) => "free",
+            Err(TryLockError::WouldBlock) => "busy",
+            Err(TryLockError::Poisoned(_)) => "poisoned",
+        }
+    }
+
+    /// Whether the writer thread still takes changes.
+    pub fn writer_running(&self) -> bool {
+        !self.mutations.is_closed()
+    }
+
+    /// Reveals the next address on `keychain`, used or not.
+    pub async fn reveal_next_address(&self, keychain: KeychainKind) -> Result<AddressInfo, Error> {
+        let (reply, response) = oneshot::channel();
+        self.send(Mutation::RevealNextAddress { keychain, reply })
+            .await?;
+        response.await.map_err(|_| writer_stopped())
+    }
+
+    /// Applies a chain sync update.
+    pub async fn apply_update(&self, update: Update) -> Result<(), String> {
+        let (reply, response) = oneshot::channel();
+        self.send(Mutation::ApplyUpdate {
+            update: Box::new(update),
+            reply,
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+        response
+            .await
+            .map_err(|_| writer_stopped().to_string())?
+            .map_err(|e| e.to_string())
+    }
+
+    /// Builds an unsigned transaction spending exactly `outpoints` to
+    /// `destination`, less the fee at `fee_rate`, satisfying the branches
+    /// `policy_path` chooses.
+    pub async fn build_sweep(
+        &self,
+        outpoints: Vec<OutPoint>,
+        destination: ScriptBuf,
+        fee_rate: FeeRate,
+        policy_path: PolicyPath,
+    ) -> Result<Psbt, Error> {
+        let (reply, response) = oneshot::channel();
+        self.send(Mutation::BuildSweep {
+            outpoints,
+            destination,
+            fee_rate,
+            policy_path,
+            reply,
+        })
+        .await?;
+        response
+            .await
+            .map_err(|_| writer_stopped())?
+            .map_err(Error::InvalidTransaction)
+    }
+
+    /// Builds an unsigned transaction paying `recipients`, with change back
+    /// to the wallet, from coins other than `unspendable`, satisfying the
+    /// branches `policy_path` chooses.
+    pub async fn build_payout(
+        &self,
+        recipients: Vec<(ScriptBuf, Amount)>,
+        unspendable: Vec<OutPoint>,
+        fee_rate: FeeRate,
+        policy_path: PolicyPath,
+    ) -> Result<Psbt, Error> {
+        let (reply, response) = oneshot::channel();
+        self.send(Mutation::BuildPayout {
+            recipients,
+            unspendable,
+            fee_rate,
+            policy_path,
+            reply,
+        })
+        .await?;
+        response
+            .await
+            .map_err(|_| writer_stopped())?
+            .map_err(Error::InvalidTransaction)
+    }
+
+    /// Records `tx` as seen in the mempool, so the coins it spends stop
+    /// being listed as unspent before the next sync picks it up.
+    pub async fn apply_unconfirmed(&self, tx: Transaction) -> Result<(), Error> {
+        let seen_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let (reply, response) = oneshot::channel();
+        self.send(Mutation::ApplyUnconfirmed {
+            tx: Box::new(tx),
+            seen_at,
+            reply,
+        })
+        .await?;
+        response.await.map_err(|_| writer_stopped())
+    }
+
+    /// Replaces the wallet with the one `config` describes, read afresh
+    /// from the store, once it passes the signing self-test. Returns the
+    /// height of its latest checkpoint.
+    pub async fn reload(&self, config: Config) -> Result<u32, Error> {
+        let (reply, response) = oneshot::channel();
+        self.send(Mutation::Reload {
+            config: Box::new(config),
+            reply,
+        })
+        .await?;
+        response
+            .await
+            .map_err(|_| writer_stopped())?
+            .map_err(|e| Error::Internal(format!("wallet reload: {e}")))
+    }
+
+    async fn send(&self, mutation: Mutation) -> Result<(), Error> {
+        self.mutations
+            .send(mutation)
+            .await
+            .map_err(|_| writer_stopped())
+    }
+}
+
+fn writer_stopped() -> Error {
+    Error::Internal("wallet writer has stopped".into())
+}
+
+fn choose_policy_path<Cs>(builder: &mut bdk_wallet::TxBuilder<'_, Cs>, path: PolicyPath) {
+    if !path.external.is_empty() {
+        builder.policy_path(path.external, KeychainKind::External);
+    }
+    if !path.internal.is_empty() {
+        builder.policy_path(path.internal, KeychainKind::Internal);
+    }
+}
+
+/// Runs until every [`SharedWallet`] handle is dropped.
+fn write_forever(
+    wallet: Arc<RwLock<Wallet>>,
+    mut queue: mpsc::Receiver<Mutation>,
+    mut store: Option<WalletStore>,
+) {
+    while let Some(mutation) = queue.blocking_recv() {
+        if let Mutation::Reload { config, reply } = mutation {
+            let _ = reply.send(reload(&wallet, &mut store, &config));
+            continue;
+        }
+        let mut guard = wallet.write().expect("wallet lock");
+        match mutation {
+            Mutation::RevealNextAddress { keychain, reply } => {
+                let _ = reply.send(guard.reveal_next_address(keychain));
+            }
+            Mutation::ApplyUpdate { update, reply } => {
+                let _ = reply.send(guard.apply_update(*update));
+            }
+            Mutation::BuildSweep {
+                outpoints,
+                destination,
+                fee_rate,
+                policy_path,
+                reply,
+            } => {
+                let mut builder = guard.build_tx();
+                choose_policy_path(&mut builder, policy_path);
+                let built = match builder.add_utxos(&outpoints) {
+                    Ok(_) => {
+                        builder
+                            .manually_selected_only()
+                            .drain_to(destination)
+                            .fee_rate(fee_rate);
+                        builder.finish().map_err(|e| e.to_string())
+                    }
+                    Err(e) => Err(e.to_string()),
+                };
+                let _ = reply.send(built);
+            }
+            Mutation::BuildPayout {
+                recipients,
+                unspendable,
+                fee_rate,
+                policy_path,
+                reply,
+            } => {
+                let mut builder = guard.build_tx();
+                choose_policy_path(&mut builder, policy_path);
+                builder
+                    .set_recipients(recipients)
+                    .unspendable(unspendable)
+                    .fee_rate(fee_rate);
+                let _ = reply.send(builder.finish().map_err(|e| e.to_string()));
+            }
+            Mutation::ApplyUnconfirmed { tx, seen_at, reply } => {
+                guard.apply_unconfirmed_txs([(*tx, seen_at)]);
+                let _ = reply.send(());
+            }
+            Mutation::Reload { .. } => unreachable!("handled above"),
+        }
+        let staged = guard.take_staged();
+        drop(guard);
+        match (&mut store, staged) {
+            (Some(store), Some(changeset)) => {
+                if let Err(e) = store.persist(changeset) {
+                    tracing::error!("saving wallet changes failed: {e}");
+                }
+            }
+            (None, Some(_)) => {
+                tracing::trace!("wallet changes not persisted, no store configured");
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Builds the wallet `config` describes, from a fresh read of `store`, and
+/// swaps it and the store in if it signs. The wallet being built is not
+/// locked, so signing goes on meanwhile.
+fn reload(
+    wallet: &RwLock<Wallet>,
+    store: &mut Option<WalletStore>,
+    config: &Config,
+) -> Result<u32, String> {
+    let mut reread = store
+        .as_ref()
+        .map(|store| WalletStore::new(store.storage.clone(), store.name.clone()));
+    let fresh = match &mut reread {
+        Some(store) => store.open(config)?,
+        None => crate::create_wallet(config).map_err(|e| format!("invalid descriptor: {e}"))?,
+    };
+    crate::self_test::run(&fresh).map_err(|e| format!("signing self-test failed: {e}"))?;
+    let height = fresh.latest_checkpoint().height();
+    *wallet.write().expect("wallet lock") = fresh;
+    if reread.is_some() {
+        *store = reread;
+    }
+    Ok(height)
+}
+
+/// The wallet's changes kept in storage at `storage.wallet_path`, every
+/// changeset so far merged into one document.
+pub struct WalletStore {
+    storage: Arc<dyn Storage>,
+    name: String,
+    changeset: ChangeSet,
+}
+
+impl WalletStore {
+    pub fn new(storage: Arc<dyn Storage>, name: String) -> Self {
+        WalletStore {
+            storage,
+            name,
+            changeset: ChangeSet::default(),
+        }
+    }
+
+    /// The wallet `config` describes, as stored or, on first start, built
+    /// and stored.
+    pub fn open(&mut self, config: &Config) -> Result<Wallet, String> {
+        let stored = self
+            .storage
+            .read(&self.name)
+            .map_err(|e| e.to_string())
+            .and_then(|stored| {
+                stored
+                    .map(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+                    .transpose()
+            })
+            .map_err(|e| format!("{}: {e}", self.name))?;
+        if let Some(changeset) = stored {
+            let changeset: ChangeSet = changeset;
+            self.changeset = changeset.clone();
+            let wallet = crate::load_wallet(config, changeset)
+                .map_err(|e| format!("{}: {e}", self.name))?
+                .ok_or_else(|| format!("{}: holds no wallet", self.name))?;
+            tracing::info!(
+                last_checkpoint = wallet.latest_checkpoint().height(),
+                "loaded stored wallet"
+            );
+            return Ok(wallet);
+        }
+        let mut wallet =
+            crate::create_wallet(config).map_err(|e| format!("invalid descriptor: {e}"))?;
+        if let Some(changeset) = wallet.take_staged() {
+            self.persist(changeset)
+                .map_err(|e| format!("{}: {e}", self.name))?;
+        }
+        Ok(wallet)
+    }
+
+    /// Merges `changeset` into those stored and writes them all. On failure
+    /// the change is kept in memory and written with the next one.
+    fn persist(&mut self, changeset: ChangeSet) -> std::io::Result<()> {
+        self.changeset.merge(changeset);
+        let json = serde_json::to_vec(&self.changeset)?;
+        self.storage.write(&self.name, &json)
+    }
+}
@@ -0,0 +1,216 @@
+//! WebSocket endpoint: sign requests and responses plus server-pushed
+//! [`Event`]s over a single connection.
+//!
+//! Client messages are JSON objects tagged by `type`; the optional `id` is
+//! echoed on the reply so requests can be pipelined:
+//!
+//! ```json
+//! {"type": "sign_psbt", "id": 1, "psbt": "cHNidP8B..."}
+//! ```
+//!
+//! A sign request may carry a `consignment` exactly as `/sign_psbt` does.
+//!
+//! Replies are `{"type": "sign_psbt_result", "id": 1, "psbt": ...}` or
+//! `{"type": "error", "id": 1, "error": {...}}`. Every event published
+//! while the connection is open arrives as `{"type": "event", "event": {...}}`.
+
+use std::{str::FromStr, sync::Arc};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+};
+use bitcoin::Psbt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{broadcast::error::RecvError, mpsc};
+
+use crate::{
+    context::RequestContext,
+    cosigners::InputSignatures,
+    error::{Error, ErrorBody},
+    events::Event,
+    priority::Priority,
+    rgb::Consignment,
+    sign_options::SignOptionsRequest,
+    timeout::RouteTimeout,
+    AppState, SignRequest,
+};
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    SignPsbt {
+        psbt: String,
+        #[serde(default)]
+        consignment: Option<Consignment>,
+        #[serde(default)]
+        metadata: crate::audit::Metadata,
+        #[serde(default)]
+        replace: bool,
+        #[serde(default)]
+        priority: Priority,
+        #[serde(default)]
+        sign_options: Option<SignOptionsRequest>,
+        #[serde(default)]
+        collaborative: bool,
+        #[serde(default)]
+        prevouts: Vec<crate::prevouts::Prevout>,
+        #[serde(default)]
+        pairings: Vec<crate::sighash_pairing::Pairing>,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    SignPsbtResult {
+        id: Value,
+        #[serde(serialize_with = "crate::serialize_psbt_to_base64")]
+        psbt: Psbt,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        dry_run: bool,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        cached: bool,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        missing_signatures: Vec<InputSignatures>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        attestation: Option<Box<crate::attestation::Attestation>>,
+    },
+    Error {
+        id: Value,
+        error: ErrorBody,
+    },
+    Event {
+        event: Event,
+    },
+}
+
+pub async fn upgrade(
+    State(state): State<Arc<AppState>>,
+    ctx: RequestContext,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(move |socket| serve(socket, state, ctx))
+}
+
+async fn serve(mut socket: WebSocket, state: Arc<AppState>, ctx: RequestContext) {
+    let mut events = state.events.subscribe();
+    // Sign requests run concurrently and hand their replies back here, so
+    // a slow signature never holds up events or later requests.
+    let (replies, mut pending) = mpsc::unbounded_channel();
+
+    loop {
+        let outgoing = tokio::select! {
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    if let Some(reply) = handle(&state, &ctx, &text, &replies) {
+                        reply
+                    } else {
+                        continue;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    tracing::debug!("websocket receive failed: {e}");
+                    break;
+                }
+            },
+            Some(reply) = pending.recv() => reply,
+            event = events.recv() => match event {
+                Ok(event) => ServerMessage::Event { event },
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "websocket subscriber fell behind, events dropped");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+        };
+        let text = serde_json::to_string(&outgoing).expect("serialize websocket message");
+        if socket.send(Message::Text(text.into())).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Parses one client message. Returns an immediate reply for messages that
+/// are rejected outright; accepted sign requests reply through `replies`.
+fn handle(
+    state: &Arc<AppState>,
+    ctx: &RequestContext,
+    text: &str,
+    replies: &mpsc::UnboundedSender<ServerMessage>,
+) -> Option<ServerMessage> {
+    let reject = |id: Value, e: Error| ServerMessage::Error {
+        id,
+        error: e.body(),
+    };
+
+    let mut message: Value = match serde_json::from_str(text) {
+        Ok(message) => message,
+        Err(e) => return Some(reject(Value::Null, Error::MalformedRequest(e.to_string()))),
+    };
+    let id = message
+        .as_object_mut()
+        .and_then(|message| message.remove("id"))
+        .unwrap_or(Value::Null);
+    let ClientMessage::SignPsbt {
+        psbt,
+        consignment,
+        metadata,
+        replace,
+        priority,
+        sign_options,
+        collaborative,
+        prevouts,
+        pairings,
+    } = match serde_json::from_value(message) {
+        Ok(message) => message,
+        Err(e) => return Some(reject(id, Error::MalformedRequest(e.to_string()))),
+    };
+    let request = match Psbt::from_str(&psbt) {
+        Ok(psbt) => SignRequest {
+            psbt,
+            consignment,
+            metadata,
+            replace,
+            priority,
+            sign_options,
+            collaborative,
+            prevouts,
+            pairings,
+        },
+        Err(e) => return Some(reject(id, Error::MalformedRequest(e.to_string()))),
+    };
+
+    let state = state.clone();
+    let ctx = ctx.clone();
+    let replies = replies.clone();
+    tokio::spawn(async move {
+        let limit = RouteTimeout::Sign.duration(&state);
+        let result = tokio::time::timeout(limit, crate::sign_and_record(&state, &ctx, request))
+            .await
+            .unwrap_or(Err(Error::Timeout(limit)));
+        let reply = match result {
+            Ok(signed) => ServerMessage::SignPsbtResult {
+                id,
+                psbt: signed.psbt,
+                dry_run: signed.dry_run,
+                cached: signed.cached,
+                missing_signatures: signed.missing_signatures,
+                attestation: signed.attestation,
+            },
+            Err(e) => {
+                tracing::error!(error = ?e, "websocket sign failed");
+                reject(id, e)
+            }
+        };
+        // The connection may have closed while signing.
+        let _ = replies.send(reply);
+    });
+    None
+}
@@ -0,0 +1,184 @@
+//! `POST /admin/derive_xpub`: the xpub at a path below the wallet's master
+//! key, for coordinators that need fresh account keys without the seed.
+//!
+//! Only paths matching one of `xpub_derivation.allowed_paths` are derived.
+//! A pattern is a path from `m` whose steps are indices, `*` for any
+//! unhardened index or `*'` for any hardened one, so `m/48'/1'/*'/2'` allows
+//! every BIP-48 P2WSH account on a test network and nothing else. Paths are
+//! from the master key: when the descriptor's xprv carries a key origin, only
+//! paths below that origin can be derived.
+
+use std::{fmt, str::FromStr, sync::Arc};
+
+use axum::{extract::State, Json};
+use bitcoin::{
+    bip32::{ChildNumber, DerivationPath, Fingerprint, Xpub},
+    key::Secp256k1,
+    Address, CompressedPublicKey,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    error::{ApiJson, Error},
+    AppState,
+};
+
+/// A derivation path with wildcard steps.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PathPattern {
+    source: String,
+    steps: Vec<Step>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Index(ChildNumber),
+    AnyNormal,
+    AnyHardened,
+}
+
+impl PathPattern {
+    pub fn matches(&self, path: &DerivationPath) -> bool {
+        self.steps.len() == path.len()
+            && self.steps.iter().zip(path).all(|(step, child)| match step {
+                Step::Index(index) => index == child,
+                Step::AnyNormal => child.is_normal(),
+                Step::AnyHardened => child.is_hardened(),
+            })
+    }
+}
+
+impl FromStr for PathPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let mut parts = s.split('/');
+        if parts.next() != Some("m") {
+            return Err(format!("{s}: must start with `m/`"));
+        }
+        let steps = parts
+            .map(|part| match part {
+                "*" => Ok(Step::AnyNormal),
+                "*'" | "*h" => Ok(Step::AnyHardened),
+                _ => ChildNumber::from_str(part)
+                    .map(Step::Index)
+                    .map_err(|e| format!("{s}: step {part}: {e}")),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if steps.is_empty() {
+            return Err(format!("{s}: the master key itself cannot be allowed"));
+        }
+        Ok(PathPattern {
+            source: s.to_owned(),
+            steps,
+        })
+    }
+}
+
+impl fmt::Display for PathPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl fmt::Debug for PathPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PathPattern({})", self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for PathPattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeriveRequest {
+    /// From the master key, such as `m/48'/1'/7'/2'`.
+    path: String,
+    /// Script type of `address`: `wpkh` (the default), `sh_wpkh`, `pkh` or
+    /// `tr`.
+    #[serde(default = "default_script_type")]
+    script_type: String,
+}
+
+fn default_script_type() -> String {
+    "wpkh".into()
+}
+
+#[derive(Serialize)]
+pub struct DeriveResponse {
+    pub path: String,
+    pub xpub: Xpub,
+    /// Fingerprint of the master key.
+    pub fingerprint: Fingerprint,
+    /// The xpub with its key origin, ready for a descriptor.
+    pub key: String,
+    /// Address of the key at `path` itself.
+    pub address: Address,
+}
+
+pub async fn derive(
+    State(state): State<Arc<AppState>>,
+    ApiJson(request): ApiJson<DeriveRequest>,
+) -> Result<Json<DeriveResponse>, Error> {
+    let config = state.config();
+    let derivation = config
+        .xpub_derivation
+        .as_ref()
+        .ok_or_else(|| Error::NotFound("xpub derivation is not configured".into()))?;
+    let path = DerivationPath::from_str(request.path.trim())
+        .map_err(|e| Error::MalformedRequest(format!("path: {e}")))?;
+    if !derivation
+        .allowed_paths
+        .iter()
+        .any(|pattern| pattern.matches(&path))
+    {
+        return Err(Error::MalformedRequest(format!(
+            "path m/{path} is not in xpub_derivation.allowed_paths"
+        )));
+    }
+
+    let root = crate::descriptor::signing_root(&config.xprv)
+        .ok_or_else(|| Error::Unavailable("the descriptor has no xprv to derive from".into()))?;
+    let secp = Secp256k1::new();
+    let (fingerprint, prefix) = match &root.origin {
+        Some((fingerprint, prefix)) => (*fingerprint, prefix.clone()),
+        None => (root.xkey.fingerprint(&secp), DerivationPath::default()),
+    };
+    let relative = path.as_ref().strip_prefix(prefix.as_ref()).ok_or_else(|| {
+        Error::MalformedRequest(format!(
+            "path m/{path} is not below the wallet key's origin m/{prefix}"
+        ))
+    })?;
+    let xprv = root
+        .xkey
+        .derive_priv(&secp, &relative)
+        .map_err(|e| Error::Internal(format!("deriving m/{path}: {e}")))?;
+    let xpub = Xpub::from_priv(&secp, &xprv);
+
+    let public_key = CompressedPublicKey(xpub.public_key);
+    let address = match request.script_type.as_str() {
+        "wpkh" => Address::p2wpkh(&public_key, config.network),
+        "sh_wpkh" => Address::p2shwpkh(&public_key, config.network),
+        "pkh" => Address::p2pkh(public_key, config.network),
+        "tr" => Address::p2tr(&secp, xpub.to_x_only_pub(), None, config.network),
+        other => {
+            return Err(Error::MalformedRequest(format!(
+                "script_type: unsupported {other}, expected wpkh, sh_wpkh, pkh or tr"
+            )))
+        }
+    };
+    tracing::info!(%path, "derived xpub");
+    Ok(Json(DeriveResponse {
+        key: format!("[{fingerprint}/{path}]{xpub}"),
+        path: format!("m/{path}"),
+        xpub,
+        fingerprint,
+        address,
+    }))
+}